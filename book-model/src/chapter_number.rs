@@ -0,0 +1,234 @@
+use std::cmp::Ordering;
+use std::num::ParseIntError;
+use std::str::FromStr;
+use std::{
+    fmt::{self, Display, Formatter},
+    ops::{Deref, DerefMut},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A section number like "1.2.3."
+#[derive(Debug, PartialEq, Clone, Default, Eq, Hash)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct ChapterNumber(pub Vec<i64>);
+
+#[cfg(feature = "schema")]
+impl schemars::JsonSchema for ChapterNumber {
+    fn schema_name() -> String {
+        "ChapterNumber".to_string()
+    }
+    fn json_schema(generator: &mut schemars::r#gen::SchemaGenerator) -> schemars::schema::Schema {
+        // Create a schema for a string that represents a chapter number
+        // The schema should validate that the string is in the format "1.2.3."
+        let mut schema = <String as schemars::JsonSchema>::json_schema(generator);
+        // Add description to explain the format
+        if let schemars::schema::Schema::Object(obj) = &mut schema {
+            obj.metadata = Some(Box::new(schemars::schema::Metadata {
+                description: Some("A chapter number in the format '1.2.3.' representing the hierarchical position in a book, front matter starts with '0.' and back matter with '-1.'".to_string()),
+                ..Default::default()
+            }));
+
+            // Add pattern to validate the format (optional numbers separated by dots)
+            obj.string = Some(Box::new(schemars::schema::StringValidation {
+                pattern: Some(r"^(-1\.)?(\d+\.)*\d+\.?$".to_string()),
+                ..Default::default()
+            }));
+        }
+        schema
+    }
+}
+
+impl ChapterNumber {
+    /// front matter chapter, number is 0.1, 0.2, ...
+    pub fn is_prefix(&self) -> bool {
+        self.first() == Some(&0)
+    }
+    /// back matter chapter, number is -1.1, -1.2, ...
+    pub fn is_suffix(&self) -> bool {
+        self.first() == Some(&-1)
+    }
+    /// whether `other` is a sub chapter of this chapter, at any depth
+    pub fn is_ancestor_of(&self, other: &ChapterNumber) -> bool {
+        self.len() < other.len() && other.starts_with(self)
+    }
+    /// nesting level in the table of contents, the synthetic 0 / -1 part is not counted
+    pub fn depth(&self) -> usize {
+        if self.is_prefix() || self.is_suffix() {
+            self.len().saturating_sub(2)
+        } else {
+            self.len().saturating_sub(1)
+        }
+    }
+}
+
+impl Display for ChapterNumber {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for item in &self.0 {
+            write!(f, "{item}.")?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for ChapterNumber {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ChapterNumber {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<ChapterNumber>().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Error returned when a string is not a valid [`ChapterNumber`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ChapterNumberError {
+    #[error("invalid chapter number {input:?}: empty section between dots")]
+    EmptySection { input: String },
+    #[error("invalid chapter number {input:?}: section {section:?} is not a number ({source})")]
+    InvalidSection {
+        input: String,
+        section: String,
+        source: ParseIntError,
+    },
+    #[error(
+        "invalid chapter number {input:?}: negative section {section}, only a leading -1 is allowed for back matter"
+    )]
+    Negative { input: String, section: i64 },
+}
+
+impl FromStr for ChapterNumber {
+    type Err = ChapterNumberError;
+    /// Accepts "3.1.", "3.1", "03.1" and surrounding whitespace, the empty string is the empty number.
+    /// Only the leading section may be negative, and only as the back matter sentinel -1.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let input = s.trim();
+        let mut number = Vec::new();
+        for (i, section) in input.split_terminator('.').enumerate() {
+            let section = section.trim();
+            if section.is_empty() {
+                return Err(ChapterNumberError::EmptySection {
+                    input: s.to_string(),
+                });
+            }
+            let value: i64 =
+                section
+                    .parse()
+                    .map_err(|source| ChapterNumberError::InvalidSection {
+                        input: s.to_string(),
+                        section: section.to_string(),
+                        source,
+                    })?;
+            if value < 0 && !(i == 0 && value == -1) {
+                return Err(ChapterNumberError::Negative {
+                    input: s.to_string(),
+                    section: value,
+                });
+            }
+            number.push(value);
+        }
+        Ok(ChapterNumber(number))
+    }
+}
+
+impl Deref for ChapterNumber {
+    type Target = Vec<i64>;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for ChapterNumber {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl FromIterator<i64> for ChapterNumber {
+    fn from_iter<I: IntoIterator<Item = i64>>(it: I) -> Self {
+        ChapterNumber(it.into_iter().collect())
+    }
+}
+
+impl PartialOrd for ChapterNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ChapterNumber {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // if self.0[0] == -1, it is a suffix chapter
+        match (self.0.first(), other.0.first()) {
+            (Some(n), Some(m)) => {
+                if (*n == -1) == (*m == -1) {
+                    self.0.cmp(&other.0)
+                } else if *n != -1 && *m == -1 {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            }
+            _ => self.0.cmp(&other.0),
+        }
+    }
+}
+
+#[test]
+fn chapter_number_cmp() {
+    let mut set: std::collections::BTreeSet<ChapterNumber> = std::collections::BTreeSet::new();
+    set.insert("3.1.4".parse().unwrap());
+    set.insert("".parse().unwrap());
+    set.insert("2.3.2".parse().unwrap());
+    set.insert("-1.3.8.".parse().unwrap());
+    set.insert("5.4.1".parse().unwrap());
+    set.insert("4.7.6".parse().unwrap());
+    println!("{:?}", set);
+}
+
+#[test]
+fn chapter_number_parse_variants() {
+    let expected = ChapterNumber::from_iter(vec![3, 1]);
+    for input in ["3.1.", "3.1", "03.1", " 3.1. ", "3. 1"] {
+        assert_eq!(
+            input.parse::<ChapterNumber>(),
+            Ok(expected.clone()),
+            "{input}"
+        );
+    }
+    assert_eq!("".parse::<ChapterNumber>(), Ok(ChapterNumber::default()));
+    assert_eq!(
+        "-1.2.".parse::<ChapterNumber>(),
+        Ok(ChapterNumber::from_iter(vec![-1, 2]))
+    );
+}
+
+#[test]
+fn chapter_number_parse_errors() {
+    assert!(matches!(
+        "3..1".parse::<ChapterNumber>(),
+        Err(ChapterNumberError::EmptySection { .. })
+    ));
+    assert!(matches!(
+        "3.a".parse::<ChapterNumber>(),
+        Err(ChapterNumberError::InvalidSection { .. })
+    ));
+    assert!(matches!(
+        "-2.1".parse::<ChapterNumber>(),
+        Err(ChapterNumberError::Negative { section: -2, .. })
+    ));
+    assert!(matches!(
+        "3.-1".parse::<ChapterNumber>(),
+        Err(ChapterNumberError::Negative { section: -1, .. })
+    ));
+}
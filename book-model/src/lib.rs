@@ -0,0 +1,7 @@
+mod chapter_number;
+mod toc;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use chapter_number::{ChapterNumber, ChapterNumberError};
+pub use toc::{ChapterMeta, TocNode, build_toc};
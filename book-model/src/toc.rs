@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+
+use crate::ChapterNumber;
+
+/// Number and title of a chapter, without its content
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct ChapterMeta {
+    pub number: ChapterNumber,
+    pub name: String,
+}
+
+/// A chapter in the table of contents with its sub chapters
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(utoipa::ToSchema))]
+pub struct TocNode {
+    pub number: ChapterNumber,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[cfg_attr(feature = "schema", schema(no_recursion))]
+    pub children: Vec<TocNode>,
+}
+
+impl TocNode {
+    /// this node and all its descendants, depth first
+    pub fn iter(&self) -> Box<dyn Iterator<Item = &TocNode> + '_> {
+        Box::new(std::iter::once(self).chain(self.children.iter().flat_map(TocNode::iter)))
+    }
+}
+
+fn insert(nodes: &mut Vec<TocNode>, node: TocNode) {
+    match nodes.last_mut() {
+        Some(last) if last.number.is_ancestor_of(&node.number) => insert(&mut last.children, node),
+        _ => nodes.push(node),
+    }
+}
+
+/// nest the chapters by their numbers, in book order, a chapter whose parent is missing stays at the top
+pub fn build_toc(chapters: impl IntoIterator<Item = ChapterMeta>) -> Vec<TocNode> {
+    let mut chapters: Vec<ChapterMeta> = chapters.into_iter().collect();
+    chapters.sort_by(|a, b| a.number.cmp(&b.number));
+    let mut toc = vec![];
+    for chapter in chapters {
+        insert(
+            &mut toc,
+            TocNode {
+                number: chapter.number,
+                name: chapter.name,
+                children: vec![],
+            },
+        );
+    }
+    toc
+}
+
+#[test]
+fn nesting() {
+    let meta = |number: &str, name: &str| ChapterMeta {
+        number: number.parse().unwrap(),
+        name: name.to_string(),
+    };
+    let toc = build_toc([
+        meta("2.1.", "Loops"),
+        meta("-1.1.", "Appendix"),
+        meta("1.", "Basics"),
+        meta("2.", "Control Flow"),
+        meta("0.1.", "Preface"),
+        meta("3.2.", "Orphan"),
+    ]);
+    let top: Vec<&str> = toc.iter().map(|node| node.name.as_str()).collect();
+    assert_eq!(
+        top,
+        ["Preface", "Basics", "Control Flow", "Orphan", "Appendix"]
+    );
+    assert_eq!(toc[2].children[0].name, "Loops");
+    assert_eq!(toc[2].iter().count(), 2);
+}
@@ -0,0 +1,31 @@
+use std::cmp::Ordering;
+
+use wasm_bindgen::prelude::*;
+
+use crate::ChapterNumber;
+
+fn parse(number: &str) -> Result<ChapterNumber, JsError> {
+    number.parse().map_err(|e| JsError::new(&format!("{e}")))
+}
+
+/// the canonical form of a chapter number, e.g. "03.1" -> "3.1."
+#[wasm_bindgen(js_name = normalizeChapterNumber)]
+pub fn normalize_chapter_number(number: &str) -> Result<String, JsError> {
+    Ok(parse(number)?.to_string())
+}
+
+/// -1, 0 or 1 as `a` comes before, with or after `b` in the book, for `Array.prototype.sort`
+#[wasm_bindgen(js_name = compareChapterNumbers)]
+pub fn compare_chapter_numbers(a: &str, b: &str) -> Result<i32, JsError> {
+    Ok(match parse(a)?.cmp(&parse(b)?) {
+        Ordering::Less => -1,
+        Ordering::Equal => 0,
+        Ordering::Greater => 1,
+    })
+}
+
+/// nesting level in the table of contents, 0 for top-level chapters
+#[wasm_bindgen(js_name = chapterDepth)]
+pub fn chapter_depth(number: &str) -> Result<usize, JsError> {
+    Ok(parse(number)?.depth())
+}
@@ -0,0 +1,15 @@
+CREATE TABLE focus_session (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    start_time DATETIME NOT NULL,
+    end_time DATETIME,
+    last_activity DATETIME NOT NULL,
+    idle_gaps INTEGER NOT NULL DEFAULT 0,
+    idle_seconds INTEGER NOT NULL DEFAULT 0,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
+
+-- minutes without student input after which the teacher re-engages in a focus session
+ALTER TABLE agent_setting ADD COLUMN focus_idle_minutes INTEGER NOT NULL DEFAULT 10;
@@ -0,0 +1,14 @@
+CREATE TABLE confidence_checkin (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    chapter_number CHAR(20) NOT NULL,
+    confidence INTEGER CHECK(
+        confidence BETWEEN 1
+        AND 5
+    ) NOT NULL,
+    note TEXT,
+    create_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
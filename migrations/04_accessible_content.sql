@@ -0,0 +1,9 @@
+CREATE TABLE accessible_content (
+    book_id INTEGER NOT NULL,
+    chapter_number CHAR(20) NOT NULL,
+    mode CHAR(20) NOT NULL,
+    content TEXT NOT NULL,
+    create_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (book_id, chapter_number, mode),
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
@@ -0,0 +1,7 @@
+-- per-student data keys for message encryption, wrapped by the master key
+CREATE TABLE student_data_key (
+    student_id INTEGER PRIMARY KEY NOT NULL,
+    wrapped_key TEXT NOT NULL,
+    create_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE
+);
@@ -0,0 +1,37 @@
+CREATE TABLE class (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    -- monthly AI spend limit in USD, no limit if NULL
+    monthly_limit REAL,
+    -- called once a month when the spend reaches 80% of the limit
+    warning_webhook TEXT
+);
+
+ALTER TABLE student ADD COLUMN class_id INTEGER REFERENCES class(id) ON DELETE SET NULL;
+
+CREATE TABLE ai_usage (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    class_id INTEGER,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    cost REAL NOT NULL,
+    create_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (class_id) REFERENCES class(id) ON DELETE SET NULL
+);
+
+CREATE INDEX ai_usage_class_time ON ai_usage (class_id, create_time);
+
+-- months in which the 80% warning of a class was already sent, e.g. '2025-05'
+CREATE TABLE class_spend_warning (
+    class_id INTEGER NOT NULL,
+    month CHAR(7) NOT NULL,
+    PRIMARY KEY (class_id, month),
+    FOREIGN KEY (class_id) REFERENCES class(id) ON DELETE CASCADE
+);
+
+-- model prices in USD per million tokens
+ALTER TABLE agent_setting ADD COLUMN prompt_price REAL NOT NULL DEFAULT 0;
+ALTER TABLE agent_setting ADD COLUMN completion_price REAL NOT NULL DEFAULT 0;
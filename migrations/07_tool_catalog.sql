@@ -0,0 +1,12 @@
+-- localized or tuned names and descriptions of the teacher tools, NULL keeps the built-in text
+CREATE TABLE tool_catalog (
+    locale CHAR(20) NOT NULL,
+    tool_name TEXT NOT NULL,
+    display_name TEXT,
+    description TEXT,
+    update_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (locale, tool_name)
+);
+
+-- locale of the tool catalog used by the teacher
+ALTER TABLE agent_setting ADD COLUMN locale CHAR(20) NOT NULL DEFAULT 'en';
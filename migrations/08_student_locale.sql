@@ -0,0 +1,2 @@
+-- locale of the prompts, replies and messages for the student, e.g. 'en' or 'zh'
+ALTER TABLE student ADD COLUMN locale CHAR(20) NOT NULL DEFAULT 'en';
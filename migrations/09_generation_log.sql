@@ -0,0 +1,19 @@
+-- every model call of the plan and summary pipeline, for debugging quality and cost
+CREATE TABLE generation_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    -- e.g. 'chapter_plan', 'chapter_summary', 'teaching_plan'
+    artifact TEXT NOT NULL,
+    -- 'generate', 'extract', 'judge' or 'merge'
+    step TEXT NOT NULL,
+    book_id INTEGER,
+    chapter_number TEXT,
+    model TEXT NOT NULL,
+    prompt TEXT NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    cost REAL NOT NULL,
+    create_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE INDEX generation_log_book ON generation_log (book_id, create_time);
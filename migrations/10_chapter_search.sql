@@ -0,0 +1,8 @@
+-- full-text index of the chapter names and contents, filled when a book is stored or first searched
+CREATE VIRTUAL TABLE chapter_search USING fts5(
+    book_id UNINDEXED,
+    chapter_number UNINDEXED,
+    name,
+    content,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
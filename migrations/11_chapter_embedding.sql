@@ -0,0 +1,13 @@
+-- chunks of chapter content with their embeddings, for semantic search
+CREATE TABLE chapter_embedding (
+    book_id INTEGER NOT NULL,
+    chapter_number CHAR(20) NOT NULL,
+    chunk_index INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    -- the embedding model, chunks of another model are recomputed
+    model TEXT NOT NULL,
+    -- little-endian f32 vector
+    embedding BLOB NOT NULL,
+    PRIMARY KEY (book_id, chapter_number, chunk_index),
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
@@ -0,0 +1,24 @@
+-- quizzes generated from a chapter, questions and answers as json
+CREATE TABLE quiz (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    book_id INTEGER NOT NULL,
+    chapter_number CHAR(20) NOT NULL,
+    questions TEXT NOT NULL,
+    create_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
+
+-- graded submissions, the score is the share of correct answers from 0 to 1
+CREATE TABLE quiz_submission (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    quiz_id INTEGER NOT NULL,
+    student_id INTEGER NOT NULL,
+    answers TEXT NOT NULL,
+    results TEXT NOT NULL,
+    score REAL NOT NULL,
+    create_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (quiz_id) REFERENCES quiz(id) ON DELETE CASCADE,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE
+);
+
+CREATE INDEX quiz_submission_student ON quiz_submission (student_id, quiz_id);
@@ -0,0 +1,18 @@
+-- flashcards of a student with their SM-2 review state
+CREATE TABLE flashcard (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    chapter_number CHAR(20) NOT NULL,
+    front TEXT NOT NULL,
+    back TEXT NOT NULL,
+    ease_factor REAL DEFAULT 2.5 NOT NULL,
+    interval_days INTEGER DEFAULT 0 NOT NULL,
+    repetitions INTEGER DEFAULT 0 NOT NULL,
+    due_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    create_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
+
+CREATE INDEX flashcard_due ON flashcard (student_id, due_time);
@@ -0,0 +1,18 @@
+-- background job items that failed every attempt, kept until an admin requeues them
+CREATE TABLE dead_letter (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    -- the job kind, e.g. 'regenerate_chapter_plans'
+    kind TEXT NOT NULL,
+    job_id INTEGER NOT NULL,
+    item TEXT NOT NULL,
+    -- json of the item, what a requeue runs again
+    payload TEXT NOT NULL,
+    -- the error of the last attempt with its cause chain
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    create_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    requeue_time DATETIME,
+    requeue_job_id INTEGER
+);
+
+CREATE INDEX dead_letter_kind ON dead_letter (kind, requeue_time);
@@ -0,0 +1,28 @@
+-- LLM backends the agents can use, api keys stay in the environment variable named here
+CREATE TABLE ai_provider (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    -- 'openai', 'azure' or 'ollama'
+    kind TEXT NOT NULL,
+    base_url TEXT NOT NULL,
+    -- the model, the deployment for azure
+    model TEXT NOT NULL,
+    api_key_env TEXT,
+    api_version TEXT
+);
+
+-- the provider of every agent, NULL for the one of the environment
+ALTER TABLE agent_setting ADD COLUMN ai_provider_id INTEGER REFERENCES ai_provider(id) ON DELETE SET NULL;
+
+-- providers of a student, a book, or a student on a book, the most specific row wins
+CREATE TABLE agent_provider (
+    student_id INTEGER,
+    book_id INTEGER,
+    ai_provider_id INTEGER NOT NULL,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE,
+    FOREIGN KEY (ai_provider_id) REFERENCES ai_provider(id) ON DELETE CASCADE,
+    CHECK (student_id IS NOT NULL OR book_id IS NOT NULL)
+);
+
+CREATE UNIQUE INDEX agent_provider_scope ON agent_provider (ifnull(student_id, 0), ifnull(book_id, 0));
@@ -0,0 +1,5 @@
+-- the model chosen for the conversation, NULL for the configured provider
+ALTER TABLE teacher_agent ADD COLUMN model TEXT;
+
+-- the model that produced an assistant message, NULL for other messages
+ALTER TABLE history_message ADD COLUMN model TEXT;
@@ -0,0 +1,17 @@
+-- rolling summary of the conversation messages archived to keep within the token budget
+ALTER TABLE teacher_agent ADD COLUMN conversation_summary TEXT;
+
+-- conversation messages replaced by the summary, kept for the history
+CREATE TABLE archived_message (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    update_time DATETIME NOT NULL,
+    model TEXT,
+    archive_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
+
+CREATE INDEX archived_message_conversation ON archived_message (student_id, book_id);
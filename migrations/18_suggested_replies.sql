@@ -0,0 +1,2 @@
+-- number of replies suggested to the student after each teacher turn, 0 to suggest none
+ALTER TABLE agent_setting ADD COLUMN suggested_replies INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+-- what the teacher remembers about a student across books and sessions
+CREATE TABLE student_memory (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    student_id INTEGER NOT NULL,
+    fact TEXT NOT NULL,
+    update_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    UNIQUE (student_id, fact)
+);
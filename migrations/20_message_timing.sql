@@ -0,0 +1,3 @@
+-- when each chunk of a streamed assistant message was sent, json of `ResponseTiming`
+ALTER TABLE history_message ADD COLUMN timing TEXT;
+ALTER TABLE archived_message ADD COLUMN timing TEXT;
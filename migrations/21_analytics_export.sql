@@ -0,0 +1,5 @@
+-- how far each analytics export got, events up to this time were sent
+CREATE TABLE analytics_export (
+    name TEXT PRIMARY KEY NOT NULL,
+    exported_until DATETIME NOT NULL
+);
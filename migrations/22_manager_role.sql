@@ -0,0 +1,2 @@
+-- admins manage the library and the agent settings, teachers only view books and student progress
+ALTER TABLE manager ADD COLUMN role TEXT NOT NULL DEFAULT 'admin' CHECK (role IN ('admin', 'teacher'));
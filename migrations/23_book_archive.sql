@@ -0,0 +1,2 @@
+-- archived books are hidden from students, their conversations are kept
+ALTER TABLE book ADD COLUMN archive_time DATETIME;
@@ -0,0 +1,5 @@
+-- tags set by admins to filter the library, comma separated like the authors
+ALTER TABLE book ADD COLUMN tags TEXT NOT NULL DEFAULT '';
+-- when the book was added, the time of this migration for books added before
+ALTER TABLE book ADD COLUMN create_time DATETIME;
+UPDATE book SET create_time = CURRENT_TIMESTAMP;
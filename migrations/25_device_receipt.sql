@@ -0,0 +1,11 @@
+-- the conversation position each device of a student has displayed up to
+CREATE TABLE device_receipt (
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    device_id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    update_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (student_id, book_id, device_id),
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+-- catalog metadata set by admins, null when unknown
+ALTER TABLE book ADD COLUMN language TEXT;
+ALTER TABLE book ADD COLUMN difficulty TEXT CHECK (difficulty IN ('beginner', 'intermediate', 'advanced'));
+-- cover image relative to the book directory
+ALTER TABLE book ADD COLUMN cover_path TEXT;
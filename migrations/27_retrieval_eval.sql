@@ -0,0 +1,11 @@
+-- questions with the chapter that answers them, to measure the retrieval of a book
+CREATE TABLE retrieval_eval_question (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    book_id INTEGER NOT NULL,
+    question TEXT NOT NULL,
+    gold_chapter TEXT NOT NULL,
+    create_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_retrieval_eval_question_book ON retrieval_eval_question(book_id);
@@ -0,0 +1,3 @@
+-- the birth date of a student, chapters rated above the age of a minor are not taught, null for
+-- students without age restrictions
+ALTER TABLE student ADD COLUMN birth_date DATE;
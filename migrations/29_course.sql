@@ -0,0 +1,11 @@
+-- the course schedule of a student on a book, one row per chapter of a session
+CREATE TABLE course_lesson (
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    session INTEGER NOT NULL,
+    lesson_date DATE NOT NULL,
+    chapter_number CHAR(20) NOT NULL,
+    PRIMARY KEY (student_id, book_id, chapter_number),
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
@@ -0,0 +1,26 @@
+-- topics the teacher must not discuss with the students of a class
+CREATE TABLE forbidden_topic (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    class_id INTEGER NOT NULL,
+    topic TEXT NOT NULL,
+    -- JSON array of words and phrases that bring the topic up
+    keywords TEXT NOT NULL DEFAULT '[]',
+    FOREIGN KEY (class_id) REFERENCES class(id) ON DELETE CASCADE
+);
+
+-- questions and responses that touched a forbidden topic
+CREATE TABLE guardrail_event (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    class_id INTEGER,
+    topic TEXT NOT NULL,
+    -- 'question' or 'response'
+    direction TEXT NOT NULL,
+    excerpt TEXT NOT NULL,
+    create_time DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (class_id) REFERENCES class(id) ON DELETE CASCADE
+);
+
+CREATE INDEX guardrail_event_class ON guardrail_event (class_id, id);
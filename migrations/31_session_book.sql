@@ -0,0 +1,10 @@
+-- books linked to the session of a student on a book, the teacher cross-references them
+CREATE TABLE session_book (
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    linked_book_id INTEGER NOT NULL,
+    PRIMARY KEY (student_id, book_id, linked_book_id),
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE,
+    FOREIGN KEY (linked_book_id) REFERENCES book(id) ON DELETE CASCADE
+);
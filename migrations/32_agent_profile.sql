@@ -0,0 +1,15 @@
+-- how the teacher talks to a student, on every book or on one book; unset fields fall back to
+-- the student-wide profile, then to the instructions
+CREATE TABLE agent_profile (
+    student_id INTEGER NOT NULL,
+    -- 0 for the profile on every book of the student
+    book_id INTEGER NOT NULL DEFAULT 0,
+    tone TEXT CHECK (tone IN ('friendly', 'formal', 'playful', 'encouraging')),
+    strictness TEXT CHECK (strictness IN ('lenient', 'balanced', 'strict')),
+    instruction_language TEXT,
+    verbosity TEXT CHECK (verbosity IN ('concise', 'normal', 'detailed')),
+    emojis BOOLEAN,
+    update_time DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (student_id, book_id),
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE
+);
@@ -0,0 +1,32 @@
+-- Rhai scripts customizing the lesson flow of a book, for every student or those of one class
+CREATE TABLE lesson_script (
+    id INTEGER PRIMARY KEY,
+    book_id INTEGER NOT NULL,
+    -- the class the script applies to, every student of the book when null
+    class_id INTEGER,
+    -- the event running the script, e.g. 'quiz_graded'
+    hook TEXT NOT NULL CHECK (hook IN ('quiz_graded', 'chapter_completed')),
+    name TEXT NOT NULL,
+    source TEXT NOT NULL,
+    update_time DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE,
+    FOREIGN KEY (class_id) REFERENCES class(id) ON DELETE CASCADE
+);
+CREATE INDEX lesson_script_book ON lesson_script(book_id, hook);
+
+-- reviews of chapters scheduled by lesson scripts, done once the student revisits the chapter
+CREATE TABLE scheduled_review (
+    id INTEGER PRIMARY KEY,
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    chapter_number CHAR(20) NOT NULL,
+    due_date DATE NOT NULL,
+    reason TEXT NOT NULL,
+    script_id INTEGER,
+    done BOOLEAN NOT NULL DEFAULT FALSE,
+    create_time DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE,
+    FOREIGN KEY (script_id) REFERENCES lesson_script(id) ON DELETE SET NULL
+);
+CREATE INDEX scheduled_review_student ON scheduled_review(student_id, book_id, due_date);
@@ -0,0 +1,9 @@
+-- prompt templates set by admins over the built-in ones in prompts/
+CREATE TABLE prompt_template (
+    name TEXT PRIMARY KEY NOT NULL CHECK (
+        name IN ('teaching_plan', 'chapter_plan', 'plan_critique', 'teacher_instruction')
+    ),
+    -- minijinja source
+    source TEXT NOT NULL,
+    update_time DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
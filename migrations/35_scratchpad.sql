@@ -0,0 +1,13 @@
+-- the Markdown scratchpad the teacher and the student share on a book
+CREATE TABLE scratchpad (
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    -- bumped on every save, edits based on an older version are refused
+    version INTEGER NOT NULL,
+    updated_by TEXT NOT NULL CHECK (updated_by IN ('teacher', 'student')),
+    update_time DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (student_id, book_id),
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
@@ -0,0 +1,51 @@
+-- multi-week projects of a book, built milestone by milestone
+CREATE TABLE project (
+    id INTEGER PRIMARY KEY,
+    book_id INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    description TEXT NOT NULL,
+    create_time DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
+CREATE INDEX project_book ON project(book_id);
+
+CREATE TABLE project_milestone (
+    id INTEGER PRIMARY KEY,
+    project_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    description TEXT NOT NULL,
+    -- days from the start of the project to the due date
+    due_days INTEGER NOT NULL,
+    -- the chapters the milestone practices, space separated
+    chapters TEXT NOT NULL,
+    UNIQUE (project_id, position),
+    FOREIGN KEY (project_id) REFERENCES project(id) ON DELETE CASCADE
+);
+
+CREATE TABLE student_project (
+    id INTEGER PRIMARY KEY,
+    student_id INTEGER NOT NULL,
+    project_id INTEGER NOT NULL,
+    start_date DATE NOT NULL,
+    -- the final report of the teacher, once every milestone is met
+    report TEXT,
+    report_time DATETIME,
+    UNIQUE (student_id, project_id),
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (project_id) REFERENCES project(id) ON DELETE CASCADE
+);
+
+-- the artifacts a student submitted for a milestone, each with the check-in of the teacher
+CREATE TABLE project_submission (
+    id INTEGER PRIMARY KEY,
+    student_project_id INTEGER NOT NULL,
+    milestone_id INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    feedback TEXT NOT NULL,
+    meets_milestone BOOLEAN NOT NULL,
+    create_time DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (student_project_id) REFERENCES student_project(id) ON DELETE CASCADE,
+    FOREIGN KEY (milestone_id) REFERENCES project_milestone(id) ON DELETE CASCADE
+);
+CREATE INDEX project_submission_student ON project_submission(student_project_id, milestone_id);
@@ -0,0 +1,16 @@
+-- the tokens of the teacher's model calls per student, book and day, for cost attribution
+CREATE TABLE token_usage (
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    -- the day of the calls, UTC
+    day DATE NOT NULL,
+    requests INTEGER NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    -- calls the provider returned no usage for, whose tokens were counted locally
+    estimated_requests INTEGER NOT NULL,
+    PRIMARY KEY (student_id, book_id, day),
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
+CREATE INDEX token_usage_day ON token_usage(day);
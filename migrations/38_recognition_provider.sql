@@ -0,0 +1,3 @@
+-- the provider reading handwriting and whiteboard snapshots, a model that accepts images;
+-- the chat provider of the student on the book when null
+ALTER TABLE agent_setting ADD COLUMN recognition_provider_id INTEGER REFERENCES ai_provider(id) ON DELETE SET NULL;
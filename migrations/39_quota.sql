@@ -0,0 +1,19 @@
+-- daily limits of every student, no limit when null
+ALTER TABLE agent_setting ADD COLUMN daily_token_limit INTEGER;
+ALTER TABLE agent_setting ADD COLUMN daily_message_limit INTEGER;
+
+-- the chat messages of the student, counted against the daily message limit
+ALTER TABLE token_usage ADD COLUMN messages INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE student_quota (
+    student_id INTEGER PRIMARY KEY NOT NULL,
+    -- the student has limits of their own, replacing those of agent_setting
+    custom BOOLEAN NOT NULL DEFAULT FALSE,
+    daily_token_limit INTEGER,
+    daily_message_limit INTEGER,
+    -- the day of the last reset and the usage of that day before it, not counted
+    reset_day DATE,
+    reset_tokens INTEGER NOT NULL DEFAULT 0,
+    reset_messages INTEGER NOT NULL DEFAULT 0,
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE
+);
@@ -0,0 +1,16 @@
+-- the Git repository a student links to a book, e.g. the code of a programming course
+CREATE TABLE student_workspace (
+    student_id INTEGER NOT NULL,
+    book_id INTEGER NOT NULL,
+    -- an https clone url
+    url TEXT NOT NULL,
+    -- the default branch of the repository when null
+    branch TEXT,
+    link_time DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- the commit of the last fetch, null until the teacher first reads the code
+    fetched_commit TEXT,
+    fetch_time DATETIME,
+    PRIMARY KEY (student_id, book_id),
+    FOREIGN KEY (student_id) REFERENCES student(id) ON DELETE CASCADE,
+    FOREIGN KEY (book_id) REFERENCES book(id) ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+-- CJK characters are now indexed one token each, the index of a book is rebuilt on its next search
+DELETE FROM chapter_search;
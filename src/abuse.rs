@@ -0,0 +1,199 @@
+use std::{
+    collections::VecDeque,
+    hash::{DefaultHasher, Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use serde::Serialize;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::i18n;
+
+#[derive(Debug, Clone)]
+pub struct ThrottleConfig {
+    /// length of the sliding window
+    pub window: Duration,
+    /// messages allowed per student in the window
+    pub max_messages: usize,
+    /// identical messages allowed per student in the window
+    pub max_duplicates: usize,
+    /// how long an offender stays throttled
+    pub cooldown: Duration,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(60),
+            max_messages: 10,
+            max_duplicates: 3,
+            cooldown: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ThrottleReason {
+    RateLimit,
+    Duplicate,
+    /// the student used up their tokens of the day, see [`crate::quota`]
+    DailyTokens,
+    /// the student sent all their messages of the day
+    DailyMessages,
+}
+
+/// Returned to the client instead of a chat response when the student is throttled
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ThrottleEvent {
+    pub reason: ThrottleReason,
+    /// seconds until the student may send again
+    pub retry_after: u64,
+    /// explanation for the student, in their locale
+    pub message: String,
+}
+
+impl ThrottleEvent {
+    pub(crate) fn new(reason: ThrottleReason, retry_after: u64) -> Self {
+        Self {
+            reason,
+            retry_after,
+            message: String::new(),
+        }
+    }
+
+    pub fn localize(mut self, locale: &str) -> Self {
+        let id = match self.reason {
+            ThrottleReason::RateLimit => "error-rate-limit",
+            ThrottleReason::Duplicate => "error-duplicate",
+            ThrottleReason::DailyTokens => "error-daily-tokens",
+            ThrottleReason::DailyMessages => "error-daily-messages",
+        };
+        self.message = i18n::tr(
+            locale,
+            id,
+            &[
+                ("seconds", self.retry_after.into()),
+                ("hours", self.retry_after.div_ceil(3600).into()),
+            ],
+        );
+        self
+    }
+}
+
+#[derive(Debug, Default)]
+struct StudentWindow {
+    /// (time, hash of the normalized message)
+    recent: VecDeque<(Instant, u64)>,
+    blocked: Option<(Instant, ThrottleReason)>,
+}
+
+/// Sliding-window detection of rapid-fire and duplicated chat messages, per student
+#[derive(Debug, Default)]
+pub struct ChatThrottle {
+    config: ThrottleConfig,
+    students: DashMap<i64, StudentWindow>,
+}
+
+impl ChatThrottle {
+    pub fn new(config: ThrottleConfig) -> Self {
+        Self {
+            config,
+            students: DashMap::new(),
+        }
+    }
+
+    /// record the message, or refuse it if the student is sending too fast or repeating themselves
+    pub fn check(&self, student_id: i64, message: &str) -> Result<(), ThrottleEvent> {
+        self.check_at(student_id, message, Instant::now())
+    }
+
+    fn check_at(&self, student_id: i64, message: &str, now: Instant) -> Result<(), ThrottleEvent> {
+        let mut window = self.students.entry(student_id).or_default();
+        if let Some((until, reason)) = window.blocked {
+            if now < until {
+                return Err(ThrottleEvent::new(reason, (until - now).as_secs().max(1)));
+            }
+            window.blocked = None;
+        }
+        while let Some((time, _)) = window.recent.front() {
+            if now.duration_since(*time) < self.config.window {
+                break;
+            }
+            window.recent.pop_front();
+        }
+        let hash = message_hash(message);
+        let reason = if window.recent.len() >= self.config.max_messages {
+            Some(ThrottleReason::RateLimit)
+        } else if window.recent.iter().filter(|(_, h)| *h == hash).count()
+            >= self.config.max_duplicates
+        {
+            Some(ThrottleReason::Duplicate)
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            warn!(
+                student_id,
+                ?reason,
+                messages = window.recent.len(),
+                "throttling chat of student"
+            );
+            window.blocked = Some((now + self.config.cooldown, reason));
+            return Err(ThrottleEvent::new(
+                reason,
+                self.config.cooldown.as_secs().max(1),
+            ));
+        }
+        window.recent.push_back((now, hash));
+        Ok(())
+    }
+
+    /// drop students with no recent activity, call it periodically to bound memory
+    pub fn prune(&self) {
+        let now = Instant::now();
+        self.students.retain(|_, window| {
+            window.blocked.is_some_and(|(until, _)| now < until)
+                || window
+                    .recent
+                    .back()
+                    .is_some_and(|(time, _)| now.duration_since(*time) < self.config.window)
+        });
+    }
+}
+
+fn message_hash(message: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    message
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn throttle() {
+    let throttle = ChatThrottle::new(ThrottleConfig {
+        max_messages: 3,
+        max_duplicates: 2,
+        ..Default::default()
+    });
+    let start = Instant::now();
+    assert!(throttle.check_at(1, "hi", start).is_ok());
+    assert!(throttle.check_at(1, "HI ", start).is_ok());
+    let event = throttle.check_at(1, "hi", start).unwrap_err();
+    assert_eq!(event.reason, ThrottleReason::Duplicate);
+    // still blocked during the cooldown, other students are not affected
+    assert!(throttle.check_at(1, "other", start).is_err());
+    assert!(throttle.check_at(2, "hi", start).is_ok());
+
+    let later = start + Duration::from_secs(61);
+    assert!(throttle.check_at(1, "a", later).is_ok());
+    assert!(throttle.check_at(1, "b", later).is_ok());
+    assert!(throttle.check_at(1, "c", later).is_ok());
+    let event = throttle.check_at(1, "d", later).unwrap_err();
+    assert_eq!(event.reason, ThrottleReason::RateLimit);
+}
@@ -0,0 +1,252 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use utoipa::ToSchema;
+
+use crate::i18n;
+
+/// the `book_id` of the profile on every book of the student
+const ALL_BOOKS: i64 = 0;
+
+/// a BCP 47 language tag, e.g. "fr" or "pt-BR"
+static LANGUAGE_TAG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[A-Za-z]{2,3}(-[A-Za-z0-9]{2,8})*$").unwrap());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Tone {
+    Friendly,
+    Formal,
+    Playful,
+    Encouraging,
+}
+
+impl Tone {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Friendly => "friendly",
+            Self::Formal => "formal",
+            Self::Playful => "playful",
+            Self::Encouraging => "encouraging",
+        }
+    }
+}
+
+/// How closely the teacher holds the student to correct and complete answers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Strictness {
+    Lenient,
+    Balanced,
+    Strict,
+}
+
+impl Strictness {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Lenient => "lenient",
+            Self::Balanced => "balanced",
+            Self::Strict => "strict",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Verbosity {
+    Concise,
+    Normal,
+    Detailed,
+}
+
+impl Verbosity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Concise => "concise",
+            Self::Normal => "normal",
+            Self::Detailed => "detailed",
+        }
+    }
+}
+
+/// How the teacher talks to a student, unset fields keep the default of the instructions
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct AgentProfile {
+    pub tone: Option<Tone>,
+    pub strictness: Option<Strictness>,
+    /// the language the teacher explains in, a BCP 47 tag like "fr" or "pt-BR", the language of
+    /// the student's locale if unset
+    pub instruction_language: Option<String>,
+    pub verbosity: Option<Verbosity>,
+    pub emojis: Option<bool>,
+}
+
+impl AgentProfile {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match &self.instruction_language {
+            Some(language) if !LANGUAGE_TAG.is_match(language) => {
+                anyhow::bail!("Invalid language tag: {}", language)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// this profile with the fields it leaves unset taken from `fallback`
+    pub fn or(self, fallback: Self) -> Self {
+        Self {
+            tone: self.tone.or(fallback.tone),
+            strictness: self.strictness.or(fallback.strictness),
+            instruction_language: self.instruction_language.or(fallback.instruction_language),
+            verbosity: self.verbosity.or(fallback.verbosity),
+            emojis: self.emojis.or(fallback.emojis),
+        }
+    }
+
+    /// the teaching style for the system prompt of the teacher, `None` when nothing is set
+    pub fn prompt(&self, locale: &str) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut lines = Vec::new();
+        let mut line = |key: &str, value: &str| {
+            lines.push(i18n::tr(
+                locale,
+                key,
+                &[("value", value.to_string().into())],
+            ));
+        };
+        if let Some(tone) = self.tone {
+            line("teacher-style-tone", tone.as_str());
+        }
+        if let Some(strictness) = self.strictness {
+            line("teacher-style-strictness", strictness.as_str());
+        }
+        if let Some(language) = &self.instruction_language {
+            line("teacher-style-language", language);
+        }
+        if let Some(verbosity) = self.verbosity {
+            line("teacher-style-verbosity", verbosity.as_str());
+        }
+        if let Some(emojis) = self.emojis {
+            line("teacher-style-emojis", if emojis { "yes" } else { "no" });
+        }
+        Some(i18n::tr(
+            locale,
+            "teacher-agent-profile",
+            &[("styles", lines.join("\n").into())],
+        ))
+    }
+}
+
+/// the profile set for the student on `book_id`, or on every book when `None`
+pub async fn get_profile(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: Option<i64>,
+) -> anyhow::Result<Option<AgentProfile>> {
+    let book_id = book_id.unwrap_or(ALL_BOOKS);
+    let profile = sqlx::query_as!(
+        AgentProfile,
+        r#"select tone as "tone: Tone", strictness as "strictness: Strictness",
+        instruction_language, verbosity as "verbosity: Verbosity", emojis as "emojis: bool"
+        from agent_profile where student_id = ? and book_id = ?"#,
+        student_id,
+        book_id
+    )
+    .fetch_optional(database)
+    .await?;
+    Ok(profile)
+}
+
+/// the profile the teacher follows on the book: the one of the book over the student-wide one
+pub async fn effective_profile(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+) -> anyhow::Result<AgentProfile> {
+    let book = get_profile(database, student_id, Some(book_id)).await?;
+    let student = get_profile(database, student_id, None).await?;
+    Ok(book.unwrap_or_default().or(student.unwrap_or_default()))
+}
+
+/// set the profile of the student on `book_id`, or on every book when `None`, replacing it
+pub async fn set_profile(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: Option<i64>,
+    profile: &AgentProfile,
+) -> anyhow::Result<()> {
+    profile.validate()?;
+    let book_id = book_id.unwrap_or(ALL_BOOKS);
+    sqlx::query!(
+        "insert into agent_profile
+        (student_id, book_id, tone, strictness, instruction_language, verbosity, emojis)
+        values (?, ?, ?, ?, ?, ?, ?)
+        on conflict (student_id, book_id) do update set
+        tone = excluded.tone, strictness = excluded.strictness,
+        instruction_language = excluded.instruction_language, verbosity = excluded.verbosity,
+        emojis = excluded.emojis, update_time = CURRENT_TIMESTAMP",
+        student_id,
+        book_id,
+        profile.tone,
+        profile.strictness,
+        profile.instruction_language,
+        profile.verbosity,
+        profile.emojis
+    )
+    .execute(database)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_profile(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: Option<i64>,
+) -> anyhow::Result<()> {
+    let book_id = book_id.unwrap_or(ALL_BOOKS);
+    sqlx::query!(
+        "delete from agent_profile where student_id = ? and book_id = ?",
+        student_id,
+        book_id
+    )
+    .execute(database)
+    .await?;
+    Ok(())
+}
+
+#[test]
+fn profile() {
+    let book = AgentProfile {
+        tone: Some(Tone::Playful),
+        ..Default::default()
+    };
+    let student = AgentProfile {
+        tone: Some(Tone::Formal),
+        verbosity: Some(Verbosity::Concise),
+        ..Default::default()
+    };
+    let effective = book.or(student);
+    assert_eq!(effective.tone, Some(Tone::Playful));
+    assert_eq!(effective.verbosity, Some(Verbosity::Concise));
+    assert!(AgentProfile::default().prompt("en").is_none());
+    let invalid = AgentProfile {
+        instruction_language: Some("French!".to_string()),
+        ..Default::default()
+    };
+    assert!(invalid.validate().is_err());
+    assert!(
+        serde_json::from_str::<AgentProfile>(r#"{"tone": "sarcastic"}"#).is_err()
+            && serde_json::from_str::<AgentProfile>(r#"{"humor": true}"#).is_err()
+    );
+}
@@ -1,41 +1,774 @@
-use std::sync::LazyLock;
+use std::{
+    str::FromStr,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
 
+use anyhow::Context;
 use async_openai::{
     Client,
-    config::OpenAIConfig,
+    config::{AzureConfig, Config, OpenAIConfig},
+    error::OpenAIError,
     types::{
-        ChatCompletionNamedToolChoice, ChatCompletionRequestMessage, ChatCompletionTool,
-        ChatCompletionToolChoiceOption, ChatCompletionToolType, CreateChatCompletionRequestArgs,
-        FunctionName, FunctionObject,
+        ChatCompletionNamedToolChoice, ChatCompletionRequestMessage,
+        ChatCompletionRequestMessageContentPartImage, ChatCompletionRequestMessageContentPartText,
+        ChatCompletionRequestUserMessage, ChatCompletionRequestUserMessageContent,
+        ChatCompletionRequestUserMessageContentPart, ChatCompletionResponseStream,
+        ChatCompletionTool, ChatCompletionToolChoiceOption, ChatCompletionToolType,
+        CompletionUsage, CreateChatCompletionRequest, CreateChatCompletionRequestArgs,
+        CreateChatCompletionResponse, CreateEmbeddingRequest, CreateEmbeddingResponse,
+        FunctionName, FunctionObject, ImageDetail, ImageUrl,
     },
 };
-
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use dashmap::DashMap;
+use futures::{FutureExt, StreamExt, TryStreamExt, future::BoxFuture};
+use parking_lot::Mutex;
 use schemars::{JsonSchema, schema_for};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use serde_json::json;
+use sqlx::SqlitePool;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{books::text, generation_log, usage};
+
+/// the api version of Azure OpenAI providers that don't set one
+pub const AZURE_API_VERSION: &str = "2024-10-21";
+
+/// prefix of the environment variables configured providers read their api key from, so a
+/// provider can't send other secrets of the server, like `OPENAI_API_KEY`, to its url
+pub const API_KEY_ENV_PREFIX: &str = "AI_PROVIDER_KEY_";
+
+/// An LLM backend, every request is sent to its model and through its circuit breaker, and
+/// retried by its retry policy when it fails transiently
+pub trait Provider: Send + Sync {
+    fn model(&self) -> &str;
+    fn breaker(&self) -> &CircuitBreaker;
+    fn retry_policy(&self) -> &RetryPolicy;
+    fn chat(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> BoxFuture<'_, anyhow::Result<CreateChatCompletionResponse>>;
+    fn chat_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> BoxFuture<'_, anyhow::Result<ChatCompletionResponseStream>>;
+    /// the embedding model is part of the request, stored vectors only compare within one model
+    fn embeddings(
+        &self,
+        request: CreateEmbeddingRequest,
+    ) -> BoxFuture<'_, anyhow::Result<CreateEmbeddingResponse>>;
+}
+
+/// OpenAI, Azure OpenAI and Ollama all speak the OpenAI API, only the configuration differs
+pub struct OpenAiCompatible<C: Config> {
+    client: Client<C>,
+    model: String,
+    breaker: CircuitBreaker,
+    retry: RetryPolicy,
+}
+
+impl<C: Config> OpenAiCompatible<C> {
+    pub fn new(config: C, model: impl Into<String>) -> Self {
+        Self {
+            client: Client::with_config(config),
+            model: model.into(),
+            breaker: CircuitBreaker::from_env(),
+            retry: RetryPolicy::from_env(),
+        }
+    }
+}
+
+impl<C: Config + 'static> Provider for OpenAiCompatible<C> {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    fn chat(
+        &self,
+        mut request: CreateChatCompletionRequest,
+    ) -> BoxFuture<'_, anyhow::Result<CreateChatCompletionResponse>> {
+        request.model = self.model.clone();
+        let (client, breaker) = (&self.client, &self.breaker);
+        self.retry
+            .run(move || {
+                let request = request.clone();
+                breaker.call(async move { client.chat().create(request).await })
+            })
+            .boxed()
+    }
+
+    fn chat_stream(
+        &self,
+        mut request: CreateChatCompletionRequest,
+    ) -> BoxFuture<'_, anyhow::Result<ChatCompletionResponseStream>> {
+        request.model = self.model.clone();
+        let (client, breaker) = (&self.client, &self.breaker);
+        self.retry
+            .run(move || {
+                let request = request.clone();
+                async move {
+                    let mut stream = breaker
+                        .call(async move { client.chat().create_stream(request).await })
+                        .await?;
+                    // a refused request, like a 429, only shows as the first item of its stream
+                    match stream.next().await {
+                        Some(Err(e)) => {
+                            breaker.record(false);
+                            Err(e.into())
+                        }
+                        first => Ok(Box::pin(futures::stream::iter(first).chain(stream))
+                            as ChatCompletionResponseStream),
+                    }
+                }
+            })
+            .boxed()
+    }
+
+    fn embeddings(
+        &self,
+        request: CreateEmbeddingRequest,
+    ) -> BoxFuture<'_, anyhow::Result<CreateEmbeddingResponse>> {
+        let (client, breaker) = (&self.client, &self.breaker);
+        self.retry
+            .run(move || {
+                let request = request.clone();
+                breaker.call(async move { client.embeddings().create(request).await })
+            })
+            .boxed()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    #[serde(rename = "openai")]
+    OpenAi,
+    Azure,
+    /// Ollama or any other local server with an OpenAI compatible API
+    Ollama,
+}
+
+impl ProviderKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderKind::OpenAi => "openai",
+            ProviderKind::Azure => "azure",
+            ProviderKind::Ollama => "ollama",
+        }
+    }
+}
+
+impl FromStr for ProviderKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "openai" => Ok(ProviderKind::OpenAi),
+            "azure" => Ok(ProviderKind::Azure),
+            "ollama" => Ok(ProviderKind::Ollama),
+            _ => anyhow::bail!("Unknown provider kind: {}", s),
+        }
+    }
+}
+
+/// How to reach a provider, a row of the `ai_provider` table
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ProviderConfig {
+    pub kind: ProviderKind,
+    /// e.g. "https://api.openai.com/v1", "https://my-resource.openai.azure.com", "http://localhost:11434/v1"
+    pub base_url: String,
+    /// the model, the deployment for Azure
+    pub model: String,
+    /// the environment variable holding the api key, starting with [`API_KEY_ENV_PREFIX`], keys
+    /// are never stored in the database
+    pub api_key_env: Option<String>,
+    /// the api version of Azure, [`AZURE_API_VERSION`] if not set
+    pub api_version: Option<String>,
+}
 
-pub static AI_MODEL: LazyLock<String> = LazyLock::new(|| dotenvy::var("AI_MODEL").unwrap());
+impl ProviderConfig {
+    pub fn build(&self) -> anyhow::Result<Arc<dyn Provider>> {
+        let api_key = match &self.api_key_env {
+            Some(var) if !var.starts_with(API_KEY_ENV_PREFIX) => {
+                anyhow::bail!(
+                    "API key variable {var} must start with {API_KEY_ENV_PREFIX}, other variables of the server are not read"
+                );
+            }
+            Some(var) => Some(
+                dotenvy::var(var).with_context(|| format!("API key variable {var} is not set"))?,
+            ),
+            None => None,
+        };
+        self.build_with_key(api_key)
+    }
+
+    fn build_with_key(&self, api_key: Option<String>) -> anyhow::Result<Arc<dyn Provider>> {
+        Ok(match self.kind {
+            ProviderKind::OpenAi | ProviderKind::Ollama => {
+                // a local server needs no key, but must not receive the OPENAI_API_KEY default
+                let api_key = api_key.unwrap_or_else(|| self.kind.as_str().to_string());
+                let config = OpenAIConfig::default()
+                    .with_api_base(&self.base_url)
+                    .with_api_key(api_key);
+                Arc::new(OpenAiCompatible::new(config, &self.model))
+            }
+            ProviderKind::Azure => {
+                let api_key = api_key.ok_or(anyhow::anyhow!("Azure providers need api_key_env"))?;
+                let config = AzureConfig::new()
+                    .with_api_base(&self.base_url)
+                    .with_api_key(api_key)
+                    .with_deployment_id(&self.model)
+                    .with_api_version(self.api_version.as_deref().unwrap_or(AZURE_API_VERSION));
+                Arc::new(OpenAiCompatible::new(config, &self.model))
+            }
+        })
+    }
+}
 
-pub static AI_CLIENT: LazyLock<Client<OpenAIConfig>> = LazyLock::new(|| {
-    let api_key = dotenvy::var("OPENAI_API_KEY").unwrap();
-    let base_url = dotenvy::var("OPENAI_BASE_URL").unwrap();
-    let config = OpenAIConfig::default()
-        .with_api_base(base_url)
-        .with_api_key(api_key);
-    Client::with_config(config)
+/// the provider of the `OPENAI_BASE_URL`, `OPENAI_API_KEY` and `AI_MODEL` environment variables,
+/// used where no provider is configured
+pub static DEFAULT_PROVIDER: LazyLock<Arc<dyn Provider>> = LazyLock::new(|| {
+    ProviderConfig {
+        kind: ProviderKind::OpenAi,
+        base_url: dotenvy::var("OPENAI_BASE_URL").unwrap(),
+        model: dotenvy::var("AI_MODEL").unwrap(),
+        api_key_env: None,
+        api_version: None,
+    }
+    .build_with_key(Some(dotenvy::var("OPENAI_API_KEY").unwrap()))
+    .unwrap()
 });
 
+/// built providers by `ai_provider` id, rebuilt when the row changes
+static PROVIDERS: LazyLock<DashMap<i64, (ProviderConfig, Arc<dyn Provider>)>> =
+    LazyLock::new(DashMap::new);
+
+/// A configured provider
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ProviderInfo {
+    pub id: i64,
+    pub name: String,
+    #[serde(flatten)]
+    pub config: ProviderConfig,
+}
+
+/// the provider for a student reading a book, the most specific `agent_provider` row wins:
+/// the student on the book, the student, the book, then the one of `agent_setting`,
+/// and [`DEFAULT_PROVIDER`] if none is set; with both a student and a book its calls are
+/// [`usage::metered`]
+pub async fn resolve_provider(
+    database: &SqlitePool,
+    student_id: Option<i64>,
+    book_id: Option<i64>,
+) -> anyhow::Result<Arc<dyn Provider>> {
+    let provider = configured_provider(database, student_id, book_id).await?;
+    match (student_id, book_id) {
+        (Some(student_id), Some(book_id)) => {
+            Ok(usage::metered(provider, database, student_id, book_id))
+        }
+        _ => Ok(provider),
+    }
+}
+
+async fn configured_provider(
+    database: &SqlitePool,
+    student_id: Option<i64>,
+    book_id: Option<i64>,
+) -> anyhow::Result<Arc<dyn Provider>> {
+    let record = sqlx::query!(
+        r#"select id, kind, base_url, model, api_key_env, api_version from ai_provider
+        where id = coalesce(
+            (select ai_provider_id from agent_provider
+                where (student_id = ?1 or student_id is null) and (book_id = ?2 or book_id is null)
+                order by student_id is null, book_id is null limit 1),
+            (select ai_provider_id from agent_setting))"#,
+        student_id,
+        book_id
+    )
+    .fetch_optional(database)
+    .await?;
+    let Some(record) = record else {
+        return Ok(DEFAULT_PROVIDER.clone());
+    };
+    let config = ProviderConfig {
+        kind: record.kind.parse()?,
+        base_url: record.base_url,
+        model: record.model,
+        api_key_env: record.api_key_env,
+        api_version: record.api_version,
+    };
+    cached_provider(record.id, config)
+}
+
+/// the provider recognizing handwriting, see [`set_recognition_provider`], else the provider of
+/// the student on the book, metered either way
+pub async fn resolve_recognition_provider(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+) -> anyhow::Result<Arc<dyn Provider>> {
+    let record = sqlx::query!(
+        "select id, kind, base_url, model, api_key_env, api_version from ai_provider
+        where id = (select recognition_provider_id from agent_setting)"
+    )
+    .fetch_optional(database)
+    .await?;
+    let Some(record) = record else {
+        return resolve_provider(database, Some(student_id), Some(book_id)).await;
+    };
+    let config = ProviderConfig {
+        kind: record.kind.parse()?,
+        base_url: record.base_url,
+        model: record.model,
+        api_key_env: record.api_key_env,
+        api_version: record.api_version,
+    };
+    Ok(usage::metered(
+        cached_provider(record.id, config)?,
+        database,
+        student_id,
+        book_id,
+    ))
+}
+
+/// recognize handwriting with provider `provider_id`, a model that accepts images, `None` for
+/// the chat provider
+pub async fn set_recognition_provider(
+    database: &SqlitePool,
+    provider_id: Option<i64>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "update agent_setting set recognition_provider_id = ?",
+        provider_id
+    )
+    .execute(database)
+    .await?;
+    Ok(())
+}
+
+fn cached_provider(id: i64, config: ProviderConfig) -> anyhow::Result<Arc<dyn Provider>> {
+    if let Some(cached) = PROVIDERS.get(&id) {
+        if cached.0 == config {
+            return Ok(cached.1.clone());
+        }
+    }
+    let provider = config.build()?;
+    PROVIDERS.insert(id, (config, provider.clone()));
+    Ok(provider)
+}
+
+/// the models a conversation may choose: the default one and those of the configured providers
+pub async fn allowed_models(database: &SqlitePool) -> anyhow::Result<Vec<String>> {
+    let mut models = vec![DEFAULT_PROVIDER.model().to_string()];
+    let configured = sqlx::query_scalar!("select distinct model from ai_provider order by model")
+        .fetch_all(database)
+        .await?;
+    for model in configured {
+        if !models.contains(&model) {
+            models.push(model);
+        }
+    }
+    Ok(models)
+}
+
+/// the provider serving `model`, the first configured one or the default,
+/// fails for a model not in [`allowed_models`]
+pub async fn provider_for_model(
+    database: &SqlitePool,
+    model: &str,
+) -> anyhow::Result<Arc<dyn Provider>> {
+    let record = sqlx::query!(
+        "select id, kind, base_url, model, api_key_env, api_version from ai_provider where model = ? order by id limit 1",
+        model
+    )
+    .fetch_optional(database)
+    .await?;
+    let Some(record) = record else {
+        if model == DEFAULT_PROVIDER.model() {
+            return Ok(DEFAULT_PROVIDER.clone());
+        }
+        anyhow::bail!("Model not allowed: {}", model);
+    };
+    let config = ProviderConfig {
+        kind: record.kind.parse()?,
+        base_url: record.base_url,
+        model: record.model,
+        api_key_env: record.api_key_env,
+        api_version: record.api_version,
+    };
+    cached_provider(record.id, config)
+}
+
+pub async fn list_providers(database: &SqlitePool) -> anyhow::Result<Vec<ProviderInfo>> {
+    let records = sqlx::query!(
+        "select id, name, kind, base_url, model, api_key_env, api_version from ai_provider order by id"
+    )
+    .fetch_all(database)
+    .await?;
+    let mut providers = Vec::with_capacity(records.len());
+    for record in records {
+        providers.push(ProviderInfo {
+            id: record.id,
+            name: record.name,
+            config: ProviderConfig {
+                kind: record.kind.parse()?,
+                base_url: record.base_url,
+                model: record.model,
+                api_key_env: record.api_key_env,
+                api_version: record.api_version,
+            },
+        });
+    }
+    Ok(providers)
+}
+
+/// create or update the provider `name`, the config must build, e.g. its key variable must be set
+pub async fn set_provider(
+    database: &SqlitePool,
+    name: &str,
+    config: &ProviderConfig,
+) -> anyhow::Result<i64> {
+    config.build()?;
+    let kind = config.kind.as_str();
+    let id = sqlx::query_scalar!(
+        r#"insert into ai_provider (name, kind, base_url, model, api_key_env, api_version) values (?, ?, ?, ?, ?, ?)
+        on conflict (name) do update set kind = excluded.kind, base_url = excluded.base_url, model = excluded.model,
+        api_key_env = excluded.api_key_env, api_version = excluded.api_version
+        returning id as "id!: i64""#,
+        name,
+        kind,
+        config.base_url,
+        config.model,
+        config.api_key_env,
+        config.api_version
+    )
+    .fetch_one(database)
+    .await?;
+    Ok(id)
+}
+
+/// use provider `provider_id` for a student, a book, a student on a book, or every agent if
+/// neither is given, `None` removes the assignment
+pub async fn assign_provider(
+    database: &SqlitePool,
+    student_id: Option<i64>,
+    book_id: Option<i64>,
+    provider_id: Option<i64>,
+) -> anyhow::Result<()> {
+    if student_id.is_none() && book_id.is_none() {
+        sqlx::query!("update agent_setting set ai_provider_id = ?", provider_id)
+            .execute(database)
+            .await?;
+        return Ok(());
+    }
+    sqlx::query!(
+        "delete from agent_provider where student_id is ? and book_id is ?",
+        student_id,
+        book_id
+    )
+    .execute(database)
+    .await?;
+    if let Some(provider_id) = provider_id {
+        sqlx::query!(
+            "insert into agent_provider (student_id, book_id, ai_provider_id) values (?, ?, ?)",
+            student_id,
+            book_id,
+            provider_id
+        )
+        .execute(database)
+        .await?;
+    }
+    Ok(())
+}
+
+/// The provider is failing and requests are refused without calling it
+#[derive(Debug, Clone, Copy, thiserror::Error)]
+#[error("AI temporarily unavailable, retry in {retry_after} seconds")]
+pub struct AiUnavailable {
+    pub retry_after: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BreakerState {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// one probe request is in flight, its outcome closes or reopens the breaker
+    HalfOpen,
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    /// trips after `AI_BREAKER_FAILURES` (default 5) failures in a row and fails fast for
+    /// `AI_BREAKER_COOLDOWN` seconds (default 30) before letting one probe request through
+    pub fn from_env() -> Self {
+        let failure_threshold = dotenvy::var("AI_BREAKER_FAILURES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5);
+        let cooldown = dotenvy::var("AI_BREAKER_COOLDOWN")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30));
+        Self::new(failure_threshold, cooldown)
+    }
+
+    /// whether a request would be let through now, without taking the probe
+    pub fn check(&self) -> Result<(), AiUnavailable> {
+        let now = Instant::now();
+        match *self.state.lock() {
+            BreakerState::Open { until } if now < until => Err(AiUnavailable {
+                retry_after: (until - now).as_secs().max(1),
+            }),
+            BreakerState::HalfOpen => Err(AiUnavailable { retry_after: 1 }),
+            _ => Ok(()),
+        }
+    }
+
+    /// let a request through, after the cooldown only the first caller gets through as the probe
+    fn acquire_at(&self, now: Instant) -> Result<(), AiUnavailable> {
+        let mut state = self.state.lock();
+        match *state {
+            BreakerState::Closed { .. } => Ok(()),
+            BreakerState::Open { until } if now < until => Err(AiUnavailable {
+                retry_after: (until - now).as_secs().max(1),
+            }),
+            BreakerState::Open { .. } => {
+                info!("AI circuit breaker half-open, probing the provider");
+                *state = BreakerState::HalfOpen;
+                Ok(())
+            }
+            BreakerState::HalfOpen => Err(AiUnavailable { retry_after: 1 }),
+        }
+    }
+
+    fn record_at(&self, success: bool, now: Instant) {
+        let mut state = self.state.lock();
+        *state = match (*state, success) {
+            (BreakerState::HalfOpen, true) => {
+                info!("AI circuit breaker closed, the provider recovered");
+                BreakerState::Closed { failures: 0 }
+            }
+            (_, true) => BreakerState::Closed { failures: 0 },
+            (BreakerState::Closed { failures }, false) if failures + 1 < self.failure_threshold => {
+                BreakerState::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (BreakerState::Open { until }, false) => BreakerState::Open { until },
+            (_, false) => {
+                warn!(
+                    "AI circuit breaker open for {}s after provider failures",
+                    self.cooldown.as_secs()
+                );
+                BreakerState::Open {
+                    until: now + self.cooldown,
+                }
+            }
+        };
+    }
+
+    pub fn record(&self, success: bool) {
+        self.record_at(success, Instant::now());
+    }
+
+    /// run a provider call through the breaker, failing fast with [`AiUnavailable`] while it is open
+    pub async fn call<T, E>(&self, request: impl Future<Output = Result<T, E>>) -> anyhow::Result<T>
+    where
+        E: Into<anyhow::Error>,
+    {
+        self.acquire_at(Instant::now())?;
+        let result = request.await;
+        self.record(result.is_ok());
+        result.map_err(Into::into)
+    }
+}
+
+/// How provider calls failing transiently, on rate limits, server errors and dropped
+/// connections, are retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// attempts of a call, the first one included
+    pub max_attempts: u32,
+    /// backoff before the first retry, doubling with every retry
+    pub base_delay: Duration,
+    /// longest backoff, a provider asking to wait longer fails the call
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, base_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            base_delay,
+            max_delay,
+        }
+    }
+
+    /// makes `AI_RETRY_ATTEMPTS` attempts (default 3), backing off from half a second to at most
+    /// `AI_RETRY_MAX_DELAY` seconds (default 30)
+    pub fn from_env() -> Self {
+        let max_attempts = dotenvy::var("AI_RETRY_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3);
+        let max_delay = dotenvy::var("AI_RETRY_MAX_DELAY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(30));
+        Self::new(max_attempts, Duration::from_millis(500), max_delay)
+    }
+
+    /// the wait before retrying a call that failed with `error` on attempt `attempt`, counted
+    /// from 1, `None` to give up: the wait the provider asked for, else exponential backoff
+    /// with jitter so the retries of many students spread out
+    pub fn delay(&self, attempt: u32, error: &anyhow::Error) -> Option<Duration> {
+        if attempt >= self.max_attempts || !is_transient(error) {
+            return None;
+        }
+        if let Some(wait) = retry_after_hint(&format!("{error:#}")) {
+            return (wait <= self.max_delay).then_some(wait);
+        }
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(self.max_delay);
+        Some(backoff / 2 + backoff.mul_f64(rand::random::<f64>() / 2.0))
+    }
+
+    /// run `request` until it succeeds, fails for good or runs out of attempts
+    pub async fn run<T, F, Fut>(&self, mut request: F) -> anyhow::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match request().await {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            let Some(delay) = self.delay(attempt, &error) else {
+                return Err(error);
+            };
+            warn!(
+                "AI provider call failed, retry {attempt} in {}ms: {error:#}",
+                delay.as_millis()
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// whether a provider call that failed with `error` may succeed when retried: rate limits,
+/// server errors, timeouts and dropped connections, but not an exhausted quota, a bad request
+/// or an open circuit breaker
+pub fn is_transient(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<OpenAIError>() {
+        Some(OpenAIError::Reqwest(e)) => {
+            e.is_timeout()
+                || e.is_connect()
+                || e.status().is_some_and(|s| transient_status(s.as_u16()))
+        }
+        Some(OpenAIError::ApiError(e)) => {
+            let kinds = [e.r#type.as_deref(), e.code.as_deref()];
+            if kinds.contains(&Some("insufficient_quota")) {
+                return false;
+            }
+            let message = e.message.to_lowercase();
+            kinds.into_iter().flatten().any(|kind| {
+                matches!(
+                    kind,
+                    "rate_limit_exceeded"
+                        | "rate_limit_error"
+                        | "server_error"
+                        | "overloaded_error"
+                )
+            }) || message.contains("rate limit")
+                || message.contains("overloaded")
+        }
+        // the status of a refused stream only survives in the message
+        Some(OpenAIError::StreamError(message)) => match stream_status(message) {
+            Some(status) => transient_status(status),
+            None => message.contains("Transport error"),
+        },
+        _ => false,
+    }
+}
+
+fn transient_status(status: u16) -> bool {
+    status == 408 || status == 429 || (500..600).contains(&status)
+}
+
+/// the status code in messages like "Invalid status code: 429 Too Many Requests"
+fn stream_status(message: &str) -> Option<u16> {
+    let (_, rest) = message.split_once("status code: ")?;
+    rest.get(..3)?.parse().ok()
+}
+
+/// the wait a provider asks for in its error message, like OpenAI's "Please try again in 1.5s"
+/// or "retry after 20 seconds", since the Retry-After header doesn't reach the error
+fn retry_after_hint(message: &str) -> Option<Duration> {
+    let message = message.to_lowercase();
+    let (_, rest) = ["try again in ", "retry after ", "retry-after: "]
+        .iter()
+        .find_map(|marker| message.split_once(marker))?;
+    let number_len = rest
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(rest.len());
+    let value = rest[..number_len].parse::<f64>().ok()?;
+    let unit = rest[number_len..].trim_start();
+    let seconds = if unit.starts_with("ms") {
+        value / 1000.0
+    } else if unit.starts_with('m') {
+        value * 60.0
+    } else {
+        value
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
 pub trait Tokens {
     fn tokens(&self) -> u64;
 }
 impl Tokens for String {
     fn tokens(&self) -> u64 {
-        (self.len() + 2) as u64 / 4
+        usage::count_tokens(self)
     }
 }
 impl Tokens for str {
     fn tokens(&self) -> u64 {
-        (self.len() + 2) as u64 / 4
+        usage::count_tokens(self)
     }
 }
 impl Tokens for ChatCompletionRequestMessage {
@@ -60,7 +793,9 @@ impl Tokens for ChatCompletionRequestMessage {
                 }
             },
             ChatCompletionRequestMessage::Assistant(content) => {
-                match &content.content {
+                // the calls are in the context as well
+                let tool_calls: u64 = content.tool_calls.iter().flatten().map(|call| call.function.name.tokens() + call.function.arguments.tokens()).sum();
+                tool_calls + match &content.content {
                     Some(async_openai::types::ChatCompletionRequestAssistantMessageContent::Text(text)) => text.tokens(),
                     Some(async_openai::types::ChatCompletionRequestAssistantMessageContent::Array(parts)) => parts.iter().map(|p| match p{
                         async_openai::types::ChatCompletionRequestAssistantMessageContentPart::Text(text) => text.text.tokens(),
@@ -90,27 +825,208 @@ impl Tokens for ChatCompletionRequestMessage {
     }
 }
 
+/// most chunks summarized at the same time
+pub const MAX_PARALLELISM: usize = 16;
+
+/// How texts too long for one request are summarized: split into chunks on headings, then
+/// paragraphs, the chunks summarized concurrently and the result made from their summaries
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Chunking {
+    /// longest text summarized in one request, in tokens
+    pub chunk_tokens: usize,
+    /// chunks summarized at the same time
+    pub parallelism: usize,
+    /// length of the summary of a chunk, in words
+    pub chunk_length: usize,
+}
+
+impl Default for Chunking {
+    fn default() -> Self {
+        Self {
+            chunk_tokens: 12_000,
+            parallelism: 4,
+            chunk_length: 400,
+        }
+    }
+}
+
+impl Chunking {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.parallelism == 0 || self.chunk_length == 0 {
+            anyhow::bail!("parallelism and chunk-length must be at least 1");
+        }
+        if self.parallelism > MAX_PARALLELISM {
+            anyhow::bail!("parallelism must be at most {MAX_PARALLELISM}");
+        }
+        // the summaries of the chunks must fit in fewer chunks than the text for it to shrink
+        if self.chunk_tokens < self.chunk_length * 4 {
+            anyhow::bail!(
+                "chunk-tokens must be at least 4 times chunk-length ({})",
+                self.chunk_length * 4
+            );
+        }
+        Ok(())
+    }
+}
+
 pub async fn summarize(
+    provider: &dyn Provider,
+    content: &str,
+    limit: usize,
+    prompt: Option<String>,
+) -> anyhow::Result<String> {
+    summarize_chunked(provider, content, limit, prompt, &Chunking::default()).await
+}
+
+/// [`summarize`] splitting a long `content` as `chunking` says
+pub async fn summarize_chunked(
+    provider: &dyn Provider,
+    content: &str,
+    limit: usize,
+    prompt: Option<String>,
+    chunking: &Chunking,
+) -> anyhow::Result<String> {
+    let content = condense(provider, content, chunking).await?;
+    summarize_step(provider, "generate", &content, limit, prompt).await
+}
+
+/// `content`, or when it is longer than a chunk the summaries of its chunks, in order, repeated
+/// until they fit in one
+async fn condense(
+    provider: &dyn Provider,
+    content: &str,
+    chunking: &Chunking,
+) -> anyhow::Result<String> {
+    let mut content = content.to_string();
+    while content.tokens() > chunking.chunk_tokens as u64 {
+        let chunks = split_chunks(&content, chunking.chunk_tokens);
+        let count = chunks.len();
+        info!("summarizing {} tokens in {count} chunks", content.tokens());
+        let summaries: Vec<String> = futures::stream::iter(chunks.into_iter().enumerate())
+            .map(|(i, chunk)| {
+                let prompt = format!(
+                    "This is part {} of {count} of a longer text. Summarize it for a later summary \
+                    of the whole text: keep its headings, key concepts, examples and exercises.",
+                    i + 1
+                );
+                summarize_step(provider, "map", chunk, chunking.chunk_length, Some(prompt))
+            })
+            .buffered(chunking.parallelism.clamp(1, MAX_PARALLELISM))
+            .try_collect()
+            .await?;
+        let condensed = summaries.join("\n\n");
+        if condensed.len() >= content.len() {
+            warn!("chunk summaries don't shorten the text, summarizing it whole");
+            break;
+        }
+        content = condensed;
+    }
+    Ok(content)
+}
+
+/// `text` cut into chunks of at most `max_tokens`: between sections where it can, else between
+/// paragraphs, else anywhere
+fn split_chunks(text: &str, max_tokens: usize) -> Vec<&str> {
+    let max_len = max_tokens.max(1) * 4;
+    let mut pieces = Vec::new();
+    for section in cut(text, heading_starts(text)) {
+        if section.len() <= max_len {
+            pieces.push(section);
+            continue;
+        }
+        let paragraphs = section.match_indices("\n\n").map(|(i, _)| i + 2);
+        for paragraph in cut(section, paragraphs) {
+            if paragraph.len() <= max_len {
+                pieces.push(paragraph);
+                continue;
+            }
+            let mut rest = paragraph;
+            while rest.len() > max_len {
+                let mut at = max_len;
+                while !rest.is_char_boundary(at) {
+                    at -= 1;
+                }
+                pieces.push(&rest[..at]);
+                rest = &rest[at..];
+            }
+            pieces.push(rest);
+        }
+    }
+    // the pieces are consecutive, pack as many as fit in each chunk
+    let mut chunks = Vec::new();
+    let (mut start, mut end) = (0, 0);
+    for piece in pieces {
+        if end + piece.len() - start > max_len && end > start {
+            chunks.push(&text[start..end]);
+            start = end;
+        }
+        end += piece.len();
+    }
+    if end > start {
+        chunks.push(&text[start..end]);
+    }
+    chunks
+}
+
+/// `text` cut before each of the offsets `starts`, in order
+fn cut(text: &str, starts: impl IntoIterator<Item = usize>) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    for at in starts {
+        if at > start && at < text.len() {
+            pieces.push(&text[start..at]);
+            start = at;
+        }
+    }
+    pieces.push(&text[start..]);
+    pieces
+}
+
+/// offsets of the markdown headings of `text`, outside code blocks
+fn heading_starts(text: &str) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut offset = 0;
+    let mut in_code = false;
+    for line in text.split_inclusive('\n') {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        } else if !in_code && line.starts_with('#') {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+    starts
+}
+
+async fn summarize_step(
+    provider: &dyn Provider,
+    step: &str,
     content: &str,
     limit: usize,
     prompt: Option<String>,
 ) -> anyhow::Result<String> {
+    let limit = text::length_limit(limit, content);
     let prompt = match prompt {
         Some(prompt) => format!(
-            "{prompt}\n\nProvide a concise result of the following text in {} words or less. Return only the result without any additional text or explanation:\n{}",
+            "{prompt}\n\nProvide a concise result of the following text in {} or less. Return only the result without any additional text or explanation:\n{}",
             limit, content
         ),
         None => format!(
-            "Provide a concise summary of the following text in {} words or less. Return only the summary without any additional text or explanation:\n{}",
+            "Provide a concise summary of the following text in {} or less. Return only the summary without any additional text or explanation:\n{}",
             limit, content
         ),
     };
     let request = CreateChatCompletionRequestArgs::default()
-        .model(AI_MODEL.as_str())
-        .messages(vec![ChatCompletionRequestMessage::User(prompt.into())])
+        .model(provider.model())
+        .messages(vec![ChatCompletionRequestMessage::User(
+            prompt.clone().into(),
+        )])
         .build()
         .unwrap();
-    let response = AI_CLIENT.chat().create(request).await?;
+    let start = Instant::now();
+    let response = provider.chat(request).await?;
+    log_generation(provider, step, &prompt, response.usage.as_ref(), start).await;
     let summary = response
         .choices
         .first()
@@ -122,31 +1038,191 @@ pub async fn summarize(
     Ok(summary)
 }
 
-pub async fn extract_key_points(content: &str) -> anyhow::Result<Vec<String>> {
+/// How the candidates of a self-consistent generation are combined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JudgeMode {
+    /// a judge call picks the best candidate
+    #[default]
+    Select,
+    /// a judge call merges the strongest parts of all candidates
+    Merge,
+}
+
+/// most candidates of a self-consistent generation
+pub const MAX_CANDIDATES: usize = 5;
+
+/// Generate `candidates` results for an artifact and combine them with a judge call,
+/// one candidate (the default) skips the judge
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SelfConsistency {
+    pub candidates: usize,
+    pub judge: JudgeMode,
+}
+
+impl Default for SelfConsistency {
+    fn default() -> Self {
+        Self {
+            candidates: 1,
+            judge: JudgeMode::Select,
+        }
+    }
+}
+
+impl SelfConsistency {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !(1..=MAX_CANDIDATES).contains(&self.candidates) {
+            anyhow::bail!("candidates must be between 1 and {MAX_CANDIDATES}");
+        }
+        Ok(())
+    }
+}
+
+/// [`summarize_chunked`] with self-consistency: several candidates, then the best or a merge of
+/// them; a long `content` is condensed once for all of them
+pub async fn summarize_consistent(
+    provider: &dyn Provider,
+    content: &str,
+    limit: usize,
+    prompt: Option<String>,
+    consistency: &SelfConsistency,
+    chunking: &Chunking,
+) -> anyhow::Result<String> {
+    let content = condense(provider, content, chunking).await?;
+    let mut candidates = futures::future::try_join_all(
+        (0..consistency.candidates.clamp(1, MAX_CANDIDATES))
+            .map(|_| summarize_step(provider, "generate", &content, limit, prompt.clone())),
+    )
+    .await?;
+    if candidates.len() == 1 {
+        return Ok(candidates.remove(0));
+    }
+    let task = prompt.unwrap_or_else(|| "Summarize the text.".to_string());
+    let numbered: String = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, candidate)| format!("## Candidate {i}\n{candidate}\n\n"))
+        .collect();
+    match consistency.judge {
+        JudgeMode::Select => {
+            /// The verdict of the judge
+            #[derive(Debug, JsonSchema, Deserialize)]
+            struct Verdict {
+                /// number of the best candidate
+                best: usize,
+                /// why it is the best
+                reason: String,
+            }
+            let judge_prompt = format!(
+                "Several candidates were generated for the following task. \
+                Pick the candidate that is most faithful to the source text, most complete and best structured.\n\n\
+                # Task\n{task}\n\n# Source Text\n{content}\n\n# Candidates\n{numbered}"
+            );
+            let verdict: Verdict = extract_step(provider, "judge", judge_prompt).await?;
+            info!(
+                "judge picked candidate {} of {}: {}",
+                verdict.best,
+                candidates.len(),
+                verdict.reason
+            );
+            let best = verdict.best.min(candidates.len() - 1);
+            Ok(candidates.swap_remove(best))
+        }
+        JudgeMode::Merge => {
+            let merge_prompt = format!(
+                "The following candidates were generated for this task:\n{task}\n\n\
+                Merge them into one result that keeps the most accurate and complete parts of each, in the format the task asks for."
+            );
+            summarize_step(provider, "merge", &numbered, limit, Some(merge_prompt)).await
+        }
+    }
+}
+
+pub async fn extract_key_points(
+    provider: &dyn Provider,
+    content: &str,
+) -> anyhow::Result<Vec<String>> {
     #[derive(Debug, JsonSchema, Serialize, Deserialize)]
     struct KeyPoints(Vec<String>);
-    let tool = extract_tool::<KeyPoints>(None);
+    let prompt = format!(
+        "Extract the key points from the following text:\n{}",
+        content
+    );
+    let key_points: KeyPoints = extract(provider, prompt).await?;
+    Ok(key_points.0)
+}
+
+/// ask the model for structured output, forcing a call of a tool with the schema of `T`
+pub async fn extract<T: JsonSchema + DeserializeOwned>(
+    provider: &dyn Provider,
+    prompt: String,
+) -> anyhow::Result<T> {
+    extract_step(provider, "extract", prompt).await
+}
+
+/// [`extract`] from a PNG image and the prompt about it, the model must accept images
+pub async fn extract_from_image<T: JsonSchema + DeserializeOwned>(
+    provider: &dyn Provider,
+    prompt: String,
+    png: &[u8],
+) -> anyhow::Result<T> {
+    let image = ImageUrl {
+        url: format!("data:image/png;base64,{}", BASE64.encode(png)),
+        detail: Some(ImageDetail::High),
+    };
+    let content = ChatCompletionRequestUserMessageContent::Array(vec![
+        ChatCompletionRequestUserMessageContentPart::Text(
+            ChatCompletionRequestMessageContentPartText {
+                text: prompt.clone(),
+            },
+        ),
+        ChatCompletionRequestUserMessageContentPart::ImageUrl(
+            ChatCompletionRequestMessageContentPartImage { image_url: image },
+        ),
+    ]);
+    extract_content(provider, "extract_image", prompt, content).await
+}
+
+async fn extract_step<T: JsonSchema + DeserializeOwned>(
+    provider: &dyn Provider,
+    step: &str,
+    prompt: String,
+) -> anyhow::Result<T> {
+    let content = ChatCompletionRequestUserMessageContent::Text(prompt.clone());
+    extract_content(provider, step, prompt, content).await
+}
+
+/// `prompt` is the text of `content`, for the generation log
+async fn extract_content<T: JsonSchema + DeserializeOwned>(
+    provider: &dyn Provider,
+    step: &str,
+    prompt: String,
+    content: ChatCompletionRequestUserMessageContent,
+) -> anyhow::Result<T> {
+    let tool = extract_tool::<T>(None);
     let tool_choice = ChatCompletionToolChoiceOption::Named(ChatCompletionNamedToolChoice {
         r#type: ChatCompletionToolType::Function,
         function: FunctionName {
             name: tool.function.name.clone(),
         },
     });
-    let prompt = format!(
-        "Extract the key points from the following text:\n{}",
-        content
-    );
     let request = CreateChatCompletionRequestArgs::default()
-        .model(AI_MODEL.as_str())
-        .messages(vec![ChatCompletionRequestMessage::User(prompt.into())])
+        .model(provider.model())
+        .messages(vec![ChatCompletionRequestMessage::User(
+            ChatCompletionRequestUserMessage {
+                content,
+                name: None,
+            },
+        )])
         .tools(vec![tool])
         .tool_choice(tool_choice)
         .build()
         .unwrap();
-    let response = AI_CLIENT
-        .chat()
-        .create(request)
-        .await?
+    let start = Instant::now();
+    let response = provider.chat(request).await?;
+    log_generation(provider, step, &prompt, response.usage.as_ref(), start).await;
+    let response = response
         .choices
         .first()
         .ok_or(anyhow::anyhow!("No response from OpenAI"))?
@@ -158,8 +1234,28 @@ pub async fn extract_key_points(content: &str) -> anyhow::Result<Vec<String>> {
         .function
         .arguments
         .clone();
-    let key_points: KeyPoints = serde_json::from_str(&response)?;
-    Ok(key_points.0)
+    Ok(serde_json::from_str(&response)?)
+}
+
+async fn log_generation(
+    provider: &dyn Provider,
+    step: &str,
+    prompt: &str,
+    usage: Option<&CompletionUsage>,
+    start: Instant,
+) {
+    let (prompt_tokens, completion_tokens) = usage
+        .map(|usage| (usage.prompt_tokens, usage.completion_tokens))
+        .unwrap_or_default();
+    generation_log::record(
+        step,
+        provider.model(),
+        prompt,
+        prompt_tokens,
+        completion_tokens,
+        start.elapsed(),
+    )
+    .await;
 }
 
 pub fn extract_tool<T: JsonSchema>(strict: Option<bool>) -> ChatCompletionTool {
@@ -174,6 +1270,97 @@ pub fn extract_tool<T: JsonSchema>(strict: Option<bool>) -> ChatCompletionTool {
     }
 }
 
+#[test]
+fn retry_policy() {
+    let policy = RetryPolicy::new(3, Duration::from_millis(100), Duration::from_secs(1));
+    let refused = |message: &str| anyhow::Error::from(OpenAIError::StreamError(message.into()));
+    let unavailable = refused("Invalid status code: 503 Service Unavailable");
+    let first = policy.delay(1, &unavailable).unwrap();
+    assert!((Duration::from_millis(50)..=Duration::from_millis(100)).contains(&first));
+    let second = policy.delay(2, &unavailable).unwrap();
+    assert!((Duration::from_millis(100)..=Duration::from_millis(200)).contains(&second));
+    assert!(policy.delay(3, &unavailable).is_none());
+    assert!(
+        policy
+            .delay(1, &refused("Invalid status code: 400 Bad Request"))
+            .is_none()
+    );
+    assert!(
+        policy
+            .delay(1, &anyhow::Error::from(AiUnavailable { retry_after: 5 }))
+            .is_none()
+    );
+    let limited = refused(
+        "Invalid status code: 429 Too Many Requests, Rate limit reached. Please try again in 0.5s.",
+    );
+    assert_eq!(policy.delay(1, &limited), Some(Duration::from_millis(500)));
+    // a provider asking to wait longer than the policy allows fails the call
+    let later = refused("Invalid status code: 429 Too Many Requests, retry after 20 seconds");
+    assert!(policy.delay(1, &later).is_none());
+    assert_eq!(
+        retry_after_hint("try again in 250ms"),
+        Some(Duration::from_millis(250))
+    );
+    assert_eq!(
+        retry_after_hint("Try again in 2m"),
+        Some(Duration::from_secs(120))
+    );
+    assert_eq!(retry_after_hint("no hint"), None);
+}
+
+#[test]
+fn circuit_breaker() {
+    let breaker = CircuitBreaker::new(3, Duration::from_secs(30));
+    let start = Instant::now();
+    for _ in 0..2 {
+        breaker.record_at(false, start);
+    }
+    assert!(breaker.acquire_at(start).is_ok());
+    // a success resets the count
+    breaker.record_at(true, start);
+    for _ in 0..3 {
+        breaker.record_at(false, start);
+    }
+    let refused = breaker
+        .acquire_at(start + Duration::from_secs(10))
+        .unwrap_err();
+    assert_eq!(refused.retry_after, 20);
+    // after the cooldown one probe goes through, the others keep failing fast
+    let after = start + Duration::from_secs(31);
+    assert!(breaker.acquire_at(after).is_ok());
+    assert!(breaker.acquire_at(after).is_err());
+    breaker.record_at(false, after);
+    assert!(breaker.acquire_at(after + Duration::from_secs(1)).is_err());
+    let later = after + Duration::from_secs(31);
+    assert!(breaker.acquire_at(later).is_ok());
+    breaker.record_at(true, later);
+    assert!(breaker.acquire_at(later).is_ok());
+}
+
+#[test]
+fn chunks() {
+    let section = |title: &str| format!("# {title}\n\n{}\n\n", "word ".repeat(150));
+    let text: String = ["One", "Two", "Three"].map(section).concat();
+    // each section is about 200 tokens, two fit in a chunk
+    let chunks = split_chunks(&text, 400);
+    assert_eq!(chunks.len(), 2);
+    assert!(chunks[0].starts_with("# One") && chunks[1].starts_with("# Three"));
+    assert_eq!(chunks.concat(), text);
+    // a heading in a code block doesn't start a section, a long paragraph is cut anywhere
+    let code = "```sh\n# comment\n```\n";
+    assert_eq!(heading_starts(code), Vec::<usize>::new());
+    let long = "长".repeat(1000);
+    let chunks = split_chunks(&long, 100);
+    assert!(chunks.iter().all(|chunk| chunk.len() <= 400));
+    assert_eq!(chunks.concat(), long);
+    assert!(Chunking::default().validate().is_ok());
+    let tiny = Chunking {
+        chunk_tokens: 100,
+        ..Default::default()
+    };
+    assert!(tiny.validate().is_err());
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -260,3 +1447,17 @@ mod tests {
         println!("{:#?}", response);
     }
 }
+
+#[test]
+fn provider_key_env() {
+    let config = |api_key_env: Option<&str>| ProviderConfig {
+        kind: ProviderKind::Ollama,
+        base_url: "http://localhost:11434/v1".to_string(),
+        model: "llama3".to_string(),
+        api_key_env: api_key_env.map(str::to_string),
+        api_version: None,
+    };
+    assert!(config(None).build().is_ok());
+    assert!(config(Some("OPENAI_API_KEY")).build().is_err());
+    assert!(config(Some("MESSAGE_MASTER_KEY")).build().is_err());
+}
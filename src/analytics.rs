@@ -0,0 +1,715 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use sha2::Sha256;
+use sqlx::SqlitePool;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use crate::teacher::messages::progress::{ChapterObjective, ChapterStatus};
+
+/// events newer than this are left for the next run, writes still in flight would be missed
+const EXPORT_LAG: time::Duration = time::Duration::minutes(1);
+const SINK_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnalyticsEventKind {
+    /// a finished focus session
+    Session,
+    /// the progress of a chapter changed
+    Progress,
+    /// a graded quiz submission
+    Grade,
+}
+
+impl AnalyticsEventKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Session => "session",
+            Self::Progress => "progress",
+            Self::Grade => "grade",
+        }
+    }
+}
+
+/// What happens to a field of the exported events
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldAction {
+    Keep,
+    Drop,
+    /// replaced by a keyed hash, the same value gets the same pseudonym in every export
+    Pseudonymize,
+    /// a time cut to its date
+    Day,
+}
+
+/// Where the events go
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "sink", rename_all = "snake_case")]
+pub enum SinkConfig {
+    /// the HTTP interface of ClickHouse, rows are inserted as JSONEachRow
+    Clickhouse {
+        url: String,
+        table: String,
+        /// environment variables with the credentials
+        user_env: Option<String>,
+        password_env: Option<String>,
+    },
+    /// the streaming insert API of BigQuery
+    Bigquery {
+        project: String,
+        dataset: String,
+        table: String,
+        /// environment variable with the path of a service account key file, exchanged for
+        /// access tokens that are refreshed before they expire;
+        /// `GOOGLE_APPLICATION_CREDENTIALS` unless `token_env` is given
+        credentials_env: Option<String>,
+        /// environment variable with an OAuth access token kept fresh outside the server,
+        /// instead of a service account
+        token_env: Option<String>,
+    },
+    /// a newline-delimited JSON file per run in a directory, e.g. synced to object storage
+    Directory { path: PathBuf },
+}
+
+/// The analytics export of a deployment, see [`AnalyticsExporter::from_json`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExportConfig {
+    /// keys the exported range, a new name exports everything again
+    #[serde(default = "default_name")]
+    pub name: String,
+    #[serde(default = "default_interval")]
+    pub interval_minutes: u64,
+    /// the events exported, all by default
+    #[serde(default = "all_events")]
+    pub events: Vec<AnalyticsEventKind>,
+    /// the action per field name, `student_id` is pseudonymized and other fields are kept
+    /// unless listed
+    #[serde(default)]
+    pub fields: BTreeMap<String, FieldAction>,
+    /// environment variable with the pseudonymization key
+    #[serde(default = "default_key_env")]
+    pub key_env: String,
+    pub sink: SinkConfig,
+}
+
+fn default_name() -> String {
+    "default".to_string()
+}
+
+fn default_interval() -> u64 {
+    60
+}
+
+fn all_events() -> Vec<AnalyticsEventKind> {
+    vec![
+        AnalyticsEventKind::Session,
+        AnalyticsEventKind::Progress,
+        AnalyticsEventKind::Grade,
+    ]
+}
+
+fn default_key_env() -> String {
+    "ANALYTICS_KEY".to_string()
+}
+
+/// Applies the field actions to the events before they leave the server
+#[derive(Clone)]
+pub struct Anonymizer {
+    fields: BTreeMap<String, FieldAction>,
+    key: Vec<u8>,
+}
+
+impl Debug for Anonymizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Anonymizer")
+            .field("fields", &self.fields)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Anonymizer {
+    pub fn new(fields: BTreeMap<String, FieldAction>, key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            fields,
+            key: key.into(),
+        }
+    }
+
+    fn action(&self, field: &str) -> FieldAction {
+        match self.fields.get(field) {
+            Some(action) => *action,
+            None if field == "student_id" => FieldAction::Pseudonymize,
+            None => FieldAction::Keep,
+        }
+    }
+
+    fn pseudonym(&self, value: &Value) -> String {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("any key length");
+        mac.update(value.as_bytes());
+        mac.finalize().into_bytes()[..16]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    pub fn apply(&self, event: Map<String, Value>) -> Map<String, Value> {
+        event
+            .into_iter()
+            .filter_map(|(field, value)| {
+                let value = match self.action(&field) {
+                    FieldAction::Keep => value,
+                    FieldAction::Drop => return None,
+                    // missing values stay missing
+                    _ if value.is_null() => value,
+                    FieldAction::Pseudonymize => Value::String(self.pseudonym(&value)),
+                    FieldAction::Day => match value
+                        .as_str()
+                        .and_then(|time| OffsetDateTime::parse(time, &Rfc3339).ok())
+                    {
+                        Some(time) => Value::String(time.date().to_string()),
+                        None => value,
+                    },
+                };
+                Some((field, value))
+            })
+            .collect()
+    }
+}
+
+/// the value of the environment variable `name`, credentials are read when they are used so
+/// they never sit in the config or the logs
+fn env(name: &Option<String>) -> anyhow::Result<Option<String>> {
+    name.as_ref()
+        .map(|name| std::env::var(name).with_context(|| format!("{name} is not set")))
+        .transpose()
+}
+
+/// Receives the anonymized events of an export run
+pub trait AnalyticsSink: Send + Sync + Debug {
+    fn send<'a>(&'a self, events: &'a [Value]) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+#[derive(Debug, Clone)]
+pub struct ClickhouseSink {
+    client: reqwest::Client,
+    url: String,
+    table: String,
+    user_env: Option<String>,
+    password_env: Option<String>,
+}
+
+impl AnalyticsSink for ClickhouseSink {
+    fn send<'a>(&'a self, events: &'a [Value]) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let body: String = events.iter().map(|event| format!("{event}\n")).collect();
+            let query = format!("INSERT INTO {} FORMAT JSONEachRow", self.table);
+            let mut request = self.client.post(&self.url).query(&[("query", query)]);
+            if let Some(user) = env(&self.user_env)? {
+                request = request.header("X-ClickHouse-User", user);
+            }
+            if let Some(password) = env(&self.password_env)? {
+                request = request.header("X-ClickHouse-Key", password);
+            }
+            request.body(body).send().await?.error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+/// scope of the access tokens of the BigQuery sink
+const BIGQUERY_SCOPE: &str = "https://www.googleapis.com/auth/bigquery.insertdata";
+/// lifetime asked for the access tokens, the longest Google grants
+const TOKEN_LIFETIME: time::Duration = time::Duration::hours(1);
+/// a token expiring sooner than this is refreshed before it is used
+const TOKEN_REFRESH_MARGIN: time::Duration = time::Duration::minutes(5);
+
+/// The fields of a Google service account key file used for the token exchange
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+impl ServiceAccountKey {
+    /// the key file named by the environment variable `credentials_env`
+    fn load(credentials_env: &str) -> anyhow::Result<Self> {
+        let path = std::env::var(credentials_env).with_context(|| {
+            format!("{credentials_env} must hold the path of the BigQuery service account key")
+        })?;
+        let key = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read the service account key {path}"))?;
+        serde_json::from_str(&key)
+            .with_context(|| format!("{path} is not a service account key file"))
+    }
+}
+
+#[derive(Serialize)]
+struct JwtClaims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Deserialize)]
+struct TokenReply {
+    access_token: String,
+    expires_in: i64,
+}
+
+struct AccessToken {
+    token: String,
+    expires: OffsetDateTime,
+}
+
+impl Debug for AccessToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessToken")
+            .field("expires", &self.expires)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+enum BigQueryAuth {
+    /// an access token rotated outside the server, read on every run
+    TokenEnv(String),
+    /// a service account key, exchanged for a token when the last one is about to expire
+    ServiceAccount {
+        credentials_env: String,
+        token: tokio::sync::Mutex<Option<AccessToken>>,
+    },
+}
+
+#[derive(Debug)]
+pub struct BigQuerySink {
+    client: reqwest::Client,
+    url: String,
+    auth: BigQueryAuth,
+}
+
+impl BigQuerySink {
+    async fn token(&self) -> anyhow::Result<String> {
+        let (credentials_env, cached) = match &self.auth {
+            BigQueryAuth::TokenEnv(token_env) => {
+                return std::env::var(token_env).with_context(|| format!("{token_env} is not set"));
+            }
+            BigQueryAuth::ServiceAccount {
+                credentials_env,
+                token,
+            } => (credentials_env, token),
+        };
+        let mut cached = cached.lock().await;
+        let now = OffsetDateTime::now_utc();
+        if let Some(token) = cached
+            .as_ref()
+            .filter(|token| token.expires - TOKEN_REFRESH_MARGIN > now)
+        {
+            return Ok(token.token.clone());
+        }
+        // read again on every exchange, so a rotated key is picked up
+        let key = ServiceAccountKey::load(credentials_env)?;
+        let claims = JwtClaims {
+            iss: &key.client_email,
+            scope: BIGQUERY_SCOPE,
+            aud: &key.token_uri,
+            iat: now.unix_timestamp(),
+            exp: (now + TOKEN_LIFETIME).unix_timestamp(),
+        };
+        let assertion = jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_rsa_pem(key.private_key.as_bytes())?,
+        )?;
+        let reply: TokenReply = self
+            .client
+            .post(&key.token_uri)
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", assertion.as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()
+            .context("the service account key was refused for a BigQuery access token")?
+            .json()
+            .await?;
+        let token = reply.access_token.clone();
+        *cached = Some(AccessToken {
+            token: reply.access_token,
+            expires: now + time::Duration::seconds(reply.expires_in),
+        });
+        Ok(token)
+    }
+}
+
+impl AnalyticsSink for BigQuerySink {
+    fn send<'a>(&'a self, events: &'a [Value]) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let token = self.token().await?;
+            let rows: Vec<_> = events
+                .iter()
+                .map(|event| json!({ "json": event }))
+                .collect();
+            let response = self
+                .client
+                .post(&self.url)
+                .bearer_auth(token)
+                .json(&json!({ "rows": rows }))
+                .send()
+                .await?;
+            if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+                match &self.auth {
+                    BigQueryAuth::TokenEnv(token_env) => {
+                        anyhow::bail!(
+                            "BigQuery refused the access token in {token_env}, it may have expired"
+                        )
+                    }
+                    BigQueryAuth::ServiceAccount { token, .. } => {
+                        // revoked early, the next run exchanges the key again
+                        *token.lock().await = None;
+                        anyhow::bail!("BigQuery refused the access token of the service account")
+                    }
+                }
+            }
+            let response: Value = response.error_for_status()?.json().await?;
+            if let Some(errors) = response.get("insertErrors") {
+                anyhow::bail!("BigQuery rejected rows: {errors}");
+            }
+            Ok(())
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectorySink {
+    path: PathBuf,
+}
+
+impl AnalyticsSink for DirectorySink {
+    fn send<'a>(&'a self, events: &'a [Value]) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            tokio::fs::create_dir_all(&self.path).await?;
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            let path = self.path.join(format!("events-{now}.jsonl"));
+            let mut file = tokio::fs::File::create(&path).await?;
+            for event in events {
+                file.write_all(format!("{event}\n").as_bytes()).await?;
+            }
+            file.flush().await?;
+            Ok(())
+        })
+    }
+}
+
+impl SinkConfig {
+    pub fn build(self) -> anyhow::Result<Box<dyn AnalyticsSink>> {
+        let client = || reqwest::Client::builder().timeout(SINK_TIMEOUT).build();
+        Ok(match self {
+            Self::Clickhouse {
+                url,
+                table,
+                user_env,
+                password_env,
+            } => Box::new(ClickhouseSink {
+                client: client()?,
+                url,
+                table,
+                user_env,
+                password_env,
+            }),
+            Self::Bigquery {
+                project,
+                dataset,
+                table,
+                credentials_env,
+                token_env,
+            } => {
+                let auth = match (token_env, credentials_env) {
+                    (Some(_), Some(_)) => anyhow::bail!(
+                        "The BigQuery sink takes either a service account key or an access token"
+                    ),
+                    (Some(token_env), None) => BigQueryAuth::TokenEnv(token_env),
+                    (None, credentials_env) => {
+                        let credentials_env = credentials_env
+                            .unwrap_or_else(|| "GOOGLE_APPLICATION_CREDENTIALS".to_string());
+                        // a missing or broken key stops the server now rather than every run
+                        ServiceAccountKey::load(&credentials_env)?;
+                        BigQueryAuth::ServiceAccount {
+                            credentials_env,
+                            token: tokio::sync::Mutex::new(None),
+                        }
+                    }
+                };
+                Box::new(BigQuerySink {
+                    client: client()?,
+                    url: format!(
+                        "https://bigquery.googleapis.com/bigquery/v2/projects/{project}/datasets/{dataset}/tables/{table}/insertAll"
+                    ),
+                    auth,
+                })
+            }
+            Self::Directory { path } => Box::new(DirectorySink { path }),
+        })
+    }
+}
+
+fn rfc3339(time: OffsetDateTime) -> Value {
+    Value::String(time.format(&Rfc3339).unwrap_or_default())
+}
+
+fn event(kind: AnalyticsEventKind, time: OffsetDateTime, fields: Value) -> Map<String, Value> {
+    let mut event = Map::new();
+    event.insert("event".to_string(), Value::String(kind.name().to_string()));
+    event.insert("time".to_string(), rfc3339(time));
+    if let Value::Object(fields) = fields {
+        event.extend(fields);
+    }
+    event
+}
+
+/// the events of `kind` that happened in `(from, to]`
+pub async fn collect(
+    database: &SqlitePool,
+    kind: AnalyticsEventKind,
+    from: OffsetDateTime,
+    to: OffsetDateTime,
+) -> anyhow::Result<Vec<Map<String, Value>>> {
+    let events = match kind {
+        AnalyticsEventKind::Session => sqlx::query!(
+            r#"select focus_session.student_id, focus_session.book_id, student.class_id,
+            start_time as "start_time: OffsetDateTime", end_time as "end_time!: OffsetDateTime", idle_gaps, idle_seconds
+            from focus_session inner join student on student.id = focus_session.student_id
+            where end_time is not null and datetime(end_time) > datetime(?) and datetime(end_time) <= datetime(?)"#,
+            from,
+            to
+        )
+        .fetch_all(database)
+        .await?
+        .into_iter()
+        .map(|record| {
+            event(
+                kind,
+                record.end_time,
+                json!({
+                    "student_id": record.student_id,
+                    "book_id": record.book_id,
+                    "class_id": record.class_id,
+                    "start_time": rfc3339(record.start_time),
+                    "duration_seconds": (record.end_time - record.start_time).whole_seconds(),
+                    "idle_gaps": record.idle_gaps,
+                    "idle_seconds": record.idle_seconds,
+                }),
+            )
+        })
+        .collect(),
+        AnalyticsEventKind::Progress => sqlx::query!(
+            r#"select chapter_progress.student_id, chapter_progress.book_id, student.class_id, chapter_number,
+            status, objectives, update_time as "update_time: OffsetDateTime"
+            from chapter_progress inner join student on student.id = chapter_progress.student_id
+            where datetime(update_time) > datetime(?) and datetime(update_time) <= datetime(?)"#,
+            from,
+            to
+        )
+        .fetch_all(database)
+        .await?
+        .into_iter()
+        .map(|record| {
+            let objectives: Vec<ChapterObjective> =
+                serde_json::from_str(&record.objectives).unwrap_or_default();
+            event(
+                kind,
+                record.update_time,
+                json!({
+                    "student_id": record.student_id,
+                    "book_id": record.book_id,
+                    "class_id": record.class_id,
+                    "chapter_number": record.chapter_number,
+                    "status": ChapterStatus::from(record.status),
+                    "objectives": objectives.len(),
+                    "objectives_completed": objectives.iter().filter(|o| o.completed).count(),
+                }),
+            )
+        })
+        .collect(),
+        AnalyticsEventKind::Grade => sqlx::query!(
+            r#"select quiz_submission.student_id, quiz.book_id, student.class_id, quiz.chapter_number,
+            quiz_submission.score, quiz_submission.create_time as "create_time: OffsetDateTime"
+            from quiz_submission inner join quiz on quiz.id = quiz_submission.quiz_id
+            inner join student on student.id = quiz_submission.student_id
+            where datetime(quiz_submission.create_time) > datetime(?) and datetime(quiz_submission.create_time) <= datetime(?)"#,
+            from,
+            to
+        )
+        .fetch_all(database)
+        .await?
+        .into_iter()
+        .map(|record| {
+            event(
+                kind,
+                record.create_time,
+                json!({
+                    "student_id": record.student_id,
+                    "book_id": record.book_id,
+                    "class_id": record.class_id,
+                    "chapter_number": record.chapter_number,
+                    "score": record.score,
+                }),
+            )
+        })
+        .collect(),
+    };
+    Ok(events)
+}
+
+/// Ships anonymized sessions, progress and grades to a warehouse on a schedule
+#[derive(Debug)]
+pub struct AnalyticsExporter {
+    name: String,
+    interval: Duration,
+    events: Vec<AnalyticsEventKind>,
+    anonymizer: Anonymizer,
+    sink: Box<dyn AnalyticsSink>,
+    database: SqlitePool,
+}
+
+impl AnalyticsExporter {
+    /// the exporter of a JSON config, e.g.
+    /// `{"interval_minutes": 60, "fields": {"class_id": "pseudonymize", "start_time": "day"},
+    /// "sink": {"sink": "clickhouse", "url": "http://clickhouse:8123", "table": "reader_events"}}`
+    pub fn from_json(json: &str, database: SqlitePool) -> anyhow::Result<Self> {
+        let config: ExportConfig = serde_json::from_str(json)?;
+        let key = std::env::var(&config.key_env).with_context(|| {
+            format!(
+                "{} must hold the pseudonymization key of the analytics export",
+                config.key_env
+            )
+        })?;
+        Ok(Self {
+            name: config.name,
+            interval: Duration::from_secs(config.interval_minutes.max(1) * 60),
+            events: config.events,
+            anonymizer: Anonymizer::new(config.fields, key),
+            sink: config.sink.build()?,
+            database,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>, database: SqlitePool) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read the analytics export {}", path.display()))?;
+        Self::from_json(&json, database)
+    }
+
+    /// export the events since the last successful run, returns how many were sent
+    pub async fn export(&self) -> anyhow::Result<usize> {
+        let from = sqlx::query_scalar!(
+            r#"select exported_until as "exported_until: OffsetDateTime" from analytics_export where name = ?"#,
+            self.name
+        )
+        .fetch_optional(&self.database)
+        .await?
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+        let to = OffsetDateTime::now_utc() - EXPORT_LAG;
+        if to <= from {
+            return Ok(0);
+        }
+        let mut events = Vec::new();
+        for kind in &self.events {
+            for event in collect(&self.database, *kind, from, to).await? {
+                events.push(Value::Object(self.anonymizer.apply(event)));
+            }
+        }
+        if !events.is_empty() {
+            self.sink.send(&events).await?;
+        }
+        sqlx::query!(
+            "insert into analytics_export (name, exported_until) values (?, ?) \
+            on conflict (name) do update set exported_until = excluded.exported_until",
+            self.name,
+            to
+        )
+        .execute(&self.database)
+        .await?;
+        Ok(events.len())
+    }
+
+    /// export on every interval, a failed run is retried with the same range the next time
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            match self.export().await {
+                Ok(0) => {}
+                Ok(count) => info!("exported {count} analytics events to {}", self.name),
+                Err(e) => warn!("analytics export {} failed: {e:?}", self.name),
+            }
+        }
+    }
+}
+
+#[test]
+fn anonymize_event() {
+    let anonymizer = Anonymizer::new(
+        BTreeMap::from([
+            ("class_id".to_string(), FieldAction::Drop),
+            ("start_time".to_string(), FieldAction::Day),
+        ]),
+        "secret",
+    );
+    let event = |student_id: i64| {
+        json!({
+            "event": "session",
+            "student_id": student_id,
+            "class_id": 3,
+            "start_time": "2025-03-04T10:20:00Z",
+            "book_id": 7,
+        })
+        .as_object()
+        .unwrap()
+        .clone()
+    };
+    let first = anonymizer.apply(event(1));
+    assert!(!first.contains_key("class_id"));
+    assert_eq!(first["start_time"], "2025-03-04");
+    assert_eq!(first["book_id"], 7);
+    let pseudonym = first["student_id"].as_str().unwrap();
+    assert_eq!(pseudonym.len(), 32);
+    assert_eq!(anonymizer.apply(event(1))["student_id"], pseudonym);
+    assert_ne!(anonymizer.apply(event(2))["student_id"], pseudonym);
+}
+
+#[test]
+fn bigquery_auth() {
+    let sink = |auth: &str| {
+        serde_json::from_str::<SinkConfig>(&format!(
+            r#"{{"sink": "bigquery", "project": "p", "dataset": "d", "table": "t"{auth}}}"#
+        ))
+        .unwrap()
+        .build()
+    };
+    assert!(sink(r#", "token_env": "BIGQUERY_TOKEN""#).is_ok());
+    assert!(sink(r#", "token_env": "BIGQUERY_TOKEN", "credentials_env": "KEY""#).is_err());
+    // the key is checked when the sink is built
+    assert!(sink(r#", "credentials_env": "BOOK_SERVER_UNSET_KEY""#).is_err());
+}
@@ -1,33 +1,97 @@
+pub mod auth;
+pub mod delivery;
+pub mod followers;
 pub mod manager;
 pub mod public;
+pub mod sessions;
 pub mod user;
 
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
-use axum::extract::Multipart;
+use axum::extract::{Multipart, multipart::Field};
+use serde::Deserialize;
+use tempfile::TempDir;
 use tokio::{fs::File, io::AsyncWriteExt};
 
-use crate::books::library::Library;
+use crate::books::library::{Library, StagedBook};
+
+/// Request body size limits in bytes, uploads get their own larger limit
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimits {
+    pub default: usize,
+    pub upload: usize,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        Self {
+            default: 2 * 1024 * 1024,
+            upload: 200 * 1024 * 1024,
+        }
+    }
+}
+
+/// `?dry_run=true` on a destructive endpoint returns what would change without changing it
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct DryRunQuery {
+    pub dry_run: Option<bool>,
+}
+
+impl DryRunQuery {
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run.unwrap_or(false)
+    }
+}
+
+/// write an uploaded file into a temporary directory under its own file name and scan it,
+/// the directory is removed when dropped
+async fn receive_upload(
+    mut field: Field<'_>,
+    library: &Library,
+) -> anyhow::Result<(TempDir, PathBuf)> {
+    let filename = field
+        .file_name()
+        .and_then(|name| std::path::Path::new(name).file_name())
+        .ok_or_else(|| anyhow::anyhow!("No filename found"))?
+        .to_os_string();
+    let temp_dir = tempfile::tempdir()?;
+    let path = temp_dir.path().join(&filename);
+    let mut file = File::create(&path).await?;
+    while let Some(chunk) = field.chunk().await? {
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    if let Some(scanner) = &library.upload_scanner {
+        scanner
+            .scan(&path)
+            .await?
+            .into_result(&filename.to_string_lossy())?;
+    }
+    Ok((temp_dir, path))
+}
 
 pub async fn upload_books(
     mut multipart: Multipart,
     library: Arc<Library>,
 ) -> anyhow::Result<Vec<i64>> {
     let mut book_ids = Vec::new();
-    while let Some(mut field) = multipart.next_field().await? {
-        let filename = field
-            .file_name()
-            .ok_or_else(|| anyhow::anyhow!("No filename found"))?
-            .to_string();
-        let temp_dir = tempfile::tempdir()?;
-        let path = temp_dir.path().join(filename);
-        let mut file = File::create(&path).await?;
-        while let Some(chunk) = field.chunk().await? {
-            file.write_all(&chunk).await?;
-        }
+    while let Some(field) = multipart.next_field().await? {
+        let (_temp_dir, path) = receive_upload(field, &library).await?;
         let book_id = library.upload_book(path).await?;
         book_ids.push(book_id);
     }
     Ok(book_ids)
 }
 
+/// stage the uploaded book archive, the first file of the form
+pub async fn stage_upload(
+    mut multipart: Multipart,
+    library: &Library,
+) -> anyhow::Result<StagedBook> {
+    let field = multipart
+        .next_field()
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("No file uploaded"))?;
+    let (_temp_dir, path) = receive_upload(field, library).await?;
+    library.stage_book(&path).await
+}
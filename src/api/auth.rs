@@ -0,0 +1,133 @@
+use axum::{
+    extract::{FromRequestParts, Request},
+    http::{StatusCode, request::Parts},
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use tower_sessions::Session;
+use utoipa::{
+    Modify,
+    openapi::{
+        OpenApi,
+        security::{ApiKey, ApiKeyValue, SecurityScheme},
+    },
+};
+
+/// session key of the logged in student
+pub const STUDENT_SESSION_KEY: &str = "student_id";
+/// session key of the logged in manager
+pub const MANAGER_SESSION_KEY: &str = "manager_id";
+/// session key of the role of the logged in manager
+pub const MANAGER_ROLE_KEY: &str = "manager_role";
+/// name of the session cookie set by the login endpoints
+const SESSION_COOKIE: &str = "id";
+
+/// The id of the logged in student, requests without a student session are rejected with 401
+#[derive(Debug, Clone, Copy)]
+pub struct StudentAuth(pub i64);
+
+/// The id of the logged in manager, requests without a manager session are rejected with 401
+#[derive(Debug, Clone, Copy)]
+pub struct ManagerAuth(pub i64);
+
+/// What a manager account may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// manages the books, the students and the agent settings
+    Admin,
+    /// views the books and the progress of every student
+    Teacher,
+}
+
+impl TryFrom<&str> for Role {
+    type Error = anyhow::Error;
+
+    fn try_from(role: &str) -> anyhow::Result<Self> {
+        match role {
+            "admin" => Ok(Self::Admin),
+            "teacher" => Ok(Self::Teacher),
+            _ => anyhow::bail!("Unknown manager role: {role}"),
+        }
+    }
+}
+
+/// The id of the logged in admin, rejects requests of other managers with 403 and requests
+/// without a manager session with 401
+#[derive(Debug, Clone, Copy)]
+pub struct AdminAuth(pub i64);
+
+async fn session_account<S: Send + Sync>(
+    parts: &mut Parts,
+    state: &S,
+    key: &str,
+) -> Result<i64, StatusCode> {
+    let session = Session::from_request_parts(parts, state)
+        .await
+        .map_err(|_| StatusCode::UNAUTHORIZED)?;
+    match session.get::<i64>(key).await {
+        Ok(Some(id)) => Ok(id),
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for StudentAuth {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        session_account(parts, state, STUDENT_SESSION_KEY)
+            .await
+            .map(Self)
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ManagerAuth {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        session_account(parts, state, MANAGER_SESSION_KEY)
+            .await
+            .map(Self)
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for AdminAuth {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let id = session_account(parts, state, MANAGER_SESSION_KEY).await?;
+        let session = Session::from_request_parts(parts, state)
+            .await
+            .map_err(|_| StatusCode::UNAUTHORIZED)?;
+        match session.get::<Role>(MANAGER_ROLE_KEY).await {
+            Ok(Some(Role::Admin)) => Ok(Self(id)),
+            Ok(Some(_)) => Err(StatusCode::FORBIDDEN),
+            // a session from before roles, the manager logs in again
+            _ => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+}
+
+/// Middleware rejecting requests without a manager session, for the whole manager scope,
+/// the handlers check the role they need on top
+pub async fn require_manager(_: ManagerAuth, request: Request, next: Next) -> Response {
+    next.run(request).await
+}
+
+/// Documents the session cookie as the `session` security scheme of the OpenAPI spec,
+/// the guarded paths require it
+pub struct SessionSecurity;
+
+impl Modify for SessionSecurity {
+    fn modify(&self, openapi: &mut OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::with_description(
+                SESSION_COOKIE,
+                "Session cookie set by the login endpoint",
+            ))),
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use utoipa::ToSchema;
+
+use super::user::ChatFrame;
+
+/// how long a disconnected client has to resume a response before the response stops
+pub const RESUME_WINDOW: Duration = Duration::from_secs(30);
+/// unacked frames buffered per response, a client further behind can't resume
+const OUTBOX_CAPACITY: usize = 1024;
+
+/// A chat WebSocket frame numbered within its response, e.g.
+/// `{"seq": 3, "type": "content", "data": "Ownership is"}`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SequencedFrame {
+    /// 1 for the first frame of the response, 0 for the stream frame announcing it
+    pub seq: u64,
+    #[serde(flatten)]
+    pub frame: ChatFrame,
+}
+
+/// A client frame acknowledging every frame of the response up to `ack`
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ChatSocketAck {
+    pub ack: u64,
+}
+
+/// A client frame stopping the response, `{"stop": true}`; the part already streamed is kept
+/// and the response ends with done
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ChatSocketStop {
+    pub stop: bool,
+}
+
+#[derive(Default)]
+struct OutboxState {
+    /// the frames after `base`, oldest first
+    frames: VecDeque<ChatFrame>,
+    /// frames dropped from the front, acked or pushed out by newer ones
+    base: u64,
+    /// whether the response ended, with done when `Some(true)`
+    ended: Option<bool>,
+    attached: usize,
+    detached_since: Option<Instant>,
+}
+
+impl OutboxState {
+    fn end(&self) -> u64 {
+        self.base + self.frames.len() as u64
+    }
+}
+
+/// The frames of one response the client hasn't acked yet, outliving the socket that asked for
+/// it so a client that reconnects within [`RESUME_WINDOW`] gets the rest
+pub struct Outbox {
+    student_id: i64,
+    state: Mutex<OutboxState>,
+    written: watch::Sender<u64>,
+}
+
+/// Detaches the client from the outbox when dropped, see [`Outbox::attach`]
+pub struct Attachment<'a>(&'a Outbox);
+
+impl Drop for Attachment<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.attached -= 1;
+        if state.attached == 0 {
+            state.detached_since = Some(Instant::now());
+        }
+    }
+}
+
+impl Outbox {
+    fn new(student_id: i64) -> Self {
+        Self {
+            student_id,
+            state: Mutex::new(OutboxState {
+                detached_since: Some(Instant::now()),
+                ..Default::default()
+            }),
+            written: watch::channel(0).0,
+        }
+    }
+
+    /// buffer the next frame of the response, false once no client came back within
+    /// [`RESUME_WINDOW`] and the response should stop
+    pub fn push(&self, frame: ChatFrame) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .detached_since
+            .is_some_and(|since| since.elapsed() > RESUME_WINDOW)
+        {
+            return false;
+        }
+        if state.frames.len() == OUTBOX_CAPACITY {
+            state.frames.pop_front();
+            state.base += 1;
+        }
+        state.frames.push_back(frame);
+        self.written.send_replace(state.end());
+        true
+    }
+
+    /// buffer the done or error frame ending the response
+    pub fn close(&self, frame: ChatFrame) {
+        let mut state = self.state.lock().unwrap();
+        state.ended = Some(matches!(frame, ChatFrame::Done));
+        state.frames.push_back(frame);
+        self.written.send_replace(state.end());
+    }
+
+    /// forget the frames up to `seq`, the client has them
+    pub fn ack(&self, seq: u64) {
+        let mut state = self.state.lock().unwrap();
+        let seq = seq.min(state.end());
+        while state.base < seq {
+            state.frames.pop_front();
+            state.base += 1;
+        }
+    }
+
+    /// the buffered frames after `seq`, an error when some of them were already dropped
+    pub fn frames_after(&self, seq: u64) -> anyhow::Result<Vec<SequencedFrame>> {
+        let state = self.state.lock().unwrap();
+        if seq < state.base {
+            anyhow::bail!(
+                "frames {} to {} are no longer buffered, replay the response with /chat/stream",
+                seq + 1,
+                state.base
+            );
+        }
+        Ok(state
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| SequencedFrame {
+                seq: state.base + 1 + i as u64,
+                frame: frame.clone(),
+            })
+            .filter(|frame| frame.seq > seq)
+            .collect())
+    }
+
+    /// whether the response ended with done, once the client was sent every frame up to `seq`
+    pub fn finished(&self, seq: u64) -> Option<bool> {
+        let state = self.state.lock().unwrap();
+        state.ended.filter(|_| seq >= state.end())
+    }
+
+    /// changes whenever a frame is buffered
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.written.subscribe()
+    }
+
+    /// note that a client is receiving the frames until the attachment is dropped
+    pub fn attach(&self) -> Attachment<'_> {
+        let mut state = self.state.lock().unwrap();
+        state.attached += 1;
+        state.detached_since = None;
+        Attachment(self)
+    }
+}
+
+/// The outboxes of the responses in flight or recently ended, keyed by stream id
+#[derive(Default)]
+pub struct Deliveries {
+    outboxes: DashMap<String, Arc<Outbox>>,
+}
+
+impl Deliveries {
+    /// a new outbox for a response to the student with its stream id
+    pub fn open(&self, student_id: i64) -> (String, Arc<Outbox>) {
+        let stream_id = format!("{:032x}", rand::random::<u128>());
+        let outbox = Arc::new(Outbox::new(student_id));
+        self.outboxes.insert(stream_id.clone(), outbox.clone());
+        (stream_id, outbox)
+    }
+
+    /// the outbox of a response to the student, `None` when unknown or expired
+    pub fn resume(&self, student_id: i64, stream_id: &str) -> Option<Arc<Outbox>> {
+        self.outboxes
+            .get(stream_id)
+            .map(|outbox| outbox.clone())
+            .filter(|outbox| outbox.student_id == student_id)
+    }
+
+    pub fn remove(&self, stream_id: &str) {
+        self.outboxes.remove(stream_id);
+    }
+}
+
+#[test]
+fn outbox_acks() {
+    let outbox = Outbox::new(1);
+    let _attachment = outbox.attach();
+    for piece in ["a", "b", "c"] {
+        assert!(outbox.push(ChatFrame::Content(piece.to_string())));
+    }
+    outbox.ack(2);
+    let frames = outbox.frames_after(2).unwrap();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0].seq, 3);
+    assert!(outbox.frames_after(1).is_err());
+    outbox.close(ChatFrame::Done);
+    assert_eq!(outbox.finished(3), None);
+    assert_eq!(outbox.finished(4), Some(true));
+    let json = serde_json::to_value(&frames[0]).unwrap();
+    assert_eq!(json["seq"], 3);
+    assert_eq!(json["type"], "content");
+}
@@ -0,0 +1,160 @@
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use tokio::sync::{broadcast, mpsc::Sender};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use super::user::ChatFrame;
+use crate::teacher::{ResponseEvent, TeacherAgent};
+
+/// frames a slow follower may fall behind before it misses some
+const FOLLOWER_BUFFER: usize = 256;
+/// the error of a chat request while the server shuts down
+pub const RESTARTING: &str = "The server is restarting, send the message again in a moment";
+/// how long stopped responses get to store what they streamed when the server shuts down
+const STOP_GRACE: Duration = Duration::from_secs(5);
+
+/// The clients of a student following a conversation, e.g. the laptop while the phone sends
+/// the messages, keyed by student and book
+#[derive(Default)]
+pub struct Followers {
+    channels: DashMap<(i64, i64), broadcast::Sender<ChatFrame>>,
+    /// the response being generated in each conversation, numbered so an input only
+    /// unregisters its own
+    responses: DashMap<(i64, i64), (u64, CancellationToken)>,
+    next_response: AtomicU64,
+    /// the server is shutting down, no new response starts
+    closing: AtomicBool,
+}
+
+impl Followers {
+    /// receive every turn of the conversation from now on, whichever client sends it
+    pub fn subscribe(&self, student_id: i64, book_id: i64) -> broadcast::Receiver<ChatFrame> {
+        self.channels
+            .entry((student_id, book_id))
+            .or_insert_with(|| broadcast::channel(FOLLOWER_BUFFER).0)
+            .subscribe()
+    }
+
+    fn is_followed(&self, student_id: i64, book_id: i64) -> bool {
+        self.channels
+            .get(&(student_id, book_id))
+            .is_some_and(|channel| channel.receiver_count() > 0)
+    }
+
+    /// stop the response being generated in the conversation, from any client of the student;
+    /// the part already streamed stays in the conversation. false when there is none
+    pub fn stop(&self, student_id: i64, book_id: i64) -> bool {
+        match self.responses.get(&(student_id, book_id)) {
+            Some(response) => {
+                response.1.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// the server is shutting down, chat requests are refused
+    pub fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::Relaxed)
+    }
+
+    /// refuse new responses and wait for those in flight to finish, stopping the ones still
+    /// running after `deadline`; a stopped response keeps the part already streamed
+    pub async fn drain(&self, deadline: Duration) {
+        self.closing.store(true, Ordering::Relaxed);
+        if !self.wait_idle(deadline).await {
+            warn!(
+                "stopping {} responses still running at shutdown",
+                self.responses.len()
+            );
+            for response in self.responses.iter() {
+                response.1.cancel();
+            }
+            if !self.wait_idle(STOP_GRACE).await {
+                warn!("{} responses did not stop in time", self.responses.len());
+            }
+        }
+        info!("no response in flight");
+    }
+
+    /// true once no response is running, false if some still are after `timeout`
+    async fn wait_idle(&self, timeout: Duration) -> bool {
+        let start = tokio::time::Instant::now();
+        while !self.responses.is_empty() {
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        true
+    }
+
+    fn publish(&self, student_id: i64, book_id: i64, frame: ChatFrame) {
+        let key = (student_id, book_id);
+        let Some(channel) = self.channels.get(&key).map(|channel| channel.clone()) else {
+            return;
+        };
+        if channel.send(frame).is_err() {
+            // every follower went away
+            self.channels
+                .remove_if(&key, |_, channel| channel.receiver_count() == 0);
+        }
+    }
+
+    /// send the student message to the locked teacher, streaming the response to `tx` and to
+    /// the followers of the conversation, who also get the message and the done or error frame;
+    /// messages sent from several clients wait for the lock and are answered one after the other
+    ///
+    /// The response stops when the sending client and every follower went away, or on
+    /// [`Self::stop`]. Fails without a response once the server is shutting down.
+    pub async fn input<E>(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        teacher: &mut TeacherAgent,
+        message: String,
+        tx: Sender<E>,
+    ) -> anyhow::Result<()>
+    where
+        E: From<ResponseEvent> + Send + Sync + 'static,
+    {
+        if self.is_closing() {
+            anyhow::bail!(RESTARTING);
+        }
+        self.publish(student_id, book_id, ChatFrame::Student(message.clone()));
+        let (event_tx, mut event_rx) = tokio::sync::mpsc::channel::<ResponseEvent>(100);
+        let forward = async {
+            let mut sender_left = false;
+            while let Some(event) = event_rx.recv().await {
+                self.publish(student_id, book_id, event.clone().into());
+                if !sender_left {
+                    sender_left = tx.send(event.into()).await.is_err();
+                }
+                if sender_left && !self.is_followed(student_id, book_id) {
+                    break;
+                }
+            }
+        };
+        let key = (student_id, book_id);
+        let id = self.next_response.fetch_add(1, Ordering::Relaxed);
+        let cancel = CancellationToken::new();
+        self.responses.insert(key, (id, cancel.clone()));
+        let (result, ()) = tokio::join!(
+            teacher.input_cancellable(message.into(), event_tx, &cancel),
+            forward
+        );
+        self.responses
+            .remove_if(&key, |_, (response, _)| *response == id);
+        let last = match &result {
+            Ok(()) => ChatFrame::Done,
+            Err(e) => ChatFrame::Error(e.to_string()),
+        };
+        self.publish(student_id, book_id, last);
+        result
+    }
+}
@@ -1,21 +1,59 @@
-use crate::books::book::BookMeta;
-use crate::books::library::Library;
+use crate::ai_utils::{self, ProviderConfig, ProviderInfo};
+use crate::books::book::{BookMeta, Difficulty};
+use crate::books::chapter::{ChapterNumber, PlanQuality};
+use crate::books::content_rating::ContentRating;
+use crate::books::cover::CoverSize;
+use crate::books::custom_tools::BookTools;
+use crate::books::library::{
+    BookDeletion, BookMetadata, BookOrder, BookQuery, Library, ReimportReport, StagedBook,
+};
+use crate::books::validation::ValidationReport;
+use crate::dead_letter::{self, DeadLetter, DeadLetterFilter};
+use crate::generation_log::{self, GenerationFilter, GenerationRecord};
+use crate::guardrail::{self, ForbiddenTopic, GuardrailEvent, TopicRequest};
+use crate::jobs::{BatchPreview, JobPolicy, JobQueue, JobStatus};
+use crate::pagination::{PageQuery, Paginated, SortOrder};
+use crate::project::{self, Project, ProjectRequest};
+use crate::prompts::{self, PromptName, PromptTemplate};
+use crate::quiz::{self, QuizScore};
+use crate::quota::{self, QuotaLimits, StudentQuota};
+use crate::scripting::{self, LessonScript, ScriptRequest};
+use crate::snapshot;
+use crate::spend::{self, ClassSetting, ClassSpend};
 use crate::student;
 use crate::student::StudentInfo;
+use crate::teacher::TeacherAgent;
+use crate::teacher::capabilities::ClientCapabilities;
+use crate::teacher::catalog::{self, ToolText};
+use crate::teacher::messages::{MessagesDatabase, replay};
+use crate::teacher::suggestions;
+use crate::usage::{self, DailyUsage, UsageFilter};
 use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use axum::{
-    Router,
-    extract::{Json, Multipart, Query, State},
+    Extension, Router,
+    body::Body,
+    extract::{DefaultBodyLimit, Json, Multipart, Path, Query, State},
+    middleware,
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post},
 };
-use serde::Deserialize;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use tokio_util::io::ReaderStream;
 use tower_sessions::Session;
 use utoipa::ToSchema;
 
-use super::upload_books;
+use super::{
+    BodyLimits, DryRunQuery,
+    auth::{AdminAuth, MANAGER_ROLE_KEY, MANAGER_SESSION_KEY, ManagerAuth, Role, require_manager},
+    public::{CoverQuery, cover_response},
+    sessions::{SessionInfo, SessionManager},
+    stage_upload, upload_books,
+    user::{ReplayQuery, replay_stream},
+};
 
 #[derive(Deserialize, ToSchema)]
 pub struct LoginRequest {
@@ -27,16 +65,19 @@ async fn manager_login(
     database: &SqlitePool,
     email: String,
     password: String,
-) -> anyhow::Result<i64> {
-    let manager = sqlx::query!("SELECT id, password FROM manager WHERE email = ?", email)
-        .fetch_one(database)
-        .await?;
+) -> anyhow::Result<(i64, Role)> {
+    let manager = sqlx::query!(
+        "SELECT id, password, role FROM manager WHERE email = ?",
+        email
+    )
+    .fetch_one(database)
+    .await?;
     let parsed_hash = PasswordHash::new(&manager.password)
         .map_err(|e| anyhow::anyhow!("Failed to parse password hash: {}", e))?;
     Argon2::default()
         .verify_password(password.as_bytes(), &parsed_hash)
         .map_err(|e| anyhow::anyhow!("Failed to verify password: {}", e))?;
-    Ok(manager.id)
+    Ok((manager.id, Role::try_from(manager.role.as_str())?))
 }
 
 #[utoipa::path(
@@ -46,7 +87,8 @@ async fn manager_login(
     request_body = LoginRequest,
     responses(
         (status = 200, description = "Login successful"),
-        (status = 400, description = "Invalid credentials")
+        (status = 400, description = "Invalid credentials"),
+        (status = 500, description = "Internal server error")
     )
 )]
 pub async fn login(
@@ -57,8 +99,14 @@ pub async fn login(
     let db = &library.database;
     let LoginRequest { email, password } = req;
     match manager_login(db, email, password).await {
-        Ok(id) => {
-            session.insert("manager_id", id).await.unwrap();
+        Ok((id, role)) => {
+            // a new session id, so an id planted in the browser before the login is worthless
+            if let Err(e) = session.cycle_id().await {
+                return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+                    .into_response();
+            }
+            session.insert(MANAGER_SESSION_KEY, id).await.unwrap();
+            session.insert(MANAGER_ROLE_KEY, role).await.unwrap();
             "Login successful".into_response()
         }
         Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
@@ -82,21 +130,33 @@ pub async fn logout(session: Session) -> impl IntoResponse {
     context_path = "/api/manager",
     path = "/list_books",
     method(get),
+    params(
+        ("cursor" = Option<String>, Query, description = "next_cursor of the previous page"),
+        ("limit" = Option<usize>, Query, description = "Items per page, defaults to 50, at most 500"),
+        ("offset" = Option<usize>, Query, description = "Books to skip after the cursor"),
+        ("sort" = Option<SortOrder>, Query, description = "asc or desc, defaults to the order of order_by"),
+        ("title" = Option<String>, Query, description = "Only books whose title contains this, ignoring case"),
+        ("tag" = Option<String>, Query, description = "Only books with this tag"),
+        ("language" = Option<String>, Query, description = "Only books in this language"),
+        ("difficulty" = Option<Difficulty>, Query, description = "Only books of this difficulty"),
+        ("order_by" = Option<BookOrder>, Query, description = "id (default, ascending), title (ascending) or added (newest first)")
+    ),
+    security(("session" = [])),
     responses(
-        (status = 200, description = "List of books", body = Vec<BookMeta>),
-        (status = 401, description = "Unauthorized")
+        (status = 200, description = "A page of books with the total of matching books", body = Paginated<BookMeta>),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Invalid cursor")
     )
 )]
 pub async fn list_books(
     State(library): State<Arc<Library>>,
-    session: Session,
+    _: ManagerAuth,
+    Query(page): Query<PageQuery>,
+    Query(query): Query<BookQuery>,
 ) -> impl IntoResponse {
-    let Ok(Some(_)) = session.get::<i64>("manager_id").await else {
-        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
-    };
-    match library.get_book_list(false).await {
+    match library.list_books(false, &query, &page).await {
         Ok(books) => Json(books).into_response(),
-        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
     }
 }
 
@@ -104,53 +164,194 @@ pub async fn list_books(
     context_path = "/api/manager",
     path = "/upload_public_book",
     method(post),
+    security(("session" = [])),
     responses(
         (status = 200, description = "Book uploaded successfully", body = Vec<i64>),
         (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
         (status = 500, description = "Internal server error")
     )
 )]
 pub async fn upload_public_book(
     State(library): State<Arc<Library>>,
-    session: Session,
+    _: AdminAuth,
     multipart: Multipart,
 ) -> impl IntoResponse {
-    let Ok(Some(_)) = session.get::<i64>("manager_id").await else {
-        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
-    };
     match upload_books(multipart, library).await {
         Ok(book_ids) => Json(book_ids).into_response(),
         Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
 
+/// A staged upload and the job importing it
+#[derive(Serialize, ToSchema)]
+pub struct BookUpload {
+    pub book: StagedBook,
+    /// ID of the background job generating the plans, poll it with /job_status
+    pub job_id: u64,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/upload_book_archive",
+    method(post),
+    request_body(content_type = "multipart/form-data", description = "A .zip of an mdbook or of Jupyter notebooks, an .epub or an .ipynb"),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Book extracted and checked, its plans are generated in the background", body = BookUpload),
+        (status = 400, description = "Invalid archive, SUMMARY.md or duplicate book"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin")
+    )
+)]
+pub async fn upload_book_archive(
+    State(library): State<Arc<Library>>,
+    Extension(jobs): Extension<Arc<JobQueue>>,
+    _: AdminAuth,
+    multipart: Multipart,
+) -> impl IntoResponse {
+    let staged = match stage_upload(multipart, &library).await {
+        Ok(staged) => staged,
+        Err(e) => return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let job_id = spawn_import_books(&jobs, library, vec![staged.clone()]);
+    Json(BookUpload {
+        book: staged,
+        job_id,
+    })
+    .into_response()
+}
+
 #[utoipa::path(
     context_path = "/api/manager",
     path = "/remove_book",
     method(post),
     params(
-        ("book_id" = i64, Query, description = "ID of the book to remove")
+        ("book_id" = i64, Query, description = "ID of the book to remove"),
+        ("dry_run" = Option<bool>, Query, description = "Only return what would be removed")
     ),
+    security(("session" = [])),
     responses(
-        (status = 200, description = "Book removed successfully"),
+        (status = 200, description = "Book removed successfully, or what would be removed on a dry run", body = BookDeletion),
         (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
         (status = 500, description = "Internal server error")
     )
 )]
 pub async fn remove_book(
     State(library): State<Arc<Library>>,
-    session: Session,
+    _: AdminAuth,
     Query(book_id): Query<i64>,
+    Query(dry_run): Query<DryRunQuery>,
 ) -> impl IntoResponse {
-    let Ok(Some(_)) = session.get::<i64>("manager_id").await else {
-        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
-    };
+    if dry_run.is_dry_run() {
+        return match library.preview_delete_book(book_id).await {
+            Ok(deletion) => Json(deletion).into_response(),
+            Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        };
+    }
     match library.delete_book(book_id).await {
         Ok(_) => "Book removed successfully".into_response(),
         Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
 
+/// How [`delete_book`] removes a book
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletionMode {
+    /// hide the book from students and keep its conversations and progress
+    #[default]
+    Archive,
+    /// delete the book with its plans, embeddings, conversations and progress
+    Hard,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteBookQuery {
+    #[serde(default)]
+    pub mode: DeletionMode,
+    /// on archive, move the book files into the archive directory of the bookbase
+    #[serde(default)]
+    pub move_files: bool,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/books/{book_id}",
+    method(delete),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book"),
+        ("mode" = Option<DeletionMode>, Query, description = "archive (default) or hard"),
+        ("move_files" = Option<bool>, Query, description = "Move the files of an archived book into the archive directory"),
+        ("dry_run" = Option<bool>, Query, description = "Only return what a hard delete would remove")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Book archived or deleted, or what would be removed on a dry run", body = BookDeletion),
+        (status = 400, description = "Book not found or already archived"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn delete_book(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Path(book_id): Path<i64>,
+    Query(query): Query<DeleteBookQuery>,
+    Query(dry_run): Query<DryRunQuery>,
+) -> impl IntoResponse {
+    let deletion = match library.preview_delete_book(book_id).await {
+        Ok(deletion) => deletion,
+        Err(e) => return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    if dry_run.is_dry_run() {
+        return Json(deletion).into_response();
+    }
+    let result = match query.mode {
+        DeletionMode::Archive => library.archive_book(book_id, query.move_files).await,
+        DeletionMode::Hard => library.delete_book(book_id).await,
+    };
+    match result {
+        Ok(_) => Json(deletion).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/reimport_book",
+    method(post),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book whose sources changed in the bookbase"),
+        ("dry_run" = Option<bool>, Query, description = "Only compare the sources with the last import, without regenerating plans")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Chapters added, changed and removed since the last import", body = ReimportReport),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn reimport_book(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Query(book_id): Query<i64>,
+    Query(dry_run): Query<DryRunQuery>,
+) -> impl IntoResponse {
+    let report = if dry_run.is_dry_run() {
+        library.preview_reimport(book_id).await
+    } else {
+        library.reimport_book(book_id).await
+    };
+    match report {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
 #[utoipa::path(
     context_path = "/api/manager",
     path = "/set_book_public",
@@ -159,60 +360,2103 @@ pub async fn remove_book(
         ("book_id" = i64, Query, description = "ID of the book to set public"),
         ("is_public" = bool, Query, description = "Whether to set the book as public")
     ),
+    security(("session" = [])),
     responses(
         (status = 200, description = "Book visibility updated successfully"),
         (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
         (status = 500, description = "Internal server error")
     )
 )]
 pub async fn set_book_public(
     State(library): State<Arc<Library>>,
-    session: Session,
+    _: AdminAuth,
     Query((book_id, is_public)): Query<(i64, bool)>,
 ) -> impl IntoResponse {
-    let Ok(Some(_)) = session.get::<i64>("manager_id").await else {
-        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
-    };
     match library.set_book_public(book_id, is_public).await {
         Ok(_) => "Book visibility updated successfully".into_response(),
         Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
 
+#[derive(Serialize, ToSchema)]
+pub struct BookStatus {
+    pub id: i64,
+    pub title: String,
+    pub chapter_count: usize,
+    /// whether the import found no problems
+    pub is_clean: bool,
+    pub report: ValidationReport,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetBookTagsRequest {
+    pub book_id: i64,
+    /// replaces the tags of the book
+    pub tags: Vec<String>,
+}
+
 #[utoipa::path(
     context_path = "/api/manager",
-    path = "/list_students",
+    path = "/set_book_tags",
+    method(post),
+    request_body = SetBookTagsRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The tags as stored, lowercase and sorted", body = Vec<String>),
+        (status = 400, description = "Book not found"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin")
+    )
+)]
+pub async fn set_book_tags(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<SetBookTagsRequest>,
+) -> impl IntoResponse {
+    match library.set_book_tags(req.book_id, &req.tags).await {
+        Ok(tags) => Json(tags).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/books/{book_id}/metadata",
+    method(get),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Catalog metadata of the book", body = BookMetadata),
+        (status = 400, description = "Book not found"),
+        (status = 401, description = "Unauthorized")
+    )
+)]
+pub async fn get_book_metadata(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Path(book_id): Path<i64>,
+) -> impl IntoResponse {
+    match library.get_book_metadata(book_id).await {
+        Ok(metadata) => Json(metadata).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/books/{book_id}/cover",
+    method(get),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book, public or not"),
+        ("size" = Option<CoverSize>, Query, description = "small (160px wide), medium (320px, default), large (640px) or original")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The cover image of the book, a jpeg thumbnail except for the original size and svg covers", content_type = "image/*"),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "No book with a cover")
+    )
+)]
+pub async fn get_book_cover(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Path(book_id): Path<i64>,
+    Query(query): Query<CoverQuery>,
+) -> impl IntoResponse {
+    cover_response(&library, book_id, false, &query).await
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/books/{book_id}/metadata",
+    method(put),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book")
+    ),
+    request_body = BookMetadata,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The metadata as stored, tags lowercase and sorted", body = BookMetadata),
+        (status = 400, description = "Book not found or invalid cover path"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin")
+    )
+)]
+pub async fn set_book_metadata(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Path(book_id): Path<i64>,
+    Json(metadata): Json<BookMetadata>,
+) -> impl IntoResponse {
+    match library.set_book_metadata(book_id, &metadata).await {
+        Ok(metadata) => Json(metadata).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/books/{book_id}/metadata",
+    method(delete),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Metadata cleared, authors restored from book.toml", body = BookMetadata),
+        (status = 400, description = "Book not found or archived"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin")
+    )
+)]
+pub async fn clear_book_metadata(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Path(book_id): Path<i64>,
+) -> impl IntoResponse {
+    match library.clear_book_metadata(book_id).await {
+        Ok(metadata) => Json(metadata).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/book_status",
     method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book to check")
+    ),
+    security(("session" = [])),
     responses(
-        (status = 200, description = "List of students", body = Vec<StudentInfo>),
+        (status = 200, description = "Import status of the book", body = BookStatus),
         (status = 401, description = "Unauthorized"),
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn list_students(
+pub async fn book_status(
     State(library): State<Arc<Library>>,
-    session: Session,
+    _: ManagerAuth,
+    Query(book_id): Query<i64>,
 ) -> impl IntoResponse {
-    let db = &library.database;
-    let Ok(Some(_)) = session.get::<i64>("manager_id").await else {
-        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
+    match library.get_book(book_id).await {
+        Ok(book) => Json(BookStatus {
+            id: book.id,
+            title: book.title.clone(),
+            chapter_count: book.chapters.len(),
+            is_clean: book.report.is_clean(),
+            report: book.report.clone(),
+        })
+        .into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PlanReview {
+    pub chapter_number: ChapterNumber,
+    pub name: String,
+    /// average of coverage and structure, from 0 to 10
+    pub score: f64,
+    pub quality: PlanQuality,
+}
+
+#[derive(Deserialize)]
+pub struct PlanReviewQuery {
+    pub book_id: i64,
+    pub flagged_only: Option<bool>,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/plan_reviews",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book"),
+        ("flagged_only" = Option<bool>, Query, description = "Only plans waiting for review, defaults to true")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Scored chapter plans, lowest score first", body = Vec<PlanReview>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn plan_reviews(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Query(query): Query<PlanReviewQuery>,
+) -> impl IntoResponse {
+    let book = match library.get_book(query.book_id).await {
+        Ok(book) => book,
+        Err(e) => {
+            return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+        }
     };
-    match student::get_student_list(db).await {
-        Ok(students) => Json(students).into_response(),
+    let flagged_only = query.flagged_only.unwrap_or(true);
+    let mut reviews: Vec<PlanReview> = book
+        .plan_quality
+        .iter()
+        .filter(|(_, quality)| !flagged_only || quality.needs_review)
+        .map(|(number, quality)| PlanReview {
+            chapter_number: number.clone(),
+            name: book
+                .chapters
+                .get(number)
+                .map(|ch| ch.name.clone())
+                .unwrap_or_default(),
+            score: quality.score(),
+            quality: quality.clone(),
+        })
+        .collect();
+    reviews.sort_by(|a, b| a.score.total_cmp(&b.score));
+    Json(reviews).into_response()
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ChapterRating {
+    pub chapter_number: ChapterNumber,
+    pub name: String,
+    pub rating: ContentRating,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/books/{book_id}/content_ratings",
+    method(get),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The content rating of every chapter, in chapter order", body = Vec<ChapterRating>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn content_ratings(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Path(book_id): Path<i64>,
+) -> impl IntoResponse {
+    let book = match library.get_book(book_id).await {
+        Ok(book) => book,
+        Err(e) => {
+            return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+        }
+    };
+    let ratings: Vec<ChapterRating> = book
+        .content_ratings
+        .iter()
+        .map(|(number, rating)| ChapterRating {
+            chapter_number: number.clone(),
+            name: book
+                .chapters
+                .get(number)
+                .map(|ch| ch.name.clone())
+                .unwrap_or_default(),
+            rating: rating.clone(),
+        })
+        .collect();
+    Json(ratings).into_response()
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/books/{book_id}/custom_tools",
+    method(get),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The tools and plugins declared in the tools.toml of the book", body = BookTools),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn custom_tools(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Path(book_id): Path<i64>,
+) -> impl IntoResponse {
+    match library.custom_tools(book_id).await {
+        Ok(tools) => Json(tools).into_response(),
         Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
 
-pub fn get_manager_scope() -> Router<Arc<Library>> {
-    Router::new().nest(
-        "/manager",
-        Router::new()
-            .route("/login", post(login))
-            .route("/logout", post(logout))
-            .route("/list_books", get(list_books))
-            .route("/upload_public_book", post(upload_public_book))
-            .route("/remove_book", post(remove_book))
-            .route("/set_book_public", post(set_book_public))
-            .route("/list_students", get(list_students)),
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/books/{book_id}/lesson_scripts",
+    method(get),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The lesson scripts of the book", body = Vec<LessonScript>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_lesson_scripts(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Path(book_id): Path<i64>,
+) -> impl IntoResponse {
+    match scripting::list_scripts(&library.database, book_id).await {
+        Ok(scripts) => Json(scripts).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/books/{book_id}/lesson_scripts",
+    method(post),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book")
+    ),
+    request_body = ScriptRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "ID of the new lesson script, run from the next event of its hook on", body = i64),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request, e.g. the script doesn't compile")
+    )
+)]
+pub async fn add_lesson_script(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Path(book_id): Path<i64>,
+    Json(req): Json<ScriptRequest>,
+) -> impl IntoResponse {
+    match scripting::add_script(&library.database, book_id, &req).await {
+        Ok(id) => Json(id).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/lesson_scripts/{id}",
+    method(delete),
+    params(
+        ("id" = i64, Path, description = "ID of the lesson script")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Lesson script removed"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn remove_lesson_script(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    match scripting::remove_script(&library.database, id).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/books/{book_id}/projects",
+    method(get),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The projects of the book with their milestones", body = Vec<Project>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_projects(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Path(book_id): Path<i64>,
+) -> impl IntoResponse {
+    match project::list_projects(&library.database, book_id).await {
+        Ok(projects) => Json(projects).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/books/{book_id}/projects",
+    method(post),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book")
+    ),
+    request_body = ProjectRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "ID of the new project", body = i64),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request, e.g. a milestone on a chapter not in the book")
+    )
+)]
+pub async fn create_project(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Path(book_id): Path<i64>,
+    Json(req): Json<ProjectRequest>,
+) -> impl IntoResponse {
+    let result = async {
+        let book = library.get_book(book_id).await?;
+        project::create_project(&library.database, &book, &req).await
+    };
+    match result.await {
+        Ok(id) => Json(id).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/projects/{id}",
+    method(delete),
+    params(
+        ("id" = i64, Path, description = "ID of the project")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Project removed with the work of its students"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn delete_project(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    match project::delete_project(&library.database, id).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ChapterPlanRequest {
+    pub book_id: i64,
+    pub chapter_number: ChapterNumber,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/regenerate_chapter_plan",
+    method(post),
+    request_body = ChapterPlanRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Score of the new plan", body = PlanQuality),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn regenerate_chapter_plan(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<ChapterPlanRequest>,
+) -> impl IntoResponse {
+    match library
+        .regenerate_chapter_plan(req.book_id, &req.chapter_number)
+        .await
+    {
+        Ok(book) => Json(book.plan_quality.get(&req.chapter_number).cloned()).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/approve_chapter_plan",
+    method(post),
+    request_body = ChapterPlanRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Plan approved, no longer flagged"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn approve_chapter_plan(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<ChapterPlanRequest>,
+) -> impl IntoResponse {
+    match library
+        .approve_chapter_plan(req.book_id, &req.chapter_number)
+        .await
+    {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/generation_log",
+    method(get),
+    params(
+        ("book_id" = Option<i64>, Query, description = "Only calls for this book"),
+        ("artifact" = Option<String>, Query, description = "Only calls for this artifact, e.g. \"chapter_plan\""),
+        ("cursor" = Option<String>, Query, description = "next_cursor of the previous page"),
+        ("limit" = Option<usize>, Query, description = "Items per page, defaults to 50, at most 500"),
+        ("sort" = Option<SortOrder>, Query, description = "Order of the call ids, defaults to desc, newest first")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "A page of model calls of the plan and summary pipeline", body = Paginated<GenerationRecord>),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Invalid cursor")
+    )
+)]
+pub async fn generation_log(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Query(filter): Query<GenerationFilter>,
+    Query(page): Query<PageQuery>,
+) -> impl IntoResponse {
+    match generation_log::list(&library.database, &filter, &page).await {
+        Ok(records) => Json(records).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/usage",
+    method(get),
+    params(
+        ("student_id" = Option<i64>, Query, description = "Only the usage of this student"),
+        ("book_id" = Option<i64>, Query, description = "Only the usage on this book"),
+        ("from" = Option<String>, Query, description = "First day, e.g. 2026-03-01, included"),
+        ("to" = Option<String>, Query, description = "Last day, included")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Tokens of the teacher's model calls per student, book and day (UTC), newest day first", body = Vec<DailyUsage>),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn usage_report(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Query(filter): Query<UsageFilter>,
+) -> impl IntoResponse {
+    match usage::report(&library.database, &filter).await {
+        Ok(usage) => Json(usage).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct QuotaQuery {
+    pub student_id: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/quota",
+    method(get),
+    params(("student_id" = i64, Query, description = "Student ID")),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Daily limits of the student and their usage today (UTC)", body = StudentQuota),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn get_quota(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Query(query): Query<QuotaQuery>,
+) -> impl IntoResponse {
+    match quota::get(&library.database, query.student_id).await {
+        Ok(quota) => Json(quota).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetQuotaRequest {
+    /// the student to give limits of their own, none to set the defaults of every student
+    pub student_id: Option<i64>,
+    /// the limits, none to move the student back to the defaults
+    pub limits: Option<QuotaLimits>,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/set_quota",
+    method(post),
+    request_body = SetQuotaRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The limits apply to the next message"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn set_quota(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<SetQuotaRequest>,
+) -> impl IntoResponse {
+    let result = match req.student_id {
+        Some(student_id) => {
+            quota::set_student_limits(&library.database, student_id, req.limits.as_ref()).await
+        }
+        None => quota::set_default_limits(&library.database, &req.limits.unwrap_or_default()).await,
+    };
+    match result {
+        Ok(()) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResetQuotaRequest {
+    pub student_id: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/reset_quota",
+    method(post),
+    request_body = ResetQuotaRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The usage of the student so far today no longer counts against their limits"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn reset_quota(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<ResetQuotaRequest>,
+) -> impl IntoResponse {
+    match quota::reset(&library.database, req.student_id).await {
+        Ok(()) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/list_students",
+    method(get),
+    params(
+        ("cursor" = Option<String>, Query, description = "next_cursor of the previous page"),
+        ("limit" = Option<usize>, Query, description = "Items per page, defaults to 50, at most 500"),
+        ("sort" = Option<SortOrder>, Query, description = "Order of the student ids, defaults to asc")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "A page of students", body = Paginated<StudentInfo>),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Invalid cursor"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_students(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Query(page): Query<PageQuery>,
+) -> impl IntoResponse {
+    let db = &library.database;
+    match student::get_student_list(db).await {
+        Ok(students) => match page.paginate(students, SortOrder::Asc, |student| student.id) {
+            Ok(students) => Json(students).into_response(),
+            Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        },
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/list_classes",
+    method(get),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Classes with their monthly AI limit and spend", body = Vec<ClassSpend>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_classes(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+) -> impl IntoResponse {
+    match spend::list_classes(&library.database).await {
+        Ok(classes) => Json(classes).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/create_class",
+    method(post),
+    request_body = ClassSetting,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "ID of the new class", body = i64),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn create_class(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<ClassSetting>,
+) -> impl IntoResponse {
+    match spend::create_class(&library.database, &req).await {
+        Ok(id) => Json(id).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateClassRequest {
+    pub class_id: i64,
+    #[serde(flatten)]
+    pub setting: ClassSetting,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/update_class",
+    method(post),
+    request_body = UpdateClassRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Class updated"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn update_class(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<UpdateClassRequest>,
+) -> impl IntoResponse {
+    match spend::update_class(&library.database, req.class_id, &req.setting).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct StudentClassRequest {
+    pub student_id: i64,
+    /// the class to put the student in, `None` to remove them from their class
+    pub class_id: Option<i64>,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/set_student_class",
+    method(post),
+    request_body = StudentClassRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Class of the student updated"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn set_student_class(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<StudentClassRequest>,
+) -> impl IntoResponse {
+    match spend::set_student_class(&library.database, req.student_id, req.class_id).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/classes/{class_id}/forbidden_topics",
+    method(get),
+    params(
+        ("class_id" = i64, Path, description = "ID of the class")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Topics the teacher must not discuss with the students of the class", body = Vec<ForbiddenTopic>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_forbidden_topics(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Path(class_id): Path<i64>,
+) -> impl IntoResponse {
+    match guardrail::list_topics(&library.database, class_id).await {
+        Ok(topics) => Json(topics).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/classes/{class_id}/forbidden_topics",
+    method(post),
+    params(
+        ("class_id" = i64, Path, description = "ID of the class")
+    ),
+    request_body = TopicRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "ID of the new forbidden topic, screened from the next message on", body = i64),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn add_forbidden_topic(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Path(class_id): Path<i64>,
+    Json(req): Json<TopicRequest>,
+) -> impl IntoResponse {
+    match guardrail::add_topic(&library.database, class_id, &req).await {
+        Ok(id) => Json(id).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/forbidden_topics/{id}",
+    method(delete),
+    params(
+        ("id" = i64, Path, description = "ID of the forbidden topic")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Forbidden topic removed"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn remove_forbidden_topic(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    match guardrail::remove_topic(&library.database, id).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct GuardrailLogQuery {
+    pub limit: Option<i64>,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/classes/{class_id}/guardrail_log",
+    method(get),
+    params(
+        ("class_id" = i64, Path, description = "ID of the class"),
+        ("limit" = Option<i64>, Query, description = "Number of events, 50 by default")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Questions and responses of the students of the class that touched a forbidden topic, newest first", body = Vec<GuardrailEvent>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn guardrail_log(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Path(class_id): Path<i64>,
+    Query(query): Query<GuardrailLogQuery>,
+) -> impl IntoResponse {
+    let limit = query.limit.unwrap_or(50).clamp(1, 1000);
+    match guardrail::list_events(&library.database, class_id, limit).await {
+        Ok(events) => Json(events).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct StudentBirthDateRequest {
+    pub student_id: i64,
+    /// the birth date as `YYYY-MM-DD`, `None` to lift the age restrictions of the student
+    pub birth_date: Option<String>,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/set_student_birth_date",
+    method(post),
+    request_body = StudentBirthDateRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Birth date of the student updated, applied from their next conversation"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn set_student_birth_date(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<StudentBirthDateRequest>,
+) -> impl IntoResponse {
+    let result = async {
+        let birth_date = match &req.birth_date {
+            Some(date) => Some(
+                time::Date::parse(
+                    date,
+                    time::macros::format_description!("[year]-[month]-[day]"),
+                )
+                .map_err(|e| anyhow::anyhow!("Invalid birth date {}: {}", date, e))?,
+            ),
+            None => None,
+        };
+        student::set_student_birth_date(&library.database, req.student_id, birth_date).await
+    };
+    match result.await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ToolCatalogQuery {
+    pub locale: Option<String>,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/tool_catalog",
+    method(get),
+    params(
+        ("locale" = Option<String>, Query, description = "Locale of the catalog, defaults to the locale used by the teacher")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Names and descriptions of the teacher tools in the locale", body = Vec<ToolText>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn tool_catalog(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Query(query): Query<ToolCatalogQuery>,
+) -> impl IntoResponse {
+    let result = async {
+        let locale = match query.locale {
+            Some(locale) => locale,
+            None => catalog::get_locale(&library.database).await?,
+        };
+        catalog::get_catalog(&library.database, &locale).await
+    }
+    .await;
+    match result {
+        Ok(texts) => Json(texts).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetToolTextRequest {
+    pub locale: String,
+    #[serde(flatten)]
+    pub text: ToolText,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/set_tool_text",
+    method(post),
+    request_body = SetToolTextRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Tool text updated, used from the next chat message"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn set_tool_text(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<SetToolTextRequest>,
+) -> impl IntoResponse {
+    match catalog::set_tool_text(&library.database, &req.locale, &req.text).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResetToolTextRequest {
+    pub locale: String,
+    pub tool_name: String,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/reset_tool_text",
+    method(post),
+    request_body = ResetToolTextRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Tool text reset to the built-in text"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn reset_tool_text(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<ResetToolTextRequest>,
+) -> impl IntoResponse {
+    match catalog::delete_tool_text(&library.database, &req.locale, &req.tool_name).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/prompt_templates",
+    method(get),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The prompts with their variables, built-in and custom templates", body = Vec<PromptTemplate>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn prompt_templates(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+) -> impl IntoResponse {
+    match prompts::list_templates(&library.database).await {
+        Ok(templates) => Json(templates).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetPromptTemplateRequest {
+    pub name: PromptName,
+    /// minijinja source, e.g. "Plan chapter {{ chapter_number }} of {{ book_title }}."
+    pub source: String,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/set_prompt_template",
+    method(post),
+    request_body = SetPromptTemplateRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Template set, used from the next plan generated or session started"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request, e.g. a syntax error or an unknown variable")
+    )
+)]
+pub async fn set_prompt_template(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<SetPromptTemplateRequest>,
+) -> impl IntoResponse {
+    match prompts::set_template(&library.database, req.name, &req.source).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ResetPromptTemplateRequest {
+    pub name: PromptName,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/reset_prompt_template",
+    method(post),
+    request_body = ResetPromptTemplateRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Template reset to the built-in one"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn reset_prompt_template(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<ResetPromptTemplateRequest>,
+) -> impl IntoResponse {
+    match prompts::reset_template(&library.database, req.name).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ToolLocaleRequest {
+    pub locale: String,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/set_tool_locale",
+    method(post),
+    request_body = ToolLocaleRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Locale of the tool catalog used by the teacher updated"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn set_tool_locale(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<ToolLocaleRequest>,
+) -> impl IntoResponse {
+    match catalog::set_locale(&library.database, &req.locale).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SuggestedRepliesRequest {
+    /// replies suggested after each teacher turn, 0 to 3, 0 suggests none
+    pub count: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/set_suggested_replies",
+    method(post),
+    request_body = SuggestedRepliesRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Number of replies suggested to students after each teacher turn updated"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn set_suggested_replies(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<SuggestedRepliesRequest>,
+) -> impl IntoResponse {
+    match suggestions::set_count(&library.database, req.count).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/conversations/{student_id}/{book_id}/replay",
+    method(get),
+    params(
+        ("student_id" = i64, Path, description = "ID of the student"),
+        ("book_id" = i64, Path, description = "ID of the book of the conversation"),
+        ("speed" = Option<f64>, Query, description = "Times the original pace, 1 by default, from 0.25 to 10")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The stored conversation replayed with its original pacing for review, with every event type of /api/user/chat/stream \
+            and student events for the student messages", content_type = "text/event-stream"),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn conversation_replay(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Path((student_id, book_id)): Path<(i64, i64)>,
+    Query(query): Query<ReplayQuery>,
+) -> impl IntoResponse {
+    // reviewers see every event a client could get
+    let capabilities = ClientCapabilities {
+        navigation: true,
+        blocks: true,
+        diagrams: true,
+        quizzes: true,
+    };
+    match replay::load(
+        library.message_store.as_ref(),
+        student_id,
+        book_id,
+        query.speed(),
+    )
+    .await
+    {
+        Ok(messages) => replay_stream(messages, capabilities).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct StudentQuizScoresQuery {
+    pub student_id: i64,
+    pub book_id: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/student_quiz_scores",
+    method(get),
+    params(
+        ("student_id" = i64, Query, description = "ID of the student"),
+        ("book_id" = i64, Query, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Scores of the quizzes the student took on the book, by chapter", body = Vec<QuizScore>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn student_quiz_scores(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Query(query): Query<StudentQuizScoresQuery>,
+) -> impl IntoResponse {
+    match quiz::scores(&library.database, query.student_id, query.book_id).await {
+        Ok(scores) => Json(scores).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/student_progress",
+    method(get),
+    params(
+        ("student_id" = i64, Query, description = "ID of the student"),
+        ("book_id" = i64, Query, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Progress of the student through the book", body = Object),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn student_progress(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Query(query): Query<StudentQuizScoresQuery>,
+) -> impl IntoResponse {
+    let result = async {
+        let messages_db = MessagesDatabase::new(
+            query.book_id,
+            query.student_id,
+            library.database.clone(),
+            library.message_store.clone(),
+        )
+        .await?;
+        messages_db.get_book_progress().await
+    };
+    match result.await {
+        Ok(progress) => Json(progress).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/export_snapshot",
+    method(get),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The database and bookbase as a zip archive, restore it with `book_teacher snapshot restore`", content_type = "application/zip"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn export_snapshot(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+) -> impl IntoResponse {
+    let result = async {
+        let temp = tempfile::tempdir()?;
+        let path = temp.path().join("snapshot.zip");
+        let manifest = snapshot::export(&library.database, &library.bookbase, &path).await?;
+        let file = tokio::fs::File::open(&path).await?;
+        // the temporary directory lives as long as the body streaming the archive out of it
+        let archive = ReaderStream::new(file).map(move |chunk| {
+            let _ = &temp;
+            chunk
+        });
+        anyhow::Ok((manifest, archive))
+    };
+    match result.await {
+        Ok((manifest, archive)) => {
+            let file_name = format!(
+                "attachment; filename=\"snapshot_{}.zip\"",
+                manifest.create_time.unix_timestamp()
+            );
+            (
+                [
+                    (
+                        axum::http::header::CONTENT_TYPE,
+                        "application/zip".to_string(),
+                    ),
+                    (axum::http::header::CONTENT_DISPOSITION, file_name),
+                ],
+                Body::from_stream(archive),
+            )
+                .into_response()
+        }
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+// job kinds, also the kinds of their dead letters
+const DELETE_BOOKS: &str = "delete_books";
+const REGENERATE_CHAPTER_PLANS: &str = "regenerate_chapter_plans";
+const ENROLL: &str = "enroll";
+const IMPORT_BOOKS: &str = "import_books";
+
+/// a failed import keeps its staging directory, so the dead letter can be requeued
+fn spawn_import_books(jobs: &Arc<JobQueue>, library: Arc<Library>, staged: Vec<StagedBook>) -> u64 {
+    jobs.spawn_batch(IMPORT_BOOKS, staged, move |staged| {
+        let library = library.clone();
+        async move { library.import_staged_book(&staged).await }
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BulkDeleteBooksRequest {
+    pub book_ids: Vec<i64>,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/bulk_delete_books",
+    method(post),
+    params(
+        ("dry_run" = Option<bool>, Query, description = "Only return what would be removed, without starting a job")
+    ),
+    request_body = BulkDeleteBooksRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "ID of the background job, poll it with /job_status; on a dry run the books that would be removed as a BatchPreview<BookDeletion>", body = u64),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin")
+    )
+)]
+pub async fn bulk_delete_books(
+    State(library): State<Arc<Library>>,
+    Extension(jobs): Extension<Arc<JobQueue>>,
+    _: AdminAuth,
+    Query(dry_run): Query<DryRunQuery>,
+    Json(req): Json<BulkDeleteBooksRequest>,
+) -> impl IntoResponse {
+    if dry_run.is_dry_run() {
+        let mut preview = BatchPreview::default();
+        for book_id in req.book_ids {
+            match library.preview_delete_book(book_id).await {
+                Ok(deletion) => preview.affected.push(deletion),
+                Err(e) => preview.skip(book_id, e),
+            }
+        }
+        return Json(preview).into_response();
+    }
+    Json(spawn_delete_books(&jobs, library, req.book_ids)).into_response()
+}
+
+fn spawn_delete_books(jobs: &Arc<JobQueue>, library: Arc<Library>, book_ids: Vec<i64>) -> u64 {
+    jobs.spawn_batch(DELETE_BOOKS, book_ids, move |book_id| {
+        let library = library.clone();
+        async move { library.delete_book(book_id).await }
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BulkRegeneratePlansRequest {
+    pub book_id: i64,
+    pub chapter_numbers: Vec<ChapterNumber>,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/bulk_regenerate_chapter_plans",
+    method(post),
+    params(
+        ("dry_run" = Option<bool>, Query, description = "Only return which chapters would be regenerated, without starting a job")
+    ),
+    request_body = BulkRegeneratePlansRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "ID of the background job, poll it with /job_status; on a dry run the chapters that would be regenerated as a BatchPreview<ChapterNumber>", body = u64),
+        (status = 400, description = "Bad request"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin")
+    )
+)]
+pub async fn bulk_regenerate_chapter_plans(
+    State(library): State<Arc<Library>>,
+    Extension(jobs): Extension<Arc<JobQueue>>,
+    _: AdminAuth,
+    Query(dry_run): Query<DryRunQuery>,
+    Json(req): Json<BulkRegeneratePlansRequest>,
+) -> impl IntoResponse {
+    if dry_run.is_dry_run() {
+        return match library
+            .preview_regenerate_chapter_plans(req.book_id, &req.chapter_numbers)
+            .await
+        {
+            Ok(preview) => Json(preview).into_response(),
+            Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        };
+    }
+    let targets = req
+        .chapter_numbers
+        .into_iter()
+        .map(|chapter_number| ChapterTarget {
+            book_id: req.book_id,
+            chapter_number,
+        })
+        .collect();
+    Json(spawn_regenerate_chapter_plans(&jobs, library, targets)).into_response()
+}
+
+/// A chapter of a book, the item of a plan regeneration job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChapterTarget {
+    pub book_id: i64,
+    pub chapter_number: ChapterNumber,
+}
+
+impl std::fmt::Display for ChapterTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "book {} chapter {}", self.book_id, self.chapter_number)
+    }
+}
+
+fn spawn_regenerate_chapter_plans(
+    jobs: &Arc<JobQueue>,
+    library: Arc<Library>,
+    targets: Vec<ChapterTarget>,
+) -> u64 {
+    jobs.spawn_batch(REGENERATE_CHAPTER_PLANS, targets, move |target| {
+        let library = library.clone();
+        async move {
+            library
+                .regenerate_chapter_plan(target.book_id, &target.chapter_number)
+                .await?;
+            Ok(())
+        }
+    })
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BulkEnrollRequest {
+    pub book_id: i64,
+    pub student_ids: Vec<i64>,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/bulk_enroll",
+    method(post),
+    params(
+        ("dry_run" = Option<bool>, Query, description = "Only return which students would be enrolled, without starting a job")
+    ),
+    request_body = BulkEnrollRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "ID of the background job, poll it with /job_status; on a dry run the students that would be enrolled as a BatchPreview<i64>", body = u64),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin")
+    )
+)]
+pub async fn bulk_enroll(
+    State(library): State<Arc<Library>>,
+    Extension(jobs): Extension<Arc<JobQueue>>,
+    _: AdminAuth,
+    Query(dry_run): Query<DryRunQuery>,
+    Json(req): Json<BulkEnrollRequest>,
+) -> impl IntoResponse {
+    if dry_run.is_dry_run() {
+        let mut preview = BatchPreview::default();
+        for student_id in req.student_ids {
+            if let Err(e) = student::get_student_info(&library.database, student_id).await {
+                preview.skip(student_id, e);
+                continue;
+            }
+            match student::is_enrolled(&library.database, student_id, req.book_id).await {
+                Ok(true) => preview.skip(student_id, "Already enrolled"),
+                Ok(false) => preview.affected.push(student_id),
+                Err(e) => preview.skip(student_id, e),
+            }
+        }
+        return Json(preview).into_response();
+    }
+    let enrollments = req
+        .student_ids
+        .into_iter()
+        .map(|student_id| Enrollment {
+            student_id,
+            book_id: req.book_id,
+        })
+        .collect();
+    Json(spawn_enroll(&jobs, library, enrollments)).into_response()
+}
+
+/// A student to enroll into a book, the item of an enroll job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Enrollment {
+    pub student_id: i64,
+    pub book_id: i64,
+}
+
+impl std::fmt::Display for Enrollment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "student {} into book {}", self.student_id, self.book_id)
+    }
+}
+
+fn spawn_enroll(jobs: &Arc<JobQueue>, library: Arc<Library>, enrollments: Vec<Enrollment>) -> u64 {
+    jobs.spawn_batch(ENROLL, enrollments, move |enrollment| {
+        let database = library.database.clone();
+        async move { TeacherAgent::init(enrollment.student_id, enrollment.book_id, database).await }
+    })
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/job_status",
+    method(get),
+    params(
+        ("job_id" = u64, Query, description = "ID returned by a bulk endpoint")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Progress of the job", body = JobStatus),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Unknown or pruned job")
+    )
+)]
+pub async fn job_status(
+    Extension(jobs): Extension<Arc<JobQueue>>,
+    _: ManagerAuth,
+    Query(job_id): Query<u64>,
+) -> impl IntoResponse {
+    match jobs.get(job_id) {
+        Some(job) => Json(job).into_response(),
+        None => (
+            axum::http::StatusCode::NOT_FOUND,
+            format!("Job not found: {job_id}"),
+        )
+            .into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/list_jobs",
+    method(get),
+    params(
+        ("cursor" = Option<String>, Query, description = "next_cursor of the previous page"),
+        ("limit" = Option<usize>, Query, description = "Items per page, defaults to 50, at most 500"),
+        ("sort" = Option<SortOrder>, Query, description = "Order of the job ids, defaults to desc, newest first")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "A page of the running and recently finished jobs", body = Paginated<JobStatus>),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Invalid cursor")
+    )
+)]
+pub async fn list_jobs(
+    Extension(jobs): Extension<Arc<JobQueue>>,
+    _: ManagerAuth,
+    Query(page): Query<PageQuery>,
+) -> impl IntoResponse {
+    match page.paginate(jobs.list(), SortOrder::Desc, |job| job.id) {
+        Ok(jobs) => Json(jobs).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/cancel_job",
+    method(post),
+    params(
+        ("job_id" = u64, Query, description = "ID of a running job")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Job cancelled, items already started still finish"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Unknown or finished job")
+    )
+)]
+pub async fn cancel_job(
+    Extension(jobs): Extension<Arc<JobQueue>>,
+    _: AdminAuth,
+    Query(job_id): Query<u64>,
+) -> impl IntoResponse {
+    match jobs.cancel(job_id) {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/retry_job",
+    method(post),
+    params(
+        ("job_id" = u64, Query, description = "ID of a completed or cancelled job")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The failed and cancelled items run again under the same job id"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Unknown or running job, or nothing to retry")
+    )
+)]
+pub async fn retry_job(
+    Extension(jobs): Extension<Arc<JobQueue>>,
+    _: AdminAuth,
+    Query(job_id): Query<u64>,
+) -> impl IntoResponse {
+    match jobs.retry(job_id) {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/job_policies",
+    method(get),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Policies by job kind, other kinds run one item at a time without retries", body = BTreeMap<String, JobPolicy>),
+        (status = 401, description = "Unauthorized")
+    )
+)]
+pub async fn job_policies(
+    Extension(jobs): Extension<Arc<JobQueue>>,
+    _: ManagerAuth,
+) -> impl IntoResponse {
+    Json(jobs.policies()).into_response()
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetJobPolicyRequest {
+    /// e.g. "regenerate_chapter_plans"
+    pub kind: String,
+    #[serde(flatten)]
+    pub policy: JobPolicy,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/set_job_policy",
+    method(post),
+    request_body = SetJobPolicyRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Policy set, it applies to jobs started afterwards"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin")
+    )
+)]
+pub async fn set_job_policy(
+    Extension(jobs): Extension<Arc<JobQueue>>,
+    _: AdminAuth,
+    Json(req): Json<SetJobPolicyRequest>,
+) -> impl IntoResponse {
+    jobs.set_policy(&req.kind, req.policy);
+    ().into_response()
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/ai_providers",
+    method(get),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The configured AI providers", body = Vec<ProviderInfo>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn ai_providers(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+) -> impl IntoResponse {
+    match ai_utils::list_providers(&library.database).await {
+        Ok(providers) => Json(providers).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetAiProviderRequest {
+    pub name: String,
+    #[serde(flatten)]
+    pub config: ProviderConfig,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/set_ai_provider",
+    method(post),
+    request_body = SetAiProviderRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Provider created or updated, returns its id", body = i64),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request, e.g. the api key variable is not set")
+    )
+)]
+pub async fn set_ai_provider(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<SetAiProviderRequest>,
+) -> impl IntoResponse {
+    match ai_utils::set_provider(&library.database, &req.name, &req.config).await {
+        Ok(id) => Json(id).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AssignAiProviderRequest {
+    /// the student, with `book_id` the student on that book
+    pub student_id: Option<i64>,
+    pub book_id: Option<i64>,
+    /// the provider, none removes the assignment, without student and book it sets the default
+    pub provider_id: Option<i64>,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/assign_ai_provider",
+    method(post),
+    request_body = AssignAiProviderRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Provider assigned, used from the next chat message"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn assign_ai_provider(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<AssignAiProviderRequest>,
+) -> impl IntoResponse {
+    match ai_utils::assign_provider(
+        &library.database,
+        req.student_id,
+        req.book_id,
+        req.provider_id,
+    )
+    .await
+    {
+        Ok(()) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetRecognitionProviderRequest {
+    /// a provider whose model accepts images, none for the chat provider of each conversation
+    pub provider_id: Option<i64>,
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/set_recognition_provider",
+    method(post),
+    request_body = SetRecognitionProviderRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Handwriting is recognized with the provider from now on"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn set_recognition_provider(
+    State(library): State<Arc<Library>>,
+    _: AdminAuth,
+    Json(req): Json<SetRecognitionProviderRequest>,
+) -> impl IntoResponse {
+    match ai_utils::set_recognition_provider(&library.database, req.provider_id).await {
+        Ok(()) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/dead_letters",
+    method(get),
+    params(
+        ("kind" = Option<String>, Query, description = "Only this job kind, e.g. \"regenerate_chapter_plans\""),
+        ("include_requeued" = Option<bool>, Query, description = "Also list the letters already requeued"),
+        ("cursor" = Option<String>, Query, description = "next_cursor of the previous page"),
+        ("limit" = Option<usize>, Query, description = "Items per page, defaults to 50, at most 500"),
+        ("sort" = Option<SortOrder>, Query, description = "Order of the ids, defaults to desc, newest first")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "A page of the job items that failed every attempt", body = Paginated<DeadLetter>),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Invalid cursor")
+    )
+)]
+pub async fn dead_letters(
+    State(library): State<Arc<Library>>,
+    _: ManagerAuth,
+    Query(filter): Query<DeadLetterFilter>,
+    Query(page): Query<PageQuery>,
+) -> impl IntoResponse {
+    match dead_letter::list(&library.database, &filter, &page).await {
+        Ok(letters) => Json(letters).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/requeue_dead_letter",
+    method(post),
+    params(
+        ("id" = i64, Query, description = "ID of the dead letter")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "ID of the new background job running the item again", body = u64),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
+        (status = 400, description = "Unknown or already requeued dead letter")
+    )
+)]
+pub async fn requeue_dead_letter(
+    State(library): State<Arc<Library>>,
+    Extension(jobs): Extension<Arc<JobQueue>>,
+    _: AdminAuth,
+    Query(id): Query<i64>,
+) -> impl IntoResponse {
+    let result = async {
+        let letter = dead_letter::get(&library.database, id).await?;
+        if letter.requeue_time.is_some() {
+            anyhow::bail!("Dead letter {} was already requeued", id);
+        }
+        let payload = letter.payload;
+        let job_id = match letter.kind.as_str() {
+            DELETE_BOOKS => spawn_delete_books(
+                &jobs,
+                library.clone(),
+                vec![serde_json::from_value(payload)?],
+            ),
+            REGENERATE_CHAPTER_PLANS => spawn_regenerate_chapter_plans(
+                &jobs,
+                library.clone(),
+                vec![serde_json::from_value(payload)?],
+            ),
+            ENROLL => spawn_enroll(
+                &jobs,
+                library.clone(),
+                vec![serde_json::from_value(payload)?],
+            ),
+            IMPORT_BOOKS => spawn_import_books(
+                &jobs,
+                library.clone(),
+                vec![serde_json::from_value(payload)?],
+            ),
+            kind => anyhow::bail!("Unknown job kind: {}", kind),
+        };
+        dead_letter::mark_requeued(&library.database, id, job_id).await?;
+        Ok(job_id)
+    };
+    match result.await {
+        Ok(job_id) => Json(job_id).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/manager",
+    path = "/sessions",
+    method(get),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The teacher sessions in memory, the most recently active first", body = Vec<SessionInfo>),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin")
+    )
+)]
+pub async fn list_sessions(
+    Extension(sessions): Extension<Arc<SessionManager>>,
+    _: AdminAuth,
+) -> impl IntoResponse {
+    Json(sessions.list()).into_response()
+}
+
+pub fn get_manager_scope(
+    limits: BodyLimits,
+    jobs: Arc<JobQueue>,
+    sessions: Arc<SessionManager>,
+) -> Router<Arc<Library>> {
+    Router::new().nest(
+        "/manager",
+        Router::new()
+            .route("/list_books", get(list_books))
+            .route(
+                "/upload_public_book",
+                post(upload_public_book).layer(DefaultBodyLimit::max(limits.upload)),
+            )
+            .route(
+                "/upload_book_archive",
+                post(upload_book_archive).layer(DefaultBodyLimit::max(limits.upload)),
+            )
+            .route("/remove_book", post(remove_book))
+            .route("/books/{book_id}", delete(delete_book))
+            .route("/reimport_book", post(reimport_book))
+            .route("/set_book_public", post(set_book_public))
+            .route("/set_book_tags", post(set_book_tags))
+            .route("/books/{book_id}/cover", get(get_book_cover))
+            .route(
+                "/books/{book_id}/metadata",
+                get(get_book_metadata)
+                    .put(set_book_metadata)
+                    .delete(clear_book_metadata),
+            )
+            .route("/book_status", get(book_status))
+            .route("/plan_reviews", get(plan_reviews))
+            .route("/books/{book_id}/content_ratings", get(content_ratings))
+            .route("/books/{book_id}/custom_tools", get(custom_tools))
+            .route(
+                "/books/{book_id}/lesson_scripts",
+                get(list_lesson_scripts).post(add_lesson_script),
+            )
+            .route("/lesson_scripts/{id}", delete(remove_lesson_script))
+            .route(
+                "/books/{book_id}/projects",
+                get(list_projects).post(create_project),
+            )
+            .route("/projects/{id}", delete(delete_project))
+            .route("/regenerate_chapter_plan", post(regenerate_chapter_plan))
+            .route("/approve_chapter_plan", post(approve_chapter_plan))
+            .route("/generation_log", get(generation_log))
+            .route("/usage", get(usage_report))
+            .route("/quota", get(get_quota))
+            .route("/set_quota", post(set_quota))
+            .route("/reset_quota", post(reset_quota))
+            .route("/list_students", get(list_students))
+            .route("/list_classes", get(list_classes))
+            .route("/create_class", post(create_class))
+            .route("/update_class", post(update_class))
+            .route("/set_student_class", post(set_student_class))
+            .route("/set_student_birth_date", post(set_student_birth_date))
+            .route(
+                "/classes/{class_id}/forbidden_topics",
+                get(list_forbidden_topics).post(add_forbidden_topic),
+            )
+            .route("/forbidden_topics/{id}", delete(remove_forbidden_topic))
+            .route("/classes/{class_id}/guardrail_log", get(guardrail_log))
+            .route("/tool_catalog", get(tool_catalog))
+            .route("/set_tool_text", post(set_tool_text))
+            .route("/reset_tool_text", post(reset_tool_text))
+            .route("/prompt_templates", get(prompt_templates))
+            .route("/set_prompt_template", post(set_prompt_template))
+            .route("/reset_prompt_template", post(reset_prompt_template))
+            .route("/set_tool_locale", post(set_tool_locale))
+            .route(
+                "/conversations/{student_id}/{book_id}/replay",
+                get(conversation_replay),
+            )
+            .route("/set_suggested_replies", post(set_suggested_replies))
+            .route("/student_quiz_scores", get(student_quiz_scores))
+            .route("/student_progress", get(student_progress))
+            .route("/export_snapshot", get(export_snapshot))
+            .route("/bulk_delete_books", post(bulk_delete_books))
+            .route(
+                "/bulk_regenerate_chapter_plans",
+                post(bulk_regenerate_chapter_plans),
+            )
+            .route("/bulk_enroll", post(bulk_enroll))
+            .route("/job_status", get(job_status))
+            .route("/list_jobs", get(list_jobs))
+            .route("/cancel_job", post(cancel_job))
+            .route("/retry_job", post(retry_job))
+            .route("/job_policies", get(job_policies))
+            .route("/set_job_policy", post(set_job_policy))
+            .route("/ai_providers", get(ai_providers))
+            .route("/set_ai_provider", post(set_ai_provider))
+            .route("/assign_ai_provider", post(assign_ai_provider))
+            .route("/set_recognition_provider", post(set_recognition_provider))
+            .route("/dead_letters", get(dead_letters))
+            .route("/requeue_dead_letter", post(requeue_dead_letter))
+            .route("/sessions", get(list_sessions))
+            // every route above needs a manager session, admin only handlers check the role
+            .route_layer(middleware::from_fn(require_manager))
+            .route("/login", post(login))
+            .route("/logout", post(logout))
+            .layer(Extension(jobs))
+            .layer(Extension(sessions))
+            .layer(DefaultBodyLimit::max(limits.default)),
     )
 }
@@ -1,32 +1,118 @@
-use crate::books::book::BookMeta;
-use crate::books::library::Library;
+use crate::books::assets;
+use crate::books::book::{BookMeta, Difficulty};
+use crate::books::cover::{CoverSize, cover_content_type};
+use crate::books::library::{BookOrder, BookQuery, Library};
+use crate::pagination::{PageQuery, Paginated, SortOrder};
 use axum::{
     Router,
-    extract::{Json, State},
-    response::IntoResponse,
+    extract::{Json, Path, Query, State},
+    response::{IntoResponse, Response},
     routing::get,
 };
+use serde::Deserialize;
 use std::sync::Arc;
 
 #[utoipa::path(
     context_path = "/api/public",
     path = "/public_books",
     method(get),
+    params(
+        ("cursor" = Option<String>, Query, description = "next_cursor of the previous page"),
+        ("limit" = Option<usize>, Query, description = "Items per page, defaults to 50, at most 500"),
+        ("offset" = Option<usize>, Query, description = "Books to skip after the cursor"),
+        ("sort" = Option<SortOrder>, Query, description = "asc or desc, defaults to the order of order_by"),
+        ("title" = Option<String>, Query, description = "Only books whose title contains this, ignoring case"),
+        ("tag" = Option<String>, Query, description = "Only books with this tag"),
+        ("language" = Option<String>, Query, description = "Only books in this language"),
+        ("difficulty" = Option<Difficulty>, Query, description = "Only books of this difficulty"),
+        ("order_by" = Option<BookOrder>, Query, description = "id (default, ascending), title (ascending) or added (newest first)")
+    ),
     responses(
-        (status = 200, description = "List of public books", body = Vec<BookMeta>),
-        (status = 500, description = "Internal server error")
+        (status = 200, description = "A page of public books with the total of matching books", body = Paginated<BookMeta>),
+        (status = 400, description = "Invalid cursor")
     )
 )]
-pub async fn get_public_books(State(library): State<Arc<Library>>) -> impl IntoResponse {
-    match library.get_book_list(true).await {
+pub async fn get_public_books(
+    State(library): State<Arc<Library>>,
+    Query(page): Query<PageQuery>,
+    Query(query): Query<BookQuery>,
+) -> impl IntoResponse {
+    match library.list_books(true, &query, &page).await {
         Ok(books) => Json(books).into_response(),
-        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
     }
 }
 
+#[derive(Deserialize)]
+pub struct CoverQuery {
+    #[serde(default)]
+    pub size: CoverSize,
+}
+
+/// the cover of the book at the size of the query as an image response
+pub(crate) async fn cover_response(
+    library: &Library,
+    book_id: i64,
+    public_only: bool,
+    query: &CoverQuery,
+) -> Response {
+    let cover = match library.book_cover(book_id, public_only, query.size).await {
+        Ok(Some(cover)) => cover,
+        Ok(None) => return axum::http::StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+        }
+    };
+    let content_type = cover_content_type(&cover).unwrap_or("application/octet-stream");
+    // svg covers come from uploaded books and aren't rasterized, like svg assets they are
+    // sandboxed and downloaded when opened on their own, an <img> still shows them
+    let disposition = if assets::is_active_content(content_type) {
+        "attachment"
+    } else {
+        "inline"
+    };
+    match tokio::fs::read(&cover).await {
+        Ok(image) => (
+            [
+                (axum::http::header::CONTENT_TYPE, content_type),
+                (axum::http::header::CACHE_CONTROL, "public, max-age=3600"),
+                (axum::http::header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+                (axum::http::header::CONTENT_SECURITY_POLICY, "sandbox"),
+                (axum::http::header::CONTENT_DISPOSITION, disposition),
+            ],
+            image,
+        )
+            .into_response(),
+        Err(_) => axum::http::StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/public",
+    path = "/books/{book_id}/cover",
+    method(get),
+    params(
+        ("book_id" = i64, Path, description = "ID of the public book"),
+        ("size" = Option<CoverSize>, Query, description = "small (160px wide), medium (320px, default), large (640px) or original")
+    ),
+    responses(
+        (status = 200, description = "The cover image of the book, a jpeg thumbnail except for the original size and svg covers", content_type = "image/*"),
+        (status = 404, description = "No public book with a cover")
+    )
+)]
+pub async fn get_book_cover(
+    State(library): State<Arc<Library>>,
+    Path(book_id): Path<i64>,
+    Query(query): Query<CoverQuery>,
+) -> impl IntoResponse {
+    cover_response(&library, book_id, true, &query).await
+}
+
 pub fn get_public_scope() -> Router<Arc<Library>> {
     Router::new().nest(
         "/public",
-        Router::new().route("/public_books", get(get_public_books)),
+        Router::new()
+            .route("/public_books", get(get_public_books))
+            .route("/books/{book_id}/cover", get(get_book_cover)),
     )
 }
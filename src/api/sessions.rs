@@ -0,0 +1,231 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        Arc,
+        atomic::{AtomicI64, Ordering},
+    },
+};
+
+use moka::future::Cache;
+use serde::Serialize;
+use time::{Duration, OffsetDateTime};
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{books::library::Library, teacher::TeacherAgent};
+
+/// most sessions kept at once, new conversations are refused beyond it until idle ones are
+/// evicted; the cache itself is unbounded so it never drops a teacher without suspending it
+const MAX_SESSIONS: u64 = 1000;
+
+/// The teacher of a student for a book, created on the first request of the conversation
+pub struct TeacherSession {
+    student_id: i64,
+    book_id: i64,
+    teacher: Mutex<TeacherAgent>,
+    created_at: OffsetDateTime,
+    /// unix seconds of the end of the last use
+    last_active: AtomicI64,
+}
+
+/// The locked teacher of a session, the session counts as active until it is dropped
+pub struct SessionGuard<'a> {
+    session: &'a TeacherSession,
+    teacher: MutexGuard<'a, TeacherAgent>,
+}
+
+impl Deref for SessionGuard<'_> {
+    type Target = TeacherAgent;
+
+    fn deref(&self) -> &TeacherAgent {
+        &self.teacher
+    }
+}
+
+impl DerefMut for SessionGuard<'_> {
+    fn deref_mut(&mut self) -> &mut TeacherAgent {
+        &mut self.teacher
+    }
+}
+
+impl Drop for SessionGuard<'_> {
+    fn drop(&mut self) {
+        self.session.touch();
+    }
+}
+
+impl TeacherSession {
+    /// wait for the teacher, inputs from several clients are answered one after the other
+    pub async fn lock(&self) -> SessionGuard<'_> {
+        let teacher = self.teacher.lock().await;
+        self.touch();
+        SessionGuard {
+            session: self,
+            teacher,
+        }
+    }
+
+    fn touch(&self) {
+        self.last_active.store(
+            OffsetDateTime::now_utc().unix_timestamp(),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn last_active(&self) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp(self.last_active.load(Ordering::Relaxed))
+            .unwrap_or(self.created_at)
+    }
+}
+
+/// A session as the admins see it
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SessionInfo {
+    pub student_id: i64,
+    pub book_id: i64,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String)]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String)]
+    pub last_active: OffsetDateTime,
+    /// a response is being generated or the conversation is being read
+    pub busy: bool,
+}
+
+/// The teacher sessions of the server, keyed by student and book. Idle sessions are evicted by
+/// [`Self::evict_idle`], after storing the state of their teacher; the messages themselves are
+/// stored as they are added.
+pub struct SessionManager {
+    sessions: Cache<(i64, i64), Arc<TeacherSession>>,
+    idle_timeout: Duration,
+}
+
+impl SessionManager {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Self {
+            sessions: Cache::builder().build(),
+            idle_timeout,
+        }
+    }
+
+    /// the session of the conversation, its teacher created on the first call; fails for a new
+    /// conversation when [`MAX_SESSIONS`] are open
+    pub async fn get(
+        &self,
+        library: Arc<Library>,
+        student_id: i64,
+        book_id: i64,
+    ) -> anyhow::Result<Arc<TeacherSession>> {
+        if let Some(session) = self.sessions.get(&(student_id, book_id)).await {
+            return Ok(session);
+        }
+        if self.sessions.entry_count() >= MAX_SESSIONS {
+            // the count lags behind invalidations until the pending ones are applied
+            self.sessions.run_pending_tasks().await;
+            if self.sessions.entry_count() >= MAX_SESSIONS {
+                anyhow::bail!("Too many open teacher sessions, try again later");
+            }
+        }
+        self.sessions
+            .try_get_with((student_id, book_id), async move {
+                let teacher = TeacherAgent::new(library, student_id, book_id).await?;
+                let created_at = OffsetDateTime::now_utc();
+                Ok::<_, anyhow::Error>(Arc::new(TeacherSession {
+                    student_id,
+                    book_id,
+                    teacher: Mutex::new(teacher),
+                    created_at,
+                    last_active: AtomicI64::new(created_at.unix_timestamp()),
+                }))
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("{e}"))
+    }
+
+    /// store the state of the teachers of the student and drop their sessions, they are
+    /// created again on the next request, e.g. with the instruction of a new locale; waits for
+    /// the responses still holding a session
+    pub async fn end_student(&self, student_id: i64) {
+        for (key, session) in self.sessions.iter() {
+            if key.0 != student_id {
+                continue;
+            }
+            let mut teacher = session.teacher.lock().await;
+            // removed while locked, so a new request gets a new teacher rather than this one
+            self.sessions.invalidate(&*key).await;
+            if let Err(e) = teacher.suspend().await {
+                warn!(
+                    student_id = key.0,
+                    book_id = key.1,
+                    "failed to store the state of a teacher: {e:?}"
+                );
+            }
+        }
+    }
+
+    /// the sessions of the server, the most recently active first
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> = self
+            .sessions
+            .iter()
+            .map(|(_, session)| SessionInfo {
+                student_id: session.student_id,
+                book_id: session.book_id,
+                created_at: session.created_at,
+                last_active: session.last_active(),
+                busy: session.teacher.try_lock().is_err(),
+            })
+            .collect();
+        sessions.sort_by(|a, b| b.last_active.cmp(&a.last_active));
+        sessions
+    }
+
+    /// store the state of every teacher and drop the sessions, when the server shuts down;
+    /// waits for the responses still holding a session
+    pub async fn close(&self) {
+        let mut closed = 0;
+        for (key, session) in self.sessions.iter() {
+            self.sessions.invalidate(&*key).await;
+            if let Err(e) = session.teacher.lock().await.suspend().await {
+                warn!(
+                    student_id = key.0,
+                    book_id = key.1,
+                    "failed to store the state of a teacher: {e:?}"
+                );
+            }
+            closed += 1;
+        }
+        info!("closed {closed} teacher sessions");
+    }
+
+    /// evict the sessions idle for longer than the timeout, a busy session is never evicted;
+    /// returns how many were
+    pub async fn evict_idle(&self) -> usize {
+        let now = OffsetDateTime::now_utc();
+        let mut evicted = 0;
+        for (key, session) in self.sessions.iter() {
+            if now - session.last_active() < self.idle_timeout {
+                continue;
+            }
+            let Ok(mut teacher) = session.teacher.try_lock() else {
+                continue;
+            };
+            // removed while locked, so a new request gets a new teacher rather than this one
+            self.sessions.invalidate(&*key).await;
+            if let Err(e) = teacher.suspend().await {
+                warn!(
+                    student_id = key.0,
+                    book_id = key.1,
+                    "failed to store the state of an idle teacher: {e:?}"
+                );
+            }
+            evicted += 1;
+        }
+        if evicted > 0 {
+            info!("evicted {evicted} idle teacher sessions");
+        }
+        evicted
+    }
+}
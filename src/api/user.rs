@@ -1,34 +1,90 @@
-use std::{convert::Infallible, sync::Arc, time::Duration};
+use std::{collections::HashSet, convert::Infallible, sync::Arc, time::Duration};
 
 use async_openai::types::{
-    ChatCompletionRequestAssistantMessageContent, ChatCompletionRequestAssistantMessageContentPart,
-    ChatCompletionRequestMessage, ChatCompletionRequestToolMessageContent,
+    ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessageContent,
+    ChatCompletionRequestAssistantMessageContentPart, ChatCompletionRequestMessage,
+    ChatCompletionRequestToolMessage, ChatCompletionRequestToolMessageContent,
     ChatCompletionRequestToolMessageContentPart, ChatCompletionRequestUserMessageContent,
     ChatCompletionRequestUserMessageContentPart,
 };
 use axum::{
     Extension, Router,
-    extract::{Json, Multipart, Query, State},
+    extract::{
+        DefaultBodyLimit, Json, Multipart, Path, Query, State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
+    },
+    http::HeaderMap,
     response::{
         IntoResponse, Sse,
         sse::{self, Event},
     },
     routing::{get, post},
 };
-use moka::future::Cache;
+use book_model::TocNode;
+use futures::{
+    SinkExt, StreamExt,
+    stream::{SplitSink, SplitStream},
+};
 use serde::{Deserialize, Serialize};
-use tokio::sync::{Mutex, mpsc::channel};
+use tokio::sync::{broadcast::error::RecvError, mpsc::channel};
 use tokio_stream::wrappers::ReceiverStream;
 use tower_sessions::Session;
 use utoipa::ToSchema;
 
 use crate::{
-    books::{book::BookMeta, library::Library},
+    abuse::{ChatThrottle, ThrottleEvent},
+    agent_profile::{self, AgentProfile},
+    ai_utils,
+    books::{
+        accessibility::{self, AccessibilityMode},
+        assets,
+        book::{Book, BookMeta, ChapterMatch},
+        chapter::{Chapter, ChapterNumber},
+        export::{self, ExportFormat, ExportOptions},
+        library::Library,
+        render::{self, ChapterFormat},
+        search::{DEFAULT_FUSION, Fusion, HybridHit, SearchHit},
+        stats::BookStats,
+        tools::{BookLocation, SessionBooks},
+    },
+    course::{self, Course, CoursePlan, Lesson, TodaysLesson},
+    flashcard::{self, Flashcard, ReviewState},
+    focus::{self, FocusSummary},
+    handwriting::{self, Handwriting, Recognition},
+    pagination::{PageQuery, Paginated, SortOrder},
+    project::{self, Project, StartProjectRequest, StudentProject, Submission},
+    quiz::{self, Quiz, QuizAnswers, QuizRequest, QuizResult, QuizScore},
+    quota,
+    receipts::{self, DeviceReceipt},
+    scratchpad::{self, Editor, Scratchpad, VersionConflict},
+    scripting::{self, ScheduledReview},
     student::{self, StudentInfo},
-    teacher::TeacherAgent,
+    student_memory::{self, StudentMemory},
+    teacher::{
+        ResponseEvent, TeacherAgent,
+        blocks::ResponseBlock,
+        capabilities::ClientCapabilities,
+        messages::{
+            MessagesDatabase,
+            history::{self, HistoryEntry, HistoryFilter, MessageRole, read_message},
+            pace::StudyEstimate,
+            progress::ConfidenceCheckIn,
+            replay::{self, ReplayMessage},
+        },
+        navigation,
+    },
+    utils::now_local,
+    workspace::{self, Workspace},
 };
 
-use super::upload_books;
+use super::{
+    BodyLimits,
+    auth::{AdminAuth, STUDENT_SESSION_KEY, StudentAuth},
+    delivery::{ChatSocketAck, ChatSocketStop, Deliveries, Outbox, RESUME_WINDOW, SequencedFrame},
+    followers::{Followers, RESTARTING},
+    sessions::{SessionManager, TeacherSession},
+    upload_books,
+};
 
 #[derive(Deserialize, ToSchema)]
 pub struct CreateUserRequest {
@@ -71,7 +127,8 @@ pub struct LoginRequest {
     request_body = LoginRequest,
     responses(
         (status = 200, description = "Login successful"),
-        (status = 400, description = "Invalid credentials")
+        (status = 400, description = "Invalid credentials"),
+        (status = 500, description = "Internal server error")
     )
 )]
 pub async fn login(
@@ -84,7 +141,12 @@ pub async fn login(
     let password = req.password;
     match student::login(&db, email, password).await {
         Ok(id) => {
-            session.insert("student_id", id).await.unwrap();
+            // a new session id, so an id planted in the browser before the login is worthless
+            if let Err(e) = session.cycle_id().await {
+                return (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+                    .into_response();
+            }
+            session.insert(STUDENT_SESSION_KEY, id).await.unwrap();
             "Login successful".into_response()
         }
         Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
@@ -95,17 +157,18 @@ pub async fn login(
     context_path = "/api/user",
     path = "/user_info",
     method(get),
+    security(("session" = [])),
     responses(
         (status = 200, description = "User info", body = StudentInfo),
         (status = 401, description = "Unauthorized"),
         (status = 500, description = "Internal server error")
     )
 )]
-pub async fn user_info(State(library): State<Arc<Library>>, session: Session) -> impl IntoResponse {
+pub async fn user_info(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+) -> impl IntoResponse {
     let db = library.database.clone();
-    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
-        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
-    };
     match student::get_student_info(&db, student_id).await {
         Ok(user) => Json(user).into_response(),
         Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
@@ -125,26 +188,68 @@ pub async fn logout(session: Session) -> impl IntoResponse {
     "Logout successful".into_response()
 }
 
+#[derive(Deserialize, ToSchema)]
+pub struct SetLocaleRequest {
+    /// e.g. "en" or "zh-CN", unsupported locales fall back to "en"
+    pub locale: String,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/set_locale",
+    method(post),
+    request_body = SetLocaleRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The supported locale that was set", body = String),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn set_locale(
+    State(library): State<Arc<Library>>,
+    Extension(sessions): Extension<Arc<SessionManager>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<SetLocaleRequest>,
+) -> impl IntoResponse {
+    match student::set_student_locale(&library.database, student_id, &req.locale).await {
+        Ok(locale) => {
+            // the teachers of the student are rebuilt with the new instruction on the next message
+            sessions.end_student(student_id).await;
+            locale.into_response()
+        }
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
 #[utoipa::path(
     context_path = "/api/user",
     path = "/list_books",
     method(get),
+    params(
+        ("cursor" = Option<String>, Query, description = "next_cursor of the previous page"),
+        ("limit" = Option<usize>, Query, description = "Items per page, defaults to 50, at most 500"),
+        ("sort" = Option<SortOrder>, Query, description = "Order of the book ids, defaults to asc")
+    ),
+    security(("session" = [])),
     responses(
-        (status = 200, description = "List of books", body = Vec<BookMeta>),
+        (status = 200, description = "A page of books", body = Paginated<BookMeta>),
         (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Invalid cursor"),
         (status = 500, description = "Internal server error")
     )
 )]
 pub async fn list_books(
     State(library): State<Arc<Library>>,
-    session: Session,
+    StudentAuth(student_id): StudentAuth,
+    Query(page): Query<PageQuery>,
 ) -> impl IntoResponse {
     let db = library.database.clone();
-    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
-        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
-    };
     match student::get_student_books(&db, student_id).await {
-        Ok(books) => Json(books).into_response(),
+        Ok(books) => match page.paginate(books, SortOrder::Asc, |book| book.id) {
+            Ok(books) => Json(books).into_response(),
+            Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+        },
         Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
     }
 }
@@ -153,21 +258,22 @@ pub async fn list_books(
     context_path = "/api/user",
     path = "/upload_and_add_books",
     method(post),
+    security(("session" = [])),
     responses(
         (status = 200, description = "Upload successful"),
         (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
         (status = 500, description = "Internal server error")
     )
 )]
 pub async fn upload_and_add_books(
     State(library): State<Arc<Library>>,
-    session: Session,
+    // adding and removing books is library management, done by an admin for the student
+    _: AdminAuth,
+    StudentAuth(student_id): StudentAuth,
     multipart: Multipart,
 ) -> impl IntoResponse {
     let db = library.database.clone();
-    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
-        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
-    };
     match upload_books(multipart, library).await {
         Ok(book_ids) => match student::add_student_books(&db, student_id, book_ids).await {
             Ok(_) => "Upload successful".into_response(),
@@ -186,21 +292,21 @@ pub async fn upload_and_add_books(
     params(
         ("book_id" = i64, Query, description = "ID of the book to add")
     ),
+    security(("session" = [])),
     responses(
         (status = 200, description = "Book added successfully"),
         (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
         (status = 400, description = "Bad request")
     )
 )]
 pub async fn add_book(
     State(library): State<Arc<Library>>,
-    session: Session,
+    _: AdminAuth,
+    StudentAuth(student_id): StudentAuth,
     Query(book_id): Query<i64>,
 ) -> impl IntoResponse {
     let db = library.database.clone();
-    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
-        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
-    };
     match TeacherAgent::init(student_id, book_id, db).await {
         Ok(_) => ().into_response(),
         Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
@@ -214,226 +320,3167 @@ pub async fn add_book(
     params(
         ("book_id" = i64, Query, description = "ID of the book to delete")
     ),
+    security(("session" = [])),
     responses(
         (status = 200, description = "Book deleted successfully"),
         (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not an admin"),
         (status = 400, description = "Bad request")
     )
 )]
 pub async fn delete_book(
     State(library): State<Arc<Library>>,
-    session: Session,
+    _: AdminAuth,
+    StudentAuth(student_id): StudentAuth,
     Query(book_id): Query<i64>,
 ) -> impl IntoResponse {
     let db = library.database.clone();
-    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
-        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
-    };
-    match student::delete_student_book(&db, student_id, book_id).await {
+    match student::delete_student_book(&db, library.message_store.as_ref(), student_id, book_id)
+        .await
+    {
         Ok(_) => ().into_response(),
         Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
     }
 }
 
-type TeacherAgentCache = Cache<(i64, i64), Arc<Mutex<TeacherAgent>>>;
+#[derive(Deserialize)]
+pub struct FindChapterQuery {
+    pub book_id: i64,
+    pub title: String,
+}
 
-#[derive(Serialize, ToSchema)]
-pub enum ConversationMessage {
-    User {
-        content: String,
-    },
-    Assistant {
-        content: String,
-        tool_calls: Vec<String>,
-    },
-    Tool {
-        content: String,
-    },
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/find_chapter",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book to search in"),
+        ("title" = String, Query, description = "Approximate title of the chapter")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Matching chapters, best match first", body = Vec<ChapterMatch>),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn find_chapter(
+    State(library): State<Arc<Library>>,
+    _: StudentAuth,
+    Query(query): Query<FindChapterQuery>,
+) -> impl IntoResponse {
+    match library.get_book(query.book_id).await {
+        Ok(book) => Json(book.find_chapters_by_title(&query.title, 10)).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
 }
 
-impl TryFrom<ChatCompletionRequestMessage> for ConversationMessage {
-    type Error = ();
+#[derive(Deserialize)]
+pub struct SearchBookQuery {
+    pub book_id: i64,
+    pub query: String,
+    pub limit: Option<i64>,
+}
 
-    fn try_from(message: ChatCompletionRequestMessage) -> Result<Self, ()> {
-        match message {
-            ChatCompletionRequestMessage::User(msg) => match msg.content {
-                ChatCompletionRequestUserMessageContent::Text(text) => {
-                    Ok(Self::User { content: text })
-                }
-                ChatCompletionRequestUserMessageContent::Array(arr) => {
-                    let mut content = String::new();
-                    for a in arr {
-                        let ChatCompletionRequestUserMessageContentPart::Text(text) = a else {
-                            continue;
-                        };
-                        content.push_str(&text.text);
-                    }
-                    Ok(Self::User { content })
-                }
-            },
-            ChatCompletionRequestMessage::Assistant(msg) => {
-                let mut content = String::new();
-                match msg.content {
-                    Some(ChatCompletionRequestAssistantMessageContent::Text(t)) => content = t,
-                    Some(ChatCompletionRequestAssistantMessageContent::Array(arr)) => {
-                        for a in arr {
-                            let ChatCompletionRequestAssistantMessageContentPart::Text(t) = a
-                            else {
-                                continue;
-                            };
-                            content.push_str(&t.text);
-                        }
-                    }
-                    None => {}
-                }
-                let tool_calls = msg
-                    .tool_calls
-                    .unwrap_or_default()
-                    .into_iter()
-                    .map(|t| t.function.name)
-                    .collect();
-                Ok(Self::Assistant {
-                    content,
-                    tool_calls,
-                })
-            }
-            ChatCompletionRequestMessage::Tool(msg) => {
-                let mut content = String::new();
-                match msg.content {
-                    ChatCompletionRequestToolMessageContent::Text(t) => content = t,
-                    ChatCompletionRequestToolMessageContent::Array(arr) => {
-                        for a in arr {
-                            let ChatCompletionRequestToolMessageContentPart::Text(t) = a;
-                            content.push_str(&t.text);
-                        }
-                    }
-                };
-                Ok(Self::Tool { content })
-            }
-            _ => Err(()),
-        }
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/search_book",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book to search in"),
+        ("query" = String, Query, description = "Words to look for, chapters matching any of them are returned"),
+        ("limit" = Option<i64>, Query, description = "Maximum number of chapters, defaults to 20")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Matching chapters with excerpts, most relevant first", body = Vec<SearchHit>),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not learning the book"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn search_book(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(query): Query<SearchBookQuery>,
+) -> impl IntoResponse {
+    if !matches!(
+        student::is_enrolled(&library.database, student_id, query.book_id).await,
+        Ok(true)
+    ) {
+        return (axum::http::StatusCode::FORBIDDEN, ()).into_response();
+    }
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let result = async {
+        let book = library.get_book(query.book_id).await?;
+        let age = student::get_student_age(&library.database, student_id).await?;
+        let mut hits = library
+            .search_book(query.book_id, &query.query, limit)
+            .await?;
+        hits.retain(|hit| allowed_hit(&book, &hit.chapter_number, age));
+        anyhow::Ok(hits)
+    };
+    match result.await {
+        Ok(hits) => Json(hits).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
     }
 }
 
+/// whether a search hit on `chapter_number` may be shown to a student of `age`, like
+/// [`get_chapter`] checks it
+fn allowed_hit(book: &Book, chapter_number: &str, age: Option<u8>) -> bool {
+    chapter_number
+        .parse::<ChapterNumber>()
+        .is_ok_and(|number| book.check_age(&number, age).is_ok())
+}
+
+#[derive(Deserialize)]
+pub struct HybridSearchQuery {
+    pub book_id: i64,
+    pub query: String,
+    pub limit: Option<usize>,
+    pub fusion: Option<String>,
+}
+
 #[utoipa::path(
     context_path = "/api/user",
-    path = "/get_conversation",
+    path = "/hybrid_search",
     method(get),
     params(
-        ("book_id" = i64, Query, description = "ID of the book to get conversation for")
+        ("book_id" = i64, Query, description = "ID of the book to search in"),
+        ("query" = String, Query, description = "Keywords or a question, chapters matching the words or close in meaning are returned"),
+        ("limit" = Option<usize>, Query, description = "Maximum number of chapters, defaults to 20"),
+        ("fusion" = Option<String>, Query, description = "rrf (default, rrf:60), rrf:<k> or weighted:<keyword weight from 0 to 1>")
     ),
+    security(("session" = [])),
     responses(
-        (status = 200, description = "Conversation", body = Vec<ConversationMessage>),
+        (status = 200, description = "One hit per chapter with its best passage, most relevant first", body = Vec<HybridHit>),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not learning the book"),
+        (status = 400, description = "Bad request")
     )
 )]
-pub async fn get_conversation(
+pub async fn hybrid_search(
     State(library): State<Arc<Library>>,
-    Extension(cache): Extension<Arc<TeacherAgentCache>>,
-    session: Session,
-    Query(book_id): Query<i64>,
+    StudentAuth(student_id): StudentAuth,
+    Query(query): Query<HybridSearchQuery>,
 ) -> impl IntoResponse {
-    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
-        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
-    };
-    let teacher = match cache
-        .try_get_with((student_id, book_id), async move {
-            match TeacherAgent::new(library, student_id, book_id).await {
-                Ok(teacher) => {
-                    let teacher = Arc::new(Mutex::new(teacher));
-                    Ok(teacher)
-                }
-                Err(e) => Err(e.to_string()),
-            }
-        })
-        .await
-    {
-        Ok(teacher) => teacher,
-        Err(e) => {
+    let fusion = match query.fusion.as_deref().map(str::parse::<Fusion>) {
+        Some(Ok(fusion)) => fusion,
+        Some(Err(e)) => {
             return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
         }
+        None => *DEFAULT_FUSION,
     };
-    let teacher = teacher.lock().await;
-    let history: Vec<ConversationMessage> = teacher
-        .get_conversation()
-        .await
-        .into_iter()
-        .filter_map(|m| ConversationMessage::try_from(m).ok())
-        .collect();
-    Json(history).into_response()
+    if !matches!(
+        student::is_enrolled(&library.database, student_id, query.book_id).await,
+        Ok(true)
+    ) {
+        return (axum::http::StatusCode::FORBIDDEN, ()).into_response();
+    }
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let result = async {
+        let book = library.get_book(query.book_id).await?;
+        let age = student::get_student_age(&library.database, student_id).await?;
+        let mut hits = library
+            .hybrid_search(query.book_id, &query.query, limit, fusion, Some(student_id))
+            .await?;
+        hits.retain(|hit| allowed_hit(&book, &hit.chapter_number, age));
+        anyhow::Ok(hits)
+    };
+    match result.await {
+        Ok(hits) => Json(hits).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
 }
 
-#[derive(Deserialize, ToSchema)]
-pub struct ChatRequest {
-    book_id: i64,
-    message: String,
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/book_toc",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Chapters nested by their numbers, in book order", body = Vec<TocNode>),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn book_toc(
+    State(library): State<Arc<Library>>,
+    _: StudentAuth,
+    Query(book_id): Query<i64>,
+) -> impl IntoResponse {
+    match library.get_book(book_id).await {
+        Ok(book) => Json(book.toc()).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
 }
 
 #[utoipa::path(
     context_path = "/api/user",
-    path = "/chat",
-    method(post),
-    request_body = ChatRequest,
+    path = "/book_stats",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book")
+    ),
+    security(("session" = [])),
     responses(
-        (status = 200, description = "Chat response stream", content_type = "text/event-stream"),
+        (status = 200, description = "Word count, reading time and block counts", body = BookStats),
         (status = 401, description = "Unauthorized"),
         (status = 400, description = "Bad request")
     )
 )]
-pub async fn chat(
+pub async fn book_stats(
     State(library): State<Arc<Library>>,
-    Extension(cache): Extension<Arc<TeacherAgentCache>>,
-    session: Session,
-    Json(req): Json<ChatRequest>,
+    _: StudentAuth,
+    Query(book_id): Query<i64>,
 ) -> impl IntoResponse {
-    let Ok(Some(student_id)) = session.get::<i64>("student_id").await else {
-        return (axum::http::StatusCode::UNAUTHORIZED, ()).into_response();
-    };
-    let ChatRequest { book_id, message } = req;
-    let teacher = match cache
-        .try_get_with((student_id, book_id), async move {
-            match TeacherAgent::new(library, student_id, book_id).await {
-                Ok(teacher) => {
-                    let teacher = Arc::new(Mutex::new(teacher));
-                    Ok(teacher)
-                }
-                Err(e) => Err(e.to_string()),
-            }
-        })
-        .await
-    {
-        Ok(teacher) => teacher,
-        Err(e) => {
-            return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
-        }
+    match library.get_book(book_id).await {
+        Ok(book) => Json(book.get_stats()).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StudyEstimateQuery {
+    pub book_id: i64,
+    pub chapter_number: ChapterNumber,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/study_estimate",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book"),
+        ("chapter_number" = String, Query, description = "Chapter number, e.g. \"3.1.\"")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Estimated study time of the chapter", body = StudyEstimate),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn study_estimate(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(query): Query<StudyEstimateQuery>,
+) -> impl IntoResponse {
+    let result = async {
+        let book = library.get_book(query.book_id).await?;
+        let messages_db = MessagesDatabase::new(
+            query.book_id,
+            student_id,
+            library.database.clone(),
+            library.message_store.clone(),
+        )
+        .await?;
+        let pace = messages_db.get_study_pace(&book).await?;
+        pace.estimate(&book, &query.chapter_number)
+            .ok_or(anyhow::anyhow!(
+                "Chapter not found: {}",
+                query.chapter_number
+            ))
     };
-    let (tx, rx) = channel::<Result<Event, Infallible>>(100);
-    tokio::spawn(async move {
-        let mut teacher = teacher.lock().await;
-        let _ = teacher.input(message.into(), tx).await;
-    });
+    match result.await {
+        Ok(estimate) => Json(estimate).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
 
-    let stream = ReceiverStream::new(rx);
-    let sse = Sse::new(stream).keep_alive(sse::KeepAlive::new().interval(Duration::from_secs(10)));
+/// the url of the asset route of the book, chapter images and audio are served under it
+fn assets_url(book_id: i64) -> String {
+    format!("/api/user/books/{book_id}/assets")
+}
 
-    sse.into_response()
+#[derive(Deserialize)]
+pub struct CourseQuery {
+    pub book_id: i64,
 }
 
-pub fn get_user_scope(cache: Arc<TeacherAgentCache>) -> Router<Arc<Library>> {
-    Router::new().nest(
-        "/user",
-        Router::new()
-            .route("/create_user", post(create_user))
-            .route("/login", post(login))
-            .route("/user_info", get(user_info))
-            .route("/logout", post(logout))
-            .route("/list_books", get(list_books))
-            .route("/delete_book", post(delete_book))
-            .route("/add_book", post(add_book))
-            .route("/upload_and_add_books", post(upload_and_add_books))
-            .route(
-                "/get_conversation",
-                get(get_conversation).layer(Extension(cache.clone())),
+/// The course of the student on a book with their pacing today
+#[derive(Serialize, ToSchema)]
+pub struct CourseStatus {
+    pub course: Course,
+    pub today: TodaysLesson,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/course",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The course schedule and today's lesson", body = CourseStatus),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "No course for the book"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn get_course(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(CourseQuery { book_id }): Query<CourseQuery>,
+) -> impl IntoResponse {
+    let result = async {
+        let Some(course) = course::get_course(&library.database, student_id, book_id).await? else {
+            return anyhow::Ok(None);
+        };
+        let messages_db = MessagesDatabase::new(
+            book_id,
+            student_id,
+            library.database.clone(),
+            library.message_store.clone(),
+        )
+        .await?;
+        let completed = messages_db
+            .completed_chapters()
+            .await?
+            .into_iter()
+            .collect();
+        let today = course.todays_lesson(now_local().date(), &completed);
+        Ok(Some(CourseStatus { course, today }))
+    };
+    match result.await {
+        Ok(Some(status)) => Json(status).into_response(),
+        Ok(None) => axum::http::StatusCode::NOT_FOUND.into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/plan_course",
+    method(post),
+    request_body = CoursePlan,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The new course, the remaining chapters spread over sessions by the student's pace", body = Course),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn plan_course(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(plan): Json<CoursePlan>,
+) -> impl IntoResponse {
+    let result = async {
+        let book = library.get_book(plan.book_id).await?;
+        let messages_db = MessagesDatabase::new(
+            book.id,
+            student_id,
+            library.database.clone(),
+            library.message_store.clone(),
+        )
+        .await?;
+        let pace = messages_db.get_study_pace(&book).await?;
+        // completed chapters and those above the age of the student are not scheduled
+        let age = student::get_student_age(&library.database, student_id).await?;
+        let mut skip: HashSet<ChapterNumber> = messages_db
+            .completed_chapters()
+            .await?
+            .into_iter()
+            .collect();
+        skip.extend(
+            book.restricted_chapters(age)
+                .into_iter()
+                .map(|(number, _)| number.clone()),
+        );
+        let lessons = course::schedule(
+            &book,
+            &pace,
+            &skip,
+            plan.start_date.unwrap_or(now_local().date()),
+            plan.sessions_per_week,
+            plan.minutes_per_session,
+        )?;
+        if lessons.is_empty() {
+            anyhow::bail!("Every chapter of the book is completed");
+        }
+        course::set_course(&library.database, student_id, &book, lessons).await
+    };
+    match result.await {
+        Ok(course) => Json(course).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetCourseRequest {
+    pub book_id: i64,
+    /// the lessons in date order, their session numbers are assigned in that order
+    pub lessons: Vec<Lesson>,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/set_course",
+    method(post),
+    request_body = SetCourseRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The course, replacing the previous one", body = Course),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn set_course(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<SetCourseRequest>,
+) -> impl IntoResponse {
+    let result = async {
+        let book = library.get_book(req.book_id).await?;
+        let age = student::get_student_age(&library.database, student_id).await?;
+        for number in req.lessons.iter().flat_map(|lesson| &lesson.chapters) {
+            book.check_age(number, age)?;
+        }
+        course::set_course(&library.database, student_id, &book, req.lessons).await
+    };
+    match result.await {
+        Ok(course) => Json(course).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/clear_course",
+    method(post),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The course of the book was removed"),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn clear_course(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(CourseQuery { book_id }): Query<CourseQuery>,
+) -> impl IntoResponse {
+    match course::delete_course(&library.database, student_id, book_id).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AgentProfileQuery {
+    /// the book of the profile, the student-wide profile when missing
+    pub book_id: Option<i64>,
+}
+
+/// The teaching style set at a scope, with the one the teacher follows on a book
+#[derive(Serialize, ToSchema)]
+pub struct AgentProfileView {
+    /// the fields set at the scope, all unset when there is no profile
+    pub profile: AgentProfile,
+    /// the book profile over the student-wide one, only for a book
+    pub effective: Option<AgentProfile>,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/agent_profile",
+    method(get),
+    params(
+        ("book_id" = Option<i64>, Query, description = "ID of the book, the student-wide profile when missing")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The teaching style of the teacher", body = AgentProfileView),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn get_agent_profile(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(AgentProfileQuery { book_id }): Query<AgentProfileQuery>,
+) -> impl IntoResponse {
+    let result = async {
+        let profile = agent_profile::get_profile(&library.database, student_id, book_id)
+            .await?
+            .unwrap_or_default();
+        let effective = match book_id {
+            Some(book_id) => Some(
+                agent_profile::effective_profile(&library.database, student_id, book_id).await?,
+            ),
+            None => None,
+        };
+        anyhow::Ok(AgentProfileView { profile, effective })
+    };
+    match result.await {
+        Ok(view) => Json(view).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetAgentProfileRequest {
+    /// the book of the profile, the student-wide profile when missing
+    pub book_id: Option<i64>,
+    pub profile: AgentProfile,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/set_agent_profile",
+    method(post),
+    request_body = SetAgentProfileRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The profile was set, replacing the one at its scope; the teacher follows it from the next session on"),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn set_agent_profile(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<SetAgentProfileRequest>,
+) -> impl IntoResponse {
+    let result = async {
+        let enrolled = match req.book_id {
+            Some(book_id) => student::is_enrolled(&library.database, student_id, book_id).await?,
+            None => true,
+        };
+        if !enrolled {
+            anyhow::bail!("The book is not in your library");
+        }
+        agent_profile::set_profile(&library.database, student_id, req.book_id, &req.profile).await
+    };
+    match result.await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/clear_agent_profile",
+    method(post),
+    params(
+        ("book_id" = Option<i64>, Query, description = "ID of the book, the student-wide profile when missing")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The profile at the scope was removed"),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn clear_agent_profile(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(AgentProfileQuery { book_id }): Query<AgentProfileQuery>,
+) -> impl IntoResponse {
+    match agent_profile::delete_profile(&library.database, student_id, book_id).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SessionBooksQuery {
+    pub book_id: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/session_books",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the main book of the session")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The books of the teaching session on the book", body = SessionBooks),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn get_session_books(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(SessionBooksQuery { book_id }): Query<SessionBooksQuery>,
+) -> impl IntoResponse {
+    let result = async {
+        MessagesDatabase::new(
+            book_id,
+            student_id,
+            library.database.clone(),
+            library.message_store.clone(),
+        )
+        .await?
+        .get_session_books()
+        .await
+    };
+    match result.await {
+        Ok(books) => Json(books).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SetSessionBooksRequest {
+    /// the main book of the session
+    pub book_id: i64,
+    /// the books to study alongside it, replacing the linked ones, empty to unlink them all
+    pub linked_book_ids: Vec<i64>,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/set_session_books",
+    method(post),
+    request_body = SetSessionBooksRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The books of the session, the teacher reads them from the next session on", body = SessionBooks),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn set_session_books(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<SetSessionBooksRequest>,
+) -> impl IntoResponse {
+    let result = async {
+        MessagesDatabase::new(
+            req.book_id,
+            student_id,
+            library.database.clone(),
+            library.message_store.clone(),
+        )
+        .await?
+        .set_linked_books(&req.linked_book_ids)
+        .await
+    };
+    match result.await {
+        Ok(books) => Json(books).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChapterContentQuery {
+    pub book_id: i64,
+    pub chapter_number: ChapterNumber,
+    #[serde(default)]
+    pub format: ChapterFormat,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/chapter",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book"),
+        ("chapter_number" = String, Query, description = "Chapter number, e.g. \"3.1.\""),
+        ("format" = Option<ChapterFormat>, Query, description = "markdown (default) or html, rendered with highlighted code and heading anchors matching the sector titles of book locations")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The chapter, its images and audio pointing at /api/user/books/{book_id}/assets", body = Chapter),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request, or the chapter is rated above the age of the student")
+    )
+)]
+pub async fn get_chapter(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(query): Query<ChapterContentQuery>,
+) -> impl IntoResponse {
+    let result = async {
+        let book = library.get_book(query.book_id).await?;
+        let age = student::get_student_age(&library.database, student_id).await?;
+        book.check_age(&query.chapter_number, age)?;
+        let mut chapter = book
+            .chapters
+            .get(&query.chapter_number)
+            .ok_or(anyhow::anyhow!(
+                "Chapter not found: {}",
+                query.chapter_number
+            ))?
+            .clone();
+        chapter.content = assets::rewrite_asset_links(
+            &chapter.content,
+            chapter.path.as_deref(),
+            &assets_url(book.id),
+        );
+        if query.format == ChapterFormat::Html {
+            chapter.content = render::render_html(&chapter.content);
+        }
+        anyhow::Ok(chapter)
+    };
+    match result.await {
+        Ok(chapter) => Json(chapter).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/books/{book_id}/assets/{path}",
+    method(get),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book"),
+        ("path" = String, Path, description = "Path of the image or audio file relative to the book sources, e.g. images/fig1.png")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The image or audio file", content_type = "application/octet-stream"),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "No such image or audio file in the book")
+    )
+)]
+pub async fn get_book_asset(
+    State(library): State<Arc<Library>>,
+    _: StudentAuth,
+    Path((book_id, path)): Path<(i64, String)>,
+) -> impl IntoResponse {
+    let file = match library.book_asset(book_id, &path).await {
+        Ok(file) => file,
+        Err(e) => return (axum::http::StatusCode::NOT_FOUND, e.to_string()).into_response(),
+    };
+    let content_type = assets::asset_content_type(&file).unwrap_or("application/octet-stream");
+    // books are uploaded by students, an svg opened on its own would run its scripts on our
+    // origin: the sandbox keeps them from running and svgs are downloaded, images still show
+    let disposition = if assets::is_active_content(content_type) {
+        "attachment"
+    } else {
+        "inline"
+    };
+    match tokio::fs::read(&file).await {
+        Ok(data) => (
+            [
+                (axum::http::header::CONTENT_TYPE, content_type),
+                (axum::http::header::CACHE_CONTROL, "private, max-age=3600"),
+                (axum::http::header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+                (axum::http::header::CONTENT_SECURITY_POLICY, "sandbox"),
+                (axum::http::header::CONTENT_DISPOSITION, disposition),
+            ],
+            data,
+        )
+            .into_response(),
+        Err(_) => axum::http::StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AccessibleChapterQuery {
+    pub book_id: i64,
+    pub chapter_number: ChapterNumber,
+    pub mode: AccessibilityMode,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/accessible_chapter",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book"),
+        ("chapter_number" = String, Query, description = "Chapter number, e.g. \"3.1.\""),
+        ("mode" = AccessibilityMode, Query, description = "How to transform the chapter content")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Chapter content in markdown, transformed for accessibility", body = String),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn accessible_chapter(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(query): Query<AccessibleChapterQuery>,
+) -> impl IntoResponse {
+    let result = async {
+        let book = library.get_book(query.book_id).await?;
+        let age = student::get_student_age(&library.database, student_id).await?;
+        book.check_age(&query.chapter_number, age)?;
+        let chapter = book
+            .chapters
+            .get(&query.chapter_number)
+            .ok_or(anyhow::anyhow!(
+                "Chapter not found: {}",
+                query.chapter_number
+            ))?;
+        let provider = library.provider(Some(student_id), Some(book.id)).await?;
+        accessibility::get_accessible_content(
+            &library.database,
+            provider.as_ref(),
+            book.id,
+            chapter,
+            query.mode,
+        )
+        .await
+        .map(|content| {
+            assets::rewrite_asset_links(&content, chapter.path.as_deref(), &assets_url(book.id))
+        })
+    };
+    match result.await {
+        Ok(content) => content.into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ExportChapterQuery {
+    pub book_id: i64,
+    pub chapter_number: ChapterNumber,
+    pub font_size: Option<u32>,
+    pub line_height: Option<f32>,
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/export_chapter",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book"),
+        ("chapter_number" = String, Query, description = "Chapter number, e.g. \"3.1.\""),
+        ("font_size" = Option<u32>, Query, description = "Base font size in points, 18 by default"),
+        ("line_height" = Option<f32>, Query, description = "Line height relative to the font size, 1.6 by default"),
+        ("format" = Option<ExportFormat>, Query, description = "epub (default) or pdf, laid out on A5 pages with the standard PDF fonts, which print characters outside Latin-1 as ?")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The chapter as an EPUB or PDF file", content_type = "application/epub+zip"),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn export_chapter(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(query): Query<ExportChapterQuery>,
+) -> impl IntoResponse {
+    let result = async {
+        let book = library.get_book(query.book_id).await?;
+        let age = student::get_student_age(&library.database, student_id).await?;
+        book.check_age(&query.chapter_number, age)?;
+        let chapter = book
+            .chapters
+            .get(&query.chapter_number)
+            .ok_or(anyhow::anyhow!(
+                "Chapter not found: {}",
+                query.chapter_number
+            ))?;
+        let mut options = ExportOptions::default();
+        if let Some(font_size) = query.font_size {
+            options.font_size = font_size;
+        }
+        if let Some(line_height) = query.line_height {
+            options.line_height = line_height;
+        }
+        match query.format {
+            ExportFormat::Epub => export::export_chapter_epub(&book, chapter, &options),
+            ExportFormat::Pdf => export::export_chapter_pdf(&book, chapter, &options),
+        }
+    };
+    match result.await {
+        Ok(file) => {
+            let file_name = format!(
+                "attachment; filename=\"book_{}_chapter_{}.{}\"",
+                query.book_id,
+                query.chapter_number.to_string().trim_end_matches('.'),
+                query.format.extension()
+            );
+            (
+                [
+                    (
+                        axum::http::header::CONTENT_TYPE,
+                        query.format.content_type().to_string(),
+                    ),
+                    (axum::http::header::CONTENT_DISPOSITION, file_name),
+                ],
+                file,
+            )
+                .into_response()
+        }
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/start_focus",
+    method(post),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book to focus on")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "ID of the focus session", body = i64),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn start_focus(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(book_id): Query<i64>,
+) -> impl IntoResponse {
+    match focus::start_focus(&library.database, student_id, book_id).await {
+        Ok(id) => Json(id).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/stop_focus",
+    method(post),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Summary of the stopped focus session", body = FocusSummary),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "No active focus session"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn stop_focus(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(book_id): Query<i64>,
+) -> impl IntoResponse {
+    match focus::stop_focus(&library.database, student_id, book_id).await {
+        Ok(Some(summary)) => Json(summary).into_response(),
+        Ok(None) => (axum::http::StatusCode::NOT_FOUND, ()).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CheckInRequest {
+    pub book_id: i64,
+    #[serde(flatten)]
+    pub checkin: ConfidenceCheckIn,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/checkin",
+    method(post),
+    request_body = CheckInRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Check-in recorded"),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn checkin(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<CheckInRequest>,
+) -> impl IntoResponse {
+    let result = async {
+        let messages_db = MessagesDatabase::new(
+            req.book_id,
+            student_id,
+            library.database.clone(),
+            library.message_store.clone(),
+        )
+        .await?;
+        messages_db.add_checkin(&req.checkin).await
+    };
+    match result.await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/list_checkins",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Confidence check-ins over time, oldest first", body = Vec<ConfidenceCheckIn>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_checkins(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(book_id): Query<i64>,
+) -> impl IntoResponse {
+    let result = async {
+        let messages_db = MessagesDatabase::new(
+            book_id,
+            student_id,
+            library.database.clone(),
+            library.message_store.clone(),
+        )
+        .await?;
+        messages_db.get_checkins().await
+    };
+    match result.await {
+        Ok(checkins) => Json(checkins).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct GenerateQuizRequest {
+    pub book_id: i64,
+    #[serde(flatten)]
+    pub quiz: QuizRequest,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/generate_quiz",
+    method(post),
+    request_body = GenerateQuizRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "A new quiz on the chapter, without the answers", body = Quiz),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not learning the book"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn generate_quiz(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<GenerateQuizRequest>,
+) -> impl IntoResponse {
+    if !matches!(
+        student::is_enrolled(&library.database, student_id, req.book_id).await,
+        Ok(true)
+    ) {
+        return (axum::http::StatusCode::FORBIDDEN, ()).into_response();
+    }
+    let result = async {
+        let book = library.get_book(req.book_id).await?;
+        let provider = library.provider(Some(student_id), Some(book.id)).await?;
+        quiz::generate(
+            &library.database,
+            provider.as_ref(),
+            &book,
+            &req.quiz.chapter_number,
+            req.quiz.questions(),
+        )
+        .await
+    };
+    match result.await {
+        Ok(quiz) => Json(quiz.without_answers()).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/submit_quiz",
+    method(post),
+    request_body = QuizAnswers,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The graded answers and the score", body = QuizResult),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not learning the book of the quiz"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn submit_quiz(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<QuizAnswers>,
+) -> impl IntoResponse {
+    let book_id = match quiz::get_quiz(&library.database, req.quiz_id).await {
+        Ok(quiz) => quiz.book_id,
+        Err(e) => return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    if !matches!(
+        student::is_enrolled(&library.database, student_id, book_id).await,
+        Ok(true)
+    ) {
+        return (axum::http::StatusCode::FORBIDDEN, ()).into_response();
+    }
+    let result = async {
+        let provider = library.provider(Some(student_id), Some(book_id)).await?;
+        quiz::submit(
+            &library.database,
+            provider.as_ref(),
+            req.quiz_id,
+            student_id,
+            req.answers,
+        )
+        .await
+    };
+    match result.await {
+        Ok(result) => Json(result).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/quiz_scores",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Scores of the quizzes taken on the book, by chapter", body = Vec<QuizScore>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn quiz_scores(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(book_id): Query<i64>,
+) -> impl IntoResponse {
+    match quiz::scores(&library.database, student_id, book_id).await {
+        Ok(scores) => Json(scores).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct GenerateFlashcardsRequest {
+    pub book_id: i64,
+    pub chapter_number: ChapterNumber,
+    /// how many cards to generate, 10 if not given
+    pub count: Option<usize>,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/generate_flashcards",
+    method(post),
+    request_body = GenerateFlashcardsRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "New cards on the chapter, due right away", body = Vec<Flashcard>),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not learning the book"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn generate_flashcards(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<GenerateFlashcardsRequest>,
+) -> impl IntoResponse {
+    if !matches!(
+        student::is_enrolled(&library.database, student_id, req.book_id).await,
+        Ok(true)
+    ) {
+        return (axum::http::StatusCode::FORBIDDEN, ()).into_response();
+    }
+    let result = async {
+        let book = library.get_book(req.book_id).await?;
+        let provider = library.provider(Some(student_id), Some(book.id)).await?;
+        flashcard::generate(
+            &library.database,
+            provider.as_ref(),
+            student_id,
+            &book,
+            &req.chapter_number,
+            req.count.unwrap_or(10).clamp(1, 50),
+        )
+        .await
+    };
+    match result.await {
+        Ok(cards) => Json(cards).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DueFlashcardsQuery {
+    /// only the cards of this book, all books if not given
+    pub book_id: Option<i64>,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/due_flashcards",
+    method(get),
+    params(
+        ("book_id" = Option<i64>, Query, description = "Only the cards of this book, all books if not given")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Cards due by the end of today (UTC), earliest first", body = Vec<Flashcard>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn due_flashcards(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(query): Query<DueFlashcardsQuery>,
+) -> impl IntoResponse {
+    match flashcard::due_today(&library.database, student_id, query.book_id).await {
+        Ok(cards) => Json(cards).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReviewsQuery {
+    pub book_id: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/reviews",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Reviews of chapters the lesson scripts of the book scheduled, not done yet, earliest first", body = Vec<ScheduledReview>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn reviews(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(ReviewsQuery { book_id }): Query<ReviewsQuery>,
+) -> impl IntoResponse {
+    match scripting::list_reviews(&library.database, student_id, book_id, false).await {
+        Ok(reviews) => Json(reviews).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ScratchpadQuery {
+    pub book_id: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/scratchpad",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The Markdown scratchpad shared with the teacher on the book, empty at version 0 until written", body = Scratchpad),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn get_scratchpad(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(ScratchpadQuery { book_id }): Query<ScratchpadQuery>,
+) -> impl IntoResponse {
+    match scratchpad::get(&library.database, student_id, book_id).await {
+        Ok(scratchpad) => Json(scratchpad).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SaveScratchpadRequest {
+    pub book_id: i64,
+    /// Markdown, replaces the whole scratchpad
+    pub content: String,
+    /// the version the edit is based on, refused if the teacher changed the scratchpad since
+    pub version: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/save_scratchpad",
+    method(post),
+    request_body = SaveScratchpadRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The saved scratchpad with its new version", body = Scratchpad),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "The book is not in the student's library"),
+        (status = 409, description = "The scratchpad changed since the version, merge with the current one and save again"),
+        (status = 400, description = "Bad request, e.g. too long")
+    )
+)]
+pub async fn save_scratchpad(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<SaveScratchpadRequest>,
+) -> impl IntoResponse {
+    if !matches!(
+        student::is_enrolled(&library.database, student_id, req.book_id).await,
+        Ok(true)
+    ) {
+        return (axum::http::StatusCode::FORBIDDEN, ()).into_response();
+    }
+    let result = scratchpad::save(
+        &library.database,
+        student_id,
+        req.book_id,
+        &req.content,
+        Editor::Student,
+        Some(req.version),
+    )
+    .await;
+    match result {
+        Ok(scratchpad) => Json(scratchpad).into_response(),
+        Err(e) if e.is::<VersionConflict>() => {
+            (axum::http::StatusCode::CONFLICT, e.to_string()).into_response()
+        }
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct WorkspaceQuery {
+    pub book_id: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/workspace",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The Git repository linked to the book, null when none", body = Option<Workspace>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn get_workspace(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(WorkspaceQuery { book_id }): Query<WorkspaceQuery>,
+) -> impl IntoResponse {
+    match workspace::get(&library.database, student_id, book_id).await {
+        Ok(workspace) => Json(workspace).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct LinkWorkspaceRequest {
+    pub book_id: i64,
+    /// https clone url of a public repository
+    pub url: String,
+    /// the default branch of the repository when absent
+    pub branch: Option<String>,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/link_workspace",
+    method(post),
+    request_body = LinkWorkspaceRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The linked repository, the teacher reads its code from now on", body = Workspace),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "The book is not in the student's library"),
+        (status = 400, description = "Bad request, e.g. not an https url")
+    )
+)]
+pub async fn link_workspace(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<LinkWorkspaceRequest>,
+) -> impl IntoResponse {
+    if !matches!(
+        student::is_enrolled(&library.database, student_id, req.book_id).await,
+        Ok(true)
+    ) {
+        return (axum::http::StatusCode::FORBIDDEN, ()).into_response();
+    }
+    let result = workspace::link(
+        &library.database,
+        &library.bookbase,
+        student_id,
+        req.book_id,
+        &req.url,
+        req.branch.as_deref(),
+    )
+    .await;
+    match result {
+        Ok(workspace) => Json(workspace).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct WorkspaceRequest {
+    pub book_id: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/unlink_workspace",
+    method(post),
+    request_body = WorkspaceRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The repository is unlinked and its checkout deleted"),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "No repository is linked to the book"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn unlink_workspace(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<WorkspaceRequest>,
+) -> impl IntoResponse {
+    match workspace::unlink(
+        &library.database,
+        &library.bookbase,
+        student_id,
+        req.book_id,
+    )
+    .await
+    {
+        Ok(true) => (axum::http::StatusCode::OK, ()).into_response(),
+        Ok(false) => (axum::http::StatusCode::NOT_FOUND, ()).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ProjectsQuery {
+    pub book_id: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/projects",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The projects of the book with their milestones", body = Vec<Project>),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "The book is not in the student's library"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn projects(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(ProjectsQuery { book_id }): Query<ProjectsQuery>,
+) -> impl IntoResponse {
+    if !matches!(
+        student::is_enrolled(&library.database, student_id, book_id).await,
+        Ok(true)
+    ) {
+        return (axum::http::StatusCode::FORBIDDEN, ()).into_response();
+    }
+    match project::list_projects(&library.database, book_id).await {
+        Ok(projects) => Json(projects).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// 403 unless the student learns the book of the project
+async fn check_project_access(
+    library: &Library,
+    student_id: i64,
+    project_id: i64,
+) -> Result<Project, axum::response::Response> {
+    let project = match project::get_project(&library.database, project_id).await {
+        Ok(project) => project,
+        Err(e) => {
+            return Err((axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response());
+        }
+    };
+    if !matches!(
+        student::is_enrolled(&library.database, student_id, project.book_id).await,
+        Ok(true)
+    ) {
+        return Err((axum::http::StatusCode::FORBIDDEN, ()).into_response());
+    }
+    Ok(project)
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/start_project",
+    method(post),
+    request_body = StartProjectRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The started project with the due date of each milestone", body = StudentProject),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "The book of the project is not in the student's library"),
+        (status = 400, description = "Bad request, e.g. already started")
+    )
+)]
+pub async fn start_project(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<StartProjectRequest>,
+) -> impl IntoResponse {
+    if let Err(response) = check_project_access(&library, student_id, req.project_id).await {
+        return response;
+    }
+    match project::start_project(
+        &library.database,
+        student_id,
+        req.project_id,
+        req.start_date,
+    )
+    .await
+    {
+        Ok(project) => Json(project).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ProjectQuery {
+    pub project_id: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/project",
+    method(get),
+    params(
+        ("project_id" = i64, Query, description = "ID of the project")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The progress of the student on the project, with the artifacts, the check-ins and the report", body = StudentProject),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request, e.g. not started")
+    )
+)]
+pub async fn get_project(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(ProjectQuery { project_id }): Query<ProjectQuery>,
+) -> impl IntoResponse {
+    match project::get_student_project(&library.database, student_id, project_id).await {
+        Ok(project) => Json(project).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SubmitArtifactRequest {
+    pub project_id: i64,
+    pub milestone_id: i64,
+    /// the work handed in, e.g. Markdown or code
+    pub content: String,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/submit_artifact",
+    method(post),
+    request_body = SubmitArtifactRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The artifact with the check-in of the teacher", body = Submission),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "The book of the project is not in the student's library"),
+        (status = 400, description = "Bad request, e.g. too long or the project is finished")
+    )
+)]
+pub async fn submit_artifact(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<SubmitArtifactRequest>,
+) -> impl IntoResponse {
+    let book_id = match check_project_access(&library, student_id, req.project_id).await {
+        Ok(project) => project.book_id,
+        Err(response) => return response,
+    };
+    let result = async {
+        let book = library.get_book(book_id).await?;
+        let provider = library.provider(Some(student_id), Some(book_id)).await?;
+        project::submit(
+            &library.database,
+            provider.as_ref(),
+            &book,
+            student_id,
+            req.project_id,
+            req.milestone_id,
+            &req.content,
+        )
+        .await
+    };
+    match result.await {
+        Ok(submission) => Json(submission).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/project_report",
+    method(post),
+    request_body = ProjectQuery,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The finished project with the final report of the teacher", body = StudentProject),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "The book of the project is not in the student's library"),
+        (status = 400, description = "Bad request, e.g. a milestone is not met yet")
+    )
+)]
+pub async fn project_report(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<ProjectQuery>,
+) -> impl IntoResponse {
+    let book_id = match check_project_access(&library, student_id, req.project_id).await {
+        Ok(project) => project.book_id,
+        Err(response) => return response,
+    };
+    let result = async {
+        let provider = library.provider(Some(student_id), Some(book_id)).await?;
+        project::generate_report(
+            &library.database,
+            provider.as_ref(),
+            student_id,
+            req.project_id,
+        )
+        .await
+    };
+    match result.await {
+        Ok(project) => Json(project).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ReviewFlashcardRequest {
+    pub card_id: i64,
+    /// how well the student recalled the back, from 0 (blackout) to 5 (perfect recall)
+    pub quality: u8,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/review_flashcard",
+    method(post),
+    request_body = ReviewFlashcardRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The new review state, the card is due again in interval_days", body = ReviewState),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn review_flashcard(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<ReviewFlashcardRequest>,
+) -> impl IntoResponse {
+    match flashcard::review(&library.database, student_id, req.card_id, req.quality).await {
+        Ok(state) => Json(state).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub enum ConversationMessage {
+    User {
+        content: String,
+    },
+    Assistant {
+        content: String,
+        tool_calls: Vec<String>,
+        /// The model that produced the message, absent for older messages
+        #[serde(skip_serializing_if = "Option::is_none")]
+        model: Option<String>,
+    },
+    Tool {
+        content: String,
+    },
+}
+
+impl TryFrom<ChatCompletionRequestMessage> for ConversationMessage {
+    type Error = ();
+
+    fn try_from(message: ChatCompletionRequestMessage) -> Result<Self, ()> {
+        match message {
+            ChatCompletionRequestMessage::User(msg) => match msg.content {
+                ChatCompletionRequestUserMessageContent::Text(text) => {
+                    Ok(Self::User { content: text })
+                }
+                ChatCompletionRequestUserMessageContent::Array(arr) => {
+                    let mut content = String::new();
+                    for a in arr {
+                        let ChatCompletionRequestUserMessageContentPart::Text(text) = a else {
+                            continue;
+                        };
+                        content.push_str(&text.text);
+                    }
+                    Ok(Self::User { content })
+                }
+            },
+            ChatCompletionRequestMessage::Assistant(msg) => {
+                let mut content = String::new();
+                match msg.content {
+                    Some(ChatCompletionRequestAssistantMessageContent::Text(t)) => content = t,
+                    Some(ChatCompletionRequestAssistantMessageContent::Array(arr)) => {
+                        for a in arr {
+                            let ChatCompletionRequestAssistantMessageContentPart::Text(t) = a
+                            else {
+                                continue;
+                            };
+                            content.push_str(&t.text);
+                        }
+                    }
+                    None => {}
+                }
+                let tool_calls = msg
+                    .tool_calls
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|t| t.function.name)
+                    .collect();
+                Ok(Self::Assistant {
+                    content,
+                    tool_calls,
+                    model: None,
+                })
+            }
+            ChatCompletionRequestMessage::Tool(msg) => {
+                let mut content = String::new();
+                match msg.content {
+                    ChatCompletionRequestToolMessageContent::Text(t) => content = t,
+                    ChatCompletionRequestToolMessageContent::Array(arr) => {
+                        for a in arr {
+                            let ChatCompletionRequestToolMessageContentPart::Text(t) = a;
+                            content.push_str(&t.text);
+                        }
+                    }
+                };
+                Ok(Self::Tool { content })
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/get_conversation",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book to get conversation for"),
+        ("cursor" = Option<String>, Query, description = "next_cursor of the previous page"),
+        ("limit" = Option<usize>, Query, description = "Items per page, defaults to 50, at most 500"),
+        ("sort" = Option<SortOrder>, Query, description = "Order of the messages, defaults to asc, oldest first")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "A page of the conversation", body = Paginated<ConversationMessage>),
+        (status = 400, description = "Bad request or invalid cursor")
+    )
+)]
+pub async fn get_conversation(
+    State(library): State<Arc<Library>>,
+    Extension(sessions): Extension<Arc<SessionManager>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(book_id): Query<i64>,
+    Query(page): Query<PageQuery>,
+) -> impl IntoResponse {
+    let teacher = match sessions.get(library, student_id, book_id).await {
+        Ok(teacher) => teacher,
+        Err(e) => {
+            return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    };
+    let teacher = teacher.lock().await;
+    let history: Vec<ConversationMessage> = teacher
+        .get_conversation()
+        .await
+        .into_iter()
+        .zip(teacher.get_message_models())
+        .filter_map(|(m, model)| {
+            let mut message = ConversationMessage::try_from(m).ok()?;
+            if let ConversationMessage::Assistant { model: m, .. } = &mut message {
+                *m = model;
+            }
+            Some(message)
+        })
+        .collect();
+    match page.paginate_positions(history, SortOrder::Asc) {
+        Ok(history) => Json(history).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/conversation_history",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book"),
+        ("role" = Option<MessageRole>, Query, description = "Only messages of this role"),
+        ("from" = Option<String>, Query, description = "Only messages stored at or after this RFC 3339 time"),
+        ("to" = Option<String>, Query, description = "Only messages stored before this RFC 3339 time"),
+        ("cursor" = Option<String>, Query, description = "next_cursor of the previous page"),
+        ("limit" = Option<usize>, Query, description = "Items per page, defaults to 50, at most 500"),
+        ("sort" = Option<SortOrder>, Query, description = "Order of the message positions, defaults to asc, oldest first")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "A page of the whole stored conversation, including messages no longer in the teacher's context", body = Paginated<HistoryEntry>),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request or invalid cursor")
+    )
+)]
+pub async fn conversation_history(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(filter): Query<HistoryFilter>,
+    Query(page): Query<PageQuery>,
+) -> impl IntoResponse {
+    match history::page(library.message_store.as_ref(), student_id, &filter, &page).await {
+        Ok(page) => Json(page).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/conversation_transcript",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book"),
+        ("role" = Option<MessageRole>, Query, description = "Only messages of this role"),
+        ("from" = Option<String>, Query, description = "Only messages stored at or after this RFC 3339 time"),
+        ("to" = Option<String>, Query, description = "Only messages stored before this RFC 3339 time")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The stored conversation as a markdown file", content_type = "text/markdown"),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn conversation_transcript(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Query(filter): Query<HistoryFilter>,
+) -> impl IntoResponse {
+    match history::list(library.message_store.as_ref(), student_id, &filter).await {
+        Ok(entries) => {
+            let file_name = format!(
+                "attachment; filename=\"book_{}_conversation.md\"",
+                filter.book_id
+            );
+            (
+                [
+                    (
+                        axum::http::header::CONTENT_TYPE,
+                        "text/markdown; charset=utf-8".to_string(),
+                    ),
+                    (axum::http::header::CONTENT_DISPOSITION, file_name),
+                ],
+                history::transcript(&entries),
+            )
+                .into_response()
+        }
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/capabilities",
+    method(post),
+    request_body = ClientCapabilities,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The capabilities the chat endpoints adapt to for the rest of the session, missing ones are off", body = ClientCapabilities),
+        (status = 401, description = "Unauthorized")
+    )
+)]
+pub async fn set_capabilities(
+    _: StudentAuth,
+    session: Session,
+    Json(capabilities): Json<ClientCapabilities>,
+) -> impl IntoResponse {
+    Json(client_capabilities(&session, Some(capabilities)).await).into_response()
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/models",
+    method(get),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Models a conversation can switch to, the default one first", body = Vec<String>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_models(State(library): State<Arc<Library>>, _: StudentAuth) -> impl IntoResponse {
+    match ai_utils::allowed_models(&library.database).await {
+        Ok(models) => Json(models).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/memory",
+    method(get),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "What the teacher remembers about the student in every book, most recent first", body = Vec<StudentMemory>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn get_memory(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+) -> impl IntoResponse {
+    match student_memory::list(&library.database, student_id).await {
+        Ok(memories) => Json(memories).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/clear_memory",
+    method(post),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The teacher forgot everything it remembered about the student"),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn clear_memory(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+) -> impl IntoResponse {
+    match student_memory::clear(&library.database, student_id).await {
+        Ok(_) => ().into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RecognizeHandwritingRequest {
+    pub book_id: i64,
+    pub handwriting: Handwriting,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/recognize_handwriting",
+    method(post),
+    request_body = RecognizeHandwritingRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The handwriting as text and LaTeX, for the student to check before sending it", body = Recognition),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "The book is not in the student's library"),
+        (status = 400, description = "Bad request, e.g. an image that can't be decoded")
+    )
+)]
+pub async fn recognize_handwriting(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Json(req): Json<RecognizeHandwritingRequest>,
+) -> impl IntoResponse {
+    if !matches!(
+        student::is_enrolled(&library.database, student_id, req.book_id).await,
+        Ok(true)
+    ) {
+        return (axum::http::StatusCode::FORBIDDEN, ()).into_response();
+    }
+    let result = async {
+        let provider =
+            ai_utils::resolve_recognition_provider(&library.database, student_id, req.book_id)
+                .await?;
+        handwriting::recognize(
+            provider.as_ref(),
+            library.upload_scanner.as_deref(),
+            req.handwriting,
+        )
+        .await
+    };
+    match result.await {
+        Ok(recognition) => Json(recognition).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ChatRequest {
+    book_id: i64,
+    /// may be empty when handwriting is sent
+    #[serde(default)]
+    message: String,
+    /// handwriting of a tablet client, recognized and added after the message
+    handwriting: Option<Handwriting>,
+    /// Model for this and the following messages, empty for the default one
+    model: Option<String>,
+    /// What the client renders, replaces the capabilities of the session if given
+    capabilities: Option<ClientCapabilities>,
+}
+
+/// Switches the conversation to the model asked for, if any
+async fn select_model(teacher: &TeacherSession, model: Option<String>) -> anyhow::Result<()> {
+    let Some(model) = model else {
+        return Ok(());
+    };
+    let model = (!model.is_empty()).then_some(model);
+    teacher.lock().await.set_model(model).await
+}
+
+/// What the throttle compares for repeats, handwriting included so new work with the same text
+/// isn't a duplicate
+fn throttle_key(message: &str, handwriting: &Option<Handwriting>) -> String {
+    match handwriting {
+        Some(handwriting) => format!("{message}\n{handwriting:?}"),
+        None => message.to_string(),
+    }
+}
+
+/// Refuse the message when the student reached a daily quota or is sending too fast. Quotas go
+/// first so a refused message doesn't count in the throttle window, and a quota that can't be
+/// read lets the message through to [`TeacherAgent::input`], which checks it again
+async fn admit(
+    database: &sqlx::SqlitePool,
+    throttle: &ChatThrottle,
+    student_id: i64,
+    key: &str,
+) -> Result<(), ThrottleEvent> {
+    if let Ok(Some(event)) = quota::check(database, student_id).await {
+        return Err(event);
+    }
+    throttle.check(student_id, key)
+}
+
+/// Session key of the [`ClientCapabilities`] the client declared
+const CAPABILITIES_KEY: &str = "client_capabilities";
+
+/// The capabilities of the session, `declared` replaces them for this and the later requests
+async fn client_capabilities(
+    session: &Session,
+    declared: Option<ClientCapabilities>,
+) -> ClientCapabilities {
+    match declared {
+        Some(capabilities) => {
+            let _ = session.insert(CAPABILITIES_KEY, capabilities).await;
+            capabilities
+        }
+        None => session
+            .get::<ClientCapabilities>(CAPABILITIES_KEY)
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or_default(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/chat",
+    method(post),
+    request_body = ChatRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Chat response stream", content_type = "text/event-stream"),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request"),
+        (status = 429, description = "Too many or repeated messages, or a daily quota reached", body = ThrottleEvent),
+        (status = 503, description = "The server is shutting down, send the message again in a moment")
+    )
+)]
+pub async fn chat(
+    State(library): State<Arc<Library>>,
+    Extension(sessions): Extension<Arc<SessionManager>>,
+    Extension(throttle): Extension<Arc<ChatThrottle>>,
+    Extension(followers): Extension<Arc<Followers>>,
+    StudentAuth(student_id): StudentAuth,
+    session: Session,
+    Json(req): Json<ChatRequest>,
+) -> impl IntoResponse {
+    let ChatRequest {
+        book_id,
+        message,
+        handwriting,
+        model,
+        capabilities,
+    } = req;
+    if followers.is_closing() {
+        return (axum::http::StatusCode::SERVICE_UNAVAILABLE, RESTARTING).into_response();
+    }
+    if let Err(event) = admit(
+        &library.database,
+        &throttle,
+        student_id,
+        &throttle_key(&message, &handwriting),
+    )
+    .await
+    {
+        let locale = student::get_student_locale(&library.database, student_id)
+            .await
+            .unwrap_or_default();
+        let event = event.localize(&locale);
+        return (
+            axum::http::StatusCode::TOO_MANY_REQUESTS,
+            [(
+                axum::http::header::RETRY_AFTER,
+                event.retry_after.to_string(),
+            )],
+            Json(event),
+        )
+            .into_response();
+    }
+    let teacher = match sessions.get(library, student_id, book_id).await {
+        Ok(teacher) => teacher,
+        Err(e) => {
+            return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    };
+    if let Err(e) = select_model(&teacher, model).await {
+        return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    let message = match handwriting::read_message(
+        &library.database,
+        student_id,
+        book_id,
+        message,
+        handwriting,
+        library.upload_scanner.as_deref(),
+    )
+    .await
+    {
+        Ok(message) => message,
+        Err(e) => {
+            return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    };
+    let capabilities = client_capabilities(&session, capabilities).await;
+    let (tx, rx) = channel::<Result<Event, Infallible>>(100);
+    tokio::spawn(async move {
+        let mut teacher = teacher.lock().await;
+        teacher.set_capabilities(capabilities);
+        let _ = followers
+            .input(student_id, book_id, &mut teacher, message, tx)
+            .await;
+    });
+
+    let stream = ReceiverStream::new(rx);
+    let sse = Sse::new(stream).keep_alive(sse::KeepAlive::new().interval(Duration::from_secs(10)));
+
+    sse.into_response()
+}
+
+/// A frame of the chat WebSocket, e.g. `{"type": "content", "data": "Ownership is"}`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum ChatFrame {
+    Content(String),
+    Refusal(String),
+    #[schema(value_type = Object)]
+    ToolCall(ChatCompletionMessageToolCall),
+    #[schema(value_type = Object)]
+    ToolResult(ChatCompletionRequestToolMessage),
+    /// a typed content block, only to clients with the blocks capability
+    Block(ResponseBlock),
+    /// move the reader to the location, only to clients with the navigation capability
+    Navigate(BookLocation),
+    /// replies the student may send next, after the last turn when the deployment suggests them
+    Suggestions(Vec<String>),
+    /// the scratchpad after the teacher changed it
+    Scratchpad(Scratchpad),
+    /// a message of the student, in replays and to the clients following the conversation
+    Student(String),
+    /// the id to resume the response with after a disconnect, first frame of a WebSocket response
+    Stream(String),
+    /// the message was not answered, the socket closes after it
+    Throttled(ThrottleEvent),
+    /// the response failed, the socket closes after it
+    Error(String),
+    /// the response is complete, the socket closes after it
+    Done,
+}
+
+impl From<ResponseEvent> for ChatFrame {
+    fn from(event: ResponseEvent) -> Self {
+        match event {
+            ResponseEvent::Content(content) => ChatFrame::Content(content),
+            ResponseEvent::Refusal(refusal) => ChatFrame::Refusal(refusal),
+            ResponseEvent::ToolCall(tool_call) => ChatFrame::ToolCall(tool_call),
+            ResponseEvent::ToolResult(tool_result) => ChatFrame::ToolResult(tool_result),
+            ResponseEvent::Block(block) => ChatFrame::Block(block),
+            ResponseEvent::Navigate(location) => ChatFrame::Navigate(location),
+            ResponseEvent::Suggestions(replies) => ChatFrame::Suggestions(replies),
+            ResponseEvent::Scratchpad(scratchpad) => ChatFrame::Scratchpad(scratchpad),
+        }
+    }
+}
+
+impl From<&ChatFrame> for Message {
+    fn from(frame: &ChatFrame) -> Self {
+        Message::Text(serde_json::to_string(frame).unwrap().into())
+    }
+}
+
+impl From<&SequencedFrame> for Message {
+    fn from(frame: &SequencedFrame) -> Self {
+        Message::Text(serde_json::to_string(frame).unwrap().into())
+    }
+}
+
+/// The first client frame of a chat WebSocket sending a message, acks may follow
+#[derive(Deserialize, ToSchema)]
+pub struct ChatSocketMessage {
+    /// may be empty when handwriting is sent
+    #[serde(default)]
+    message: String,
+    /// handwriting of a tablet client, recognized and added after the message
+    handwriting: Option<Handwriting>,
+    /// Model for this and the following messages, empty for the default one
+    model: Option<String>,
+    /// What the client renders, replaces the capabilities of the session if given
+    capabilities: Option<ClientCapabilities>,
+}
+
+/// The first client frame of a chat WebSocket resuming a response after a disconnect
+#[derive(Deserialize, ToSchema)]
+pub struct ChatSocketResume {
+    /// the data of the stream frame of the response
+    resume: String,
+    /// seq of the last frame received, the frames after it are sent again
+    ack: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ChatSocketRequest {
+    Resume(ChatSocketResume),
+    Send(ChatSocketMessage),
+}
+
+/// send the frames of the outbox after `ack` until the response ends, forgetting the frames the
+/// client acks and calling `stop` when it asks to stop the response; when the client goes away
+/// the rest stays buffered for a resume
+async fn deliver(
+    outbox: &Outbox,
+    ack: u64,
+    sender: &mut SplitSink<WebSocket, Message>,
+    receiver: &mut SplitStream<WebSocket>,
+    stop: impl Fn(),
+) {
+    let _attachment = outbox.attach();
+    outbox.ack(ack);
+    let mut written = outbox.subscribe();
+    let mut sent = ack;
+    let mut keepalive = tokio::time::interval(Duration::from_secs(10));
+    loop {
+        let frames = match outbox.frames_after(sent) {
+            Ok(frames) => frames,
+            Err(e) => {
+                let _ = sender.send((&ChatFrame::Error(e.to_string())).into()).await;
+                let _ = sender.send(close(close_code::INVALID)).await;
+                return;
+            }
+        };
+        for frame in frames {
+            if sender.send((&frame).into()).await.is_err() {
+                return;
+            }
+            sent = frame.seq;
+        }
+        if let Some(done) = outbox.finished(sent) {
+            let code = if done {
+                close_code::NORMAL
+            } else {
+                close_code::ERROR
+            };
+            let _ = sender.send(close(code)).await;
+            return;
+        }
+        tokio::select! {
+            changed = written.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+            _ = keepalive.tick() => {
+                if sender.send(Message::Ping(Default::default())).await.is_err() {
+                    return;
+                }
+            }
+            incoming = receiver.next() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    if let Ok(ChatSocketStop { stop: true }) = serde_json::from_str(&text) {
+                        stop();
+                    } else if let Ok(ack) = serde_json::from_str::<ChatSocketAck>(&text) {
+                        outbox.ack(ack.ack);
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/chat_ws",
+    method(get),
+    params(
+        ("book_id" = i64, Query, description = "ID of the book")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 101, description = "WebSocket: send one ChatSocketMessage, receive a stream frame with seq 0, then SequencedFrame text frames until done, then a normal close. \
+            Send a ChatSocketAck now and then, the server buffers the unacked frames, and a ChatSocketStop to stop the response. After a disconnect, open a new socket within 30 seconds \
+            and send a ChatSocketResume with the stream id and the last seq received to get the rest of the response. \
+            Frames rejecting the first client frame, like throttled, come without seq."),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn chat_ws(
+    State(library): State<Arc<Library>>,
+    Extension(sessions): Extension<Arc<SessionManager>>,
+    Extension(throttle): Extension<Arc<ChatThrottle>>,
+    Extension(followers): Extension<Arc<Followers>>,
+    Extension(deliveries): Extension<Arc<Deliveries>>,
+    StudentAuth(student_id): StudentAuth,
+    session: Session,
+    Query(book_id): Query<i64>,
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let database = library.database.clone();
+    let scanner = library.upload_scanner.clone();
+    let teacher = match sessions.get(library, student_id, book_id).await {
+        Ok(teacher) => teacher,
+        Err(e) => {
+            return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    };
+    ws.on_upgrade(move |socket| async move {
+        let (mut sender, mut receiver) = socket.split();
+        let (message, handwriting, model, capabilities) = loop {
+            match receiver.next().await {
+                Some(Ok(Message::Text(text))) => {
+                    match serde_json::from_str::<ChatSocketRequest>(&text) {
+                        Ok(ChatSocketRequest::Send(req)) => {
+                            break (req.message, req.handwriting, req.model, req.capabilities);
+                        }
+                        Ok(ChatSocketRequest::Resume(req)) => {
+                            let Some(outbox) = deliveries.resume(student_id, &req.resume) else {
+                                let error = "Unknown or expired stream, replay the response with /chat/stream";
+                                let _ = sender.send((&ChatFrame::Error(error.into())).into()).await;
+                                let _ = sender.send(close(close_code::INVALID)).await;
+                                return;
+                            };
+                            let stop = || {
+                                followers.stop(student_id, book_id);
+                            };
+                            deliver(&outbox, req.ack, &mut sender, &mut receiver, stop).await;
+                            return;
+                        }
+                        Err(e) => {
+                            let _ = sender.send((&ChatFrame::Error(e.to_string())).into()).await;
+                            let _ = sender.send(close(close_code::INVALID)).await;
+                            return;
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => continue,
+            }
+        };
+        if followers.is_closing() {
+            let _ = sender.send((&ChatFrame::Error(RESTARTING.into())).into()).await;
+            let _ = sender.send(close(close_code::AGAIN)).await;
+            return;
+        }
+        if let Err(event) = admit(
+            &database,
+            &throttle,
+            student_id,
+            &throttle_key(&message, &handwriting),
+        )
+        .await
+        {
+            let locale = student::get_student_locale(&database, student_id)
+                .await
+                .unwrap_or_default();
+            let frame = ChatFrame::Throttled(event.localize(&locale));
+            let _ = sender.send((&frame).into()).await;
+            let _ = sender.send(close(close_code::AGAIN)).await;
+            return;
+        }
+        if let Err(e) = select_model(&teacher, model).await {
+            let _ = sender.send((&ChatFrame::Error(e.to_string())).into()).await;
+            let _ = sender.send(close(close_code::INVALID)).await;
+            return;
+        }
+        let message =
+            match handwriting::read_message(
+                &database,
+                student_id,
+                book_id,
+                message,
+                handwriting,
+                scanner.as_deref(),
+            )
+            .await
+            {
+                Ok(message) => message,
+                Err(e) => {
+                    let _ = sender.send((&ChatFrame::Error(e.to_string())).into()).await;
+                    let _ = sender.send(close(close_code::INVALID)).await;
+                    return;
+                }
+            };
+        let capabilities = client_capabilities(&session, capabilities).await;
+        let (stream_id, outbox) = deliveries.open(student_id);
+        let (tx, mut rx) = channel::<ChatFrame>(100);
+        let stopper = followers.clone();
+        let response = tokio::spawn(async move {
+            let mut teacher = teacher.lock().await;
+            teacher.set_capabilities(capabilities);
+            followers
+                .input(student_id, book_id, &mut teacher, message, tx)
+                .await
+        });
+        // buffers the response apart from the socket, so it survives a disconnect
+        let producer = outbox.clone();
+        let id = stream_id.clone();
+        tokio::spawn(async move {
+            while let Some(frame) = rx.recv().await {
+                if !producer.push(frame) {
+                    // nobody came back, dropping `rx` stops the response
+                    break;
+                }
+            }
+            drop(rx);
+            let frame = match response.await {
+                Ok(Ok(())) => ChatFrame::Done,
+                Ok(Err(e)) => ChatFrame::Error(e.to_string()),
+                Err(e) => ChatFrame::Error(e.to_string()),
+            };
+            producer.close(frame);
+            tokio::time::sleep(RESUME_WINDOW).await;
+            deliveries.remove(&id);
+        });
+        let stream = SequencedFrame {
+            seq: 0,
+            frame: ChatFrame::Stream(stream_id),
+        };
+        if sender.send((&stream).into()).await.is_err() {
+            return;
+        }
+        let stop = || {
+            stopper.stop(student_id, book_id);
+        };
+        deliver(&outbox, 0, &mut sender, &mut receiver, stop).await;
+    })
+}
+
+impl ChatFrame {
+    /// the frames a stored conversation message was streamed as
+    fn replay(
+        message: ChatCompletionRequestMessage,
+        capabilities: &ClientCapabilities,
+    ) -> Vec<ChatFrame> {
+        let mut frames = Vec::new();
+        match message {
+            ChatCompletionRequestMessage::Assistant(msg) => {
+                if let Ok(ConversationMessage::Assistant { content, .. }) =
+                    ConversationMessage::try_from(ChatCompletionRequestMessage::Assistant(
+                        msg.clone(),
+                    ))
+                {
+                    if !content.is_empty() {
+                        frames.push(ChatFrame::Content(content));
+                    }
+                }
+                if let Some(refusal) = msg.refusal {
+                    frames.push(ChatFrame::Refusal(refusal));
+                }
+                let tool_calls = msg.tool_calls.unwrap_or_default();
+                for tool_call in &tool_calls {
+                    frames.push(ChatFrame::ToolCall(tool_call.clone()));
+                }
+                for tool_call in &tool_calls {
+                    if let Some(block) = ResponseBlock::from_tool_call(tool_call) {
+                        frames.push(capabilities.present(block).into());
+                    }
+                    if let Some(location) = navigation(tool_call) {
+                        if capabilities.navigation {
+                            frames.push(ChatFrame::Navigate(location));
+                        }
+                    }
+                }
+            }
+            ChatCompletionRequestMessage::Tool(msg) => frames.push(ChatFrame::ToolResult(msg)),
+            _ => {}
+        }
+        frames
+    }
+
+    /// the frame as a typed SSE event, the type as the event name and the data as JSON
+    fn into_event(self, id: usize) -> Result<Event, Infallible> {
+        self.into_unnumbered_event()
+            .map(|event| event.id(id.to_string()))
+    }
+
+    /// [`into_event`](Self::into_event) without the conversation position
+    fn into_unnumbered_event(self) -> Result<Event, Infallible> {
+        let frame = serde_json::to_value(&self).unwrap();
+        let name = frame["type"].as_str().unwrap_or_default();
+        Ok(Event::default()
+            .event(name)
+            .json_data(&frame["data"])
+            .unwrap())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ChatStreamQuery {
+    message: Option<String>,
+    model: Option<String>,
+    /// on a reconnect, skip what this device already reported as seen
+    device_id: Option<String>,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/students/{id}/books/{book_id}/chat/stream",
+    method(get),
+    params(
+        ("id" = i64, Path, description = "ID of the logged in student"),
+        ("book_id" = i64, Path, description = "ID of the book"),
+        ("message" = Option<String>, Query, description = "The message to send, required without Last-Event-ID"),
+        ("model" = Option<String>, Query, description = "Model for this and the following messages, one of /models, empty for the default one"),
+        ("device_id" = Option<String>, Query, description = "On a reconnect, the replay starts after what this device reported seen with /conversations/{book_id}/seen if that is later"),
+        ("Last-Event-ID" = Option<usize>, Header, description = "id of the last event received, replays the response from the messages store instead of sending the message")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Events named content, refusal, tool_call, tool_result, block, navigate, suggestions, error and done, with the ChatFrame data as JSON. \
+            The id is the conversation position the response starts at, done carries the position after it. \
+            Close the EventSource on done, a reconnect only replays.", content_type = "text/event-stream"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not the logged in student"),
+        (status = 400, description = "Bad request"),
+        (status = 429, description = "Too many or repeated messages, or a daily quota reached", body = ThrottleEvent),
+        (status = 503, description = "The server is shutting down, send the message again in a moment")
+    )
+)]
+pub async fn chat_stream(
+    State(library): State<Arc<Library>>,
+    Extension(sessions): Extension<Arc<SessionManager>>,
+    Extension(throttle): Extension<Arc<ChatThrottle>>,
+    Extension(followers): Extension<Arc<Followers>>,
+    StudentAuth(student_id): StudentAuth,
+    session: Session,
+    Path((id, book_id)): Path<(i64, i64)>,
+    Query(query): Query<ChatStreamQuery>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    if id != student_id {
+        return (axum::http::StatusCode::FORBIDDEN, ()).into_response();
+    }
+    let last_event_id = match headers.get("last-event-id").map(|id| id.to_str()) {
+        Some(Ok(id)) => match id.trim().parse::<usize>() {
+            Ok(id) => Some(id),
+            Err(e) => {
+                return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
+            }
+        },
+        Some(Err(e)) => {
+            return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+        None => None,
+    };
+    let message = match (last_event_id, query.message) {
+        (Some(_), _) => None,
+        (None, Some(message)) => Some(message),
+        (None, None) => {
+            return (
+                axum::http::StatusCode::BAD_REQUEST,
+                "message or Last-Event-ID is required",
+            )
+                .into_response();
+        }
+    };
+    if message.is_some() && followers.is_closing() {
+        return (axum::http::StatusCode::SERVICE_UNAVAILABLE, RESTARTING).into_response();
+    }
+    if let Some(message) = &message {
+        if let Err(event) = admit(&library.database, &throttle, student_id, message).await {
+            let locale = student::get_student_locale(&library.database, student_id)
+                .await
+                .unwrap_or_default();
+            let event = event.localize(&locale);
+            return (
+                axum::http::StatusCode::TOO_MANY_REQUESTS,
+                [(
+                    axum::http::header::RETRY_AFTER,
+                    event.retry_after.to_string(),
+                )],
+                Json(event),
+            )
+                .into_response();
+        }
+    }
+    let database = library.database.clone();
+    let teacher = match sessions.get(library, student_id, book_id).await {
+        Ok(teacher) => teacher,
+        Err(e) => {
+            return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    };
+    let capabilities = client_capabilities(&session, None).await;
+    let keep_alive = sse::KeepAlive::new().interval(Duration::from_secs(10));
+    let Some(message) = message else {
+        // waits for a response still in flight, then replays it whole
+        let conversation = teacher.lock().await.get_conversation().await;
+        let end = conversation.len();
+        let seen = match &query.device_id {
+            Some(device_id) => receipts::last_seen(&database, student_id, book_id, device_id)
+                .await
+                .ok()
+                .flatten()
+                .unwrap_or_default()
+                .max(0) as usize,
+            None => 0,
+        };
+        let start = last_event_id.unwrap_or_default().max(seen).min(end);
+        let mut events: Vec<_> = conversation
+            .into_iter()
+            .skip(start)
+            .flat_map(|message| ChatFrame::replay(message, &capabilities))
+            .map(|frame| frame.into_event(start))
+            .collect();
+        events.push(ChatFrame::Done.into_event(end));
+        return Sse::new(futures::stream::iter(events))
+            .keep_alive(keep_alive)
+            .into_response();
+    };
+    if let Err(e) = select_model(&teacher, query.model).await {
+        return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    let (tx, rx) = channel::<Result<Event, Infallible>>(100);
+    tokio::spawn(async move {
+        let mut teacher = teacher.lock().await;
+        teacher.set_capabilities(capabilities);
+        let start = teacher.get_conversation().await.len();
+        let (frame_tx, mut frame_rx) = channel::<ChatFrame>(100);
+        let forward = async {
+            while let Some(frame) = frame_rx.recv().await {
+                if tx.send(frame.into_event(start)).await.is_err() {
+                    break;
+                }
+            }
+        };
+        let (result, ()) = tokio::join!(
+            followers.input(student_id, book_id, &mut teacher, message, frame_tx),
+            forward
+        );
+        let last = match result {
+            Ok(()) => ChatFrame::Done.into_event(teacher.get_conversation().await.len()),
+            Err(e) => ChatFrame::Error(e.to_string()).into_event(start),
+        };
+        let _ = tx.send(last).await;
+    });
+    Sse::new(ReceiverStream::new(rx))
+        .keep_alive(keep_alive)
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct ReplayQuery {
+    speed: Option<f64>,
+}
+
+impl ReplayQuery {
+    /// times the original pace, 1 by default
+    pub fn speed(&self) -> f64 {
+        self.speed.unwrap_or(1.0).clamp(0.25, 10.0)
+    }
+}
+
+/// the messages as typed SSE events at their recorded pace, the event ids are the conversation
+/// positions, student messages come as `student` events
+pub(crate) fn replay_stream(
+    messages: Vec<ReplayMessage>,
+    capabilities: ClientCapabilities,
+) -> Sse<ReceiverStream<Result<Event, Infallible>>> {
+    let (tx, rx) = channel::<Result<Event, Infallible>>(100);
+    tokio::spawn(async move {
+        let end = messages.len();
+        for (position, replay) in messages.into_iter().enumerate() {
+            tokio::time::sleep(replay.delay).await;
+            let frames = match replay.message {
+                ChatCompletionRequestMessage::User(msg) => {
+                    let (_, text, _) = read_message(ChatCompletionRequestMessage::User(msg));
+                    if tx
+                        .send(ChatFrame::Student(text).into_event(position))
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                    continue;
+                }
+                ChatCompletionRequestMessage::Assistant(mut msg) if !replay.pieces.is_empty() => {
+                    for (wait, piece) in replay.pieces {
+                        tokio::time::sleep(wait).await;
+                        if tx
+                            .send(ChatFrame::Content(piece).into_event(position))
+                            .await
+                            .is_err()
+                        {
+                            return;
+                        }
+                    }
+                    // sent in its original pieces above
+                    msg.content = None;
+                    ChatFrame::replay(ChatCompletionRequestMessage::Assistant(msg), &capabilities)
+                }
+                message => ChatFrame::replay(message, &capabilities),
+            };
+            for frame in frames {
+                if tx.send(frame.into_event(position)).await.is_err() {
+                    return;
+                }
+            }
+        }
+        let _ = tx.send(ChatFrame::Done.into_event(end)).await;
+    });
+    Sse::new(ReceiverStream::new(rx))
+        .keep_alive(sse::KeepAlive::new().interval(Duration::from_secs(10)))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SeenRequest {
+    /// chosen by the client, the same on every request of the device
+    pub device_id: String,
+    /// the conversation position after the last message displayed, e.g. the id of the done event
+    pub position: i64,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/conversations/{book_id}/seen",
+    method(post),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book of the conversation")
+    ),
+    request_body = SeenRequest,
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The receipt of the device, a lower position than before is ignored", body = DeviceReceipt),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn mark_seen(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Path(book_id): Path<i64>,
+    Json(req): Json<SeenRequest>,
+) -> impl IntoResponse {
+    match receipts::mark_seen(
+        &library.database,
+        student_id,
+        book_id,
+        &req.device_id,
+        req.position,
+    )
+    .await
+    {
+        Ok(receipt) => Json(receipt).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/conversations/{book_id}/devices",
+    method(get),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book of the conversation")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "How far each device displayed the conversation, most recently seen first", body = Vec<DeviceReceipt>),
+        (status = 401, description = "Unauthorized"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn list_devices(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    Path(book_id): Path<i64>,
+) -> impl IntoResponse {
+    match receipts::list(&library.database, student_id, book_id).await {
+        Ok(receipts) => Json(receipts).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SinceQuery {
+    /// start after what this device displayed
+    device_id: Option<String>,
+    /// start at this position, overrides the device
+    position: Option<usize>,
+}
+
+/// A message of the conversation with its position
+#[derive(Serialize, ToSchema)]
+pub struct PositionedMessage {
+    pub position: usize,
+    pub message: ConversationMessage,
+}
+
+/// The conversation from a position on
+#[derive(Serialize, ToSchema)]
+pub struct ConversationSince {
+    /// the position the messages start at
+    pub start: usize,
+    /// the position after the last message, to report as seen once displayed
+    pub end: usize,
+    pub messages: Vec<PositionedMessage>,
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/conversations/{book_id}/since",
+    method(get),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book of the conversation"),
+        ("device_id" = Option<String>, Query, description = "Start after what this device reported as seen"),
+        ("position" = Option<usize>, Query, description = "Start at this position instead")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The messages a reconnecting client has not displayed yet", body = ConversationSince),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn conversation_since(
+    State(library): State<Arc<Library>>,
+    Extension(sessions): Extension<Arc<SessionManager>>,
+    StudentAuth(student_id): StudentAuth,
+    Path(book_id): Path<i64>,
+    Query(query): Query<SinceQuery>,
+) -> impl IntoResponse {
+    let start = match (query.position, &query.device_id) {
+        (Some(position), _) => position,
+        (None, Some(device_id)) => {
+            match receipts::last_seen(&library.database, student_id, book_id, device_id).await {
+                Ok(position) => position.unwrap_or_default().max(0) as usize,
+                Err(e) => {
+                    return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
+                }
+            }
+        }
+        (None, None) => 0,
+    };
+    let teacher = match sessions.get(library, student_id, book_id).await {
+        Ok(teacher) => teacher,
+        Err(e) => {
+            return (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response();
+        }
+    };
+    let teacher = teacher.lock().await;
+    let conversation = teacher.get_conversation().await;
+    let end = conversation.len();
+    // positions shrink when old messages are archived
+    let start = start.min(end);
+    let messages = conversation
+        .into_iter()
+        .zip(teacher.get_message_models())
+        .enumerate()
+        .skip(start)
+        .filter_map(|(position, (m, model))| {
+            let mut message = ConversationMessage::try_from(m).ok()?;
+            if let ConversationMessage::Assistant { model: m, .. } = &mut message {
+                *m = model;
+            }
+            Some(PositionedMessage { position, message })
+        })
+        .collect();
+    Json(ConversationSince {
+        start,
+        end,
+        messages,
+    })
+    .into_response()
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/conversations/{book_id}/stop",
+    method(post),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book of the conversation")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The response being generated stops, from whichever client of the student sent the message. \
+            The part already streamed is kept in the conversation and the response ends with done."),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "No response is being generated")
+    )
+)]
+pub async fn stop_response(
+    Extension(followers): Extension<Arc<Followers>>,
+    StudentAuth(student_id): StudentAuth,
+    Path(book_id): Path<i64>,
+) -> impl IntoResponse {
+    if followers.stop(student_id, book_id) {
+        ().into_response()
+    } else {
+        (axum::http::StatusCode::NOT_FOUND, ()).into_response()
+    }
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/conversations/{book_id}/follow",
+    method(get),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book of the conversation")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "Every turn of the conversation sent from now on by any client of the student, e.g. the laptop while the phone sends. \
+            Events named like /chat/stream, without ids, each turn starts with a student event and ends with done or error; \
+            blocks are presented for the capabilities of the sending client. Stays open until the client closes it.", content_type = "text/event-stream"),
+        (status = 401, description = "Unauthorized")
+    )
+)]
+pub async fn follow_conversation(
+    Extension(followers): Extension<Arc<Followers>>,
+    StudentAuth(student_id): StudentAuth,
+    Path(book_id): Path<i64>,
+) -> impl IntoResponse {
+    let mut frames = followers.subscribe(student_id, book_id);
+    let (tx, rx) = channel::<Result<Event, Infallible>>(100);
+    tokio::spawn(async move {
+        loop {
+            let frame = match frames.recv().await {
+                Ok(frame) => frame,
+                // a slow client misses the frames it fell behind on
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            };
+            if tx.send(frame.into_unnumbered_event()).await.is_err() {
+                return;
+            }
+        }
+    });
+    Sse::new(ReceiverStream::new(rx))
+        .keep_alive(sse::KeepAlive::new().interval(Duration::from_secs(10)))
+}
+
+#[utoipa::path(
+    context_path = "/api/user",
+    path = "/conversations/{book_id}/replay",
+    method(get),
+    params(
+        ("book_id" = i64, Path, description = "ID of the book of the conversation"),
+        ("speed" = Option<f64>, Query, description = "Times the original pace, 1 by default, from 0.25 to 10")
+    ),
+    security(("session" = [])),
+    responses(
+        (status = 200, description = "The stored conversation replayed with its original pacing, pauses between messages shortened to a few seconds. \
+            Events named student for the student messages and like /chat/stream for the teacher, the id is the conversation position; done ends the replay.", content_type = "text/event-stream"),
+        (status = 401, description = "Unauthorized"),
+        (status = 400, description = "Bad request")
+    )
+)]
+pub async fn conversation_replay(
+    State(library): State<Arc<Library>>,
+    StudentAuth(student_id): StudentAuth,
+    session: Session,
+    Path(book_id): Path<i64>,
+    Query(query): Query<ReplayQuery>,
+) -> impl IntoResponse {
+    let capabilities = client_capabilities(&session, None).await;
+    match replay::load(
+        library.message_store.as_ref(),
+        student_id,
+        book_id,
+        query.speed(),
+    )
+    .await
+    {
+        Ok(messages) => replay_stream(messages, capabilities).into_response(),
+        Err(e) => (axum::http::StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+fn close(code: u16) -> Message {
+    Message::Close(Some(CloseFrame {
+        code,
+        reason: Default::default(),
+    }))
+}
+
+pub fn get_user_scope(
+    sessions: Arc<SessionManager>,
+    followers: Arc<Followers>,
+    throttle: Arc<ChatThrottle>,
+    limits: BodyLimits,
+) -> Router<Arc<Library>> {
+    let deliveries = Arc::new(Deliveries::default());
+    Router::new().nest(
+        "/user",
+        Router::new()
+            .route("/create_user", post(create_user))
+            .route("/login", post(login))
+            .route("/user_info", get(user_info))
+            .route("/logout", post(logout))
+            .route(
+                "/set_locale",
+                post(set_locale).layer(Extension(sessions.clone())),
+            )
+            .route("/list_books", get(list_books))
+            .route("/delete_book", post(delete_book))
+            .route("/add_book", post(add_book))
+            .route(
+                "/upload_and_add_books",
+                post(upload_and_add_books).layer(DefaultBodyLimit::max(limits.upload)),
+            )
+            .route("/find_chapter", get(find_chapter))
+            .route("/search_book", get(search_book))
+            .route("/hybrid_search", get(hybrid_search))
+            .route("/book_toc", get(book_toc))
+            .route("/book_stats", get(book_stats))
+            .route("/study_estimate", get(study_estimate))
+            .route("/course", get(get_course))
+            .route("/plan_course", post(plan_course))
+            .route("/set_course", post(set_course))
+            .route("/clear_course", post(clear_course))
+            .route("/session_books", get(get_session_books))
+            .route("/set_session_books", post(set_session_books))
+            .route("/agent_profile", get(get_agent_profile))
+            .route("/set_agent_profile", post(set_agent_profile))
+            .route("/clear_agent_profile", post(clear_agent_profile))
+            .route("/reviews", get(reviews))
+            .route("/scratchpad", get(get_scratchpad))
+            .route("/save_scratchpad", post(save_scratchpad))
+            .route("/workspace", get(get_workspace))
+            .route("/link_workspace", post(link_workspace))
+            .route("/unlink_workspace", post(unlink_workspace))
+            .route("/recognize_handwriting", post(recognize_handwriting))
+            .route("/projects", get(projects))
+            .route("/start_project", post(start_project))
+            .route("/project", get(get_project))
+            .route("/submit_artifact", post(submit_artifact))
+            .route("/project_report", post(project_report))
+            .route("/chapter", get(get_chapter))
+            .route("/books/{book_id}/assets/{*path}", get(get_book_asset))
+            .route("/accessible_chapter", get(accessible_chapter))
+            .route("/export_chapter", get(export_chapter))
+            .route("/start_focus", post(start_focus))
+            .route("/stop_focus", post(stop_focus))
+            .route("/checkin", post(checkin))
+            .route("/list_checkins", get(list_checkins))
+            .route("/generate_quiz", post(generate_quiz))
+            .route("/submit_quiz", post(submit_quiz))
+            .route("/quiz_scores", get(quiz_scores))
+            .route("/generate_flashcards", post(generate_flashcards))
+            .route("/due_flashcards", get(due_flashcards))
+            .route("/review_flashcard", post(review_flashcard))
+            .route("/conversation_history", get(conversation_history))
+            .route("/conversation_transcript", get(conversation_transcript))
+            .route("/conversations/{book_id}/replay", get(conversation_replay))
+            .route("/conversations/{book_id}/seen", post(mark_seen))
+            .route("/conversations/{book_id}/devices", get(list_devices))
+            .route(
+                "/conversations/{book_id}/since",
+                get(conversation_since).layer(Extension(sessions.clone())),
+            )
+            .route(
+                "/conversations/{book_id}/follow",
+                get(follow_conversation).layer(Extension(followers.clone())),
+            )
+            .route(
+                "/conversations/{book_id}/stop",
+                post(stop_response).layer(Extension(followers.clone())),
+            )
+            .route("/capabilities", post(set_capabilities))
+            .route("/models", get(list_models))
+            .route("/memory", get(get_memory))
+            .route("/clear_memory", post(clear_memory))
+            .route(
+                "/get_conversation",
+                get(get_conversation).layer(Extension(sessions.clone())),
+            )
+            .route(
+                "/chat",
+                post(chat)
+                    .layer(Extension(sessions.clone()))
+                    .layer(Extension(throttle.clone()))
+                    .layer(Extension(followers.clone())),
+            )
+            .route(
+                "/chat_ws",
+                get(chat_ws)
+                    .layer(Extension(sessions.clone()))
+                    .layer(Extension(throttle.clone()))
+                    .layer(Extension(followers.clone()))
+                    .layer(Extension(deliveries)),
+            )
+            .route(
+                "/students/{id}/books/{book_id}/chat/stream",
+                get(chat_stream)
+                    .layer(Extension(sessions))
+                    .layer(Extension(throttle))
+                    .layer(Extension(followers)),
             )
-            .route("/chat", post(chat).layer(Extension(cache))),
+            .layer(DefaultBodyLimit::max(limits.default)),
     )
 }
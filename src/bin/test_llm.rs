@@ -5,10 +5,7 @@ use async_openai::{
         CreateChatCompletionRequestArgs,
     },
 };
-use ai_reader::{
-    ai_utils::{AI_CLIENT, AI_MODEL},
-    utils::init_log,
-};
+use book_server_core::{ai_utils::DEFAULT_PROVIDER, utils::init_log};
 use futures::StreamExt;
 use rand::{Rng, rng, seq::IndexedRandom};
 use schemars::JsonSchema;
@@ -23,7 +20,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let _guard = init_log(None);
     let mut manager = ChatManager::default();
     manager.tools.add_tool(WeatherTool);
-    println!("AI_MODEL: {}", AI_MODEL.as_str());
+    println!("AI_MODEL: {}", DEFAULT_PROVIDER.model());
     loop {
         println!("\n[User]:");
         let stdin = tokio::io::stdin();
@@ -68,12 +65,12 @@ impl ChatManager {
         let mut tool_call_count = 0;
         loop {
             let request = CreateChatCompletionRequestArgs::default()
-                .model(AI_MODEL.as_str())
+                .model(DEFAULT_PROVIDER.model())
                 .messages(self.conversation.clone())
                 .tools(self.tools.get_tools())
                 .build()
                 .unwrap();
-            let mut stream = AI_CLIENT.chat().create_stream(request).await?;
+            let mut stream = DEFAULT_PROVIDER.chat_stream(request).await?;
             let mut response_content = String::new();
             let mut tool_call_stream = ToolCallStreamManager::new();
             while let Some(result) = stream.next().await {
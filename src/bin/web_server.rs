@@ -1,22 +1,34 @@
 use std::sync::Arc;
 use std::{net::SocketAddr, path::PathBuf};
 
-use axum::Router;
-use axum_server::tls_rustls::RustlsConfig;
-use ai_reader::{
-    api::{manager::get_manager_scope, public::get_public_scope, user::get_user_scope},
-    books::library::Library,
+use axum::{
+    Router,
+    http::{HeaderValue, Method, header},
+};
+use axum_server::{Handle, tls_rustls::RustlsConfig};
+use book_server_core::{
+    abuse::{ChatThrottle, ThrottleConfig},
+    analytics::AnalyticsExporter,
+    api::{
+        BodyLimits, auth::SessionSecurity, followers::Followers, manager::get_manager_scope,
+        public::get_public_scope, sessions::SessionManager, user::get_user_scope,
+    },
+    books::library::{Library, LibraryConfig},
+    embeddings::store::VectorStoreConfig,
+    jobs::{JobPolicy, JobQueue},
+    scan::{ClamAvScanner, WebhookScanner},
+    teacher::filters::ResponsePipeline,
     utils::init_log,
 };
-use clap::Parser;
-use moka::future::Cache;
-use sqlx::SqlitePool;
+use clap::{Parser, ValueEnum};
 use time::Duration;
-use tower_http::{cors::CorsLayer, trace::TraceLayer};
-use tower_sessions::{CachingSessionStore, Expiry, SessionManagerLayer};
+use tower_http::{cors::CorsLayer, set_header::SetResponseHeaderLayer, trace::TraceLayer};
+use tower_sessions::{
+    CachingSessionStore, Expiry, SessionManagerLayer, SessionStore, cookie::SameSite,
+};
 use tower_sessions_moka_store::MokaStore;
 use tower_sessions_sqlx_store::SqliteStore;
-use tracing::info;
+use tracing::{info, warn};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
@@ -27,40 +39,285 @@ struct Args {
     database: PathBuf,
     #[arg(short, long, default_value = "bookbase")]
     bookbase: PathBuf,
+    /// keep conversation messages in Redis streams at this url, e.g. redis://127.0.0.1/,
+    /// instead of the main database
+    #[arg(long)]
+    redis_messages: Option<String>,
+    /// keep conversation messages in the Postgres database at this url, e.g.
+    /// postgres://user@127.0.0.1/book_server, instead of the main database
+    #[arg(long, conflicts_with = "redis_messages")]
+    postgres_messages: Option<String>,
+    /// encrypt stored message content and conversation summaries with per-student
+    /// keys wrapped by the base64 master key in the MESSAGE_MASTER_KEY env var
+    #[arg(long)]
+    encrypt_messages: bool,
+    /// where the embedded chunks for semantic search are kept: sqlite (default),
+    /// sqlite-vec=<extension path>, a Qdrant url like http://127.0.0.1:6333 or a pgvector
+    /// postgres:// url
+    #[arg(long, default_value = "sqlite")]
+    vector_store: VectorStoreConfig,
     #[arg(short = 'H', long, default_value = "127.0.0.1")]
     host: String,
     #[arg(short, long, default_value = "8080")]
     port: u16,
     #[arg(short, long, default_value = "database/session.db")]
     session_database: PathBuf,
+    /// PEM certificate chain, serve HTTPS (HTTP/2 and HTTP/1.1) with --tls-key; there is no
+    /// ACME support, renew the certificate with an ACME client and restart
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// origin allowed to call the API with cookies, e.g. https://app.example.com,
+    /// repeat for several origins, any origin without cookies if not set
+    #[arg(long = "cors-origin")]
+    cors_origins: Vec<String>,
+    /// source allowed to embed the reader in a frame, e.g. https://lms.example.com,
+    /// repeat for several sources, only the same origin if not set
+    #[arg(long = "frame-ancestor")]
+    frame_ancestors: Vec<String>,
+    /// SameSite of the session cookie, `none` is needed for cross-site clients and requires TLS
+    #[arg(long, value_enum, default_value_t = CookieSameSite::Strict)]
+    cookie_same_site: CookieSameSite,
+    /// domain of the session cookie, e.g. example.com to share it with subdomains
+    #[arg(long)]
+    cookie_domain: Option<String>,
+    /// request body limit in bytes, except for uploads
+    #[arg(long, default_value_t = BodyLimits::default().default)]
+    body_limit: usize,
+    /// request body limit in bytes of book uploads
+    #[arg(long, default_value_t = BodyLimits::default().upload)]
+    upload_limit: usize,
+    /// scan uploads and handwriting snapshots with clamd at host:port or unix:/path/to/clamd.sock
+    #[arg(long, conflicts_with = "scan_webhook")]
+    clamav: Option<String>,
+    /// scan uploads and handwriting snapshots by streaming them to this url, which answers
+    /// {"clean": bool, "reason": "..."}
+    #[arg(long)]
+    scan_webhook: Option<String>,
+    /// chat messages a student may send per minute
+    #[arg(long, default_value_t = ThrottleConfig::default().max_messages)]
+    chat_rate_limit: usize,
+    /// identical chat messages a student may send per minute
+    #[arg(long, default_value_t = ThrottleConfig::default().max_duplicates)]
+    chat_duplicate_limit: usize,
+    /// minutes a teacher session stays in memory without any request
+    #[arg(long, default_value_t = 30)]
+    session_idle_minutes: i64,
+    /// seconds the responses in flight get to finish on SIGTERM or Ctrl-C, before they are stopped
+    #[arg(long, default_value_t = 30)]
+    shutdown_grace_seconds: u64,
+    /// JSON file of the filters run on every teacher response, see `ResponsePipeline::from_json`
+    #[arg(long)]
+    response_filters: Option<PathBuf>,
+    /// JSON file of the scheduled analytics export, see `AnalyticsExporter::from_json`
+    #[arg(long)]
+    analytics_export: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum CookieSameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl From<CookieSameSite> for SameSite {
+    fn from(value: CookieSameSite) -> Self {
+        match value {
+            CookieSameSite::Strict => SameSite::Strict,
+            CookieSameSite::Lax => SameSite::Lax,
+            CookieSameSite::None => SameSite::None,
+        }
+    }
+}
+
+impl Args {
+    /// the certificate and key to serve with, ./cert.pem and ./key.pem are used if present
+    fn tls_paths(&self) -> Option<(PathBuf, PathBuf)> {
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => Some((cert.clone(), key.clone())),
+            _ => {
+                let (cert, key) = (PathBuf::from("./cert.pem"), PathBuf::from("./key.pem"));
+                (cert.exists() && key.exists()).then_some((cert, key))
+            }
+        }
+    }
+
+    fn cors_layer(&self) -> anyhow::Result<CorsLayer> {
+        if self.cors_origins.is_empty() {
+            return Ok(CorsLayer::permissive());
+        }
+        let origins = self
+            .cors_origins
+            .iter()
+            .map(|origin| HeaderValue::from_str(origin.trim_end_matches('/')))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(CorsLayer::new()
+            .allow_origin(origins)
+            .allow_credentials(true)
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+            .allow_headers([header::CONTENT_TYPE, header::ACCEPT]))
+    }
+
+    /// `Content-Security-Policy: frame-ancestors` for the embeddable reader
+    fn frame_ancestors(&self) -> anyhow::Result<HeaderValue> {
+        let mut policy = "frame-ancestors 'self'".to_string();
+        for source in &self.frame_ancestors {
+            policy.push(' ');
+            policy.push_str(source);
+        }
+        Ok(HeaderValue::from_str(&policy)?)
+    }
 }
 
 #[derive(OpenApi)]
-#[openapi(paths(
-    ai_reader::api::user::create_user,
-    ai_reader::api::user::login,
-    ai_reader::api::user::logout,
-    ai_reader::api::user::user_info,
-    ai_reader::api::user::list_books,
-    ai_reader::api::user::upload_and_add_books,
-    ai_reader::api::user::add_book,
-    ai_reader::api::user::delete_book,
-    ai_reader::api::user::get_conversation,
-    ai_reader::api::user::chat,
-    ai_reader::api::public::get_public_books,
+#[openapi(modifiers(&SessionSecurity), paths(
+    book_server_core::api::user::create_user,
+    book_server_core::api::user::login,
+    book_server_core::api::user::logout,
+    book_server_core::api::user::user_info,
+    book_server_core::api::user::set_locale,
+    book_server_core::api::user::list_books,
+    book_server_core::api::user::upload_and_add_books,
+    book_server_core::api::user::add_book,
+    book_server_core::api::user::delete_book,
+    book_server_core::api::user::find_chapter,
+    book_server_core::api::user::search_book,
+    book_server_core::api::user::hybrid_search,
+    book_server_core::api::user::book_toc,
+    book_server_core::api::user::book_stats,
+    book_server_core::api::user::study_estimate,
+    book_server_core::api::user::get_course,
+    book_server_core::api::user::plan_course,
+    book_server_core::api::user::set_course,
+    book_server_core::api::user::clear_course,
+    book_server_core::api::user::get_session_books,
+    book_server_core::api::user::set_session_books,
+    book_server_core::api::user::get_agent_profile,
+    book_server_core::api::user::set_agent_profile,
+    book_server_core::api::user::clear_agent_profile,
+    book_server_core::api::user::reviews,
+    book_server_core::api::user::get_scratchpad,
+    book_server_core::api::user::save_scratchpad,
+    book_server_core::api::user::get_workspace,
+    book_server_core::api::user::link_workspace,
+    book_server_core::api::user::unlink_workspace,
+    book_server_core::api::user::projects,
+    book_server_core::api::user::start_project,
+    book_server_core::api::user::get_project,
+    book_server_core::api::user::submit_artifact,
+    book_server_core::api::user::project_report,
+    book_server_core::api::user::get_chapter,
+    book_server_core::api::user::get_book_asset,
+    book_server_core::api::user::accessible_chapter,
+    book_server_core::api::user::export_chapter,
+    book_server_core::api::user::start_focus,
+    book_server_core::api::user::stop_focus,
+    book_server_core::api::user::checkin,
+    book_server_core::api::user::list_checkins,
+    book_server_core::api::user::generate_quiz,
+    book_server_core::api::user::submit_quiz,
+    book_server_core::api::user::quiz_scores,
+    book_server_core::api::user::generate_flashcards,
+    book_server_core::api::user::due_flashcards,
+    book_server_core::api::user::review_flashcard,
+    book_server_core::api::user::conversation_history,
+    book_server_core::api::user::conversation_transcript,
+    book_server_core::api::user::conversation_replay,
+    book_server_core::api::user::follow_conversation,
+    book_server_core::api::user::conversation_since,
+    book_server_core::api::user::list_devices,
+    book_server_core::api::user::mark_seen,
+    book_server_core::api::user::set_capabilities,
+    book_server_core::api::user::list_models,
+    book_server_core::api::user::get_memory,
+    book_server_core::api::user::clear_memory,
+    book_server_core::api::user::get_conversation,
+    book_server_core::api::user::chat,
+    book_server_core::api::user::recognize_handwriting,
+    book_server_core::api::user::chat_ws,
+    book_server_core::api::user::stop_response,
+    book_server_core::api::user::chat_stream,
+    book_server_core::api::public::get_public_books,
+    book_server_core::api::public::get_book_cover,
 ))]
 struct UserApiDoc;
 
 #[derive(OpenApi)]
-#[openapi(paths(
-    ai_reader::api::manager::login,
-    ai_reader::api::manager::logout,
-    ai_reader::api::manager::list_books,
-    ai_reader::api::manager::upload_public_book,
-    ai_reader::api::manager::remove_book,
-    ai_reader::api::manager::set_book_public,
-    ai_reader::api::manager::list_students,
-    ai_reader::api::public::get_public_books,
+#[openapi(modifiers(&SessionSecurity), paths(
+    book_server_core::api::manager::login,
+    book_server_core::api::manager::logout,
+    book_server_core::api::manager::list_books,
+    book_server_core::api::manager::upload_public_book,
+    book_server_core::api::manager::upload_book_archive,
+    book_server_core::api::manager::remove_book,
+    book_server_core::api::manager::delete_book,
+    book_server_core::api::manager::reimport_book,
+    book_server_core::api::manager::set_book_public,
+    book_server_core::api::manager::set_book_tags,
+    book_server_core::api::manager::get_book_cover,
+    book_server_core::api::manager::get_book_metadata,
+    book_server_core::api::manager::set_book_metadata,
+    book_server_core::api::manager::clear_book_metadata,
+    book_server_core::api::manager::book_status,
+    book_server_core::api::manager::plan_reviews,
+    book_server_core::api::manager::content_ratings,
+    book_server_core::api::manager::custom_tools,
+    book_server_core::api::manager::list_lesson_scripts,
+    book_server_core::api::manager::add_lesson_script,
+    book_server_core::api::manager::remove_lesson_script,
+    book_server_core::api::manager::list_projects,
+    book_server_core::api::manager::create_project,
+    book_server_core::api::manager::delete_project,
+    book_server_core::api::manager::regenerate_chapter_plan,
+    book_server_core::api::manager::approve_chapter_plan,
+    book_server_core::api::manager::generation_log,
+    book_server_core::api::manager::usage_report,
+    book_server_core::api::manager::get_quota,
+    book_server_core::api::manager::set_quota,
+    book_server_core::api::manager::reset_quota,
+    book_server_core::api::manager::list_students,
+    book_server_core::api::manager::list_classes,
+    book_server_core::api::manager::create_class,
+    book_server_core::api::manager::update_class,
+    book_server_core::api::manager::set_student_class,
+    book_server_core::api::manager::set_student_birth_date,
+    book_server_core::api::manager::list_forbidden_topics,
+    book_server_core::api::manager::add_forbidden_topic,
+    book_server_core::api::manager::remove_forbidden_topic,
+    book_server_core::api::manager::guardrail_log,
+    book_server_core::api::manager::tool_catalog,
+    book_server_core::api::manager::set_tool_text,
+    book_server_core::api::manager::reset_tool_text,
+    book_server_core::api::manager::prompt_templates,
+    book_server_core::api::manager::set_prompt_template,
+    book_server_core::api::manager::reset_prompt_template,
+    book_server_core::api::manager::set_tool_locale,
+    book_server_core::api::manager::set_suggested_replies,
+    book_server_core::api::manager::conversation_replay,
+    book_server_core::api::manager::student_quiz_scores,
+    book_server_core::api::manager::student_progress,
+    book_server_core::api::manager::export_snapshot,
+    book_server_core::api::manager::bulk_delete_books,
+    book_server_core::api::manager::bulk_regenerate_chapter_plans,
+    book_server_core::api::manager::bulk_enroll,
+    book_server_core::api::manager::job_status,
+    book_server_core::api::manager::list_jobs,
+    book_server_core::api::manager::cancel_job,
+    book_server_core::api::manager::retry_job,
+    book_server_core::api::manager::job_policies,
+    book_server_core::api::manager::set_job_policy,
+    book_server_core::api::manager::ai_providers,
+    book_server_core::api::manager::set_ai_provider,
+    book_server_core::api::manager::assign_ai_provider,
+    book_server_core::api::manager::set_recognition_provider,
+    book_server_core::api::manager::dead_letters,
+    book_server_core::api::manager::requeue_dead_letter,
+    book_server_core::api::manager::list_sessions,
+    book_server_core::api::public::get_public_books,
+    book_server_core::api::public::get_book_cover,
 ))]
 struct ManagerApiDoc;
 
@@ -68,57 +325,229 @@ struct ManagerApiDoc;
 async fn main() -> anyhow::Result<()> {
     let _guard = init_log(None);
     let args = Args::parse();
+    let tls_paths = args.tls_paths();
+    let cors_layer = args.cors_layer()?;
+    let frame_ancestors = args.frame_ancestors()?;
+    if matches!(args.cookie_same_site, CookieSameSite::None) && tls_paths.is_none() {
+        anyhow::bail!("--cookie-same-site none requires TLS");
+    }
 
     // Initialize crypto provider for Rustls
     rustls::crypto::ring::default_provider()
         .install_default()
         .expect("Failed to install default crypto provider");
 
-    let database = SqlitePool::connect(&args.database.to_string_lossy()).await?;
-    let library = Arc::new(Library::new(database.clone(), args.bookbase).await?);
+    let mut library = Library::open(&LibraryConfig {
+        database: args.database.clone(),
+        bookbase: args.bookbase.clone(),
+        migrate: false,
+        redis_messages: args.redis_messages.clone(),
+        postgres_messages: args.postgres_messages.clone(),
+        encrypt_messages: args.encrypt_messages,
+        vector_store: args.vector_store.clone(),
+    })
+    .await?;
+    if let Some(address) = &args.clamav {
+        library = library.with_upload_scanner(Arc::new(ClamAvScanner::new(address)));
+    }
+    if let Some(url) = &args.scan_webhook {
+        library = library.with_upload_scanner(Arc::new(WebhookScanner::new(url)?));
+    }
+    if let Some(path) = &args.response_filters {
+        library = library.with_response_filters(ResponsePipeline::load(path)?);
+    }
+    let library = Arc::new(library);
+    // kept to close it once the server stopped
+    let database = library.database.clone();
+    if let Some(path) = &args.analytics_export {
+        let exporter = AnalyticsExporter::load(path, library.database.clone())?;
+        tokio::spawn(exporter.run());
+    }
+    let body_limits = BodyLimits {
+        default: args.body_limit,
+        upload: args.upload_limit,
+    };
 
     let sqlite_store = init_session_database(args.session_database).await?;
     let moka_store = MokaStore::new(Some(2000));
     let caching_store = CachingSessionStore::new(moka_store, sqlite_store);
-    let session_layer = SessionManagerLayer::new(caching_store)
+    // browsers drop secure cookies over plain HTTP
+    let mut session_layer = SessionManagerLayer::new(caching_store)
+        .with_secure(tls_paths.is_some())
+        .with_same_site(args.cookie_same_site.into())
         .with_expiry(Expiry::OnInactivity(Duration::days(5)));
+    if let Some(domain) = args.cookie_domain.clone() {
+        session_layer = session_layer.with_domain(domain);
+    }
 
-    // Initialize teacher cache
-    let cache = Arc::new(Cache::new(1000));
+    // Initialize teacher sessions, evicting the idle ones every minute
+    let followers = Arc::new(Followers::default());
+    let sessions = Arc::new(SessionManager::new(Duration::minutes(
+        args.session_idle_minutes.max(1),
+    )));
+    tokio::spawn({
+        let sessions = sessions.clone();
+        async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                sessions.evict_idle().await;
+            }
+        }
+    });
+    let throttle = Arc::new(ChatThrottle::new(ThrottleConfig {
+        max_messages: args.chat_rate_limit,
+        max_duplicates: args.chat_duplicate_limit,
+        ..Default::default()
+    }));
+    tokio::spawn({
+        let throttle = throttle.clone();
+        async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(600));
+            loop {
+                interval.tick().await;
+                throttle.prune();
+            }
+        }
+    });
+
+    // plan generation calls the model and fails transiently, retry it with backoff
+    let jobs = Arc::new(
+        JobQueue::new()
+            .with_policy(
+                "regenerate_chapter_plans",
+                JobPolicy {
+                    concurrency: 2,
+                    max_attempts: 3,
+                    backoff_seconds: 10,
+                },
+            )
+            .with_dead_letters(library.database.clone()),
+    );
+    tokio::spawn({
+        let jobs = jobs.clone();
+        async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                jobs.prune(Duration::days(1));
+            }
+        }
+    });
 
     // Build the router
-    let app = Router::new()
+    let api = Router::new()
+        .merge(get_user_scope(
+            sessions.clone(),
+            followers.clone(),
+            throttle,
+            body_limits,
+        ))
+        .merge(get_manager_scope(body_limits, jobs, sessions.clone()))
+        .merge(get_public_scope());
+    let app = build_app(api, library, session_layer, frame_ancestors).layer(cors_layer);
+
+    // On SIGTERM or Ctrl-C, refuse new chat requests, let the responses in flight finish or
+    // stop them at the deadline, store the teachers, then stop serving
+    let handle = Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        let grace = std::time::Duration::from_secs(args.shutdown_grace_seconds);
+        async move {
+            shutdown_signal().await;
+            info!("Shutting down, waiting up to {grace:?} for the responses in flight");
+            followers.drain(grace).await;
+            sessions.close().await;
+            handle.graceful_shutdown(Some(std::time::Duration::from_secs(5)));
+        }
+    });
+
+    // Start the server
+    let listener = SocketAddr::new(args.host.parse()?, args.port);
+    match tls_paths {
+        Some((cert, key)) => {
+            // ALPN advertises h2 and http/1.1, websockets fall back to http/1.1
+            let tls_config = RustlsConfig::from_pem_file(&cert, &key).await?;
+            info!("Starting server at https://{}", listener);
+            info!(
+                "Swagger UI available at https://{}:{}/swagger-ui/",
+                args.host, args.port
+            );
+            axum_server::bind_rustls(listener, tls_config)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await?;
+        }
+        None => {
+            warn!("No TLS certificate configured, serving plain HTTP");
+            info!("Starting server at http://{}", listener);
+            info!(
+                "Swagger UI available at http://{}:{}/swagger-ui/",
+                args.host, args.port
+            );
+            axum_server::bind(listener)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await?;
+        }
+    }
+    // every write is done once the responses stopped, close the database cleanly
+    database.close().await;
+    info!("Server stopped");
+    Ok(())
+}
+
+/// resolves on Ctrl-C, or SIGTERM on unix
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("failed to listen for Ctrl-C: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                warn!("failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
+
+/// The app serving `api` under `/api` with the Swagger UI, sessions and the headers of every
+/// response; a route setting its own `Content-Security-Policy`, e.g. the sandboxed book assets,
+/// keeps it
+fn build_app<S: SessionStore + Clone>(
+    api: Router<Arc<Library>>,
+    library: Arc<Library>,
+    session_layer: SessionManagerLayer<S>,
+    frame_ancestors: HeaderValue,
+) -> Router {
+    Router::new()
         .merge(
             SwaggerUi::new("/swagger-ui")
                 .url("/api-docs/user/openapi.json", UserApiDoc::openapi())
                 .url("/api-docs/manager/openapi.json", ManagerApiDoc::openapi()),
         )
-        .nest(
-            "/api",
-            Router::new()
-                .merge(get_user_scope(cache.clone()))
-                .merge(get_manager_scope())
-                .merge(get_public_scope()),
-        )
+        .nest("/api", api)
         .with_state(library)
         .layer(session_layer)
+        .layer(SetResponseHeaderLayer::if_not_present(
+            header::CONTENT_SECURITY_POLICY,
+            frame_ancestors,
+        ))
         .layer(TraceLayer::new_for_http())
-        .layer(CorsLayer::permissive());
-
-    // Start the server
-    let listener = SocketAddr::new(args.host.parse()?, args.port);
-    let tls_config = RustlsConfig::from_pem_file("./cert.pem", "./key.pem").await?;
-
-    info!("Starting server at {}", listener);
-    info!(
-        "Swagger UI available at https://{}:{}/swagger-ui/",
-        args.host, args.port
-    );
-
-    axum_server::bind_rustls(listener, tls_config)
-        .serve(app.into_make_service())
-        .await?;
-    Ok(())
 }
 
 async fn init_session_database(path: PathBuf) -> anyhow::Result<SqliteStore> {
@@ -138,3 +567,81 @@ async fn init_session_database(path: PathBuf) -> anyhow::Result<SqliteStore> {
     store.migrate().await?;
     Ok(store)
 }
+
+#[tokio::test]
+async fn asset_headers() {
+    use axum::body::Body;
+    use book_server_core::student;
+    use tower::ServiceExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let library = Library::open(&LibraryConfig {
+        database: dir.path().join("book.db"),
+        bookbase: dir.path().join("bookbase"),
+        migrate: true,
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let book_dir = dir.path().join("bookbase/book_1");
+    std::fs::create_dir_all(book_dir.join("src")).unwrap();
+    std::fs::write(book_dir.join("book.toml"), "[book]\ntitle = \"Shapes\"\n").unwrap();
+    std::fs::write(book_dir.join("src/fig.svg"), "<svg><script/></svg>").unwrap();
+    student::create_student(
+        &library.database,
+        "Ann".to_string(),
+        "ann@example.com".to_string(),
+        "secret".to_string(),
+    )
+    .await
+    .unwrap();
+    let sessions = Arc::new(SessionManager::new(Duration::minutes(5)));
+    let api = get_user_scope(
+        sessions,
+        Arc::new(Followers::default()),
+        Arc::new(ChatThrottle::new(ThrottleConfig::default())),
+        BodyLimits::default(),
+    );
+    let app = build_app(
+        api,
+        Arc::new(library),
+        SessionManagerLayer::new(MokaStore::new(Some(10))).with_secure(false),
+        HeaderValue::from_static("frame-ancestors 'self'"),
+    );
+
+    let login = axum::http::Request::post("/api/user/login")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            r#"{"email": "ann@example.com", "password": "secret"}"#,
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(login).await.unwrap();
+    assert!(response.status().is_success());
+    let cookie = response.headers()[header::SET_COOKIE]
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string();
+
+    let asset = axum::http::Request::get("/api/user/books/1/assets/fig.svg")
+        .header(header::COOKIE, &cookie)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(asset).await.unwrap();
+    assert!(response.status().is_success());
+    let headers = response.headers();
+    assert_eq!(headers[header::CONTENT_SECURITY_POLICY], "sandbox");
+    assert_eq!(headers[header::CONTENT_DISPOSITION], "attachment");
+
+    let info = axum::http::Request::get("/api/user/user_info")
+        .header(header::COOKIE, &cookie)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(info).await.unwrap();
+    assert_eq!(
+        response.headers()[header::CONTENT_SECURITY_POLICY],
+        "frame-ancestors 'self'"
+    );
+}
@@ -1,4 +1,27 @@
+pub mod accessibility;
+pub mod assets;
+pub mod blocks;
 pub mod book;
 pub mod chapter;
+pub mod content_rating;
+pub mod cover;
+pub mod custom_tools;
+pub mod datasets;
+pub mod directives;
+pub mod export;
+pub mod fuzzy;
 pub mod library;
+pub mod links;
+pub mod notebook;
+pub mod pages;
+pub mod pdf;
+#[cfg(feature = "plugins")]
+pub mod plugins;
+pub mod preprocess;
+pub mod render;
+pub mod search;
+pub mod sections;
+pub mod stats;
+pub mod text;
 pub mod tools;
+pub mod validation;
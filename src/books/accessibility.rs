@@ -0,0 +1,269 @@
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use super::{
+    blocks::{BlockKind, ContentBlock},
+    chapter::{Chapter, ChapterNumber},
+    text,
+};
+use crate::{
+    ai_utils::{Provider, summarize},
+    generation_log,
+};
+
+/// sentences per chunk in the dyslexia friendly layout
+pub const SENTENCES_PER_CHUNK: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessibilityMode {
+    /// the chapter rewritten in plain language
+    Simplified,
+    /// short chunks of a few sentences each, code, tables and lists untouched
+    Chunked,
+    /// the chapter with generated alt text for figures that have none
+    AltText,
+}
+
+impl AccessibilityMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AccessibilityMode::Simplified => "simplified",
+            AccessibilityMode::Chunked => "chunked",
+            AccessibilityMode::AltText => "alt_text",
+        }
+    }
+}
+
+/// get the chapter content transformed for `mode`, transforms are cached per chapter
+pub async fn get_accessible_content(
+    database: &SqlitePool,
+    provider: &dyn Provider,
+    book_id: i64,
+    chapter: &Chapter,
+    mode: AccessibilityMode,
+) -> anyhow::Result<String> {
+    let chapter_number = chapter.number.to_string();
+    let mode_str = mode.as_str();
+    if let Some(content) = sqlx::query_scalar!(
+        "select content from accessible_content where book_id = ? and chapter_number = ? and mode = ?",
+        book_id,
+        chapter_number,
+        mode_str
+    )
+    .fetch_optional(database)
+    .await?
+    {
+        return Ok(content);
+    }
+    let generate = async {
+        anyhow::Ok(match mode {
+            AccessibilityMode::Simplified => simplify(provider, &chapter.content).await?,
+            AccessibilityMode::Chunked => chunk_for_dyslexia(&chapter.content),
+            AccessibilityMode::AltText => {
+                add_alt_text(provider, &chapter.content, &chapter.blocks).await?
+            }
+        })
+    };
+    let content = match generation_log::with_context(
+        |context| {
+            context.artifact = Some(mode_str);
+            context.book_id = Some(book_id);
+            context.chapter_number = Some(chapter_number.clone());
+        },
+        generate,
+    )
+    .await
+    {
+        Ok(content) => content,
+        Err(e) => {
+            // the reader keeps working while the AI is down, the transform is retried next time
+            warn!(
+                "failed to transform chapter {chapter_number} to {mode_str}, serving the original: {e:?}"
+            );
+            return Ok(chapter.content.clone());
+        }
+    };
+    sqlx::query!(
+        "insert or replace into accessible_content (book_id, chapter_number, mode, content) values (?, ?, ?, ?)",
+        book_id,
+        chapter_number,
+        mode_str,
+        content
+    )
+    .execute(database)
+    .await?;
+    Ok(content)
+}
+
+/// drop the cached transforms of a chapter, or of the whole book if `chapter_number` is `None`
+pub async fn clear_cache(
+    database: &SqlitePool,
+    book_id: i64,
+    chapter_number: Option<&ChapterNumber>,
+) -> anyhow::Result<()> {
+    match chapter_number {
+        Some(number) => {
+            let number = number.to_string();
+            sqlx::query!(
+                "delete from accessible_content where book_id = ? and chapter_number = ?",
+                book_id,
+                number
+            )
+            .execute(database)
+            .await?;
+        }
+        None => {
+            sqlx::query!("delete from accessible_content where book_id = ?", book_id)
+                .execute(database)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn simplify(provider: &dyn Provider, content: &str) -> anyhow::Result<String> {
+    let prompt = "Rewrite the following markdown in plain language for a reader who struggles with complex text. \
+Use short sentences and common words, explain jargon the first time it appears, and keep headings, code blocks, tables and links unchanged. \
+Return only the rewritten markdown."
+        .to_string();
+    let (words, cjk_chars) = text::count_words(content);
+    let words = if text::is_mostly_cjk(content) {
+        cjk_chars as usize / 2
+    } else {
+        words as usize
+    };
+    let words = words.max(100);
+    summarize(provider, content, words, Some(prompt)).await
+}
+
+async fn add_alt_text(
+    provider: &dyn Provider,
+    content: &str,
+    blocks: &[ContentBlock],
+) -> anyhow::Result<String> {
+    let mut content = content.to_string();
+    for block in blocks {
+        if block.kind != BlockKind::Figure || block.caption.is_some() {
+            continue;
+        }
+        let Some(link) = block.content.find("](") else {
+            continue;
+        };
+        let context = surrounding_text(&content, &block.content);
+        let prompt = format!(
+            "Write a one sentence alt text for the image `{}` in the text below, describe what the image most likely shows for a reader who cannot see it. Return only the alt text.",
+            block.content
+        );
+        let alt = summarize(provider, &context, 30, Some(prompt)).await?;
+        let alt = alt.trim().replace(['[', ']'], "");
+        let figure = format!("![{alt}]{}", &block.content[link + 1..]);
+        content = content.replacen(&block.content, &figure, 1);
+    }
+    Ok(content)
+}
+
+/// the text around the first occurrence of `needle`, used as context for alt text
+fn surrounding_text(content: &str, needle: &str) -> String {
+    const CONTEXT: usize = 1000;
+    let Some(start) = content.find(needle) else {
+        return String::new();
+    };
+    let end = start + needle.len();
+    let mut from = start.saturating_sub(CONTEXT);
+    while !content.is_char_boundary(from) {
+        from -= 1;
+    }
+    let mut to = (end + CONTEXT).min(content.len());
+    while !content.is_char_boundary(to) {
+        to += 1;
+    }
+    content[from..to].to_string()
+}
+
+/// split prose paragraphs into chunks of [`SENTENCES_PER_CHUNK`] sentences separated by blank lines,
+/// headings, lists, quotes, tables, html and code blocks are kept as they are
+pub fn chunk_for_dyslexia(content: &str) -> String {
+    let mut output = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut in_fence = false;
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            flush_paragraph(&mut paragraph, &mut output);
+            in_fence = !in_fence;
+            output.push(line.to_string());
+            continue;
+        }
+        if in_fence || trimmed.is_empty() || is_structural(trimmed) {
+            flush_paragraph(&mut paragraph, &mut output);
+            output.push(line.to_string());
+            continue;
+        }
+        paragraph.push(trimmed);
+    }
+    flush_paragraph(&mut paragraph, &mut output);
+    output.join("\n")
+}
+
+fn is_structural(line: &str) -> bool {
+    let ordered_list = line
+        .split_once(['.', ')'])
+        .is_some_and(|(n, _)| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+    ordered_list
+        || line.starts_with(['#', '|', '>', '<', '-', '*', '+'])
+        || line.starts_with("![")
+        || line.starts_with("    ")
+}
+
+fn flush_paragraph(paragraph: &mut Vec<&str>, output: &mut Vec<String>) {
+    if paragraph.is_empty() {
+        return;
+    }
+    let joined = text::join(paragraph);
+    paragraph.clear();
+    let sentences = split_sentences(&joined);
+    for (i, chunk) in sentences.chunks(SENTENCES_PER_CHUNK).enumerate() {
+        if i > 0 {
+            output.push(String::new());
+        }
+        output.push(text::join(chunk));
+    }
+}
+
+fn split_sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if super::text::is_sentence_end(c, chars.peek().map(|(_, next)| *next)) {
+            let end = i + c.len_utf8();
+            let sentence = text[start..end].trim();
+            if !sentence.is_empty() {
+                sentences.push(sentence);
+            }
+            start = end;
+        }
+    }
+    let rest = text[start..].trim();
+    if !rest.is_empty() {
+        sentences.push(rest);
+    }
+    sentences
+}
+
+#[test]
+fn test_chunk_for_dyslexia() {
+    let content = "# Title\n\nOne. Two! Three? Four.\nFive\n\n```rust\nlet a = 1. b = 2.;\n```\n- item. one. two.";
+    let chunked = chunk_for_dyslexia(content);
+    assert_eq!(
+        chunked,
+        "# Title\n\nOne. Two!\n\nThree? Four.\n\nFive\n\n```rust\nlet a = 1. b = 2.;\n```\n- item. one. two."
+    );
+    assert_eq!(
+        split_sentences("v1.2 is out. Done"),
+        vec!["v1.2 is out.", "Done"]
+    );
+}
@@ -0,0 +1,183 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::LazyLock,
+};
+
+use regex::{Captures, Regex};
+
+/// markdown images: `![alt](target "title")`
+static IMAGE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"!\[([^\]]*)\]\(([^)\s]+)(\s+"[^"]*")?\)"#).unwrap());
+/// the source of html media in chapters: `<img src="...">`, `<audio src="...">`, `<source src="...">`
+static MEDIA_SRC: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)(<(?:img|audio|video|source)\b[^>]*?\bsrc\s*=\s*)"([^"]*)""#).unwrap()
+});
+
+/// files of a book served to readers with their content type, anything else stays private
+const ASSET_TYPES: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("webp", "image/webp"),
+    ("gif", "image/gif"),
+    ("avif", "image/avif"),
+    // served as attachments in a sandbox, see `is_active_content`
+    ("svg", "image/svg+xml"),
+    ("mp3", "audio/mpeg"),
+    ("m4a", "audio/mp4"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("flac", "audio/flac"),
+    // datasets of data-science books, see `datasets`
+    ("csv", "text/csv"),
+    ("tsv", "text/tab-separated-values"),
+    ("jsonl", "application/x-ndjson"),
+];
+
+/// the content type of a book asset, `None` for files that aren't served
+pub fn asset_content_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    ASSET_TYPES
+        .iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, content_type)| *content_type)
+}
+
+/// whether assets of `content_type` can run scripts when opened on their own, they are only
+/// served as downloads
+pub fn is_active_content(content_type: &str) -> bool {
+    content_type == "image/svg+xml"
+}
+
+fn percent_decode(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+/// `path` with `.` and `..` resolved, `None` when it leaves its root or isn't relative
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(resolved)
+}
+
+/// the asset a link of the chapter at `chapter_path` points to, relative to the book `src`
+/// directory with forward slashes; `None` for external links and paths leaving `src`
+fn resolve_target(chapter_path: Option<&Path>, target: &str) -> Option<String> {
+    if target.contains("://") || target.starts_with("data:") || target.starts_with('/') {
+        return None;
+    }
+    let target = percent_decode(target.split(['#', '?']).next().unwrap_or_default())?;
+    let dir = chapter_path
+        .and_then(|path| path.parent())
+        .unwrap_or(Path::new(""));
+    let path = normalize(&dir.join(target))?;
+    asset_content_type(&path)?;
+    Some(path.to_str()?.replace('\\', "/"))
+}
+
+/// Point the images and audio of a chapter at the asset route under `base_url`.
+///
+/// `chapter_path` is the path of the chapter relative to the book `src` directory, external
+/// links and links to other files are kept as is.
+pub fn rewrite_asset_links(content: &str, chapter_path: Option<&Path>, base_url: &str) -> String {
+    let url = |target: &str| {
+        resolve_target(chapter_path, target).map(|path| {
+            format!(
+                "{}/{}",
+                base_url.trim_end_matches('/'),
+                percent_encode(&path)
+            )
+        })
+    };
+    let content = IMAGE.replace_all(content, |caps: &Captures| match url(&caps[2]) {
+        Some(url) => {
+            let title = caps.get(3).map_or("", |m| m.as_str());
+            format!("![{}]({url}{title})", &caps[1])
+        }
+        None => caps[0].to_string(),
+    });
+    MEDIA_SRC
+        .replace_all(&content, |caps: &Captures| match url(&caps[2]) {
+            Some(url) => format!("{}\"{url}\"", &caps[1]),
+            None => caps[0].to_string(),
+        })
+        .into_owned()
+}
+
+/// The file of the asset at `path`, relative to the book `src` directory `src_dir`.
+///
+/// Fails for paths leaving `src_dir`, also through symbolic links, for hidden files and for
+/// files that aren't images, audio or datasets.
+pub fn asset_path(src_dir: &Path, path: &str) -> anyhow::Result<PathBuf> {
+    let relative = Path::new(path);
+    let is_plain = relative.components().all(|component| {
+        matches!(component, Component::Normal(part) if !part.to_string_lossy().starts_with('.'))
+    });
+    if !is_plain || asset_content_type(relative).is_none() {
+        anyhow::bail!("not a book asset: {path}");
+    }
+    let root = src_dir.canonicalize()?;
+    let file = root.join(relative).canonicalize()?;
+    if !file.starts_with(&root) || !file.is_file() {
+        anyhow::bail!("not a book asset: {path}");
+    }
+    Ok(file)
+}
+
+#[test]
+fn rewrite() {
+    let content = "![fig](../images/fig%201.png \"Figure\") ![web](https://example.com/a.png) \
+        [notes](notes.md) <audio controls src=\"sound/a.mp3\"></audio> ![up](../../secret.png)";
+    let content = rewrite_asset_links(
+        content,
+        Some(Path::new("ch03/intro.md")),
+        "/api/user/books/1/assets",
+    );
+    assert_eq!(
+        content,
+        "![fig](/api/user/books/1/assets/images/fig%201.png \"Figure\") ![web](https://example.com/a.png) \
+        [notes](notes.md) <audio controls src=\"/api/user/books/1/assets/ch03/sound/a.mp3\"></audio> \
+        ![up](../../secret.png)"
+    );
+    let src_dir = Path::new("/bookbase/book_1/src");
+    assert!(asset_path(src_dir, "../book.toml").is_err());
+    assert!(asset_path(src_dir, "/etc/passwd.png").is_err());
+    assert!(asset_path(src_dir, ".hidden/a.png").is_err());
+    assert!(asset_path(src_dir, "chapter_1.md").is_err());
+}
@@ -0,0 +1,168 @@
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::chapter::ChapterNumber;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum BlockKind {
+    Table,
+    Code,
+    Figure,
+}
+
+impl BlockKind {
+    fn label(&self) -> &'static str {
+        match self {
+            BlockKind::Table => "Table",
+            BlockKind::Code => "Code",
+            BlockKind::Figure => "Figure",
+        }
+    }
+}
+
+/// A non-text block of a chapter, numbered per kind like "Table 3.1"
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ContentBlock {
+    /// e.g. "Table 3.1", "Code 3.2", "Figure 3.1"
+    pub id: String,
+    pub kind: BlockKind,
+    pub chapter_number: ChapterNumber,
+    /// code language, figure alt text, or the "Table ..." line right before a table
+    pub caption: Option<String>,
+    /// the markdown source of the block
+    pub content: String,
+    /// html anchor of the block, e.g. "table-3-1"
+    pub anchor: String,
+}
+
+/// normalize user or model supplied ids like " table 3.1. " to "Table 3.1"
+pub fn normalize_block_id(id: &str) -> String {
+    let id = id.trim().trim_end_matches('.');
+    let mut parts = id.splitn(2, char::is_whitespace);
+    let kind = parts.next().unwrap_or_default().to_lowercase();
+    let number = parts.next().unwrap_or_default().trim();
+    let mut chars = kind.chars();
+    let kind = match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    };
+    format!("{kind} {number}")
+}
+
+/// Detect tables, code blocks and figures in the markdown content of a chapter
+pub fn extract_blocks(number: &ChapterNumber, content: &str) -> Vec<ContentBlock> {
+    let prefix = number.to_string();
+    let prefix = prefix.trim_end_matches('.');
+    let mut counts = [0usize; 3];
+    let mut blocks = Vec::new();
+    let mut last_paragraph = String::new();
+    let mut paragraph = String::new();
+    let mut in_paragraph = false;
+    // (kind, caption, start offset) of the block being read
+    let mut current: Option<(BlockKind, Option<String>, usize)> = None;
+    let mut alt_text = String::new();
+
+    let parser = Parser::new_ext(content, Options::ENABLE_TABLES).into_offset_iter();
+    for (event, range) in parser {
+        match event {
+            Event::Start(Tag::Paragraph) => {
+                in_paragraph = true;
+                paragraph.clear();
+            }
+            Event::End(TagEnd::Paragraph) => {
+                in_paragraph = false;
+                last_paragraph = std::mem::take(&mut paragraph);
+            }
+            Event::Start(Tag::Table(_)) if current.is_none() => {
+                let caption = last_paragraph
+                    .trim()
+                    .starts_with("Table")
+                    .then(|| last_paragraph.trim().to_string());
+                current = Some((BlockKind::Table, caption, range.start));
+            }
+            Event::Start(Tag::CodeBlock(kind)) if current.is_none() => {
+                let caption = match kind {
+                    CodeBlockKind::Fenced(lang) if !lang.is_empty() => Some(lang.to_string()),
+                    _ => None,
+                };
+                current = Some((BlockKind::Code, caption, range.start));
+            }
+            Event::Start(Tag::Image { .. }) if current.is_none() => {
+                alt_text.clear();
+                current = Some((BlockKind::Figure, None, range.start));
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((BlockKind::Figure, _, _)) = current {
+                    alt_text.push_str(&text);
+                } else if in_paragraph {
+                    paragraph.push_str(&text);
+                }
+            }
+            Event::End(end @ (TagEnd::Table | TagEnd::CodeBlock | TagEnd::Image)) => {
+                let Some((kind, caption, start)) = current.take() else {
+                    continue;
+                };
+                let matches = matches!(
+                    (kind, end),
+                    (BlockKind::Table, TagEnd::Table)
+                        | (BlockKind::Code, TagEnd::CodeBlock)
+                        | (BlockKind::Figure, TagEnd::Image)
+                );
+                if !matches {
+                    current = Some((kind, caption, start));
+                    continue;
+                }
+                let caption = match kind {
+                    BlockKind::Figure if !alt_text.is_empty() => Some(alt_text.clone()),
+                    _ => caption,
+                };
+                let count = &mut counts[kind as usize];
+                *count += 1;
+                let id = format!("{} {prefix}.{count}", kind.label());
+                let anchor = format!(
+                    "{}-{}-{count}",
+                    kind.label().to_lowercase(),
+                    prefix.replace('.', "-")
+                );
+                blocks.push(ContentBlock {
+                    id,
+                    kind,
+                    chapter_number: number.clone(),
+                    caption,
+                    content: content[start..range.end].to_string(),
+                    anchor,
+                });
+            }
+            _ => {}
+        }
+    }
+    blocks
+}
+
+#[test]
+fn extract() {
+    let content = r#"# Verbs
+
+Table: tense overview
+
+| tense | example |
+|-------|---------|
+| past  | ran     |
+
+```rust
+fn main() {}
+```
+
+![a verb timeline](timeline.png)
+"#;
+    let number = "3.".parse().unwrap();
+    let blocks = extract_blocks(&number, content);
+    let ids: Vec<&str> = blocks.iter().map(|b| b.id.as_str()).collect();
+    assert_eq!(ids, ["Table 3.1", "Code 3.1", "Figure 3.1"]);
+    assert_eq!(blocks[0].caption.as_deref(), Some("Table: tense overview"));
+    assert_eq!(blocks[1].caption.as_deref(), Some("rust"));
+    assert_eq!(blocks[2].caption.as_deref(), Some("a verb timeline"));
+    assert_eq!(blocks[2].anchor, "figure-3-1");
+    assert_eq!(normalize_block_id(" table 3.1. "), "Table 3.1");
+}
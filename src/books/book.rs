@@ -1,16 +1,36 @@
 use std::{
-    collections::{BTreeMap, BTreeSet, btree_map::Entry},
+    collections::{BTreeMap, BTreeSet, HashMap, btree_map::Entry},
     hash::{DefaultHasher, Hash, Hasher},
     path::{Path, PathBuf},
 };
 
-use crate::ai_utils;
+use crate::{
+    ai_utils::{self, Provider},
+    generation_log,
+    prompts::{PromptName, PromptTemplates},
+};
+
+use super::{
+    blocks::{ContentBlock, normalize_block_id},
+    content_rating::{self, ContentRating},
+    fuzzy,
+    links::rewrite_links,
+    notebook, pages,
+    preprocess::{BookServerConfig, GenerationConfig, Pipeline, PreprocessContext},
+    stats::{BookStats, ContentStats},
+    text,
+    validation::ValidationReport,
+};
 
-use super::chapter::{Chapter, ChapterNumber, ChapterPlan, ChapterRaw};
-use anyhow::bail;
+use super::chapter::{
+    Chapter, ChapterNumber, ChapterPlan, ChapterRaw, PlanQuality, normalize_chapter_numbers,
+};
+use anyhow::{Context, bail};
+use book_model::{ChapterMeta, TocNode, build_toc};
 use mdbook::book;
 use serde::{Deserialize, Serialize};
-use tracing::{error, info};
+use time::OffsetDateTime;
+use tracing::{error, info, warn};
 use tree_iter::{
     iter::TreeIter,
     prelude::{DepthFirst, TreeIterMut},
@@ -21,6 +41,36 @@ use utoipa::ToSchema;
 pub struct BookTeachingPlan {
     pub teaching_plan: Option<String>,
     pub chapter_plans: BTreeMap<ChapterNumber, ChapterPlan>,
+    /// critique of the generated chapter plans, plans written before scoring have none
+    #[serde(default)]
+    pub plan_quality: BTreeMap<ChapterNumber, PlanQuality>,
+    /// [`ChapterRaw::content_hash`] of each planned chapter, a plan is regenerated when its chapter changes
+    #[serde(default)]
+    pub chapter_hashes: BTreeMap<ChapterNumber, u64>,
+    /// id kept by a re-import, instead of the hash of the changed sources
+    #[serde(default)]
+    pub book_id: Option<i64>,
+    /// mature content of each chapter, rated again when the chapter changes
+    #[serde(default)]
+    pub content_ratings: BTreeMap<ChapterNumber, ContentRating>,
+}
+
+impl BookTeachingPlan {
+    pub const FILE_NAME: &str = "teaching_plan.toml";
+
+    pub async fn load(book_path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let content = tokio::fs::read_to_string(book_path.as_ref().join(Self::FILE_NAME)).await?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    pub async fn save(&self, book_path: impl AsRef<Path>) -> anyhow::Result<()> {
+        tokio::fs::write(
+            book_path.as_ref().join(Self::FILE_NAME),
+            toml::to_string(self)?,
+        )
+        .await?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -30,6 +80,8 @@ pub struct BookRaw {
     pub chapters: BTreeMap<ChapterNumber, ChapterRaw>,
     pub authors: Vec<String>,
     pub description: Option<String>,
+    pub report: ValidationReport,
+    pub generation: GenerationConfig,
 }
 
 #[derive(Debug, Clone, Serialize, ToSchema)]
@@ -44,8 +96,18 @@ pub struct Book {
     pub authors: Vec<String>,
     pub description: Option<String>,
     pub teaching_plan: String,
+    pub stats: ContentStats,
     #[serde(skip_serializing)]
     pub chapters: BTreeMap<ChapterNumber, Chapter>,
+    /// first chapter of each marked original print page, e.g. for books imported from PDFs
+    #[serde(skip_serializing)]
+    pub page_map: BTreeMap<u32, ChapterNumber>,
+    #[serde(skip_serializing)]
+    pub report: ValidationReport,
+    #[serde(skip_serializing)]
+    pub plan_quality: BTreeMap<ChapterNumber, PlanQuality>,
+    #[serde(skip_serializing)]
+    pub content_ratings: BTreeMap<ChapterNumber, ContentRating>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
@@ -55,10 +117,68 @@ pub struct BookMeta {
     pub authors: Vec<String>,
     pub description: Option<String>,
     pub is_public: bool,
+    /// hidden from students, see [`Library::archive_book`](super::library::Library::archive_book)
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// when the book was added
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    #[schema(value_type = Option<String>)]
+    pub create_time: Option<OffsetDateTime>,
+    /// language of the book, e.g. "en"
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub difficulty: Option<Difficulty>,
+    /// whether the book has a cover image, see [`Library::book_cover`](super::library::Library::book_cover)
+    #[serde(default)]
+    pub has_cover: bool,
+}
+
+/// How advanced the readers of a book are expected to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    Beginner,
+    Intermediate,
+    Advanced,
+}
+
+impl Difficulty {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Beginner => "beginner",
+            Self::Intermediate => "intermediate",
+            Self::Advanced => "advanced",
+        }
+    }
+}
+
+impl TryFrom<&str> for Difficulty {
+    type Error = anyhow::Error;
+
+    fn try_from(difficulty: &str) -> anyhow::Result<Self> {
+        match difficulty {
+            "beginner" => Ok(Self::Beginner),
+            "intermediate" => Ok(Self::Intermediate),
+            "advanced" => Ok(Self::Advanced),
+            _ => bail!("Unknown difficulty: {difficulty}"),
+        }
+    }
+}
+
+/// A chapter matched by approximate title
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ChapterMatch {
+    pub chapter_number: ChapterNumber,
+    pub name: String,
+    /// similarity between the query and the chapter title, from 0 to 1
+    pub score: f64,
 }
 
 impl BookRaw {
-    async fn load(root_dir: impl AsRef<Path>) -> anyhow::Result<BookRaw> {
+    pub async fn load(root_dir: impl AsRef<Path>) -> anyhow::Result<BookRaw> {
         let root_dir = root_dir.as_ref();
         info!("Loading book from {}", root_dir.display());
         let file_name = root_dir
@@ -69,6 +189,7 @@ impl BookRaw {
             .to_string();
         let book_toml_content = tokio::fs::read_to_string(root_dir.join("book.toml")).await?;
         let book_cfg = toml::from_str::<mdbook::config::Config>(&book_toml_content)?.book;
+        let server_cfg = BookServerConfig::from_book_toml(&book_toml_content)?;
         let src_dir = root_dir.join(book_cfg.src);
         let build_config = mdbook::config::BuildConfig {
             build_dir: PathBuf::from(""),
@@ -85,6 +206,11 @@ impl BookRaw {
             chapters: BTreeMap::new(),
             authors: book_cfg.authors,
             description: book_cfg.description,
+            report: ValidationReport::default(),
+            generation: GenerationConfig {
+                summarize: server_cfg.summarize.clone(),
+                ..server_cfg.self_consistency.clone()
+            },
         };
         let ori_book = mdbook::book::load_book(src_dir.clone(), &build_config)?;
         let mut chapters: Vec<ChapterRaw> = vec![];
@@ -93,25 +219,7 @@ impl BookRaw {
                 chapters.push(ch.into());
             }
         }
-        let mut is_prefix = true;
-        let mut prefix_idx = 1;
-        let mut suffix_idx = 1;
-        let mut iter = TreeIterMut::<ChapterRaw, DepthFirst>::new(chapters.iter_mut());
-        while let Some(mut ch) = iter.next() {
-            if !ch.number.is_empty() {
-                is_prefix = false;
-                continue;
-            }
-            if is_prefix {
-                // prefix chapter, number is 0.1, 0.2, 0.3, ...
-                ch.number = ChapterNumber::from_iter(vec![0, prefix_idx]);
-                prefix_idx += 1;
-            } else {
-                // suffix chapter, number is -1.1, -1.2, -1.3, ...
-                ch.number = ChapterNumber::from_iter(vec![-1, suffix_idx]);
-                suffix_idx += 1;
-            }
-        }
+        normalize_chapter_numbers(&mut chapters);
 
         let len = chapters.len();
         book.chapters = chapters
@@ -127,13 +235,60 @@ impl BookRaw {
         book.authors.hash(&mut hasher);
         book.description.hash(&mut hasher);
         book.chapters.hash(&mut hasher);
-        book.id = (hasher.finish() as i64).abs();
+        let pinned_id = BookTeachingPlan::load(root_dir)
+            .await
+            .ok()
+            .and_then(|plan| plan.book_id);
+        book.id = pinned_id.unwrap_or((hasher.finish() as i64).abs());
+
+        // the id is computed from the raw sources, so changing the pipeline keeps the id stable
+        let pipeline = Pipeline::from_names(&server_cfg.preprocess)?;
+        let mut iter = book.iter_mut();
+        while let Some(mut ch) = iter.next() {
+            let chapter_path = ch.path.clone();
+            let ctx = PreprocessContext {
+                book_dir: root_dir,
+                src_dir: &src_dir,
+                chapter_path: chapter_path.as_deref(),
+            };
+            let mut content = std::mem::take(&mut ch.content);
+            // mdbook reads the notebooks linked from SUMMARY.md as they are, JSON
+            let is_notebook = chapter_path.as_deref().is_some_and(|path| {
+                path.extension()
+                    .is_some_and(|ext| ext == notebook::EXTENSION)
+            });
+            if is_notebook {
+                content = notebook::to_markdown(&content)
+                    .with_context(|| format!("chapter {}", ch.number))?;
+            }
+            ch.content = pipeline.run(&ctx, content)?;
+        }
+
+        let paths: HashMap<PathBuf, ChapterNumber> = book
+            .iter()
+            .filter_map(|ch| Some((ch.path.clone()?, ch.number.clone())))
+            .collect();
+        let mut report = ValidationReport::default();
+        let mut iter = book.iter_mut();
+        while let Some(mut ch) = iter.next() {
+            let (content, unresolved) =
+                rewrite_links(&ch.content, &ch.number, ch.path.as_deref(), &paths);
+            for target in unresolved {
+                warn!("unresolved link in chapter {}: {}", ch.number, target);
+                report.add_dead_link(&ch.number, target);
+            }
+            ch.content = content;
+            report.check_chapter(&src_dir, &ch);
+        }
+        book.report = report;
         Ok(book)
     }
 
     async fn generate_plan(
         &self,
+        provider: &dyn Provider,
         chapters: &BTreeMap<ChapterNumber, Chapter>,
+        prompts: &PromptTemplates,
     ) -> anyhow::Result<String> {
         let description = match self.description.as_ref() {
             Some(description) => format!("## Description\n{}\n\n", description),
@@ -150,82 +305,120 @@ impl BookRaw {
             ));
         }
         info!("generating teaching plan for book: {}", self.title);
-        let prompt = r#"Generate a teaching plan for the book.
-Example:
-```
-# Teaching Plan for "Mastering English Grammar"
-
-## Overall Objectives
-- **Primary Goal**: Enable the student to accurately understand and apply English grammar rules in written and spoken contexts.
-- **Secondary Goals**:
-  - Build a strong foundation in parts of speech, sentence structures, tenses, and punctuation.
-  - Improve the student's ability to identify and correct grammatical mistakes.
-  - Increase confidence in using complex grammar during communication.
-
-## Learning Path
-The book is divided into three stages, each designed to progressively build the student's skills:
-
-1. **Basic Stage (Chapters 1-3)**:
-   - **Focus**: Core grammar concepts (nouns, verbs, adjectives, adverbs, and simple sentences).
-   - **Approach**: Interactive exercises and personalized practice.
-
-2. **Intermediate Stage (Chapters 4-6)**:
-   - **Focus**: Complex grammar topics (verb tenses, subject-verb agreement, pronouns, and clauses).
-   - **Approach**: Tailored explanations and writing tasks.
-
-3. **Advanced Stage (Chapters 7-9)**:
-   - **Focus**: Advanced topics (passive voice, conditionals, reported speech, and punctuation details).
-   - **Approach**: In-depth analysis and practical application.
-
-## Teaching Strategies
-- **Customized Lessons**: Adapt explanations and exercises to the student's learning pace and style.
-- **Repetition and Reinforcement**: Revisit key concepts regularly to solidify understanding.
-- **Feedback-Driven Approach**: Provide immediate, detailed feedback to address errors and encourage improvement.
-
-## Assessment Methods
-- **Chapter Quizzes**: Short tests after each chapter to check comprehension.
-- **Comprehensive Exams**: Midterm and final tests covering multiple topics.
-- **Practical Tasks**: Assignments that apply grammar rules to real-life writing or speaking scenarios.
-```"#;
-        let teaching_plan =
-            ai_utils::summarize(&chapter_summaries, 1000, Some(prompt.to_string())).await?;
+        let chapter_list: Vec<String> = chapters
+            .values()
+            .map(|ch| format!("{} {}", ch.number, ch.name))
+            .collect();
+        let context = minijinja::context! {
+            book_title => &self.title,
+            book_description => self.description.as_deref().unwrap_or_default(),
+            chapter_list => chapter_list.join("\n"),
+        };
+        let prompt = prompts.render(PromptName::TeachingPlan, context)?;
+        let teaching_plan = generation_log::artifact(
+            "teaching_plan",
+            ai_utils::summarize_consistent(
+                provider,
+                &chapter_summaries,
+                self.generation.summarize.plan_length,
+                prompt,
+                &self.generation.teaching_plan,
+                &self.generation.summarize.chunking,
+            ),
+        )
+        .await?;
         Ok(teaching_plan)
     }
 
-    async fn to_book(&self, book_path: impl AsRef<Path>) -> anyhow::Result<Book> {
-        let teaching_plan_path = book_path.as_ref().join("teaching_plan.toml");
+    async fn to_book(
+        &self,
+        book_path: impl AsRef<Path>,
+        provider: &dyn Provider,
+        prompts: &PromptTemplates,
+    ) -> anyhow::Result<Book> {
         let mut changed = false;
-        let mut book_plan = match tokio::fs::read_to_string(&teaching_plan_path)
-            .await
-            .map(|s| toml::from_str::<BookTeachingPlan>(&s))
-        {
-            Ok(Ok(plan)) => plan,
-            _ => BookTeachingPlan::default(),
-        };
+        let mut book_plan = BookTeachingPlan::load(&book_path).await.unwrap_or_default();
 
         let mut chapters = BTreeMap::new();
         for ch in self.iter() {
+            let hash = ch.content_hash();
+            if book_plan
+                .chapter_hashes
+                .get(&ch.number)
+                .is_some_and(|old| *old != hash)
+            {
+                info!("chapter {} changed, regenerating its plan", ch.number);
+                book_plan.chapter_plans.remove(&ch.number);
+                book_plan.plan_quality.remove(&ch.number);
+                book_plan.content_ratings.remove(&ch.number);
+                book_plan.teaching_plan = None;
+            }
+            if book_plan.chapter_hashes.insert(ch.number.clone(), hash) != Some(hash) {
+                changed = true;
+            }
             let chapter_plan = match book_plan.chapter_plans.entry(ch.number.clone()) {
                 Entry::Vacant(o) => {
                     changed = true;
-                    o.insert(ch.generate_chapter_plan().await?).clone()
+                    let (plan, quality) = generation_log::with_context(
+                        |context| context.chapter_number = Some(ch.number.to_string()),
+                        ch.generate_scored_chapter_plan(
+                            provider,
+                            &self.generation,
+                            prompts,
+                            &self.title,
+                        ),
+                    )
+                    .await?;
+                    book_plan.plan_quality.insert(ch.number.clone(), quality);
+                    o.insert(plan).clone()
                 }
                 Entry::Occupied(o) => o.get().clone(),
             };
+            if !book_plan.content_ratings.contains_key(&ch.number) {
+                changed = true;
+                let rating = generation_log::with_context(
+                    |context| context.chapter_number = Some(ch.number.to_string()),
+                    content_rating::rate_chapter(provider, ch),
+                )
+                .await?;
+                book_plan.content_ratings.insert(ch.number.clone(), rating);
+            }
             let chapter = ch.to_chapter(chapter_plan);
             chapters.insert(ch.number.clone(), chapter);
         }
+        let planned = book_plan.chapter_plans.len();
+        book_plan
+            .chapter_plans
+            .retain(|number, _| chapters.contains_key(number));
+        if book_plan.chapter_plans.len() != planned {
+            book_plan.teaching_plan = None;
+            changed = true;
+        }
+        book_plan
+            .plan_quality
+            .retain(|number, _| chapters.contains_key(number));
+        book_plan
+            .chapter_hashes
+            .retain(|number, _| chapters.contains_key(number));
+        book_plan
+            .content_ratings
+            .retain(|number, _| chapters.contains_key(number));
         let teaching_plan = match &book_plan.teaching_plan {
             Some(teaching_plan) => teaching_plan.clone(),
             None => {
-                let teaching_plan = self.generate_plan(&chapters).await?;
+                let teaching_plan = self.generate_plan(provider, &chapters, prompts).await?;
                 book_plan.teaching_plan = Some(teaching_plan.clone());
                 changed = true;
                 teaching_plan
             }
         };
         if changed {
-            tokio::fs::write(&teaching_plan_path, toml::to_string(&book_plan)?).await?;
+            book_plan.save(&book_path).await?;
+        }
+        let page_map = pages::build_page_map(chapters.values());
+        let mut stats = ContentStats::default();
+        for ch in chapters.values() {
+            stats += &ch.stats;
         }
         let book = Book {
             id: self.id,
@@ -234,8 +427,13 @@ The book is divided into three stages, each designed to progressively build the
             authors: self.authors.clone(),
             description: self.description.clone(),
             teaching_plan,
+            stats,
             chapters,
             chapter_numbers: self.chapters.keys().cloned().collect(),
+            page_map,
+            report: self.report.clone(),
+            plan_quality: book_plan.plan_quality,
+            content_ratings: book_plan.content_ratings,
         };
         Ok(book)
     }
@@ -248,7 +446,7 @@ The book is divided into three stages, each designed to progressively build the
     }
 
     pub fn get_table_of_contents(&self) -> String {
-        let mut toc = format!("# {}\n", self.title);
+        let mut toc = format!("# {}\n", text::isolate(&self.title));
         for ch in self.chapters.values() {
             toc.push_str(&ch.get_toc_item());
         }
@@ -257,8 +455,83 @@ The book is divided into three stages, each designed to progressively build the
 }
 
 impl Book {
-    pub async fn load(book_path: impl AsRef<Path>) -> anyhow::Result<Book> {
+    /// load the book, generating the missing plans with `provider` and `prompts`
+    pub async fn load(
+        book_path: impl AsRef<Path>,
+        provider: &dyn Provider,
+        prompts: &PromptTemplates,
+    ) -> anyhow::Result<Book> {
         let book_raw = BookRaw::load(&book_path).await?;
-        book_raw.to_book(&book_path).await
+        let book_id = book_raw.id;
+        generation_log::with_context(
+            |context| context.book_id = Some(book_id),
+            book_raw.to_book(&book_path, provider, prompts),
+        )
+        .await
+    }
+
+    /// the chapters a student of `age` may not read, none when the age is unknown
+    pub fn restricted_chapters(&self, age: Option<u8>) -> Vec<(&ChapterNumber, &ContentRating)> {
+        self.content_ratings
+            .iter()
+            .filter(|(_, rating)| !rating.allows(age))
+            .collect()
+    }
+
+    /// fails when the chapter is rated above the age of the student
+    pub fn check_age(&self, chapter_number: &ChapterNumber, age: Option<u8>) -> anyhow::Result<()> {
+        match self.content_ratings.get(chapter_number) {
+            Some(rating) if !rating.allows(age) => {
+                anyhow::bail!("Chapter {} is not available at your age", chapter_number)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// the chapter containing the original print `page`, if the book has page markers
+    pub fn resolve_page(&self, page: u32) -> Option<&Chapter> {
+        let (_, number) = self.page_map.range(..=page).next_back()?;
+        self.chapters.get(number)
+    }
+
+    pub fn get_stats(&self) -> BookStats {
+        BookStats {
+            total: self.stats.clone(),
+            chapters: self
+                .chapters
+                .iter()
+                .map(|(number, ch)| (number.clone(), ch.stats.clone()))
+                .collect(),
+        }
+    }
+
+    /// find a table, code block or figure by id, e.g. "Table 3.1"
+    pub fn find_block(&self, id: &str) -> Option<&ContentBlock> {
+        let id = normalize_block_id(id);
+        self.chapters
+            .values()
+            .flat_map(|ch| ch.blocks.iter())
+            .find(|block| block.id == id)
+    }
+
+    /// the chapters nested by their numbers, as shared with front-ends by `book_model`
+    pub fn toc(&self) -> Vec<TocNode> {
+        build_toc(self.chapters.values().map(|ch| ChapterMeta {
+            number: ch.number.clone(),
+            name: ch.name.clone(),
+        }))
+    }
+
+    /// find chapters whose title approximately matches `query`, best match first
+    pub fn find_chapters_by_title(&self, query: &str, limit: usize) -> Vec<ChapterMatch> {
+        let candidates = self.chapters.values().map(|ch| (ch.name.as_str(), ch));
+        fuzzy::rank(query, candidates, 0.2, limit)
+            .into_iter()
+            .map(|(ch, score)| ChapterMatch {
+                chapter_number: ch.number.clone(),
+                name: ch.name.clone(),
+                score,
+            })
+            .collect()
     }
 }
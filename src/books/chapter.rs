@@ -1,22 +1,29 @@
-use std::cmp::Ordering;
-use std::num::ParseIntError;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::path::PathBuf;
-use std::str::FromStr;
 
-use mdbook::book::{self, SectionNumber};
+pub use book_model::{ChapterNumber, ChapterNumberError};
+use mdbook::book;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
-use std::{
-    fmt::{self, Display, Formatter},
-    ops::{Deref, DerefMut},
-};
-use tracing::info;
+use tracing::{info, warn};
 use tree_iter::iter::TreeNode;
 use tree_iter::prelude::TreeNodeMut;
 use utoipa::ToSchema;
 
-use crate::ai_utils;
+use crate::{
+    ai_utils::{self, Provider},
+    generation_log,
+    prompts::{PromptName, PromptTemplates},
+};
+
+use super::{
+    blocks::{ContentBlock, extract_blocks},
+    preprocess::GenerationConfig,
+    sections::{Section, extract_sections, find_section},
+    stats::ContentStats,
+    text,
+};
 
 #[derive(Debug, Clone, Default, Serialize, Hash)]
 pub struct ChapterRaw {
@@ -35,6 +42,34 @@ pub struct ChapterPlan {
     pub summary: String,
 }
 
+/// score below which a chapter plan is regenerated, and flagged for review if still below
+pub const PLAN_REVIEW_THRESHOLD: f64 = 6.0;
+
+/// Critique of a chapter plan against the chapter content
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
+pub struct PlanQuality {
+    /// how much of the chapter's concepts the plan covers, from 0 to 10
+    pub coverage: u8,
+    /// how well the plan is ordered and structured for teaching, from 0 to 10
+    pub structure: u8,
+    /// what is missing or wrong in the plan
+    pub critique: String,
+    /// low score left after regeneration, waiting for a manager
+    #[serde(default)]
+    #[schemars(skip)]
+    pub needs_review: bool,
+}
+
+impl PlanQuality {
+    pub fn score(&self) -> f64 {
+        (self.coverage.min(10) as f64 + self.structure.min(10) as f64) / 2.0
+    }
+
+    pub fn is_low(&self) -> bool {
+        self.score() < PLAN_REVIEW_THRESHOLD
+    }
+}
+
 #[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct Chapter {
     pub name: String,
@@ -45,92 +80,189 @@ pub struct Chapter {
     pub content: String,
     #[serde(flatten)]
     pub chapter_plan: ChapterPlan,
+    pub stats: ContentStats,
+    #[serde(skip_serializing)]
+    #[schema(ignore)]
+    pub blocks: Vec<ContentBlock>,
+    #[serde(skip_serializing)]
+    #[schema(ignore)]
+    pub sections: Vec<Section>,
+}
+
+impl Chapter {
+    /// the section titled `title`, see [`find_section`]; the error lists the closest titles
+    pub fn find_section(&self, title: &str) -> anyhow::Result<&Section> {
+        find_section(&self.sections, title).map_err(|suggestions| {
+            if suggestions.is_empty() {
+                anyhow::anyhow!("Section not found in chapter {}: {}", self.number, title)
+            } else {
+                anyhow::anyhow!(
+                    "Section not found in chapter {}: {}, did you mean: {}",
+                    self.number,
+                    title,
+                    suggestions.join(" | ")
+                )
+            }
+        })
+    }
+
+    /// the markdown of the section, its heading included
+    pub fn section_text(&self, section: &Section) -> &str {
+        self.content.get(section.range.clone()).unwrap_or_default()
+    }
 }
 
 impl ChapterRaw {
-    pub async fn generate_chapter_plan(&self) -> anyhow::Result<ChapterPlan> {
+    /// the variables of the chapter in the prompts about it
+    fn prompt_context(&self, book_title: &str) -> minijinja::Value {
+        minijinja::context! {
+            book_title => book_title,
+            chapter_number => self.number.to_string(),
+            chapter_title => &self.name,
+        }
+    }
+
+    pub async fn generate_chapter_plan(
+        &self,
+        provider: &dyn Provider,
+        config: &GenerationConfig,
+        prompts: &PromptTemplates,
+        book_title: &str,
+    ) -> anyhow::Result<ChapterPlan> {
         info!(
             "generating chapter plan for chapter: {} {}",
             self.number, self.name
         );
-        let prompt = r#"Generate a teaching plan for the following chapter.
-Example:
-```
-# Chapter Plan for Chapter 3: Verb Tenses
-
-## Chapter Objectives
-- Understand how verb tenses express time in English.
-- Master the use of simple present, past, and future tenses.
-- Learn to apply progressive and perfect tenses correctly.
-
-## Teaching Outline
-1. **Introduction to Verb Tenses**:
-   - Explain what tenses are and why they matter.
-   - Introduce the three main time frames: past, present, and future.
-
-2. **Simple Tenses**:
-   - **Present Simple**: Teach its uses (habits, facts), structure, and examples.
-   - **Past Simple**: Cover regular/irregular verbs and common uses.
-   - **Future Simple**: Explain "will" vs. "going to" for predictions and plans.
-
-3. **Progressive Tenses**:
-   - **Present Progressive**: Focus on ongoing actions and temporary states.
-   - **Past Progressive**: Teach interrupted or simultaneous past actions.
-   - **Future Progressive**: Cover planned or predicted ongoing actions.
-
-4. **Perfect Tenses**:
-   - **Present Perfect**: Discuss completed actions affecting the present.
-   - **Past Perfect**: Explain actions finished before another past event.
-   - **Future Perfect**: Teach actions completed by a future point.
-
-## Activities and Methods
-- **Tailored Examples**: Use sentences relevant to the student's interests to explain tenses.
-- **Practice Exercises**: Provide worksheets with fill-in-the-blank and sentence rewriting tasks.
-- **Error Correction**: Work together to fix tense-related mistakes in sample sentences.
-- **End-of-Chapter Quiz**: Test the student's grasp of the chapter's concepts.
-
-## Next Steps
-- Assign homework to reinforce tense usage.
-- Prepare for the next chapter ("Subject-Verb Agreement") by linking it to tense knowledge.
-```"#;
-        let chapter_plan =
-            ai_utils::summarize(&self.content, 1000, Some(prompt.to_string())).await?;
-        let summary = ai_utils::summarize(&self.content, 100, None).await?;
+        let prompt = prompts.render(PromptName::ChapterPlan, self.prompt_context(book_title))?;
+        let chapter_plan = generation_log::artifact(
+            "chapter_plan",
+            ai_utils::summarize_consistent(
+                provider,
+                &self.content,
+                config.summarize.plan_length,
+                prompt,
+                &config.chapter_plan,
+                &config.summarize.chunking,
+            ),
+        )
+        .await?;
+        let summary = generation_log::artifact(
+            "chapter_summary",
+            ai_utils::summarize_consistent(
+                provider,
+                &self.content,
+                config.summarize.summary_length,
+                None,
+                &config.chapter_summary,
+                &config.summarize.chunking,
+            ),
+        )
+        .await?;
         Ok(ChapterPlan {
             plan: chapter_plan,
             summary,
         })
     }
 
+    pub async fn critique_chapter_plan(
+        &self,
+        provider: &dyn Provider,
+        plan: &ChapterPlan,
+        prompts: &PromptTemplates,
+        book_title: &str,
+    ) -> anyhow::Result<PlanQuality> {
+        info!(
+            "scoring chapter plan for chapter: {} {}",
+            self.number, self.name
+        );
+        let context = minijinja::context! {
+            chapter => &self.content,
+            plan => &plan.plan,
+            ..self.prompt_context(book_title)
+        };
+        let prompt = prompts
+            .render(PromptName::PlanCritique, context)?
+            .unwrap_or_default();
+        let mut quality: PlanQuality =
+            generation_log::artifact("plan_critique", ai_utils::extract(provider, prompt)).await?;
+        quality.needs_review = false;
+        Ok(quality)
+    }
+
+    /// generate a plan and score it, regenerate once if the score is low,
+    /// and flag the better plan for review if it is still low
+    pub async fn generate_scored_chapter_plan(
+        &self,
+        provider: &dyn Provider,
+        config: &GenerationConfig,
+        prompts: &PromptTemplates,
+        book_title: &str,
+    ) -> anyhow::Result<(ChapterPlan, PlanQuality)> {
+        let plan = self
+            .generate_chapter_plan(provider, config, prompts, book_title)
+            .await?;
+        let quality = self
+            .critique_chapter_plan(provider, &plan, prompts, book_title)
+            .await?;
+        if !quality.is_low() {
+            return Ok((plan, quality));
+        }
+        warn!(
+            "low chapter plan score {:.1} for chapter {}, regenerating",
+            quality.score(),
+            self.number
+        );
+        let retry = self
+            .generate_chapter_plan(provider, config, prompts, book_title)
+            .await?;
+        let retry_quality = self
+            .critique_chapter_plan(provider, &retry, prompts, book_title)
+            .await?;
+        let (plan, mut quality) = if retry_quality.score() > quality.score() {
+            (retry, retry_quality)
+        } else {
+            (plan, quality)
+        };
+        quality.needs_review = quality.is_low();
+        Ok((plan, quality))
+    }
+
+    /// hash of the chapter's own name and content, sub chapters excluded, to tell if its plan is stale
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.name.hash(&mut hasher);
+        self.content.hash(&mut hasher);
+        hasher.finish()
+    }
+
     pub fn to_chapter(&self, chapter_plan: ChapterPlan) -> Chapter {
+        let blocks = extract_blocks(&self.number, &self.content);
         Chapter {
             name: self.name.clone(),
             number: self.number.clone(),
             path: self.path.clone(),
             content: self.content.clone(),
             chapter_plan,
+            stats: ContentStats::compute(&self.content, &blocks),
+            blocks,
+            sections: extract_sections(&self.content),
         }
     }
 }
 
 impl ChapterRaw {
     pub fn get_toc_item(&self) -> String {
-        let indent = if let Some(i) = self.number.0.first() {
-            if [0, -1].contains(i) {
-                0
-            } else {
-                self.number.0.len() - 1
-            }
-        } else {
-            0
-        };
-        let indent = "  ".repeat(indent);
+        let indent = "  ".repeat(self.number.depth());
         let path = if let Some(path) = &self.path {
             path.to_str().unwrap_or("")
         } else {
             ""
         };
-        let mut s = format!("{indent}{} [{}]({path})  \n", self.number, self.name,);
+        let mut s = format!(
+            "{indent}{} [{}]({path})  \n",
+            self.number,
+            text::isolate(&self.name)
+        );
         for sub in &self.sub_chapters {
             s.push_str(&sub.get_toc_item());
         }
@@ -143,7 +275,13 @@ impl From<book::Chapter> for ChapterRaw {
         let mut chapter = ChapterRaw {
             name: ch.name,
             content: ch.content,
-            number: ch.number.unwrap_or_default().into(),
+            number: ch
+                .number
+                .unwrap_or_default()
+                .0
+                .into_iter()
+                .map(|x| x as i64)
+                .collect(),
             parent_names: ch.parent_names,
             path: ch.path,
             sub_chapters: vec![],
@@ -157,141 +295,135 @@ impl From<book::Chapter> for ChapterRaw {
     }
 }
 
-impl TreeNode for ChapterRaw {
-    fn children(&self) -> impl DoubleEndedIterator<Item = &Self> {
-        self.sub_chapters.iter()
-    }
-}
-
-impl TreeNodeMut for ChapterRaw {
-    fn children_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut Self> {
-        self.sub_chapters.iter_mut()
-    }
-}
-
-/// A section number like "1.2.3."
-#[derive(Debug, PartialEq, Clone, Default, Eq, Hash, ToSchema)]
-pub struct ChapterNumber(pub Vec<i64>);
-
-impl JsonSchema for ChapterNumber {
-    fn schema_name() -> String {
-        "ChapterNumber".to_string()
-    }
-    fn json_schema(generator: &mut schemars::r#gen::SchemaGenerator) -> schemars::schema::Schema {
-        // Create a schema for a string that represents a chapter number
-        // The schema should validate that the string is in the format "1.2.3."
-        let mut schema = String::json_schema(generator);
-        // Add description to explain the format
-        if let schemars::schema::Schema::Object(obj) = &mut schema {
-            obj.metadata = Some(Box::new(schemars::schema::Metadata {
-                description: Some("A chapter number in the format '1.2.3.' representing the hierarchical position in a book".to_string()),
-                ..Default::default()
-            }));
-
-            // Add pattern to validate the format (optional numbers separated by dots)
-            obj.string = Some(Box::new(schemars::schema::StringValidation {
-                pattern: Some(r"^(\d+\.)+$".to_string()),
-                ..Default::default()
-            }));
+/// Give every chapter without a SUMMARY number a stable synthetic one.
+///
+/// Top-level chapters before the first numbered chapter are front matter and get `0.1.`, `0.2.`, ...;
+/// the ones after it are back matter and get `-1.1.`, `-1.2.`, ...
+/// A book without any numbered chapter is treated as front matter only.
+/// Unnumbered sub chapters are numbered below their parent, after the numbered siblings.
+pub fn normalize_chapter_numbers(chapters: &mut [ChapterRaw]) {
+    let mut is_prefix = true;
+    let mut prefix_idx = 1;
+    let mut suffix_idx = 1;
+    for ch in chapters.iter_mut() {
+        if !ch.number.is_empty() {
+            is_prefix = false;
+        } else if is_prefix {
+            ch.number = ChapterNumber::from_iter(vec![0, prefix_idx]);
+            prefix_idx += 1;
+        } else {
+            ch.number = ChapterNumber::from_iter(vec![-1, suffix_idx]);
+            suffix_idx += 1;
         }
-        schema
+        normalize_sub_chapter_numbers(ch);
     }
 }
 
-impl Display for ChapterNumber {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        for item in &self.0 {
-            write!(f, "{item}.")?;
+fn normalize_sub_chapter_numbers(parent: &mut ChapterRaw) {
+    let mut next_idx = parent
+        .sub_chapters
+        .iter()
+        .filter_map(|ch| ch.number.last().copied())
+        .max()
+        .unwrap_or(0)
+        + 1;
+    for ch in parent.sub_chapters.iter_mut() {
+        if ch.number.is_empty() {
+            let mut number = parent.number.clone();
+            number.push(next_idx);
+            ch.number = number;
+            next_idx += 1;
         }
-        Ok(())
-    }
-}
-
-impl Serialize for ChapterNumber {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        serializer.serialize_str(&self.to_string())
+        normalize_sub_chapter_numbers(ch);
     }
 }
 
-impl<'de> Deserialize<'de> for ChapterNumber {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let s = String::deserialize(deserializer)?;
-        s.parse::<ChapterNumber>().map_err(serde::de::Error::custom)
-    }
-}
-
-impl FromStr for ChapterNumber {
-    type Err = ParseIntError;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let number: Result<Vec<i64>, Self::Err> =
-            s.split_terminator('.').map(|x| x.parse()).collect();
-        Ok(ChapterNumber(number?))
-    }
-}
-
-impl Deref for ChapterNumber {
-    type Target = Vec<i64>;
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl TreeNode for ChapterRaw {
+    fn children(&self) -> impl DoubleEndedIterator<Item = &Self> {
+        self.sub_chapters.iter()
     }
 }
 
-impl DerefMut for ChapterNumber {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+impl TreeNodeMut for ChapterRaw {
+    fn children_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut Self> {
+        self.sub_chapters.iter_mut()
     }
 }
 
-impl FromIterator<i64> for ChapterNumber {
-    fn from_iter<I: IntoIterator<Item = i64>>(it: I) -> Self {
-        ChapterNumber(it.into_iter().collect())
+#[cfg(test)]
+fn unnumbered(name: &str, sub_chapters: Vec<ChapterRaw>) -> ChapterRaw {
+    ChapterRaw {
+        name: name.to_string(),
+        sub_chapters,
+        ..Default::default()
     }
 }
 
-impl From<SectionNumber> for ChapterNumber {
-    fn from(number: SectionNumber) -> Self {
-        ChapterNumber(number.0.into_iter().map(|x| x as i64).collect())
+#[cfg(test)]
+fn numbered(name: &str, number: &str, sub_chapters: Vec<ChapterRaw>) -> ChapterRaw {
+    ChapterRaw {
+        name: name.to_string(),
+        number: number.parse().unwrap(),
+        sub_chapters,
+        ..Default::default()
     }
 }
 
-impl PartialOrd for ChapterNumber {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
+#[test]
+fn normalize_front_matter_only() {
+    let mut chapters = vec![
+        unnumbered("Preface", vec![]),
+        unnumbered("Intro", vec![unnumbered("Notes", vec![])]),
+    ];
+    normalize_chapter_numbers(&mut chapters);
+    assert_eq!(chapters[0].number.to_string(), "0.1.");
+    assert_eq!(chapters[1].number.to_string(), "0.2.");
+    assert_eq!(chapters[1].sub_chapters[0].number.to_string(), "0.2.1.");
+    assert_eq!(chapters[1].sub_chapters[0].number.depth(), 1);
+    let toc = chapters[1].get_toc_item();
+    assert_eq!(toc, "0.2. [Intro]()  \n  0.2.1. [Notes]()  \n");
 }
 
-impl Ord for ChapterNumber {
-    fn cmp(&self, other: &Self) -> Ordering {
-        // if self.0[0] == -1, it is a suffix chapter
-        match (self.0.first(), other.0.first()) {
-            (Some(n), Some(m)) => {
-                if (*n == -1) == (*m == -1) {
-                    self.0.cmp(&other.0)
-                } else if *n != -1 && *m == -1 {
-                    Ordering::Less
-                } else {
-                    Ordering::Greater
-                }
-            }
-            _ => self.0.cmp(&other.0),
-        }
-    }
+#[test]
+fn normalize_prefix_and_suffix() {
+    let mut chapters = vec![
+        unnumbered("Preface", vec![]),
+        numbered(
+            "One",
+            "1.",
+            vec![
+                numbered("One.One", "1.1.", vec![]),
+                unnumbered("Extra", vec![]),
+            ],
+        ),
+        unnumbered("Appendix", vec![]),
+        unnumbered("Glossary", vec![]),
+    ];
+    normalize_chapter_numbers(&mut chapters);
+    let numbers: Vec<String> = chapters.iter().map(|ch| ch.number.to_string()).collect();
+    assert_eq!(numbers, ["0.1.", "1.", "-1.1.", "-1.2."]);
+    assert_eq!(chapters[1].sub_chapters[1].number.to_string(), "1.2.");
+    let sorted: std::collections::BTreeSet<ChapterNumber> =
+        chapters.iter().map(|ch| ch.number.clone()).collect();
+    let sorted: Vec<String> = sorted.iter().map(|n| n.to_string()).collect();
+    assert_eq!(sorted, ["0.1.", "1.", "-1.1.", "-1.2."]);
+    assert_eq!(chapters[2].number.depth(), 0);
 }
 
 #[test]
-fn chapter_number_cmp() {
-    let mut set: std::collections::BTreeSet<ChapterNumber> = std::collections::BTreeSet::new();
-    set.insert("3.1.4".parse().unwrap());
-    set.insert("".parse().unwrap());
-    set.insert("2.3.2".parse().unwrap());
-    set.insert("-1.3.8.".parse().unwrap());
-    set.insert("5.4.1".parse().unwrap());
-    set.insert("4.7.6".parse().unwrap());
-    println!("{:?}", set);
+fn plan_quality_score() {
+    let quality = PlanQuality {
+        coverage: 4,
+        structure: 7,
+        critique: String::new(),
+        needs_review: false,
+    };
+    assert_eq!(quality.score(), 5.5);
+    assert!(quality.is_low());
+    // out of range scores from the model are clamped
+    let quality = PlanQuality {
+        coverage: 12,
+        ..quality
+    };
+    assert_eq!(quality.score(), 8.5);
 }
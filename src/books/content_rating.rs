@@ -0,0 +1,238 @@
+use std::collections::HashSet;
+
+use async_openai::types::{
+    ChatCompletionRequestToolMessage, ChatCompletionRequestToolMessageContent,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use time::Date;
+use tracing::info;
+use utoipa::ToSchema;
+
+use super::chapter::{ChapterNumber, ChapterRaw};
+use crate::ai_utils::{self, Provider};
+
+/// the ages a chapter can be rated for, the rating is rounded up to one of them
+const AGE_RATINGS: [u8; 4] = [0, 13, 16, 18];
+
+/// A kind of mature content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentWarning {
+    /// graphic violence or gore
+    Violence,
+    /// sexual content or nudity
+    Sexual,
+    /// drug, alcohol or tobacco use
+    Substances,
+    /// suicide or self-harm
+    SelfHarm,
+    /// strong language
+    Profanity,
+    /// other disturbing themes, e.g. abuse or torture
+    Disturbing,
+}
+
+/// The mature content of a chapter, rated on import
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema, ToSchema)]
+pub struct ContentRating {
+    /// the kinds of mature content in the chapter, empty when it suits every age
+    pub warnings: Vec<ContentWarning>,
+    /// the youngest age the chapter suits, 0, 13, 16 or 18
+    pub min_age: u8,
+}
+
+impl ContentRating {
+    /// whether a student of `age` may read the chapter, any student when the age is unknown
+    pub fn allows(&self, age: Option<u8>) -> bool {
+        age.is_none_or(|age| age >= self.min_age)
+    }
+}
+
+/// rate the mature content of the chapter with the model, sub chapters are rated on their own
+pub async fn rate_chapter(
+    provider: &dyn Provider,
+    chapter: &ChapterRaw,
+) -> anyhow::Result<ContentRating> {
+    info!(
+        "rating the content of chapter: {} {}",
+        chapter.number, chapter.name
+    );
+    let prompt = format!(
+        "Rate the following textbook chapter for young readers. List the kinds of mature content \
+        it shows or describes in detail, not those it merely names, and the youngest age it suits: \
+        0 for every age, 13, 16 or 18. Educational mentions, like a history chapter naming a war, \
+        suit every age.\n\n# Chapter {} {}\n{}",
+        chapter.number, chapter.name, chapter.content
+    );
+    let mut rating: ContentRating = ai_utils::extract(provider, prompt).await?;
+    rating.min_age = AGE_RATINGS
+        .into_iter()
+        .find(|age| *age >= rating.min_age)
+        .unwrap_or(18);
+    rating.warnings.dedup();
+    Ok(rating)
+}
+
+/// the age of someone born on `birth_date`, on `today`
+pub fn age_on(birth_date: Date, today: Date) -> u8 {
+    let mut age = today.year() - birth_date.year();
+    if (today.month() as u8, today.day()) < (birth_date.month() as u8, birth_date.day()) {
+        age -= 1;
+    }
+    age.clamp(0, u8::MAX as i32) as u8
+}
+
+/// Keeps the chapters rated above the age of a minor out of the teacher's tool calls
+pub struct AgeGate {
+    age: u8,
+    /// the book tool calls read when they name none
+    main: i64,
+    /// the restricted chapters of each book, as written in tool calls
+    restricted: HashSet<(i64, String)>,
+}
+
+impl AgeGate {
+    /// the gate for a student of `age` on the chapters of the books of a session, rated
+    /// `ratings` by book id, `None` when nothing is restricted
+    pub fn new<'a>(
+        age: Option<u8>,
+        main: i64,
+        ratings: impl IntoIterator<Item = (i64, &'a ChapterNumber, &'a ContentRating)>,
+    ) -> Option<Self> {
+        let age = age?;
+        let restricted: HashSet<(i64, String)> = ratings
+            .into_iter()
+            .filter(|(_, _, rating)| !rating.allows(Some(age)))
+            .map(|(book_id, number, _)| (book_id, number.to_string()))
+            .collect();
+        (!restricted.is_empty()).then_some(Self {
+            age,
+            main,
+            restricted,
+        })
+    }
+
+    fn is_restricted(&self, book_id: i64, chapter_number: &str) -> bool {
+        match chapter_number.parse::<ChapterNumber>() {
+            Ok(number) => self.restricted.contains(&(book_id, number.to_string())),
+            Err(_) => false,
+        }
+    }
+
+    /// the book a tool call reads, from its `book_id` argument or the main book
+    pub fn book_of(&self, arguments: &str) -> i64 {
+        serde_json::from_str::<Value>(arguments)
+            .ok()
+            .and_then(|arguments| arguments.get("book_id")?.as_i64())
+            .unwrap_or(self.main)
+    }
+
+    /// the restricted chapter a tool call reads, from its `chapter_number` argument or the
+    /// argument itself
+    pub fn check_call(&self, arguments: &str) -> Option<String> {
+        let book_id = self.book_of(arguments);
+        let arguments: Value = serde_json::from_str(arguments).ok()?;
+        let chapter_number = match &arguments {
+            Value::String(number) => number.as_str(),
+            Value::Object(fields) => fields.get("chapter_number")?.as_str()?,
+            _ => return None,
+        };
+        self.is_restricted(book_id, chapter_number)
+            .then(|| self.refusal(chapter_number))
+    }
+
+    /// the message returned to the model instead of a restricted chapter
+    pub fn refusal(&self, chapter_number: &str) -> String {
+        format!(
+            "Chapter {chapter_number} is not available to this student, it is rated above their age of {}. \
+            Do not discuss its content: tell the student kindly that it isn't available to them and \
+            suggest another chapter.",
+            self.age
+        )
+    }
+
+    /// drop the restricted chapters from the result of a tool: the items of a list, or the whole
+    /// result for a single one, of a call on `book_id`
+    pub fn filter_result(&self, book_id: i64, message: &mut ChatCompletionRequestToolMessage) {
+        let ChatCompletionRequestToolMessageContent::Text(text) = &mut message.content else {
+            return;
+        };
+        let Ok(mut result) = serde_json::from_str::<Value>(text) else {
+            return;
+        };
+        let chapter_of = |value: &Value| {
+            value
+                .get("chapter_number")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        };
+        match &mut result {
+            Value::Array(items) => {
+                let count = items.len();
+                items.retain(|item| {
+                    chapter_of(item).is_none_or(|number| !self.is_restricted(book_id, &number))
+                });
+                if items.len() == count {
+                    return;
+                }
+            }
+            value => match chapter_of(value) {
+                Some(number) if self.is_restricted(book_id, &number) => {
+                    *text = self.refusal(&number);
+                    return;
+                }
+                _ => return,
+            },
+        }
+        *text = result.to_string();
+    }
+}
+
+#[test]
+fn age_gate() {
+    let ratings = [
+        (
+            "1.".parse::<ChapterNumber>().unwrap(),
+            ContentRating::default(),
+        ),
+        (
+            "2.1.".parse().unwrap(),
+            ContentRating {
+                warnings: vec![ContentWarning::Violence],
+                min_age: 16,
+            },
+        ),
+    ];
+    let ratings = ratings.iter().map(|(number, rating)| (1, number, rating));
+    assert!(AgeGate::new(None, 1, ratings.clone()).is_none());
+    assert!(AgeGate::new(Some(16), 1, ratings.clone()).is_none());
+    let gate = AgeGate::new(Some(12), 1, ratings).unwrap();
+    assert!(gate.check_call(r#""2.1.""#).is_some());
+    assert!(
+        gate.check_call(r#"{"chapter_number": "2.1", "sector_title": null}"#)
+            .is_some()
+    );
+    assert!(gate.check_call(r#"{"chapter_number": "1."}"#).is_none());
+    assert!(
+        gate.check_call(r#"{"book_id": 2, "chapter_number": "2.1."}"#)
+            .is_none()
+    );
+    assert_eq!(gate.book_of(r#"{"book_id": 2, "query": "a"}"#), 2);
+    let mut message = ChatCompletionRequestToolMessage {
+        content: ChatCompletionRequestToolMessageContent::Text(
+            r#"[{"chapter_number": "1.", "content": "a"}, {"chapter_number": "2.1.", "content": "b"}]"#
+                .to_string(),
+        ),
+        tool_call_id: "call".to_string(),
+    };
+    gate.filter_result(1, &mut message);
+    let ChatCompletionRequestToolMessageContent::Text(text) = &message.content else {
+        unreachable!();
+    };
+    assert!(!text.contains("2.1."));
+    let birth = time::macros::date!(2010 - 06 - 15);
+    assert_eq!(age_on(birth, time::macros::date!(2026 - 06 - 14)), 15);
+    assert_eq!(age_on(birth, time::macros::date!(2026 - 06 - 15)), 16);
+}
@@ -0,0 +1,193 @@
+use std::path::{Component, Path, PathBuf};
+
+use image::{ImageFormat, imageops::FilterType};
+use pulldown_cmark::{Event, Parser, Tag};
+use serde::Deserialize;
+use utoipa::ToSchema;
+use walkdir::WalkDir;
+
+use super::book::Book;
+
+/// directory of the bookbase holding the thumbnails of the covers, derived from the books and
+/// regenerated on demand
+pub const COVERS_DIR: &str = ".covers";
+
+/// image formats accepted as book covers with their content type, svg covers are served as is
+/// without thumbnails
+const COVER_TYPES: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("webp", "image/webp"),
+    ("gif", "image/gif"),
+    ("svg", "image/svg+xml"),
+];
+
+/// the content type of a cover image, `None` for other files
+pub fn cover_content_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    COVER_TYPES
+        .iter()
+        .find(|(ext, _)| *ext == extension)
+        .map(|(_, content_type)| *content_type)
+}
+
+/// The size of a served cover, thumbnails keep the aspect ratio and are never upscaled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CoverSize {
+    /// 160 pixels wide, for lists
+    Small,
+    /// 320 pixels wide, for catalog cards
+    #[default]
+    Medium,
+    /// 640 pixels wide, for book pages
+    Large,
+    /// the image as found in the book
+    Original,
+}
+
+impl CoverSize {
+    fn width(self) -> Option<u32> {
+        match self {
+            Self::Small => Some(160),
+            Self::Medium => Some(320),
+            Self::Large => Some(640),
+            Self::Original => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Small => "small",
+            Self::Medium => "medium",
+            Self::Large => "large",
+            Self::Original => "original",
+        }
+    }
+}
+
+fn is_cover_image(path: &Path) -> bool {
+    cover_content_type(path).is_some()
+}
+
+/// `path` relative to `root` with forward slashes, `None` when it leaves `root`
+fn relative_to(root: &Path, path: &Path) -> Option<String> {
+    let mut resolved = PathBuf::new();
+    for component in path.strip_prefix(root).ok()?.components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(resolved.to_str()?.replace('\\', "/"))
+}
+
+/// the first image of the chapters in their order, e.g. the cover page of a converted EPUB
+fn first_chapter_image(book_dir: &Path, src_dir: &Path, book: &Book) -> Option<String> {
+    for chapter in book.chapters.values() {
+        let Some(chapter_path) = &chapter.path else {
+            continue;
+        };
+        let chapter_dir = src_dir.join(chapter_path.parent().unwrap_or(Path::new("")));
+        for event in Parser::new(&chapter.content) {
+            let Event::Start(Tag::Image { dest_url, .. }) = event else {
+                continue;
+            };
+            if dest_url.contains("://") || dest_url.starts_with("data:") {
+                continue;
+            }
+            let target = chapter_dir.join(dest_url.split(['#', '?']).next().unwrap_or_default());
+            if !is_cover_image(&target) {
+                continue;
+            }
+            let Some(relative) = relative_to(book_dir, &target) else {
+                continue;
+            };
+            if book_dir.join(&relative).is_file() {
+                return Some(relative);
+            }
+        }
+    }
+    None
+}
+
+/// the cover image of the book in `book_dir`, relative to it: an image named like "cover", the
+/// shallowest first, or else the first image of the chapters
+pub fn detect_cover(book_dir: &Path, book: &Book) -> anyhow::Result<Option<String>> {
+    let named = WalkDir::new(book_dir)
+        .sort_by_file_name()
+        .into_iter()
+        // skips the build output of mdbook
+        .filter_entry(|entry| !(entry.depth() == 1 && entry.file_name() == "book"))
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file() && is_cover_image(entry.path()))
+        .filter(|entry| {
+            entry
+                .path()
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .is_some_and(|stem| stem.to_lowercase().contains("cover"))
+        })
+        .min_by_key(|entry| entry.depth());
+    if let Some(entry) = named {
+        return Ok(relative_to(book_dir, entry.path()));
+    }
+    let book_toml = std::fs::read_to_string(book_dir.join("book.toml"))?;
+    let src_dir = book_dir.join(
+        toml::from_str::<mdbook::config::Config>(&book_toml)?
+            .book
+            .src,
+    );
+    Ok(first_chapter_image(book_dir, &src_dir, book))
+}
+
+/// the thumbnail of `cover` at `size` in `thumbnails_dir`, generated when missing; the cover
+/// itself for svg images and the original size
+pub fn thumbnail(cover: &Path, thumbnails_dir: &Path, size: CoverSize) -> anyhow::Result<PathBuf> {
+    let Some(width) = size.width() else {
+        return Ok(cover.to_path_buf());
+    };
+    if cover_content_type(cover).is_none_or(|content_type| content_type == "image/svg+xml") {
+        return Ok(cover.to_path_buf());
+    }
+    let target = thumbnails_dir.join(format!("{}.jpg", size.name()));
+    if target.is_file() {
+        return Ok(target);
+    }
+    let image = image::open(cover)?;
+    let image = if image.width() > width {
+        image.resize(width, u32::MAX, FilterType::Lanczos3)
+    } else {
+        image
+    };
+    std::fs::create_dir_all(thumbnails_dir)?;
+    // written aside and renamed, so a concurrent request never serves half a file
+    let temp = tempfile::NamedTempFile::new_in(thumbnails_dir)?;
+    image
+        .to_rgb8()
+        .save_with_format(temp.path(), ImageFormat::Jpeg)?;
+    temp.persist(&target)?;
+    Ok(target)
+}
+
+#[test]
+fn relative_paths() {
+    let root = Path::new("/bookbase/book_1");
+    assert_eq!(
+        relative_to(root, &root.join("src/chapter_1/../images/cover.png")).as_deref(),
+        Some("src/images/cover.png")
+    );
+    assert_eq!(
+        relative_to(root, &root.join("src/../../book_2/cover.png")),
+        None
+    );
+    assert!(is_cover_image(Path::new("Cover.JPG")));
+    assert!(!is_cover_image(Path::new("cover.md")));
+}
@@ -0,0 +1,505 @@
+use std::{
+    future::Future,
+    path::{Component, Path},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use async_openai::{
+    tools::ToolDyn,
+    types::{ChatCompletionTool, ChatCompletionToolType, FunctionObject},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+
+use crate::{
+    teacher::catalog::{builtin_tool_texts, is_valid_function_name},
+    utils::{PublicResolver, is_local_host},
+};
+
+/// the file of a book declaring its tools, next to `book.toml`
+pub const FILE_NAME: &str = "tools.toml";
+/// the tool running the tests of the exercises of a book, see [`ExerciseSpec`]
+pub const RUN_TESTS_TOOL: &str = "RunTests";
+/// longest response of an endpoint given to the model, in characters
+const MAX_RESPONSE_CHARS: usize = 8000;
+/// bytes of a response read at most, enough for [`MAX_RESPONSE_CHARS`] characters
+const MAX_RESPONSE_BYTES: usize = MAX_RESPONSE_CHARS * 4;
+/// the environment variables a tool may read its secret from, the other variables of the
+/// server, e.g. `OPENAI_API_KEY`, are never sent to the endpoint of a book
+pub const SECRET_ENV_PREFIX: &str = "BOOK_TOOL_";
+const MAX_TIMEOUT_SECS: u64 = 60;
+const MAX_FUEL: u64 = 1_000_000_000;
+const MAX_MEMORY_MB: u32 = 256;
+/// largest test suite of an exercise, in bytes
+pub const MAX_TESTS_BYTES: u64 = 1024 * 1024;
+
+/// The tools a book declares in its `tools.toml`, next to its `book.toml`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct BookTools {
+    /// `[[tool]]` tables, backed by HTTP endpoints
+    #[serde(default, rename(deserialize = "tool"))]
+    pub tools: Vec<CustomToolSpec>,
+    /// `[[plugin]]` tables, WASM components run in a sandbox, see `wit/plugin.wit`
+    #[serde(default, rename(deserialize = "plugin"))]
+    pub plugins: Vec<PluginSpec>,
+    /// `[[exercise]]` tables, programming exercises with a test suite
+    #[serde(default, rename(deserialize = "exercise"))]
+    pub exercises: Vec<ExerciseSpec>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum HttpMethod {
+    /// the arguments are sent as query parameters
+    Get,
+    /// the arguments are sent as a JSON body
+    #[default]
+    Post,
+}
+
+/// How a tool authenticates to its endpoint, the secrets are read from the environment of the
+/// server so they stay out of the book, from variables starting with [`SECRET_ENV_PREFIX`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ToolAuth {
+    /// `Authorization: Bearer <token>`
+    Bearer { token_env: String },
+    /// a header of its own, e.g. `X-Api-Key`
+    Header { name: String, value_env: String },
+}
+
+/// A tool declared by a book, backed by an HTTP endpoint
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CustomToolSpec {
+    /// the function name shown to the model
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments, an object
+    #[serde(default = "empty_object")]
+    #[schema(value_type = Object)]
+    pub parameters: Value,
+    /// the http(s) url the arguments are sent to
+    pub endpoint: String,
+    #[serde(default)]
+    pub method: HttpMethod,
+    #[serde(default)]
+    pub auth: Option<ToolAuth>,
+    #[serde(default = "default_timeout")]
+    pub timeout_secs: u64,
+}
+
+/// A host function a plugin may import, none are granted by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// write to the server log
+    Log,
+    /// read the current time
+    Clock,
+}
+
+/// A tool declared by a book, implemented by a WASM component
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PluginSpec {
+    /// the component file, relative to the book directory
+    pub path: String,
+    /// the host functions the plugin may import, it fails to load when it imports others
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+    /// fuel of a call, about the number of WASM instructions it may run
+    #[serde(default = "default_fuel")]
+    pub fuel: u64,
+    /// memory the plugin may grow to, in MiB
+    #[serde(default = "default_memory_mb")]
+    pub memory_mb: u32,
+}
+
+/// A programming exercise of a book: the student's solution is checked by running the test
+/// suite of the book on it, with a test runner, a WASM component implementing the
+/// `test-runner` interface of `wit/plugin.wit`, e.g. an interpreter of the language of the book
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ExerciseSpec {
+    /// how the teacher names the exercise, e.g. "fizzbuzz"
+    pub id: String,
+    pub title: String,
+    /// the chapter of the exercise, e.g. "3.2."
+    #[serde(default)]
+    pub chapter: Option<String>,
+    /// the test suite, relative to the book directory
+    pub tests: String,
+    /// the test runner component, relative to the book directory
+    pub runner: String,
+    /// fuel of a run, about the number of WASM instructions it may run
+    #[serde(default = "default_runner_fuel")]
+    pub fuel: u64,
+    /// memory the runner may grow to, in MiB
+    #[serde(default = "default_runner_memory_mb")]
+    pub memory_mb: u32,
+}
+
+fn default_fuel() -> u64 {
+    100_000_000
+}
+
+fn default_memory_mb() -> u32 {
+    32
+}
+
+/// interpreters need more than plugins to run a test suite
+fn default_runner_fuel() -> u64 {
+    500_000_000
+}
+
+fn default_runner_memory_mb() -> u32 {
+    64
+}
+
+/// checks a file declared by a book stays inside the book directory
+fn check_book_path(path: &str, kind: &str) -> anyhow::Result<()> {
+    if !Path::new(path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        anyhow::bail!("{kind} path {path} must be relative to the book and stay inside it");
+    }
+    Ok(())
+}
+
+/// checks a WASM component declared by a book and the limits of one of its calls
+fn check_component(path: &str, kind: &str, fuel: u64, memory_mb: u32) -> anyhow::Result<()> {
+    check_book_path(path, kind)?;
+    if Path::new(path)
+        .extension()
+        .is_none_or(|extension| extension != "wasm")
+    {
+        anyhow::bail!("{kind} {path} is not a .wasm file");
+    }
+    if !(1..=MAX_FUEL).contains(&fuel) {
+        anyhow::bail!("Fuel of {kind} {path} must be between 1 and {MAX_FUEL}");
+    }
+    if !(1..=MAX_MEMORY_MB).contains(&memory_mb) {
+        anyhow::bail!("Memory of {kind} {path} must be between 1 and {MAX_MEMORY_MB} MiB");
+    }
+    Ok(())
+}
+
+impl PluginSpec {
+    fn validate(&self) -> anyhow::Result<()> {
+        check_component(&self.path, "Plugin", self.fuel, self.memory_mb)
+    }
+}
+
+impl ExerciseSpec {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.id.is_empty()
+            || self.id.len() > 64
+            || !self
+                .id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+        {
+            anyhow::bail!(
+                "Invalid exercise id {:?}, use up to 64 letters, digits, '_', '-' or '.'",
+                self.id
+            );
+        }
+        check_book_path(&self.tests, "Test suite")?;
+        check_component(&self.runner, "Test runner", self.fuel, self.memory_mb)
+    }
+}
+
+fn empty_object() -> Value {
+    serde_json::json!({ "type": "object", "properties": {} })
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+impl ToolAuth {
+    /// the environment variable holding the secret
+    fn env(&self) -> &str {
+        match self {
+            ToolAuth::Bearer { token_env } => token_env,
+            ToolAuth::Header { value_env, .. } => value_env,
+        }
+    }
+}
+
+impl CustomToolSpec {
+    fn validate(&self) -> anyhow::Result<()> {
+        check_tool_name(&self.name)?;
+        let url = reqwest::Url::parse(&self.endpoint)
+            .with_context(|| format!("Invalid endpoint of tool {}", self.name))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            anyhow::bail!("Endpoint of tool {} must be http or https", self.name);
+        }
+        if is_local_host(&url) {
+            anyhow::bail!("Endpoint of tool {} must be on a public host", self.name);
+        }
+        if self
+            .auth
+            .as_ref()
+            .is_some_and(|auth| !auth.env().starts_with(SECRET_ENV_PREFIX))
+        {
+            anyhow::bail!(
+                "The secret of tool {} must be in a variable starting with {}",
+                self.name,
+                SECRET_ENV_PREFIX
+            );
+        }
+        if self.parameters.get("type").and_then(Value::as_str) != Some("object") {
+            anyhow::bail!("Parameters of tool {} must be an object schema", self.name);
+        }
+        if !(1..=MAX_TIMEOUT_SECS).contains(&self.timeout_secs) {
+            anyhow::bail!(
+                "Timeout of tool {} must be between 1 and {} seconds",
+                self.name,
+                MAX_TIMEOUT_SECS
+            );
+        }
+        Ok(())
+    }
+}
+
+/// the tools declared in a `tools.toml`, checked; the names of plugins are only known once
+/// they are loaded
+pub fn parse(content: &str) -> anyhow::Result<BookTools> {
+    let file: BookTools = toml::from_str(content).context("Invalid tools.toml")?;
+    let mut names = std::collections::HashSet::new();
+    for tool in &file.tools {
+        tool.validate()?;
+        if !names.insert(tool.name.as_str()) {
+            anyhow::bail!("Tool {} is declared twice", tool.name);
+        }
+    }
+    for plugin in &file.plugins {
+        plugin.validate()?;
+    }
+    let mut ids = std::collections::HashSet::new();
+    for exercise in &file.exercises {
+        exercise.validate()?;
+        if !ids.insert(exercise.id.as_str()) {
+            anyhow::bail!("Exercise {} is declared twice", exercise.id);
+        }
+    }
+    Ok(file)
+}
+
+/// the tools declared by the book at `book_dir`, none without a `tools.toml`
+pub fn load(book_dir: &Path) -> anyhow::Result<BookTools> {
+    let tools = match std::fs::read_to_string(book_dir.join(FILE_NAME)) {
+        Ok(content) => parse(&content)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BookTools::default()),
+        Err(e) => return Err(e.into()),
+    };
+    for plugin in &tools.plugins {
+        if !book_dir.join(&plugin.path).is_file() {
+            anyhow::bail!("Plugin not found: {}", plugin.path);
+        }
+    }
+    for exercise in &tools.exercises {
+        if !book_dir.join(&exercise.runner).is_file() {
+            anyhow::bail!("Test runner not found: {}", exercise.runner);
+        }
+        let tests = std::fs::metadata(book_dir.join(&exercise.tests))
+            .with_context(|| format!("Test suite not found: {}", exercise.tests))?;
+        if tests.len() > MAX_TESTS_BYTES {
+            anyhow::bail!(
+                "Test suite {} is larger than {} KiB",
+                exercise.tests,
+                MAX_TESTS_BYTES / 1024
+            );
+        }
+    }
+    Ok(tools)
+}
+
+/// checks a tool name chosen by a book, see [`is_valid_function_name`]
+pub fn check_tool_name(name: &str) -> anyhow::Result<()> {
+    if !is_valid_function_name(name) {
+        anyhow::bail!(
+            "Invalid tool name {:?}, use up to 64 letters, digits, '_' or '-'",
+            name
+        );
+    }
+    if name == RUN_TESTS_TOOL
+        || builtin_tool_texts()
+            .iter()
+            .any(|text| text.tool_name == name)
+    {
+        anyhow::bail!("Tool {} is a built-in tool", name);
+    }
+    Ok(())
+}
+
+/// A custom tool registered for the sessions on its book
+pub struct HttpTool {
+    spec: CustomToolSpec,
+    client: reqwest::Client,
+}
+
+impl HttpTool {
+    pub fn new(spec: CustomToolSpec) -> anyhow::Result<Self> {
+        // the endpoint was checked by name, redirects and names resolving to local addresses
+        // could still reach the server's network
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(spec.timeout_secs))
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(PublicResolver))
+            .build()?;
+        Ok(Self { spec, client })
+    }
+
+    async fn request(&self, arguments: &str) -> anyhow::Result<String> {
+        let arguments: Value = if arguments.trim().is_empty() {
+            empty_object()
+        } else {
+            serde_json::from_str(arguments)?
+        };
+        let mut request = match self.spec.method {
+            HttpMethod::Get => {
+                let query: Vec<(String, String)> = arguments
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .map(|(key, value)| {
+                        let value = match value {
+                            Value::String(s) => s.clone(),
+                            value => value.to_string(),
+                        };
+                        (key.clone(), value)
+                    })
+                    .collect();
+                self.client.get(&self.spec.endpoint).query(&query)
+            }
+            HttpMethod::Post => self.client.post(&self.spec.endpoint).json(&arguments),
+        };
+        let secret = |var: &str| {
+            if !var.starts_with(SECRET_ENV_PREFIX) {
+                anyhow::bail!("{} can't be read by the tool", var);
+            }
+            std::env::var(var).with_context(|| format!("{} is not set for the tool", var))
+        };
+        request = match &self.spec.auth {
+            Some(ToolAuth::Bearer { token_env }) => request.bearer_auth(secret(token_env)?),
+            Some(ToolAuth::Header { name, value_env }) => {
+                request.header(name.as_str(), secret(value_env)?)
+            }
+            None => request,
+        };
+        let mut response = request.send().await?.error_for_status()?;
+        let mut body = Vec::new();
+        while body.len() < MAX_RESPONSE_BYTES {
+            let Some(chunk) = response.chunk().await? else {
+                break;
+            };
+            body.extend_from_slice(&chunk);
+        }
+        body.truncate(MAX_RESPONSE_BYTES);
+        let text = String::from_utf8_lossy(&body);
+        Ok(text.chars().take(MAX_RESPONSE_CHARS).collect())
+    }
+}
+
+impl ToolDyn for HttpTool {
+    fn definition(&self) -> ChatCompletionTool {
+        ChatCompletionTool {
+            r#type: ChatCompletionToolType::Function,
+            function: FunctionObject {
+                name: self.spec.name.clone(),
+                description: Some(self.spec.description.clone()),
+                parameters: Some(self.spec.parameters.clone()),
+                strict: None,
+            },
+        }
+    }
+
+    fn call(
+        &self,
+        arguments: String,
+    ) -> Pin<Box<dyn Future<Output = Result<String, String>> + Send + '_>> {
+        Box::pin(async move {
+            self.request(&arguments)
+                .await
+                .map_err(|e| format!("Tool {} failed: {e}", self.spec.name))
+        })
+    }
+}
+
+#[test]
+fn parse_tools() {
+    let file = parse(
+        r#"
+        [[tool]]
+        name = "PeriodicTable"
+        description = "Look up an element by symbol"
+        endpoint = "https://chem.example.com/elements"
+        method = "GET"
+        auth = { type = "bearer", token_env = "BOOK_TOOL_CHEM_TOKEN" }
+        [tool.parameters]
+        type = "object"
+        required = ["symbol"]
+        properties.symbol = { type = "string" }
+
+        [[plugin]]
+        path = "plugins/balance.wasm"
+        capabilities = ["log"]
+
+        [[exercise]]
+        id = "fizzbuzz"
+        title = "FizzBuzz"
+        chapter = "3.2."
+        tests = "exercises/fizzbuzz_test.py"
+        runner = "runners/python.wasm"
+        "#,
+    )
+    .unwrap();
+    let tools = &file.tools;
+    assert_eq!(tools.len(), 1);
+    assert_eq!(tools[0].method, HttpMethod::Get);
+    assert_eq!(tools[0].timeout_secs, 10);
+    assert_eq!(
+        tools[0].parameters["properties"]["symbol"]["type"],
+        "string"
+    );
+    assert_eq!(file.plugins[0].capabilities, [Capability::Log]);
+    assert_eq!(file.plugins[0].memory_mb, 32);
+    let tool = |name: &str, endpoint: &str| {
+        format!("[[tool]]\nname = \"{name}\"\ndescription = \"d\"\nendpoint = \"{endpoint}\"\n")
+    };
+    assert!(parse(&tool("BookJump", "https://a.example")).is_err());
+    assert!(parse(&tool("bad name", "https://a.example")).is_err());
+    assert!(parse(&tool("Lookup", "file:///etc/passwd")).is_err());
+    assert!(parse(&tool("Lookup", "http://169.254.169.254/latest/meta-data")).is_err());
+    assert!(parse(&tool("Lookup", "http://localhost:8080")).is_err());
+    let secret = |env: &str| {
+        format!(
+            "{}auth = {{ type = \"bearer\", token_env = \"{env}\" }}\n",
+            tool("Lookup", "https://a.example")
+        )
+    };
+    assert!(parse(&secret("BOOK_TOOL_LOOKUP")).is_ok());
+    assert!(parse(&secret("OPENAI_API_KEY")).is_err());
+    assert!(parse(&tool("Lookup", "https://a.example").repeat(2)).is_err());
+    let plugin = |path: &str| format!("[[plugin]]\npath = \"{path}\"\n");
+    assert!(parse(&plugin("../other_book/tool.wasm")).is_err());
+    assert!(parse(&plugin("/usr/lib/tool.wasm")).is_err());
+    assert!(parse(&plugin("plugins/tool.so")).is_err());
+    assert_eq!(file.exercises[0].memory_mb, 64);
+    assert!(parse(&tool("RunTests", "https://a.example")).is_err());
+    let exercise = |id: &str, tests: &str| {
+        format!(
+            "[[exercise]]\nid = \"{id}\"\ntitle = \"t\"\ntests = \"{tests}\"\nrunner = \"r.wasm\"\n"
+        )
+    };
+    assert!(parse(&exercise("fizz buzz", "tests.py")).is_err());
+    assert!(parse(&exercise("fizzbuzz", "../tests.py")).is_err());
+    assert!(parse(&exercise("fizzbuzz", "tests.py").repeat(2)).is_err());
+    let empty = parse("").unwrap();
+    assert!(empty.tools.is_empty() && empty.plugins.is_empty() && empty.exercises.is_empty());
+}
@@ -0,0 +1,369 @@
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::Value;
+use utoipa::ToSchema;
+use walkdir::WalkDir;
+
+use super::assets;
+
+/// the extensions of the datasets a book ships in its `src` directory
+pub const EXTENSIONS: &[&str] = &["csv", "tsv", "jsonl"];
+/// largest dataset previewed, larger files are still served as assets
+pub const MAX_DATASET_BYTES: u64 = 20 * 1024 * 1024;
+pub const DEFAULT_PREVIEW_ROWS: usize = 10;
+pub const MAX_PREVIEW_ROWS: usize = 50;
+/// most columns described, wider datasets are cut
+const MAX_COLUMNS: usize = 100;
+/// longest cell of the head rows and examples, in characters
+const MAX_CELL_CHARS: usize = 200;
+/// cells read as missing values, besides empty ones
+const NULLS: &[&str] = &["na", "n/a", "null", "none", "nan"];
+
+/// A dataset of a book
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DatasetInfo {
+    /// relative to the book `src` directory, with forward slashes, e.g. "data/titanic.csv"
+    pub path: String,
+    pub bytes: u64,
+}
+
+/// The type of the values of a column, the narrowest one all its values fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    /// only missing values
+    Empty,
+    Boolean,
+    Integer,
+    Float,
+    Text,
+}
+
+impl ColumnType {
+    fn of_cell(cell: &str) -> Self {
+        let cell = cell.trim();
+        if cell.is_empty() || NULLS.contains(&cell.to_lowercase().as_str()) {
+            ColumnType::Empty
+        } else if cell.parse::<i64>().is_ok() {
+            ColumnType::Integer
+        } else if cell.parse::<f64>().is_ok() {
+            ColumnType::Float
+        } else if cell.eq_ignore_ascii_case("true") || cell.eq_ignore_ascii_case("false") {
+            ColumnType::Boolean
+        } else {
+            ColumnType::Text
+        }
+    }
+
+    fn of_json(value: &Value) -> Self {
+        match value {
+            Value::Null => ColumnType::Empty,
+            Value::Bool(_) => ColumnType::Boolean,
+            Value::Number(number) if number.is_i64() || number.is_u64() => ColumnType::Integer,
+            Value::Number(_) => ColumnType::Float,
+            _ => ColumnType::Text,
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        use ColumnType::*;
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Empty, t) | (t, Empty) => t,
+            (Integer, Float) | (Float, Integer) => Float,
+            _ => Text,
+        }
+    }
+}
+
+/// A column of a dataset, described from all its rows
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Column {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub r#type: ColumnType,
+    /// rows missing a value
+    pub nulls: usize,
+    /// the first value of the column
+    pub example: Option<String>,
+}
+
+/// The schema and first rows of a dataset
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DatasetPreview {
+    pub path: String,
+    pub columns: Vec<Column>,
+    /// the first rows, a cell per column, missing values empty
+    pub head: Vec<Vec<String>>,
+    pub row_count: usize,
+    /// the dataset has more columns than described
+    pub columns_truncated: bool,
+}
+
+/// collects the columns and head of a dataset row by row
+struct Builder {
+    columns: Vec<Column>,
+    head: Vec<Vec<String>>,
+    rows: usize,
+    row_count: usize,
+    columns_truncated: bool,
+}
+
+fn cell_text(cell: &str) -> String {
+    match cell.char_indices().nth(MAX_CELL_CHARS) {
+        Some((end, _)) => format!("{}…", &cell[..end]),
+        None => cell.to_string(),
+    }
+}
+
+impl Builder {
+    fn new(rows: usize) -> Self {
+        Self {
+            columns: Vec::new(),
+            head: Vec::new(),
+            rows,
+            row_count: 0,
+            columns_truncated: false,
+        }
+    }
+
+    /// the index of the column, added when it's new; `None` beyond [`MAX_COLUMNS`]
+    fn column(&mut self, name: &str) -> Option<usize> {
+        if let Some(index) = self.columns.iter().position(|column| column.name == name) {
+            return Some(index);
+        }
+        if self.columns.len() == MAX_COLUMNS {
+            self.columns_truncated = true;
+            return None;
+        }
+        self.columns.push(Column {
+            name: name.to_string(),
+            r#type: ColumnType::Empty,
+            // the rows before it didn't have it
+            nulls: self.row_count,
+            example: None,
+        });
+        Some(self.columns.len() - 1)
+    }
+
+    /// a row as the cells of the known columns with their types
+    fn push(&mut self, cells: Vec<(usize, String, ColumnType)>) {
+        let mut row = vec![String::new(); self.columns.len()];
+        let mut seen = vec![false; self.columns.len()];
+        for (index, text, r#type) in cells {
+            let column = &mut self.columns[index];
+            column.r#type = column.r#type.merge(r#type);
+            if r#type == ColumnType::Empty {
+                continue;
+            }
+            seen[index] = true;
+            if column.example.is_none() {
+                column.example = Some(cell_text(&text));
+            }
+            row[index] = cell_text(&text);
+        }
+        for (column, seen) in self.columns.iter_mut().zip(seen) {
+            if !seen {
+                column.nulls += 1;
+            }
+        }
+        if self.head.len() < self.rows {
+            self.head.push(row);
+        }
+        self.row_count += 1;
+    }
+
+    fn finish(mut self, path: &str) -> DatasetPreview {
+        // rows read before a column of JSON lines appeared lack its cell
+        let width = self.columns.len();
+        for row in &mut self.head {
+            row.resize(width, String::new());
+        }
+        DatasetPreview {
+            path: path.to_string(),
+            columns: self.columns,
+            head: self.head,
+            row_count: self.row_count,
+            columns_truncated: self.columns_truncated,
+        }
+    }
+}
+
+fn preview_delimited(file: &Path, delimiter: u8, builder: &mut Builder) -> anyhow::Result<()> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_path(file)?;
+    let headers = reader.headers()?.clone();
+    let indices: Vec<Option<usize>> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, name)| match name.trim() {
+            "" => builder.column(&format!("column_{}", i + 1)),
+            name => builder.column(name),
+        })
+        .collect();
+    for record in reader.records() {
+        let record = record?;
+        let cells = record
+            .iter()
+            .zip(&indices)
+            .filter_map(|(cell, index)| Some(((*index)?, cell)))
+            .map(|(index, cell)| (index, cell.trim().to_string(), ColumnType::of_cell(cell)))
+            .collect();
+        builder.push(cells);
+    }
+    Ok(())
+}
+
+fn preview_json_lines(file: &Path, builder: &mut Builder) -> anyhow::Result<()> {
+    let content = std::fs::read_to_string(file)?;
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Value::Object(object) = serde_json::from_str(line)
+            .with_context(|| format!("Invalid JSON on line {}", number + 1))?
+        else {
+            anyhow::bail!("Line {} is not a JSON object", number + 1);
+        };
+        let cells = object
+            .iter()
+            .filter_map(|(name, value)| {
+                let text = match value {
+                    Value::String(text) => text.clone(),
+                    Value::Null => String::new(),
+                    value => value.to_string(),
+                };
+                Some((builder.column(name)?, text, ColumnType::of_json(value)))
+            })
+            .collect();
+        builder.push(cells);
+    }
+    Ok(())
+}
+
+/// The datasets under the book `src` directory `src_dir`, by path, without hidden files
+pub fn find_datasets(src_dir: &Path) -> Vec<DatasetInfo> {
+    let mut datasets: Vec<DatasetInfo> = WalkDir::new(src_dir)
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+        })
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter(|entry| {
+            entry.path().extension().is_some_and(|extension| {
+                EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str())
+            })
+        })
+        .filter_map(|entry| {
+            let path = entry.path().strip_prefix(src_dir).ok()?;
+            Some(DatasetInfo {
+                path: path.to_str()?.replace('\\', "/"),
+                bytes: entry.metadata().ok()?.len(),
+            })
+        })
+        .collect();
+    datasets.sort_by(|a, b| a.path.cmp(&b.path));
+    datasets
+}
+
+/// The columns, row count and first `rows` rows of the dataset at `path`, relative to the book
+/// `src` directory `src_dir`, which it can't leave, see [`assets::asset_path`].
+///
+/// The type of a column is inferred from all its values: CSV and TSV cells are parsed, cells
+/// such as "NA" or "null" count as missing; JSON lines keep their JSON types, their columns are
+/// the keys of the objects, new keys after the ones of the lines before.
+pub fn preview(src_dir: &Path, path: &str, rows: usize) -> anyhow::Result<DatasetPreview> {
+    let extension = Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if !EXTENSIONS.contains(&extension.as_str()) {
+        anyhow::bail!("not a dataset: {path}, datasets are .csv, .tsv or .jsonl files");
+    }
+    let file =
+        assets::asset_path(src_dir, path).with_context(|| format!("Dataset not found: {path}"))?;
+    let bytes = std::fs::metadata(&file)?.len();
+    if bytes > MAX_DATASET_BYTES {
+        anyhow::bail!(
+            "Dataset {path} is larger than {} MiB, too large to preview",
+            MAX_DATASET_BYTES / 1024 / 1024
+        );
+    }
+    let mut builder = Builder::new(rows.min(MAX_PREVIEW_ROWS));
+    match extension.as_str() {
+        "tsv" => preview_delimited(&file, b'\t', &mut builder),
+        "jsonl" => preview_json_lines(&file, &mut builder),
+        _ => preview_delimited(&file, b',', &mut builder),
+    }
+    .with_context(|| format!("Invalid dataset {path}"))?;
+    Ok(builder.finish(path))
+}
+
+#[test]
+fn dataset_preview() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("data/.hidden")).unwrap();
+    std::fs::write(
+        dir.path().join("data/titanic.csv"),
+        "name,age,fare,survived,\nAllen,29,211.3375,true,x\nAllison,NA,151.55,false,\nAnderson,48,26,TRUE,\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.path().join("data/events.jsonl"),
+        "{\"id\": 1, \"kind\": \"click\"}\n\n{\"id\": 2, \"kind\": null, \"score\": 0.5}\n",
+    )
+    .unwrap();
+    std::fs::write(dir.path().join("data/.hidden/secret.csv"), "a\n1\n").unwrap();
+    std::fs::write(dir.path().join("chapter_1.md"), "# Chapter").unwrap();
+
+    let datasets: Vec<String> = find_datasets(dir.path())
+        .into_iter()
+        .map(|dataset| dataset.path)
+        .collect();
+    assert_eq!(datasets, ["data/events.jsonl", "data/titanic.csv"]);
+
+    let titanic = preview(dir.path(), "data/titanic.csv", 2).unwrap();
+    assert_eq!(titanic.row_count, 3);
+    let types: Vec<(&str, ColumnType)> = titanic
+        .columns
+        .iter()
+        .map(|column| (column.name.as_str(), column.r#type))
+        .collect();
+    assert_eq!(
+        types,
+        [
+            ("name", ColumnType::Text),
+            ("age", ColumnType::Integer),
+            ("fare", ColumnType::Float),
+            ("survived", ColumnType::Boolean),
+            ("column_5", ColumnType::Text),
+        ]
+    );
+    assert_eq!(titanic.columns[1].nulls, 1);
+    assert_eq!(titanic.columns[4].nulls, 2);
+    assert_eq!(
+        titanic.head,
+        [
+            ["Allen", "29", "211.3375", "true", "x"],
+            ["Allison", "", "151.55", "false", ""],
+        ]
+    );
+
+    let events = preview(dir.path(), "data/events.jsonl", 10).unwrap();
+    assert_eq!(events.row_count, 2);
+    assert_eq!(events.columns[1].r#type, ColumnType::Text);
+    assert_eq!(events.columns[1].nulls, 1);
+    assert_eq!(events.columns[2].name, "score");
+    assert_eq!(events.columns[2].r#type, ColumnType::Float);
+    assert_eq!(events.columns[2].nulls, 1);
+    assert_eq!(events.head[0], ["1", "click", ""]);
+
+    assert!(preview(dir.path(), "../titanic.csv", 10).is_err());
+    assert!(preview(dir.path(), "chapter_1.md", 10).is_err());
+    assert!(preview(dir.path(), "data/.hidden/secret.csv", 10).is_err());
+}
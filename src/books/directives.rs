@@ -0,0 +1,214 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::LazyLock,
+};
+
+use regex::{Captures, Regex};
+use tracing::warn;
+
+/// mdbook stops resolving nested includes at the same depth
+const MAX_DEPTH: usize = 10;
+
+/// `{{#include file.rs:2:10}}`, `{{#playground file.rs editable}}`, `{{#rustdoc_include file.rs:anchor}}`,
+/// a leading backslash escapes the directive
+static DIRECTIVE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(\\)?\{\{#(include|playground|rustdoc_include)\s+([^}\s]+)[^}]*\}\}").unwrap()
+});
+static ANCHOR_START: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"ANCHOR:\s*([\w_-]+)").unwrap());
+static ANCHOR_END: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"ANCHOR_END:\s*([\w_-]+)").unwrap());
+
+/// Which part of the file a directive includes
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Selection {
+    /// 1-based, inclusive line range
+    Lines(Option<usize>, Option<usize>),
+    Anchor(String),
+}
+
+impl Selection {
+    /// parse the part after the file name, e.g. "2:10", ":10", "2", "anchor"
+    fn parse(spec: Option<&str>) -> Self {
+        let Some(spec) = spec.filter(|s| !s.is_empty()) else {
+            return Selection::Lines(None, None);
+        };
+        let (start, end) = match spec.split_once(':') {
+            Some((start, end)) => (start, Some(end)),
+            None => (spec, None),
+        };
+        let parse = |s: &str| s.parse::<usize>().ok();
+        match (parse(start), end) {
+            (Some(start), None) => Selection::Lines(Some(start), Some(start)),
+            (Some(start), Some(end)) => Selection::Lines(Some(start), parse(end)),
+            (None, Some(end)) if start.is_empty() => Selection::Lines(None, parse(end)),
+            _ => Selection::Anchor(spec.to_string()),
+        }
+    }
+
+    /// whether each line of `content` is selected, anchor marker lines are never selected
+    fn select(&self, content: &str) -> Vec<(bool, String)> {
+        let mut in_anchor = false;
+        let mut lines = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let number = i + 1;
+            if let Some(caps) = ANCHOR_START.captures(line) {
+                if let Selection::Anchor(name) = self {
+                    in_anchor |= &caps[1] == name;
+                }
+                continue;
+            }
+            if let Some(caps) = ANCHOR_END.captures(line) {
+                if let Selection::Anchor(name) = self {
+                    in_anchor &= &caps[1] != name;
+                }
+                continue;
+            }
+            let selected = match self {
+                Selection::Lines(start, end) => {
+                    start.is_none_or(|s| number >= s) && end.is_none_or(|e| number <= e)
+                }
+                Selection::Anchor(_) => in_anchor,
+            };
+            lines.push((selected, line.to_string()));
+        }
+        lines
+    }
+}
+
+/// Resolve the mdbook `include`, `playground` and `rustdoc_include` directives in `content`.
+///
+/// Paths are relative to `dir`, the directory of the file containing the directive, and must
+/// stay inside `root`, the book directory holding `book.toml`, so listings next to `src` are found
+/// like mdbook finds them; absolute paths and paths leaving `root`, also through symbolic links,
+/// are left unresolved.
+/// `rustdoc_include` keeps the whole file and hides unselected lines with `# ` like rustdoc does.
+pub fn resolve_directives(root: &Path, dir: &Path, content: &str) -> String {
+    match root.canonicalize() {
+        Ok(root) => resolve(&root, dir, content, 0),
+        Err(e) => {
+            warn!("failed to resolve book root {}: {}", root.display(), e);
+            content.to_string()
+        }
+    }
+}
+
+/// the file `file` of a directive in `dir` points to, `None` when it leaves `root`
+fn contained(root: &Path, dir: &Path, file: &str) -> Option<PathBuf> {
+    let is_relative = Path::new(file)
+        .components()
+        .all(|component| !matches!(component, Component::Prefix(_) | Component::RootDir));
+    if !is_relative {
+        return None;
+    }
+    let path = dir.join(file).canonicalize().ok()?;
+    path.starts_with(root).then_some(path)
+}
+
+fn resolve(root: &Path, dir: &Path, content: &str, depth: usize) -> String {
+    DIRECTIVE
+        .replace_all(content, |caps: &Captures| {
+            if caps.get(1).is_some() {
+                return caps[0][1..].to_string();
+            }
+            if depth >= MAX_DEPTH {
+                warn!("include depth exceeded at {}", &caps[0]);
+                return caps[0].to_string();
+            }
+            let (file, spec) = match caps[3].split_once(':') {
+                Some((file, spec)) => (file, Some(spec)),
+                None => (&caps[3], None),
+            };
+            let Some(path) = contained(root, dir, file) else {
+                warn!("failed to include {file}: missing or outside of the book");
+                return caps[0].to_string();
+            };
+            let included = match std::fs::read_to_string(&path) {
+                Ok(included) => included,
+                Err(e) => {
+                    warn!("failed to include {}: {}", path.display(), e);
+                    return caps[0].to_string();
+                }
+            };
+            let lines = Selection::parse(spec).select(&included);
+            let text = if &caps[2] == "rustdoc_include" {
+                lines
+                    .into_iter()
+                    .map(|(selected, line)| if selected { line } else { format!("# {line}") })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            } else {
+                lines
+                    .into_iter()
+                    .filter_map(|(selected, line)| selected.then_some(line))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            let nested_dir = path.parent().unwrap_or(dir);
+            resolve(root, nested_dir, &text, depth + 1)
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let code = "// ANCHOR: all\nfn main() {\n    // ANCHOR: body\n    println!(\"hi\");\n    // ANCHOR_END: body\n}\n// ANCHOR_END: all\n";
+        std::fs::write(dir.path().join("main.rs"), code).unwrap();
+        dir
+    }
+
+    #[test]
+    fn ranges_and_anchors() {
+        let dir = book_dir();
+        let resolve = |s: &str| resolve_directives(dir.path(), dir.path(), s);
+        assert_eq!(resolve("{{#include main.rs:2}}"), "fn main() {");
+        assert_eq!(
+            resolve("{{#include main.rs:4:6}}"),
+            "    println!(\"hi\");\n}"
+        );
+        assert_eq!(resolve("{{#include main.rs::2}}"), "fn main() {");
+        assert_eq!(
+            resolve("{{#include main.rs:body}}"),
+            "    println!(\"hi\");"
+        );
+        assert_eq!(
+            resolve("{{#playground main.rs editable}}"),
+            "fn main() {\n    println!(\"hi\");\n}"
+        );
+        assert_eq!(
+            resolve("{{#rustdoc_include main.rs:body}}"),
+            "# fn main() {\n    println!(\"hi\");\n# }"
+        );
+        assert_eq!(resolve("\\{{#include main.rs}}"), "{{#include main.rs}}");
+        assert_eq!(
+            resolve("{{#include missing.rs}}"),
+            "{{#include missing.rs}}"
+        );
+    }
+
+    #[test]
+    fn stays_inside_the_book() {
+        let dir = book_dir();
+        std::fs::write(dir.path().join("secret.txt"), "secret").unwrap();
+        let book = dir.path().join("book");
+        let src = book.join("src");
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::create_dir(book.join("listings")).unwrap();
+        std::fs::write(book.join("listings/main.rs"), "fn main() {}").unwrap();
+        let resolve = |s: &str| resolve_directives(&book, &src, s);
+        assert_eq!(resolve("{{#include ../listings/main.rs}}"), "fn main() {}");
+        assert_eq!(
+            resolve("{{#include ../../secret.txt}}"),
+            "{{#include ../../secret.txt}}"
+        );
+        let absolute = format!(
+            "{{{{#include {}}}}}",
+            dir.path().join("secret.txt").display()
+        );
+        assert_eq!(resolve(&absolute), absolute);
+    }
+}
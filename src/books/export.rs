@@ -0,0 +1,615 @@
+use std::io::{Cursor, Write};
+
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag, TagEnd, html};
+use serde::Deserialize;
+use utoipa::ToSchema;
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+use super::{book::Book, chapter::Chapter};
+
+/// Layout of an exported chapter, tuned for large print and e-ink readers
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    /// base font size in points
+    pub font_size: u32,
+    /// line height relative to the font size
+    pub line_height: f32,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            font_size: 18,
+            line_height: 1.6,
+        }
+    }
+}
+
+/// File format of an exported chapter
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// reflowed by the reader, for any script
+    #[default]
+    Epub,
+    /// laid out on A5 pages with the standard PDF fonts, which cover Latin-1 text only; other
+    /// characters are printed as `?`
+    Pdf,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Epub => "application/epub+zip",
+            Self::Pdf => "application/pdf",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Epub => "epub",
+            Self::Pdf => "pdf",
+        }
+    }
+}
+
+impl ExportOptions {
+    fn font_size(&self) -> u32 {
+        self.font_size.clamp(8, 72)
+    }
+
+    fn line_height(&self) -> f32 {
+        self.line_height.clamp(1.0, 3.0)
+    }
+
+    fn stylesheet(&self) -> String {
+        let font_size = self.font_size();
+        let line_height = self.line_height();
+        format!(
+            r#"body {{ font-size: {font_size}pt; line-height: {line_height}; margin: 0 1em; color: #000; background: #fff; }}
+h1, h2, h3, h4, h5, h6 {{ line-height: 1.2; page-break-after: avoid; }}
+pre, code {{ font-size: 0.9em; white-space: pre-wrap; word-wrap: break-word; }}
+table {{ border-collapse: collapse; }}
+th, td {{ border: 1px solid #000; padding: 0.2em 0.4em; }}
+.figure {{ font-style: italic; border-left: 3px solid #000; padding-left: 0.5em; }}
+"#
+        )
+    }
+}
+
+/// Export a chapter as a single chapter EPUB 3 file,
+/// images are replaced by their alt text so the layout never depends on them
+pub fn export_chapter_epub(
+    book: &Book,
+    chapter: &Chapter,
+    options: &ExportOptions,
+) -> anyhow::Result<Vec<u8>> {
+    let title = format!("{} {}", chapter.number, chapter.name);
+    let identifier = format!("book-server:{}:{}", book.id, chapter.number);
+    let body = render_xhtml_body(&chapter.content);
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // the mimetype must be the first entry and stored uncompressed
+    zip.start_file(
+        "mimetype",
+        SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+    )?;
+    zip.write_all(b"application/epub+zip")?;
+
+    let options_deflated = SimpleFileOptions::default();
+    zip.start_file("META-INF/container.xml", options_deflated)?;
+    zip.write_all(CONTAINER_XML.as_bytes())?;
+
+    zip.start_file("OEBPS/content.opf", options_deflated)?;
+    zip.write_all(
+        content_opf(
+            &escape_xml(&identifier),
+            &escape_xml(&title),
+            &escape_xml(&book.authors.join(", ")),
+        )
+        .as_bytes(),
+    )?;
+
+    zip.start_file("OEBPS/nav.xhtml", options_deflated)?;
+    zip.write_all(nav_xhtml(&escape_xml(&title)).as_bytes())?;
+
+    zip.start_file("OEBPS/style.css", options_deflated)?;
+    zip.write_all(options.stylesheet().as_bytes())?;
+
+    zip.start_file("OEBPS/chapter.xhtml", options_deflated)?;
+    zip.write_all(chapter_xhtml(&escape_xml(&title), &body).as_bytes())?;
+
+    Ok(zip.finish()?.into_inner())
+}
+
+/// Export a chapter as a PDF, see [`ExportFormat::Pdf`]; images are replaced by their alt text
+/// like in the EPUB
+pub fn export_chapter_pdf(
+    book: &Book,
+    chapter: &Chapter,
+    options: &ExportOptions,
+) -> anyhow::Result<Vec<u8>> {
+    let title = format!("{} {}", chapter.number, chapter.name);
+    Ok(render_pdf(
+        &title,
+        &book.authors.join(", "),
+        &chapter.content,
+        options,
+    ))
+}
+
+/// A5 in points
+const PAGE_WIDTH: f32 = 420.0;
+const PAGE_HEIGHT: f32 = 595.0;
+const MARGIN: f32 = 36.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PdfFont {
+    Regular,
+    Bold,
+    Mono,
+}
+
+impl PdfFont {
+    /// the name in the page resources
+    fn resource(self) -> &'static str {
+        match self {
+            Self::Regular => "F1",
+            Self::Bold => "F2",
+            Self::Mono => "F3",
+        }
+    }
+
+    /// the advance of a character relative to the font size, wide enough for the widest
+    /// common letters so a line never runs into the margin
+    fn char_width(self) -> f32 {
+        match self {
+            Self::Regular => 0.55,
+            Self::Bold | Self::Mono => 0.6,
+        }
+    }
+}
+
+/// A heading, paragraph, list item, table row or code block of the chapter
+#[derive(Debug)]
+struct PdfBlock {
+    font: PdfFont,
+    /// font size relative to the base one
+    scale: f32,
+    text: String,
+    /// code keeps its lines and spaces
+    preformatted: bool,
+}
+
+/// the blocks of chapter markdown, raw html is dropped and images become their alt text
+fn pdf_blocks(content: &str) -> Vec<PdfBlock> {
+    let mut blocks = Vec::new();
+    let mut current: Option<PdfBlock> = None;
+    let mut alt_text: Option<String> = None;
+    let block = |font, scale, text: &str, preformatted| PdfBlock {
+        font,
+        scale,
+        text: text.to_string(),
+        preformatted,
+    };
+    let flush = |current: &mut Option<PdfBlock>, blocks: &mut Vec<PdfBlock>| {
+        if let Some(block) = current.take().filter(|block| !block.text.trim().is_empty()) {
+            blocks.push(block);
+        }
+    };
+    for event in Parser::new_ext(
+        content,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+    ) {
+        match event {
+            Event::Start(Tag::Image { .. }) => alt_text = Some(String::new()),
+            Event::End(TagEnd::Image) => {
+                let alt = alt_text.take().unwrap_or_default();
+                let alt = if alt.trim().is_empty() {
+                    "[Image]".to_string()
+                } else {
+                    format!("[Image: {}]", alt.trim())
+                };
+                current
+                    .get_or_insert_with(|| block(PdfFont::Regular, 1.0, "", false))
+                    .text
+                    .push_str(&alt);
+            }
+            Event::Text(text) | Event::Code(text) if alt_text.is_some() => {
+                if let Some(alt) = alt_text.as_mut() {
+                    alt.push_str(&text);
+                }
+            }
+            _ if alt_text.is_some() => {}
+            Event::Start(Tag::Heading { level, .. }) => {
+                flush(&mut current, &mut blocks);
+                let scale = match level {
+                    HeadingLevel::H1 => 1.6,
+                    HeadingLevel::H2 => 1.35,
+                    HeadingLevel::H3 => 1.15,
+                    _ => 1.0,
+                };
+                current = Some(block(PdfFont::Bold, scale, "", false));
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                flush(&mut current, &mut blocks);
+                current = Some(block(PdfFont::Mono, 0.9, "", true));
+            }
+            Event::Start(Tag::Item) => {
+                flush(&mut current, &mut blocks);
+                current = Some(block(PdfFont::Regular, 1.0, "• ", false));
+            }
+            Event::Start(Tag::TableCell) => {
+                let row = current.get_or_insert_with(|| block(PdfFont::Regular, 1.0, "", false));
+                if !row.text.is_empty() {
+                    row.text.push_str(" | ");
+                }
+            }
+            Event::End(
+                TagEnd::Heading(_)
+                | TagEnd::Paragraph
+                | TagEnd::CodeBlock
+                | TagEnd::Item
+                | TagEnd::TableHead
+                | TagEnd::TableRow,
+            ) => flush(&mut current, &mut blocks),
+            Event::Text(text) | Event::Code(text) => current
+                .get_or_insert_with(|| block(PdfFont::Regular, 1.0, "", false))
+                .text
+                .push_str(&text),
+            Event::SoftBreak => {
+                if let Some(current) = current.as_mut() {
+                    current.text.push(' ');
+                }
+            }
+            Event::HardBreak => {
+                if let Some(current) = current.as_mut() {
+                    current.text.push('\n');
+                }
+            }
+            _ => {}
+        }
+    }
+    flush(&mut current, &mut blocks);
+    blocks
+}
+
+/// the lines of a block at most `width` characters long, words longer than a line are cut
+fn wrap(block: &PdfBlock, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in block.text.replace('\t', "    ").lines() {
+        if block.preformatted {
+            let chars: Vec<char> = paragraph.chars().collect();
+            if chars.is_empty() {
+                lines.push(String::new());
+            }
+            lines.extend(chars.chunks(width).map(|chunk| chunk.iter().collect()));
+            continue;
+        }
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            while word.len() > width {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                lines.push(word.drain(..width).collect());
+            }
+            let length = line.chars().count();
+            if length > 0 && length + 1 + word.len() > width {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.extend(word);
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+/// `text` as a PDF string literal in WinAnsiEncoding
+fn pdf_string(text: &str) -> String {
+    let mut literal = String::from("(");
+    for c in text.chars() {
+        let byte = match c {
+            ' '..='~' | '\u{a0}'..='\u{ff}' => c as u32 as u8,
+            '€' => 0x80,
+            '‚' => 0x82,
+            '„' => 0x84,
+            '…' => 0x85,
+            '‘' => 0x91,
+            '’' => 0x92,
+            '“' => 0x93,
+            '”' => 0x94,
+            '•' => 0x95,
+            '–' => 0x96,
+            '—' => 0x97,
+            '™' => 0x99,
+            _ => b'?',
+        };
+        match byte {
+            b'(' | b')' | b'\\' => {
+                literal.push('\\');
+                literal.push(byte as char);
+            }
+            b' '..=b'~' => literal.push(byte as char),
+            _ => literal.push_str(&format!("\\{byte:03o}")),
+        }
+    }
+    literal.push(')');
+    literal
+}
+
+/// the content streams of the pages of `content`
+fn pdf_pages(content: &str, options: &ExportOptions) -> Vec<String> {
+    let font_size = options.font_size() as f32;
+    let line_height = options.line_height();
+    let mut pages = vec![];
+    let mut page = String::new();
+    let mut y = PAGE_HEIGHT - MARGIN;
+    for block in pdf_blocks(content) {
+        let size = font_size * block.scale;
+        let leading = size * line_height;
+        let width = ((PAGE_WIDTH - 2.0 * MARGIN) / (size * block.font.char_width())) as usize;
+        // half a line between blocks, none at the top of a page
+        if !page.is_empty() {
+            y -= font_size * 0.5;
+        }
+        for line in wrap(&block, width.max(1)) {
+            if y - leading < MARGIN {
+                pages.push(std::mem::take(&mut page));
+                y = PAGE_HEIGHT - MARGIN;
+            }
+            y -= leading;
+            page.push_str(&format!(
+                "BT /{} {size:.1} Tf {MARGIN:.1} {y:.1} Td {} Tj ET\n",
+                block.font.resource(),
+                pdf_string(&line)
+            ));
+        }
+    }
+    if !page.is_empty() || pages.is_empty() {
+        pages.push(page);
+    }
+    pages
+}
+
+/// a PDF 1.4 file with the standard fonts, so nothing needs embedding
+fn render_pdf(title: &str, author: &str, content: &str, options: &ExportOptions) -> Vec<u8> {
+    let pages = pdf_pages(content, options);
+    // the catalog, the page tree, the three fonts and the document information, then a page
+    // object and its content stream for each page
+    let first_page = 7;
+    let kids: Vec<String> = (0..pages.len())
+        .map(|i| format!("{} 0 R", first_page + 2 * i))
+        .collect();
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        ),
+    ];
+    for font in ["Helvetica", "Helvetica-Bold", "Courier"] {
+        objects.push(format!(
+            "<< /Type /Font /Subtype /Type1 /BaseFont /{font} /Encoding /WinAnsiEncoding >>"
+        ));
+    }
+    objects.push(format!(
+        "<< /Title {} /Author {} /Producer (book-server) >>",
+        pdf_string(title),
+        pdf_string(author)
+    ));
+    for (i, page) in pages.iter().enumerate() {
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {PAGE_WIDTH} {PAGE_HEIGHT}] \
+            /Resources << /Font << /F1 3 0 R /F2 4 0 R /F3 5 0 R >> >> /Contents {} 0 R >>",
+            first_page + 2 * i + 1
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{page}endstream",
+            page.len()
+        ));
+    }
+    let mut pdf = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.push_str(&format!("{} 0 obj\n{object}\nendobj\n", i + 1));
+    }
+    let xref = pdf.len();
+    pdf.push_str(&format!(
+        "xref\n0 {}\n0000000000 65535 f \n",
+        objects.len() + 1
+    ));
+    for offset in offsets {
+        pdf.push_str(&format!("{offset:010} 00000 n \n"));
+    }
+    pdf.push_str(&format!(
+        "trailer\n<< /Size {} /Root 1 0 R /Info 6 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+        objects.len() + 1
+    ));
+    pdf.into_bytes()
+}
+
+/// render chapter markdown to an xhtml fragment, raw html is dropped and images become their alt text
+fn render_xhtml_body(content: &str) -> String {
+    let mut events = Vec::new();
+    let mut alt_text: Option<String> = None;
+    for event in Parser::new_ext(
+        content,
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
+    ) {
+        match event {
+            Event::Start(Tag::Image { .. }) => alt_text = Some(String::new()),
+            Event::End(TagEnd::Image) => {
+                let alt = alt_text.take().unwrap_or_default();
+                let alt = if alt.trim().is_empty() {
+                    "Image".to_string()
+                } else {
+                    format!("Image: {}", alt.trim())
+                };
+                events.push(Event::InlineHtml(
+                    format!("<span class=\"figure\">[{}]</span>", escape_xml(&alt)).into(),
+                ));
+            }
+            Event::Text(text) | Event::Code(text) if alt_text.is_some() => {
+                if let Some(alt) = alt_text.as_mut() {
+                    alt.push_str(&text);
+                }
+            }
+            _ if alt_text.is_some() => {}
+            Event::Html(_) | Event::InlineHtml(_) => {}
+            event => events.push(event),
+        }
+    }
+    let mut body = String::new();
+    html::push_html(&mut body, events.into_iter());
+    body
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+fn content_opf(identifier: &str, title: &str, authors: &str) -> String {
+    let modified = time::OffsetDateTime::now_utc()
+        .replace_nanosecond(0)
+        .unwrap_or(time::OffsetDateTime::UNIX_EPOCH)
+        .format(&time::format_description::well_known::Rfc3339)
+        .unwrap_or_default();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="book-id">{identifier}</dc:identifier>
+    <dc:title>{title}</dc:title>
+    <dc:creator>{authors}</dc:creator>
+    <dc:language>en</dc:language>
+    <meta property="dcterms:modified">{modified}</meta>
+  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+    <item id="style" href="style.css" media-type="text/css"/>
+    <item id="chapter" href="chapter.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine>
+    <itemref idref="chapter"/>
+  </spine>
+</package>
+"#
+    )
+}
+
+fn nav_xhtml(title: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+<head><title>{title}</title></head>
+<body>
+  <nav epub:type="toc"><ol><li><a href="chapter.xhtml">{title}</a></li></ol></nav>
+</body>
+</html>
+"#
+    )
+}
+
+fn chapter_xhtml(title: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head>
+  <title>{title}</title>
+  <link rel="stylesheet" type="text/css" href="style.css"/>
+</head>
+<body>
+{body}
+</body>
+</html>
+"#
+    )
+}
+
+#[test]
+fn test_render_xhtml_body() {
+    let body =
+        render_xhtml_body("# Title\n\n<!-- page 3 -->\n\nSee ![a *red* box](box.png) & more.");
+    assert!(body.contains("<h1>Title</h1>"));
+    assert!(!body.contains("page 3"));
+    assert!(!body.contains("box.png"));
+    assert!(body.contains("[Image: a red box]"));
+    assert!(body.contains("&amp; more."));
+}
+
+#[test]
+fn test_render_pdf() {
+    let options = ExportOptions::default();
+    let pdf = render_pdf(
+        "1. Intro",
+        "Ann",
+        "# Title\n\n<!-- page 3 -->\n\nSee ![a *red* box](box.png) (and “more”).\n\n- one\n",
+        &options,
+    );
+    let pdf = String::from_utf8(pdf).unwrap();
+    assert!(pdf.starts_with("%PDF-1.4\n"));
+    assert!(pdf.contains("/Count 1 "));
+    assert!(pdf.contains("/F2 28.8 Tf 36.0 512.9 Td (Title) Tj"));
+    assert!(pdf.contains("(See [Image: a red box] \\(and \\223more\\224\\).) Tj"));
+    assert!(pdf.contains("(\\225 one) Tj"));
+    assert!(!pdf.contains("page 3"));
+    // every object is where the cross-reference table says
+    let xref = pdf.split("xref\n").nth(1).unwrap();
+    for (id, entry) in xref
+        .lines()
+        .skip(2)
+        .take_while(|line| line.ends_with(" n "))
+        .enumerate()
+    {
+        let offset: usize = entry[..10].parse().unwrap();
+        assert!(pdf[offset..].starts_with(&format!("{} 0 obj", id + 1)));
+    }
+    let long = "word ".repeat(2000);
+    let pdf = String::from_utf8(render_pdf("t", "", &long, &options)).unwrap();
+    assert!(!pdf.contains("/Count 1 "));
+}
+
+#[test]
+fn test_wrap() {
+    let block = PdfBlock {
+        font: PdfFont::Regular,
+        scale: 1.0,
+        text: "a bb ccc ddddddddd".to_string(),
+        preformatted: false,
+    };
+    assert_eq!(wrap(&block, 5), ["a bb", "ccc", "ddddd", "dddd"]);
+    let code = PdfBlock {
+        text: "fn main() {\n\n}".to_string(),
+        preformatted: true,
+        ..block
+    };
+    assert_eq!(wrap(&code, 6), ["fn mai", "n() {", "", "}"]);
+}
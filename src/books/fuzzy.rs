@@ -0,0 +1,74 @@
+use std::collections::HashSet;
+
+use super::text::tokenize;
+
+/// Words that carry no meaning when a model describes a chapter, e.g. "the one about verb tenses"
+const STOP_WORDS: &[&str] = &[
+    "a", "about", "an", "and", "chapter", "for", "in", "is", "of", "on", "one", "section", "that",
+    "the", "to", "which", "with",
+];
+
+fn normalize(s: &str) -> Vec<String> {
+    tokenize(s)
+        .into_iter()
+        .filter(|w| !STOP_WORDS.contains(&w.as_str()))
+        .collect()
+}
+
+fn trigrams(s: &str) -> HashSet<[char; 3]> {
+    let mut set = HashSet::new();
+    for word in normalize(s) {
+        // pad like pg_trgm so short words and word starts still produce trigrams
+        let chars: Vec<char> = format!("  {word} ").chars().collect();
+        for w in chars.windows(3) {
+            set.insert([w[0], w[1], w[2]]);
+        }
+    }
+    set
+}
+
+/// Dice coefficient of the word trigrams of two strings, in `0.0..=1.0`
+pub fn trigram_similarity(a: &str, b: &str) -> f64 {
+    let a = trigrams(a);
+    let b = trigrams(b);
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let common = a.intersection(&b).count();
+    2.0 * common as f64 / (a.len() + b.len()) as f64
+}
+
+/// Rank `candidates` by similarity to `query`, keeping those scoring at least `threshold`
+pub fn rank<'a, T>(
+    query: &str,
+    candidates: impl IntoIterator<Item = (&'a str, T)>,
+    threshold: f64,
+    limit: usize,
+) -> Vec<(T, f64)> {
+    let mut scored: Vec<(T, f64)> = candidates
+        .into_iter()
+        .map(|(text, item)| (item, trigram_similarity(query, text)))
+        .filter(|(_, score)| *score >= threshold)
+        .collect();
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored.truncate(limit);
+    scored
+}
+
+#[test]
+fn similarity() {
+    assert_eq!(trigram_similarity("Verb Tenses", "verb tenses"), 1.0);
+    assert!(trigram_similarity("the one about verb tenses", "Verb Tenses") > 0.9);
+    assert!(trigram_similarity("verb tense", "Verb Tenses") > 0.6);
+    assert!(trigram_similarity("verb tenses", "Punctuation") < 0.1);
+    assert_eq!(trigram_similarity("the", "Verb Tenses"), 0.0);
+    assert!(trigram_similarity("时态", "第三章 动词时态") > 0.3);
+}
+
+#[test]
+fn ranking() {
+    let titles = ["Nouns", "Verb Tenses", "Subject-Verb Agreement"];
+    let ranked = rank("verbs tenses", titles.iter().map(|t| (*t, *t)), 0.2, 2);
+    assert_eq!(ranked[0].0, "Verb Tenses");
+    assert!(ranked.len() <= 2);
+}
@@ -1,23 +1,304 @@
 use std::{
+    collections::{BTreeMap, HashSet},
     fs::File,
     path::{Path, PathBuf},
     sync::Arc,
 };
 
-use super::book::{Book, BookMeta};
+use super::{
+    assets,
+    book::{Book, BookMeta, BookRaw, BookTeachingPlan, Difficulty},
+    chapter::ChapterNumber,
+    cover::{self, COVERS_DIR, CoverSize, cover_content_type},
+    custom_tools::{self, BookTools},
+    datasets, notebook, pdf,
+    search::{self, Fusion, HybridHit, SearchHit},
+    validation,
+};
+use crate::{
+    ai_utils::{self, DEFAULT_PROVIDER, Provider},
+    embeddings::{
+        self, SemanticHit,
+        store::{
+            PgVectorStore, QdrantVectorStore, SqliteVectorStore, VectorStore, VectorStoreConfig,
+        },
+    },
+    generation_log,
+    jobs::BatchPreview,
+    pagination::{PageQuery, Paginated, SortOrder},
+    prompts::PromptTemplates,
+    scan::UploadScanner,
+    teacher::{
+        filters::ResponsePipeline,
+        messages::{
+            encryption::{EncryptedMessageStore, LocalMasterKey},
+            store::{MessageStore, PgMessageStore, RedisMessageStore, SqliteMessageStore},
+        },
+    },
+    usage,
+};
 use anyhow::bail;
 
 use moka::future::Cache;
-use sqlx::SqlitePool;
+use serde::{Deserialize, Serialize};
+use sqlx::{SqlitePool, sqlite::SqliteConnectOptions};
+use time::OffsetDateTime;
 use tokio::task::{block_in_place, spawn_blocking};
-use tracing::{error, info};
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
 use zip::ZipArchive;
 
+/// Filters and order of [`Library::list_books`]
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BookQuery {
+    /// part of the title, ignoring case
+    pub title: Option<String>,
+    /// only books with the tag
+    pub tag: Option<String>,
+    /// only books in the language
+    pub language: Option<String>,
+    pub difficulty: Option<Difficulty>,
+    pub order_by: Option<BookOrder>,
+}
+
+/// The order of a book list, ids ascending by default, `sort` reverses it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BookOrder {
+    #[default]
+    Id,
+    /// by title ignoring case
+    Title,
+    /// most recently added first
+    Added,
+}
+
+fn normalize_tag(tag: &str) -> String {
+    tag.replace(',', " ").trim().to_lowercase()
+}
+
+fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = tags
+        .iter()
+        .map(|tag| normalize_tag(tag))
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    normalized.sort();
+    normalized.dedup();
+    normalized
+}
+
+/// most entries of an uploaded zip
+const MAX_ZIP_ENTRIES: usize = 10_000;
+/// most bytes an uploaded zip extracts to
+const MAX_ZIP_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// extract the uploaded zip `archive` into `dir`, refusing archives with too many entries or
+/// extracting to too much data
+fn extract_zip(archive: &Path, dir: &Path) -> anyhow::Result<()> {
+    let mut zip = ZipArchive::new(File::open(archive)?)?;
+    if zip.len() > MAX_ZIP_ENTRIES {
+        bail!(
+            "The archive has {} entries, at most {MAX_ZIP_ENTRIES} are allowed",
+            zip.len()
+        );
+    }
+    let mut total: u64 = 0;
+    for i in 0..zip.len() {
+        total = total.saturating_add(zip.by_index_raw(i)?.size());
+    }
+    if total > MAX_ZIP_BYTES {
+        bail!(
+            "The archive extracts to {} MiB, at most {} MiB are allowed",
+            total / (1024 * 1024),
+            MAX_ZIP_BYTES / (1024 * 1024)
+        );
+    }
+    zip.extract(dir)?;
+    Ok(())
+}
+
+/// the root of the mdbook extracted into `dir`, or, for a collection of Jupyter notebooks, an
+/// mdbook made of them and named after the uploaded `archive`
+fn book_root(dir: &Path, archive: &Path) -> anyhow::Result<PathBuf> {
+    let error = match validation::mdbook_root(dir) {
+        Ok(root) => return Ok(root),
+        Err(e) => e,
+    };
+    if notebook::find_notebooks(dir).is_empty() {
+        return Err(error);
+    }
+    // a zip of a folder of notebooks
+    let mut entries = std::fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| !path.file_name().is_some_and(|name| name == "__MACOSX"));
+    let root = match (entries.next(), entries.next()) {
+        (Some(only), None) if only.is_dir() => only,
+        _ => dir.to_path_buf(),
+    };
+    let title = archive
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "Untitled".to_string());
+    notebook::notebooks_to_mdbook(&root, &title)?;
+    Ok(root)
+}
+
+/// a cover path must stay inside the book directory
+fn check_cover_path(cover_path: &str) -> anyhow::Result<()> {
+    let path = Path::new(cover_path);
+    let inside = path
+        .components()
+        .all(|component| matches!(component, std::path::Component::Normal(_)));
+    if cover_path.is_empty() || !inside {
+        bail!("cover path must be relative to the book directory: {cover_path}");
+    }
+    if cover_content_type(path).is_none() {
+        bail!("cover must be a png, jpg, webp, gif or svg image: {cover_path}");
+    }
+    Ok(())
+}
+
+/// The catalog metadata of a book, editable by admins
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct BookMetadata {
+    /// imported from book.toml, a reimport restores them
+    #[serde(default)]
+    pub authors: Vec<String>,
+    /// language of the book, e.g. "en"
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub difficulty: Option<Difficulty>,
+    /// subject tags, lowercase and without commas
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// cover image relative to the book directory, e.g. "src/images/cover.png"
+    #[serde(default)]
+    pub cover_path: Option<String>,
+}
+
+/// directory of the bookbase holding the files of archived books
+const ARCHIVE_DIR: &str = "archive";
+
+/// Chapters that differ between two imports of a book
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct ReimportReport {
+    pub added: Vec<ChapterNumber>,
+    /// chapters whose plan was regenerated
+    pub changed: Vec<ChapterNumber>,
+    /// chapters dropped with their student data
+    pub removed: Vec<ChapterNumber>,
+}
+
+impl ReimportReport {
+    fn diff(old: &BTreeMap<ChapterNumber, u64>, new: &BTreeMap<ChapterNumber, u64>) -> Self {
+        let mut report = Self::default();
+        for (number, hash) in new {
+            match old.get(number) {
+                None => report.added.push(number.clone()),
+                Some(old_hash) if old_hash != hash => report.changed.push(number.clone()),
+                Some(_) => {}
+            }
+        }
+        report.removed = old
+            .keys()
+            .filter(|number| !new.contains_key(*number))
+            .cloned()
+            .collect();
+        report
+    }
+}
+
+/// What deleting a book would remove
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BookDeletion {
+    pub book_id: i64,
+    pub title: String,
+    pub chapters: i64,
+    /// students learning the book, their teacher agents are removed
+    pub student_ids: Vec<i64>,
+    pub progress_records: i64,
+    /// conversation messages of those students, archived ones included
+    pub messages: i64,
+}
+
+/// An uploaded book extracted into the staging directory of the bookbase, checked but not
+/// imported yet
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct StagedBook {
+    pub book_id: i64,
+    pub title: String,
+    /// the mdbook directory
+    #[schema(value_type = String)]
+    pub path: PathBuf,
+    /// the staging directory holding `path`, removed once the book is imported
+    #[schema(value_type = String)]
+    pub staging_dir: PathBuf,
+}
+
+impl std::fmt::Display for StagedBook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.title, self.book_id)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Library {
     pub books: Cache<i64, Arc<Book>>,
     pub bookbase: PathBuf,
     pub database: SqlitePool,
+    /// where conversation messages are kept, the `history_message` table by default
+    pub message_store: Arc<dyn MessageStore>,
+    /// where the embedded chunks of the books are kept, the `chapter_embedding` table by default
+    pub vector_store: Arc<dyn VectorStore>,
+    /// scans uploaded archives before they are imported and handwriting snapshots before they
+    /// are read, no scan if `None`
+    pub upload_scanner: Option<Arc<dyn UploadScanner>>,
+    /// rewrites the teacher's responses before they are sent and stored, none by default
+    pub response_filters: Arc<ResponsePipeline>,
+}
+
+/// Where the library keeps its data, for opening it with [`Library::open`]
+#[derive(Debug, Clone)]
+pub struct LibraryConfig {
+    /// the SQLite database file, created if missing
+    pub database: PathBuf,
+    /// the directory of the imported books
+    pub bookbase: PathBuf,
+    /// apply the bundled migrations when opening, so a new database is ready to use
+    pub migrate: bool,
+    /// keep conversation messages in Redis streams at this url instead of the main database
+    pub redis_messages: Option<String>,
+    /// keep conversation messages in the Postgres database at this url instead
+    pub postgres_messages: Option<String>,
+    /// encrypt stored message content with per-student keys wrapped by the
+    /// base64 master key in the MESSAGE_MASTER_KEY env var
+    pub encrypt_messages: bool,
+    /// where the embedded chunks of the books are kept for semantic search
+    pub vector_store: VectorStoreConfig,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            database: PathBuf::from("database/book.db"),
+            bookbase: PathBuf::from("bookbase"),
+            migrate: true,
+            redis_messages: None,
+            postgres_messages: None,
+            encrypt_messages: false,
+            vector_store: VectorStoreConfig::default(),
+        }
+    }
 }
 
 impl Default for Library {
@@ -26,6 +307,10 @@ impl Default for Library {
         Self {
             books: Cache::new(1000),
             bookbase: PathBuf::new(),
+            message_store: Arc::new(SqliteMessageStore::new(database.clone())),
+            vector_store: Arc::new(SqliteVectorStore::new(database.clone())),
+            upload_scanner: None,
+            response_filters: Arc::new(ResponsePipeline::default()),
             database,
         }
     }
@@ -37,15 +322,95 @@ impl Library {
         sqlx::query!("PRAGMA foreign_keys = ON;")
             .execute(&database)
             .await?;
+        generation_log::init(database.clone());
         let server = Self {
             books: Cache::new(1000),
             bookbase: bookbase.as_ref().to_path_buf(),
+            message_store: Arc::new(SqliteMessageStore::new(database.clone())),
+            vector_store: Arc::new(SqliteVectorStore::new(database.clone())),
+            upload_scanner: None,
+            response_filters: Arc::new(ResponsePipeline::default()),
             database,
         };
         server.restore_db_from_bookbase().await?;
         Ok(server)
     }
 
+    /// open the library described by `config`, with its message store
+    pub async fn open(config: &LibraryConfig) -> anyhow::Result<Self> {
+        if let Some(dir) = config.database.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let mut options = SqliteConnectOptions::new()
+            .filename(&config.database)
+            .create_if_missing(true);
+        if let VectorStoreConfig::SqliteVec(extension) = &config.vector_store {
+            options = options.extension(extension.to_string_lossy().to_string());
+        }
+        let database = SqlitePool::connect_with(options).await?;
+        if config.migrate {
+            sqlx::migrate!().run(&database).await?;
+        }
+        let mut library = Self::new(database.clone(), &config.bookbase).await?;
+        match (&config.redis_messages, &config.postgres_messages) {
+            (Some(_), Some(_)) => {
+                anyhow::bail!("Messages can be kept in Redis or in Postgres, not both")
+            }
+            (Some(url), None) => {
+                library =
+                    library.with_message_store(Arc::new(RedisMessageStore::connect(url).await?));
+            }
+            (None, Some(url)) => {
+                library = library.with_message_store(Arc::new(PgMessageStore::connect(url).await?));
+            }
+            (None, None) => {}
+        }
+        if config.encrypt_messages {
+            let store = EncryptedMessageStore::new(
+                library.message_store.clone(),
+                database,
+                Arc::new(LocalMasterKey::from_env()?),
+            );
+            library = library.with_message_store(Arc::new(store));
+        }
+        match &config.vector_store {
+            VectorStoreConfig::Sqlite => {}
+            VectorStoreConfig::SqliteVec(_) => {
+                let store = SqliteVectorStore::with_sqlite_vec(library.database.clone());
+                library = library.with_vector_store(Arc::new(store));
+            }
+            VectorStoreConfig::Qdrant(url) => {
+                library = library.with_vector_store(Arc::new(QdrantVectorStore::new(url)?));
+            }
+            VectorStoreConfig::Pgvector(url) => {
+                library = library.with_vector_store(Arc::new(PgVectorStore::connect(url).await?));
+            }
+        }
+        Ok(library)
+    }
+
+    /// keep conversation messages in `store` instead of the main database
+    pub fn with_message_store(mut self, store: Arc<dyn MessageStore>) -> Self {
+        self.message_store = store;
+        self
+    }
+
+    /// keep the embedded chunks in `store` instead of the main database
+    pub fn with_vector_store(mut self, store: Arc<dyn VectorStore>) -> Self {
+        self.vector_store = store;
+        self
+    }
+
+    pub fn with_upload_scanner(mut self, scanner: Arc<dyn UploadScanner>) -> Self {
+        self.upload_scanner = Some(scanner);
+        self
+    }
+
+    pub fn with_response_filters(mut self, filters: ResponsePipeline) -> Self {
+        self.response_filters = Arc::new(filters);
+        self
+    }
+
     pub async fn get_book(&self, id: i64) -> anyhow::Result<Arc<Book>> {
         if let Some(book) = self.books.get(&id).await {
             Ok(book)
@@ -55,11 +420,33 @@ impl Library {
         }
     }
 
+    /// the provider for a student reading a book, see [`ai_utils::resolve_provider`]
+    pub async fn provider(
+        &self,
+        student_id: Option<i64>,
+        book_id: Option<i64>,
+    ) -> anyhow::Result<Arc<dyn Provider>> {
+        ai_utils::resolve_provider(&self.database, student_id, book_id).await
+    }
+
     async fn load_book(&self, id: i64) -> anyhow::Result<Arc<Book>> {
-        let _exist = sqlx::query_scalar!("select id from book where id = ?", id)
-            .fetch_one(&self.database)
-            .await?;
-        let book = Book::load(self.bookbase.join(format!("book_{}", id))).await?;
+        let archived = sqlx::query_scalar!(
+            r#"select archive_time is not null as "archived!: bool" from book where id = ?"#,
+            id
+        )
+        .fetch_one(&self.database)
+        .await?;
+        if archived {
+            bail!("Book {} is archived", id);
+        }
+        let provider = self.provider(None, Some(id)).await?;
+        let prompts = PromptTemplates::load(&self.database).await?;
+        let book = Book::load(
+            self.bookbase.join(format!("book_{}", id)),
+            provider.as_ref(),
+            &prompts,
+        )
+        .await?;
         if id != book.id {
             bail!("Book ID mismatch: {} != {}", id, book.id);
         }
@@ -69,46 +456,185 @@ impl Library {
     }
 
     pub async fn load_books(&self) -> anyhow::Result<()> {
-        let book_ids: Vec<i64> = sqlx::query_scalar!("select id from book")
-            .fetch_all(&self.database)
-            .await?;
+        let book_ids: Vec<i64> =
+            sqlx::query_scalar!("select id from book where archive_time is null")
+                .fetch_all(&self.database)
+                .await?;
         for id in book_ids {
             self.load_book(id).await?;
         }
         Ok(())
     }
 
+    /// delete the book with its files, plans, embeddings and every student's conversations and
+    /// progress, archived books included
     pub async fn delete_book(&self, book_id: i64) -> anyhow::Result<()> {
         let path = self.bookbase.join(format!("book_{}", book_id));
+        // the database cascades to the messages it keeps, not to those of other stores
+        for student_id in self.book_student_ids(book_id).await? {
+            self.message_store.clear(student_id, book_id).await?;
+        }
         sqlx::query!("delete from chapter where book_id = ?", book_id)
             .execute(&self.database)
             .await?;
         sqlx::query!("delete from book where id = ?", book_id)
             .execute(&self.database)
             .await?;
+        search::remove_book(&self.database, book_id).await?;
+        embeddings::remove_book(self.vector_store.as_ref(), book_id).await?;
+        self.books.invalidate(&book_id).await;
         let _ = tokio::fs::remove_dir_all(path).await;
+        let _ = tokio::fs::remove_dir_all(self.archived_book_dir(book_id)).await;
+        let _ = tokio::fs::remove_dir_all(self.covers_dir(book_id)).await;
         Ok(())
     }
 
+    /// the thumbnails of the cover of the book
+    fn covers_dir(&self, book_id: i64) -> PathBuf {
+        self.bookbase
+            .join(COVERS_DIR)
+            .join(format!("book_{}", book_id))
+    }
+
+    /// detect the cover of an imported book unless it has one, and drop the stale
+    /// thumbnails, a book without a cover is not an error
+    async fn detect_cover(&self, book: &Book) -> anyhow::Result<()> {
+        let book_id = book.id;
+        let _ = tokio::fs::remove_dir_all(self.covers_dir(book_id)).await;
+        let current = sqlx::query_scalar!("select cover_path from book where id = ?", book_id)
+            .fetch_one(&self.database)
+            .await?;
+        if current.is_some() {
+            return Ok(());
+        }
+        let book_dir = self.bookbase.join(format!("book_{}", book_id));
+        let book = book.clone();
+        let cover = spawn_blocking(move || cover::detect_cover(&book_dir, &book)).await??;
+        if let Some(cover_path) = &cover {
+            info!("detected cover {} of book {}", cover_path, book_id);
+            sqlx::query!(
+                "update book set cover_path = ? where id = ?",
+                cover_path,
+                book_id
+            )
+            .execute(&self.database)
+            .await?;
+        }
+        Ok(())
+    }
+
+    fn archived_book_dir(&self, book_id: i64) -> PathBuf {
+        self.bookbase
+            .join(ARCHIVE_DIR)
+            .join(format!("book_{}", book_id))
+    }
+
+    /// hide the book from students while keeping its conversations and progress for export,
+    /// `move_files` moves its sources and plans into the archive directory of the bookbase
+    pub async fn archive_book(&self, book_id: i64, move_files: bool) -> anyhow::Result<()> {
+        let now = OffsetDateTime::now_utc();
+        let archived = sqlx::query!(
+            "update book set archive_time = ?, is_public = false where id = ? and archive_time is null",
+            now,
+            book_id
+        )
+        .execute(&self.database)
+        .await?;
+        if archived.rows_affected() == 0 {
+            bail!("Book {} not found or already archived", book_id);
+        }
+        search::remove_book(&self.database, book_id).await?;
+        self.books.invalidate(&book_id).await;
+        if move_files {
+            let archive = self.archived_book_dir(book_id);
+            if let Some(parent) = archive.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::rename(self.bookbase.join(format!("book_{}", book_id)), archive).await?;
+        }
+        info!("archived book {}", book_id);
+        Ok(())
+    }
+
+    /// what [`delete_book`](Self::delete_book) would remove, without removing it
+    pub async fn preview_delete_book(&self, book_id: i64) -> anyhow::Result<BookDeletion> {
+        let Some(title) = sqlx::query_scalar!("select title from book where id = ?", book_id)
+            .fetch_optional(&self.database)
+            .await?
+        else {
+            bail!("Book not found: {}", book_id);
+        };
+        let counts = sqlx::query!(
+            r#"select (select count(*) from chapter where book_id = ?1) as "chapters!: i64",
+            (select count(*) from chapter_progress where book_id = ?1) as "progress_records!: i64""#,
+            book_id
+        )
+        .fetch_one(&self.database)
+        .await?;
+        let student_ids = self.book_student_ids(book_id).await?;
+        // counted by the message store, the messages may not be in the main database
+        let mut messages = 0;
+        for &student_id in &student_ids {
+            messages += self.message_store.count(student_id, book_id).await?;
+        }
+        Ok(BookDeletion {
+            book_id,
+            title,
+            chapters: counts.chapters,
+            student_ids,
+            progress_records: counts.progress_records,
+            messages,
+        })
+    }
+
+    /// the students learning the book, by their teacher agents
+    async fn book_student_ids(&self, book_id: i64) -> anyhow::Result<Vec<i64>> {
+        let student_ids = sqlx::query_scalar!(
+            "select student_id from teacher_agent where book_id = ? order by student_id",
+            book_id
+        )
+        .fetch_all(&self.database)
+        .await?;
+        Ok(student_ids)
+    }
+
+    /// upsert the book and its chapters, rows are updated in place so the student data of kept chapters survives
     async fn store_book_to_db(&self, book: &Book) -> anyhow::Result<()> {
         let authors = book.authors.join(",");
         let description = book.description.clone().unwrap_or_default();
+        let now = OffsetDateTime::now_utc();
         sqlx::query!(
-            "insert or replace into book (id, title, authors, description) values (?, ?, ?, ?)",
+            "insert into book (id, title, authors, description, create_time) values (?, ?, ?, ?, ?)
+            on conflict (id) do update set title = excluded.title, authors = excluded.authors, description = excluded.description",
             book.id,
             book.title,
             authors,
-            description
+            description,
+            now
         )
         .execute(&self.database)
         .await?;
-        sqlx::query!("delete from chapter where book_id = ?", book.id)
+        let stored: Vec<String> = sqlx::query_scalar!(
+            "select chapter_number from chapter where book_id = ?",
+            book.id
+        )
+        .fetch_all(&self.database)
+        .await?;
+        let numbers: HashSet<String> = book.chapters.keys().map(|n| n.to_string()).collect();
+        for number in stored.iter().filter(|n| !numbers.contains(*n)) {
+            sqlx::query!(
+                "delete from chapter where book_id = ? and chapter_number = ?",
+                book.id,
+                number
+            )
             .execute(&self.database)
             .await?;
+        }
         for (number, chapter) in book.chapters.iter() {
             let number = number.to_string();
             sqlx::query!(
-                "insert or replace into chapter (book_id, chapter_number, name) values (?, ?, ?)",
+                "insert into chapter (book_id, chapter_number, name) values (?, ?, ?)
+                on conflict (book_id, chapter_number) do update set name = excluded.name",
                 book.id,
                 number,
                 chapter.name
@@ -116,9 +642,76 @@ impl Library {
             .execute(&self.database)
             .await?;
         }
+        search::index_book(&self.database, book).await?;
         Ok(())
     }
 
+    /// full-text search over the chapters of a book, books stored before the index existed are indexed first
+    pub async fn search_book(
+        &self,
+        book_id: i64,
+        query: &str,
+        limit: i64,
+    ) -> anyhow::Result<Vec<SearchHit>> {
+        let book = self.get_book(book_id).await?;
+        if !search::is_indexed(&self.database, book_id).await? {
+            search::index_book(&self.database, &book).await?;
+        }
+        search::search(&self.database, book_id, query, limit).await
+    }
+
+    /// chunks of a book closest in meaning to `query`, the book is embedded on its first search;
+    /// the query of a student is charged to them
+    pub async fn semantic_search(
+        &self,
+        book_id: i64,
+        query: &str,
+        limit: usize,
+        student_id: Option<i64>,
+    ) -> anyhow::Result<Vec<SemanticHit>> {
+        let book = self.get_book(book_id).await?;
+        let store = self.vector_store.as_ref();
+        if !embeddings::is_indexed(store, book_id).await? {
+            embeddings::index_book(store, &book).await?;
+        }
+        let provider = match student_id {
+            Some(student_id) => usage::metered(
+                DEFAULT_PROVIDER.clone(),
+                &self.database,
+                student_id,
+                book_id,
+            ),
+            None => DEFAULT_PROVIDER.clone(),
+        };
+        embeddings::search(store, provider.as_ref(), book_id, query, limit).await
+    }
+
+    /// chapters of a book matching `query` by keywords or by meaning, ranked by `fusion`; the
+    /// keyword ranking alone when the book can't be embedded
+    pub async fn hybrid_search(
+        &self,
+        book_id: i64,
+        query: &str,
+        limit: usize,
+        fusion: Fusion,
+        student_id: Option<i64>,
+    ) -> anyhow::Result<Vec<HybridHit>> {
+        // deeper than the limit, a chapter found both ways may rank low in each
+        let candidates = (limit * 4).max(20);
+        let keyword = self.search_book(book_id, query, candidates as i64).await?;
+        let semantic = match self
+            .semantic_search(book_id, query, candidates, student_id)
+            .await
+        {
+            Ok(semantic) => semantic,
+            Err(e) => {
+                warn!("semantic search of book {} failed: {}", book_id, e);
+                vec![]
+            }
+        };
+        Ok(search::fuse(&keyword, &semantic, fusion, limit))
+    }
+
     pub async fn restore_db_from_bookbase(&self) -> anyhow::Result<()> {
         let mut entries = tokio::fs::read_dir(&self.bookbase).await?;
         while let Some(entry) = entries.next_entry().await? {
@@ -140,7 +733,9 @@ impl Library {
             if existing.is_some() {
                 continue;
             }
-            let book = match Book::load(&path).await {
+            let provider = self.provider(None, Some(book_id)).await?;
+            let prompts = PromptTemplates::load(&self.database).await?;
+            let book = match Book::load(&path, provider.as_ref(), &prompts).await {
                 Ok(book) => book,
                 Err(e) => {
                     error!("load book {} failed: {}", path.display(), e);
@@ -159,7 +754,10 @@ impl Library {
 
     pub async fn upload_book_from_mdbook(&self, path: impl AsRef<Path>) -> anyhow::Result<i64> {
         let path = path.as_ref();
-        let book = Book::load(path).await?;
+        custom_tools::load(path)?;
+        let provider = self.provider(None, None).await?;
+        let prompts = PromptTemplates::load(&self.database).await?;
+        let book = Book::load(path, provider.as_ref(), &prompts).await?;
 
         // Check if the book already exists in the database
         let existing = sqlx::query!("SELECT id FROM book WHERE id = ?", book.id)
@@ -187,6 +785,9 @@ impl Library {
 
         // Insert or replace book in the database
         self.store_book_to_db(&book).await?;
+        if let Err(e) = self.detect_cover(&book).await {
+            error!("detect cover of book {} failed: {}", book.id, e);
+        }
         info!(
             "add book {}-{} from {} success",
             book.id,
@@ -196,6 +797,15 @@ impl Library {
         Ok(book.id)
     }
 
+    /// import a PDF, chapters come from its headings or, without headings, from page ranges
+    pub async fn import_pdf(&self, path: impl AsRef<Path>) -> anyhow::Result<i64> {
+        let path = path.as_ref().to_path_buf();
+        let output_dir = tempfile::tempdir()?;
+        let output_path = output_dir.path().to_path_buf();
+        spawn_blocking(move || pdf::convert_pdf_to_mdbook(&path, &output_path)).await??;
+        self.upload_book_from_mdbook(&output_dir).await
+    }
+
     pub async fn upload_book(&self, path: impl AsRef<Path>) -> anyhow::Result<i64> {
         let path = path.as_ref();
         if path.is_dir() {
@@ -210,15 +820,24 @@ impl Library {
                     })
                     .await
                 }
-                Some(ext) if ext == "zip" => {
+                Some(ext) if ext == "pdf" => self.import_pdf(path).await,
+                Some(ext) if ext == notebook::EXTENSION => {
                     block_in_place(async || -> anyhow::Result<i64> {
                         let output_dir = tempfile::tempdir()?;
-                        let mut zip = ZipArchive::new(File::open(path)?)?;
-                        zip.extract(&output_dir)?;
+                        notebook::notebook_to_mdbook(path, output_dir.path())?;
                         self.upload_book_from_mdbook(&output_dir).await
                     })
                     .await
                 }
+                Some(ext) if ext == "zip" => {
+                    block_in_place(async || -> anyhow::Result<i64> {
+                        let output_dir = tempfile::tempdir()?;
+                        extract_zip(path, output_dir.path())?;
+                        let root = book_root(output_dir.path(), path)?;
+                        self.upload_book_from_mdbook(root).await
+                    })
+                    .await
+                }
                 _ => Err(anyhow::anyhow!("Invalid book path: {}", path.display())),
             }
         } else {
@@ -226,6 +845,61 @@ impl Library {
         }
     }
 
+    /// extract an uploaded zip of an mdbook, or convert an uploaded EPUB, into the staging
+    /// directory of the bookbase and check its `SUMMARY.md` and that it is new, the plans are
+    /// generated by [`import_staged_book`](Self::import_staged_book)
+    pub async fn stage_book(&self, archive: &Path) -> anyhow::Result<StagedBook> {
+        let staging = self.bookbase.join("staging");
+        tokio::fs::create_dir_all(&staging).await?;
+        let staging_dir = tempfile::Builder::new()
+            .prefix("upload_")
+            .tempdir_in(&staging)?;
+        let archive = archive.to_path_buf();
+        let output_dir = staging_dir.path().to_path_buf();
+        let path = spawn_blocking(move || -> anyhow::Result<PathBuf> {
+            match archive.extension().map(|s| s.to_string_lossy()) {
+                Some(ext) if ext == "zip" => {
+                    extract_zip(&archive, &output_dir)?;
+                }
+                Some(ext) if ext == "epub" => {
+                    epub2mdbook::convert_epub_to_mdbook(&archive, &output_dir, false)?;
+                }
+                Some(ext) if ext == notebook::EXTENSION => {
+                    notebook::notebook_to_mdbook(&archive, &output_dir)?;
+                }
+                _ => bail!("Upload a .zip of an mdbook or of notebooks, an .epub or an .ipynb"),
+            }
+            let root = book_root(&output_dir, &archive)?;
+            validation::check_summary(&root)?;
+            custom_tools::load(&root)?;
+            Ok(root)
+        })
+        .await??;
+        let book = BookRaw::load(&path).await?;
+        let existing = sqlx::query_scalar!("select id from book where id = ?", book.id)
+            .fetch_optional(&self.database)
+            .await?;
+        if existing.is_some() {
+            bail!("Book with ID {} already exists", book.id);
+        }
+        Ok(StagedBook {
+            book_id: book.id,
+            title: book.title,
+            path,
+            staging_dir: staging_dir.into_path(),
+        })
+    }
+
+    /// import a staged book, generating its plans, and remove the staging directory
+    pub async fn import_staged_book(&self, staged: &StagedBook) -> anyhow::Result<()> {
+        let book_id = self.upload_book_from_mdbook(&staged.path).await?;
+        if book_id != staged.book_id {
+            bail!("Book ID mismatch: {} != {}", staged.book_id, book_id);
+        }
+        tokio::fs::remove_dir_all(&staged.staging_dir).await?;
+        Ok(())
+    }
+
     pub async fn set_book_public(&self, book_id: i64, is_public: bool) -> anyhow::Result<()> {
         sqlx::query!(
             "update book set is_public = ? where id = ?",
@@ -237,6 +911,107 @@ impl Library {
         Ok(())
     }
 
+    /// drop the plan of the chapter and reload the book, which generates and scores a new one
+    pub async fn regenerate_chapter_plan(
+        &self,
+        book_id: i64,
+        chapter_number: &ChapterNumber,
+    ) -> anyhow::Result<Arc<Book>> {
+        let book_path = self.bookbase.join(format!("book_{}", book_id));
+        let mut plan = BookTeachingPlan::load(&book_path).await?;
+        if plan.chapter_plans.remove(chapter_number).is_none() {
+            bail!("Chapter not found: {}", chapter_number);
+        }
+        plan.plan_quality.remove(chapter_number);
+        plan.save(&book_path).await?;
+        self.books.invalidate(&book_id).await;
+        self.load_book(book_id).await
+    }
+
+    /// which of the chapters [`regenerate_chapter_plan`](Self::regenerate_chapter_plan) would regenerate
+    pub async fn preview_regenerate_chapter_plans(
+        &self,
+        book_id: i64,
+        chapter_numbers: &[ChapterNumber],
+    ) -> anyhow::Result<BatchPreview<ChapterNumber>> {
+        let book_path = self.bookbase.join(format!("book_{}", book_id));
+        let plan = BookTeachingPlan::load(&book_path).await?;
+        let mut preview = BatchPreview::default();
+        for chapter_number in chapter_numbers {
+            if plan.chapter_plans.contains_key(chapter_number) {
+                preview.affected.push(chapter_number.clone());
+            } else {
+                preview.skip(chapter_number, "Chapter not found");
+            }
+        }
+        Ok(preview)
+    }
+
+    /// reload a book after its sources in the bookbase changed, keeping its id,
+    /// the plans of unchanged chapters and the student data of the chapters that still exist
+    pub async fn reimport_book(&self, book_id: i64) -> anyhow::Result<ReimportReport> {
+        let book_path = self.bookbase.join(format!("book_{}", book_id));
+        let mut plan = BookTeachingPlan::load(&book_path).await.unwrap_or_default();
+        let old_hashes = plan.chapter_hashes.clone();
+        if plan.book_id != Some(book_id) {
+            plan.book_id = Some(book_id);
+            plan.save(&book_path).await?;
+        }
+        self.books.invalidate(&book_id).await;
+        let book = self.load_book(book_id).await?;
+        let new_hashes = BookTeachingPlan::load(&book_path).await?.chapter_hashes;
+        self.store_book_to_db(&book).await?;
+        if let Err(e) = self.detect_cover(&book).await {
+            error!("detect cover of book {} failed: {}", book_id, e);
+        }
+        embeddings::remove_book(self.vector_store.as_ref(), book_id).await?;
+        let report = ReimportReport::diff(&old_hashes, &new_hashes);
+        info!(
+            "reimport book {}: {} added, {} changed, {} removed",
+            book_id,
+            report.added.len(),
+            report.changed.len(),
+            report.removed.len()
+        );
+        Ok(report)
+    }
+
+    /// what [`reimport_book`](Self::reimport_book) would change, without generating plans or touching the database
+    pub async fn preview_reimport(&self, book_id: i64) -> anyhow::Result<ReimportReport> {
+        if sqlx::query_scalar!("select id from book where id = ?", book_id)
+            .fetch_optional(&self.database)
+            .await?
+            .is_none()
+        {
+            bail!("Book not found: {}", book_id);
+        }
+        let book_path = self.bookbase.join(format!("book_{}", book_id));
+        let plan = BookTeachingPlan::load(&book_path).await.unwrap_or_default();
+        let book_raw = BookRaw::load(&book_path).await?;
+        let hashes = book_raw
+            .iter()
+            .map(|ch| (ch.number.clone(), ch.content_hash()))
+            .collect();
+        Ok(ReimportReport::diff(&plan.chapter_hashes, &hashes))
+    }
+
+    /// accept a low-scoring chapter plan after human review
+    pub async fn approve_chapter_plan(
+        &self,
+        book_id: i64,
+        chapter_number: &ChapterNumber,
+    ) -> anyhow::Result<()> {
+        let book_path = self.bookbase.join(format!("book_{}", book_id));
+        let mut plan = BookTeachingPlan::load(&book_path).await?;
+        let Some(quality) = plan.plan_quality.get_mut(chapter_number) else {
+            bail!("No score for the plan of chapter {}", chapter_number);
+        };
+        quality.needs_review = false;
+        plan.save(&book_path).await?;
+        self.books.invalidate(&book_id).await;
+        Ok(())
+    }
+
     pub async fn upload_books_in_dir(&self, dir: impl AsRef<Path>) -> anyhow::Result<()> {
         let mut entries = tokio::fs::read_dir(dir).await?;
         while let Some(entry) = entries.next_entry().await? {
@@ -249,24 +1024,277 @@ impl Library {
     }
 
     pub async fn get_book_list(&self, public_only: bool) -> anyhow::Result<Vec<BookMeta>> {
-        let books = sqlx::query!("select id, title, authors, description, is_public from book")
-            .fetch_all(&self.database)
-            .await?;
-        let mut book_list = Vec::new();
-        for book in books {
-            if public_only && !book.is_public {
-                continue;
-            }
-            let book_meta = BookMeta {
+        self.find_books(public_only, &BookQuery::default()).await
+    }
+
+    /// the books matching the filters of `query`, unordered
+    pub async fn find_books(
+        &self,
+        public_only: bool,
+        query: &BookQuery,
+    ) -> anyhow::Result<Vec<BookMeta>> {
+        let title = query.title.as_deref().map(str::trim);
+        let tag = query.tag.as_deref().map(normalize_tag);
+        let language = query.language.as_deref().map(str::trim);
+        let difficulty = query.difficulty.map(Difficulty::as_str);
+        let books = sqlx::query!(
+            r#"select id, title, authors, description, is_public, tags, language, difficulty,
+            cover_path is not null as "has_cover!: bool",
+            archive_time is not null as "archived!: bool",
+            create_time as "create_time: OffsetDateTime"
+            from book
+            where (?1 is null or instr(lower(title), lower(?1)) > 0)
+            and (?2 is null or instr(',' || tags || ',', ',' || ?2 || ',') > 0)
+            and (not ?3 or (is_public and archive_time is null))
+            and (?4 is null or lower(language) = lower(?4))
+            and (?5 is null or difficulty = ?5)"#,
+            title,
+            tag,
+            public_only,
+            language,
+            difficulty
+        )
+        .fetch_all(&self.database)
+        .await?;
+        Ok(books
+            .into_iter()
+            .map(|book| BookMeta {
                 id: book.id,
                 title: book.title,
                 authors: book.authors.split(',').map(|s| s.to_string()).collect(),
                 description: book.description,
                 is_public: book.is_public,
-            };
-            book_list.push(book_meta);
+                archived: book.archived,
+                tags: split_tags(&book.tags),
+                create_time: book.create_time,
+                language: book.language,
+                difficulty: book
+                    .difficulty
+                    .as_deref()
+                    .and_then(|difficulty| Difficulty::try_from(difficulty).ok()),
+                has_cover: book.has_cover,
+            })
+            .collect())
+    }
+
+    /// a page of the books matching `query` in its order
+    pub async fn list_books(
+        &self,
+        public_only: bool,
+        query: &BookQuery,
+        page: &PageQuery,
+    ) -> anyhow::Result<Paginated<BookMeta>> {
+        let mut books = self.find_books(public_only, query).await?;
+        match query.order_by.unwrap_or_default() {
+            BookOrder::Id => page.paginate(books, SortOrder::Asc, |book| book.id),
+            BookOrder::Title => {
+                books.sort_by_cached_key(|book| (book.title.to_lowercase(), book.id));
+                page.paginate_positions(books, SortOrder::Asc)
+            }
+            BookOrder::Added => {
+                books.sort_by_key(|book| (book.create_time, book.id));
+                page.paginate_positions(books, SortOrder::Desc)
+            }
+        }
+    }
+
+    /// replace the tags of the book, tags are lowercase and without commas
+    pub async fn set_book_tags(
+        &self,
+        book_id: i64,
+        tags: &[String],
+    ) -> anyhow::Result<Vec<String>> {
+        let normalized = normalize_tags(tags);
+        let joined = normalized.join(",");
+        let updated = sqlx::query!("update book set tags = ? where id = ?", joined, book_id)
+            .execute(&self.database)
+            .await?;
+        if updated.rows_affected() == 0 {
+            bail!("Book not found: {}", book_id);
         }
-        Ok(book_list)
+        Ok(normalized)
+    }
+
+    pub async fn get_book_metadata(&self, book_id: i64) -> anyhow::Result<BookMetadata> {
+        let Some(book) = sqlx::query!(
+            "select authors, language, difficulty, tags, cover_path from book where id = ?",
+            book_id
+        )
+        .fetch_optional(&self.database)
+        .await?
+        else {
+            bail!("Book not found: {}", book_id);
+        };
+        Ok(BookMetadata {
+            authors: split_tags(&book.authors),
+            language: book.language,
+            difficulty: book
+                .difficulty
+                .as_deref()
+                .map(Difficulty::try_from)
+                .transpose()?,
+            tags: split_tags(&book.tags),
+            cover_path: book.cover_path,
+        })
+    }
+
+    /// replace the metadata of the book, the cover must be an image in the book directory
+    pub async fn set_book_metadata(
+        &self,
+        book_id: i64,
+        metadata: &BookMetadata,
+    ) -> anyhow::Result<BookMetadata> {
+        let book_path = self.bookbase.join(format!("book_{}", book_id));
+        if let Some(cover_path) = &metadata.cover_path {
+            check_cover_path(cover_path)?;
+            if !tokio::fs::try_exists(book_path.join(cover_path)).await? {
+                bail!("cover not found in the book: {cover_path}");
+            }
+        }
+        let authors: Vec<String> = metadata
+            .authors
+            .iter()
+            .map(|author| author.replace(',', " ").trim().to_string())
+            .filter(|author| !author.is_empty())
+            .collect();
+        let language = metadata
+            .language
+            .as_deref()
+            .map(str::trim)
+            .filter(|language| !language.is_empty())
+            .map(str::to_string);
+        let metadata = BookMetadata {
+            authors,
+            language,
+            difficulty: metadata.difficulty,
+            tags: normalize_tags(&metadata.tags),
+            cover_path: metadata.cover_path.clone(),
+        };
+        let authors = metadata.authors.join(",");
+        let difficulty = metadata.difficulty.map(Difficulty::as_str);
+        let tags = metadata.tags.join(",");
+        let updated = sqlx::query!(
+            "update book set authors = ?, language = ?, difficulty = ?, tags = ?, cover_path = ?
+            where id = ?",
+            authors,
+            metadata.language,
+            difficulty,
+            tags,
+            metadata.cover_path,
+            book_id
+        )
+        .execute(&self.database)
+        .await?;
+        if updated.rows_affected() == 0 {
+            bail!("Book not found: {}", book_id);
+        }
+        let _ = tokio::fs::remove_dir_all(self.covers_dir(book_id)).await;
+        Ok(metadata)
+    }
+
+    /// drop the metadata set by admins, the authors are restored from book.toml
+    pub async fn clear_book_metadata(&self, book_id: i64) -> anyhow::Result<BookMetadata> {
+        let book = self.get_book(book_id).await?;
+        let metadata = BookMetadata {
+            authors: book.authors.clone(),
+            ..Default::default()
+        };
+        self.set_book_metadata(book_id, &metadata).await
+    }
+
+    /// the cover image of the book at `size`, `None` without one or for a book students can't see
+    /// when `public_only`; thumbnails are generated on the first request
+    pub async fn book_cover(
+        &self,
+        book_id: i64,
+        public_only: bool,
+        size: CoverSize,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        let cover_path = sqlx::query_scalar!(
+            "select cover_path from book
+            where id = ? and (not ? or (is_public and archive_time is null))",
+            book_id,
+            public_only
+        )
+        .fetch_optional(&self.database)
+        .await?
+        .flatten();
+        let Some(cover_path) = cover_path else {
+            return Ok(None);
+        };
+        let cover = self
+            .bookbase
+            .join(format!("book_{}", book_id))
+            .join(cover_path);
+        let thumbnails_dir = self.covers_dir(book_id);
+        let path =
+            spawn_blocking(move || cover::thumbnail(&cover, &thumbnails_dir, size)).await??;
+        Ok(Some(path))
+    }
+
+    /// the tools declared in the `tools.toml` of the book, see [`custom_tools::load`]
+    pub async fn custom_tools(&self, book_id: i64) -> anyhow::Result<BookTools> {
+        let book_dir = self.bookbase.join(format!("book_{}", book_id));
+        spawn_blocking(move || custom_tools::load(&book_dir)).await?
+    }
+
+    /// a plugin declared by the book, compiled and asked for its definition
+    #[cfg(feature = "plugins")]
+    pub async fn plugin_tool(
+        &self,
+        book_id: i64,
+        spec: custom_tools::PluginSpec,
+    ) -> anyhow::Result<super::plugins::PluginTool> {
+        let book_dir = self.bookbase.join(format!("book_{}", book_id));
+        super::plugins::PluginTool::load(&book_dir, spec).await
+    }
+
+    /// the `RunTests` tool of the exercises declared by the book
+    #[cfg(feature = "plugins")]
+    pub async fn test_runner(
+        &self,
+        book_id: i64,
+        exercises: Vec<custom_tools::ExerciseSpec>,
+    ) -> anyhow::Result<super::plugins::TestRunnerTool> {
+        let book_dir = self.bookbase.join(format!("book_{}", book_id));
+        super::plugins::TestRunnerTool::load(&book_dir, exercises).await
+    }
+
+    /// the image or audio file at `path`, relative to the `src` directory of the book, see
+    /// [`assets::asset_path`]
+    pub async fn book_asset(&self, book_id: i64, path: &str) -> anyhow::Result<PathBuf> {
+        let src_dir = self.book_src_dir(book_id).await?;
+        let path = path.to_string();
+        spawn_blocking(move || assets::asset_path(&src_dir, &path)).await?
+    }
+
+    /// the datasets the book ships in its `src` directory, see [`datasets::find_datasets`]
+    pub async fn book_datasets(&self, book_id: i64) -> anyhow::Result<Vec<datasets::DatasetInfo>> {
+        let src_dir = self.book_src_dir(book_id).await?;
+        Ok(spawn_blocking(move || datasets::find_datasets(&src_dir)).await?)
+    }
+
+    /// the schema and first rows of a dataset of the book, see [`datasets::preview`]
+    pub async fn dataset_preview(
+        &self,
+        book_id: i64,
+        path: &str,
+        rows: usize,
+    ) -> anyhow::Result<datasets::DatasetPreview> {
+        let src_dir = self.book_src_dir(book_id).await?;
+        let path = path.to_string();
+        spawn_blocking(move || datasets::preview(&src_dir, &path, rows)).await?
+    }
+
+    /// the `src` directory of the book, from its `book.toml`
+    async fn book_src_dir(&self, book_id: i64) -> anyhow::Result<PathBuf> {
+        let book_dir = self.bookbase.join(format!("book_{}", book_id));
+        let book_toml = tokio::fs::read_to_string(book_dir.join("book.toml")).await?;
+        Ok(book_dir.join(
+            toml::from_str::<mdbook::config::Config>(&book_toml)?
+                .book
+                .src,
+        ))
     }
 }
 
@@ -289,4 +1317,55 @@ mod tests {
         };
         server.upload_books_in_dir("./test-book").await.unwrap();
     }
+
+    #[test]
+    fn reimport_diff() {
+        let hashes = |entries: &[(&str, u64)]| -> BTreeMap<ChapterNumber, u64> {
+            entries
+                .iter()
+                .map(|(number, hash)| (number.parse().unwrap(), *hash))
+                .collect()
+        };
+        let report = ReimportReport::diff(
+            &hashes(&[("1.", 1), ("2.", 2), ("3.", 3)]),
+            &hashes(&[("1.", 1), ("2.", 20), ("4.", 4)]),
+        );
+        assert_eq!(report.added, ["4.".parse::<ChapterNumber>().unwrap()]);
+        assert_eq!(report.changed, ["2.".parse::<ChapterNumber>().unwrap()]);
+        assert_eq!(report.removed, ["3.".parse::<ChapterNumber>().unwrap()]);
+    }
+
+    #[test]
+    fn cover_paths() {
+        assert!(check_cover_path("src/images/cover.PNG").is_ok());
+        assert!(check_cover_path("../book_2/cover.png").is_err());
+        assert!(check_cover_path("/etc/cover.png").is_err());
+        assert!(check_cover_path("src/SUMMARY.md").is_err());
+        assert!(check_cover_path("").is_err());
+    }
+
+    #[test]
+    fn zip_limits() {
+        use std::io::Write;
+        use zip::{ZipWriter, write::SimpleFileOptions};
+
+        let dir = tempfile::tempdir().unwrap();
+        let write_zip = |name: &str, entries: usize| {
+            let path = dir.path().join(name);
+            let mut zip = ZipWriter::new(File::create(&path).unwrap());
+            for i in 0..entries {
+                zip.start_file(format!("src/{i}.md"), SimpleFileOptions::default())
+                    .unwrap();
+                zip.write_all(b"# Chapter").unwrap();
+            }
+            zip.finish().unwrap();
+            path
+        };
+        let small = write_zip("small.zip", 3);
+        extract_zip(&small, &dir.path().join("small")).unwrap();
+        assert!(dir.path().join("small/src/2.md").is_file());
+        let many = write_zip("many.zip", MAX_ZIP_ENTRIES + 1);
+        assert!(extract_zip(&many, &dir.path().join("many")).is_err());
+        assert!(!dir.path().join("many").exists());
+    }
 }
@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::LazyLock,
+};
+
+use regex::{Captures, Regex};
+
+use super::chapter::{ChapterNumber, ChapterNumberError};
+
+/// markdown inline links and images: `[text](target "title")`
+static LINK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(!?)\[([^\]]*)\]\(([^)\s]+)(\s+"[^"]*")?\)"#).unwrap());
+
+/// Reference to a chapter and an optional heading anchor in it, written as `chapter:4.2.#anchor`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChapterLink {
+    pub chapter_number: ChapterNumber,
+    pub anchor: Option<String>,
+}
+
+impl ChapterLink {
+    pub const SCHEME: &str = "chapter:";
+}
+
+impl Display for ChapterLink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", Self::SCHEME, self.chapter_number)?;
+        if let Some(anchor) = &self.anchor {
+            write!(f, "#{anchor}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ChapterLink {
+    type Err = ChapterNumberError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.strip_prefix(Self::SCHEME).unwrap_or(s);
+        let (number, anchor) = match s.split_once('#') {
+            Some((number, anchor)) => (number, Some(anchor.to_string())),
+            None => (s, None),
+        };
+        Ok(ChapterLink {
+            chapter_number: number.parse()?,
+            anchor,
+        })
+    }
+}
+
+/// resolve `.` and `..` without touching the file system
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn is_external(target: &str) -> bool {
+    target.contains("://") || target.starts_with("mailto:") || target.starts_with('/')
+}
+
+/// Rewrite relative links to other chapters into [`ChapterLink`]s.
+///
+/// `chapter_path` is the path of the chapter relative to the book `src` directory and
+/// `paths` maps those paths to chapter numbers. Returns the rewritten content and
+/// the link targets that look like chapters but could not be resolved.
+pub fn rewrite_links(
+    content: &str,
+    number: &ChapterNumber,
+    chapter_path: Option<&Path>,
+    paths: &HashMap<PathBuf, ChapterNumber>,
+) -> (String, Vec<String>) {
+    let dir = chapter_path
+        .and_then(|p| p.parent())
+        .unwrap_or(Path::new(""));
+    let mut unresolved = Vec::new();
+    let content = LINK.replace_all(content, |caps: &Captures| {
+        let target = &caps[3];
+        if !caps[1].is_empty() || is_external(target) {
+            return caps[0].to_string();
+        }
+        let (file, anchor) = match target.split_once('#') {
+            Some((file, anchor)) => (file, Some(anchor.to_string())),
+            None => (target, None),
+        };
+        let chapter_number = if file.is_empty() {
+            number.clone()
+        } else {
+            let file = match file.strip_suffix(".html") {
+                Some(stem) => format!("{stem}.md"),
+                None => file.to_string(),
+            };
+            if !file.ends_with(".md") {
+                return caps[0].to_string();
+            }
+            match paths.get(&normalize_path(&dir.join(file))) {
+                Some(number) => number.clone(),
+                None => {
+                    unresolved.push(target.to_string());
+                    return caps[0].to_string();
+                }
+            }
+        };
+        let link = ChapterLink {
+            chapter_number,
+            anchor,
+        };
+        let title = caps.get(4).map_or("", |m| m.as_str());
+        format!("[{}]({link}{title})", &caps[2])
+    });
+    (content.into_owned(), unresolved)
+}
+
+#[test]
+fn rewrite() {
+    let paths = HashMap::from([
+        (PathBuf::from("ch04/s02.md"), "4.2.".parse().unwrap()),
+        (PathBuf::from("ch03/intro.md"), "3.".parse().unwrap()),
+    ]);
+    let number: ChapterNumber = "3.".parse().unwrap();
+    let content = "see [here](../ch04/s02.md#verbs), [top](#intro), [page](../ch04/s02.html), \
+        [gone](missing.md), [web](https://example.com/a.md), ![img](fig.png)";
+    let (content, unresolved) =
+        rewrite_links(content, &number, Some(Path::new("ch03/intro.md")), &paths);
+    assert_eq!(
+        content,
+        "see [here](chapter:4.2.#verbs), [top](chapter:3.#intro), [page](chapter:4.2.), \
+        [gone](missing.md), [web](https://example.com/a.md), ![img](fig.png)"
+    );
+    assert_eq!(unresolved, vec!["missing.md".to_string()]);
+    let link: ChapterLink = "chapter:4.2.#verbs".parse().unwrap();
+    assert_eq!(link.anchor.as_deref(), Some("verbs"));
+}
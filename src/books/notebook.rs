@@ -0,0 +1,321 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use walkdir::WalkDir;
+
+/// the extension of Jupyter notebooks, imported as chapters
+pub const EXTENSION: &str = "ipynb";
+
+/// longest text output of a cell kept in the chapter, in characters
+const MAX_TEXT_OUTPUT_CHARS: usize = 20_000;
+/// largest image output of a cell kept in the chapter, base64 encoded, in bytes
+const MAX_IMAGE_OUTPUT_BYTES: usize = 1024 * 1024;
+
+/// colors and cursor moves of terminal output, e.g. in tracebacks
+static ANSI_ESCAPE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\x1b\[[0-9;?]*[A-Za-z]").unwrap());
+
+/// A Jupyter notebook, format 4, with only what the chapter needs
+#[derive(Debug, Deserialize)]
+struct Notebook {
+    cells: Vec<Cell>,
+    #[serde(default)]
+    metadata: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct Cell {
+    cell_type: String,
+    #[serde(default)]
+    source: Source,
+    #[serde(default)]
+    outputs: Vec<Output>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Output {
+    output_type: String,
+    /// `stream` outputs
+    #[serde(default)]
+    text: Source,
+    /// `execute_result` and `display_data` outputs, by mime type
+    #[serde(default)]
+    data: Map<String, Value>,
+    /// `error` outputs
+    #[serde(default)]
+    ename: String,
+    #[serde(default)]
+    evalue: String,
+}
+
+/// multiline strings of notebooks are a string or a list of lines
+#[derive(Debug, Default, Deserialize)]
+#[serde(untagged)]
+enum Source {
+    #[default]
+    Empty,
+    Text(String),
+    Lines(Vec<String>),
+}
+
+impl Source {
+    fn text(&self) -> String {
+        match self {
+            Source::Empty => String::new(),
+            Source::Text(text) => text.clone(),
+            Source::Lines(lines) => lines.concat(),
+        }
+    }
+}
+
+/// a mime bundle value is a string or a list of lines too
+fn mime_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(lines) => Some(lines.iter().filter_map(Value::as_str).collect()),
+        _ => None,
+    }
+}
+
+fn truncate(text: &str) -> String {
+    let text = ANSI_ESCAPE.replace_all(text.trim_end(), "");
+    match text.char_indices().nth(MAX_TEXT_OUTPUT_CHARS) {
+        Some((end, _)) => format!("{}\n[output truncated]", &text[..end]),
+        None => text.into_owned(),
+    }
+}
+
+/// a fenced block longer than any backtick run in `text`, so outputs can't close it
+fn fenced(language: &str, text: &str) -> String {
+    let longest = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{fence}{language}\n{text}\n{fence}\n")
+}
+
+/// the markdown of the richest representation of a display output
+fn display_output(data: &Map<String, Value>) -> Option<String> {
+    for mime in ["image/png", "image/jpeg", "image/gif"] {
+        if let Some(image) = data.get(mime).and_then(mime_text) {
+            let image: String = image.split_whitespace().collect();
+            if image.len() > MAX_IMAGE_OUTPUT_BYTES {
+                return Some("*[image output too large, omitted]*\n".to_string());
+            }
+            return Some(format!("![output](data:{mime};base64,{image})\n"));
+        }
+    }
+    if let Some(markdown) = data.get("text/markdown").and_then(mime_text) {
+        return Some(format!("{}\n", markdown.trim_end()));
+    }
+    // svg plots become images so their scripts never run, html outputs, e.g. data frames,
+    // fall back to their text: the notebooks are uploaded by students
+    if let Some(svg) = data.get("image/svg+xml").and_then(mime_text) {
+        if svg.len() > MAX_IMAGE_OUTPUT_BYTES {
+            return Some("*[image output too large, omitted]*\n".to_string());
+        }
+        return Some(format!(
+            "![output](data:image/svg+xml;base64,{})\n",
+            STANDARD.encode(svg)
+        ));
+    }
+    let text = data.get("text/plain").and_then(mime_text)?;
+    Some(fenced("text", &truncate(&text)))
+}
+
+/// Convert a notebook to the markdown of a chapter: markdown cells as they are, so their
+/// headings are the sections of the chapter, code cells as code blocks in the language of the
+/// kernel, and their outputs after them, in a `notebook-output` div
+pub fn to_markdown(json: &str) -> anyhow::Result<String> {
+    let notebook: Notebook = serde_json::from_str(json).context("Invalid Jupyter notebook")?;
+    let language = ["/kernelspec/language", "/language_info/name"]
+        .iter()
+        .find_map(|pointer| notebook.metadata.pointer(pointer)?.as_str())
+        .unwrap_or("python")
+        .to_lowercase();
+    let mut cells = Vec::new();
+    for cell in &notebook.cells {
+        let source = cell.source.text();
+        let markdown = match cell.cell_type.as_str() {
+            "markdown" => source.trim_end().to_string(),
+            "code" => {
+                let mut markdown = String::new();
+                if !source.trim().is_empty() {
+                    markdown.push_str(&fenced(&language, source.trim_end()));
+                }
+                let outputs: Vec<String> = cell
+                    .outputs
+                    .iter()
+                    .filter_map(|output| match output.output_type.as_str() {
+                        "stream" => Some(fenced("text", &truncate(&output.text.text()))),
+                        "error" => Some(fenced(
+                            "text",
+                            &truncate(&format!("{}: {}", output.ename, output.evalue)),
+                        )),
+                        "execute_result" | "display_data" => display_output(&output.data),
+                        _ => None,
+                    })
+                    .collect();
+                if !outputs.is_empty() {
+                    markdown.push_str(&format!(
+                        "\n<div class=\"notebook-output\">\n\n{}\n\n</div>",
+                        outputs.join("\n\n")
+                    ));
+                }
+                markdown.trim().to_string()
+            }
+            // raw cells are meant for other converters
+            _ => String::new(),
+        };
+        if !markdown.is_empty() {
+            cells.push(markdown);
+        }
+    }
+    Ok(cells.join("\n\n") + "\n")
+}
+
+/// the title of a notebook, its first heading
+fn notebook_title(json: &str) -> Option<String> {
+    let notebook: Notebook = serde_json::from_str(json).ok()?;
+    notebook
+        .cells
+        .iter()
+        .filter(|cell| cell.cell_type == "markdown")
+        .flat_map(|cell| {
+            cell.source
+                .text()
+                .lines()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .find_map(|line| {
+            let title = line.strip_prefix('#')?.trim_start_matches('#').trim();
+            (!title.is_empty()).then(|| title.to_string())
+        })
+}
+
+/// the notebooks under `dir`, by path, without the checkpoints Jupyter saves next to them
+pub fn find_notebooks(dir: &Path) -> Vec<PathBuf> {
+    let mut notebooks: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|entry| {
+            let name = entry.file_name().to_string_lossy();
+            name != ".ipynb_checkpoints" && name != "__MACOSX"
+        })
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == EXTENSION))
+        .collect();
+    notebooks.sort();
+    notebooks
+}
+
+/// Make an mdbook of a collection of notebooks, a chapter per notebook in path order: writes a
+/// `book.toml` and a `SUMMARY.md` into `dir`, which stays the source directory so the images
+/// the notebooks link are found. [`BookRaw::load`](super::book::BookRaw::load) converts the
+/// notebooks with [`to_markdown`].
+pub fn notebooks_to_mdbook(dir: &Path, title: &str) -> anyhow::Result<()> {
+    if dir.join("book.toml").exists() || dir.join("SUMMARY.md").exists() {
+        anyhow::bail!("{} is already an mdbook", dir.display());
+    }
+    let notebooks = find_notebooks(dir);
+    if notebooks.is_empty() {
+        anyhow::bail!("No Jupyter notebook found");
+    }
+    let mut summary = "# Summary\n\n".to_string();
+    for notebook in &notebooks {
+        let relative = notebook.strip_prefix(dir)?;
+        let json = std::fs::read_to_string(notebook)?;
+        let name = notebook_title(&json).unwrap_or_else(|| {
+            relative
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().replace(['_', '-'], " "))
+                .unwrap_or_default()
+        });
+        let link = relative.to_string_lossy().replace('\\', "/");
+        // a link with spaces needs the angle brackets
+        let link = if link.contains(' ') {
+            format!("<{link}>")
+        } else {
+            link
+        };
+        summary.push_str(&format!("- [{}]({link})\n", name.replace(['[', ']'], "")));
+    }
+    std::fs::write(
+        dir.join("book.toml"),
+        format!(
+            "[book]\ntitle = {}\nsrc = \".\"\n",
+            toml::Value::String(title.to_string())
+        ),
+    )?;
+    std::fs::write(dir.join("SUMMARY.md"), summary)?;
+    Ok(())
+}
+
+/// Make an mdbook in `output_dir` of a single notebook
+pub fn notebook_to_mdbook(notebook: &Path, output_dir: &Path) -> anyhow::Result<()> {
+    let file_name = notebook
+        .file_name()
+        .context("The notebook has no file name")?;
+    std::fs::create_dir_all(output_dir)?;
+    std::fs::copy(notebook, output_dir.join(file_name))?;
+    let title = notebook
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "Untitled".to_string());
+    notebooks_to_mdbook(output_dir, &title)
+}
+
+#[test]
+fn notebook_markdown() {
+    let json = r##"{
+        "metadata": {"kernelspec": {"language": "python", "name": "python3"}},
+        "nbformat": 4,
+        "cells": [
+            {"cell_type": "markdown", "source": ["# Pandas basics\n", "\n", "Load a frame."]},
+            {"cell_type": "code", "source": "import pandas as pd\ndf = pd.DataFrame({'a': [1]})\ndf",
+             "outputs": [
+                {"output_type": "stream", "name": "stdout", "text": ["loaded\n"]},
+                {"output_type": "execute_result", "data": {
+                    "text/plain": ["   a\n", "0  1"],
+                    "text/html": ["<table>\n", "\n", "<tr><td>1</td></tr>\n", "</table>"]
+                }},
+                {"output_type": "display_data", "data": {"image/png": "iVBORw0K\nGgo=\n", "text/plain": "<Figure>"}},
+                {"output_type": "display_data", "data": {"image/svg+xml": "<svg><script/></svg>", "text/plain": "<Figure>"}}
+             ]},
+            {"cell_type": "code", "source": "1/0", "outputs": [
+                {"output_type": "error", "ename": "ZeroDivisionError", "evalue": "division by zero",
+                 "traceback": ["\u001b[0;31m..."]}
+            ]},
+            {"cell_type": "raw", "source": "\\newpage"},
+            {"cell_type": "code", "source": "", "outputs": []}
+        ]
+    }"##;
+    let markdown = to_markdown(json).unwrap();
+    assert!(markdown.starts_with("# Pandas basics\n\nLoad a frame.\n\n```python\nimport pandas"));
+    assert!(markdown.contains("<div class=\"notebook-output\">\n\n```text\nloaded\n```"));
+    assert!(markdown.contains("```text\n   a\n0  1\n```"));
+    assert!(!markdown.contains("<table>"));
+    assert!(markdown.contains("![output](data:image/svg+xml;base64,PHN2Zz48c2NyaXB0Lz48L3N2Zz4=)"));
+    assert!(markdown.contains("![output](data:image/png;base64,iVBORw0KGgo=)"));
+    assert!(markdown.contains("ZeroDivisionError: division by zero"));
+    assert!(!markdown.contains("newpage"));
+    let sections = super::sections::extract_sections(&markdown);
+    assert_eq!(sections.len(), 1);
+    assert_eq!(sections[0].title, "Pandas basics");
+    assert_eq!(notebook_title(json).as_deref(), Some("Pandas basics"));
+
+    // an output can't close the fence around it
+    assert_eq!(fenced("text", "a ``` b"), "````text\na ``` b\n````\n");
+    assert!(to_markdown("{}").is_err());
+}
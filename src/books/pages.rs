@@ -0,0 +1,51 @@
+use std::{collections::BTreeMap, sync::LazyLock};
+
+use regex::Regex;
+
+use super::chapter::{Chapter, ChapterNumber};
+
+/// `<!-- page 212 -->` comments written by importers, and EPUB page break markers
+static PAGE_MARKER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"(?i)<!--\s*page[:\s]\s*(\d+)\s*-->|epub:type="pagebreak"[^>]*?(?:title|aria-label)="(\d+)""#,
+    )
+    .unwrap()
+});
+
+/// the marker importers put at the start of each original print page
+pub fn page_marker(page: u32) -> String {
+    format!("<!-- page {page} -->")
+}
+
+/// original page numbers marked in the content, in order of appearance
+pub fn page_markers(content: &str) -> Vec<u32> {
+    PAGE_MARKER
+        .captures_iter(content)
+        .filter_map(|c| c.get(1).or(c.get(2)))
+        .filter_map(|m| m.as_str().parse().ok())
+        .collect()
+}
+
+/// Map the first page of every marked page run to the chapter it starts in.
+///
+/// A page that has no marker of its own belongs to the chapter of the closest marked page before it.
+pub fn build_page_map<'a>(
+    chapters: impl IntoIterator<Item = &'a Chapter>,
+) -> BTreeMap<u32, ChapterNumber> {
+    let mut map = BTreeMap::new();
+    for ch in chapters {
+        for page in page_markers(&ch.content) {
+            map.entry(page).or_insert_with(|| ch.number.clone());
+        }
+    }
+    map
+}
+
+#[test]
+fn markers() {
+    let content = format!(
+        "{}\nintro\n<span epub:type=\"pagebreak\" role=\"doc-pagebreak\" title=\"13\"/>\n<!-- Page: 14 -->",
+        page_marker(12)
+    );
+    assert_eq!(page_markers(&content), vec![12, 13, 14]);
+}
@@ -0,0 +1,232 @@
+use std::{path::Path, sync::LazyLock};
+
+use regex::Regex;
+
+use super::pages::page_marker;
+
+/// title of the text before the first heading, imported as an unnumbered prefix chapter
+pub const FRONT_MATTER: &str = "Front Matter";
+
+/// pages per chapter when the PDF has no detectable headings
+pub const PAGES_PER_CHAPTER: usize = 10;
+
+/// `Chapter 3`, `CHAPTER IV: Title`, `第三章 标题`
+static CHAPTER_HEADING: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?:(?i:chapter)\s+(\d+|[IVXLC]+)\b[\s:.\-–—]*(.*)|第([一二三四五六七八九十百零\d]+)章\s*(.*))$",
+    )
+    .unwrap()
+});
+
+/// `3 Title` or `3. Title`
+static NUMBERED_HEADING: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\d{1,3})\.?\s+(\p{Lu}.{0,80})$").unwrap());
+
+/// `3.2 Title`
+static SECTION_HEADING: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(\d{1,3})\.(\d{1,3})\.?\s+(\S.{0,80})$").unwrap());
+
+/// A chapter or section detected in the PDF text
+#[derive(Debug, Clone, PartialEq)]
+pub struct PdfSection {
+    pub title: String,
+    /// 0 for chapters, 1 for sections
+    pub level: usize,
+    /// markdown with a page marker at the start of every page
+    pub content: String,
+}
+
+/// Heading of a line, with its level
+fn detect_heading(line: &str, current_chapter: Option<u32>) -> Option<(usize, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.chars().count() > 100 {
+        return None;
+    }
+    if CHAPTER_HEADING.is_match(line) {
+        return Some((0, line.to_string()));
+    }
+    if let Some(captures) = SECTION_HEADING.captures(line) {
+        let chapter: u32 = captures[1].parse().ok()?;
+        let title = &captures[3];
+        // a section of the current chapter, not a number at the start of a sentence
+        if Some(chapter) == current_chapter && !title.ends_with(['.', ',', ';']) {
+            return Some((1, line.to_string()));
+        }
+        return None;
+    }
+    if let Some(captures) = NUMBERED_HEADING.captures(line) {
+        let chapter: u32 = captures[1].parse().ok()?;
+        let title = &captures[2];
+        // only the next chapter in sequence, so numbered list items and figures are skipped
+        if chapter == current_chapter.map_or(1, |c| c + 1) && !title.ends_with(['.', ',', ';']) {
+            return Some((0, line.to_string()));
+        }
+    }
+    None
+}
+
+fn chapter_number_of(heading: &str) -> Option<u32> {
+    if let Some(captures) = NUMBERED_HEADING.captures(heading) {
+        return captures[1].parse().ok();
+    }
+    CHAPTER_HEADING
+        .captures(heading)?
+        .get(1)
+        .and_then(|n| n.as_str().parse().ok())
+}
+
+/// Split the text of the pages into chapters and sections by their headings,
+/// or into ranges of [`PAGES_PER_CHAPTER`] pages if fewer than two chapter headings are found.
+/// Text before the first heading becomes a [`FRONT_MATTER`] section at level 0.
+pub fn split_sections(pages: &[String]) -> Vec<PdfSection> {
+    let mut sections: Vec<PdfSection> = vec![];
+    let mut current_chapter = None;
+    let mut front_matter = String::new();
+    for (i, page) in pages.iter().enumerate() {
+        // the marker goes to the section the page starts in, which may begin on its first line
+        let mut marker = Some(format!("{}\n", page_marker(i as u32 + 1)));
+        for line in page.lines() {
+            if let Some((level, title)) = detect_heading(line, current_chapter) {
+                if level == 0 {
+                    current_chapter = chapter_number_of(&title).or(current_chapter.map(|c| c + 1));
+                }
+                sections.push(PdfSection {
+                    title,
+                    level,
+                    content: marker.take().unwrap_or_default(),
+                });
+                continue;
+            }
+            let content = match sections.last_mut() {
+                Some(section) => &mut section.content,
+                None => &mut front_matter,
+            };
+            if let Some(marker) = marker.take() {
+                content.push_str(&marker);
+            }
+            content.push_str(line.trim_end());
+            content.push('\n');
+        }
+        if let Some(marker) = marker {
+            match sections.last_mut() {
+                Some(section) => section.content.push_str(&marker),
+                None => front_matter.push_str(&marker),
+            }
+        }
+    }
+    if sections.iter().filter(|s| s.level == 0).count() < 2 {
+        return split_by_pages(pages);
+    }
+    if front_matter.lines().any(|line| {
+        let line = line.trim();
+        !line.is_empty() && !line.starts_with("<!--")
+    }) {
+        sections.insert(
+            0,
+            PdfSection {
+                title: FRONT_MATTER.to_string(),
+                level: 0,
+                content: front_matter,
+            },
+        );
+    }
+    sections
+}
+
+fn split_by_pages(pages: &[String]) -> Vec<PdfSection> {
+    pages
+        .chunks(PAGES_PER_CHAPTER)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let first = i * PAGES_PER_CHAPTER + 1;
+            let last = first + chunk.len() - 1;
+            let mut content = String::new();
+            for (j, page) in chunk.iter().enumerate() {
+                content.push_str(&page_marker((first + j) as u32));
+                content.push('\n');
+                content.push_str(page.trim_end());
+                content.push_str("\n\n");
+            }
+            PdfSection {
+                title: format!("Pages {first}-{last}"),
+                level: 0,
+                content,
+            }
+        })
+        .collect()
+}
+
+/// Convert a PDF to an mdbook in `output_dir`, chapters are detected by [`split_sections`]
+pub fn convert_pdf_to_mdbook(pdf: &Path, output_dir: &Path) -> anyhow::Result<()> {
+    let pages = pdf_extract::extract_text_by_pages(pdf)
+        .map_err(|e| anyhow::anyhow!("Failed to extract text from {}: {}", pdf.display(), e))?;
+    if pages.iter().all(|page| page.trim().is_empty()) {
+        anyhow::bail!(
+            "No text found in {}, scanned PDFs need OCR first",
+            pdf.display()
+        );
+    }
+    let title = pdf
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "Untitled".to_string());
+    let src_dir = output_dir.join("src");
+    std::fs::create_dir_all(&src_dir)?;
+    std::fs::write(
+        output_dir.join("book.toml"),
+        format!(
+            "[book]\ntitle = {}\nsrc = \"src\"\n",
+            toml::Value::String(title)
+        ),
+    )?;
+    let mut summary = "# Summary\n\n".to_string();
+    for (i, section) in split_sections(&pages).iter().enumerate() {
+        let file_name = format!("section_{:04}.md", i + 1);
+        let title = section.title.replace(['[', ']'], "");
+        if i == 0 && section.title == FRONT_MATTER {
+            summary.push_str(&format!("[{title}]({file_name})\n\n"));
+        } else {
+            let indent = "  ".repeat(section.level);
+            summary.push_str(&format!("{indent}- [{title}]({file_name})\n"));
+        }
+        std::fs::write(
+            src_dir.join(&file_name),
+            format!("# {}\n\n{}", section.title, section.content),
+        )?;
+    }
+    std::fs::write(src_dir.join("SUMMARY.md"), summary)?;
+    Ok(())
+}
+
+#[test]
+fn sections() {
+    let pages = [
+        "Copyright 2024\nPreface".to_string(),
+        "Chapter 1 Basics\nSome text.\n1.1 First Steps\nMore text.\n2. This is a list item."
+            .to_string(),
+        "continued text\n2 Advanced Topics\n2.1 Deep Dive\n3.5 million people agree.".to_string(),
+    ];
+    let sections = split_sections(&pages);
+    let titles: Vec<(usize, &str)> = sections
+        .iter()
+        .map(|s| (s.level, s.title.as_str()))
+        .collect();
+    assert_eq!(
+        titles,
+        vec![
+            (0, FRONT_MATTER),
+            (0, "Chapter 1 Basics"),
+            (1, "1.1 First Steps"),
+            (0, "2 Advanced Topics"),
+            (1, "2.1 Deep Dive"),
+        ]
+    );
+    assert!(sections[1].content.starts_with("<!-- page 2 -->"));
+    assert!(sections[2].content.contains("<!-- page 3 -->"));
+    assert!(sections[4].content.contains("3.5 million"));
+
+    let pages: Vec<String> = (0..25).map(|_| "no headings".to_string()).collect();
+    let sections = split_sections(&pages);
+    assert_eq!(sections.len(), 3);
+    assert_eq!(sections[2].title, "Pages 21-25");
+}
@@ -0,0 +1,427 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, LazyLock},
+};
+
+use anyhow::Context;
+use async_openai::{
+    tools::ToolDyn,
+    types::{ChatCompletionTool, ChatCompletionToolType, FunctionObject},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use time::OffsetDateTime;
+use tokio::task::spawn_blocking;
+use tracing::info;
+use wasmtime::{
+    Config, Engine, Store, StoreLimits, StoreLimitsBuilder,
+    component::{Component, Linker},
+};
+
+use super::custom_tools::{Capability, ExerciseSpec, PluginSpec, RUN_TESTS_TOOL, check_tool_name};
+
+wasmtime::component::bindgen!({
+    world: "plugin",
+    path: "wit/plugin.wit",
+});
+
+use book_server::plugin::{clock, logging};
+
+/// the bindings of the test runners, a world of its own in the same package
+mod runner_world {
+    wasmtime::component::bindgen!({
+        world: "runner",
+        path: "wit/plugin.wit",
+    });
+}
+
+use runner_world::{Runner, exports::book_server::plugin::test_runner::TestCase};
+
+/// longest result of a plugin given to the model, in characters
+const MAX_RESULT_CHARS: usize = 8000;
+/// longest solution of a student the runners are given, in characters
+const MAX_SOLUTION_CHARS: usize = 100_000;
+/// most test cases of a run reported to the model, failures first
+const MAX_REPORTED_CASES: usize = 50;
+/// longest failure message of a test case given to the model, in characters
+const MAX_MESSAGE_CHARS: usize = 500;
+
+static ENGINE: LazyLock<Engine> = LazyLock::new(|| {
+    let mut config = Config::new();
+    config.wasm_component_model(true).consume_fuel(true);
+    Engine::new(&config).expect("the plugin engine config is valid")
+});
+
+/// The state of one call of a plugin, it lives as long as the instance
+struct PluginState {
+    /// the file of the plugin, for its log lines
+    path: String,
+    limits: StoreLimits,
+}
+
+impl logging::Host for PluginState {
+    fn log(&mut self, message: String) {
+        info!(plugin = %self.path, "{message}");
+    }
+}
+
+impl clock::Host for PluginState {
+    fn now(&mut self) -> u64 {
+        OffsetDateTime::now_utc().unix_timestamp().max(0) as u64
+    }
+}
+
+/// A WASM component implementing the `tool` interface of `wit/plugin.wit`. Each call runs in a
+/// fresh instance, with the fuel and memory of its spec and only the host functions of its
+/// capabilities; the plugin has no WASI, so no files, network or environment.
+#[derive(Clone)]
+pub struct PluginTool {
+    spec: PluginSpec,
+    component: Component,
+    linker: Arc<Linker<PluginState>>,
+    definition: ChatCompletionTool,
+}
+
+impl PluginTool {
+    /// compile the plugin of the book at `book_dir` and read its definition
+    pub async fn load(book_dir: &Path, spec: PluginSpec) -> anyhow::Result<Self> {
+        let path = book_dir.join(&spec.path);
+        spawn_blocking(move || Self::load_blocking(&path, spec)).await?
+    }
+
+    fn load_blocking(path: &Path, spec: PluginSpec) -> anyhow::Result<Self> {
+        let component = Component::from_file(&ENGINE, path)
+            .with_context(|| format!("Invalid plugin {}", spec.path))?;
+        let mut linker = Linker::new(&ENGINE);
+        for capability in &spec.capabilities {
+            match capability {
+                Capability::Log => logging::add_to_linker(&mut linker, |state| state)?,
+                Capability::Clock => clock::add_to_linker(&mut linker, |state| state)?,
+            }
+        }
+        let (mut store, plugin) = instantiate(&spec, &component, &linker)?;
+        let definition = plugin
+            .book_server_plugin_tool()
+            .call_definition(&mut store)
+            .with_context(|| format!("Plugin {} failed to define its tool", spec.path))?;
+        check_tool_name(&definition.name)?;
+        let parameters: Value = serde_json::from_str(&definition.parameters)
+            .with_context(|| format!("Invalid parameters of plugin {}", spec.path))?;
+        let definition = ChatCompletionTool {
+            r#type: ChatCompletionToolType::Function,
+            function: FunctionObject {
+                name: definition.name,
+                description: Some(definition.description),
+                parameters: Some(parameters),
+                strict: None,
+            },
+        };
+        Ok(Self {
+            spec,
+            component,
+            linker: Arc::new(linker),
+            definition,
+        })
+    }
+
+    /// the name of the tool, as the plugin defined it
+    pub fn name(&self) -> &str {
+        &self.definition.function.name
+    }
+
+    fn call_blocking(&self, arguments: &str) -> anyhow::Result<Result<String, String>> {
+        let (mut store, plugin) = instantiate(&self.spec, &self.component, &self.linker)?;
+        let result = plugin
+            .book_server_plugin_tool()
+            .call_call(&mut store, arguments)
+            .map_err(|e| match store.get_fuel() {
+                Ok(0) => anyhow::anyhow!("ran out of fuel"),
+                _ => e,
+            })?;
+        Ok(result.map(|result| result.chars().take(MAX_RESULT_CHARS).collect()))
+    }
+}
+
+/// a fresh instance with the fuel and memory of one call, failing when the plugin imports a
+/// capability it wasn't granted
+fn instantiate(
+    spec: &PluginSpec,
+    component: &Component,
+    linker: &Linker<PluginState>,
+) -> anyhow::Result<(Store<PluginState>, Plugin)> {
+    let state = PluginState {
+        path: spec.path.clone(),
+        limits: StoreLimitsBuilder::new()
+            .memory_size(spec.memory_mb as usize * 1024 * 1024)
+            .instances(1)
+            .build(),
+    };
+    let mut store = Store::new(&ENGINE, state);
+    store.limiter(|state| &mut state.limits);
+    store.set_fuel(spec.fuel)?;
+    let plugin = Plugin::instantiate(&mut store, component, linker).with_context(|| {
+        format!(
+            "Plugin {} imports a capability it wasn't granted",
+            spec.path
+        )
+    })?;
+    Ok((store, plugin))
+}
+
+impl ToolDyn for PluginTool {
+    fn definition(&self) -> ChatCompletionTool {
+        self.definition.clone()
+    }
+
+    fn call(
+        &self,
+        arguments: String,
+    ) -> Pin<Box<dyn Future<Output = Result<String, String>> + Send + '_>> {
+        Box::pin(async move {
+            let name = &self.definition.function.name;
+            // the wasm runs synchronously, off the async workers
+            let tool = self.clone();
+            match spawn_blocking(move || tool.call_blocking(&arguments)).await {
+                Ok(Ok(result)) => result.map_err(|e| format!("Tool {name} failed: {e}")),
+                Ok(Err(e)) => Err(format!("Tool {name} failed: {e}")),
+                Err(e) => Err(format!("Tool {name} failed to run: {e}")),
+            }
+        })
+    }
+}
+
+/// The state of one run of a test runner, which has no host functions
+struct RunnerState {
+    limits: StoreLimits,
+}
+
+/// an exercise of the book with its test suite read and its runner compiled
+struct Exercise {
+    spec: ExerciseSpec,
+    tests: String,
+    runner: Component,
+}
+
+/// The `RunTests` tool: runs the test suite of an exercise of the book on the solution the
+/// student submitted, each run in a fresh instance of the runner of the exercise with its fuel
+/// and memory, and reports which tests passed for the teacher to discuss
+#[derive(Clone)]
+pub struct TestRunnerTool {
+    exercises: Arc<Vec<Exercise>>,
+    linker: Arc<Linker<RunnerState>>,
+}
+
+#[derive(Deserialize)]
+struct RunTestsArgs {
+    exercise: String,
+    solution: String,
+}
+
+#[derive(Debug, Serialize)]
+struct TestReport {
+    exercise: String,
+    passed: usize,
+    failed: usize,
+    total: usize,
+    /// the failed cases first, at most [`MAX_REPORTED_CASES`]
+    cases: Vec<CaseReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct CaseReport {
+    name: String,
+    passed: bool,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    message: String,
+}
+
+impl TestReport {
+    fn new(exercise: &str, mut cases: Vec<TestCase>) -> Self {
+        let total = cases.len();
+        let passed = cases.iter().filter(|case| case.passed).count();
+        // stable, so the failures keep the order of the suite
+        cases.sort_by_key(|case| case.passed);
+        Self {
+            exercise: exercise.to_string(),
+            passed,
+            failed: total - passed,
+            total,
+            cases: cases
+                .into_iter()
+                .take(MAX_REPORTED_CASES)
+                .map(|case| CaseReport {
+                    name: case.name,
+                    passed: case.passed,
+                    message: case.message.chars().take(MAX_MESSAGE_CHARS).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TestRunnerTool {
+    /// read the test suites of the exercises of the book at `book_dir` and compile their
+    /// runners, a runner shared by several exercises is compiled once
+    pub async fn load(book_dir: &Path, exercises: Vec<ExerciseSpec>) -> anyhow::Result<Self> {
+        let book_dir = book_dir.to_path_buf();
+        spawn_blocking(move || Self::load_blocking(&book_dir, exercises)).await?
+    }
+
+    fn load_blocking(book_dir: &Path, exercises: Vec<ExerciseSpec>) -> anyhow::Result<Self> {
+        let mut runners: HashMap<String, Component> = HashMap::new();
+        let mut loaded = Vec::with_capacity(exercises.len());
+        for spec in exercises {
+            let tests = std::fs::read_to_string(book_dir.join(&spec.tests))
+                .with_context(|| format!("Invalid test suite {}", spec.tests))?;
+            let runner = match runners.get(&spec.runner) {
+                Some(runner) => runner.clone(),
+                None => {
+                    let runner = Component::from_file(&ENGINE, book_dir.join(&spec.runner))
+                        .with_context(|| format!("Invalid test runner {}", spec.runner))?;
+                    runners.insert(spec.runner.clone(), runner.clone());
+                    runner
+                }
+            };
+            loaded.push(Exercise {
+                spec,
+                tests,
+                runner,
+            });
+        }
+        Ok(Self {
+            exercises: Arc::new(loaded),
+            linker: Arc::new(Linker::new(&ENGINE)),
+        })
+    }
+
+    fn run_blocking(&self, exercise: &Exercise, solution: &str) -> anyhow::Result<TestReport> {
+        let spec = &exercise.spec;
+        let state = RunnerState {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(spec.memory_mb as usize * 1024 * 1024)
+                .instances(1)
+                .build(),
+        };
+        let mut store = Store::new(&ENGINE, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(spec.fuel)?;
+        let runner = Runner::instantiate(&mut store, &exercise.runner, &self.linker)
+            .with_context(|| format!("Test runner {} imports host functions", spec.runner))?;
+        let cases = runner
+            .book_server_plugin_test_runner()
+            .call_run(&mut store, solution, &exercise.tests)
+            .map_err(|e| match store.get_fuel() {
+                // most likely an endless loop of the solution
+                Ok(0) => anyhow::anyhow!("the tests ran out of fuel, does the solution end?"),
+                _ => e,
+            })?
+            .map_err(|e| anyhow::anyhow!("the tests can't run: {e}"))?;
+        Ok(TestReport::new(&spec.id, cases))
+    }
+}
+
+impl ToolDyn for TestRunnerTool {
+    fn definition(&self) -> ChatCompletionTool {
+        let ids: Vec<&str> = self
+            .exercises
+            .iter()
+            .map(|exercise| exercise.spec.id.as_str())
+            .collect();
+        let list = self
+            .exercises
+            .iter()
+            .map(|exercise| match &exercise.spec.chapter {
+                Some(chapter) => format!(
+                    "- {}: {} (chapter {chapter})",
+                    exercise.spec.id, exercise.spec.title
+                ),
+                None => format!("- {}: {}", exercise.spec.id, exercise.spec.title),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        ChatCompletionTool {
+            r#type: ChatCompletionToolType::Function,
+            function: FunctionObject {
+                name: RUN_TESTS_TOOL.to_string(),
+                description: Some(format!(
+                    "Run the test suite of a programming exercise of the book on the solution \
+                    the student submitted, returns which tests passed and why the others \
+                    failed. Discuss the failures with the student rather than fixing the code \
+                    for them. The exercises:\n{list}"
+                )),
+                parameters: Some(json!({
+                    "type": "object",
+                    "properties": {
+                        "exercise": {
+                            "type": "string",
+                            "enum": ids,
+                            "description": "The id of the exercise"
+                        },
+                        "solution": {
+                            "type": "string",
+                            "description": "The complete source code the student submitted, unchanged"
+                        }
+                    },
+                    "required": ["exercise", "solution"]
+                })),
+                strict: None,
+            },
+        }
+    }
+
+    fn call(
+        &self,
+        arguments: String,
+    ) -> Pin<Box<dyn Future<Output = Result<String, String>> + Send + '_>> {
+        Box::pin(async move {
+            let args: RunTestsArgs = serde_json::from_str(&arguments)
+                .map_err(|e| format!("Invalid arguments of {RUN_TESTS_TOOL}: {e}"))?;
+            let Some(index) = self
+                .exercises
+                .iter()
+                .position(|exercise| exercise.spec.id == args.exercise)
+            else {
+                return Err(format!("Unknown exercise {}", args.exercise));
+            };
+            if args.solution.chars().count() > MAX_SOLUTION_CHARS {
+                return Err(format!(
+                    "The solution is longer than {MAX_SOLUTION_CHARS} characters"
+                ));
+            }
+            // the wasm runs synchronously, off the async workers
+            let tool = self.clone();
+            let report =
+                spawn_blocking(move || tool.run_blocking(&tool.exercises[index], &args.solution))
+                    .await
+                    .map_err(|e| format!("{RUN_TESTS_TOOL} failed to run: {e}"))?
+                    .map_err(|e| format!("{RUN_TESTS_TOOL} failed: {e}"))?;
+            serde_json::to_string(&report).map_err(|e| e.to_string())
+        })
+    }
+}
+
+#[test]
+fn test_report() {
+    let case = |name: &str, passed: bool| TestCase {
+        name: name.to_string(),
+        passed,
+        message: if passed {
+            String::new()
+        } else {
+            "x".repeat(1000)
+        },
+    };
+    let report = TestReport::new(
+        "fizzbuzz",
+        vec![case("one", true), case("three", false), case("five", false)],
+    );
+    assert_eq!((report.passed, report.failed, report.total), (1, 2, 3));
+    let names: Vec<&str> = report.cases.iter().map(|case| case.name.as_str()).collect();
+    assert_eq!(names, ["three", "five", "one"]);
+    assert_eq!(report.cases[0].message.len(), MAX_MESSAGE_CHARS);
+    let json = serde_json::to_value(&report).unwrap();
+    assert!(json["cases"][2].get("message").is_none());
+}
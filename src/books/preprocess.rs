@@ -0,0 +1,294 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use super::directives::resolve_directives;
+use crate::ai_utils::{Chunking, SelfConsistency};
+
+/// `[book-server]` table of `book.toml`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct BookServerConfig {
+    /// preprocessing stages applied to every chapter, in order
+    pub preprocess: Vec<String>,
+    pub self_consistency: GenerationConfig,
+    pub summarize: SummarizeConfig,
+}
+
+impl Default for BookServerConfig {
+    fn default() -> Self {
+        Self {
+            preprocess: vec![ExpandIncludes::NAME.to_string()],
+            self_consistency: GenerationConfig::default(),
+            summarize: SummarizeConfig::default(),
+        }
+    }
+}
+
+/// `[book-server.self-consistency]` table, candidates generated per AI artifact of the book
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct GenerationConfig {
+    pub teaching_plan: SelfConsistency,
+    pub chapter_plan: SelfConsistency,
+    pub chapter_summary: SelfConsistency,
+    /// the `[book-server.summarize]` table
+    #[serde(skip)]
+    pub summarize: SummarizeConfig,
+}
+
+/// `[book-server.summarize]` table, lengths of the generated texts and how long chapters are
+/// split to summarize them
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct SummarizeConfig {
+    /// words of the teaching plan and the chapter plans
+    pub plan_length: usize,
+    /// words of the chapter summaries
+    pub summary_length: usize,
+    #[serde(flatten)]
+    pub chunking: Chunking,
+}
+
+impl Default for SummarizeConfig {
+    fn default() -> Self {
+        Self {
+            plan_length: 1000,
+            summary_length: 100,
+            chunking: Chunking::default(),
+        }
+    }
+}
+
+impl BookServerConfig {
+    pub fn from_book_toml(content: &str) -> anyhow::Result<Self> {
+        #[derive(Deserialize)]
+        struct BookToml {
+            #[serde(default, rename = "book-server")]
+            book_server: BookServerConfig,
+        }
+        let config = toml::from_str::<BookToml>(content)?.book_server;
+        config.summarize.chunking.validate()?;
+        let generation = &config.self_consistency;
+        for consistency in [
+            &generation.teaching_plan,
+            &generation.chapter_plan,
+            &generation.chapter_summary,
+        ] {
+            consistency.validate()?;
+        }
+        Ok(config)
+    }
+}
+
+/// Where the chapter being processed lives
+pub struct PreprocessContext<'a> {
+    /// the mdbook directory holding `book.toml`
+    pub book_dir: &'a Path,
+    /// the `src` directory of the mdbook
+    pub src_dir: &'a Path,
+    /// path of the chapter file relative to `src_dir`
+    pub chapter_path: Option<&'a Path>,
+}
+
+impl PreprocessContext<'_> {
+    /// directory that relative paths in the chapter are resolved against
+    pub fn chapter_dir(&self) -> PathBuf {
+        match self.chapter_path.and_then(|p| p.parent()) {
+            Some(parent) => self.src_dir.join(parent),
+            None => self.src_dir.to_path_buf(),
+        }
+    }
+}
+
+/// A stage of the chapter content preprocessing pipeline
+pub trait Preprocessor: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn run(&self, ctx: &PreprocessContext, content: String) -> anyhow::Result<String>;
+}
+
+/// Resolve the mdbook `{{#include}}`, `{{#playground}}` and `{{#rustdoc_include}}` directives,
+/// see [`resolve_directives`]
+pub struct ExpandIncludes;
+
+impl ExpandIncludes {
+    pub const NAME: &str = "include";
+}
+
+impl Preprocessor for ExpandIncludes {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+    fn run(&self, ctx: &PreprocessContext, content: String) -> anyhow::Result<String> {
+        Ok(resolve_directives(
+            ctx.book_dir,
+            &ctx.chapter_dir(),
+            &content,
+        ))
+    }
+}
+
+/// Remove footnote references `[^1]` and footnote definitions `[^1]: ...`
+pub struct StripFootnotes;
+
+static FOOTNOTE_DEFINITION: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?m)^\[\^[^\]]+\]:.*(\n {2,}.*)*\n?").unwrap());
+static FOOTNOTE_REFERENCE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[\^[^\]]+\]").unwrap());
+
+impl StripFootnotes {
+    pub const NAME: &str = "strip-footnotes";
+}
+
+impl Preprocessor for StripFootnotes {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+    fn run(&self, _ctx: &PreprocessContext, content: String) -> anyhow::Result<String> {
+        let content = FOOTNOTE_DEFINITION.replace_all(&content, "");
+        Ok(FOOTNOTE_REFERENCE.replace_all(&content, "").into_owned())
+    }
+}
+
+/// Replace typographic quotes with plain ASCII quotes
+pub struct NormalizeQuotes;
+
+impl NormalizeQuotes {
+    pub const NAME: &str = "smart-quotes";
+}
+
+impl Preprocessor for NormalizeQuotes {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+    fn run(&self, _ctx: &PreprocessContext, content: String) -> anyhow::Result<String> {
+        Ok(content
+            .replace(['‘', '’', '‚', '‛'], "'")
+            .replace(['“', '”', '„', '‟'], "\""))
+    }
+}
+
+/// Ordered list of preprocessing stages
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Preprocessor>>,
+}
+
+impl Pipeline {
+    /// build a pipeline from stage names, see [`Pipeline::stage`] for the known names
+    pub fn from_names(names: &[String]) -> anyhow::Result<Self> {
+        let mut pipeline = Self::default();
+        for name in names {
+            let stage = Self::stage(name)
+                .ok_or_else(|| anyhow::anyhow!("unknown preprocessor: {}", name))?;
+            pipeline = pipeline.with_stage(stage);
+        }
+        Ok(pipeline)
+    }
+
+    /// the built-in stage called `name`
+    pub fn stage(name: &str) -> Option<Box<dyn Preprocessor>> {
+        match name {
+            ExpandIncludes::NAME => Some(Box::new(ExpandIncludes)),
+            StripFootnotes::NAME => Some(Box::new(StripFootnotes)),
+            NormalizeQuotes::NAME => Some(Box::new(NormalizeQuotes)),
+            _ => None,
+        }
+    }
+
+    /// append a stage, custom stages can be added by library users
+    pub fn with_stage(mut self, stage: Box<dyn Preprocessor>) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    pub fn run(&self, ctx: &PreprocessContext, mut content: String) -> anyhow::Result<String> {
+        for stage in &self.stages {
+            content = stage
+                .run(ctx, content)
+                .map_err(|e| anyhow::anyhow!("preprocessor {} failed: {}", stage.name(), e))?;
+        }
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_utils::JudgeMode;
+
+    #[test]
+    fn footnotes_and_quotes() {
+        let ctx = PreprocessContext {
+            book_dir: Path::new("."),
+            src_dir: Path::new("."),
+            chapter_path: None,
+        };
+        let pipeline = Pipeline::from_names(&[
+            StripFootnotes::NAME.to_string(),
+            NormalizeQuotes::NAME.to_string(),
+        ])
+        .unwrap();
+        let content = "“Hi”, it’s me[^1].\n\n[^1]: A note\n    continued\nEnd";
+        let result = pipeline.run(&ctx, content.to_string()).unwrap();
+        assert_eq!(result, "\"Hi\", it's me.\n\nEnd");
+    }
+
+    #[test]
+    fn include() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("ch1")).unwrap();
+        std::fs::write(dir.path().join("ch1/code.rs"), "fn main() {}").unwrap();
+        let chapter_path = PathBuf::from("ch1/intro.md");
+        let ctx = PreprocessContext {
+            book_dir: dir.path(),
+            src_dir: dir.path(),
+            chapter_path: Some(&chapter_path),
+        };
+        let result = ExpandIncludes
+            .run(&ctx, "```rust\n{{#include code.rs}}\n```".to_string())
+            .unwrap();
+        assert_eq!(result, "```rust\nfn main() {}\n```");
+        assert!(Pipeline::from_names(&["nope".to_string()]).is_err());
+    }
+
+    #[test]
+    fn self_consistency_config() {
+        let config = BookServerConfig::from_book_toml(
+            "[book]\ntitle = \"t\"\n\n[book-server.self-consistency.teaching-plan]\ncandidates = 3\njudge = \"merge\"\n",
+        )
+        .unwrap();
+        let generation = config.self_consistency;
+        assert_eq!(generation.teaching_plan.candidates, 3);
+        assert_eq!(generation.teaching_plan.judge, JudgeMode::Merge);
+        assert_eq!(generation.chapter_plan.candidates, 1);
+        assert_eq!(config.preprocess, vec![ExpandIncludes::NAME.to_string()]);
+        assert_eq!(config.summarize, SummarizeConfig::default());
+        let book_toml = |candidates: usize| {
+            format!(
+                "[book]\ntitle = \"t\"\n\n[book-server.self-consistency.chapter-plan]\ncandidates = {candidates}\n"
+            )
+        };
+        assert!(BookServerConfig::from_book_toml(&book_toml(5)).is_ok());
+        assert!(BookServerConfig::from_book_toml(&book_toml(0)).is_err());
+        assert!(BookServerConfig::from_book_toml(&book_toml(1000)).is_err());
+    }
+
+    #[test]
+    fn summarize_config() {
+        let book_toml =
+            |table: &str| format!("[book]\ntitle = \"t\"\n\n[book-server.summarize]\n{table}");
+        let config = BookServerConfig::from_book_toml(&book_toml(
+            "chunk-tokens = 4000\nplan-length = 600\n",
+        ))
+        .unwrap();
+        assert_eq!(config.summarize.chunking.chunk_tokens, 4000);
+        assert_eq!(config.summarize.chunking.parallelism, 4);
+        assert_eq!(config.summarize.plan_length, 600);
+        assert!(BookServerConfig::from_book_toml(&book_toml("parallelism = 0\n")).is_err());
+        assert!(BookServerConfig::from_book_toml(&book_toml("parallelism = 1000\n")).is_err());
+    }
+}
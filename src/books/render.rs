@@ -0,0 +1,341 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd, html};
+use regex::Regex;
+use serde::Deserialize;
+use syntect::{highlighting::ThemeSet, html::highlighted_html_for_string, parsing::SyntaxSet};
+use utoipa::ToSchema;
+
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+/// light theme of the highlighted code blocks, styled inline so clients need no stylesheet
+const CODE_THEME: &str = "InspiredGitHub";
+
+/// a tag of raw html, attributes quoted with `"` only
+static HTML_TAG: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"<(/?)([a-zA-Z][a-zA-Z0-9]*)((?:\s+[a-zA-Z-]+(?:\s*=\s*"[^"]*")?)*)\s*/?>"#)
+        .unwrap()
+});
+static HTML_ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"([a-zA-Z-]+)(?:\s*=\s*"([^"]*)")?"#).unwrap());
+/// the raw html tags kept in rendered chapters, any other markup is shown as text
+const HTML_TAGS: &[&str] = &[
+    "a",
+    "abbr",
+    "audio",
+    "b",
+    "blockquote",
+    "br",
+    "caption",
+    "code",
+    "dd",
+    "del",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "figcaption",
+    "figure",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "i",
+    "img",
+    "ins",
+    "kbd",
+    "li",
+    "mark",
+    "ol",
+    "p",
+    "picture",
+    "pre",
+    "q",
+    "s",
+    "samp",
+    "small",
+    "source",
+    "span",
+    "strong",
+    "sub",
+    "summary",
+    "sup",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "u",
+    "ul",
+    "video",
+];
+/// the attributes kept on those tags, event handlers and styles are dropped
+const HTML_ATTRIBUTES: &[&str] = &[
+    "align", "alt", "class", "colspan", "controls", "height", "href", "id", "loop", "open",
+    "rowspan", "src", "title", "type", "width",
+];
+
+/// How the content of a chapter is returned
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChapterFormat {
+    /// the markdown as stored
+    #[default]
+    Markdown,
+    /// an html fragment with highlighted code and anchored headings
+    Html,
+}
+
+/// The html anchor of a heading, the same as mdbook's so links into books keep working, e.g.
+/// "Ownership & Borrowing" becomes "ownership--borrowing"
+///
+/// The anchor of the section a [`BookLocation`](super::tools::BookLocation) points to is the
+/// anchor of its `sector_title`.
+pub fn heading_anchor(title: &str) -> String {
+    title
+        .trim()
+        .chars()
+        .filter_map(|c| {
+            if c.is_alphanumeric() || c == '_' || c == '-' {
+                Some(c.to_ascii_lowercase())
+            } else if c.is_whitespace() {
+                Some('-')
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// the anchor of the heading `title` in a chapter, numbered like mdbook when `anchors`, those of
+/// the headings before it, already has it: "intro", "intro-1", ...
+pub fn unique_anchor(anchors: &mut HashMap<String, usize>, title: &str) -> String {
+    let anchor = heading_anchor(title);
+    let count = anchors.entry(anchor.clone()).or_default();
+    *count += 1;
+    match *count {
+        1 => anchor,
+        count => format!("{anchor}-{}", count - 1),
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// whether `url` is harmless as a link or image source: relative, web, mail or an inline
+/// image, never `javascript:`
+fn is_safe_url(url: &str) -> bool {
+    if url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return false;
+    }
+    let scheme = url.split(['/', '?', '#']).next().unwrap_or_default();
+    if !scheme.contains(':') {
+        return true;
+    }
+    let url = url.to_ascii_lowercase();
+    ["http:", "https:", "mailto:", "data:image/"]
+        .iter()
+        .any(|allowed| url.starts_with(allowed))
+}
+
+/// whether the link of a raw `src` or `href` attribute is harmless, see [`is_safe_url`];
+/// browsers decode the entities of attributes, so those are refused
+fn is_safe_link(link: &str) -> bool {
+    !link.contains('&') && is_safe_url(link)
+}
+
+/// Raw html of a chapter made safe to show: the tags of [`HTML_TAGS`] are kept with the
+/// attributes of [`HTML_ATTRIBUTES`], everything else, e.g. `<script>` or comments, is escaped
+/// to text.
+fn sanitize_html(raw: &str) -> String {
+    let mut sanitized = String::with_capacity(raw.len());
+    let mut last = 0;
+    for caps in HTML_TAG.captures_iter(raw) {
+        let tag = caps.get(0).expect("unreachable");
+        sanitized.push_str(&escape_html(&raw[last..tag.start()]));
+        last = tag.end();
+        let name = caps[2].to_ascii_lowercase();
+        if !HTML_TAGS.contains(&name.as_str()) {
+            sanitized.push_str(&escape_html(tag.as_str()));
+            continue;
+        }
+        sanitized.push_str(&format!("<{}{name}", &caps[1]));
+        if caps[1].is_empty() {
+            for attribute in HTML_ATTRIBUTE.captures_iter(&caps[3]) {
+                let key = attribute[1].to_ascii_lowercase();
+                if !HTML_ATTRIBUTES.contains(&key.as_str()) {
+                    continue;
+                }
+                match attribute.get(2) {
+                    Some(value)
+                        if matches!(key.as_str(), "src" | "href")
+                            && !is_safe_link(value.as_str()) => {}
+                    Some(value) => sanitized.push_str(&format!(" {key}=\"{}\"", value.as_str())),
+                    None => sanitized.push_str(&format!(" {key}")),
+                }
+            }
+        }
+        sanitized.push('>');
+    }
+    sanitized.push_str(&escape_html(&raw[last..]));
+    sanitized
+}
+
+/// `code` highlighted for `language`, a plain code block for unknown languages
+fn highlight(code: &str, language: &str) -> String {
+    let syntax = SYNTAXES
+        .find_syntax_by_token(language)
+        .filter(|_| !language.is_empty());
+    let highlighted = syntax.and_then(|syntax| {
+        highlighted_html_for_string(code, &SYNTAXES, syntax, &THEMES.themes[CODE_THEME]).ok()
+    });
+    match highlighted {
+        Some(highlighted) => highlighted,
+        None if language.is_empty() => format!("<pre><code>{}</code></pre>\n", escape_html(code)),
+        None => format!(
+            "<pre><code class=\"language-{}\">{}</code></pre>\n",
+            escape_html(language),
+            escape_html(code)
+        ),
+    }
+}
+
+/// Render the markdown content of a chapter to an html fragment.
+///
+/// Headings get an `id` from [`unique_anchor`] and fenced code blocks are highlighted for their
+/// language. The raw html of the chapter goes through [`sanitize_html`], books are uploaded by
+/// students and the fragment is shown in the reader's page.
+pub fn render_html(content: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let mut events = Vec::new();
+    let mut anchors: HashMap<String, usize> = HashMap::new();
+    // the events and the title text of the heading being read
+    let mut heading: Option<(Vec<Event>, String)> = None;
+    // the language and text of the code block being read
+    let mut code: Option<(String, String)> = None;
+    // whether the link or image being read is dropped for its unsafe url, its text is kept
+    let (mut drop_link, mut drop_image) = (false, false);
+    for event in Parser::new_ext(content, options) {
+        let event = match event {
+            Event::Html(raw) => Event::Html(sanitize_html(&raw).into()),
+            Event::InlineHtml(raw) => Event::InlineHtml(sanitize_html(&raw).into()),
+            Event::Start(Tag::Link { ref dest_url, .. }) if !is_safe_url(dest_url) => {
+                drop_link = true;
+                continue;
+            }
+            Event::End(TagEnd::Link) if drop_link => {
+                drop_link = false;
+                continue;
+            }
+            Event::Start(Tag::Image { ref dest_url, .. }) if !is_safe_url(dest_url) => {
+                drop_image = true;
+                continue;
+            }
+            Event::End(TagEnd::Image) if drop_image => {
+                drop_image = false;
+                continue;
+            }
+            event => event,
+        };
+        match event {
+            Event::Start(Tag::Heading { .. }) => heading = Some((Vec::new(), String::new())),
+            Event::End(TagEnd::Heading(level)) => {
+                let (inner, title) = heading.take().unwrap_or_default();
+                let anchor = unique_anchor(&mut anchors, &title);
+                events.push(Event::Html(
+                    format!("<{level} id=\"{}\">", escape_html(&anchor)).into(),
+                ));
+                events.extend(inner);
+                events.push(Event::Html(format!("</{level}>\n").into()));
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => info
+                        .split([',', ' '])
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code = Some((language, String::new()));
+            }
+            Event::Text(text) if code.is_some() => {
+                if let Some((_, code)) = code.as_mut() {
+                    code.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                let (language, code) = code.take().unwrap_or_default();
+                events.push(Event::Html(CowStr::from(highlight(&code, &language))));
+            }
+            event => match heading.as_mut() {
+                Some((inner, title)) => {
+                    if let Event::Text(text) | Event::Code(text) = &event {
+                        title.push_str(text);
+                    }
+                    inner.push(event);
+                }
+                None => events.push(event),
+            },
+        }
+    }
+    let mut body = String::new();
+    html::push_html(&mut body, events.into_iter());
+    body
+}
+
+#[test]
+fn render() {
+    assert_eq!(
+        heading_anchor("Ownership & Borrowing"),
+        "ownership--borrowing"
+    );
+    let html = render_html(
+        "# Intro\n\ntext\n\n## The `Box` type\n\n# Intro\n\n```rust\nfn main() {}\n```\n\n```\nplain <b>\n```\n",
+    );
+    assert!(html.contains("<h1 id=\"intro\">Intro</h1>"));
+    assert!(html.contains("<h2 id=\"the-box-type\">The <code>Box</code> type</h2>"));
+    assert!(html.contains("<h1 id=\"intro-1\">"));
+    assert!(html.contains("<pre style="));
+    assert!(html.contains("<pre><code>plain &lt;b&gt;\n</code></pre>"));
+    let html = render_html(
+        "<script>alert(1)</script>\n\ntext <img src=\"a.png\" onerror=\"alert(1)\"> \
+        <a href=\"javascript:alert(1)\">x</a> <span class=\"note\">y</span>\n\n\
+        <div class=\"notebook-output\">\n\n<!-- hidden -->\n\n</div>\n",
+    );
+    assert!(!html.contains("<script"));
+    assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+    assert!(html.contains("<img src=\"a.png\">"));
+    assert!(html.contains("<a>x</a>"));
+    assert!(html.contains("<span class=\"note\">y</span>"));
+    assert!(html.contains("<div class=\"notebook-output\">"));
+    assert!(!html.contains("<!--"));
+    let html = render_html(
+        "[click](javascript:alert(1)) ![img](JavaScript:alert(1)) <javascript:alert(1)> \
+        [ok](https://example.com/?a=1&b=2)",
+    );
+    assert!(!html.to_lowercase().contains("src=\"javascript"));
+    assert!(!html.contains("href=\"javascript"));
+    assert!(html.contains("click"));
+    assert!(html.contains("<a href=\"https://example.com/?a=1&amp;b=2\">ok</a>"));
+}
@@ -0,0 +1,395 @@
+use std::{collections::HashMap, fmt, str::FromStr, sync::LazyLock};
+
+use serde::Serialize;
+use sqlx::{SqliteConnection, SqlitePool};
+use utoipa::ToSchema;
+
+use super::book::Book;
+use crate::embeddings::SemanticHit;
+
+/// How [`fuse`] combines the keyword and the semantic ranking, written as `rrf`, `rrf:<k>` or
+/// `weighted:<keyword weight>`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fusion {
+    /// reciprocal rank fusion, each ranking adds `1 / (k + rank)`; a larger `k` flattens the
+    /// advantage of the top ranks
+    Rrf { k: f64 },
+    /// the scores of each ranking scaled to 0..1 by its best one, weighted from 0 (semantic
+    /// only) to 1 (keyword only)
+    Weighted { keyword_weight: f64 },
+}
+
+impl Default for Fusion {
+    fn default() -> Self {
+        Self::Rrf { k: 60.0 }
+    }
+}
+
+impl FromStr for Fusion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (name, parameter) = match s.split_once(':') {
+            Some((name, parameter)) => (name, Some(parameter.trim().parse::<f64>()?)),
+            None => (s, None),
+        };
+        match (name.trim(), parameter) {
+            ("rrf", None) => Ok(Self::default()),
+            ("rrf", Some(k)) if k >= 0.0 => Ok(Self::Rrf { k }),
+            ("weighted", None) => Ok(Self::Weighted {
+                keyword_weight: 0.5,
+            }),
+            ("weighted", Some(weight)) if (0.0..=1.0).contains(&weight) => Ok(Self::Weighted {
+                keyword_weight: weight,
+            }),
+            _ => anyhow::bail!(
+                "Unknown fusion {s}, use rrf, rrf:<k >= 0> or weighted:<keyword weight from 0 to 1>"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Fusion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rrf { k } => write!(f, "rrf:{k}"),
+            Self::Weighted { keyword_weight } => write!(f, "weighted:{keyword_weight}"),
+        }
+    }
+}
+
+/// the fusion of the search tools of the teacher and of requests without one, from the
+/// SEARCH_FUSION env var, reciprocal rank fusion by default
+pub static DEFAULT_FUSION: LazyLock<Fusion> = LazyLock::new(|| {
+    dotenvy::var("SEARCH_FUSION")
+        .ok()
+        .and_then(|fusion| fusion.parse().ok())
+        .unwrap_or_default()
+});
+
+/// A chapter found by keywords, by meaning or both
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct HybridHit {
+    pub chapter_number: String,
+    /// the passage closest in meaning, or the keyword excerpt when no passage of the chapter was
+    pub content: String,
+    /// the fused relevance, higher is better, comparable within one search only
+    pub score: f64,
+    /// BM25 relevance of the chapter, `None` when no keyword matched
+    pub keyword_score: Option<f64>,
+    /// cosine similarity of the passage, `None` when the chapter was not among the closest
+    pub semantic_score: Option<f32>,
+}
+
+/// A chapter matching a full-text search
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SearchHit {
+    pub chapter_number: String,
+    pub name: String,
+    /// excerpt around the matched terms, which are wrapped in `**`
+    pub snippet: String,
+    /// BM25 relevance, higher is better
+    pub score: f64,
+}
+
+/// separates the characters of CJK text in the index, the tokenizer only splits at spaces and
+/// punctuation so a Chinese sentence would otherwise be one token
+const CJK_SEPARATOR: char = '\u{200b}';
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}' // hiragana and katakana
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}' // hangul syllables
+        | '\u{f900}'..='\u{faff}'
+        | '\u{20000}'..='\u{2ebef}')
+}
+
+/// the text with every CJK character a token of its own, a word of several characters is then
+/// found as the phrase of its characters
+fn segment(text: &str) -> String {
+    let mut segmented = String::with_capacity(text.len());
+    for c in text.chars() {
+        if is_cjk(c) {
+            if !segmented.is_empty() && !segmented.ends_with(CJK_SEPARATOR) {
+                segmented.push(CJK_SEPARATOR);
+            }
+            segmented.push(c);
+            segmented.push(CJK_SEPARATOR);
+        } else {
+            segmented.push(c);
+        }
+    }
+    segmented
+}
+
+fn unsegment(text: &str) -> String {
+    text.replace(CJK_SEPARATOR, "")
+}
+
+/// FTS5 query matching any of the words of `query`, quoted so the text is never parsed as syntax
+fn fts_query(query: &str) -> String {
+    query
+        .split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .filter(|word| !word.is_empty())
+        .map(|word| format!("\"{}\"", segment(&word.replace('"', ""))))
+        .collect::<Vec<_>>()
+        .join(" OR ")
+}
+
+async fn insert_chapter(
+    connection: &mut SqliteConnection,
+    book_id: i64,
+    number: &str,
+    name: &str,
+    content: &str,
+) -> anyhow::Result<()> {
+    let name = segment(name);
+    let content = segment(content);
+    sqlx::query!(
+        "insert into chapter_search (book_id, chapter_number, name, content) values (?, ?, ?, ?)",
+        book_id,
+        number,
+        name,
+        content
+    )
+    .execute(connection)
+    .await?;
+    Ok(())
+}
+
+/// replace the indexed chapters of the book
+pub async fn index_book(database: &SqlitePool, book: &Book) -> anyhow::Result<()> {
+    let mut tx = database.begin().await?;
+    sqlx::query!("delete from chapter_search where book_id = ?", book.id)
+        .execute(&mut *tx)
+        .await?;
+    for (number, chapter) in book.chapters.iter() {
+        insert_chapter(
+            &mut tx,
+            book.id,
+            &number.to_string(),
+            &chapter.name,
+            &chapter.content,
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn remove_book(database: &SqlitePool, book_id: i64) -> anyhow::Result<()> {
+    sqlx::query!("delete from chapter_search where book_id = ?", book_id)
+        .execute(database)
+        .await?;
+    Ok(())
+}
+
+pub async fn is_indexed(database: &SqlitePool, book_id: i64) -> anyhow::Result<bool> {
+    let indexed = sqlx::query_scalar!(
+        r#"select exists(select 1 from chapter_search where book_id = ?) as "indexed!: bool""#,
+        book_id
+    )
+    .fetch_one(database)
+    .await?;
+    Ok(indexed)
+}
+
+/// chapters of the book matching the words of `query`, most relevant first
+pub async fn search(
+    database: &SqlitePool,
+    book_id: i64,
+    query: &str,
+    limit: i64,
+) -> anyhow::Result<Vec<SearchHit>> {
+    let query = fts_query(query);
+    if query.is_empty() {
+        return Ok(vec![]);
+    }
+    // names weigh more than contents, bm25 is negative with the best match lowest
+    let hits = sqlx::query_as!(
+        SearchHit,
+        r#"select chapter_number as "chapter_number!: String", name as "name!: String",
+        snippet(chapter_search, 3, '**', '**', '…', 16) as "snippet!: String",
+        -bm25(chapter_search, 0.0, 0.0, 5.0, 1.0) as "score!: f64"
+        from chapter_search
+        where chapter_search match ?1 and book_id = ?2
+        order by bm25(chapter_search, 0.0, 0.0, 5.0, 1.0) limit ?3"#,
+        query,
+        book_id,
+        limit
+    )
+    .fetch_all(database)
+    .await?;
+    Ok(hits
+        .into_iter()
+        .map(|hit| SearchHit {
+            name: unsegment(&hit.name),
+            snippet: unsegment(&hit.snippet),
+            ..hit
+        })
+        .collect())
+}
+
+/// one hit per chapter ranked by the fusion of the keyword hits and the semantic hits, both most
+/// relevant first
+pub fn fuse(
+    keyword: &[SearchHit],
+    semantic: &[SemanticHit],
+    fusion: Fusion,
+    limit: usize,
+) -> Vec<HybridHit> {
+    let mut hits: Vec<HybridHit> = vec![];
+    let mut ranks: Vec<(Option<usize>, Option<usize>)> = vec![];
+    let mut chapters: HashMap<&str, usize> = HashMap::new();
+    for (rank, hit) in keyword.iter().enumerate() {
+        if chapters.contains_key(hit.chapter_number.as_str()) {
+            continue;
+        }
+        chapters.insert(&hit.chapter_number, hits.len());
+        hits.push(HybridHit {
+            chapter_number: hit.chapter_number.clone(),
+            content: hit.snippet.clone(),
+            score: 0.0,
+            keyword_score: Some(hit.score),
+            semantic_score: None,
+        });
+        ranks.push((Some(rank + 1), None));
+    }
+    for (rank, hit) in semantic.iter().enumerate() {
+        match chapters.get(hit.chapter_number.as_str()) {
+            // the best passage of the chapter comes first
+            Some(&index) if hits[index].semantic_score.is_some() => {}
+            Some(&index) => {
+                hits[index].content = hit.content.clone();
+                hits[index].semantic_score = Some(hit.score);
+                ranks[index].1 = Some(rank + 1);
+            }
+            None => {
+                chapters.insert(&hit.chapter_number, hits.len());
+                hits.push(HybridHit {
+                    chapter_number: hit.chapter_number.clone(),
+                    content: hit.content.clone(),
+                    score: 0.0,
+                    keyword_score: None,
+                    semantic_score: Some(hit.score),
+                });
+                ranks.push((None, Some(rank + 1)));
+            }
+        }
+    }
+    let best_keyword = keyword.iter().map(|hit| hit.score).fold(0.0, f64::max);
+    let best_semantic = semantic
+        .iter()
+        .map(|hit| hit.score as f64)
+        .fold(0.0, f64::max);
+    for (hit, (keyword_rank, semantic_rank)) in hits.iter_mut().zip(ranks) {
+        hit.score = match fusion {
+            Fusion::Rrf { k } => [keyword_rank, semantic_rank]
+                .into_iter()
+                .flatten()
+                .map(|rank| 1.0 / (k + rank as f64))
+                .sum(),
+            Fusion::Weighted { keyword_weight } => {
+                let scaled = |score: f64, best: f64| if best > 0.0 { score / best } else { 0.0 };
+                let keyword = hit.keyword_score.map_or(0.0, |s| scaled(s, best_keyword));
+                let semantic = hit
+                    .semantic_score
+                    .map_or(0.0, |s| scaled(s as f64, best_semantic));
+                keyword_weight * keyword + (1.0 - keyword_weight) * semantic
+            }
+        };
+    }
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hits.truncate(limit);
+    hits
+}
+
+#[test]
+fn queries() {
+    assert_eq!(fts_query("borrow checker"), r#""borrow" OR "checker""#);
+    assert_eq!(
+        fts_query(r#"NEAR("x" AND y*)"#),
+        r#""NEAR" OR "x" OR "AND" OR "y""#
+    );
+    assert_eq!(fts_query(" -- "), "");
+    assert_eq!(
+        fts_query("变量 rust"),
+        "\"变\u{200b}量\u{200b}\" OR \"rust\""
+    );
+}
+
+#[tokio::test]
+async fn chinese() {
+    let database = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    sqlx::migrate!().run(&database).await.unwrap();
+    let mut connection = database.acquire().await.unwrap();
+    insert_chapter(&mut connection, 1, "1.", "所有权", "每个值都有一个所有者。")
+        .await
+        .unwrap();
+    insert_chapter(
+        &mut connection,
+        1,
+        "2.",
+        "引用与借用",
+        "借用检查器比较作用域。",
+    )
+    .await
+    .unwrap();
+    drop(connection);
+    let hits = search(&database, 1, "借用", 10).await.unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].chapter_number, "2.");
+    assert_eq!(hits[0].name, "引用与借用");
+    assert!(hits[0].snippet.contains("**借用**检查器"));
+    // a phrase of its characters, not any of them
+    assert!(search(&database, 1, "用值", 10).await.unwrap().is_empty());
+    assert_eq!(
+        search(&database, 1, "所有", 10).await.unwrap()[0].chapter_number,
+        "1."
+    );
+}
+
+#[test]
+fn fusion() {
+    let keyword = |chapter: &str, score: f64| SearchHit {
+        chapter_number: chapter.to_string(),
+        name: String::new(),
+        snippet: format!("**{chapter}**"),
+        score,
+    };
+    let semantic = |chapter: &str, score: f32| SemanticHit {
+        chapter_number: chapter.to_string(),
+        content: format!("passage of {chapter}"),
+        score,
+    };
+    let keyword_hits = [keyword("1.", 9.0), keyword("2.", 3.0)];
+    let semantic_hits = [
+        semantic("3.", 0.9),
+        semantic("2.", 0.8),
+        semantic("2.", 0.7),
+    ];
+    let hits = fuse(&keyword_hits, &semantic_hits, Fusion::default(), 10);
+    // found both ways, chapter 2 wins with reciprocal ranks
+    assert_eq!(hits[0].chapter_number, "2.");
+    assert_eq!(hits[0].content, "passage of 2.");
+    assert_eq!(hits[0].semantic_score, Some(0.8));
+    assert_eq!(hits.len(), 3);
+    let keyword_only = fuse(
+        &keyword_hits,
+        &semantic_hits,
+        Fusion::Weighted {
+            keyword_weight: 1.0,
+        },
+        2,
+    );
+    assert_eq!(keyword_only[0].chapter_number, "1.");
+    assert_eq!(keyword_only.len(), 2);
+    assert_eq!("rrf:20".parse::<Fusion>().unwrap(), Fusion::Rrf { k: 20.0 });
+    assert!("weighted:2".parse::<Fusion>().is_err());
+}
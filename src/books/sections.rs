@@ -0,0 +1,127 @@
+use std::{collections::HashMap, ops::Range};
+
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::{
+    fuzzy,
+    render::{heading_anchor, unique_anchor},
+};
+
+/// sections suggested when a title matches none
+const SUGGESTIONS: usize = 5;
+
+/// A heading of a chapter with the text under it, up to the next heading of the same or a
+/// higher level
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Section {
+    pub title: String,
+    /// 1 for `#`, 2 for `##`, ...
+    pub level: u8,
+    /// the html anchor of the heading, as rendered by
+    /// [`render_html`](super::render::render_html)
+    pub anchor: String,
+    /// titles of the enclosing sections, outermost first
+    pub parents: Vec<String>,
+    /// byte range of the section in the chapter content, its heading included
+    #[serde(skip_serializing)]
+    #[schema(ignore)]
+    pub range: Range<usize>,
+}
+
+/// Parse the headings of the markdown content of a chapter into its sections, in order
+pub fn extract_sections(content: &str) -> Vec<Section> {
+    let mut sections: Vec<Section> = Vec::new();
+    let mut anchors = HashMap::new();
+    // the level, start and title text of the heading being read
+    let mut heading: Option<(u8, usize, String)> = None;
+    let parser = Parser::new_ext(content, Options::ENABLE_TABLES).into_offset_iter();
+    for (event, range) in parser {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                heading = Some((level as u8, range.start, String::new()))
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, _, title)) = heading.as_mut() {
+                    title.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                let Some((level, start, title)) = heading.take() else {
+                    continue;
+                };
+                let title = title.trim().to_string();
+                // the sections still open end at this heading, unless they enclose it
+                for section in sections.iter_mut() {
+                    if section.range.end == content.len() && section.level >= level {
+                        section.range.end = start;
+                    }
+                }
+                let parents = sections
+                    .iter()
+                    .filter(|section| section.range.end == content.len() && section.level < level)
+                    .map(|section| section.title.clone())
+                    .collect();
+                sections.push(Section {
+                    anchor: unique_anchor(&mut anchors, &title),
+                    title,
+                    level,
+                    parents,
+                    range: start..content.len(),
+                });
+            }
+            _ => {}
+        }
+    }
+    sections
+}
+
+/// The section of `sections` titled `title`, ignoring case and punctuation, or else the titles
+/// of the closest sections
+pub fn find_section<'a>(sections: &'a [Section], title: &str) -> Result<&'a Section, Vec<String>> {
+    let anchor = heading_anchor(title);
+    let found = sections
+        .iter()
+        .find(|section| section.title.eq_ignore_ascii_case(title.trim()))
+        .or_else(|| sections.iter().find(|section| section.anchor == anchor));
+    if let Some(section) = found {
+        return Ok(section);
+    }
+    let candidates = sections
+        .iter()
+        .map(|section| (section.title.as_str(), section));
+    let suggestions = fuzzy::rank(title, candidates, 0.2, SUGGESTIONS)
+        .into_iter()
+        .map(|(section, _)| section.title.clone())
+        .collect();
+    Err(suggestions)
+}
+
+#[test]
+fn sections() {
+    let content = "Intro text\n\n# Ownership\n\nrules\n\n## The `Box` type\n\nheap\n\n## Moves\n\nmoved\n\n# Borrowing\n\nrefs\n";
+    let sections = extract_sections(content);
+    let titles: Vec<&str> = sections.iter().map(|s| s.title.as_str()).collect();
+    assert_eq!(titles, ["Ownership", "The Box type", "Moves", "Borrowing"]);
+    assert_eq!(
+        &content[sections[0].range.clone()],
+        "# Ownership\n\nrules\n\n## The `Box` type\n\nheap\n\n## Moves\n\nmoved\n\n"
+    );
+    assert_eq!(
+        &content[sections[1].range.clone()],
+        "## The `Box` type\n\nheap\n\n"
+    );
+    assert_eq!(sections[2].parents, ["Ownership"]);
+    assert_eq!(sections[3].range.end, content.len());
+    assert_eq!(sections[1].anchor, "the-box-type");
+    assert_eq!(
+        find_section(&sections, "the box type").unwrap().title,
+        "The Box type"
+    );
+    assert_eq!(find_section(&sections, "the-box-type").unwrap().level, 2);
+    assert_eq!(
+        find_section(&sections, "Borowing").unwrap_err(),
+        ["Borrowing"]
+    );
+}
@@ -0,0 +1,79 @@
+use std::{collections::BTreeMap, ops::AddAssign, sync::LazyLock};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{
+    blocks::{BlockKind, ContentBlock},
+    chapter::ChapterNumber,
+    text::{self, CJK_CHARS_PER_MINUTE},
+};
+
+/// average silent reading speed of technical text
+pub const WORDS_PER_MINUTE: u64 = 200;
+
+/// headings or numbered paragraphs that start an exercise
+static EXERCISE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?im)^(#+\s.*\b(exercises?|practice|problems?|quiz)\b|\s*\**(exercise|problem)\s+\d)",
+    )
+    .unwrap()
+});
+
+/// Size of a chapter or a whole book
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ContentStats {
+    /// whitespace separated words, each CJK character counts as a word
+    pub word_count: u64,
+    /// estimated reading time, without exercises
+    pub reading_minutes: u64,
+    pub code_blocks: u64,
+    pub figures: u64,
+    pub tables: u64,
+    pub exercises: u64,
+}
+
+impl ContentStats {
+    pub fn compute(content: &str, blocks: &[ContentBlock]) -> Self {
+        let (words, cjk_chars) = text::count_words(content);
+        let count = |kind: BlockKind| blocks.iter().filter(|b| b.kind == kind).count() as u64;
+        Self {
+            word_count: words + cjk_chars,
+            reading_minutes: (words as f64 / WORDS_PER_MINUTE as f64
+                + cjk_chars as f64 / CJK_CHARS_PER_MINUTE as f64)
+                .ceil() as u64,
+            code_blocks: count(BlockKind::Code),
+            figures: count(BlockKind::Figure),
+            tables: count(BlockKind::Table),
+            exercises: EXERCISE.find_iter(content).count() as u64,
+        }
+    }
+}
+
+impl AddAssign<&ContentStats> for ContentStats {
+    fn add_assign(&mut self, other: &ContentStats) {
+        self.word_count += other.word_count;
+        self.reading_minutes += other.reading_minutes;
+        self.code_blocks += other.code_blocks;
+        self.figures += other.figures;
+        self.tables += other.tables;
+        self.exercises += other.exercises;
+    }
+}
+
+/// Statistics of a book and each of its chapters
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BookStats {
+    pub total: ContentStats,
+    pub chapters: BTreeMap<ChapterNumber, ContentStats>,
+}
+
+#[test]
+fn compute() {
+    let content = "# Verbs\n\nRun, jump and swim.\n\n## Exercises\n\n**Exercise 1** pick a verb\n";
+    let stats = ContentStats::compute(content, &[]);
+    assert_eq!(stats.word_count, 13);
+    assert_eq!(stats.reading_minutes, 1);
+    assert_eq!(stats.exercises, 2);
+}
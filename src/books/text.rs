@@ -0,0 +1,184 @@
+/// average reading speed of CJK text, in characters
+pub const CJK_CHARS_PER_MINUTE: u64 = 400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Ltr,
+    Rtl,
+}
+
+/// Han, Hiragana, Katakana, Hangul and fullwidth forms
+pub fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x11FF
+        | 0x2E80..=0x2FDF
+        | 0x3040..=0x30FF
+        | 0x3100..=0x312F
+        | 0x3130..=0x318F
+        | 0x31A0..=0x31FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xAC00..=0xD7AF
+        | 0xF900..=0xFAFF
+        | 0xFF00..=0xFFEF
+        | 0x20000..=0x2FA1F
+    )
+}
+
+/// Hebrew, Arabic, Syriac, Thaana, NKo and the Hebrew/Arabic presentation forms
+pub fn is_rtl(c: char) -> bool {
+    matches!(c as u32,
+        0x0590..=0x07FF | 0xFB1D..=0xFDFF | 0xFE70..=0xFEFF
+    )
+}
+
+/// direction of the first strong character, like the html `dir="auto"` rule
+pub fn direction(text: &str) -> Direction {
+    for c in text.chars() {
+        if is_rtl(c) {
+            return Direction::Rtl;
+        }
+        if c.is_alphabetic() {
+            return Direction::Ltr;
+        }
+    }
+    Direction::Ltr
+}
+
+/// wrap RTL text in unicode isolates so it doesn't reorder the surrounding LTR text
+pub fn isolate(text: &str) -> String {
+    match direction(text) {
+        Direction::Rtl => format!("\u{2067}{text}\u{2069}"),
+        Direction::Ltr => text.to_string(),
+    }
+}
+
+/// true if more than half of the letters are CJK
+pub fn is_mostly_cjk(text: &str) -> bool {
+    let (mut cjk, mut letters) = (0usize, 0usize);
+    for c in text.chars().filter(|c| c.is_alphanumeric()) {
+        letters += 1;
+        if is_cjk(c) {
+            cjk += 1;
+        }
+    }
+    letters > 0 && cjk * 2 > letters
+}
+
+/// count whitespace separated words and CJK characters, returns `(words, cjk_chars)`
+pub fn count_words(text: &str) -> (u64, u64) {
+    let (mut words, mut cjk_chars) = (0, 0);
+    for token in text.split_whitespace() {
+        let cjk = token.chars().filter(|c| is_cjk(*c)).count() as u64;
+        cjk_chars += cjk;
+        if cjk == 0 || token.chars().any(|c| c.is_alphanumeric() && !is_cjk(c)) {
+            words += 1;
+        }
+    }
+    (words, cjk_chars)
+}
+
+/// a length limit for prompts, in words or, for CJK text, in characters
+pub fn length_limit(limit: usize, text: &str) -> String {
+    if is_mostly_cjk(text) {
+        format!("{} characters", limit * 2)
+    } else {
+        format!("{limit} words")
+    }
+}
+
+/// whether `c` ends a sentence, CJK full stops end it even without a following space
+pub fn is_sentence_end(c: char, next: Option<char>) -> bool {
+    match c {
+        '。' | '！' | '？' | '｡' => true,
+        '.' | '!' | '?' | '؟' | '۔' | '।' => next.is_none_or(char::is_whitespace),
+        _ => false,
+    }
+}
+
+/// join lines or sentences, without a space between CJK characters
+pub fn join(parts: &[&str]) -> String {
+    let mut joined = String::new();
+    for part in parts {
+        let part = part.trim();
+        if part.is_empty() {
+            continue;
+        }
+        let glue = joined.chars().next_back().is_some_and(is_cjk_boundary)
+            && part.chars().next().is_some_and(is_cjk_boundary);
+        if !joined.is_empty() && !glue {
+            joined.push(' ');
+        }
+        joined.push_str(part);
+    }
+    joined
+}
+
+fn is_cjk_boundary(c: char) -> bool {
+    is_cjk(c)
+        || matches!(
+            c,
+            '。' | '、' | '，' | '！' | '？' | '：' | '；' | '」' | '「'
+        )
+}
+
+/// lowercase search tokens, runs of CJK characters become overlapping bigrams
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for word in text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+    {
+        let mut latin = String::new();
+        let mut cjk: Vec<char> = Vec::new();
+        for c in word.chars() {
+            if is_cjk(c) {
+                if !latin.is_empty() {
+                    tokens.push(std::mem::take(&mut latin));
+                }
+                cjk.push(c);
+            } else {
+                push_cjk_bigrams(&mut cjk, &mut tokens);
+                latin.push(c);
+            }
+        }
+        push_cjk_bigrams(&mut cjk, &mut tokens);
+        if !latin.is_empty() {
+            tokens.push(latin);
+        }
+    }
+    tokens
+}
+
+fn push_cjk_bigrams(run: &mut Vec<char>, tokens: &mut Vec<String>) {
+    match run.len() {
+        0 => {}
+        1 => tokens.push(run[0].to_string()),
+        _ => tokens.extend(run.windows(2).map(|w| w.iter().collect())),
+    }
+    run.clear();
+}
+
+#[test]
+fn cjk() {
+    assert_eq!(count_words("Rust 是一门 language"), (2, 3));
+    assert!(is_mostly_cjk("第三章 动词时态"));
+    assert_eq!(
+        tokenize("动词时态 Verb"),
+        vec!["动词", "词时", "时态", "verb"]
+    );
+    assert_eq!(
+        join(&["第一句。", "第二句", "and more"]),
+        "第一句。第二句 and more"
+    );
+    assert!(is_sentence_end('。', Some('第')));
+    assert!(!is_sentence_end('.', Some('2')));
+}
+
+#[test]
+fn rtl() {
+    assert_eq!(direction("123 שלום"), Direction::Rtl);
+    assert_eq!(direction("Hello שלום"), Direction::Ltr);
+    assert_eq!(isolate("مرحبا"), "\u{2067}مرحبا\u{2069}");
+}
@@ -2,26 +2,103 @@ use std::sync::Arc;
 
 use async_openai::tools::Tool;
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 use super::{
+    blocks::ContentBlock,
+    book::{Book, ChapterMatch},
     chapter::{Chapter, ChapterNumber},
+    datasets::{DEFAULT_PREVIEW_ROWS, DatasetInfo, DatasetPreview},
     library::Library,
+    search::{DEFAULT_FUSION, HybridHit, SearchHit},
+    sections::Section,
 };
 
+/// The books of a teaching session: the main one and the books linked to it for
+/// cross-referencing, e.g. a grammar book and its workbook
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct SessionBooks {
+    /// the book the session is on
+    pub main: i64,
+    /// the books linked to it, in the student's library
+    pub linked: Vec<i64>,
+}
+
+impl SessionBooks {
+    pub fn single(book_id: i64) -> Self {
+        Self {
+            main: book_id,
+            linked: vec![],
+        }
+    }
+
+    /// the book a tool call selects, the main one when it names none
+    pub fn select(&self, book_id: Option<i64>) -> anyhow::Result<i64> {
+        match book_id {
+            None => Ok(self.main),
+            Some(id) if id == self.main || self.linked.contains(&id) => Ok(id),
+            Some(id) => anyhow::bail!("Book {} is not part of this session", id),
+        }
+    }
+
+    /// the main book first, then the linked ones
+    pub fn all(&self) -> impl Iterator<Item = i64> + '_ {
+        std::iter::once(self.main).chain(self.linked.iter().copied())
+    }
+}
+
+/// A chapter of a book of the session
+#[derive(Debug, Clone, JsonSchema)]
+pub struct ChapterRequest {
+    /// The id of the book, the main book of the session when missing
+    pub book_id: Option<i64>,
+    /// The chapter number, e.g. "3.", "4.2."
+    pub chapter_number: ChapterNumber,
+}
+
+/// also a bare chapter number, as the tool took before sessions had several books
+impl<'de> Deserialize<'de> for ChapterRequest {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Number(ChapterNumber),
+            Request {
+                #[serde(default)]
+                book_id: Option<i64>,
+                chapter_number: ChapterNumber,
+            },
+        }
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Number(chapter_number) => Self {
+                book_id: None,
+                chapter_number,
+            },
+            Repr::Request {
+                book_id,
+                chapter_number,
+            } => Self {
+                book_id,
+                chapter_number,
+            },
+        })
+    }
+}
+
 pub struct GetChapterTool {
-    book_id: i64,
+    books: SessionBooks,
     library: Arc<Library>,
 }
 
 impl GetChapterTool {
-    pub fn new(book_id: i64, library: Arc<Library>) -> Self {
-        Self { book_id, library }
+    pub fn new(books: SessionBooks, library: Arc<Library>) -> Self {
+        Self { books, library }
     }
 }
 
 impl Tool for GetChapterTool {
-    type Args = ChapterNumber;
+    type Args = ChapterRequest;
     type Output = Chapter;
     type Error = anyhow::Error;
     fn name() -> String {
@@ -35,11 +112,15 @@ impl Tool for GetChapterTool {
         )
     }
     async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
-        let book = self.library.get_book(self.book_id).await?;
+        let book_id = self.books.select(args.book_id)?;
+        let book = self.library.get_book(book_id).await?;
         let chapter = book
             .chapters
-            .get(&args)
-            .ok_or(anyhow::anyhow!("Chapter not found: {:?}", args))?;
+            .get(&args.chapter_number)
+            .ok_or(anyhow::anyhow!(
+                "Chapter not found: {}",
+                args.chapter_number
+            ))?;
         Ok(chapter.clone())
     }
 }
@@ -47,23 +128,71 @@ impl Tool for GetChapterTool {
 async fn t() {
     println!("{:#?}", BookJumpTool::definition());
 }
+
+#[test]
+fn session_books() {
+    let books = SessionBooks {
+        main: 1,
+        linked: vec![2],
+    };
+    assert_eq!(books.select(None).unwrap(), 1);
+    assert_eq!(books.select(Some(2)).unwrap(), 2);
+    assert!(books.select(Some(3)).is_err());
+    let request: ChapterRequest = serde_json::from_str(r#""3.1""#).unwrap();
+    assert_eq!(request.book_id, None);
+    let request: ChapterRequest =
+        serde_json::from_str(r#"{"book_id": 2, "chapter_number": "3.1"}"#).unwrap();
+    assert_eq!(request.book_id, Some(2));
+    assert_eq!(request.chapter_number, "3.1.".parse().unwrap());
+}
 /// Specifies a location in the book by chapter number and optional section title
-#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
 pub struct BookLocation {
+    /// The id of the book, the main book of the session when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub book_id: Option<i64>,
     /// The chapter number to navigate to
     pub chapter_number: ChapterNumber,
     /// Optional section title within the chapter
     pub sector_title: Option<String>,
+    /// html anchor of the section, filled in once the title is resolved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(skip)]
+    pub anchor: Option<String>,
+}
+
+impl BookLocation {
+    /// the location with the section title as written in the book and its anchor, an error
+    /// listing the closest titles when the chapter has no such section
+    pub fn resolve(&self, book: &Book) -> anyhow::Result<BookLocation> {
+        let chapter = book
+            .chapters
+            .get(&self.chapter_number)
+            .ok_or(anyhow::anyhow!(
+                "Chapter not found: {}",
+                self.chapter_number
+            ))?;
+        let Some(title) = &self.sector_title else {
+            return Ok(self.clone());
+        };
+        let section = chapter.find_section(title)?;
+        Ok(BookLocation {
+            book_id: self.book_id,
+            chapter_number: self.chapter_number.clone(),
+            sector_title: Some(section.title.clone()),
+            anchor: Some(section.anchor.clone()),
+        })
+    }
 }
 
 pub struct BookJumpTool {
-    book_id: i64,
+    books: SessionBooks,
     library: Arc<Library>,
 }
 
 impl BookJumpTool {
-    pub fn new(book_id: i64, library: Arc<Library>) -> Self {
-        Self { book_id, library }
+    pub fn new(books: SessionBooks, library: Arc<Library>) -> Self {
+        Self { books, library }
     }
 }
 
@@ -78,26 +207,419 @@ impl Tool for BookJumpTool {
         Some(
             "Use this tool to navigate to a specific chapter or section in the book \
              when you need the student to read particular content. It helps direct the \
-             student's attention to the relevant material."
+             student's attention to the relevant material. The section title must be a heading \
+             of the chapter; the text of the section is returned."
                 .to_string(),
         )
     }
     async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
-        let book = self.library.get_book(self.book_id).await?;
+        let book = self
+            .library
+            .get_book(self.books.select(args.book_id)?)
+            .await?;
+        let location = args.resolve(&book)?;
+        let chapter = &book.chapters[&location.chapter_number];
+        let Some(title) = &location.sector_title else {
+            return Ok(format!(
+                "Jumped to {} {}",
+                location.chapter_number, chapter.name
+            ));
+        };
+        let section = chapter.find_section(title)?;
+        Ok(format!(
+            "Jumped to {} {}#{}\n\n{}",
+            location.chapter_number,
+            chapter.name,
+            title,
+            chapter.section_text(section)
+        ))
+    }
+}
+
+/// Identifies a section of a chapter by its heading
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct SectionQuery {
+    /// The id of the book, the main book of the session when missing
+    #[serde(default)]
+    pub book_id: Option<i64>,
+    /// The chapter number of the section
+    pub chapter_number: ChapterNumber,
+    /// The heading of the section, e.g. "Verb Tenses"
+    pub title: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SectionContent {
+    pub chapter_number: ChapterNumber,
+    #[serde(flatten)]
+    pub section: Section,
+    /// the markdown of the section, its heading and subsections included
+    pub content: String,
+}
+
+pub struct GetSectionTool {
+    books: SessionBooks,
+    library: Arc<Library>,
+}
+
+impl GetSectionTool {
+    pub fn new(books: SessionBooks, library: Arc<Library>) -> Self {
+        Self { books, library }
+    }
+}
+
+impl Tool for GetSectionTool {
+    type Args = SectionQuery;
+    type Output = SectionContent;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "GetSection".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Get the text of one section of a chapter by its heading, instead of the whole chapter. \
+            When the heading doesn't exist, the error lists the closest ones."
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        let book = self
+            .library
+            .get_book(self.books.select(args.book_id)?)
+            .await?;
         let chapter = book
             .chapters
             .get(&args.chapter_number)
             .ok_or(anyhow::anyhow!(
-                "Chapter not found: {:?}",
+                "Chapter not found: {}",
                 args.chapter_number
             ))?;
-        let sector_title = args
-            .sector_title
-            .map(|s| "#".to_string() + &s)
-            .unwrap_or_default();
-        Ok(format!(
-            "Jumped to {} {}{}",
-            args.chapter_number, chapter.name, sector_title
-        ))
+        let section = chapter.find_section(&args.title)?;
+        Ok(SectionContent {
+            chapter_number: args.chapter_number,
+            section: section.clone(),
+            content: chapter.section_text(section).to_string(),
+        })
+    }
+}
+
+/// Describes a chapter by its approximate title
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ChapterQuery {
+    /// The id of the book, the main book of the session when missing
+    #[serde(default)]
+    pub book_id: Option<i64>,
+    /// The title or a short description of the chapter, e.g. "verb tenses"
+    pub title: String,
+}
+
+pub struct FindChapterTool {
+    books: SessionBooks,
+    library: Arc<Library>,
+}
+
+impl FindChapterTool {
+    pub fn new(books: SessionBooks, library: Arc<Library>) -> Self {
+        Self { books, library }
+    }
+}
+
+impl Tool for FindChapterTool {
+    type Args = ChapterQuery;
+    type Output = Vec<ChapterMatch>;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "FindChapter".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Find chapters by approximate title when you know what a chapter is about \
+            but not its number. Returns the best matching chapter numbers with a similarity score."
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        let book = self
+            .library
+            .get_book(self.books.select(args.book_id)?)
+            .await?;
+        let matches = book.find_chapters_by_title(&args.title, 5);
+        if matches.is_empty() {
+            anyhow::bail!("No chapter matches title: {}", args.title);
+        }
+        Ok(matches)
+    }
+}
+
+/// Words to look for in the text of the book
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct SearchQuery {
+    /// The id of the book, the main book of the session when missing
+    #[serde(default)]
+    pub book_id: Option<i64>,
+    /// Keywords or a short phrase, e.g. "borrow checker lifetimes"
+    pub query: String,
+}
+
+pub struct SearchBookTool {
+    books: SessionBooks,
+    library: Arc<Library>,
+}
+
+impl SearchBookTool {
+    pub fn new(books: SessionBooks, library: Arc<Library>) -> Self {
+        Self { books, library }
+    }
+}
+
+impl Tool for SearchBookTool {
+    type Args = SearchQuery;
+    type Output = Vec<SearchHit>;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "SearchBook".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Search the full text of all chapters when you need to know where a term or topic \
+            is covered. Returns chapter numbers with an excerpt around the match and a relevance score."
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        let hits = self
+            .library
+            .search_book(self.books.select(args.book_id)?, &args.query, 5)
+            .await?;
+        if hits.is_empty() {
+            anyhow::bail!("No chapter mentions: {}", args.query);
+        }
+        Ok(hits)
+    }
+}
+
+/// A question or statement to find related passages for
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct SemanticQuery {
+    /// The id of the book, the main book of the session when missing
+    #[serde(default)]
+    pub book_id: Option<i64>,
+    /// The question in natural language, e.g. "why can't two mutable references coexist?"
+    pub query: String,
+}
+
+pub struct SemanticSearchTool {
+    books: SessionBooks,
+    library: Arc<Library>,
+    /// charged for the embedding of the query
+    student_id: i64,
+}
+
+impl SemanticSearchTool {
+    pub fn new(books: SessionBooks, library: Arc<Library>, student_id: i64) -> Self {
+        Self {
+            books,
+            library,
+            student_id,
+        }
+    }
+}
+
+impl Tool for SemanticSearchTool {
+    type Args = SemanticQuery;
+    type Output = Vec<HybridHit>;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "SemanticSearch".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Find the passages of the book that answer a question, by meaning and by its exact terms, \
+            to answer it grounded in the book without loading whole chapters. Returns passages with their chapter numbers."
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        self.library
+            .hybrid_search(
+                self.books.select(args.book_id)?,
+                &args.query,
+                5,
+                *DEFAULT_FUSION,
+                Some(self.student_id),
+            )
+            .await
+    }
+}
+
+/// An original print page number of the book
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct PageQuery {
+    /// The id of the book, the main book of the session when missing
+    #[serde(default)]
+    pub book_id: Option<i64>,
+    /// The page number as printed in the original book, e.g. 212
+    pub page: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PageLocation {
+    pub page: u32,
+    pub chapter_number: ChapterNumber,
+    pub chapter_name: String,
+}
+
+pub struct ResolvePageTool {
+    books: SessionBooks,
+    library: Arc<Library>,
+}
+
+impl ResolvePageTool {
+    pub fn new(books: SessionBooks, library: Arc<Library>) -> Self {
+        Self { books, library }
+    }
+}
+
+impl Tool for ResolvePageTool {
+    type Args = PageQuery;
+    type Output = PageLocation;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "ResolvePage".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Find the chapter that contains a page of the original printed book, \
+            use it when the student refers to a page number like \"explain page 212\"."
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        let book = self
+            .library
+            .get_book(self.books.select(args.book_id)?)
+            .await?;
+        if book.page_map.is_empty() {
+            anyhow::bail!("This book has no original page numbers");
+        }
+        let chapter = book
+            .resolve_page(args.page)
+            .ok_or(anyhow::anyhow!("Page not found: {}", args.page))?;
+        Ok(PageLocation {
+            page: args.page,
+            chapter_number: chapter.number.clone(),
+            chapter_name: chapter.name.clone(),
+        })
+    }
+}
+
+/// Identifies a table, code block or figure of the book
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct BlockQuery {
+    /// The id of the book, the main book of the session when missing
+    #[serde(default)]
+    pub book_id: Option<i64>,
+    /// The block id, e.g. "Table 3.1", "Code 2.4", "Figure 5.2"
+    pub block_id: String,
+}
+
+pub struct GetBlockTool {
+    books: SessionBooks,
+    library: Arc<Library>,
+}
+
+impl GetBlockTool {
+    pub fn new(books: SessionBooks, library: Arc<Library>) -> Self {
+        Self { books, library }
+    }
+}
+
+impl Tool for GetBlockTool {
+    type Args = BlockQuery;
+    type Output = ContentBlock;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "GetBlock".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Get a single table, code block or figure of the book by id, \
+            the id is the kind followed by the chapter number and the index in the chapter, \
+            e.g. \"Table 3.1\" is the first table of chapter 3."
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        let book = self
+            .library
+            .get_book(self.books.select(args.book_id)?)
+            .await?;
+        let block = book
+            .find_block(&args.block_id)
+            .ok_or(anyhow::anyhow!("Block not found: {}", args.block_id))?;
+        Ok(block.clone())
+    }
+}
+
+/// A dataset of a book to preview, or none to list them
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct DatasetQuery {
+    /// The id of the book, the main book of the session when missing
+    #[serde(default)]
+    pub book_id: Option<i64>,
+    /// The path of the dataset, e.g. "data/titanic.csv"; omit it to list the datasets of the book
+    #[serde(default)]
+    pub path: Option<String>,
+    /// How many of the first rows to show, 10 by default, at most 50
+    #[serde(default)]
+    pub rows: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum DatasetAnswer {
+    Datasets(Vec<DatasetInfo>),
+    Preview(DatasetPreview),
+}
+
+pub struct PreviewDatasetTool {
+    books: SessionBooks,
+    library: Arc<Library>,
+}
+
+impl PreviewDatasetTool {
+    pub fn new(books: SessionBooks, library: Arc<Library>) -> Self {
+        Self { books, library }
+    }
+}
+
+impl Tool for PreviewDatasetTool {
+    type Args = DatasetQuery;
+    type Output = DatasetAnswer;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "PreviewDataset".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Preview a dataset the book ships, a CSV, TSV or JSON lines file: its columns with \
+            their type, missing values and an example, its row count and its first rows. \
+            Without a path, list the datasets of the book. Use the real column names and values \
+            when a lesson works with the dataset."
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        let book_id = self.books.select(args.book_id)?;
+        match args.path {
+            None => Ok(DatasetAnswer::Datasets(
+                self.library.book_datasets(book_id).await?,
+            )),
+            Some(path) => Ok(DatasetAnswer::Preview(
+                self.library
+                    .dataset_preview(book_id, &path, args.rows.unwrap_or(DEFAULT_PREVIEW_ROWS))
+                    .await?,
+            )),
+        }
     }
 }
@@ -0,0 +1,195 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use anyhow::{Context, bail};
+use mdbook::book::{SummaryItem, parse_summary};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::chapter::{ChapterNumber, ChapterRaw};
+
+/// markdown images `![alt](src)` and html `<img src="...">`
+static IMAGE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"!\[[^\]]*\]\(([^)\s]+)[^)]*\)|<img[^>]*?\ssrc="([^"]+)""#).unwrap()
+});
+
+/// A link or asset reference of a chapter that points nowhere
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct BrokenReference {
+    pub chapter_number: ChapterNumber,
+    pub target: String,
+}
+
+/// Problems found while importing a book, to be fixed by the author before publishing
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct ValidationReport {
+    /// intra-book links to chapters that don't exist
+    pub dead_links: Vec<BrokenReference>,
+    /// images referenced by chapters but missing from the book sources
+    pub missing_assets: Vec<BrokenReference>,
+    /// chapters without any text besides headings
+    pub empty_chapters: Vec<ChapterNumber>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.dead_links.is_empty()
+            && self.missing_assets.is_empty()
+            && self.empty_chapters.is_empty()
+    }
+
+    pub fn add_dead_link(&mut self, chapter_number: &ChapterNumber, target: String) {
+        self.dead_links.push(BrokenReference {
+            chapter_number: chapter_number.clone(),
+            target,
+        });
+    }
+
+    /// check the images and the emptiness of a chapter, `src_dir` is the book `src` directory
+    pub fn check_chapter(&mut self, src_dir: &Path, ch: &ChapterRaw) {
+        let is_empty = ch
+            .content
+            .lines()
+            .map(str::trim)
+            .all(|line| line.is_empty() || line.starts_with('#'));
+        if is_empty {
+            self.empty_chapters.push(ch.number.clone());
+        }
+        let dir = match ch.path.as_deref().and_then(Path::parent) {
+            Some(parent) => src_dir.join(parent),
+            None => src_dir.to_path_buf(),
+        };
+        for target in image_targets(&ch.content) {
+            if target.contains("://") || target.starts_with("data:") {
+                continue;
+            }
+            let file = target.split(['?', '#']).next().unwrap_or_default();
+            if !dir.join(file).is_file() {
+                self.missing_assets.push(BrokenReference {
+                    chapter_number: ch.number.clone(),
+                    target,
+                });
+            }
+        }
+    }
+}
+
+/// image sources referenced by markdown content
+pub fn image_targets(content: &str) -> Vec<String> {
+    IMAGE
+        .captures_iter(content)
+        .filter_map(|c| c.get(1).or(c.get(2)))
+        .map(|m| m.as_str().to_string())
+        .collect()
+}
+
+/// the directory of the mdbook in `dir`, `dir` itself or its only subdirectory, as archives
+/// often wrap the book in a folder
+pub fn mdbook_root(dir: &Path) -> anyhow::Result<PathBuf> {
+    if dir.join("book.toml").is_file() {
+        return Ok(dir.to_path_buf());
+    }
+    let mut entries = std::fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| !path.file_name().is_some_and(|name| name == "__MACOSX"));
+    match (entries.next(), entries.next()) {
+        (Some(only), None) if only.join("book.toml").is_file() => Ok(only),
+        _ => bail!("No book.toml found"),
+    }
+}
+
+/// check that the mdbook at `root_dir` has a `SUMMARY.md` that parses and only links chapter
+/// files that exist, the import would create empty ones otherwise
+pub fn check_summary(root_dir: &Path) -> anyhow::Result<()> {
+    let book_toml = std::fs::read_to_string(root_dir.join("book.toml"))?;
+    let src_dir = root_dir.join(
+        toml::from_str::<mdbook::config::Config>(&book_toml)?
+            .book
+            .src,
+    );
+    let summary =
+        std::fs::read_to_string(src_dir.join("SUMMARY.md")).context("No SUMMARY.md found")?;
+    let summary = parse_summary(&summary).context("Invalid SUMMARY.md")?;
+    let mut missing = Vec::new();
+    let mut items: Vec<&SummaryItem> = summary
+        .prefix_chapters
+        .iter()
+        .chain(&summary.numbered_chapters)
+        .chain(&summary.suffix_chapters)
+        .collect();
+    let mut chapters = 0;
+    while let Some(item) = items.pop() {
+        let SummaryItem::Link(link) = item else {
+            continue;
+        };
+        chapters += 1;
+        if let Some(location) = &link.location {
+            if !src_dir.join(location).is_file() {
+                missing.push(location.display().to_string());
+            }
+        }
+        items.extend(&link.nested_items);
+    }
+    if chapters == 0 {
+        bail!("SUMMARY.md lists no chapters");
+    }
+    if !missing.is_empty() {
+        missing.sort();
+        bail!("SUMMARY.md links missing files: {}", missing.join(", "));
+    }
+    Ok(())
+}
+
+#[test]
+fn check() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("ch1")).unwrap();
+    std::fs::write(dir.path().join("ch1/ok.png"), b"").unwrap();
+    let ch = ChapterRaw {
+        number: "1.".parse().unwrap(),
+        path: Some(PathBuf::from("ch1/intro.md")),
+        content: "![ok](ok.png) ![gone](gone.png \"t\") <img alt=\"x\" src=\"web.png\">"
+            .to_string(),
+        ..Default::default()
+    };
+    let empty = ChapterRaw {
+        number: "2.".parse().unwrap(),
+        content: "# Title\n\n".to_string(),
+        ..Default::default()
+    };
+    let mut report = ValidationReport::default();
+    report.check_chapter(dir.path(), &ch);
+    report.check_chapter(dir.path(), &empty);
+    let missing: Vec<&str> = report
+        .missing_assets
+        .iter()
+        .map(|r| r.target.as_str())
+        .collect();
+    assert_eq!(missing, ["gone.png", "web.png"]);
+    assert_eq!(report.empty_chapters, vec!["2.".parse().unwrap()]);
+    assert!(!report.is_clean());
+}
+
+#[test]
+fn summary() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("my-book");
+    std::fs::create_dir_all(root.join("src")).unwrap();
+    std::fs::write(root.join("book.toml"), "[book]\ntitle = \"My Book\"\n").unwrap();
+    std::fs::write(root.join("src/intro.md"), "# Intro").unwrap();
+    assert!(check_summary(&root).is_err());
+    std::fs::write(
+        root.join("src/SUMMARY.md"),
+        "# Summary\n\n- [Intro](intro.md)\n  - [Details](details.md)\n- [Draft]()\n",
+    )
+    .unwrap();
+    assert_eq!(mdbook_root(dir.path()).unwrap(), root);
+    let error = check_summary(&root).unwrap_err().to_string();
+    assert_eq!(error, "SUMMARY.md links missing files: details.md");
+    std::fs::write(root.join("src/details.md"), "# Details").unwrap();
+    check_summary(&root).unwrap();
+}
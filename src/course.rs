@@ -0,0 +1,379 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::{Date, Duration};
+use utoipa::ToSchema;
+
+use crate::{
+    books::{book::Book, chapter::ChapterNumber},
+    i18n,
+    teacher::messages::pace::StudyPace,
+};
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+/// One session of a course: the chapters to study on a date
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Lesson {
+    /// the number of the session in the course, from 1
+    pub session: u32,
+    #[serde(with = "iso_date")]
+    #[schema(value_type = String, format = Date)]
+    pub date: Date,
+    #[schema(value_type = Vec<String>)]
+    pub chapters: Vec<ChapterNumber>,
+}
+
+/// The schedule of a student through a book, lessons in order
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Course {
+    pub book_id: i64,
+    pub lessons: Vec<Lesson>,
+}
+
+/// How to spread the remaining chapters of a book over sessions
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CoursePlan {
+    pub book_id: i64,
+    /// the date of the first session, today by default
+    #[serde(default, with = "iso_date::option")]
+    #[schema(value_type = Option<String>, format = Date)]
+    pub start_date: Option<Date>,
+    /// sessions per week, spread evenly over it, from 1 to 7
+    pub sessions_per_week: u8,
+    /// study time of a session, a chapter longer than that gets a session of its own
+    pub minutes_per_session: u32,
+}
+
+/// Where the student stands on the schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PacingStatus {
+    OnTrack,
+    /// chapters of past sessions are not completed
+    Behind,
+    /// chapters of coming sessions are already completed
+    Ahead,
+    /// every chapter of the course is completed
+    Finished,
+}
+
+/// The lesson of the day and the pacing of the student, as given to the teacher
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TodaysLesson {
+    #[serde(with = "iso_date")]
+    #[schema(value_type = String, format = Date)]
+    pub today: Date,
+    pub status: PacingStatus,
+    /// the lesson of today, or else the next one, `None` after the last one
+    pub lesson: Option<Lesson>,
+    /// chapters of past sessions not completed yet, to catch up on first
+    #[schema(value_type = Vec<String>)]
+    pub overdue: Vec<ChapterNumber>,
+    /// sessions from the current one to the end of the course
+    pub remaining_sessions: usize,
+}
+
+impl Course {
+    /// the pacing of the student on `today`, having completed the `completed` chapters
+    pub fn todays_lesson(&self, today: Date, completed: &HashSet<ChapterNumber>) -> TodaysLesson {
+        let overdue: Vec<ChapterNumber> = self
+            .lessons
+            .iter()
+            .filter(|lesson| lesson.date < today)
+            .flat_map(|lesson| &lesson.chapters)
+            .filter(|number| !completed.contains(*number))
+            .cloned()
+            .collect();
+        let coming: Vec<&Lesson> = self
+            .lessons
+            .iter()
+            .filter(|lesson| lesson.date >= today)
+            .collect();
+        let finished = self
+            .lessons
+            .iter()
+            .flat_map(|lesson| &lesson.chapters)
+            .all(|number| completed.contains(number));
+        let ahead = coming
+            .iter()
+            .filter(|lesson| lesson.date > today)
+            .flat_map(|lesson| &lesson.chapters)
+            .any(|number| completed.contains(number));
+        let status = if finished {
+            PacingStatus::Finished
+        } else if !overdue.is_empty() {
+            PacingStatus::Behind
+        } else if ahead {
+            PacingStatus::Ahead
+        } else {
+            PacingStatus::OnTrack
+        };
+        TodaysLesson {
+            today,
+            status,
+            lesson: coming.first().map(|lesson| (*lesson).clone()),
+            overdue,
+            remaining_sessions: coming.len(),
+        }
+    }
+}
+
+/// the date of the session `index`, from 0, with `sessions_per_week` sessions spread evenly
+fn session_date(start: Date, index: u32, sessions_per_week: u8) -> Date {
+    let days = index as i64 * 7 / sessions_per_week as i64;
+    start + Duration::days(days)
+}
+
+/// Spread the chapters of the book over sessions of `minutes_per_session`, in book order, with
+/// the study time of each chapter estimated from the student's `pace`. The `skip` chapters, e.g.
+/// completed ones, are left out.
+pub fn schedule(
+    book: &Book,
+    pace: &StudyPace,
+    skip: &HashSet<ChapterNumber>,
+    start: Date,
+    sessions_per_week: u8,
+    minutes_per_session: u32,
+) -> anyhow::Result<Vec<Lesson>> {
+    if !(1..=7).contains(&sessions_per_week) {
+        anyhow::bail!(
+            "Sessions per week must be between 1 and 7, got {}",
+            sessions_per_week
+        );
+    }
+    if minutes_per_session == 0 {
+        anyhow::bail!("Minutes per session must be more than 0");
+    }
+    let mut sessions: Vec<Vec<ChapterNumber>> = Vec::new();
+    let mut minutes = 0;
+    for number in book.chapters.keys() {
+        if skip.contains(number) {
+            continue;
+        }
+        let estimated = pace
+            .estimate(book, number)
+            .map_or(0, |estimate| estimate.estimated_minutes)
+            .max(1);
+        match sessions.last_mut() {
+            Some(session) if minutes + estimated <= minutes_per_session as u64 => {
+                session.push(number.clone());
+                minutes += estimated;
+            }
+            _ => {
+                sessions.push(vec![number.clone()]);
+                minutes = estimated;
+            }
+        }
+    }
+    Ok(sessions
+        .into_iter()
+        .enumerate()
+        .map(|(i, chapters)| Lesson {
+            session: i as u32 + 1,
+            date: session_date(start, i as u32, sessions_per_week),
+            chapters,
+        })
+        .collect())
+}
+
+pub async fn get_course(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+) -> anyhow::Result<Option<Course>> {
+    let records = sqlx::query!(
+        r#"select session, lesson_date as "lesson_date: Date", chapter_number from course_lesson
+        where student_id = ? and book_id = ? order by session, chapter_number"#,
+        student_id,
+        book_id
+    )
+    .fetch_all(database)
+    .await?;
+    if records.is_empty() {
+        return Ok(None);
+    }
+    let mut lessons: Vec<Lesson> = Vec::new();
+    for record in records {
+        let number: ChapterNumber = record.chapter_number.parse()?;
+        match lessons.last_mut() {
+            Some(lesson) if lesson.session == record.session as u32 => lesson.chapters.push(number),
+            _ => lessons.push(Lesson {
+                session: record.session as u32,
+                date: record.lesson_date,
+                chapters: vec![number],
+            }),
+        }
+    }
+    for lesson in &mut lessons {
+        lesson.chapters.sort();
+    }
+    Ok(Some(Course { book_id, lessons }))
+}
+
+/// Replace the course of the student on the book with `lessons`, renumbered in order.
+///
+/// Every chapter must be in the book and in one lesson at most, and the dates must not go back.
+pub async fn set_course(
+    database: &SqlitePool,
+    student_id: i64,
+    book: &Book,
+    mut lessons: Vec<Lesson>,
+) -> anyhow::Result<Course> {
+    let mut seen = HashSet::new();
+    for (i, lesson) in lessons.iter_mut().enumerate() {
+        if lesson.chapters.is_empty() {
+            anyhow::bail!("Lesson on {} has no chapters", lesson.date);
+        }
+        for number in &lesson.chapters {
+            if !book.chapters.contains_key(number) {
+                anyhow::bail!("Chapter not found: {}", number);
+            }
+            if !seen.insert(number.clone()) {
+                anyhow::bail!("Chapter {} is in several lessons", number);
+            }
+        }
+        lesson.session = i as u32 + 1;
+    }
+    if lessons.windows(2).any(|w| w[1].date < w[0].date) {
+        anyhow::bail!("Lessons must be in date order");
+    }
+    let mut tx = database.begin().await?;
+    sqlx::query!(
+        "delete from course_lesson where student_id = ? and book_id = ?",
+        student_id,
+        book.id
+    )
+    .execute(&mut *tx)
+    .await?;
+    for lesson in &lessons {
+        let session = lesson.session as i64;
+        for number in &lesson.chapters {
+            let chapter_number = number.to_string();
+            sqlx::query!(
+                "insert into course_lesson (student_id, book_id, session, lesson_date, chapter_number)
+                values (?, ?, ?, ?, ?)",
+                student_id,
+                book.id,
+                session,
+                lesson.date,
+                chapter_number
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(Course {
+        book_id: book.id,
+        lessons,
+    })
+}
+
+pub async fn delete_course(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "delete from course_lesson where student_id = ? and book_id = ?",
+        student_id,
+        book_id
+    )
+    .execute(database)
+    .await?;
+    Ok(())
+}
+
+fn join(chapters: &[ChapterNumber]) -> String {
+    chapters
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// the pacing of the student for the system prompt of the teacher
+pub fn pacing_prompt(locale: &str, today: &TodaysLesson) -> String {
+    let status = match today.status {
+        PacingStatus::OnTrack => "on_track",
+        PacingStatus::Behind => "behind",
+        PacingStatus::Ahead => "ahead",
+        PacingStatus::Finished => "finished",
+    };
+    let mut prompt = i18n::tr(
+        locale,
+        "teacher-course-pacing",
+        &[
+            ("status", status.into()),
+            ("overdue", join(&today.overdue).into()),
+        ],
+    );
+    if let Some(lesson) = today
+        .lesson
+        .as_ref()
+        .filter(|_| today.status != PacingStatus::Finished)
+    {
+        let key = if lesson.date == today.today {
+            "teacher-course-today"
+        } else {
+            "teacher-course-next"
+        };
+        prompt.push('\n');
+        prompt.push_str(&i18n::tr(
+            locale,
+            key,
+            &[
+                ("session", lesson.session.into()),
+                ("date", lesson.date.to_string().into()),
+                ("chapters", join(&lesson.chapters).into()),
+            ],
+        ));
+    }
+    prompt
+}
+
+#[test]
+fn pacing() {
+    let day = |day: u8| Date::from_calendar_date(2026, time::Month::March, day).unwrap();
+    let number = |s: &str| s.parse::<ChapterNumber>().unwrap();
+    assert_eq!(session_date(day(2), 1, 3), day(4));
+    assert_eq!(session_date(day(2), 3, 3), day(9));
+    let course = Course {
+        book_id: 1,
+        lessons: vec![
+            Lesson {
+                session: 1,
+                date: day(2),
+                chapters: vec![number("1."), number("1.1.")],
+            },
+            Lesson {
+                session: 2,
+                date: day(4),
+                chapters: vec![number("2.")],
+            },
+            Lesson {
+                session: 3,
+                date: day(6),
+                chapters: vec![number("3.")],
+            },
+        ],
+    };
+    let mut completed: HashSet<ChapterNumber> = [number("1.")].into();
+    let today = course.todays_lesson(day(4), &completed);
+    assert_eq!(today.status, PacingStatus::Behind);
+    assert_eq!(today.overdue, [number("1.1.")]);
+    assert_eq!(today.lesson.unwrap().session, 2);
+    assert_eq!(today.remaining_sessions, 2);
+    completed.insert(number("1.1."));
+    completed.insert(number("3."));
+    assert_eq!(
+        course.todays_lesson(day(3), &completed).status,
+        PacingStatus::Ahead
+    );
+    completed.insert(number("2."));
+    let today = course.todays_lesson(day(9), &completed);
+    assert_eq!(today.status, PacingStatus::Finished);
+    assert!(today.lesson.is_none());
+}
@@ -0,0 +1,619 @@
+use anyhow::bail;
+use futures::TryStreamExt;
+use serde::Serialize;
+use sqlx::{
+    PgPool, Postgres, QueryBuilder, Row, SqlitePool, ValueRef, postgres::PgPoolOptions,
+    query_builder::Separated, sqlite::SqliteRow,
+};
+use tracing::{info, warn};
+
+/// rows inserted per statement
+const BATCH_SIZE: usize = 500;
+/// migration history of the SQLite schema, meaningless for Postgres
+const MIGRATIONS_TABLE: &str = "_sqlx_migrations";
+
+/// How a SQLite column is stored in Postgres, from the declared type like SQLite's type affinity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnKind {
+    Integer,
+    Boolean,
+    Real,
+    Text,
+    Timestamp,
+    Blob,
+}
+
+impl ColumnKind {
+    fn from_declared(declared: &str) -> Self {
+        let declared = declared.to_uppercase();
+        if declared.contains("BOOL") {
+            Self::Boolean
+        } else if declared.contains("INT") {
+            Self::Integer
+        } else if declared.contains("DATE") || declared.contains("TIME") {
+            Self::Timestamp
+        } else if declared.contains("REAL")
+            || declared.contains("FLOA")
+            || declared.contains("DOUB")
+        {
+            Self::Real
+        } else if declared.contains("BLOB") || declared.is_empty() {
+            Self::Blob
+        } else {
+            Self::Text
+        }
+    }
+
+    fn postgres_type(self) -> &'static str {
+        match self {
+            Self::Integer => "BIGINT",
+            Self::Boolean => "BOOLEAN",
+            Self::Real => "DOUBLE PRECISION",
+            Self::Text => "TEXT",
+            Self::Timestamp => "TIMESTAMPTZ",
+            Self::Blob => "BYTEA",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Column {
+    name: String,
+    kind: ColumnKind,
+    not_null: bool,
+    default: Option<String>,
+    /// position in the primary key, 0 if not part of it
+    primary_key: i64,
+}
+
+#[derive(Debug, Clone)]
+struct Index {
+    name: String,
+    unique: bool,
+    columns: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct ForeignKey {
+    columns: Vec<String>,
+    table: String,
+    /// empty when referencing the primary key
+    references: Vec<String>,
+    on_delete: String,
+    on_update: String,
+}
+
+#[derive(Debug, Clone)]
+struct Table {
+    name: String,
+    columns: Vec<Column>,
+    indexes: Vec<Index>,
+    foreign_keys: Vec<ForeignKey>,
+}
+
+impl Table {
+    /// the `INTEGER PRIMARY KEY` column, an alias of the rowid that becomes an identity column
+    fn identity(&self) -> Option<&Column> {
+        let mut keys = self.columns.iter().filter(|c| c.primary_key > 0);
+        match (keys.next(), keys.next()) {
+            (Some(column), None) if column.kind == ColumnKind::Integer => Some(column),
+            _ => None,
+        }
+    }
+
+    fn create_sql(&self) -> String {
+        let identity = self.identity().map(|c| c.name.clone());
+        let mut definitions: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| {
+                let mut definition =
+                    format!("{} {}", quote(&column.name), column.kind.postgres_type());
+                if identity.as_deref() == Some(column.name.as_str()) {
+                    definition.push_str(" GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY");
+                    return definition;
+                }
+                if column.not_null {
+                    definition.push_str(" NOT NULL");
+                }
+                if let Some(default) = &column.default {
+                    definition.push_str(" DEFAULT ");
+                    definition.push_str(default);
+                }
+                definition
+            })
+            .collect();
+        if identity.is_none() {
+            let mut keys: Vec<&Column> =
+                self.columns.iter().filter(|c| c.primary_key > 0).collect();
+            keys.sort_by_key(|c| c.primary_key);
+            if !keys.is_empty() {
+                let keys: Vec<String> = keys.iter().map(|c| quote(&c.name)).collect();
+                definitions.push(format!("PRIMARY KEY ({})", keys.join(", ")));
+            }
+        }
+        format!(
+            "CREATE TABLE {} (\n    {}\n)",
+            quote(&self.name),
+            definitions.join(",\n    ")
+        )
+    }
+
+    fn index_sql(&self) -> Vec<String> {
+        self.indexes
+            .iter()
+            .map(|index| {
+                let columns: Vec<String> = index.columns.iter().map(|c| quote(c)).collect();
+                format!(
+                    "CREATE {}INDEX {} ON {} ({})",
+                    if index.unique { "UNIQUE " } else { "" },
+                    quote(&index.name),
+                    quote(&self.name),
+                    columns.join(", ")
+                )
+            })
+            .collect()
+    }
+
+    fn foreign_key_sql(&self) -> Vec<String> {
+        self.foreign_keys
+            .iter()
+            .map(|key| {
+                let columns: Vec<String> = key.columns.iter().map(|c| quote(c)).collect();
+                let references = if key.references.is_empty() {
+                    String::new()
+                } else {
+                    let columns: Vec<String> = key.references.iter().map(|c| quote(c)).collect();
+                    format!(" ({})", columns.join(", "))
+                };
+                format!(
+                    "ALTER TABLE {} ADD FOREIGN KEY ({}) REFERENCES {}{} ON DELETE {} ON UPDATE {}",
+                    quote(&self.name),
+                    columns.join(", "),
+                    quote(&key.table),
+                    references,
+                    key.on_delete,
+                    key.on_update
+                )
+            })
+            .collect()
+    }
+}
+
+/// A table copied into Postgres
+#[derive(Debug, Clone, Serialize)]
+pub struct CopiedTable {
+    pub name: String,
+    pub rows: i64,
+}
+
+/// What [`migrate_to_postgres`] copied
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationReport {
+    /// the latest SQLite migration applied to the copied schema
+    pub schema_version: i64,
+    pub tables: Vec<CopiedTable>,
+    /// full-text indexes and their shadow tables, rebuilt from the chapters on the first search
+    pub skipped: Vec<String>,
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// a default value Postgres understands the same way, `None` for SQLite expressions
+fn translate_default(default: &str, kind: ColumnKind) -> Option<String> {
+    if kind == ColumnKind::Boolean {
+        match default {
+            "0" => return Some("FALSE".to_string()),
+            "1" => return Some("TRUE".to_string()),
+            _ => {}
+        }
+    }
+    let is_literal = default.parse::<f64>().is_ok()
+        || (default.starts_with('\'') && default.ends_with('\''))
+        || ["TRUE", "FALSE", "NULL", "CURRENT_TIMESTAMP"]
+            .contains(&default.to_uppercase().as_str());
+    is_literal.then(|| default.to_string())
+}
+
+/// the action of a foreign key, `NO ACTION` for anything unknown
+fn translate_action(action: &str) -> String {
+    let action = action.to_uppercase();
+    match action.as_str() {
+        "CASCADE" | "RESTRICT" | "SET NULL" | "SET DEFAULT" => action,
+        _ => "NO ACTION".to_string(),
+    }
+}
+
+/// the tables to copy and the ones skipped, full-text indexes and the migration history are not
+/// copied
+async fn read_schema(sqlite: &SqlitePool) -> anyhow::Result<(Vec<Table>, Vec<String>)> {
+    let entries: Vec<(String, String)> = sqlx::query_as(
+        "select name, coalesce(sql, '') from sqlite_master \
+        where type = 'table' and name not like 'sqlite_%' order by name",
+    )
+    .fetch_all(sqlite)
+    .await?;
+    let virtual_tables: Vec<String> = entries
+        .iter()
+        .filter(|(_, sql)| sql.to_uppercase().starts_with("CREATE VIRTUAL TABLE"))
+        .map(|(name, _)| name.clone())
+        .collect();
+    let mut tables = Vec::new();
+    let mut skipped = Vec::new();
+    for (name, _) in entries {
+        if name == MIGRATIONS_TABLE {
+            continue;
+        }
+        if virtual_tables
+            .iter()
+            .any(|vt| name == *vt || name.starts_with(&format!("{vt}_")))
+        {
+            skipped.push(name);
+            continue;
+        }
+        tables.push(read_table(sqlite, name).await?);
+    }
+    Ok((tables, skipped))
+}
+
+async fn read_table(sqlite: &SqlitePool, name: String) -> anyhow::Result<Table> {
+    let columns = sqlx::query(&format!("pragma table_info({})", quote(&name)))
+        .fetch_all(sqlite)
+        .await?
+        .into_iter()
+        .map(|row| -> anyhow::Result<Column> {
+            let kind = ColumnKind::from_declared(&row.try_get::<String, _>("type")?);
+            let default: Option<String> = row.try_get("dflt_value")?;
+            Ok(Column {
+                name: row.try_get("name")?,
+                kind,
+                not_null: row.try_get::<i64, _>("notnull")? != 0,
+                default: default.and_then(|default| translate_default(&default, kind)),
+                primary_key: row.try_get("pk")?,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut indexes = Vec::new();
+    let index_list = sqlx::query(&format!("pragma index_list({})", quote(&name)))
+        .fetch_all(sqlite)
+        .await?;
+    for row in index_list {
+        let index: String = row.try_get("name")?;
+        let origin: String = row.try_get("origin")?;
+        // the primary key is part of the table, partial indexes have SQLite conditions
+        if origin == "pk" || row.try_get::<i64, _>("partial")? != 0 {
+            continue;
+        }
+        let columns: Vec<Option<String>> =
+            sqlx::query(&format!("pragma index_info({})", quote(&index)))
+                .fetch_all(sqlite)
+                .await?
+                .into_iter()
+                .map(|row| row.try_get("name"))
+                .collect::<Result<_, _>>()?;
+        // expression indexes have no column names
+        let Some(columns) = columns.into_iter().collect::<Option<Vec<String>>>() else {
+            warn!("index {} of {} is not copied", index, name);
+            continue;
+        };
+        let index = if index.starts_with("sqlite_autoindex_") {
+            format!("{}_{}_key", name, columns.join("_"))
+        } else {
+            index
+        };
+        indexes.push(Index {
+            name: index,
+            unique: row.try_get::<i64, _>("unique")? != 0,
+            columns,
+        });
+    }
+
+    let mut foreign_keys: Vec<(i64, ForeignKey)> = Vec::new();
+    let key_list = sqlx::query(&format!("pragma foreign_key_list({})", quote(&name)))
+        .fetch_all(sqlite)
+        .await?;
+    for row in key_list {
+        let id: i64 = row.try_get("id")?;
+        let column: String = row.try_get("from")?;
+        let reference: Option<String> = row.try_get("to")?;
+        match foreign_keys.iter_mut().find(|(key_id, _)| *key_id == id) {
+            Some((_, key)) => {
+                key.columns.push(column);
+                key.references.extend(reference);
+            }
+            None => foreign_keys.push((
+                id,
+                ForeignKey {
+                    columns: vec![column],
+                    table: row.try_get("table")?,
+                    references: reference.into_iter().collect(),
+                    on_delete: translate_action(&row.try_get::<String, _>("on_delete")?),
+                    on_update: translate_action(&row.try_get::<String, _>("on_update")?),
+                },
+            )),
+        }
+    }
+
+    Ok(Table {
+        name,
+        columns,
+        indexes,
+        foreign_keys: foreign_keys.into_iter().map(|(_, key)| key).collect(),
+    })
+}
+
+/// bind the value of the column in the row as the Postgres type of the column
+fn push_value(
+    values: &mut Separated<'_, '_, Postgres, &'static str>,
+    row: &SqliteRow,
+    index: usize,
+    kind: ColumnKind,
+) -> anyhow::Result<()> {
+    let is_null = row.try_get_raw(index)?.is_null();
+    // SQLite is dynamically typed, decoding unchecked converts values stored with another type
+    match kind {
+        ColumnKind::Integer => {
+            values.push_bind(
+                (!is_null)
+                    .then(|| row.try_get_unchecked::<i64, _>(index))
+                    .transpose()?,
+            );
+        }
+        ColumnKind::Boolean => {
+            values.push_bind(
+                (!is_null)
+                    .then(|| row.try_get_unchecked::<i64, _>(index).map(|v| v != 0))
+                    .transpose()?,
+            );
+        }
+        ColumnKind::Real => {
+            values.push_bind(
+                (!is_null)
+                    .then(|| row.try_get_unchecked::<f64, _>(index))
+                    .transpose()?,
+            );
+        }
+        ColumnKind::Text => {
+            values.push_bind(
+                (!is_null)
+                    .then(|| row.try_get_unchecked::<String, _>(index))
+                    .transpose()?,
+            );
+        }
+        ColumnKind::Timestamp => {
+            values.push_bind(
+                (!is_null)
+                    .then(|| row.try_get_unchecked::<String, _>(index))
+                    .transpose()?,
+            );
+            values.push_unseparated("::timestamptz");
+        }
+        ColumnKind::Blob => {
+            values.push_bind(
+                (!is_null)
+                    .then(|| row.try_get_unchecked::<Vec<u8>, _>(index))
+                    .transpose()?,
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn insert_rows(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    table: &Table,
+    rows: &[SqliteRow],
+) -> anyhow::Result<()> {
+    let columns: Vec<String> = table.columns.iter().map(|c| quote(&c.name)).collect();
+    let mut query = QueryBuilder::<Postgres>::new(format!(
+        "INSERT INTO {} ({}) ",
+        quote(&table.name),
+        columns.join(", ")
+    ));
+    let mut error = None;
+    query.push_values(rows, |mut values, row| {
+        for (index, column) in table.columns.iter().enumerate() {
+            if let Err(e) = push_value(&mut values, row, index, column.kind) {
+                error.get_or_insert(e);
+            }
+        }
+    });
+    if let Some(e) = error {
+        return Err(e.context(format!("Failed to read a row of {}", table.name)));
+    }
+    query.build().execute(&mut **tx).await?;
+    Ok(())
+}
+
+/// Copy every table of the SQLite database into the empty Postgres database at `postgres_url`.
+///
+/// The source must pass SQLite's integrity and foreign key checks. The schema is translated
+/// from the SQLite declared types, `INTEGER PRIMARY KEY` columns become identity columns whose
+/// sequences continue after the copied ids, and indexes and foreign keys are created after the
+/// data, so Postgres checks every row. The row counts are compared before committing, a failed
+/// migration leaves the target empty. Check constraints and partial indexes are not copied.
+pub async fn migrate_to_postgres(
+    sqlite: &SqlitePool,
+    postgres_url: &str,
+) -> anyhow::Result<MigrationReport> {
+    let integrity: Vec<String> = sqlx::query_scalar("pragma integrity_check")
+        .fetch_all(sqlite)
+        .await?;
+    if integrity != ["ok"] {
+        bail!("SQLite integrity check failed: {}", integrity.join("; "));
+    }
+    let violations = sqlx::query("pragma foreign_key_check")
+        .fetch_all(sqlite)
+        .await?;
+    if !violations.is_empty() {
+        bail!(
+            "SQLite foreign key check found {} rows referencing missing rows",
+            violations.len()
+        );
+    }
+    let schema_version: i64 = sqlx::query_scalar(&format!(
+        "select coalesce(max(version), 0) from {MIGRATIONS_TABLE}"
+    ))
+    .fetch_one(sqlite)
+    .await?;
+    let (tables, skipped) = read_schema(sqlite).await?;
+
+    let postgres: PgPool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect(postgres_url)
+        .await?;
+    for table in &tables {
+        let exists: Option<String> = sqlx::query_scalar("select to_regclass($1)::text")
+            .bind(quote(&table.name))
+            .fetch_one(&postgres)
+            .await?;
+        if exists.is_some() {
+            bail!("Table {} already exists in Postgres", table.name);
+        }
+    }
+
+    let mut tx = postgres.begin().await?;
+    // SQLite timestamps without an offset are UTC
+    sqlx::query("SET LOCAL TIME ZONE 'UTC'")
+        .execute(&mut *tx)
+        .await?;
+    let mut copied = Vec::with_capacity(tables.len());
+    for table in &tables {
+        sqlx::query(&table.create_sql()).execute(&mut *tx).await?;
+        let mut rows = sqlx::query(&format!("select * from {}", quote(&table.name))).fetch(sqlite);
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        let mut count = 0;
+        while let Some(row) = rows.try_next().await? {
+            batch.push(row);
+            if batch.len() == BATCH_SIZE {
+                insert_rows(&mut tx, table, &batch).await?;
+                count += batch.len() as i64;
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            insert_rows(&mut tx, table, &batch).await?;
+            count += batch.len() as i64;
+        }
+        info!("copied {} rows of {}", count, table.name);
+        copied.push(CopiedTable {
+            name: table.name.clone(),
+            rows: count,
+        });
+    }
+
+    for table in &tables {
+        for sql in table.index_sql().into_iter().chain(table.foreign_key_sql()) {
+            sqlx::query(&sql).execute(&mut *tx).await?;
+        }
+        if let Some(identity) = table.identity() {
+            sqlx::query(&format!(
+                "SELECT setval(pg_get_serial_sequence($1, $2), coalesce(max({column}), 0) + 1, false) FROM {table}",
+                column = quote(&identity.name),
+                table = quote(&table.name)
+            ))
+            .bind(quote(&table.name))
+            .bind(&identity.name)
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    for table in &copied {
+        let source: i64 =
+            sqlx::query_scalar(&format!("select count(*) from {}", quote(&table.name)))
+                .fetch_one(sqlite)
+                .await?;
+        let target: i64 =
+            sqlx::query_scalar(&format!("SELECT count(*) FROM {}", quote(&table.name)))
+                .fetch_one(&mut *tx)
+                .await?;
+        if source != table.rows || target != table.rows {
+            bail!(
+                "Row count of {} differs: {} in SQLite, {} copied, {} in Postgres",
+                table.name,
+                source,
+                table.rows,
+                target
+            );
+        }
+    }
+    tx.commit().await?;
+    if !skipped.is_empty() {
+        warn!("not copied: {}", skipped.join(", "));
+    }
+    Ok(MigrationReport {
+        schema_version,
+        tables: copied,
+        skipped,
+    })
+}
+
+#[test]
+fn translate_table() {
+    let table = Table {
+        name: "quiz_submission".to_string(),
+        columns: vec![
+            Column {
+                name: "id".to_string(),
+                kind: ColumnKind::from_declared("INTEGER"),
+                not_null: false,
+                default: None,
+                primary_key: 1,
+            },
+            Column {
+                name: "quiz_id".to_string(),
+                kind: ColumnKind::from_declared("INTEGER"),
+                not_null: true,
+                default: None,
+                primary_key: 0,
+            },
+            Column {
+                name: "passed".to_string(),
+                kind: ColumnKind::from_declared("BOOLEAN"),
+                not_null: true,
+                default: translate_default("0", ColumnKind::Boolean),
+                primary_key: 0,
+            },
+            Column {
+                name: "create_time".to_string(),
+                kind: ColumnKind::from_declared("DATETIME"),
+                not_null: true,
+                default: translate_default("CURRENT_TIMESTAMP", ColumnKind::Timestamp),
+                primary_key: 0,
+            },
+        ],
+        indexes: vec![],
+        foreign_keys: vec![ForeignKey {
+            columns: vec!["quiz_id".to_string()],
+            table: "quiz".to_string(),
+            references: vec!["id".to_string()],
+            on_delete: translate_action("CASCADE"),
+            on_update: translate_action("NO ACTION"),
+        }],
+    };
+    assert_eq!(
+        table.create_sql(),
+        "CREATE TABLE \"quiz_submission\" (\n    \
+        \"id\" BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,\n    \
+        \"quiz_id\" BIGINT NOT NULL,\n    \
+        \"passed\" BOOLEAN NOT NULL DEFAULT FALSE,\n    \
+        \"create_time\" TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP\n)"
+    );
+    assert_eq!(
+        table.foreign_key_sql(),
+        [
+            "ALTER TABLE \"quiz_submission\" ADD FOREIGN KEY (\"quiz_id\") REFERENCES \"quiz\" (\"id\") ON DELETE CASCADE ON UPDATE NO ACTION"
+        ]
+    );
+    assert_eq!(ColumnKind::from_declared("REAL"), ColumnKind::Real);
+    assert_eq!(ColumnKind::from_declared("BLOB"), ColumnKind::Blob);
+    assert_eq!(ColumnKind::from_declared("TEXT"), ColumnKind::Text);
+    assert_eq!(
+        translate_default("lower(hex(randomblob(16)))", ColumnKind::Text),
+        None
+    );
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+use crate::pagination::{PageQuery, Paginated, SortOrder};
+
+/// A job item that failed every attempt
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeadLetter {
+    pub id: i64,
+    /// the job kind, e.g. "regenerate_chapter_plans"
+    pub kind: String,
+    pub job_id: i64,
+    pub item: String,
+    /// the item as the job received it, run again by a requeue
+    #[schema(value_type = Object)]
+    pub payload: serde_json::Value,
+    /// the error of the last attempt with its cause chain
+    pub error: String,
+    pub attempts: i64,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String)]
+    pub create_time: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    #[schema(value_type = Option<String>)]
+    pub requeue_time: Option<OffsetDateTime>,
+    /// the job the item was requeued into
+    pub requeue_job_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DeadLetterFilter {
+    pub kind: Option<String>,
+    /// also list the letters already requeued
+    pub include_requeued: Option<bool>,
+}
+
+/// persist a permanently failed job item
+pub async fn record(
+    database: &SqlitePool,
+    kind: &str,
+    job_id: u64,
+    item: &str,
+    payload: &serde_json::Value,
+    error: &str,
+    attempts: u32,
+) -> anyhow::Result<i64> {
+    let job_id = job_id as i64;
+    let payload = payload.to_string();
+    let now = OffsetDateTime::now_utc();
+    let result = sqlx::query!(
+        "insert into dead_letter (kind, job_id, item, payload, error, attempts, create_time) values (?, ?, ?, ?, ?, ?, ?)",
+        kind,
+        job_id,
+        item,
+        payload,
+        error,
+        attempts,
+        now
+    )
+    .execute(database)
+    .await?;
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn get(database: &SqlitePool, id: i64) -> anyhow::Result<DeadLetter> {
+    let record = sqlx::query!(
+        r#"select id, kind, job_id, item, payload, error, attempts,
+        create_time as "create_time: OffsetDateTime", requeue_time as "requeue_time: OffsetDateTime", requeue_job_id
+        from dead_letter where id = ?"#,
+        id
+    )
+    .fetch_optional(database)
+    .await?
+    .ok_or(anyhow::anyhow!("Dead letter not found: {}", id))?;
+    Ok(DeadLetter {
+        id: record.id,
+        kind: record.kind,
+        job_id: record.job_id,
+        item: record.item,
+        payload: serde_json::from_str(&record.payload)?,
+        error: record.error,
+        attempts: record.attempts,
+        create_time: record.create_time,
+        requeue_time: record.requeue_time,
+        requeue_job_id: record.requeue_job_id,
+    })
+}
+
+/// the dead letters paged by id, newest first by default, requeued ones only if asked for
+pub async fn list(
+    database: &SqlitePool,
+    filter: &DeadLetterFilter,
+    page: &PageQuery,
+) -> anyhow::Result<Paginated<DeadLetter>> {
+    let ascending = page.order(SortOrder::Desc) == SortOrder::Asc;
+    let cursor = page.cursor::<i64>()?;
+    let limit = page.limit() as i64 + 1;
+    let include_requeued = filter.include_requeued.unwrap_or(false);
+    let records = sqlx::query!(
+        r#"select id as "id!", kind, job_id, item, payload, error, attempts,
+        create_time as "create_time: OffsetDateTime", requeue_time as "requeue_time: OffsetDateTime", requeue_job_id
+        from dead_letter
+        where (?1 is null or kind = ?1) and (?2 or requeue_time is null)
+        and (?3 is null or (?4 and id > ?3) or (not ?4 and id < ?3))
+        order by case when ?4 then id else -id end limit ?5"#,
+        filter.kind,
+        include_requeued,
+        cursor,
+        ascending,
+        limit
+    )
+    .fetch_all(database)
+    .await?;
+    let mut letters = Vec::with_capacity(records.len());
+    for record in records {
+        letters.push(DeadLetter {
+            id: record.id,
+            kind: record.kind,
+            job_id: record.job_id,
+            item: record.item,
+            payload: serde_json::from_str(&record.payload)?,
+            error: record.error,
+            attempts: record.attempts,
+            create_time: record.create_time,
+            requeue_time: record.requeue_time,
+            requeue_job_id: record.requeue_job_id,
+        });
+    }
+    Ok(Paginated::from_overfetched(
+        letters,
+        page.limit(),
+        |letter| letter.id,
+    ))
+}
+
+pub async fn mark_requeued(database: &SqlitePool, id: i64, job_id: u64) -> anyhow::Result<()> {
+    let job_id = job_id as i64;
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        "update dead_letter set requeue_time = ?, requeue_job_id = ? where id = ?",
+        now,
+        job_id,
+        id
+    )
+    .execute(database)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,183 @@
+pub mod store;
+
+use std::sync::LazyLock;
+
+use async_openai::types::CreateEmbeddingRequestArgs;
+use serde::Serialize;
+use store::{EmbeddedChunk, VectorStore};
+use tracing::info;
+use utoipa::ToSchema;
+
+use crate::{
+    ai_utils::{DEFAULT_PROVIDER, Provider},
+    books::book::Book,
+};
+
+pub static EMBEDDING_MODEL: LazyLock<String> = LazyLock::new(|| {
+    dotenvy::var("EMBEDDING_MODEL").unwrap_or_else(|_| "text-embedding-3-small".to_string())
+});
+
+/// characters per chunk, paragraphs are kept whole when they fit
+pub const CHUNK_CHARS: usize = 2000;
+
+/// chunks per embeddings request
+const BATCH_SIZE: usize = 64;
+
+/// A chunk of chapter content close in meaning to a query
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SemanticHit {
+    pub chapter_number: String,
+    pub content: String,
+    /// cosine similarity to the query, from -1 to 1
+    pub score: f32,
+}
+
+/// split `content` into chunks of at most [`CHUNK_CHARS`] characters at paragraph breaks
+pub fn chunk_content(content: &str) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut current = String::new();
+    let mut current_chars = 0;
+    for paragraph in content.split("\n\n").map(str::trim) {
+        // page markers and blank lines say nothing on their own
+        if paragraph.is_empty() || (paragraph.starts_with("<!--") && paragraph.ends_with("-->")) {
+            continue;
+        }
+        let chars: Vec<char> = paragraph.chars().collect();
+        for piece in chars.chunks(CHUNK_CHARS) {
+            if !current.is_empty() && current_chars + 2 + piece.len() > CHUNK_CHARS {
+                chunks.push(std::mem::take(&mut current));
+                current_chars = 0;
+            }
+            if !current.is_empty() {
+                current.push_str("\n\n");
+                current_chars += 2;
+            }
+            current.extend(piece);
+            current_chars += piece.len();
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+fn encode(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn decode(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+/// embeddings of `inputs`, in order, always from the default provider so the stored vectors
+/// of every book stay comparable
+pub async fn embed(inputs: Vec<String>) -> anyhow::Result<Vec<Vec<f32>>> {
+    embed_with(DEFAULT_PROVIDER.as_ref(), inputs).await
+}
+
+/// [`embed`] through `provider`, the default provider or a metered one wrapping it
+async fn embed_with(provider: &dyn Provider, inputs: Vec<String>) -> anyhow::Result<Vec<Vec<f32>>> {
+    let mut vectors = Vec::with_capacity(inputs.len());
+    for batch in inputs.chunks(BATCH_SIZE) {
+        let request = CreateEmbeddingRequestArgs::default()
+            .model(EMBEDDING_MODEL.as_str())
+            .input(batch.to_vec())
+            .build()?;
+        let mut response = provider.embeddings(request).await?;
+        response.data.sort_by_key(|embedding| embedding.index);
+        if response.data.len() != batch.len() {
+            anyhow::bail!(
+                "Expected {} embeddings, got {}",
+                batch.len(),
+                response.data.len()
+            );
+        }
+        vectors.extend(response.data.into_iter().map(|e| e.embedding));
+    }
+    Ok(vectors)
+}
+
+/// whether the book has chunks embedded with the current model
+pub async fn is_indexed(store: &dyn VectorStore, book_id: i64) -> anyhow::Result<bool> {
+    store.is_indexed(book_id, EMBEDDING_MODEL.as_str()).await
+}
+
+/// drop the chunks of the book, they are recomputed on its next search
+pub async fn remove_book(store: &dyn VectorStore, book_id: i64) -> anyhow::Result<()> {
+    store.remove_book(book_id).await
+}
+
+/// chunk and embed every chapter of the book, replacing its previous chunks
+pub async fn index_book(store: &dyn VectorStore, book: &Book) -> anyhow::Result<()> {
+    let chunks: Vec<(String, i64, String)> = book
+        .chapters
+        .iter()
+        .flat_map(|(number, chapter)| {
+            chunk_content(&chapter.content)
+                .into_iter()
+                .enumerate()
+                .map(move |(i, chunk)| (number.to_string(), i as i64, chunk))
+        })
+        .collect();
+    info!("embedding {} chunks of book {}", chunks.len(), book.id);
+    let vectors = embed(chunks.iter().map(|(_, _, chunk)| chunk.clone()).collect()).await?;
+    let chunks = chunks
+        .into_iter()
+        .zip(vectors)
+        .map(
+            |((chapter_number, chunk_index, content), vector)| EmbeddedChunk {
+                chapter_number,
+                chunk_index,
+                content,
+                vector,
+            },
+        )
+        .collect();
+    store
+        .replace_book(book.id, EMBEDDING_MODEL.as_str(), chunks)
+        .await
+}
+
+/// the chunks of the book closest in meaning to `query`, most similar first; the query is
+/// embedded through `provider`, see [`embed_with`]
+pub async fn search(
+    store: &dyn VectorStore,
+    provider: &dyn Provider,
+    book_id: i64,
+    query: &str,
+    limit: usize,
+) -> anyhow::Result<Vec<SemanticHit>> {
+    let Some(query_vector) = embed_with(provider, vec![query.to_string()]).await?.pop() else {
+        return Ok(vec![]);
+    };
+    store
+        .search(book_id, EMBEDDING_MODEL.as_str(), query_vector, limit)
+        .await
+}
+
+#[test]
+fn chunks_and_vectors() {
+    let paragraph = "word ".repeat(300);
+    let content = format!("<!-- page 1 -->\n\n{paragraph}\n\n{paragraph}\n\nshort");
+    let chunks = chunk_content(&content);
+    assert_eq!(chunks.len(), 2);
+    assert!(chunks.iter().all(|c| c.chars().count() <= CHUNK_CHARS));
+    assert!(chunks[1].ends_with("short"));
+    assert_eq!(chunk_content(&"x".repeat(CHUNK_CHARS * 2 + 1)).len(), 3);
+
+    let vector = [0.5, -1.0, 2.0];
+    assert_eq!(decode(&encode(&vector)), vector);
+    assert!((cosine(&vector, &vector) - 1.0).abs() < 1e-6);
+    assert_eq!(cosine(&vector, &[0.0; 3]), 0.0);
+}
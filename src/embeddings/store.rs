@@ -0,0 +1,590 @@
+use std::{fmt::Debug, path::PathBuf, str::FromStr, time::Duration};
+
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{PgPool, QueryBuilder, Row, SqlitePool};
+
+use super::{SemanticHit, cosine, decode, encode};
+
+/// A chunk of chapter content with its embedding
+#[derive(Debug, Clone)]
+pub struct EmbeddedChunk {
+    pub chapter_number: String,
+    pub chunk_index: i64,
+    pub content: String,
+    pub vector: Vec<f32>,
+}
+
+/// Storage and similarity search of the embedded chunks of each book, the vectors of a book
+/// are only compared with query vectors of the same model
+pub trait VectorStore: Send + Sync + Debug {
+    /// whether the book has chunks embedded with `model`
+    fn is_indexed<'a>(
+        &'a self,
+        book_id: i64,
+        model: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<bool>>;
+    /// replace every chunk of the book with `chunks`
+    fn replace_book<'a>(
+        &'a self,
+        book_id: i64,
+        model: &'a str,
+        chunks: Vec<EmbeddedChunk>,
+    ) -> BoxFuture<'a, anyhow::Result<()>>;
+    fn remove_book(&self, book_id: i64) -> BoxFuture<'_, anyhow::Result<()>>;
+    /// the `limit` chunks of the book closest to `vector`, most similar first
+    fn search<'a>(
+        &'a self,
+        book_id: i64,
+        model: &'a str,
+        vector: Vec<f32>,
+        limit: usize,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<SemanticHit>>>;
+}
+
+/// Which [`VectorStore`] the library uses, parsed from the `--vector-store` option:
+/// `sqlite`, `sqlite-vec=<extension path>`, a Qdrant `http(s)://` url or a `postgres://` url
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum VectorStoreConfig {
+    /// the `chapter_embedding` table, compared in Rust
+    #[default]
+    Sqlite,
+    /// the `chapter_embedding` table, compared in SQL by the sqlite-vec extension at this path
+    SqliteVec(PathBuf),
+    /// a Qdrant server, the api key comes from the QDRANT_API_KEY env var
+    Qdrant(String),
+    /// a Postgres database with the pgvector extension
+    Pgvector(String),
+}
+
+impl FromStr for VectorStoreConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s == "sqlite" {
+            Ok(Self::Sqlite)
+        } else if let Some(path) = s.strip_prefix("sqlite-vec=") {
+            Ok(Self::SqliteVec(PathBuf::from(path)))
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(Self::Qdrant(s.trim_end_matches('/').to_string()))
+        } else if s.starts_with("postgres://") || s.starts_with("postgresql://") {
+            Ok(Self::Pgvector(s.to_string()))
+        } else {
+            anyhow::bail!(
+                "Unknown vector store {s}, use sqlite, sqlite-vec=<path>, a qdrant http url or a postgres url"
+            )
+        }
+    }
+}
+
+/// The default backend, the `chapter_embedding` table of the main database
+#[derive(Debug, Clone)]
+pub struct SqliteVectorStore {
+    database: SqlitePool,
+    /// rank in SQL with `vec_distance_cosine`, the pool must have loaded sqlite-vec
+    sqlite_vec: bool,
+}
+
+impl SqliteVectorStore {
+    pub fn new(database: SqlitePool) -> Self {
+        Self {
+            database,
+            sqlite_vec: false,
+        }
+    }
+
+    /// rank with the sqlite-vec extension, loaded into every connection of `database`
+    pub fn with_sqlite_vec(database: SqlitePool) -> Self {
+        Self {
+            database,
+            sqlite_vec: true,
+        }
+    }
+}
+
+impl VectorStore for SqliteVectorStore {
+    fn is_indexed<'a>(
+        &'a self,
+        book_id: i64,
+        model: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        Box::pin(async move {
+            let indexed = sqlx::query_scalar!(
+                r#"select exists(select 1 from chapter_embedding where book_id = ? and model = ?) as "indexed!: bool""#,
+                book_id,
+                model
+            )
+            .fetch_one(&self.database)
+            .await?;
+            Ok(indexed)
+        })
+    }
+
+    fn replace_book<'a>(
+        &'a self,
+        book_id: i64,
+        model: &'a str,
+        chunks: Vec<EmbeddedChunk>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let mut tx = self.database.begin().await?;
+            sqlx::query!("delete from chapter_embedding where book_id = ?", book_id)
+                .execute(&mut *tx)
+                .await?;
+            for chunk in chunks {
+                let embedding = encode(&chunk.vector);
+                sqlx::query!(
+                    "insert into chapter_embedding (book_id, chapter_number, chunk_index, content, model, embedding) values (?, ?, ?, ?, ?, ?)",
+                    book_id,
+                    chunk.chapter_number,
+                    chunk.chunk_index,
+                    chunk.content,
+                    model,
+                    embedding
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            tx.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn remove_book(&self, book_id: i64) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            sqlx::query!("delete from chapter_embedding where book_id = ?", book_id)
+                .execute(&self.database)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn search<'a>(
+        &'a self,
+        book_id: i64,
+        model: &'a str,
+        vector: Vec<f32>,
+        limit: usize,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<SemanticHit>>> {
+        Box::pin(async move {
+            if self.sqlite_vec {
+                // the embedding blobs are already the float32 vectors sqlite-vec reads
+                let rows = sqlx::query(
+                    "select chapter_number, content, 1.0 - vec_distance_cosine(embedding, ?) as score
+                    from chapter_embedding where book_id = ? and model = ?
+                    order by score desc limit ?",
+                )
+                .bind(encode(&vector))
+                .bind(book_id)
+                .bind(model)
+                .bind(limit as i64)
+                .fetch_all(&self.database)
+                .await?;
+                return rows
+                    .into_iter()
+                    .map(|row| {
+                        Ok(SemanticHit {
+                            chapter_number: row.try_get("chapter_number")?,
+                            content: row.try_get("content")?,
+                            score: row.try_get::<f64, _>("score")? as f32,
+                        })
+                    })
+                    .collect();
+            }
+            let rows = sqlx::query!(
+                "select chapter_number, content, embedding from chapter_embedding where book_id = ? and model = ?",
+                book_id,
+                model
+            )
+            .fetch_all(&self.database)
+            .await?;
+            let mut hits: Vec<SemanticHit> = rows
+                .into_iter()
+                .map(|row| SemanticHit {
+                    score: cosine(&vector, &decode(&row.embedding)),
+                    chapter_number: row.chapter_number,
+                    content: row.content,
+                })
+                .collect();
+            hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+            hits.truncate(limit);
+            Ok(hits)
+        })
+    }
+}
+
+/// the collection of the Qdrant backend, one for every book
+const QDRANT_COLLECTION: &str = "book_chunks";
+const QDRANT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Keeps the chunks in a Qdrant collection, created with the size of the first vectors stored,
+/// with the book, model, chapter and content as payload
+#[derive(Debug, Clone)]
+pub struct QdrantVectorStore {
+    url: String,
+    api_key: Option<String>,
+    client: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct QdrantReply<T> {
+    result: T,
+}
+
+#[derive(Deserialize)]
+struct QdrantCount {
+    count: u64,
+}
+
+#[derive(Deserialize)]
+struct QdrantPoint {
+    score: f32,
+    payload: QdrantPayload,
+}
+
+#[derive(Deserialize)]
+struct QdrantPayload {
+    chapter_number: String,
+    content: String,
+}
+
+impl QdrantVectorStore {
+    pub fn new(url: impl Into<String>) -> anyhow::Result<Self> {
+        Ok(Self {
+            url: url.into(),
+            api_key: dotenvy::var("QDRANT_API_KEY").ok(),
+            client: reqwest::Client::builder().timeout(QDRANT_TIMEOUT).build()?,
+        })
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self.client.request(
+            method,
+            format!("{}/collections/{QDRANT_COLLECTION}{path}", self.url),
+        );
+        match &self.api_key {
+            Some(key) => request.header("api-key", key),
+            None => request,
+        }
+    }
+
+    fn book_filter(book_id: i64, model: Option<&str>) -> serde_json::Value {
+        let mut must = vec![json!({"key": "book_id", "match": {"value": book_id}})];
+        if let Some(model) = model {
+            must.push(json!({"key": "model", "match": {"value": model}}));
+        }
+        json!({ "must": must })
+    }
+
+    /// whether the collection exists, Qdrant answers 404 before the first book is indexed
+    async fn has_collection(&self) -> anyhow::Result<bool> {
+        let response = self.request(reqwest::Method::GET, "").send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        response.error_for_status()?;
+        Ok(true)
+    }
+}
+
+impl VectorStore for QdrantVectorStore {
+    fn is_indexed<'a>(
+        &'a self,
+        book_id: i64,
+        model: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        Box::pin(async move {
+            if !self.has_collection().await? {
+                return Ok(false);
+            }
+            let reply: QdrantReply<QdrantCount> = self
+                .request(reqwest::Method::POST, "/points/count")
+                .json(&json!({"filter": Self::book_filter(book_id, Some(model)), "exact": true}))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            Ok(reply.result.count > 0)
+        })
+    }
+
+    fn replace_book<'a>(
+        &'a self,
+        book_id: i64,
+        model: &'a str,
+        chunks: Vec<EmbeddedChunk>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let Some(size) = chunks.first().map(|chunk| chunk.vector.len()) else {
+                return self.remove_book(book_id).await;
+            };
+            if !self.has_collection().await? {
+                self.request(reqwest::Method::PUT, "")
+                    .json(&json!({"vectors": {"size": size, "distance": "Cosine"}}))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            self.remove_book(book_id).await?;
+            for (batch_index, batch) in chunks.chunks(256).enumerate() {
+                let points: Vec<_> = batch
+                    .iter()
+                    .enumerate()
+                    .map(|(i, chunk)| {
+                        // unique per book, the chunks of a book are replaced all at once
+                        let id = ((book_id as u64) << 24) | (batch_index * 256 + i) as u64;
+                        json!({
+                            "id": id,
+                            "vector": chunk.vector,
+                            "payload": {
+                                "book_id": book_id,
+                                "model": model,
+                                "chapter_number": chunk.chapter_number,
+                                "chunk_index": chunk.chunk_index,
+                                "content": chunk.content,
+                            },
+                        })
+                    })
+                    .collect();
+                self.request(reqwest::Method::PUT, "/points?wait=true")
+                    .json(&json!({ "points": points }))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Ok(())
+        })
+    }
+
+    fn remove_book(&self, book_id: i64) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            if !self.has_collection().await? {
+                return Ok(());
+            }
+            self.request(reqwest::Method::POST, "/points/delete?wait=true")
+                .json(&json!({ "filter": Self::book_filter(book_id, None) }))
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+
+    fn search<'a>(
+        &'a self,
+        book_id: i64,
+        model: &'a str,
+        vector: Vec<f32>,
+        limit: usize,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<SemanticHit>>> {
+        Box::pin(async move {
+            let reply: QdrantReply<Vec<QdrantPoint>> = self
+                .request(reqwest::Method::POST, "/points/search")
+                .json(&json!({
+                    "vector": vector,
+                    "limit": limit,
+                    "filter": Self::book_filter(book_id, Some(model)),
+                    "with_payload": true,
+                }))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            Ok(reply
+                .result
+                .into_iter()
+                .map(|point| SemanticHit {
+                    chapter_number: point.payload.chapter_number,
+                    content: point.payload.content,
+                    score: point.score,
+                })
+                .collect())
+        })
+    }
+}
+
+/// Keeps the chunks in the `book_chunk_vector` table of a Postgres database with pgvector,
+/// created with the dimensions of the first vectors stored and an HNSW cosine index
+#[derive(Debug, Clone)]
+pub struct PgVectorStore {
+    database: PgPool,
+}
+
+/// a vector as the text input of pgvector, e.g. `[0.5,-1,2]`
+fn pgvector_literal(vector: &[f32]) -> String {
+    let values: Vec<String> = vector.iter().map(f32::to_string).collect();
+    format!("[{}]", values.join(","))
+}
+
+impl PgVectorStore {
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        let database = PgPool::connect(url).await?;
+        sqlx::query("create extension if not exists vector")
+            .execute(&database)
+            .await?;
+        Ok(Self { database })
+    }
+
+    async fn has_table(&self) -> anyhow::Result<bool> {
+        let exists: bool =
+            sqlx::query_scalar("select to_regclass('book_chunk_vector') is not null")
+                .fetch_one(&self.database)
+                .await?;
+        Ok(exists)
+    }
+
+    /// the table is typed with the dimensions of the embedding model, a model with other
+    /// dimensions needs the table dropped
+    async fn create_table(&self, dimensions: usize) -> anyhow::Result<()> {
+        sqlx::query(&format!(
+            "create table if not exists book_chunk_vector (
+                book_id bigint not null,
+                chapter_number text not null,
+                chunk_index bigint not null,
+                content text not null,
+                model text not null,
+                embedding vector({dimensions}) not null,
+                primary key (book_id, chapter_number, chunk_index)
+            )"
+        ))
+        .execute(&self.database)
+        .await?;
+        sqlx::query(
+            "create index if not exists book_chunk_vector_embedding
+            on book_chunk_vector using hnsw (embedding vector_cosine_ops)",
+        )
+        .execute(&self.database)
+        .await?;
+        Ok(())
+    }
+}
+
+impl VectorStore for PgVectorStore {
+    fn is_indexed<'a>(
+        &'a self,
+        book_id: i64,
+        model: &'a str,
+    ) -> BoxFuture<'a, anyhow::Result<bool>> {
+        Box::pin(async move {
+            if !self.has_table().await? {
+                return Ok(false);
+            }
+            let indexed: bool = sqlx::query_scalar(
+                "select exists(select 1 from book_chunk_vector where book_id = $1 and model = $2)",
+            )
+            .bind(book_id)
+            .bind(model)
+            .fetch_one(&self.database)
+            .await?;
+            Ok(indexed)
+        })
+    }
+
+    fn replace_book<'a>(
+        &'a self,
+        book_id: i64,
+        model: &'a str,
+        chunks: Vec<EmbeddedChunk>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let Some(dimensions) = chunks.first().map(|chunk| chunk.vector.len()) else {
+                return self.remove_book(book_id).await;
+            };
+            self.create_table(dimensions).await?;
+            let mut tx = self.database.begin().await?;
+            sqlx::query("delete from book_chunk_vector where book_id = $1")
+                .bind(book_id)
+                .execute(&mut *tx)
+                .await?;
+            for batch in chunks.chunks(500) {
+                let mut insert = QueryBuilder::new(
+                    "insert into book_chunk_vector (book_id, chapter_number, chunk_index, content, model, embedding) ",
+                );
+                insert.push_values(batch, |mut row, chunk| {
+                    row.push_bind(book_id)
+                        .push_bind(&chunk.chapter_number)
+                        .push_bind(chunk.chunk_index)
+                        .push_bind(&chunk.content)
+                        .push_bind(model)
+                        .push_bind(pgvector_literal(&chunk.vector))
+                        .push_unseparated("::vector");
+                });
+                insert.build().execute(&mut *tx).await?;
+            }
+            tx.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn remove_book(&self, book_id: i64) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            if !self.has_table().await? {
+                return Ok(());
+            }
+            sqlx::query("delete from book_chunk_vector where book_id = $1")
+                .bind(book_id)
+                .execute(&self.database)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn search<'a>(
+        &'a self,
+        book_id: i64,
+        model: &'a str,
+        vector: Vec<f32>,
+        limit: usize,
+    ) -> BoxFuture<'a, anyhow::Result<Vec<SemanticHit>>> {
+        Box::pin(async move {
+            if !self.has_table().await? {
+                return Ok(vec![]);
+            }
+            let rows = sqlx::query(
+                "select chapter_number, content, 1 - (embedding <=> $1::vector) as score
+                from book_chunk_vector where book_id = $2 and model = $3
+                order by embedding <=> $1::vector limit $4",
+            )
+            .bind(pgvector_literal(&vector))
+            .bind(book_id)
+            .bind(model)
+            .bind(limit as i64)
+            .fetch_all(&self.database)
+            .await?;
+            rows.into_iter()
+                .map(|row| {
+                    Ok(SemanticHit {
+                        chapter_number: row.try_get("chapter_number")?,
+                        content: row.try_get("content")?,
+                        score: row.try_get::<f64, _>("score")? as f32,
+                    })
+                })
+                .collect()
+        })
+    }
+}
+
+#[test]
+fn vector_store_config() {
+    assert_eq!(
+        "sqlite".parse::<VectorStoreConfig>().unwrap(),
+        VectorStoreConfig::Sqlite
+    );
+    assert_eq!(
+        "sqlite-vec=/usr/lib/vec0"
+            .parse::<VectorStoreConfig>()
+            .unwrap(),
+        VectorStoreConfig::SqliteVec(PathBuf::from("/usr/lib/vec0"))
+    );
+    assert_eq!(
+        "http://localhost:6333/"
+            .parse::<VectorStoreConfig>()
+            .unwrap(),
+        VectorStoreConfig::Qdrant("http://localhost:6333".to_string())
+    );
+    assert!("redis://localhost".parse::<VectorStoreConfig>().is_err());
+    assert_eq!(pgvector_literal(&[0.5, -1.0, 2.0]), "[0.5,-1,2]");
+}
@@ -0,0 +1,269 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::{Duration, OffsetDateTime, Time};
+use utoipa::ToSchema;
+
+use crate::{
+    ai_utils::{self, Provider},
+    books::{book::Book, chapter::ChapterNumber},
+};
+
+/// A flashcard the student reviews
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, ToSchema)]
+pub struct NewFlashcard {
+    /// The chapter the card is about, e.g. "3.", "4.2."
+    pub chapter_number: ChapterNumber,
+    /// A short question or prompt
+    pub front: String,
+    /// The answer, one or two sentences
+    pub back: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Flashcard {
+    pub id: i64,
+    pub book_id: i64,
+    #[serde(flatten)]
+    pub card: NewFlashcard,
+    #[serde(flatten)]
+    pub review: ReviewState,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String)]
+    pub due_time: OffsetDateTime,
+}
+
+/// longest interval between two reviews, about a hundred years
+pub const MAX_INTERVAL_DAYS: i64 = 36500;
+
+/// SM-2 scheduling state of a card
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct ReviewState {
+    pub ease_factor: f64,
+    pub interval_days: i64,
+    /// successful reviews in a row
+    pub repetitions: i64,
+}
+
+impl Default for ReviewState {
+    fn default() -> Self {
+        Self {
+            ease_factor: 2.5,
+            interval_days: 0,
+            repetitions: 0,
+        }
+    }
+}
+
+impl ReviewState {
+    /// the state after a review graded from 0 (blackout) to 5 (perfect recall),
+    /// a grade below 3 starts the card over
+    pub fn review(self, quality: u8) -> Self {
+        let quality = quality.min(5);
+        let (interval_days, repetitions) = if quality < 3 {
+            (1, 0)
+        } else {
+            let interval = match self.repetitions {
+                0 => 1,
+                1 => 6,
+                _ => (self.interval_days as f64 * self.ease_factor).round() as i64,
+            };
+            (interval.min(MAX_INTERVAL_DAYS), self.repetitions + 1)
+        };
+        let miss = (5 - quality) as f64;
+        let ease_factor = (self.ease_factor + 0.1 - miss * (0.08 + miss * 0.02)).max(1.3);
+        Self {
+            ease_factor,
+            interval_days,
+            repetitions,
+        }
+    }
+}
+
+pub async fn create(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+    card: NewFlashcard,
+) -> anyhow::Result<Flashcard> {
+    let chapter_number = card.chapter_number.to_string();
+    let now = OffsetDateTime::now_utc();
+    let result = sqlx::query!(
+        "insert into flashcard (student_id, book_id, chapter_number, front, back, due_time) values (?, ?, ?, ?, ?, ?)",
+        student_id,
+        book_id,
+        chapter_number,
+        card.front,
+        card.back,
+        now
+    )
+    .execute(database)
+    .await?;
+    Ok(Flashcard {
+        id: result.last_insert_rowid(),
+        book_id,
+        card,
+        review: ReviewState::default(),
+        due_time: now,
+    })
+}
+
+/// generate `count` cards on the key facts of a chapter, due right away
+pub async fn generate(
+    database: &SqlitePool,
+    provider: &dyn Provider,
+    student_id: i64,
+    book: &Book,
+    chapter_number: &ChapterNumber,
+    count: usize,
+) -> anyhow::Result<Vec<Flashcard>> {
+    /// The flashcards on the chapter
+    #[derive(Debug, JsonSchema, Deserialize)]
+    struct Cards {
+        cards: Vec<Card>,
+    }
+    /// A flashcard
+    #[derive(Debug, JsonSchema, Deserialize)]
+    struct Card {
+        /// A short question or prompt
+        front: String,
+        /// The answer, one or two sentences
+        back: String,
+    }
+    let chapter = book
+        .chapters
+        .get(chapter_number)
+        .ok_or(anyhow::anyhow!("Chapter not found: {}", chapter_number))?;
+    let prompt = format!(
+        "Write {count} flashcards on the key facts and concepts of the following chapter, \
+        one fact per card, for spaced-repetition review.\n\n# Chapter {} {}\n{}",
+        chapter.number, chapter.name, chapter.content
+    );
+    let generated: Cards = ai_utils::extract(provider, prompt).await?;
+    let mut cards = Vec::with_capacity(generated.cards.len());
+    for card in generated.cards {
+        let card = NewFlashcard {
+            chapter_number: chapter_number.clone(),
+            front: card.front,
+            back: card.back,
+        };
+        cards.push(create(database, student_id, book.id, card).await?);
+    }
+    Ok(cards)
+}
+
+/// the cards of a student due by the end of today (UTC), of one book or all books, earliest first
+pub async fn due_today(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: Option<i64>,
+) -> anyhow::Result<Vec<Flashcard>> {
+    let end_of_day = OffsetDateTime::now_utc().replace_time(Time::MIDNIGHT) + Duration::days(1);
+    let records = sqlx::query!(
+        r#"select id, book_id, chapter_number, front, back, ease_factor, interval_days, repetitions,
+        due_time as "due_time: OffsetDateTime"
+        from flashcard where student_id = ?1 and (?2 is null or book_id = ?2) and due_time < ?3
+        order by due_time"#,
+        student_id,
+        book_id,
+        end_of_day
+    )
+    .fetch_all(database)
+    .await?;
+    let mut cards = Vec::with_capacity(records.len());
+    for record in records {
+        cards.push(Flashcard {
+            id: record.id,
+            book_id: record.book_id,
+            card: NewFlashcard {
+                chapter_number: record.chapter_number.parse()?,
+                front: record.front,
+                back: record.back,
+            },
+            review: ReviewState {
+                ease_factor: record.ease_factor,
+                interval_days: record.interval_days,
+                repetitions: record.repetitions,
+            },
+            due_time: record.due_time,
+        });
+    }
+    Ok(cards)
+}
+
+/// record a review graded from 0 to 5 and schedule the next one
+pub async fn review(
+    database: &SqlitePool,
+    student_id: i64,
+    card_id: i64,
+    quality: u8,
+) -> anyhow::Result<ReviewState> {
+    if quality > 5 {
+        anyhow::bail!("Review quality must be between 0 and 5, got {}", quality);
+    }
+    let record = sqlx::query!(
+        "select ease_factor, interval_days, repetitions from flashcard where id = ? and student_id = ?",
+        card_id,
+        student_id
+    )
+    .fetch_optional(database)
+    .await?
+    .ok_or(anyhow::anyhow!("Flashcard not found: {}", card_id))?;
+    let state = ReviewState {
+        ease_factor: record.ease_factor,
+        interval_days: record.interval_days,
+        repetitions: record.repetitions,
+    }
+    .review(quality);
+    let due_time = OffsetDateTime::now_utc()
+        .checked_add(Duration::days(state.interval_days))
+        .ok_or(anyhow::anyhow!(
+            "Review interval out of range: {} days",
+            state.interval_days
+        ))?;
+    sqlx::query!(
+        "update flashcard set ease_factor = ?, interval_days = ?, repetitions = ?, due_time = ? where id = ?",
+        state.ease_factor,
+        state.interval_days,
+        state.repetitions,
+        due_time,
+        card_id
+    )
+    .execute(database)
+    .await?;
+    Ok(state)
+}
+
+#[test]
+fn sm2_schedule() {
+    let state = ReviewState::default().review(4);
+    assert_eq!((state.interval_days, state.repetitions), (1, 1));
+    let state = state.review(5);
+    assert_eq!((state.interval_days, state.repetitions), (6, 2));
+    // 6 days times the ease of 2.6 after a perfect review
+    let state = state.review(4);
+    assert_eq!((state.interval_days, state.repetitions), (16, 3));
+    let lapsed = state.review(1);
+    assert_eq!((lapsed.interval_days, lapsed.repetitions), (1, 0));
+    assert!(lapsed.ease_factor < state.ease_factor);
+    let mut floor = ReviewState::default();
+    for _ in 0..10 {
+        floor = floor.review(0);
+    }
+    assert_eq!(floor.ease_factor, 1.3);
+}
+
+#[test]
+fn interval_cap() {
+    let mut state = ReviewState::default();
+    for _ in 0..100 {
+        state = state.review(5);
+    }
+    assert_eq!(state.interval_days, MAX_INTERVAL_DAYS);
+    assert_eq!(state.repetitions, 100);
+    assert!(
+        OffsetDateTime::now_utc()
+            .checked_add(Duration::days(state.interval_days))
+            .is_some()
+    );
+}
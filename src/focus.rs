@@ -0,0 +1,114 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+use time::{Duration, OffsetDateTime};
+use utoipa::ToSchema;
+
+/// Summary of a finished focus session
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FocusSummary {
+    pub id: i64,
+    /// time spent studying, idle gaps excluded
+    pub active_minutes: i64,
+    /// number of times the student was idle longer than the configured threshold
+    pub idle_gaps: i64,
+    pub idle_minutes: i64,
+}
+
+/// start a focus session, an open session on the same book is stopped first
+pub async fn start_focus(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+) -> anyhow::Result<i64> {
+    stop_focus(database, student_id, book_id).await?;
+    let now = OffsetDateTime::now_utc();
+    let result = sqlx::query!(
+        "insert into focus_session (student_id, book_id, start_time, last_activity) values (?, ?, ?, ?)",
+        student_id,
+        book_id,
+        now,
+        now
+    )
+    .execute(database)
+    .await?;
+    Ok(result.last_insert_rowid())
+}
+
+/// stop the open focus session, returns `None` if there is none
+pub async fn stop_focus(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+) -> anyhow::Result<Option<FocusSummary>> {
+    let Some(record) = sqlx::query!(
+        "select id, start_time, idle_gaps, idle_seconds from focus_session where student_id = ? and book_id = ? and end_time is null",
+        student_id,
+        book_id
+    )
+    .fetch_optional(database)
+    .await?
+    else {
+        return Ok(None);
+    };
+    let now = OffsetDateTime::now_utc();
+    sqlx::query!(
+        "update focus_session set end_time = ? where id = ?",
+        now,
+        record.id
+    )
+    .execute(database)
+    .await?;
+    let idle = Duration::seconds(record.idle_seconds);
+    Ok(Some(FocusSummary {
+        id: record.id,
+        active_minutes: (now - record.start_time - idle).whole_minutes().max(0),
+        idle_gaps: record.idle_gaps,
+        idle_minutes: idle.whole_minutes(),
+    }))
+}
+
+/// Record student activity in the open focus session.
+///
+/// Returns the idle gap if the student was away longer than `idle_threshold`,
+/// so the teacher can re-engage them. Nothing nudges an idle student, the gap is only noticed
+/// on the next message.
+pub async fn record_activity(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+    idle_threshold: Duration,
+) -> anyhow::Result<Option<Duration>> {
+    let Some(record) = sqlx::query!(
+        "select id, last_activity from focus_session where student_id = ? and book_id = ? and end_time is null",
+        student_id,
+        book_id
+    )
+    .fetch_optional(database)
+    .await?
+    else {
+        return Ok(None);
+    };
+    let now = OffsetDateTime::now_utc();
+    let gap = now - record.last_activity;
+    if gap > idle_threshold {
+        let idle_seconds = gap.whole_seconds();
+        sqlx::query!(
+            "update focus_session set last_activity = ?, idle_gaps = idle_gaps + 1, idle_seconds = idle_seconds + ? where id = ?",
+            now,
+            idle_seconds,
+            record.id
+        )
+        .execute(database)
+        .await?;
+        Ok(Some(gap))
+    } else {
+        sqlx::query!(
+            "update focus_session set last_activity = ? where id = ?",
+            now,
+            record.id
+        )
+        .execute(database)
+        .await?;
+        Ok(None)
+    }
+}
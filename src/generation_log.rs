@@ -0,0 +1,143 @@
+use std::{future::Future, sync::OnceLock, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::{
+    pagination::{PageQuery, Paginated, SortOrder},
+    spend,
+};
+
+static DATABASE: OnceLock<SqlitePool> = OnceLock::new();
+
+tokio::task_local! {
+    static CONTEXT: GenerationContext;
+}
+
+/// What the model calls of the current task are generating
+#[derive(Debug, Clone, Default)]
+pub struct GenerationContext {
+    pub artifact: Option<&'static str>,
+    pub book_id: Option<i64>,
+    pub chapter_number: Option<String>,
+}
+
+/// A logged model call
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct GenerationRecord {
+    pub id: i64,
+    pub artifact: String,
+    pub step: String,
+    pub book_id: Option<i64>,
+    pub chapter_number: Option<String>,
+    pub model: String,
+    pub prompt: String,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub duration_ms: i64,
+    /// USD
+    pub cost: f64,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String)]
+    pub create_time: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct GenerationFilter {
+    pub book_id: Option<i64>,
+    pub artifact: Option<String>,
+}
+
+/// log the model calls to `database` from now on, the first call wins
+pub fn init(database: SqlitePool) {
+    let _ = DATABASE.set(database);
+}
+
+/// run `f` with the generation context of the current task updated by `update`
+pub async fn with_context<F: Future>(
+    update: impl FnOnce(&mut GenerationContext),
+    f: F,
+) -> F::Output {
+    let mut context = CONTEXT.try_with(Clone::clone).unwrap_or_default();
+    update(&mut context);
+    CONTEXT.scope(context, f).await
+}
+
+/// shorthand of [`with_context`] setting only the artifact
+pub async fn artifact<F: Future>(artifact: &'static str, f: F) -> F::Output {
+    with_context(|context| context.artifact = Some(artifact), f).await
+}
+
+/// log a model call under the current generation context, failures are only traced
+pub async fn record(
+    step: &str,
+    model: &str,
+    prompt: &str,
+    prompt_tokens: u32,
+    completion_tokens: u32,
+    duration: Duration,
+) {
+    let Some(database) = DATABASE.get() else {
+        return;
+    };
+    let context = CONTEXT.try_with(Clone::clone).unwrap_or_default();
+    let result = async {
+        let cost = spend::price(database, prompt_tokens, completion_tokens).await?;
+        let artifact = context.artifact.unwrap_or("other");
+        let (prompt_tokens, completion_tokens) = (prompt_tokens as i64, completion_tokens as i64);
+        let duration_ms = duration.as_millis() as i64;
+        sqlx::query!(
+            "insert into generation_log (artifact, step, book_id, chapter_number, model, prompt, prompt_tokens, completion_tokens, duration_ms, cost) values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            artifact,
+            step,
+            context.book_id,
+            context.chapter_number,
+            model,
+            prompt,
+            prompt_tokens,
+            completion_tokens,
+            duration_ms,
+            cost
+        )
+        .execute(database)
+        .await?;
+        anyhow::Ok(())
+    };
+    if let Err(e) = result.await {
+        error!("record generation log failed: {}", e);
+    }
+}
+
+/// a page of the logged calls by id, newest first by default
+pub async fn list(
+    database: &SqlitePool,
+    filter: &GenerationFilter,
+    page: &PageQuery,
+) -> anyhow::Result<Paginated<GenerationRecord>> {
+    let ascending = page.order(SortOrder::Desc) == SortOrder::Asc;
+    let cursor = page.cursor::<i64>()?;
+    let limit = page.limit() as i64 + 1;
+    let records = sqlx::query_as!(
+        GenerationRecord,
+        r#"select id as "id!", artifact, step, book_id, chapter_number, model, prompt, prompt_tokens, completion_tokens, duration_ms, cost, create_time as "create_time: OffsetDateTime"
+        from generation_log
+        where (?1 is null or book_id = ?1) and (?2 is null or artifact = ?2)
+        and (?3 is null or (?4 and id > ?3) or (not ?4 and id < ?3))
+        order by case when ?4 then id else -id end limit ?5"#,
+        filter.book_id,
+        filter.artifact,
+        cursor,
+        ascending,
+        limit
+    )
+    .fetch_all(database)
+    .await?;
+    Ok(Paginated::from_overfetched(
+        records,
+        page.limit(),
+        |record| record.id,
+    ))
+}
@@ -0,0 +1,312 @@
+use std::sync::Mutex;
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+use crate::teacher::filters::{FilterContext, ResponseFilter};
+
+/// characters of a screened text kept in the log
+const EXCERPT_CHARS: usize = 200;
+
+/// A topic the teacher must not discuss with the students of a class
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ForbiddenTopic {
+    pub id: i64,
+    pub class_id: i64,
+    pub topic: String,
+    /// words and phrases that bring the topic up, matched as whole words ignoring case
+    pub keywords: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct TopicRequest {
+    pub topic: String,
+    /// words and phrases that bring the topic up, the topic itself is always one
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
+/// Which side of the conversation touched a forbidden topic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// the student asked about it, the question was not sent to the model
+    Question,
+    /// the teacher brought it up, the lines were removed from the response
+    Response,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Question => "question",
+            Self::Response => "response",
+        }
+    }
+}
+
+/// A screened question or response that touched a forbidden topic
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct GuardrailEvent {
+    pub id: i64,
+    pub student_id: i64,
+    pub book_id: i64,
+    pub topic: String,
+    pub direction: String,
+    /// the start of the screened text
+    pub excerpt: String,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String)]
+    pub create_time: OffsetDateTime,
+}
+
+/// the whole-word pattern of a keyword, CJK text has no word boundaries to match
+fn keyword_pattern(keyword: &str) -> String {
+    let boundary = |c: Option<char>| {
+        if c.is_some_and(|c| c.is_ascii_alphanumeric()) {
+            r"\b"
+        } else {
+            ""
+        }
+    };
+    format!(
+        "{}{}{}",
+        boundary(keyword.chars().next()),
+        regex::escape(keyword),
+        boundary(keyword.chars().last())
+    )
+}
+
+/// The forbidden topics of a class, compiled to screen texts
+#[derive(Debug)]
+pub struct Guardrail {
+    topics: Vec<(String, Regex)>,
+}
+
+impl Guardrail {
+    pub fn new(topics: &[ForbiddenTopic]) -> anyhow::Result<Self> {
+        let topics = topics
+            .iter()
+            .map(|topic| {
+                let pattern = std::iter::once(&topic.topic)
+                    .chain(&topic.keywords)
+                    .map(|keyword| keyword.trim())
+                    .filter(|keyword| !keyword.is_empty())
+                    .map(keyword_pattern)
+                    .collect::<Vec<_>>()
+                    .join("|");
+                let regex = RegexBuilder::new(&pattern).case_insensitive(true).build()?;
+                Ok((topic.topic.clone(), regex))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { topics })
+    }
+
+    /// the guardrail of the class of the student, `None` without a class or forbidden topics
+    pub async fn for_student(
+        database: &SqlitePool,
+        student_id: i64,
+    ) -> anyhow::Result<Option<Self>> {
+        let class_id = sqlx::query_scalar!("select class_id from student where id = ?", student_id)
+            .fetch_one(database)
+            .await?;
+        let Some(class_id) = class_id else {
+            return Ok(None);
+        };
+        let topics = list_topics(database, class_id).await?;
+        if topics.is_empty() {
+            return Ok(None);
+        }
+        Self::new(&topics).map(Some)
+    }
+
+    /// the names of the topics, for the instructions of the teacher
+    pub fn topics(&self) -> impl Iterator<Item = &str> {
+        self.topics.iter().map(|(topic, _)| topic.as_str())
+    }
+
+    /// the first forbidden topic `text` touches
+    pub fn screen(&self, text: &str) -> Option<&str> {
+        self.topics
+            .iter()
+            .find(|(_, regex)| regex.is_match(text))
+            .map(|(topic, _)| topic.as_str())
+    }
+}
+
+/// Removes the lines of a response that touch a forbidden topic, keeping them to be logged
+pub struct TopicFilter {
+    guardrail: Guardrail,
+    /// the line put in place of a removed one
+    replacement: String,
+    /// the topics and lines removed so far
+    removed: Mutex<Vec<(String, String)>>,
+}
+
+impl TopicFilter {
+    pub fn new(guardrail: Guardrail, replacement: String) -> Self {
+        Self {
+            guardrail,
+            replacement,
+            removed: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// the topics and lines removed since the last call
+    pub fn take_removed(&self) -> Vec<(String, String)> {
+        std::mem::take(&mut *self.removed.lock().unwrap())
+    }
+}
+
+impl ResponseFilter for TopicFilter {
+    fn name(&self) -> &str {
+        "forbidden_topics"
+    }
+    fn apply(&self, line: &str, _context: &FilterContext) -> String {
+        match self.guardrail.screen(line) {
+            Some(topic) => {
+                self.removed
+                    .lock()
+                    .unwrap()
+                    .push((topic.to_string(), line.to_string()));
+                self.replacement.clone()
+            }
+            None => line.to_string(),
+        }
+    }
+}
+
+pub async fn add_topic(
+    database: &SqlitePool,
+    class_id: i64,
+    request: &TopicRequest,
+) -> anyhow::Result<i64> {
+    let topic = request.topic.trim();
+    if topic.is_empty() {
+        anyhow::bail!("Topic must not be empty");
+    }
+    let keywords: Vec<&str> = request
+        .keywords
+        .iter()
+        .map(|keyword| keyword.trim())
+        .filter(|keyword| !keyword.is_empty())
+        .collect();
+    let keywords = serde_json::to_string(&keywords)?;
+    let id = sqlx::query_scalar!(
+        "insert into forbidden_topic (class_id, topic, keywords) values (?, ?, ?) returning id",
+        class_id,
+        topic,
+        keywords
+    )
+    .fetch_one(database)
+    .await?;
+    Ok(id)
+}
+
+pub async fn list_topics(
+    database: &SqlitePool,
+    class_id: i64,
+) -> anyhow::Result<Vec<ForbiddenTopic>> {
+    let records = sqlx::query!(
+        "select id, class_id, topic, keywords from forbidden_topic where class_id = ? order by id",
+        class_id
+    )
+    .fetch_all(database)
+    .await?;
+    records
+        .into_iter()
+        .map(|record| {
+            Ok(ForbiddenTopic {
+                id: record.id,
+                class_id: record.class_id,
+                topic: record.topic,
+                keywords: serde_json::from_str(&record.keywords)?,
+            })
+        })
+        .collect()
+}
+
+pub async fn remove_topic(database: &SqlitePool, id: i64) -> anyhow::Result<()> {
+    let result = sqlx::query!("delete from forbidden_topic where id = ?", id)
+        .execute(database)
+        .await?;
+    if result.rows_affected() == 0 {
+        anyhow::bail!("Forbidden topic not found: {}", id);
+    }
+    Ok(())
+}
+
+/// log that the student or the teacher touched a forbidden topic
+pub async fn log_event(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+    topic: &str,
+    direction: Direction,
+    text: &str,
+) -> anyhow::Result<()> {
+    let excerpt: String = text.chars().take(EXCERPT_CHARS).collect();
+    let direction = direction.as_str();
+    sqlx::query!(
+        "insert into guardrail_event (student_id, book_id, class_id, topic, direction, excerpt)
+        select id, ?, class_id, ?, ?, ? from student where id = ?",
+        book_id,
+        topic,
+        direction,
+        excerpt,
+        student_id
+    )
+    .execute(database)
+    .await?;
+    Ok(())
+}
+
+/// the latest events of the students of the class, newest first
+pub async fn list_events(
+    database: &SqlitePool,
+    class_id: i64,
+    limit: i64,
+) -> anyhow::Result<Vec<GuardrailEvent>> {
+    let events = sqlx::query_as!(
+        GuardrailEvent,
+        r#"select id, student_id, book_id, topic, direction, excerpt,
+        create_time as "create_time: OffsetDateTime"
+        from guardrail_event where class_id = ? order by id desc limit ?"#,
+        class_id,
+        limit
+    )
+    .fetch_all(database)
+    .await?;
+    Ok(events)
+}
+
+#[test]
+fn screen() {
+    let topic = |topic: &str, keywords: &[&str]| ForbiddenTopic {
+        id: 0,
+        class_id: 0,
+        topic: topic.to_string(),
+        keywords: keywords.iter().map(|k| k.to_string()).collect(),
+    };
+    let guardrail = Guardrail::new(&[
+        topic("Gambling", &["poker", "sports betting"]),
+        topic("赌博", &[]),
+    ])
+    .unwrap();
+    assert_eq!(guardrail.screen("How do I win at POKER?"), Some("Gambling"));
+    assert_eq!(
+        guardrail.screen("Is sports betting legal?"),
+        Some("Gambling")
+    );
+    assert_eq!(guardrail.screen("a pokery word"), None);
+    assert_eq!(guardrail.screen("网上赌博怎么玩"), Some("赌博"));
+    let filter = TopicFilter::new(guardrail, "[removed]".to_string());
+    let context = FilterContext { book_id: 1 };
+    assert_eq!(filter.apply("Let's play poker", &context), "[removed]");
+    assert_eq!(filter.apply("Back to loops", &context), "Back to loops");
+    assert_eq!(filter.take_removed().len(), 1);
+    assert!(filter.take_removed().is_empty());
+}
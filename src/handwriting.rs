@@ -0,0 +1,286 @@
+use std::io::Cursor;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use image::{DynamicImage, GrayImage, ImageFormat, ImageReader, Limits, Luma};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tokio::task::spawn_blocking;
+use utoipa::ToSchema;
+
+use crate::{
+    ai_utils::{self, Provider},
+    i18n,
+    scan::UploadScanner,
+    student,
+};
+
+/// longest side of a canvas or a snapshot, in pixels
+const MAX_SIDE: u32 = 8192;
+/// longest side of the image the recognition model reads, larger ones are scaled down
+const RECOGNITION_SIDE: u32 = 1568;
+const MAX_POINTS: usize = 100_000;
+/// longest ink of all the strokes, in pixels of the scaled canvas
+const MAX_INK: f32 = 1_000_000.0;
+const MAX_SNAPSHOT_BYTES: usize = 8 * 1024 * 1024;
+const PEN_RADIUS: i64 = 2;
+
+const RECOGNITION_PROMPT: &str = "Read the handwriting of a student in this image, written on a \
+    tablet or a whiteboard. Transcribe it exactly as written, mistakes included, since a teacher \
+    will check the work. Keep the lines in order and write math as LaTeX.";
+
+/// A pen stroke, its points in pixels from the top left of the canvas
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct Stroke {
+    #[schema(value_type = Vec<Vec<f32>>)]
+    pub points: Vec<[f32; 2]>,
+}
+
+/// Handwriting sent by a tablet client along a chat message
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Handwriting {
+    /// the strokes drawn on a canvas of `width` by `height` pixels
+    Strokes {
+        width: u32,
+        height: u32,
+        strokes: Vec<Stroke>,
+    },
+    /// a snapshot of a whiteboard, a base64 PNG, JPEG or WebP, a data URL works too
+    Snapshot { image: String },
+}
+
+/// What the recognition model read
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
+pub struct Recognition {
+    /// The handwriting as text, line by line, with the math inline as LaTeX between $ signs
+    pub text: String,
+    /// Only the math of the handwriting as LaTeX without delimiters, one equation per line, empty if there is none
+    #[serde(default)]
+    pub latex: String,
+}
+
+impl Handwriting {
+    /// the handwriting as a PNG the recognition model can read
+    pub fn to_png(&self) -> anyhow::Result<Vec<u8>> {
+        let image = match self {
+            Self::Strokes {
+                width,
+                height,
+                strokes,
+            } => DynamicImage::ImageLuma8(render(*width, *height, strokes)?),
+            Self::Snapshot { image } => decode_snapshot(image)?,
+        };
+        let image = if image.width().max(image.height()) > RECOGNITION_SIDE {
+            image.thumbnail(RECOGNITION_SIDE, RECOGNITION_SIDE)
+        } else {
+            image
+        };
+        let mut png = Vec::new();
+        image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+        Ok(png)
+    }
+
+    /// scan a snapshot like an uploaded file, strokes are drawn by the server and need none
+    async fn scan(&self, scanner: &dyn UploadScanner) -> anyhow::Result<()> {
+        let Self::Snapshot { image } = self else {
+            return Ok(());
+        };
+        let bytes = snapshot_bytes(image)?;
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("snapshot");
+        tokio::fs::write(&path, bytes).await?;
+        scanner.scan(&path).await?.into_result("snapshot")
+    }
+}
+
+fn snapshot_bytes(image: &str) -> anyhow::Result<Vec<u8>> {
+    let data = match image.strip_prefix("data:") {
+        Some(url) => url.split_once(',').map_or(url, |(_, data)| data),
+        None => image,
+    };
+    let bytes = STANDARD.decode(data.trim())?;
+    if bytes.len() > MAX_SNAPSHOT_BYTES {
+        anyhow::bail!(
+            "A snapshot is limited to {} MiB",
+            MAX_SNAPSHOT_BYTES / 1024 / 1024
+        );
+    }
+    Ok(bytes)
+}
+
+fn decode_snapshot(image: &str) -> anyhow::Result<DynamicImage> {
+    let bytes = snapshot_bytes(image)?;
+    let mut reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SIDE);
+    limits.max_image_height = Some(MAX_SIDE);
+    reader.limits(limits);
+    Ok(reader.decode()?)
+}
+
+/// the strokes in black on white, on the canvas scaled down to [`RECOGNITION_SIDE`]
+fn render(width: u32, height: u32, strokes: &[Stroke]) -> anyhow::Result<GrayImage> {
+    if !(1..=MAX_SIDE).contains(&width) || !(1..=MAX_SIDE).contains(&height) {
+        anyhow::bail!("The canvas must be 1 to {} pixels a side", MAX_SIDE);
+    }
+    let points = strokes
+        .iter()
+        .map(|stroke| stroke.points.len())
+        .sum::<usize>();
+    if points == 0 {
+        anyhow::bail!("The handwriting has no strokes");
+    }
+    if points > MAX_POINTS {
+        anyhow::bail!("The handwriting is limited to {} points", MAX_POINTS);
+    }
+    let scale = (RECOGNITION_SIDE as f32 / width.max(height) as f32).min(1.0);
+    let (width, height) = (
+        ((width as f32 * scale).round() as u32).max(1),
+        ((height as f32 * scale).round() as u32).max(1),
+    );
+    let scaled = |[x, y]: [f32; 2]| {
+        anyhow::ensure!(x.is_finite() && y.is_finite(), "Invalid point");
+        Ok([
+            (x * scale).clamp(0.0, width as f32),
+            (y * scale).clamp(0.0, height as f32),
+        ])
+    };
+    let mut image = GrayImage::from_pixel(width, height, Luma([255]));
+    let mut ink = 0.0;
+    for stroke in strokes {
+        let points = stroke
+            .points
+            .iter()
+            .map(|point| scaled(*point))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if let [point] = points.as_slice() {
+            dot(&mut image, *point);
+        }
+        for segment in points.windows(2) {
+            ink += (segment[1][0] - segment[0][0]).hypot(segment[1][1] - segment[0][1]);
+            if ink > MAX_INK {
+                anyhow::bail!("The handwriting has too many strokes");
+            }
+            line(&mut image, segment[0], segment[1]);
+        }
+    }
+    Ok(image)
+}
+
+fn line(image: &mut GrayImage, from: [f32; 2], to: [f32; 2]) {
+    let steps = (to[0] - from[0]).hypot(to[1] - from[1]).ceil().max(1.0) as usize;
+    for step in 0..=steps {
+        let t = step as f32 / steps as f32;
+        dot(
+            image,
+            [
+                from[0] + (to[0] - from[0]) * t,
+                from[1] + (to[1] - from[1]) * t,
+            ],
+        );
+    }
+}
+
+fn dot(image: &mut GrayImage, [x, y]: [f32; 2]) {
+    let (x, y) = (x.round() as i64, y.round() as i64);
+    for dy in -PEN_RADIUS..=PEN_RADIUS {
+        for dx in -PEN_RADIUS..=PEN_RADIUS {
+            let (px, py) = (x + dx, y + dy);
+            if dx * dx + dy * dy <= PEN_RADIUS * PEN_RADIUS
+                && (0..image.width() as i64).contains(&px)
+                && (0..image.height() as i64).contains(&py)
+            {
+                image.put_pixel(px as u32, py as u32, Luma([0]));
+            }
+        }
+    }
+}
+
+/// read the handwriting with `provider`, a model that accepts images
+/// the handwriting as text, a snapshot is scanned first when `scanner` is given
+pub async fn recognize(
+    provider: &dyn Provider,
+    scanner: Option<&dyn UploadScanner>,
+    handwriting: Handwriting,
+) -> anyhow::Result<Recognition> {
+    if let Some(scanner) = scanner {
+        handwriting.scan(scanner).await?;
+    }
+    let png = spawn_blocking(move || handwriting.to_png()).await??;
+    ai_utils::extract_from_image(provider, RECOGNITION_PROMPT.to_string(), &png).await
+}
+
+/// the message of the student with the handwriting sent along, recognized, after it
+pub async fn read_message(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+    message: String,
+    handwriting: Option<Handwriting>,
+    scanner: Option<&dyn UploadScanner>,
+) -> anyhow::Result<String> {
+    let Some(handwriting) = handwriting else {
+        return Ok(message);
+    };
+    let provider = ai_utils::resolve_recognition_provider(database, student_id, book_id).await?;
+    let recognition = recognize(provider.as_ref(), scanner, handwriting).await?;
+    let locale = student::get_student_locale(database, student_id).await?;
+    Ok(with_message(&locale, &message, &recognition))
+}
+
+fn with_message(locale: &str, message: &str, recognition: &Recognition) -> String {
+    let handwriting = i18n::tr(
+        locale,
+        "student-handwriting",
+        &[("content", recognition.text.trim().into())],
+    );
+    if message.trim().is_empty() {
+        handwriting
+    } else {
+        format!("{}\n\n{}", message.trim_end(), handwriting)
+    }
+}
+
+#[test]
+fn render_strokes() {
+    let strokes = vec![
+        Stroke {
+            points: vec![[10.0, 10.0], [90.0, 10.0]],
+        },
+        Stroke {
+            points: vec![[50.0, 80.0]],
+        },
+    ];
+    let image = render(100, 100, &strokes).unwrap();
+    assert_eq!(image.get_pixel(50, 10), &Luma([0]));
+    assert_eq!(image.get_pixel(50, 80), &Luma([0]));
+    assert_eq!(image.get_pixel(50, 50), &Luma([255]));
+    // a large canvas is scaled down for the model
+    let image = render(3136, 1000, &strokes).unwrap();
+    assert_eq!((image.width(), image.height()), (1568, 500));
+    assert!(render(100, 100, &[]).is_err());
+    assert!(render(0, 100, &strokes).is_err());
+    let nan = Stroke {
+        points: vec![[f32::NAN, 1.0]],
+    };
+    assert!(render(100, 100, &[nan]).is_err());
+    let png = Handwriting::Strokes {
+        width: 100,
+        height: 100,
+        strokes,
+    }
+    .to_png()
+    .unwrap();
+    let snapshot = Handwriting::Snapshot {
+        image: format!("data:image/png;base64,{}", STANDARD.encode(&png)),
+    };
+    assert!(snapshot.to_png().is_ok());
+    let recognition = Recognition {
+        text: "$x^2 = 4$, so $x = 2$".to_string(),
+        latex: "x^2 = 4\nx = 2".to_string(),
+    };
+    let message = with_message("en", "Is this right?", &recognition);
+    assert!(message.starts_with("Is this right?\n\n"));
+    assert!(message.contains("$x = 2$"));
+}
@@ -0,0 +1,106 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+use fluent_bundle::{FluentArgs, FluentResource, FluentValue, concurrent::FluentBundle};
+use tracing::error;
+use unic_langid::LanguageIdentifier;
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// (locale, fluent source) of the bundled translations
+const RESOURCES: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en/main.ftl")),
+    ("zh", include_str!("../locales/zh/main.ftl")),
+];
+
+static BUNDLES: LazyLock<HashMap<&'static str, FluentBundle<FluentResource>>> =
+    LazyLock::new(|| {
+        RESOURCES
+            .iter()
+            .map(|(locale, source)| {
+                let langid: LanguageIdentifier = locale.parse().expect("invalid locale");
+                let resource = FluentResource::try_new(source.to_string())
+                    .unwrap_or_else(|(_, errors)| panic!("invalid {locale} ftl: {errors:?}"));
+                let mut bundle = FluentBundle::new_concurrent(vec![langid]);
+                // no bidi isolation marks, the text goes to the model and to plain text clients
+                bundle.set_use_isolating(false);
+                bundle
+                    .add_resource(resource)
+                    .unwrap_or_else(|errors| panic!("duplicated {locale} messages: {errors:?}"));
+                (*locale, bundle)
+            })
+            .collect()
+    });
+
+/// the supported locale closest to `locale`, e.g. "zh-CN" -> "zh", unknown -> "en"
+pub fn negotiate(locale: &str) -> &'static str {
+    let language = locale
+        .parse::<LanguageIdentifier>()
+        .map(|langid| langid.language.to_string())
+        .unwrap_or_default();
+    RESOURCES
+        .iter()
+        .map(|(supported, _)| *supported)
+        .find(|supported| *supported == locale || *supported == language)
+        .unwrap_or(DEFAULT_LOCALE)
+}
+
+pub fn is_supported(locale: &str) -> bool {
+    RESOURCES.iter().any(|(supported, _)| *supported == locale)
+}
+
+/// format the message `id` in `locale`, falling back to the default locale, then to the id itself
+pub fn message(locale: &str, id: &str, args: Option<&FluentArgs>) -> String {
+    for locale in [negotiate(locale), DEFAULT_LOCALE] {
+        let bundle = &BUNDLES[locale];
+        let Some(pattern) = bundle.get_message(id).and_then(|message| message.value()) else {
+            continue;
+        };
+        let mut errors = vec![];
+        let text = bundle.format_pattern(pattern, args, &mut errors);
+        if !errors.is_empty() {
+            error!("formatting {id} in {locale}: {errors:?}");
+        }
+        return text.into_owned();
+    }
+    error!("missing message {id}");
+    id.to_string()
+}
+
+/// `message` with named arguments, e.g. `tr(locale, "error-rate-limit", &[("seconds", 5.into())])`
+pub fn tr(locale: &str, id: &str, args: &[(&str, FluentValue)]) -> String {
+    if args.is_empty() {
+        return message(locale, id, None);
+    }
+    let mut fluent_args = FluentArgs::new();
+    for (name, value) in args {
+        fluent_args.set(*name, value.clone());
+    }
+    message(locale, id, Some(&fluent_args))
+}
+
+#[test]
+fn messages() {
+    assert_eq!(negotiate("zh-CN"), "zh");
+    assert_eq!(negotiate("fr"), "en");
+    for (locale, _) in RESOURCES {
+        let instruction = tr(
+            locale,
+            "teacher-instruction",
+            &[("student_name", "Ada".into()), ("book_name", "Rust".into())],
+        );
+        assert!(instruction.contains("Ada"));
+        assert!(instruction.contains(r#"[BookJump: {"chapter_number""#));
+        assert!(!instruction.contains("teacher-instruction"));
+    }
+    assert!(tr("zh", "error-rate-limit", &[("seconds", 5.into())]).contains('5'));
+    let advanced = tr(
+        "en",
+        "teacher-book-difficulty",
+        &[
+            ("student_name", "Ada".into()),
+            ("book_name", "Rust".into()),
+            ("difficulty", "advanced".into()),
+        ],
+    );
+    assert!(advanced.contains("advanced readers"));
+}
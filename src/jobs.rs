@@ -0,0 +1,501 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Debug, Display},
+    future::Future,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
+
+use dashmap::DashMap;
+use futures::{StreamExt, future::BoxFuture};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+use tokio::sync::Semaphore;
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::dead_letter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    /// every item was processed, some may have failed
+    Completed,
+    /// stopped before every item was processed
+    Cancelled,
+}
+
+/// An item of a job that failed
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobItemError {
+    pub item: String,
+    /// the error of the last attempt
+    pub error: String,
+    /// how often the item was tried, 0 in a dry run
+    pub attempts: u32,
+}
+
+/// Progress of a background batch job
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobStatus {
+    pub id: u64,
+    /// what the job does, e.g. "delete_books"
+    pub kind: String,
+    pub state: JobState,
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: Vec<JobItemError>,
+    /// items not run because the job was cancelled
+    pub cancelled: usize,
+    /// attempts beyond the first, over all items
+    pub retries: usize,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String)]
+    pub create_time: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    #[schema(value_type = Option<String>)]
+    pub finish_time: Option<OffsetDateTime>,
+}
+
+/// What a batch would do, returned by a dry run instead of starting the job
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BatchPreview<T> {
+    /// the items the job would change
+    pub affected: Vec<T>,
+    /// the items the job would fail on or leave unchanged
+    pub skipped: Vec<JobItemError>,
+}
+
+impl<T> Default for BatchPreview<T> {
+    fn default() -> Self {
+        Self {
+            affected: vec![],
+            skipped: vec![],
+        }
+    }
+}
+
+impl<T> BatchPreview<T> {
+    pub fn skip(&mut self, item: impl Display, reason: impl Display) {
+        self.skipped.push(JobItemError {
+            item: item.to_string(),
+            error: reason.to_string(),
+            attempts: 0,
+        });
+    }
+}
+
+/// How the jobs of a kind run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct JobPolicy {
+    /// items of this kind processed at the same time, over all its jobs; items waiting for a
+    /// retry don't count
+    pub concurrency: usize,
+    /// attempts per item before it is recorded as failed
+    pub max_attempts: u32,
+    /// wait before the first retry of an item, doubled for every further retry
+    pub backoff_seconds: u64,
+}
+
+impl Default for JobPolicy {
+    fn default() -> Self {
+        Self {
+            concurrency: 1,
+            max_attempts: 1,
+            backoff_seconds: 0,
+        }
+    }
+}
+
+impl JobPolicy {
+    fn backoff(&self, attempt: u32) -> std::time::Duration {
+        std::time::Duration::from_secs(self.backoff_seconds << (attempt - 1).min(10))
+    }
+}
+
+type ItemTask = Arc<dyn Fn() -> BoxFuture<'static, anyhow::Result<()>> + Send + Sync>;
+
+struct JobItem {
+    name: String,
+    /// json of the item, kept with a dead letter so it can be requeued
+    payload: serde_json::Value,
+    run: ItemTask,
+}
+
+impl Debug for JobItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JobItem").field("name", &self.name).finish()
+    }
+}
+
+#[derive(Debug, Default)]
+struct JobControl {
+    cancelled: AtomicBool,
+    /// failed and cancelled items, run again by a retry
+    retryable: Mutex<Vec<JobItem>>,
+}
+
+/// Batch jobs running in the background, kept in memory until pruned
+#[derive(Debug, Default)]
+pub struct JobQueue {
+    jobs: DashMap<u64, JobStatus>,
+    controls: DashMap<u64, Arc<JobControl>>,
+    policies: DashMap<String, JobPolicy>,
+    /// one semaphore per kind, sized by the concurrency of its policy
+    limits: DashMap<String, Arc<Semaphore>>,
+    /// where items that failed every attempt are persisted, only traced if `None`
+    dead_letters: Option<SqlitePool>,
+    next_id: AtomicU64,
+}
+
+impl JobQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// persist the items that fail every attempt to the `dead_letter` table of `database`
+    pub fn with_dead_letters(mut self, database: SqlitePool) -> Self {
+        self.dead_letters = Some(database);
+        self
+    }
+
+    /// run the jobs of `kind` with `policy` instead of the default, which runs one item at a
+    /// time without retries; an item waiting for its retry leaves its slot to the others
+    pub fn with_policy(self, kind: &str, policy: JobPolicy) -> Self {
+        self.set_policy(kind, policy);
+        self
+    }
+
+    /// change the policy of `kind`, jobs already waiting keep the old concurrency
+    pub fn set_policy(&self, kind: &str, policy: JobPolicy) {
+        let policy = JobPolicy {
+            concurrency: policy.concurrency.max(1),
+            max_attempts: policy.max_attempts.max(1),
+            ..policy
+        };
+        self.policies.insert(kind.to_string(), policy);
+        self.limits.insert(
+            kind.to_string(),
+            Arc::new(Semaphore::new(policy.concurrency)),
+        );
+    }
+
+    pub fn policy(&self, kind: &str) -> JobPolicy {
+        self.policies
+            .get(kind)
+            .map(|policy| *policy)
+            .unwrap_or_default()
+    }
+
+    /// the configured policies, kinds not listed run with the default
+    pub fn policies(&self) -> BTreeMap<String, JobPolicy> {
+        self.policies
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+
+    fn limit(&self, kind: &str) -> Arc<Semaphore> {
+        self.limits
+            .entry(kind.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.policy(kind).concurrency)))
+            .clone()
+    }
+
+    /// run `task` on the items in the background under the policy of `kind`, returns the job id to poll
+    pub fn spawn_batch<T, F, Fut>(self: &Arc<Self>, kind: &str, items: Vec<T>, task: F) -> u64
+    where
+        T: Display + Serialize + Clone + Send + Sync + 'static,
+        F: Fn(T) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let task = Arc::new(task);
+        let items: Vec<JobItem> = items
+            .into_iter()
+            .map(|item| {
+                let task = task.clone();
+                JobItem {
+                    name: item.to_string(),
+                    payload: serde_json::to_value(&item).unwrap_or_default(),
+                    run: Arc::new(move || -> BoxFuture<'static, anyhow::Result<()>> {
+                        Box::pin(task(item.clone()))
+                    }),
+                }
+            })
+            .collect();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.jobs.insert(
+            id,
+            JobStatus {
+                id,
+                kind: kind.to_string(),
+                state: JobState::Running,
+                total: items.len(),
+                succeeded: 0,
+                failed: vec![],
+                cancelled: 0,
+                retries: 0,
+                create_time: OffsetDateTime::now_utc(),
+                finish_time: None,
+            },
+        );
+        self.controls.insert(id, Arc::default());
+        self.run(id, items);
+        id
+    }
+
+    fn run(self: &Arc<Self>, id: u64, items: Vec<JobItem>) {
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let Some(kind) = queue.jobs.get(&id).map(|job| job.kind.clone()) else {
+                return;
+            };
+            let Some(control) = queue.controls.get(&id).map(|control| control.clone()) else {
+                return;
+            };
+            let policy = queue.policy(&kind);
+            let limit = queue.limit(&kind);
+            futures::stream::iter(items)
+                .for_each_concurrent(None, |item| {
+                    let (queue, control, limit, kind) = (&queue, &control, &limit, &kind);
+                    async move {
+                        let mut attempts = 0;
+                        let result = loop {
+                            let Ok(permit) = limit.acquire().await else {
+                                return;
+                            };
+                            if attempts == 0 && control.cancelled.load(Ordering::Relaxed) {
+                                if let Some(mut job) = queue.jobs.get_mut(&id) {
+                                    job.cancelled += 1;
+                                }
+                                control.retryable.lock().push(item);
+                                return;
+                            }
+                            attempts += 1;
+                            let result = (item.run)().await;
+                            // another item runs while this one waits for its retry
+                            drop(permit);
+                            let Err(e) = &result else {
+                                break result;
+                            };
+                            if attempts >= policy.max_attempts
+                                || control.cancelled.load(Ordering::Relaxed)
+                            {
+                                break result;
+                            }
+                            let backoff = policy.backoff(attempts);
+                            warn!(
+                                "job {} {}: {} attempt {} failed, retrying in {:?}: {}",
+                                id, kind, item.name, attempts, backoff, e
+                            );
+                            tokio::time::sleep(backoff).await;
+                            if control.cancelled.load(Ordering::Relaxed) {
+                                break result;
+                            }
+                        };
+                        let Some(mut job) = queue.jobs.get_mut(&id) else {
+                            return;
+                        };
+                        job.retries += attempts as usize - 1;
+                        match result {
+                            Ok(()) => job.succeeded += 1,
+                            Err(e) => {
+                                error!("job {} {}: {} failed: {}", id, kind, item.name, e);
+                                job.failed.push(JobItemError {
+                                    item: item.name.clone(),
+                                    error: e.to_string(),
+                                    attempts,
+                                });
+                                drop(job);
+                                if let Some(database) = &queue.dead_letters {
+                                    let context = format!("{e:?}");
+                                    if let Err(e) = dead_letter::record(
+                                        database,
+                                        kind,
+                                        id,
+                                        &item.name,
+                                        &item.payload,
+                                        &context,
+                                        attempts,
+                                    )
+                                    .await
+                                    {
+                                        error!("record dead letter of job {} failed: {}", id, e);
+                                    }
+                                }
+                                control.retryable.lock().push(item);
+                            }
+                        }
+                    }
+                })
+                .await;
+            if let Some(mut job) = queue.jobs.get_mut(&id) {
+                job.state = if control.cancelled.load(Ordering::Relaxed) {
+                    JobState::Cancelled
+                } else {
+                    JobState::Completed
+                };
+                job.finish_time = Some(OffsetDateTime::now_utc());
+                info!(
+                    "job {} {} {:?}, {} of {} succeeded",
+                    id, job.kind, job.state, job.succeeded, job.total
+                );
+            }
+        });
+    }
+
+    /// stop a running job, items already started finish and the rest can be retried later
+    pub fn cancel(&self, id: u64) -> anyhow::Result<()> {
+        let job = self.get(id).ok_or(anyhow::anyhow!("Job not found: {id}"))?;
+        if job.state != JobState::Running {
+            anyhow::bail!("Job {id} is not running");
+        }
+        if let Some(control) = self.controls.get(&id) {
+            control.cancelled.store(true, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// run the failed and cancelled items of a finished job again, under the same id
+    pub fn retry(self: &Arc<Self>, id: u64) -> anyhow::Result<()> {
+        let control = self
+            .controls
+            .get(&id)
+            .map(|control| control.clone())
+            .ok_or(anyhow::anyhow!("Job not found: {id}"))?;
+        {
+            let Some(mut job) = self.jobs.get_mut(&id) else {
+                anyhow::bail!("Job not found: {id}");
+            };
+            if job.state == JobState::Running {
+                anyhow::bail!("Job {id} is still running");
+            }
+            if job.failed.is_empty() && job.cancelled == 0 {
+                anyhow::bail!("Job {id} has nothing to retry");
+            }
+            job.state = JobState::Running;
+            job.failed.clear();
+            job.cancelled = 0;
+            job.finish_time = None;
+        }
+        control.cancelled.store(false, Ordering::Relaxed);
+        let items = std::mem::take(&mut *control.retryable.lock());
+        self.run(id, items);
+        Ok(())
+    }
+
+    pub fn get(&self, id: u64) -> Option<JobStatus> {
+        self.jobs.get(&id).map(|job| job.clone())
+    }
+
+    pub fn list(&self) -> Vec<JobStatus> {
+        self.jobs.iter().map(|job| job.clone()).collect()
+    }
+
+    /// drop jobs finished more than `keep` ago, call it periodically to bound memory
+    pub fn prune(&self, keep: time::Duration) {
+        let now = OffsetDateTime::now_utc();
+        self.jobs
+            .retain(|_, job| job.finish_time.is_none_or(|finish| now - finish < keep));
+        self.controls.retain(|id, _| self.jobs.contains_key(id));
+    }
+}
+
+#[tokio::test]
+async fn batch() {
+    let queue = Arc::new(JobQueue::new());
+    let id = queue.spawn_batch("check", vec![1, 2, 3], |n| async move {
+        if n == 2 {
+            anyhow::bail!("two");
+        }
+        Ok(())
+    });
+    while queue.get(id).unwrap().state == JobState::Running {
+        tokio::task::yield_now().await;
+    }
+    let job = queue.get(id).unwrap();
+    assert_eq!((job.total, job.succeeded), (3, 2));
+    assert_eq!(job.failed[0].item, "2");
+    queue.prune(time::Duration::ZERO);
+    assert!(queue.get(id).is_none());
+}
+
+#[tokio::test]
+async fn retry_with_backoff() {
+    use std::sync::atomic::AtomicU32;
+
+    let queue = Arc::new(JobQueue::new().with_policy(
+        "flaky",
+        JobPolicy {
+            concurrency: 2,
+            max_attempts: 2,
+            backoff_seconds: 0,
+        },
+    ));
+    let calls = Arc::new(AtomicU32::new(0));
+    let id = queue.spawn_batch("flaky", vec![1, 2], {
+        let calls = calls.clone();
+        move |n| {
+            let calls = calls.clone();
+            async move {
+                // item 1 fails on its first attempt only, item 2 always fails
+                if n == 2 || calls.fetch_add(1, Ordering::Relaxed) == 0 {
+                    anyhow::bail!("flaky");
+                }
+                Ok(())
+            }
+        }
+    });
+    while queue.get(id).unwrap().state == JobState::Running {
+        tokio::task::yield_now().await;
+    }
+    let job = queue.get(id).unwrap();
+    assert_eq!(job.succeeded, 1);
+    assert_eq!(job.failed[0].attempts, 2);
+    assert!(queue.cancel(id).is_err());
+    queue.retry(id).unwrap();
+    while queue.get(id).unwrap().state == JobState::Running {
+        tokio::task::yield_now().await;
+    }
+    let job = queue.get(id).unwrap();
+    assert_eq!((job.succeeded, job.failed.len()), (1, 1));
+}
+
+#[tokio::test]
+async fn backoff_frees_the_slot() {
+    let queue = Arc::new(JobQueue::new().with_policy(
+        "slow",
+        JobPolicy {
+            concurrency: 1,
+            max_attempts: 2,
+            backoff_seconds: 1,
+        },
+    ));
+    let runs = Arc::new(Mutex::new(vec![]));
+    let id = queue.spawn_batch("slow", vec![1, 2], {
+        let runs = runs.clone();
+        move |n| {
+            let runs = runs.clone();
+            async move {
+                let mut runs = runs.lock();
+                runs.push(n);
+                // item 1 fails on its first attempt only
+                if runs.len() == 1 {
+                    anyhow::bail!("slow");
+                }
+                Ok(())
+            }
+        }
+    });
+    while queue.get(id).unwrap().state == JobState::Running {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    // item 2 ran while item 1 waited for its retry
+    assert_eq!(*runs.lock(), [1, 2, 1]);
+    assert_eq!(queue.get(id).unwrap().succeeded, 2);
+}
@@ -1,7 +1,37 @@
+pub mod abuse;
+pub mod agent_profile;
 pub mod ai_utils;
+pub mod analytics;
+#[cfg(feature = "server")]
 pub mod api;
 pub mod books;
+pub mod course;
+pub mod db_migrate;
+pub mod dead_letter;
+pub mod embeddings;
 pub mod error;
+pub mod flashcard;
+pub mod focus;
+pub mod generation_log;
+pub mod guardrail;
+pub mod handwriting;
+pub mod i18n;
+pub mod jobs;
+pub mod pagination;
+pub mod project;
+pub mod prompts;
+pub mod quiz;
+pub mod quota;
+pub mod receipts;
+pub mod retrieval_eval;
+pub mod scan;
+pub mod scratchpad;
+pub mod scripting;
+pub mod snapshot;
+pub mod spend;
 pub mod student;
+pub mod student_memory;
 pub mod teacher;
+pub mod usage;
 pub mod utils;
+pub mod workspace;
@@ -0,0 +1,175 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+pub const DEFAULT_LIMIT: usize = 50;
+pub const MAX_LIMIT: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Cursor, limit and sort order of a list endpoint, taken from the query string next to its filters
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PageQuery {
+    /// `next_cursor` of the previous page, the first page if `None`
+    pub cursor: Option<String>,
+    /// items per page, [`DEFAULT_LIMIT`] by default and at most [`MAX_LIMIT`]
+    pub limit: Option<usize>,
+    /// order of the sort key of the endpoint, each endpoint has its own default
+    pub sort: Option<SortOrder>,
+    /// items to skip after the cursor, to jump to a page
+    pub offset: Option<usize>,
+}
+
+/// A page of a list endpoint
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    /// pass as `cursor` to get the next page, no more items if `None`
+    pub next_cursor: Option<String>,
+    /// items of the list across all pages, for lists loaded whole
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+}
+
+impl PageQuery {
+    pub fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    pub fn order(&self, default: SortOrder) -> SortOrder {
+        self.sort.unwrap_or(default)
+    }
+
+    /// the cursor as the sort key of the endpoint
+    pub fn cursor<K: FromStr>(&self) -> anyhow::Result<Option<K>> {
+        self.cursor
+            .as_deref()
+            .map(|cursor| {
+                cursor
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid cursor: {cursor}"))
+            })
+            .transpose()
+    }
+
+    /// sort `items` by `key`, then take the page after the cursor
+    pub fn paginate<T, K>(
+        &self,
+        mut items: Vec<T>,
+        default: SortOrder,
+        key: impl Fn(&T) -> K,
+    ) -> anyhow::Result<Paginated<T>>
+    where
+        K: Ord + Display + FromStr,
+    {
+        let order = self.order(default);
+        items.sort_by_key(&key);
+        if order == SortOrder::Desc {
+            items.reverse();
+        }
+        let total = items.len();
+        if let Some(cursor) = self.cursor::<K>()? {
+            items.retain(|item| match order {
+                SortOrder::Asc => key(item) > cursor,
+                SortOrder::Desc => key(item) < cursor,
+            });
+        }
+        items.drain(..self.offset.unwrap_or_default().min(items.len()));
+        Ok(Paginated {
+            total: Some(total),
+            ..Paginated::from_overfetched(items, self.limit(), key)
+        })
+    }
+
+    /// [`paginate`](Self::paginate) keyed by the position of the items, for lists without ids
+    pub fn paginate_positions<T>(
+        &self,
+        items: Vec<T>,
+        default: SortOrder,
+    ) -> anyhow::Result<Paginated<T>> {
+        let page = self.paginate(
+            items.into_iter().enumerate().collect(),
+            default,
+            |(position, _)| *position,
+        )?;
+        Ok(Paginated {
+            items: page.items.into_iter().map(|(_, item)| item).collect(),
+            next_cursor: page.next_cursor,
+            total: page.total,
+        })
+    }
+}
+
+impl<T> Paginated<T> {
+    /// the first `limit` of `items`, which are in page order and may hold one more to tell if a next page exists
+    pub fn from_overfetched<K: Display>(
+        mut items: Vec<T>,
+        limit: usize,
+        key: impl Fn(&T) -> K,
+    ) -> Self {
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(|item| key(item).to_string())
+        } else {
+            None
+        };
+        Self {
+            items,
+            next_cursor,
+            total: None,
+        }
+    }
+}
+
+#[test]
+fn pages() {
+    let ids: Vec<i64> = (1..=7).collect();
+    let mut query = PageQuery {
+        limit: Some(3),
+        ..Default::default()
+    };
+    let first = query
+        .paginate(ids.clone(), SortOrder::Asc, |id| *id)
+        .unwrap();
+    assert_eq!(first.items, [1, 2, 3]);
+    assert_eq!(first.next_cursor.as_deref(), Some("3"));
+    assert_eq!(first.total, Some(7));
+
+    query.offset = Some(3);
+    let skipped = query
+        .paginate(ids.clone(), SortOrder::Asc, |id| *id)
+        .unwrap();
+    assert_eq!(skipped.items, [4, 5, 6]);
+    query.offset = None;
+
+    query.cursor = Some("6".to_string());
+    let last = query
+        .paginate(ids.clone(), SortOrder::Asc, |id| *id)
+        .unwrap();
+    assert_eq!(last.items, [7]);
+    assert_eq!(last.next_cursor, None);
+
+    query.sort = Some(SortOrder::Desc);
+    let desc = query.paginate(ids, SortOrder::Asc, |id| *id).unwrap();
+    assert_eq!(desc.items, [5, 4, 3]);
+
+    query.cursor = Some("x".to_string());
+    assert!(query.paginate(vec![1], SortOrder::Asc, |id| *id).is_err());
+
+    let query = PageQuery {
+        limit: Some(2),
+        cursor: Some("1".to_string()),
+        ..Default::default()
+    };
+    let page = query
+        .paginate_positions(vec!["a", "b", "c", "d"], SortOrder::Asc)
+        .unwrap();
+    assert_eq!(page.items, ["c", "d"]);
+    assert_eq!(page.next_cursor, None);
+}
@@ -0,0 +1,574 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::{Date, Duration, OffsetDateTime};
+use utoipa::ToSchema;
+
+use crate::{
+    ai_utils::{self, Provider},
+    books::{book::Book, chapter::ChapterNumber},
+    i18n,
+    utils::now_local,
+};
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+/// longest artifact a student may submit, in characters
+pub const MAX_ARTIFACT_CHARS: usize = 50_000;
+const MAX_MILESTONES: usize = 20;
+/// length of the final report, in words
+const REPORT_LENGTH: usize = 500;
+
+/// A step of a project, due some days after the student starts it
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MilestoneSpec {
+    pub title: String,
+    /// what the student hands in, the teacher checks the artifacts against it
+    pub description: String,
+    /// days from the start of the project to the due date
+    pub due_days: u32,
+    /// the chapters the milestone practices
+    #[serde(default)]
+    #[schema(value_type = Vec<String>)]
+    pub chapters: Vec<ChapterNumber>,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct ProjectRequest {
+    pub title: String,
+    pub description: String,
+    /// in order, with non-decreasing due days
+    pub milestones: Vec<MilestoneSpec>,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct StartProjectRequest {
+    pub project_id: i64,
+    /// the due dates of the milestones count from it, today by default
+    #[serde(default, with = "iso_date::option")]
+    #[schema(value_type = Option<String>, format = Date)]
+    pub start_date: Option<Date>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Milestone {
+    pub id: i64,
+    /// the place of the milestone in the project, from 1
+    pub position: u32,
+    #[serde(flatten)]
+    pub spec: MilestoneSpec,
+}
+
+/// A multi-week project attached to a book
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Project {
+    pub id: i64,
+    pub book_id: i64,
+    pub title: String,
+    pub description: String,
+    pub milestones: Vec<Milestone>,
+}
+
+/// The check-in of the teacher on an artifact
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, ToSchema)]
+pub struct CheckIn {
+    /// Feedback to the student: what works, what is missing, and the next step
+    pub feedback: String,
+    /// Whether the artifact does everything the milestone asks for
+    pub meets_milestone: bool,
+}
+
+/// An artifact the student submitted for a milestone
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Submission {
+    pub id: i64,
+    pub milestone_id: i64,
+    pub content: String,
+    pub check_in: CheckIn,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String)]
+    pub create_time: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MilestoneProgress {
+    pub milestone_id: i64,
+    #[serde(with = "iso_date")]
+    #[schema(value_type = String, format = Date)]
+    pub due_date: Date,
+    /// an artifact met the milestone
+    pub met: bool,
+    /// oldest first
+    pub submissions: Vec<Submission>,
+}
+
+/// A project a student started
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StudentProject {
+    pub id: i64,
+    pub project: Project,
+    #[serde(with = "iso_date")]
+    #[schema(value_type = String, format = Date)]
+    pub start_date: Date,
+    /// one per milestone of the project, in order
+    pub progress: Vec<MilestoneProgress>,
+    /// the final report of the teacher, once every milestone is met
+    pub report: Option<String>,
+}
+
+impl StudentProject {
+    /// the first milestone not met yet, `None` once the project is done
+    pub fn next_milestone(&self) -> Option<(&Milestone, &MilestoneProgress)> {
+        self.project
+            .milestones
+            .iter()
+            .zip(&self.progress)
+            .find(|(_, progress)| !progress.met)
+    }
+}
+
+impl ProjectRequest {
+    fn validate(&self, book: &Book) -> anyhow::Result<()> {
+        if self.title.trim().is_empty() {
+            anyhow::bail!("Project title must not be empty");
+        }
+        if self.milestones.is_empty() || self.milestones.len() > MAX_MILESTONES {
+            anyhow::bail!("A project has between 1 and {} milestones", MAX_MILESTONES);
+        }
+        for milestone in &self.milestones {
+            if milestone.title.trim().is_empty() {
+                anyhow::bail!("Milestone title must not be empty");
+            }
+            for number in &milestone.chapters {
+                if !book.chapters.contains_key(number) {
+                    anyhow::bail!("Chapter not found: {}", number);
+                }
+            }
+        }
+        if self
+            .milestones
+            .windows(2)
+            .any(|w| w[1].due_days < w[0].due_days)
+        {
+            anyhow::bail!("Milestones must be in due order");
+        }
+        Ok(())
+    }
+}
+
+pub async fn create_project(
+    database: &SqlitePool,
+    book: &Book,
+    request: &ProjectRequest,
+) -> anyhow::Result<i64> {
+    request.validate(book)?;
+    let title = request.title.trim();
+    let mut tx = database.begin().await?;
+    let id = sqlx::query_scalar!(
+        "insert into project (book_id, title, description) values (?, ?, ?) returning id",
+        book.id,
+        title,
+        request.description
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    for (i, milestone) in request.milestones.iter().enumerate() {
+        let position = i as i64 + 1;
+        let title = milestone.title.trim();
+        let chapters = join_chapters(&milestone.chapters);
+        sqlx::query!(
+            "insert into project_milestone (project_id, position, title, description, due_days, chapters)
+            values (?, ?, ?, ?, ?, ?)",
+            id,
+            position,
+            title,
+            milestone.description,
+            milestone.due_days,
+            chapters
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(id)
+}
+
+fn join_chapters(chapters: &[ChapterNumber]) -> String {
+    chapters
+        .iter()
+        .map(ChapterNumber::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+async fn milestones(database: &SqlitePool, project_id: i64) -> anyhow::Result<Vec<Milestone>> {
+    let records = sqlx::query!(
+        "select id, position, title, description, due_days, chapters
+        from project_milestone where project_id = ? order by position",
+        project_id
+    )
+    .fetch_all(database)
+    .await?;
+    records
+        .into_iter()
+        .map(|record| {
+            let chapters = record
+                .chapters
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_, _>>()?;
+            Ok(Milestone {
+                id: record.id,
+                position: record.position as u32,
+                spec: MilestoneSpec {
+                    title: record.title,
+                    description: record.description,
+                    due_days: record.due_days as u32,
+                    chapters,
+                },
+            })
+        })
+        .collect()
+}
+
+pub async fn get_project(database: &SqlitePool, id: i64) -> anyhow::Result<Project> {
+    let record = sqlx::query!(
+        "select book_id, title, description from project where id = ?",
+        id
+    )
+    .fetch_optional(database)
+    .await?
+    .ok_or(anyhow::anyhow!("Project not found: {}", id))?;
+    Ok(Project {
+        id,
+        book_id: record.book_id,
+        title: record.title,
+        description: record.description,
+        milestones: milestones(database, id).await?,
+    })
+}
+
+pub async fn list_projects(database: &SqlitePool, book_id: i64) -> anyhow::Result<Vec<Project>> {
+    let ids = sqlx::query_scalar!(
+        "select id from project where book_id = ? order by id",
+        book_id
+    )
+    .fetch_all(database)
+    .await?;
+    let mut projects = Vec::with_capacity(ids.len());
+    for id in ids {
+        projects.push(get_project(database, id).await?);
+    }
+    Ok(projects)
+}
+
+/// delete the project with the work of its students
+pub async fn delete_project(database: &SqlitePool, id: i64) -> anyhow::Result<()> {
+    let result = sqlx::query!("delete from project where id = ?", id)
+        .execute(database)
+        .await?;
+    if result.rows_affected() == 0 {
+        anyhow::bail!("Project not found: {}", id);
+    }
+    Ok(())
+}
+
+/// start the project for the student, its due dates count from `start_date`, today by default
+pub async fn start_project(
+    database: &SqlitePool,
+    student_id: i64,
+    project_id: i64,
+    start_date: Option<Date>,
+) -> anyhow::Result<StudentProject> {
+    let start_date = start_date.unwrap_or(now_local().date());
+    let result = sqlx::query!(
+        "insert or ignore into student_project (student_id, project_id, start_date) values (?, ?, ?)",
+        student_id,
+        project_id,
+        start_date
+    )
+    .execute(database)
+    .await?;
+    if result.rows_affected() == 0 {
+        anyhow::bail!("Project {} is already started", project_id);
+    }
+    get_student_project(database, student_id, project_id).await
+}
+
+pub async fn get_student_project(
+    database: &SqlitePool,
+    student_id: i64,
+    project_id: i64,
+) -> anyhow::Result<StudentProject> {
+    let record = sqlx::query!(
+        r#"select id, start_date as "start_date: Date", report
+        from student_project where student_id = ? and project_id = ?"#,
+        student_id,
+        project_id
+    )
+    .fetch_optional(database)
+    .await?
+    .ok_or(anyhow::anyhow!("Project {} is not started", project_id))?;
+    let project = get_project(database, project_id).await?;
+    let submissions = sqlx::query!(
+        r#"select id, milestone_id, content, feedback, meets_milestone,
+        create_time as "create_time: OffsetDateTime"
+        from project_submission where student_project_id = ? order by id"#,
+        record.id
+    )
+    .fetch_all(database)
+    .await?;
+    let progress = project
+        .milestones
+        .iter()
+        .map(|milestone| {
+            let submissions: Vec<Submission> = submissions
+                .iter()
+                .filter(|submission| submission.milestone_id == milestone.id)
+                .map(|submission| Submission {
+                    id: submission.id,
+                    milestone_id: submission.milestone_id,
+                    content: submission.content.clone(),
+                    check_in: CheckIn {
+                        feedback: submission.feedback.clone(),
+                        meets_milestone: submission.meets_milestone,
+                    },
+                    create_time: submission.create_time,
+                })
+                .collect();
+            MilestoneProgress {
+                milestone_id: milestone.id,
+                due_date: record.start_date + Duration::days(milestone.spec.due_days as i64),
+                met: submissions
+                    .iter()
+                    .any(|submission| submission.check_in.meets_milestone),
+                submissions,
+            }
+        })
+        .collect();
+    Ok(StudentProject {
+        id: record.id,
+        project,
+        start_date: record.start_date,
+        progress,
+        report: record.report,
+    })
+}
+
+/// the projects the student started on the book and hasn't got a report for
+pub async fn active_projects(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+) -> anyhow::Result<Vec<StudentProject>> {
+    let ids = sqlx::query_scalar!(
+        "select project_id from student_project
+        join project on project.id = student_project.project_id
+        where student_id = ? and book_id = ? and report is null
+        order by student_project.id",
+        student_id,
+        book_id
+    )
+    .fetch_all(database)
+    .await?;
+    let mut projects = Vec::with_capacity(ids.len());
+    for id in ids {
+        projects.push(get_student_project(database, student_id, id).await?);
+    }
+    Ok(projects)
+}
+
+/// submit an artifact for a milestone of a started project, checked in by the teacher
+pub async fn submit(
+    database: &SqlitePool,
+    provider: &dyn Provider,
+    book: &Book,
+    student_id: i64,
+    project_id: i64,
+    milestone_id: i64,
+    content: &str,
+) -> anyhow::Result<Submission> {
+    if content.trim().is_empty() {
+        anyhow::bail!("The artifact must not be empty");
+    }
+    if content.chars().count() > MAX_ARTIFACT_CHARS {
+        anyhow::bail!(
+            "An artifact is limited to {} characters",
+            MAX_ARTIFACT_CHARS
+        );
+    }
+    let student_project = get_student_project(database, student_id, project_id).await?;
+    if student_project.report.is_some() {
+        anyhow::bail!("Project {} is finished", project_id);
+    }
+    let project = &student_project.project;
+    let (milestone, progress) = project
+        .milestones
+        .iter()
+        .zip(&student_project.progress)
+        .find(|(milestone, _)| milestone.id == milestone_id)
+        .ok_or(anyhow::anyhow!("Milestone not found: {}", milestone_id))?;
+    let chapters: Vec<String> = milestone
+        .spec
+        .chapters
+        .iter()
+        .filter_map(|number| book.chapters.get(number))
+        .map(|chapter| format!("{} {}", chapter.number, chapter.name))
+        .collect();
+    let earlier: Vec<String> = progress
+        .submissions
+        .iter()
+        .map(|submission| format!("- {}", submission.check_in.feedback))
+        .collect();
+    let prompt = format!(
+        "You are the teacher of the book \"{}\" checking in on the project of a student. \
+        Decide whether the artifact does everything the milestone asks for, \
+        and give short, encouraging feedback with the next step.\n\n\
+        # Project: {}\n{}\n\n# Milestone {}: {}\n{}\n\nChapters practiced: {}\n\n\
+        # Feedback on earlier artifacts\n{}\n\n# Artifact\n{}",
+        book.title,
+        project.title,
+        project.description,
+        milestone.position,
+        milestone.spec.title,
+        milestone.spec.description,
+        chapters.join(", "),
+        earlier.join("\n"),
+        content
+    );
+    let check_in: CheckIn = ai_utils::extract(provider, prompt).await?;
+    let id = sqlx::query_scalar!(
+        "insert into project_submission
+        (student_project_id, milestone_id, content, feedback, meets_milestone)
+        values (?, ?, ?, ?, ?) returning id",
+        student_project.id,
+        milestone_id,
+        content,
+        check_in.feedback,
+        check_in.meets_milestone
+    )
+    .fetch_one(database)
+    .await?;
+    Ok(Submission {
+        id,
+        milestone_id,
+        content: content.to_string(),
+        check_in,
+        create_time: now_local(),
+    })
+}
+
+/// write the final report on a project whose milestones are all met
+pub async fn generate_report(
+    database: &SqlitePool,
+    provider: &dyn Provider,
+    student_id: i64,
+    project_id: i64,
+) -> anyhow::Result<StudentProject> {
+    let mut student_project = get_student_project(database, student_id, project_id).await?;
+    if let Some((milestone, _)) = student_project.next_milestone() {
+        anyhow::bail!(
+            "Milestone {} ({}) is not met yet",
+            milestone.position,
+            milestone.spec.title
+        );
+    }
+    let project = &student_project.project;
+    let mut content = format!("# Project: {}\n{}\n", project.title, project.description);
+    for (milestone, progress) in project.milestones.iter().zip(&student_project.progress) {
+        content.push_str(&format!(
+            "\n## Milestone {}: {} (due {})\n{}\n",
+            milestone.position, milestone.spec.title, progress.due_date, milestone.spec.description
+        ));
+        for submission in &progress.submissions {
+            content.push_str(&format!(
+                "\n### Artifact of {}\n{}\n\nFeedback: {}\n",
+                submission.create_time.date(),
+                submission.content,
+                submission.check_in.feedback
+            ));
+        }
+    }
+    let prompt = "Write the final report on the project of a student for the student and their \
+        teachers, from the milestones, the artifacts and the feedback on them. Cover what was \
+        built, how the work progressed across the milestones, the strengths shown, and what to \
+        practice next. Write it in Markdown."
+        .to_string();
+    let report = ai_utils::summarize(provider, &content, REPORT_LENGTH, Some(prompt)).await?;
+    sqlx::query!(
+        "update student_project set report = ?, report_time = CURRENT_TIMESTAMP where id = ?",
+        report,
+        student_project.id
+    )
+    .execute(database)
+    .await?;
+    student_project.report = Some(report);
+    Ok(student_project)
+}
+
+/// the next milestone of each active project for the system prompt of the teacher, `None`
+/// without any
+pub fn projects_prompt(locale: &str, projects: &[StudentProject]) -> Option<String> {
+    let lines: Vec<String> = projects
+        .iter()
+        .filter_map(|student_project| {
+            let (milestone, progress) = student_project.next_milestone()?;
+            Some(format!(
+                "- {} / {} {} ({}): {}",
+                student_project.project.title,
+                milestone.position,
+                milestone.spec.title,
+                progress.due_date,
+                milestone.spec.description
+            ))
+        })
+        .collect();
+    if lines.is_empty() {
+        return None;
+    }
+    Some(i18n::tr(
+        locale,
+        "teacher-project-milestones",
+        &[("milestones", lines.join("\n").into())],
+    ))
+}
+
+#[test]
+fn next_milestone() {
+    let milestone = |id: i64, due_days: u32| Milestone {
+        id,
+        position: id as u32,
+        spec: MilestoneSpec {
+            title: format!("Step {id}"),
+            description: String::new(),
+            due_days,
+            chapters: vec![],
+        },
+    };
+    let start_date = Date::from_calendar_date(2026, time::Month::March, 2).unwrap();
+    let progress = |id: i64, due_days: u32, met: bool| MilestoneProgress {
+        milestone_id: id,
+        due_date: start_date + Duration::days(due_days as i64),
+        met,
+        submissions: vec![],
+    };
+    let mut student_project = StudentProject {
+        id: 1,
+        project: Project {
+            id: 1,
+            book_id: 1,
+            title: "Parser".to_string(),
+            description: String::new(),
+            milestones: vec![milestone(1, 7), milestone(2, 14)],
+        },
+        start_date,
+        progress: vec![progress(1, 7, true), progress(2, 14, false)],
+        report: None,
+    };
+    let (next, progress) = student_project.next_milestone().unwrap();
+    assert_eq!(next.id, 2);
+    assert_eq!(progress.due_date.to_string(), "2026-03-16");
+    student_project.progress[1].met = true;
+    assert!(student_project.next_milestone().is_none());
+    assert!(projects_prompt("en", &[student_project]).is_none());
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use minijinja::{Environment, UndefinedBehavior, Value};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+/// The prompts admins can tune, each a minijinja template
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum PromptName {
+    /// the plan of a book, generated from the summaries of its chapters
+    TeachingPlan,
+    /// the plan of a chapter, generated from its content
+    ChapterPlan,
+    /// the review scoring a chapter plan
+    PlanCritique,
+    /// the system prompt of the teacher, the localized built-in one unless set
+    TeacherInstruction,
+}
+
+impl PromptName {
+    pub const ALL: [Self; 4] = [
+        Self::TeachingPlan,
+        Self::ChapterPlan,
+        Self::PlanCritique,
+        Self::TeacherInstruction,
+    ];
+
+    /// the variables the template of the prompt can use
+    pub fn variables(self) -> &'static [&'static str] {
+        match self {
+            Self::TeachingPlan => &["book_title", "book_description", "chapter_list"],
+            Self::ChapterPlan => &["book_title", "chapter_number", "chapter_title"],
+            Self::PlanCritique => &[
+                "book_title",
+                "chapter_number",
+                "chapter_title",
+                "chapter",
+                "plan",
+            ],
+            Self::TeacherInstruction => &[
+                "student_name",
+                "student_profile",
+                "book_title",
+                "chapter_list",
+                "locale",
+            ],
+        }
+    }
+
+    /// the template shipped in `prompts/`
+    pub fn builtin(self) -> Option<&'static str> {
+        match self {
+            Self::TeachingPlan => Some(include_str!("../prompts/teaching_plan.j2")),
+            Self::ChapterPlan => Some(include_str!("../prompts/chapter_plan.j2")),
+            Self::PlanCritique => Some(include_str!("../prompts/plan_critique.j2")),
+            Self::TeacherInstruction => None,
+        }
+    }
+}
+
+/// A prompt with its built-in template and the one set by an admin
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PromptTemplate {
+    pub name: PromptName,
+    pub variables: Vec<String>,
+    pub builtin: Option<String>,
+    /// the template used over the built-in one, if set
+    pub custom: Option<String>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    #[schema(value_type = Option<String>)]
+    pub update_time: Option<OffsetDateTime>,
+}
+
+/// The templates set by admins, read when a book is loaded or a session starts so edits apply
+/// without a restart
+#[derive(Debug, Clone, Default)]
+pub struct PromptTemplates {
+    custom: HashMap<PromptName, String>,
+}
+
+impl PromptTemplates {
+    pub async fn load(database: &SqlitePool) -> anyhow::Result<Self> {
+        let records =
+            sqlx::query!(r#"select name as "name: PromptName", source from prompt_template"#)
+                .fetch_all(database)
+                .await?;
+        let custom = records
+            .into_iter()
+            .map(|record| (record.name, record.source))
+            .collect();
+        Ok(Self { custom })
+    }
+
+    /// the prompt rendered with `context`, from the custom template or else the built-in one,
+    /// `None` without either
+    pub fn render(&self, name: PromptName, context: Value) -> anyhow::Result<Option<String>> {
+        let source = match self.custom.get(&name) {
+            Some(source) => source.as_str(),
+            None => match name.builtin() {
+                Some(source) => source,
+                None => return Ok(None),
+            },
+        };
+        Ok(Some(render(source, context)?))
+    }
+}
+
+fn render(source: &str, context: Value) -> anyhow::Result<String> {
+    let mut env = Environment::new();
+    // a misspelled variable fails instead of rendering as nothing
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    Ok(env.render_str(source, context)?)
+}
+
+/// check that `source` renders with the variables of the prompt, and only those
+pub fn check(name: PromptName, source: &str) -> anyhow::Result<()> {
+    let sample: HashMap<&str, &str> = name
+        .variables()
+        .iter()
+        .map(|variable| (*variable, ""))
+        .collect();
+    render(source, Value::from_serialize(&sample))
+        .map_err(|e| anyhow::anyhow!("Invalid template for {:?}: {}", name, e))?;
+    Ok(())
+}
+
+pub async fn list_templates(database: &SqlitePool) -> anyhow::Result<Vec<PromptTemplate>> {
+    let records = sqlx::query!(
+        r#"select name as "name: PromptName", source, update_time as "update_time: OffsetDateTime"
+        from prompt_template"#
+    )
+    .fetch_all(database)
+    .await?;
+    Ok(PromptName::ALL
+        .into_iter()
+        .map(|name| {
+            let record = records.iter().find(|record| record.name == name);
+            PromptTemplate {
+                name,
+                variables: name.variables().iter().map(|v| v.to_string()).collect(),
+                builtin: name.builtin().map(str::to_string),
+                custom: record.map(|record| record.source.clone()),
+                update_time: record.map(|record| record.update_time),
+            }
+        })
+        .collect())
+}
+
+/// set the template of the prompt, replacing the built-in one
+pub async fn set_template(
+    database: &SqlitePool,
+    name: PromptName,
+    source: &str,
+) -> anyhow::Result<()> {
+    check(name, source)?;
+    sqlx::query!(
+        "insert into prompt_template (name, source) values (?, ?)
+        on conflict (name) do update set source = excluded.source, update_time = CURRENT_TIMESTAMP",
+        name,
+        source
+    )
+    .execute(database)
+    .await?;
+    Ok(())
+}
+
+/// go back to the built-in template of the prompt
+pub async fn reset_template(database: &SqlitePool, name: PromptName) -> anyhow::Result<()> {
+    sqlx::query!("delete from prompt_template where name = ?", name)
+        .execute(database)
+        .await?;
+    Ok(())
+}
+
+#[test]
+fn templates() {
+    for name in PromptName::ALL {
+        if let Some(builtin) = name.builtin() {
+            assert!(check(name, builtin).is_ok(), "{name:?}");
+        }
+    }
+    let prompts = PromptTemplates {
+        custom: HashMap::from([(
+            PromptName::ChapterPlan,
+            "Plan {{ chapter_number }} {{ chapter_title }} of {{ book_title }}.".to_string(),
+        )]),
+    };
+    let context = minijinja::context! {
+        book_title => "Grammar",
+        chapter_number => "1.2.",
+        chapter_title => "Verbs",
+    };
+    assert_eq!(
+        prompts.render(PromptName::ChapterPlan, context).unwrap(),
+        Some("Plan 1.2. Verbs of Grammar.".to_string())
+    );
+    assert_eq!(
+        prompts
+            .render(PromptName::TeacherInstruction, Value::UNDEFINED)
+            .unwrap(),
+        None
+    );
+    assert!(check(PromptName::ChapterPlan, "{{ student_name }}").is_err());
+    assert!(check(PromptName::ChapterPlan, "{% if %}").is_err());
+}
@@ -0,0 +1,338 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+use crate::{
+    ai_utils::{self, Provider},
+    books::{book::Book, chapter::ChapterNumber},
+    scripting::{self, HookEvent},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QuestionKind {
+    MultipleChoice,
+    ShortAnswer,
+}
+
+/// A quiz question on the chapter
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, ToSchema)]
+pub struct QuizQuestion {
+    /// The question, answerable from the chapter alone
+    pub question: String,
+    pub kind: QuestionKind,
+    /// The options of a multiple choice question, empty for a short answer question
+    #[serde(default)]
+    pub options: Vec<String>,
+    /// The correct option, copied exactly, or a model answer for a short answer question
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub answer: String,
+}
+
+impl QuizQuestion {
+    /// whether a multiple choice answer picks the correct option, by its text or its letter
+    pub fn is_correct_choice(&self, answer: &str) -> bool {
+        let answer = answer.trim();
+        if answer.eq_ignore_ascii_case(self.answer.trim()) {
+            return true;
+        }
+        let mut letter = answer.chars();
+        let (Some(letter), None) = (letter.next(), letter.next()) else {
+            return false;
+        };
+        (letter.to_ascii_uppercase() as u32)
+            .checked_sub('A' as u32)
+            .and_then(|index| self.options.get(index as usize))
+            .is_some_and(|option| option.trim().eq_ignore_ascii_case(self.answer.trim()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Quiz {
+    pub id: i64,
+    pub book_id: i64,
+    pub chapter_number: ChapterNumber,
+    pub questions: Vec<QuizQuestion>,
+}
+
+impl Quiz {
+    /// the quiz as handed to the student, with the answers removed
+    pub fn without_answers(mut self) -> Self {
+        for question in &mut self.questions {
+            question.answer.clear();
+        }
+        self
+    }
+}
+
+/// The chapter to quiz the student on
+#[derive(Debug, Clone, Deserialize, JsonSchema, ToSchema)]
+pub struct QuizRequest {
+    /// The chapter number, e.g. "3.", "4.2."
+    pub chapter_number: ChapterNumber,
+    /// How many questions to ask, 5 if not given
+    pub questions: Option<usize>,
+}
+
+impl QuizRequest {
+    pub fn questions(&self) -> usize {
+        self.questions.unwrap_or(5).clamp(1, 20)
+    }
+}
+
+/// The answers of the student to a quiz
+#[derive(Debug, Clone, Deserialize, JsonSchema, ToSchema)]
+pub struct QuizAnswers {
+    /// The id of the quiz
+    pub quiz_id: i64,
+    /// One answer per question in order, the option text or letter for a multiple choice question
+    pub answers: Vec<String>,
+}
+
+/// The grade of one answer
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, ToSchema)]
+pub struct AnswerResult {
+    pub correct: bool,
+    /// What was right or missing, addressed to the student
+    pub feedback: String,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct QuizResult {
+    pub submission_id: i64,
+    pub quiz_id: i64,
+    /// share of correct answers, from 0 to 1
+    pub score: f64,
+    pub results: Vec<AnswerResult>,
+}
+
+/// The best and latest score of a student on a quiz
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct QuizScore {
+    pub quiz_id: i64,
+    pub chapter_number: ChapterNumber,
+    pub attempts: i64,
+    pub best_score: f64,
+    pub last_score: f64,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String)]
+    pub last_submit_time: OffsetDateTime,
+}
+
+/// generate a quiz of `questions` questions from a chapter and store it
+pub async fn generate(
+    database: &SqlitePool,
+    provider: &dyn Provider,
+    book: &Book,
+    chapter_number: &ChapterNumber,
+    questions: usize,
+) -> anyhow::Result<Quiz> {
+    /// The questions of the quiz
+    #[derive(Debug, JsonSchema, Deserialize)]
+    struct QuizQuestions {
+        questions: Vec<QuizQuestion>,
+    }
+    let chapter = book
+        .chapters
+        .get(chapter_number)
+        .ok_or(anyhow::anyhow!("Chapter not found: {}", chapter_number))?;
+    let prompt = format!(
+        "Write a quiz of {questions} questions that checks whether a student understood the following chapter. \
+        Mix multiple choice questions with four options and short answer questions. \
+        Every question must be answerable from the chapter alone.\n\n# Chapter {} {}\n{}",
+        chapter.number, chapter.name, chapter.content
+    );
+    let generated: QuizQuestions = ai_utils::extract(provider, prompt).await?;
+    let questions = generated.questions;
+    let number = chapter_number.to_string();
+    let json = serde_json::to_string(&questions)?;
+    let result = sqlx::query!(
+        "insert into quiz (book_id, chapter_number, questions) values (?, ?, ?)",
+        book.id,
+        number,
+        json
+    )
+    .execute(database)
+    .await?;
+    Ok(Quiz {
+        id: result.last_insert_rowid(),
+        book_id: book.id,
+        chapter_number: chapter_number.clone(),
+        questions,
+    })
+}
+
+pub async fn get_quiz(database: &SqlitePool, quiz_id: i64) -> anyhow::Result<Quiz> {
+    let record = sqlx::query!(
+        "select book_id, chapter_number, questions from quiz where id = ?",
+        quiz_id
+    )
+    .fetch_optional(database)
+    .await?
+    .ok_or(anyhow::anyhow!("Quiz not found: {}", quiz_id))?;
+    Ok(Quiz {
+        id: quiz_id,
+        book_id: record.book_id,
+        chapter_number: record.chapter_number.parse()?,
+        questions: serde_json::from_str(&record.questions)?,
+    })
+}
+
+/// grade the answers of a student, multiple choice locally and short answers with the model,
+/// and record the score
+pub async fn submit(
+    database: &SqlitePool,
+    provider: &dyn Provider,
+    quiz_id: i64,
+    student_id: i64,
+    answers: Vec<String>,
+) -> anyhow::Result<QuizResult> {
+    let quiz = get_quiz(database, quiz_id).await?;
+    if answers.len() != quiz.questions.len() {
+        anyhow::bail!(
+            "Expected {} answers, got {}",
+            quiz.questions.len(),
+            answers.len()
+        );
+    }
+    let mut results = Vec::with_capacity(answers.len());
+    let mut short_answers = Vec::new();
+    for (i, (question, answer)) in quiz.questions.iter().zip(&answers).enumerate() {
+        match question.kind {
+            QuestionKind::MultipleChoice => {
+                let correct = question.is_correct_choice(answer);
+                let feedback = if correct {
+                    String::new()
+                } else {
+                    format!("The correct answer is: {}", question.answer)
+                };
+                results.push(AnswerResult { correct, feedback });
+            }
+            QuestionKind::ShortAnswer => {
+                short_answers.push(i);
+                results.push(AnswerResult {
+                    correct: false,
+                    feedback: String::new(),
+                });
+            }
+        }
+    }
+    if !short_answers.is_empty() {
+        /// The grades of the answers, in the order they were given
+        #[derive(Debug, JsonSchema, Deserialize)]
+        struct Grades {
+            grades: Vec<AnswerResult>,
+        }
+        let numbered: String = short_answers
+            .iter()
+            .enumerate()
+            .map(|(n, &i)| {
+                let question = &quiz.questions[i];
+                format!(
+                    "## Answer {n}\nQuestion: {}\nModel answer: {}\nStudent answer: {}\n\n",
+                    question.question, question.answer, answers[i]
+                )
+            })
+            .collect();
+        let prompt = format!(
+            "Grade the following student answers against the model answers. \
+            An answer is correct if it covers the key idea of the model answer, wording does not matter. \
+            Give one grade per answer in the same order, with short feedback for the student.\n\n{numbered}"
+        );
+        let grades: Grades = ai_utils::extract(provider, prompt).await?;
+        for (grade, &i) in grades.grades.into_iter().zip(&short_answers) {
+            results[i] = grade;
+        }
+    }
+    let score =
+        results.iter().filter(|result| result.correct).count() as f64 / results.len() as f64;
+    let answers_json = serde_json::to_string(&answers)?;
+    let results_json = serde_json::to_string(&results)?;
+    let result = sqlx::query!(
+        "insert into quiz_submission (quiz_id, student_id, answers, results, score) values (?, ?, ?, ?, ?)",
+        quiz_id,
+        student_id,
+        answers_json,
+        results_json,
+        score
+    )
+    .execute(database)
+    .await?;
+    let event = HookEvent::QuizGraded {
+        chapter_number: quiz.chapter_number.clone(),
+        quiz_id,
+        score,
+    };
+    scripting::run_hook(database, student_id, quiz.book_id, event).await;
+    Ok(QuizResult {
+        submission_id: result.last_insert_rowid(),
+        quiz_id,
+        score,
+        results,
+    })
+}
+
+/// the scores of a student on the quizzes of a book, by chapter
+pub async fn scores(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+) -> anyhow::Result<Vec<QuizScore>> {
+    let records = sqlx::query!(
+        r#"select quiz.id as "quiz_id!: i64", quiz.chapter_number,
+        count(*) as "attempts!: i64",
+        max(quiz_submission.score) as "best_score!: f64",
+        (select score from quiz_submission latest where latest.quiz_id = quiz.id and latest.student_id = ?1
+            order by latest.create_time desc, latest.id desc limit 1) as "last_score!: f64",
+        max(quiz_submission.create_time) as "last_submit_time!: OffsetDateTime"
+        from quiz join quiz_submission on quiz_submission.quiz_id = quiz.id
+        where quiz_submission.student_id = ?1 and quiz.book_id = ?2
+        group by quiz.id order by quiz.chapter_number, quiz.id"#,
+        student_id,
+        book_id
+    )
+    .fetch_all(database)
+    .await?;
+    let mut scores = Vec::with_capacity(records.len());
+    for record in records {
+        scores.push(QuizScore {
+            quiz_id: record.quiz_id,
+            chapter_number: record.chapter_number.parse()?,
+            attempts: record.attempts,
+            best_score: record.best_score,
+            last_score: record.last_score,
+            last_submit_time: record.last_submit_time,
+        });
+    }
+    Ok(scores)
+}
+
+#[test]
+fn choice_by_text_or_letter() {
+    let question = QuizQuestion {
+        question: "Which keyword declares a mutable binding?".to_string(),
+        kind: QuestionKind::MultipleChoice,
+        options: vec![
+            "let".to_string(),
+            "let mut".to_string(),
+            "const".to_string(),
+            "static".to_string(),
+        ],
+        answer: "let mut".to_string(),
+    };
+    assert!(question.is_correct_choice(" Let Mut "));
+    assert!(question.is_correct_choice("b"));
+    assert!(!question.is_correct_choice("A"));
+    assert!(!question.is_correct_choice("const"));
+    assert!(!question.is_correct_choice("E"));
+    let quiz = Quiz {
+        id: 1,
+        book_id: 1,
+        chapter_number: "3.1.".parse().unwrap(),
+        questions: vec![question],
+    }
+    .without_answers();
+    assert!(quiz.questions[0].answer.is_empty());
+}
@@ -0,0 +1,270 @@
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::{Date, Duration, OffsetDateTime, Time};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::abuse::{ThrottleEvent, ThrottleReason};
+
+/// Daily limits of a student across all their books, `None` for no limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct QuotaLimits {
+    /// prompt and completion tokens of the teacher
+    pub daily_tokens: Option<i64>,
+    /// chat messages of the student
+    pub daily_messages: Option<i64>,
+}
+
+/// The quota of a student and their usage today, UTC
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StudentQuota {
+    pub student_id: i64,
+    pub limits: QuotaLimits,
+    /// the limits are the student's own rather than the defaults
+    pub custom: bool,
+    /// tokens counted against the limit, since midnight or the last reset
+    pub tokens_today: i64,
+    /// messages counted against the limit, since midnight or the last reset
+    pub messages_today: i64,
+}
+
+impl StudentQuota {
+    /// the limit the student reached, if any
+    pub fn exhausted(&self) -> Option<ThrottleReason> {
+        if self
+            .limits
+            .daily_tokens
+            .is_some_and(|limit| self.tokens_today >= limit)
+        {
+            Some(ThrottleReason::DailyTokens)
+        } else if self
+            .limits
+            .daily_messages
+            .is_some_and(|limit| self.messages_today >= limit)
+        {
+            Some(ThrottleReason::DailyMessages)
+        } else {
+            None
+        }
+    }
+}
+
+/// the limits of `agent_setting`, for students without their own
+pub async fn default_limits(database: &SqlitePool) -> anyhow::Result<QuotaLimits> {
+    let record = sqlx::query!("select daily_token_limit, daily_message_limit from agent_setting")
+        .fetch_one(database)
+        .await?;
+    Ok(QuotaLimits {
+        daily_tokens: record.daily_token_limit,
+        daily_messages: record.daily_message_limit,
+    })
+}
+
+pub async fn set_default_limits(database: &SqlitePool, limits: &QuotaLimits) -> anyhow::Result<()> {
+    validate(limits)?;
+    sqlx::query!(
+        "update agent_setting set daily_token_limit = ?, daily_message_limit = ?",
+        limits.daily_tokens,
+        limits.daily_messages
+    )
+    .execute(database)
+    .await?;
+    Ok(())
+}
+
+/// give the student limits of their own, `None` to go back to the defaults
+pub async fn set_student_limits(
+    database: &SqlitePool,
+    student_id: i64,
+    limits: Option<&QuotaLimits>,
+) -> anyhow::Result<()> {
+    if let Some(limits) = limits {
+        validate(limits)?;
+    }
+    let custom = limits.is_some();
+    let limits = limits.copied().unwrap_or_default();
+    sqlx::query!(
+        "insert into student_quota (student_id, custom, daily_token_limit, daily_message_limit)
+        values (?, ?, ?, ?)
+        on conflict (student_id) do update set custom = excluded.custom,
+        daily_token_limit = excluded.daily_token_limit,
+        daily_message_limit = excluded.daily_message_limit",
+        student_id,
+        custom,
+        limits.daily_tokens,
+        limits.daily_messages
+    )
+    .execute(database)
+    .await?;
+    Ok(())
+}
+
+fn validate(limits: &QuotaLimits) -> anyhow::Result<()> {
+    if limits.daily_tokens.is_some_and(|limit| limit < 0)
+        || limits.daily_messages.is_some_and(|limit| limit < 0)
+    {
+        anyhow::bail!("A quota limit can't be negative");
+    }
+    Ok(())
+}
+
+/// the quota of the student, with the defaults when they have no limits of their own
+pub async fn get(database: &SqlitePool, student_id: i64) -> anyhow::Result<StudentQuota> {
+    let today = OffsetDateTime::now_utc().date();
+    let used = sqlx::query!(
+        r#"select coalesce(sum(prompt_tokens + completion_tokens), 0) as "tokens!: i64",
+        coalesce(sum(messages), 0) as "messages!: i64"
+        from token_usage where student_id = ? and day = ?"#,
+        student_id,
+        today
+    )
+    .fetch_one(database)
+    .await?;
+    let quota = sqlx::query!(
+        r#"select custom, daily_token_limit, daily_message_limit, reset_day as "reset_day: Date",
+        reset_tokens, reset_messages
+        from student_quota where student_id = ?"#,
+        student_id
+    )
+    .fetch_optional(database)
+    .await?;
+    let (custom, limits, reset) = match quota {
+        Some(quota) => (
+            quota.custom,
+            QuotaLimits {
+                daily_tokens: quota.daily_token_limit,
+                daily_messages: quota.daily_message_limit,
+            },
+            (quota.reset_day == Some(today)).then_some((quota.reset_tokens, quota.reset_messages)),
+        ),
+        None => (false, QuotaLimits::default(), None),
+    };
+    let limits = if custom {
+        limits
+    } else {
+        default_limits(database).await?
+    };
+    let (reset_tokens, reset_messages) = reset.unwrap_or_default();
+    Ok(StudentQuota {
+        student_id,
+        limits,
+        custom,
+        tokens_today: (used.tokens - reset_tokens).max(0),
+        messages_today: (used.messages - reset_messages).max(0),
+    })
+}
+
+/// start the day over for the student, the usage so far no longer counts against the limits
+pub async fn reset(database: &SqlitePool, student_id: i64) -> anyhow::Result<()> {
+    let today = OffsetDateTime::now_utc().date();
+    sqlx::query!(
+        "insert into student_quota (student_id, reset_day, reset_tokens, reset_messages)
+        select ?1, ?2, coalesce(sum(prompt_tokens + completion_tokens), 0),
+        coalesce(sum(messages), 0)
+        from token_usage where student_id = ?1 and day = ?2
+        on conflict (student_id) do update set reset_day = excluded.reset_day,
+        reset_tokens = excluded.reset_tokens, reset_messages = excluded.reset_messages",
+        student_id,
+        today
+    )
+    .execute(database)
+    .await?;
+    Ok(())
+}
+
+/// refuse the student until midnight UTC once they reached a daily limit
+pub async fn check(
+    database: &SqlitePool,
+    student_id: i64,
+) -> anyhow::Result<Option<ThrottleEvent>> {
+    let quota = get(database, student_id).await?;
+    let Some(reason) = quota.exhausted() else {
+        return Ok(None);
+    };
+    warn!(
+        student_id,
+        ?reason,
+        tokens = quota.tokens_today,
+        messages = quota.messages_today,
+        "daily quota of student exhausted"
+    );
+    Ok(Some(ThrottleEvent::new(reason, until_midnight())))
+}
+
+/// seconds until the next UTC day, when the quotas start over
+fn until_midnight() -> u64 {
+    let now = OffsetDateTime::now_utc();
+    let midnight = (now.date() + Duration::days(1))
+        .with_time(Time::MIDNIGHT)
+        .assume_utc();
+    (midnight - now).whole_seconds().max(1) as u64
+}
+
+#[tokio::test]
+async fn daily_quota() {
+    use crate::{
+        books::library::{Library, LibraryConfig},
+        usage::{self, Usage},
+    };
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = LibraryConfig {
+        database: dir.path().join("book.db"),
+        bookbase: dir.path().join("bookbase"),
+        migrate: true,
+        ..Default::default()
+    };
+    let database = Library::open(&config).await.unwrap().database.clone();
+    sqlx::query!("insert into book (id, title, authors) values (1, 'Rust', '')")
+        .execute(&database)
+        .await
+        .unwrap();
+    sqlx::query!("insert into student (id, name, email, password) values (1, 'Ann', 'a@b.c', '')")
+        .execute(&database)
+        .await
+        .unwrap();
+    assert!(check(&database, 1).await.unwrap().is_none());
+
+    let limits = QuotaLimits {
+        daily_tokens: Some(1000),
+        daily_messages: Some(2),
+    };
+    set_default_limits(&database, &limits).await.unwrap();
+    usage::record_message(&database, 1, 1).await.unwrap();
+    usage::record(&database, 1, 1, Usage::estimated(900, 100))
+        .await
+        .unwrap();
+    let event = check(&database, 1).await.unwrap().unwrap();
+    assert_eq!(event.reason, ThrottleReason::DailyTokens);
+    assert!(event.retry_after <= 24 * 60 * 60);
+
+    // the student's own limits replace the defaults
+    let own = QuotaLimits {
+        daily_tokens: None,
+        daily_messages: Some(1),
+    };
+    set_student_limits(&database, 1, Some(&own)).await.unwrap();
+    let event = check(&database, 1).await.unwrap().unwrap();
+    assert_eq!(event.reason, ThrottleReason::DailyMessages);
+
+    reset(&database, 1).await.unwrap();
+    let quota = get(&database, 1).await.unwrap();
+    assert!(quota.custom);
+    assert_eq!((quota.tokens_today, quota.messages_today), (0, 0));
+    assert!(check(&database, 1).await.unwrap().is_none());
+
+    set_student_limits(&database, 1, None).await.unwrap();
+    let quota = get(&database, 1).await.unwrap();
+    assert_eq!(quota.limits, limits);
+    assert!(
+        set_default_limits(
+            &database,
+            &QuotaLimits {
+                daily_tokens: Some(-1),
+                daily_messages: None,
+            }
+        )
+        .await
+        .is_err()
+    );
+}
@@ -0,0 +1,100 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+/// the longest device id accepted, clients pick their own
+const MAX_DEVICE_ID_LENGTH: usize = 64;
+
+/// How far a device of the student has displayed a conversation
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DeviceReceipt {
+    pub device_id: String,
+    /// the conversation position after the last message displayed, like the id of a done event
+    pub position: i64,
+    /// when the device last reported
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String)]
+    pub update_time: OffsetDateTime,
+}
+
+fn check_device_id(device_id: &str) -> anyhow::Result<()> {
+    if device_id.is_empty() || device_id.chars().count() > MAX_DEVICE_ID_LENGTH {
+        anyhow::bail!("device id must have 1 to {MAX_DEVICE_ID_LENGTH} characters");
+    }
+    Ok(())
+}
+
+/// note that the device displayed the conversation up to `position`, a receipt never moves back
+pub async fn mark_seen(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+    device_id: &str,
+    position: i64,
+) -> anyhow::Result<DeviceReceipt> {
+    check_device_id(device_id)?;
+    if position < 0 {
+        anyhow::bail!("position must not be negative");
+    }
+    let now = OffsetDateTime::now_utc();
+    let receipt = sqlx::query_as!(
+        DeviceReceipt,
+        r#"insert into device_receipt (student_id, book_id, device_id, position, update_time)
+        values (?, ?, ?, ?, ?)
+        on conflict (student_id, book_id, device_id) do update
+        set position = max(position, excluded.position), update_time = excluded.update_time
+        returning device_id, position, update_time as "update_time: OffsetDateTime""#,
+        student_id,
+        book_id,
+        device_id,
+        position,
+        now
+    )
+    .fetch_one(database)
+    .await?;
+    Ok(receipt)
+}
+
+/// the position the device displayed the conversation up to, `None` for a new device
+pub async fn last_seen(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+    device_id: &str,
+) -> anyhow::Result<Option<i64>> {
+    let position = sqlx::query_scalar!(
+        "select position from device_receipt where student_id = ? and book_id = ? and device_id = ?",
+        student_id,
+        book_id,
+        device_id
+    )
+    .fetch_optional(database)
+    .await?;
+    Ok(position)
+}
+
+/// the receipts of every device of the student on the conversation, most recent first
+pub async fn list(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+) -> anyhow::Result<Vec<DeviceReceipt>> {
+    let receipts = sqlx::query_as!(
+        DeviceReceipt,
+        r#"select device_id, position, update_time as "update_time: OffsetDateTime"
+        from device_receipt where student_id = ? and book_id = ? order by update_time desc"#,
+        student_id,
+        book_id
+    )
+    .fetch_all(database)
+    .await?;
+    Ok(receipts)
+}
+
+#[test]
+fn device_ids() {
+    assert!(check_device_id("phone-1").is_ok());
+    assert!(check_device_id("").is_err());
+    assert!(check_device_id(&"x".repeat(65)).is_err());
+}
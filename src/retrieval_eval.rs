@@ -0,0 +1,294 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    path::Path,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::books::{chapter::ChapterNumber, library::Library, search::Fusion};
+
+/// A question with the chapter that answers it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EvalQuestion {
+    pub id: i64,
+    pub question: String,
+    pub gold_chapter: String,
+}
+
+/// A retrieval configuration to evaluate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Retriever {
+    /// full-text BM25 ranking
+    Keyword,
+    /// embedding similarity of the chunks
+    Semantic,
+    /// both rankings fused, see [`Library::hybrid_search`]
+    Hybrid(Fusion),
+}
+
+impl FromStr for Retriever {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.trim() {
+            "keyword" => Ok(Self::Keyword),
+            "semantic" => Ok(Self::Semantic),
+            "hybrid" => Ok(Self::Hybrid(Fusion::default())),
+            s => match s.strip_prefix("hybrid=") {
+                Some(fusion) => Ok(Self::Hybrid(fusion.parse()?)),
+                None => anyhow::bail!(
+                    "Unknown retriever {s}, use keyword, semantic, hybrid or hybrid=<fusion>"
+                ),
+            },
+        }
+    }
+}
+
+impl Display for Retriever {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Keyword => write!(f, "keyword"),
+            Self::Semantic => write!(f, "semantic"),
+            Self::Hybrid(fusion) => write!(f, "hybrid={fusion}"),
+        }
+    }
+}
+
+/// How well a retriever found the gold chapters of the questions of a book
+#[derive(Debug, Clone, Serialize)]
+pub struct EvalReport {
+    pub retriever: String,
+    pub questions: usize,
+    /// for each k, the share of questions with their gold chapter in the top k
+    pub recall: Vec<(usize, f64)>,
+    /// mean reciprocal rank of the gold chapter, 0 for a question when it wasn't retrieved
+    pub mrr: f64,
+    /// questions whose retrieval failed, counted as misses
+    pub errors: usize,
+}
+
+/// whether `chapter` is the gold chapter or one of its sections
+fn is_gold(gold: &ChapterNumber, chapter: &str) -> bool {
+    chapter
+        .parse::<ChapterNumber>()
+        .is_ok_and(|chapter| chapter == *gold || gold.is_ancestor_of(&chapter))
+}
+
+/// the chapters retrieved for the question, best first and each once
+async fn retrieve(
+    library: &Library,
+    book_id: i64,
+    retriever: Retriever,
+    question: &str,
+    limit: usize,
+) -> anyhow::Result<Vec<String>> {
+    let chapters: Vec<String> = match retriever {
+        Retriever::Keyword => library
+            .search_book(book_id, question, limit as i64)
+            .await?
+            .into_iter()
+            .map(|hit| hit.chapter_number)
+            .collect(),
+        // several chunks of a chapter may come first, fetched deeper to fill the limit
+        Retriever::Semantic => library
+            .semantic_search(book_id, question, limit * 4, None)
+            .await?
+            .into_iter()
+            .map(|hit| hit.chapter_number)
+            .collect(),
+        Retriever::Hybrid(fusion) => library
+            .hybrid_search(book_id, question, limit, fusion, None)
+            .await?
+            .into_iter()
+            .map(|hit| hit.chapter_number)
+            .collect(),
+    };
+    let mut unique = Vec::new();
+    for chapter in chapters {
+        if !unique.contains(&chapter) {
+            unique.push(chapter);
+        }
+    }
+    unique.truncate(limit);
+    Ok(unique)
+}
+
+/// the rank from 1 of the first gold chapter among the retrieved ones
+fn gold_rank(gold: &ChapterNumber, retrieved: &[String]) -> Option<usize> {
+    retrieved
+        .iter()
+        .position(|chapter| is_gold(gold, chapter))
+        .map(|i| i + 1)
+}
+
+fn report(
+    retriever: Retriever,
+    ranks: &[Option<usize>],
+    ks: &[usize],
+    errors: usize,
+) -> EvalReport {
+    let questions = ranks.len();
+    let share = |count: usize| {
+        if questions == 0 {
+            0.0
+        } else {
+            count as f64 / questions as f64
+        }
+    };
+    let recall = ks
+        .iter()
+        .map(|&k| {
+            let found = ranks
+                .iter()
+                .filter(|rank| rank.is_some_and(|rank| rank <= k));
+            (k, share(found.count()))
+        })
+        .collect();
+    let reciprocal: f64 = ranks.iter().flatten().map(|&rank| 1.0 / rank as f64).sum();
+    EvalReport {
+        retriever: retriever.to_string(),
+        questions,
+        recall,
+        mrr: if questions == 0 {
+            0.0
+        } else {
+            reciprocal / questions as f64
+        },
+        errors,
+    }
+}
+
+pub async fn add_question(
+    database: &SqlitePool,
+    book_id: i64,
+    question: &str,
+    gold_chapter: &str,
+) -> anyhow::Result<i64> {
+    let question = question.trim();
+    if question.is_empty() {
+        anyhow::bail!("question must not be empty");
+    }
+    let gold_chapter = gold_chapter.parse::<ChapterNumber>()?.to_string();
+    let id = sqlx::query_scalar!(
+        "insert into retrieval_eval_question (book_id, question, gold_chapter)
+        values (?, ?, ?) returning id",
+        book_id,
+        question,
+        gold_chapter
+    )
+    .fetch_one(database)
+    .await?;
+    Ok(id)
+}
+
+/// add the `question,gold_chapter` rows of a csv file, returns how many were added
+pub async fn import_questions(
+    database: &SqlitePool,
+    book_id: i64,
+    file: &Path,
+) -> anyhow::Result<usize> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_path(file)?;
+    let mut count = 0;
+    for record in reader.records() {
+        let record = record?;
+        let (Some(question), Some(gold_chapter)) = (record.get(0), record.get(1)) else {
+            anyhow::bail!("expected question,gold_chapter rows, got {:?}", record);
+        };
+        if question == "question" && gold_chapter == "gold_chapter" {
+            continue;
+        }
+        add_question(database, book_id, question, gold_chapter).await?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+pub async fn list_questions(
+    database: &SqlitePool,
+    book_id: i64,
+) -> anyhow::Result<Vec<EvalQuestion>> {
+    let questions = sqlx::query_as!(
+        EvalQuestion,
+        "select id, question, gold_chapter from retrieval_eval_question
+        where book_id = ? order by id",
+        book_id
+    )
+    .fetch_all(database)
+    .await?;
+    Ok(questions)
+}
+
+pub async fn remove_question(database: &SqlitePool, id: i64) -> anyhow::Result<()> {
+    let result = sqlx::query!("delete from retrieval_eval_question where id = ?", id)
+        .execute(database)
+        .await?;
+    if result.rows_affected() == 0 {
+        anyhow::bail!("Question not found: {}", id);
+    }
+    Ok(())
+}
+
+/// Run every retriever on the questions of the book and report recall@k for each of `ks`.
+///
+/// A failed retrieval counts as a miss, so one flaky embedding call doesn't stop the run.
+pub async fn evaluate(
+    library: &Library,
+    book_id: i64,
+    retrievers: &[Retriever],
+    ks: &[usize],
+) -> anyhow::Result<Vec<EvalReport>> {
+    let questions = list_questions(&library.database, book_id).await?;
+    if questions.is_empty() {
+        anyhow::bail!("Book {} has no evaluation questions", book_id);
+    }
+    let limit = ks.iter().copied().max().unwrap_or(10).max(1);
+    let mut reports = Vec::new();
+    for &retriever in retrievers {
+        let mut ranks = Vec::with_capacity(questions.len());
+        let mut errors = 0;
+        for question in &questions {
+            let gold = question.gold_chapter.parse::<ChapterNumber>()?;
+            match retrieve(library, book_id, retriever, &question.question, limit).await {
+                Ok(retrieved) => ranks.push(gold_rank(&gold, &retrieved)),
+                Err(e) => {
+                    tracing::warn!(
+                        "{} retrieval failed for question {}: {}",
+                        retriever,
+                        question.id,
+                        e
+                    );
+                    errors += 1;
+                    ranks.push(None);
+                }
+            }
+        }
+        reports.push(report(retriever, &ranks, ks, errors));
+    }
+    Ok(reports)
+}
+
+#[test]
+fn recall_at_k() {
+    let gold: ChapterNumber = "3.".parse().unwrap();
+    let retrieved = ["1.".to_string(), "3.2.".to_string(), "3.".to_string()];
+    assert_eq!(gold_rank(&gold, &retrieved), Some(2));
+    assert_eq!(gold_rank(&gold, &retrieved[..1]), None);
+    let report = report(
+        Retriever::Keyword,
+        &[Some(1), Some(3), None, Some(2)],
+        &[1, 3],
+        0,
+    );
+    assert_eq!(report.recall, vec![(1, 0.25), (3, 0.75)]);
+    assert!((report.mrr - (1.0 + 1.0 / 3.0 + 0.5) / 4.0).abs() < 1e-9);
+    assert_eq!(
+        "hybrid=weighted:0.3".parse::<Retriever>().unwrap(),
+        Retriever::Hybrid(Fusion::Weighted {
+            keyword_weight: 0.3
+        })
+    );
+}
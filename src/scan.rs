@@ -0,0 +1,173 @@
+use std::{fmt::Debug, path::Path, time::Duration};
+
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_util::io::ReaderStream;
+
+/// chunk size of the clamd INSTREAM protocol
+const CHUNK_SIZE: usize = 64 * 1024;
+const SCAN_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    Clean,
+    /// the file is rejected, with the signature or reason reported by the scanner
+    Infected(String),
+}
+
+impl ScanVerdict {
+    pub fn into_result(self, file_name: &str) -> anyhow::Result<()> {
+        match self {
+            ScanVerdict::Clean => Ok(()),
+            ScanVerdict::Infected(reason) => {
+                anyhow::bail!("Upload {file_name} rejected by virus scan: {reason}")
+            }
+        }
+    }
+}
+
+/// Scans uploaded files before they are stored
+pub trait UploadScanner: Send + Sync + Debug {
+    fn scan<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, anyhow::Result<ScanVerdict>>;
+}
+
+/// Streams the file to a clamd daemon, `address` is `host:port` or `unix:/path/to/clamd.sock`
+#[derive(Debug, Clone)]
+pub struct ClamAvScanner {
+    address: String,
+}
+
+impl ClamAvScanner {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+        }
+    }
+
+    async fn scan_with<S: AsyncRead + AsyncWrite + Unpin>(
+        mut stream: S,
+        path: &Path,
+    ) -> anyhow::Result<ScanVerdict> {
+        stream.write_all(b"zINSTREAM\0").await?;
+        let mut file = File::open(path).await?;
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            stream.write_all(&(n as u32).to_be_bytes()).await?;
+            stream.write_all(&buf[..n]).await?;
+        }
+        stream.write_all(&0u32.to_be_bytes()).await?;
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).await?;
+        parse_clamd_reply(&reply)
+    }
+}
+
+impl UploadScanner for ClamAvScanner {
+    fn scan<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, anyhow::Result<ScanVerdict>> {
+        Box::pin(async move {
+            let scan = async {
+                match self.address.strip_prefix("unix:") {
+                    #[cfg(unix)]
+                    Some(socket) => {
+                        let stream = tokio::net::UnixStream::connect(socket).await?;
+                        Self::scan_with(stream, path).await
+                    }
+                    #[cfg(not(unix))]
+                    Some(_) => anyhow::bail!("Unix sockets are not supported on this platform"),
+                    None => {
+                        let stream = TcpStream::connect(&self.address).await?;
+                        Self::scan_with(stream, path).await
+                    }
+                }
+            };
+            tokio::time::timeout(SCAN_TIMEOUT, scan).await?
+        })
+    }
+}
+
+/// parse `stream: OK` or `stream: Eicar-Signature FOUND`
+fn parse_clamd_reply(reply: &str) -> anyhow::Result<ScanVerdict> {
+    let reply = reply.trim_end_matches(['\0', '\n']).trim();
+    let result = reply.strip_prefix("stream:").unwrap_or(reply).trim();
+    if result == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(signature) = result.strip_suffix("FOUND") {
+        Ok(ScanVerdict::Infected(signature.trim().to_string()))
+    } else {
+        anyhow::bail!("Unexpected clamd reply: {reply}")
+    }
+}
+
+/// Streams the file to an HTTP endpoint which answers `{"clean": bool, "reason": "..."}`
+#[derive(Debug, Clone)]
+pub struct WebhookScanner {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl WebhookScanner {
+    pub fn new(url: impl Into<String>) -> anyhow::Result<Self> {
+        Ok(Self {
+            url: url.into(),
+            client: reqwest::Client::builder().timeout(SCAN_TIMEOUT).build()?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct WebhookReply {
+    clean: bool,
+    reason: Option<String>,
+}
+
+impl UploadScanner for WebhookScanner {
+    fn scan<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, anyhow::Result<ScanVerdict>> {
+        Box::pin(async move {
+            let file_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let file = File::open(path).await?;
+            let length = file.metadata().await?.len();
+            let body = reqwest::Body::wrap_stream(ReaderStream::new(file));
+            let reply: WebhookReply = self
+                .client
+                .post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+                .header(reqwest::header::CONTENT_LENGTH, length)
+                .header("X-File-Name", file_name)
+                .body(body)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            Ok(match reply.clean {
+                true => ScanVerdict::Clean,
+                false => ScanVerdict::Infected(reply.reason.unwrap_or_default()),
+            })
+        })
+    }
+}
+
+#[test]
+fn clamd_reply() {
+    assert_eq!(
+        parse_clamd_reply("stream: OK\0").unwrap(),
+        ScanVerdict::Clean
+    );
+    assert_eq!(
+        parse_clamd_reply("stream: Win.Test.EICAR_HDB-1 FOUND\0").unwrap(),
+        ScanVerdict::Infected("Win.Test.EICAR_HDB-1".to_string())
+    );
+    assert!(parse_clamd_reply("INSTREAM size limit exceeded. ERROR\0").is_err());
+}
@@ -0,0 +1,198 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+/// longest scratchpad, in characters
+pub const MAX_SCRATCHPAD_CHARS: usize = 20_000;
+
+/// Who last wrote the scratchpad
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Editor {
+    Teacher,
+    Student,
+}
+
+/// The Markdown document the teacher and the student share on a book, kept across sessions
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Scratchpad {
+    pub book_id: i64,
+    pub content: String,
+    /// bumped on every save, 0 while the scratchpad was never written
+    pub version: i64,
+    pub updated_by: Option<Editor>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    #[schema(value_type = Option<String>)]
+    pub update_time: Option<OffsetDateTime>,
+}
+
+/// A change of the scratchpad by the teacher
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ScratchpadUpdate {
+    /// Markdown, the whole new scratchpad or the text to add at its end
+    pub content: String,
+    /// add `content` at the end instead of replacing the scratchpad
+    #[serde(default)]
+    pub append: bool,
+    /// the version of the scratchpad you read, replacing fails if the student changed it since
+    pub version: Option<i64>,
+}
+
+pub async fn get(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+) -> anyhow::Result<Scratchpad> {
+    let scratchpad = sqlx::query!(
+        r#"select content, version, updated_by as "updated_by: Editor",
+        update_time as "update_time: OffsetDateTime"
+        from scratchpad where student_id = ? and book_id = ?"#,
+        student_id,
+        book_id
+    )
+    .fetch_optional(database)
+    .await?;
+    Ok(match scratchpad {
+        Some(record) => Scratchpad {
+            book_id,
+            content: record.content,
+            version: record.version,
+            updated_by: Some(record.updated_by),
+            update_time: Some(record.update_time),
+        },
+        None => Scratchpad {
+            book_id,
+            content: String::new(),
+            version: 0,
+            updated_by: None,
+            update_time: None,
+        },
+    })
+}
+
+/// replace the scratchpad, only if it is still at `version` when given
+pub async fn save(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+    content: &str,
+    editor: Editor,
+    version: Option<i64>,
+) -> anyhow::Result<Scratchpad> {
+    if content.chars().count() > MAX_SCRATCHPAD_CHARS {
+        anyhow::bail!(
+            "The scratchpad is limited to {} characters",
+            MAX_SCRATCHPAD_CHARS
+        );
+    }
+    let updated = sqlx::query!(
+        "update scratchpad set content = ?, version = version + 1, updated_by = ?,
+        update_time = CURRENT_TIMESTAMP
+        where student_id = ? and book_id = ? and (? is null or version = ?)",
+        content,
+        editor,
+        student_id,
+        book_id,
+        version,
+        version
+    )
+    .execute(database)
+    .await?;
+    if updated.rows_affected() == 0 {
+        // the first version, unless the scratchpad was written meanwhile
+        let inserted = sqlx::query!(
+            "insert or ignore into scratchpad (student_id, book_id, content, version, updated_by)
+            select ?, ?, ?, 1, ? where coalesce(?, 0) = 0",
+            student_id,
+            book_id,
+            content,
+            editor,
+            version
+        )
+        .execute(database)
+        .await?;
+        if inserted.rows_affected() == 0 {
+            return Err(VersionConflict.into());
+        }
+    }
+    get(database, student_id, book_id).await
+}
+
+/// add `content` at the end of the scratchpad, on a line of its own
+pub async fn append(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+    content: &str,
+    editor: Editor,
+) -> anyhow::Result<Scratchpad> {
+    let current = get(database, student_id, book_id).await?;
+    let content = if current.content.trim().is_empty() {
+        content.to_string()
+    } else {
+        format!("{}\n\n{}", current.content.trim_end(), content)
+    };
+    save(
+        database,
+        student_id,
+        book_id,
+        &content,
+        editor,
+        Some(current.version),
+    )
+    .await
+}
+
+/// The scratchpad changed since the version an edit was based on
+#[derive(Debug, thiserror::Error)]
+#[error("The scratchpad was changed since it was read, read it again")]
+pub struct VersionConflict;
+
+#[tokio::test]
+async fn versions() {
+    use crate::books::library::{Library, LibraryConfig};
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = LibraryConfig {
+        database: dir.path().join("book.db"),
+        bookbase: dir.path().join("bookbase"),
+        migrate: true,
+        ..Default::default()
+    };
+    let database = Library::open(&config).await.unwrap().database.clone();
+    sqlx::query!("insert into book (id, title, authors) values (1, 'Rust', '')")
+        .execute(&database)
+        .await
+        .unwrap();
+    sqlx::query!("insert into student (id, name, email, password) values (1, 'Ann', 'a@b.c', '')")
+        .execute(&database)
+        .await
+        .unwrap();
+    assert_eq!(get(&database, 1, 1).await.unwrap().version, 0);
+    let pad = save(&database, 1, 1, "# Project", Editor::Student, Some(0))
+        .await
+        .unwrap();
+    assert_eq!(pad.version, 1);
+    let pad = append(&database, 1, 1, "- parse the input", Editor::Teacher)
+        .await
+        .unwrap();
+    assert_eq!(pad.content, "# Project\n\n- parse the input");
+    assert_eq!((pad.version, pad.updated_by), (2, Some(Editor::Teacher)));
+    // an edit based on the first version would drop the teacher's line
+    let stale = save(&database, 1, 1, "# Project v2", Editor::Student, Some(1)).await;
+    assert!(stale.unwrap_err().is::<VersionConflict>());
+    assert!(
+        save(&database, 1, 1, "# New", Editor::Student, Some(0))
+            .await
+            .is_err()
+    );
+    let long = "x".repeat(MAX_SCRATCHPAD_CHARS + 1);
+    assert!(
+        save(&database, 1, 1, &long, Editor::Teacher, None)
+            .await
+            .is_err()
+    );
+}
@@ -0,0 +1,389 @@
+use std::sync::{Arc, Mutex};
+
+use rhai::{Dynamic, Engine, Map, Scope, module_resolvers::DummyModuleResolver};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::{Date, Duration};
+use tokio::task::spawn_blocking;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+use crate::{books::chapter::ChapterNumber, i18n, utils::now_local};
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+/// operations a script may run per event, about one per expression evaluated
+const MAX_OPERATIONS: u64 = 100_000;
+const MAX_REVIEW_DAYS: i64 = 365;
+
+/// The events of a lesson that run the scripts of the book
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum Hook {
+    /// a quiz was graded, `event.chapter`, `event.quiz_id` and `event.score` from 0 to 1
+    QuizGraded,
+    /// the student completed a chapter, `event.chapter`
+    ChapterCompleted,
+}
+
+/// What happened, as the script sees it in `event`
+#[derive(Debug, Clone)]
+pub enum HookEvent {
+    QuizGraded {
+        chapter_number: ChapterNumber,
+        quiz_id: i64,
+        score: f64,
+    },
+    ChapterCompleted {
+        chapter_number: ChapterNumber,
+    },
+}
+
+impl HookEvent {
+    pub fn hook(&self) -> Hook {
+        match self {
+            Self::QuizGraded { .. } => Hook::QuizGraded,
+            Self::ChapterCompleted { .. } => Hook::ChapterCompleted,
+        }
+    }
+
+    fn chapter_number(&self) -> &ChapterNumber {
+        match self {
+            Self::QuizGraded { chapter_number, .. } | Self::ChapterCompleted { chapter_number } => {
+                chapter_number
+            }
+        }
+    }
+
+    fn to_map(&self) -> Map {
+        let mut map = Map::new();
+        map.insert("chapter".into(), self.chapter_number().to_string().into());
+        if let Self::QuizGraded { quiz_id, score, .. } = self {
+            map.insert("quiz_id".into(), Dynamic::from(*quiz_id));
+            map.insert("score".into(), Dynamic::from(*score));
+        }
+        map
+    }
+}
+
+/// What a script asked for, applied once it ran to the end
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptAction {
+    ScheduleReview {
+        chapter_number: ChapterNumber,
+        days: i64,
+        reason: String,
+    },
+}
+
+/// A script of a book run on one of its hooks
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LessonScript {
+    pub id: i64,
+    pub book_id: i64,
+    /// the class the script applies to, every student of the book if `None`
+    pub class_id: Option<i64>,
+    pub hook: Hook,
+    pub name: String,
+    pub source: String,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct ScriptRequest {
+    /// the class the script applies to, every student of the book if missing
+    pub class_id: Option<i64>,
+    pub hook: Hook,
+    pub name: String,
+    /// Rhai source, see the README for the functions it can call
+    pub source: String,
+}
+
+/// A review of a chapter a script scheduled for the student
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ScheduledReview {
+    pub id: i64,
+    pub book_id: i64,
+    #[schema(value_type = String)]
+    pub chapter_number: ChapterNumber,
+    #[serde(with = "iso_date")]
+    #[schema(value_type = String, format = Date)]
+    pub due_date: Date,
+    pub reason: String,
+}
+
+/// the sandboxed engine scripts run in: no modules, no `eval`, no I/O but the log, and bounded
+/// operations, depth and sizes
+fn engine(name: &str) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(16)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(10_000)
+        .set_max_array_size(1_000)
+        .set_max_map_size(1_000)
+        .set_module_resolver(DummyModuleResolver::new())
+        .disable_symbol("eval");
+    let script = name.to_string();
+    engine.on_print(move |text| info!(script = %script, "{text}"));
+    let script = name.to_string();
+    engine.on_debug(move |text, _, _| info!(script = %script, "{text}"));
+    engine
+}
+
+/// check that `source` parses
+pub fn compile(source: &str) -> anyhow::Result<()> {
+    engine("").compile(source)?;
+    Ok(())
+}
+
+/// run `source` on `event`, the actions it asked for in order
+pub fn evaluate(name: &str, source: &str, event: &HookEvent) -> anyhow::Result<Vec<ScriptAction>> {
+    let mut engine = engine(name);
+    let actions = Arc::new(Mutex::new(Vec::new()));
+    let schedule = actions.clone();
+    engine.register_fn(
+        "schedule_review",
+        move |chapter: &str, days: i64, reason: &str| -> Result<(), Box<rhai::EvalAltResult>> {
+            let chapter_number: ChapterNumber = chapter
+                .parse()
+                .map_err(|_| format!("Invalid chapter number: {chapter}"))?;
+            if !(0..=MAX_REVIEW_DAYS).contains(&days) {
+                return Err(format!("Days must be between 0 and {MAX_REVIEW_DAYS}").into());
+            }
+            schedule.lock().unwrap().push(ScriptAction::ScheduleReview {
+                chapter_number,
+                days,
+                reason: reason.to_string(),
+            });
+            Ok(())
+        },
+    );
+    let mut scope = Scope::new();
+    scope.push_constant("event", event.to_map());
+    engine
+        .run_with_scope(&mut scope, source)
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+    let actions = std::mem::take(&mut *actions.lock().unwrap());
+    Ok(actions)
+}
+
+pub async fn add_script(
+    database: &SqlitePool,
+    book_id: i64,
+    request: &ScriptRequest,
+) -> anyhow::Result<i64> {
+    let name = request.name.trim();
+    if name.is_empty() {
+        anyhow::bail!("Script name must not be empty");
+    }
+    compile(&request.source)?;
+    let id = sqlx::query_scalar!(
+        "insert into lesson_script (book_id, class_id, hook, name, source) values (?, ?, ?, ?, ?)
+        returning id",
+        book_id,
+        request.class_id,
+        request.hook,
+        name,
+        request.source
+    )
+    .fetch_one(database)
+    .await?;
+    Ok(id)
+}
+
+pub async fn list_scripts(
+    database: &SqlitePool,
+    book_id: i64,
+) -> anyhow::Result<Vec<LessonScript>> {
+    let scripts = sqlx::query_as!(
+        LessonScript,
+        r#"select id, book_id, class_id, hook as "hook: Hook", name, source
+        from lesson_script where book_id = ? order by id"#,
+        book_id
+    )
+    .fetch_all(database)
+    .await?;
+    Ok(scripts)
+}
+
+pub async fn remove_script(database: &SqlitePool, id: i64) -> anyhow::Result<()> {
+    let result = sqlx::query!("delete from lesson_script where id = ?", id)
+        .execute(database)
+        .await?;
+    if result.rows_affected() == 0 {
+        anyhow::bail!("Lesson script not found: {}", id);
+    }
+    Ok(())
+}
+
+/// Run the scripts of the book for the student on `event`. The due reviews of its chapter are
+/// done first, the student just revisited it. Script errors are logged, they never fail the
+/// lesson.
+pub async fn run_hook(database: &SqlitePool, student_id: i64, book_id: i64, event: HookEvent) {
+    if let Err(e) = try_run_hook(database, student_id, book_id, event).await {
+        warn!("lesson scripts of book {book_id} failed: {e:?}");
+    }
+}
+
+async fn try_run_hook(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+    event: HookEvent,
+) -> anyhow::Result<()> {
+    let today = now_local().date();
+    let chapter_number = event.chapter_number().to_string();
+    sqlx::query!(
+        "update scheduled_review set done = true
+        where student_id = ? and book_id = ? and chapter_number = ? and due_date <= ? and not done",
+        student_id,
+        book_id,
+        chapter_number,
+        today
+    )
+    .execute(database)
+    .await?;
+    let hook = event.hook();
+    let scripts = sqlx::query!(
+        r#"select id, name, source from lesson_script
+        where book_id = ? and hook = ?
+        and (class_id is null or class_id = (select class_id from student where id = ?))
+        order by id"#,
+        book_id,
+        hook,
+        student_id
+    )
+    .fetch_all(database)
+    .await?;
+    for script in scripts {
+        let event = event.clone();
+        let name = script.name.clone();
+        let result = spawn_blocking(move || evaluate(&name, &script.source, &event)).await?;
+        let actions = match result {
+            Ok(actions) => actions,
+            Err(e) => {
+                warn!("lesson script {} ({}) failed: {e}", script.id, script.name);
+                continue;
+            }
+        };
+        for action in actions {
+            match action {
+                ScriptAction::ScheduleReview {
+                    chapter_number,
+                    days,
+                    reason,
+                } => {
+                    let chapter_number = chapter_number.to_string();
+                    let due_date = today + Duration::days(days);
+                    sqlx::query!(
+                        "insert into scheduled_review
+                        (student_id, book_id, chapter_number, due_date, reason, script_id)
+                        values (?, ?, ?, ?, ?, ?)",
+                        student_id,
+                        book_id,
+                        chapter_number,
+                        due_date,
+                        reason,
+                        script.id
+                    )
+                    .execute(database)
+                    .await?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// the reviews of the student on the book not done yet, earliest first; only the due ones
+/// when `due_only`
+pub async fn list_reviews(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+    due_only: bool,
+) -> anyhow::Result<Vec<ScheduledReview>> {
+    let today = now_local().date();
+    let records = sqlx::query!(
+        r#"select id, book_id, chapter_number, due_date as "due_date: Date", reason
+        from scheduled_review
+        where student_id = ? and book_id = ? and not done and (not ? or due_date <= ?)
+        order by due_date, id"#,
+        student_id,
+        book_id,
+        due_only,
+        today
+    )
+    .fetch_all(database)
+    .await?;
+    records
+        .into_iter()
+        .map(|record| {
+            Ok(ScheduledReview {
+                id: record.id,
+                book_id: record.book_id,
+                chapter_number: record.chapter_number.parse()?,
+                due_date: record.due_date,
+                reason: record.reason,
+            })
+        })
+        .collect()
+}
+
+/// the due reviews for the system prompt of the teacher, `None` without any
+pub fn reviews_prompt(locale: &str, reviews: &[ScheduledReview]) -> Option<String> {
+    if reviews.is_empty() {
+        return None;
+    }
+    let lines: Vec<String> = reviews
+        .iter()
+        .map(|review| format!("- {}: {}", review.chapter_number, review.reason))
+        .collect();
+    Some(i18n::tr(
+        locale,
+        "teacher-due-reviews",
+        &[("reviews", lines.join("\n").into())],
+    ))
+}
+
+#[test]
+fn evaluate_script() {
+    let event = HookEvent::QuizGraded {
+        chapter_number: "2.1.".parse().unwrap(),
+        quiz_id: 7,
+        score: 0.5,
+    };
+    let source = r#"
+        if event.score < 0.6 {
+            schedule_review(event.chapter, 2, "quiz score below 60%");
+        }
+    "#;
+    assert!(compile(source).is_ok());
+    assert_eq!(
+        evaluate("review", source, &event).unwrap(),
+        [ScriptAction::ScheduleReview {
+            chapter_number: "2.1.".parse().unwrap(),
+            days: 2,
+            reason: "quiz score below 60%".to_string(),
+        }]
+    );
+    let passed = HookEvent::ChapterCompleted {
+        chapter_number: "3.".parse().unwrap(),
+    };
+    assert!(
+        evaluate(
+            "review",
+            "if \"score\" in event { schedule_review(event.chapter, 2, \"\") }",
+            &passed
+        )
+        .unwrap()
+        .is_empty()
+    );
+    // the sandbox stops endless loops and refuses eval and modules
+    assert!(evaluate("loop", "loop {}", &event).is_err());
+    assert!(compile("eval(\"1\")").is_err());
+    assert!(evaluate("import", "import \"fs\" as fs;", &event).is_err());
+    assert!(evaluate("days", "schedule_review(event.chapter, 1000, \"\")", &event).is_err());
+}
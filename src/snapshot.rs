@@ -0,0 +1,212 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+use tokio::task::spawn_blocking;
+use utoipa::ToSchema;
+use walkdir::WalkDir;
+use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use crate::books::cover::COVERS_DIR;
+
+const MANIFEST: &str = "snapshot.json";
+const DATABASE: &str = "database.db";
+const BOOKBASE: &str = "bookbase/";
+
+/// Describes a snapshot archive, stored as `snapshot.json` inside it
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SnapshotManifest {
+    pub format: u32,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String)]
+    pub create_time: OffsetDateTime,
+    /// the latest migration applied to the database
+    pub schema_version: i64,
+    pub books: i64,
+    pub students: i64,
+}
+
+/// Export the whole server, database and bookbase, as a zip archive at `path`.
+///
+/// The database is copied with `VACUUM INTO`, so a running server can be snapshotted.
+/// Messages kept in Redis are not part of the database and not exported,
+/// encrypted messages need the same `MESSAGE_MASTER_KEY` after a restore.
+pub async fn export(
+    database: &SqlitePool,
+    bookbase: &Path,
+    path: &Path,
+) -> anyhow::Result<SnapshotManifest> {
+    let schema_version =
+        sqlx::query_scalar!(r#"select max(version) as "version!: i64" from _sqlx_migrations"#)
+            .fetch_one(database)
+            .await?;
+    let books = sqlx::query_scalar!(r#"select count(*) as "count!: i64" from book"#)
+        .fetch_one(database)
+        .await?;
+    let students = sqlx::query_scalar!(r#"select count(*) as "count!: i64" from student"#)
+        .fetch_one(database)
+        .await?;
+    let manifest = SnapshotManifest {
+        format: 1,
+        create_time: OffsetDateTime::now_utc(),
+        schema_version,
+        books,
+        students,
+    };
+
+    let temp = tempfile::tempdir()?;
+    let copy = temp.path().join(DATABASE);
+    let copy_str = copy.to_string_lossy().to_string();
+    sqlx::query("vacuum into ?")
+        .bind(copy_str)
+        .execute(database)
+        .await?;
+
+    let bookbase = bookbase.to_path_buf();
+    let path = path.to_path_buf();
+    let manifest_json = serde_json::to_vec_pretty(&manifest)?;
+    spawn_blocking(move || -> anyhow::Result<()> {
+        let mut zip = ZipWriter::new(File::create(&path)?);
+        let options = SimpleFileOptions::default().large_file(true);
+        zip.start_file(MANIFEST, options)?;
+        zip.write_all(&manifest_json)?;
+        zip.start_file(DATABASE, options)?;
+        io::copy(&mut File::open(&copy)?, &mut zip)?;
+        // the cover thumbnails are regenerated on demand
+        let entries = WalkDir::new(&bookbase)
+            .into_iter()
+            .filter_entry(|entry| !(entry.depth() == 1 && entry.file_name() == COVERS_DIR));
+        for entry in entries {
+            let entry = entry?;
+            let relative = entry.path().strip_prefix(&bookbase)?;
+            let Some(relative) = relative.to_str().filter(|r| !r.is_empty()) else {
+                continue;
+            };
+            let name = format!("{BOOKBASE}{}", relative.replace('\\', "/"));
+            if entry.file_type().is_dir() {
+                zip.add_directory(name, options)?;
+            } else if entry.file_type().is_file() {
+                zip.start_file(name, options)?;
+                io::copy(&mut File::open(entry.path())?, &mut zip)?;
+            }
+        }
+        zip.finish()?;
+        Ok(())
+    })
+    .await??;
+    drop(temp);
+    Ok(manifest)
+}
+
+/// read the manifest of a snapshot archive without restoring it
+pub fn read_manifest(path: &Path) -> anyhow::Result<SnapshotManifest> {
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let mut manifest = String::new();
+    zip.by_name(MANIFEST)?.read_to_string(&mut manifest)?;
+    Ok(serde_json::from_str(&manifest)?)
+}
+
+/// Restore a snapshot archive into an empty `database` path and `bookbase` directory.
+///
+/// Refuses to overwrite existing data, move it aside first. Open the library with
+/// `migrate` afterwards to bring a snapshot of an older server up to the current schema.
+pub async fn restore(
+    path: &Path,
+    database: &Path,
+    bookbase: &Path,
+) -> anyhow::Result<SnapshotManifest> {
+    let manifest = read_manifest(path)?;
+    let latest = sqlx::migrate!()
+        .migrations
+        .iter()
+        .map(|m| m.version)
+        .max()
+        .unwrap_or_default();
+    if manifest.schema_version > latest {
+        bail!(
+            "Snapshot schema version {} is newer than this server ({})",
+            manifest.schema_version,
+            latest
+        );
+    }
+    if database.exists() {
+        bail!("Database already exists: {}", database.display());
+    }
+    if bookbase.exists() && bookbase.read_dir()?.next().is_some() {
+        bail!("Bookbase is not empty: {}", bookbase.display());
+    }
+
+    let path = path.to_path_buf();
+    let database = database.to_path_buf();
+    let bookbase = bookbase.to_path_buf();
+    spawn_blocking(move || -> anyhow::Result<()> {
+        let mut zip = ZipArchive::new(File::open(&path)?)?;
+        if let Some(dir) = database.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::create_dir_all(&bookbase)?;
+        io::copy(&mut zip.by_name(DATABASE)?, &mut File::create(&database)?)?;
+        for i in 0..zip.len() {
+            let mut entry = zip.by_index(i)?;
+            let Some(relative) = entry
+                .enclosed_name()
+                .and_then(|name| name.strip_prefix(BOOKBASE).ok().map(PathBuf::from))
+            else {
+                continue;
+            };
+            let target = bookbase.join(relative);
+            if entry.is_dir() {
+                std::fs::create_dir_all(&target)?;
+            } else {
+                if let Some(dir) = target.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                io::copy(&mut entry, &mut File::create(&target)?)?;
+            }
+        }
+        Ok(())
+    })
+    .await??;
+    Ok(manifest)
+}
+
+#[tokio::test]
+async fn export_and_restore() {
+    use crate::books::library::{Library, LibraryConfig};
+
+    let source = tempfile::tempdir().unwrap();
+    let config = LibraryConfig {
+        database: source.path().join("book.db"),
+        bookbase: source.path().join("bookbase"),
+        migrate: true,
+        ..Default::default()
+    };
+    let library = Library::open(&config).await.unwrap();
+    std::fs::create_dir_all(config.bookbase.join("book_1")).unwrap();
+    std::fs::write(config.bookbase.join("book_1/plan.json"), "{}").unwrap();
+    let archive = source.path().join("snapshot.zip");
+    let manifest = export(&library.database, &config.bookbase, &archive)
+        .await
+        .unwrap();
+    assert_eq!(
+        read_manifest(&archive).unwrap().schema_version,
+        manifest.schema_version
+    );
+
+    let target = tempfile::tempdir().unwrap();
+    let database = target.path().join("data/book.db");
+    let bookbase = target.path().join("bookbase");
+    restore(&archive, &database, &bookbase).await.unwrap();
+    assert!(database.exists());
+    assert_eq!(
+        std::fs::read_to_string(bookbase.join("book_1/plan.json")).unwrap(),
+        "{}"
+    );
+    assert!(restore(&archive, &database, &bookbase).await.is_err());
+}
@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::SqlitePool;
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+/// share of the monthly limit at which the warning webhook is called
+pub const WARNING_RATIO: f64 = 0.8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BudgetStatus {
+    /// no class or no limit
+    Unlimited,
+    Available {
+        spent: f64,
+        limit: f64,
+    },
+    Exhausted {
+        spent: f64,
+        limit: f64,
+    },
+}
+
+/// A class (tenant) and what it spent this month
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ClassSpend {
+    pub id: i64,
+    pub name: String,
+    /// USD per month, no limit if `None`
+    pub monthly_limit: Option<f64>,
+    pub warning_webhook: Option<String>,
+    /// USD spent since the start of the month (UTC)
+    pub spent: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct ClassSetting {
+    pub name: String,
+    pub monthly_limit: Option<f64>,
+    pub warning_webhook: Option<String>,
+}
+
+pub async fn create_class(database: &SqlitePool, setting: &ClassSetting) -> anyhow::Result<i64> {
+    let result = sqlx::query!(
+        "insert into class (name, monthly_limit, warning_webhook) values (?, ?, ?)",
+        setting.name,
+        setting.monthly_limit,
+        setting.warning_webhook
+    )
+    .execute(database)
+    .await?;
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn update_class(
+    database: &SqlitePool,
+    class_id: i64,
+    setting: &ClassSetting,
+) -> anyhow::Result<()> {
+    let result = sqlx::query!(
+        "update class set name = ?, monthly_limit = ?, warning_webhook = ? where id = ?",
+        setting.name,
+        setting.monthly_limit,
+        setting.warning_webhook,
+        class_id
+    )
+    .execute(database)
+    .await?;
+    if result.rows_affected() == 0 {
+        anyhow::bail!("Class not found: {class_id}");
+    }
+    Ok(())
+}
+
+pub async fn set_student_class(
+    database: &SqlitePool,
+    student_id: i64,
+    class_id: Option<i64>,
+) -> anyhow::Result<()> {
+    let result = sqlx::query!(
+        "update student set class_id = ? where id = ?",
+        class_id,
+        student_id
+    )
+    .execute(database)
+    .await?;
+    if result.rows_affected() == 0 {
+        anyhow::bail!("Student not found: {student_id}");
+    }
+    Ok(())
+}
+
+pub async fn list_classes(database: &SqlitePool) -> anyhow::Result<Vec<ClassSpend>> {
+    let classes = sqlx::query_as!(
+        ClassSpend,
+        r#"select c.id as "id!", c.name, c.monthly_limit, c.warning_webhook,
+        coalesce((select sum(u.cost) from ai_usage u where u.class_id = c.id and u.create_time >= date('now', 'start of month')), 0.0) as "spent!: f64"
+        from class c order by c.id"#
+    )
+    .fetch_all(database)
+    .await?;
+    Ok(classes)
+}
+
+async fn get_student_class(
+    database: &SqlitePool,
+    student_id: i64,
+) -> anyhow::Result<Option<ClassSpend>> {
+    let class = sqlx::query_as!(
+        ClassSpend,
+        r#"select c.id as "id!", c.name, c.monthly_limit, c.warning_webhook,
+        coalesce((select sum(u.cost) from ai_usage u where u.class_id = c.id and u.create_time >= date('now', 'start of month')), 0.0) as "spent!: f64"
+        from class c join student s on s.class_id = c.id where s.id = ?"#,
+        student_id
+    )
+    .fetch_optional(database)
+    .await?;
+    Ok(class)
+}
+
+/// the budget left this month for the class of the student
+pub async fn check_budget(database: &SqlitePool, student_id: i64) -> anyhow::Result<BudgetStatus> {
+    let Some(class) = get_student_class(database, student_id).await? else {
+        return Ok(BudgetStatus::Unlimited);
+    };
+    let Some(limit) = class.monthly_limit else {
+        return Ok(BudgetStatus::Unlimited);
+    };
+    if class.spent >= limit {
+        Ok(BudgetStatus::Exhausted {
+            spent: class.spent,
+            limit,
+        })
+    } else {
+        Ok(BudgetStatus::Available {
+            spent: class.spent,
+            limit,
+        })
+    }
+}
+
+/// USD cost of a model call with the `agent_setting` prices
+pub async fn price(
+    database: &SqlitePool,
+    prompt_tokens: u32,
+    completion_tokens: u32,
+) -> anyhow::Result<f64> {
+    let prices = sqlx::query!("select prompt_price, completion_price from agent_setting")
+        .fetch_one(database)
+        .await?;
+    Ok((prompt_tokens as f64 * prices.prompt_price
+        + completion_tokens as f64 * prices.completion_price)
+        / 1_000_000.0)
+}
+
+/// record the tokens of a model call, priced with the `agent_setting` prices,
+/// and warn the class once a month when its spend crosses [`WARNING_RATIO`] of the limit
+pub async fn record_usage(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+    prompt_tokens: u32,
+    completion_tokens: u32,
+) -> anyhow::Result<()> {
+    let cost = price(database, prompt_tokens, completion_tokens).await?;
+    let class_id = sqlx::query_scalar!("select class_id from student where id = ?", student_id)
+        .fetch_one(database)
+        .await?;
+    let (prompt_tokens, completion_tokens) = (prompt_tokens as i64, completion_tokens as i64);
+    sqlx::query!(
+        "insert into ai_usage (student_id, book_id, class_id, prompt_tokens, completion_tokens, cost) values (?, ?, ?, ?, ?, ?)",
+        student_id,
+        book_id,
+        class_id,
+        prompt_tokens,
+        completion_tokens,
+        cost
+    )
+    .execute(database)
+    .await?;
+
+    let Some(class) = get_student_class(database, student_id).await? else {
+        return Ok(());
+    };
+    let (Some(limit), Some(webhook)) = (class.monthly_limit, class.warning_webhook.clone()) else {
+        return Ok(());
+    };
+    if class.spent < limit * WARNING_RATIO {
+        return Ok(());
+    }
+    let month = sqlx::query_scalar!(r#"select strftime('%Y-%m', 'now') as "month!: String""#)
+        .fetch_one(database)
+        .await?;
+    let inserted = sqlx::query!(
+        "insert or ignore into class_spend_warning (class_id, month) values (?, ?)",
+        class.id,
+        month
+    )
+    .execute(database)
+    .await?
+    .rows_affected();
+    if inserted == 0 {
+        return Ok(());
+    }
+    info!(
+        "class {} reached {:.2} of its {:.2} USD monthly AI budget",
+        class.name, class.spent, limit
+    );
+    let payload = json!({
+        "class_id": class.id,
+        "class_name": class.name,
+        "month": month,
+        "spent": class.spent,
+        "monthly_limit": limit,
+    });
+    tokio::spawn(async move {
+        let result = reqwest::Client::new()
+            .post(&webhook)
+            .json(&payload)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = result {
+            error!("spend warning webhook {} failed: {}", webhook, e);
+        }
+    });
+    Ok(())
+}
@@ -5,19 +5,29 @@ use argon2::{
 
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
+use time::{Date, OffsetDateTime};
 use utoipa::ToSchema;
 
-use crate::{books::book::BookMeta, teacher::TeacherAgent};
+use crate::{
+    agent_profile,
+    books::{
+        book::{BookMeta, Difficulty},
+        content_rating,
+    },
+    course, i18n,
+    teacher::{TeacherAgent, messages::store::MessageStore},
+};
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct StudentInfo {
     pub id: i64,
     pub name: String,
     pub email: String,
+    pub locale: String,
 }
 
 pub async fn get_student_list(database: &SqlitePool) -> anyhow::Result<Vec<StudentInfo>> {
-    let students = sqlx::query_as!(StudentInfo, "SELECT id, name, email FROM student")
+    let students = sqlx::query_as!(StudentInfo, "SELECT id, name, email, locale FROM student")
         .fetch_all(database)
         .await?;
     Ok(students)
@@ -45,6 +55,61 @@ pub async fn create_student(
     Ok(student.last_insert_rowid() as i64)
 }
 
+pub async fn get_student_locale(database: &SqlitePool, id: i64) -> anyhow::Result<String> {
+    let locale = sqlx::query_scalar!("SELECT locale FROM student WHERE id = ?", id)
+        .fetch_one(database)
+        .await?;
+    Ok(locale)
+}
+
+/// set the locale of the student, normalized to a supported one, returns it
+pub async fn set_student_locale(
+    database: &SqlitePool,
+    id: i64,
+    locale: &str,
+) -> anyhow::Result<&'static str> {
+    let locale = i18n::negotiate(locale);
+    sqlx::query!("UPDATE student SET locale = ? WHERE id = ?", locale, id)
+        .execute(database)
+        .await?;
+    Ok(locale)
+}
+
+/// the age of the student today, `None` when their birth date isn't set
+pub async fn get_student_age(database: &SqlitePool, id: i64) -> anyhow::Result<Option<u8>> {
+    let birth_date = sqlx::query_scalar!(
+        r#"SELECT birth_date as "birth_date: Date" FROM student WHERE id = ?"#,
+        id
+    )
+    .fetch_one(database)
+    .await?;
+    let today = OffsetDateTime::now_utc().date();
+    Ok(birth_date.map(|birth_date| content_rating::age_on(birth_date, today)))
+}
+
+/// set the birth date of the student, chapters rated above their age are not taught, `None`
+/// lifts the restriction
+pub async fn set_student_birth_date(
+    database: &SqlitePool,
+    id: i64,
+    birth_date: Option<Date>,
+) -> anyhow::Result<()> {
+    if birth_date.is_some_and(|date| date > OffsetDateTime::now_utc().date()) {
+        anyhow::bail!("Birth date must not be in the future");
+    }
+    let result = sqlx::query!(
+        "UPDATE student SET birth_date = ? WHERE id = ?",
+        birth_date,
+        id
+    )
+    .execute(database)
+    .await?;
+    if result.rows_affected() == 0 {
+        anyhow::bail!("Student not found: {}", id);
+    }
+    Ok(())
+}
+
 pub async fn delete_student(database: &SqlitePool, id: i64) -> anyhow::Result<()> {
     sqlx::query!("DELETE FROM student WHERE id = ?", id)
         .execute(database)
@@ -53,7 +118,7 @@ pub async fn delete_student(database: &SqlitePool, id: i64) -> anyhow::Result<()
 }
 
 pub async fn get_student_books(database: &SqlitePool, id: i64) -> anyhow::Result<Vec<BookMeta>> {
-    let books = sqlx::query!("SELECT book.id, book.title, book.authors, book.description, book.is_public FROM book inner join teacher_agent on book.id = teacher_agent.book_id WHERE student_id = ?", id)
+    let books = sqlx::query!(r#"SELECT book.id, book.title, book.authors, book.description, book.is_public, book.tags, book.create_time as "create_time: OffsetDateTime", book.language, book.difficulty, book.cover_path is not null as "has_cover!: bool" FROM book inner join teacher_agent on book.id = teacher_agent.book_id WHERE student_id = ? and book.archive_time is null"#, id)
         .fetch_all(database)
         .await?;
     let mut book_list = Vec::new();
@@ -64,12 +129,37 @@ pub async fn get_student_books(database: &SqlitePool, id: i64) -> anyhow::Result
             authors: book.authors.split(',').map(|s| s.to_string()).collect(),
             description: book.description,
             is_public: book.is_public,
+            archived: false,
+            tags: book
+                .tags
+                .split(',')
+                .filter(|tag| !tag.is_empty())
+                .map(|tag| tag.to_string())
+                .collect(),
+            create_time: book.create_time,
+            language: book.language,
+            difficulty: book
+                .difficulty
+                .as_deref()
+                .and_then(|difficulty| Difficulty::try_from(difficulty).ok()),
+            has_cover: book.has_cover,
         };
         book_list.push(book_meta);
     }
     Ok(book_list)
 }
 
+pub async fn is_enrolled(database: &SqlitePool, id: i64, book_id: i64) -> anyhow::Result<bool> {
+    let enrolled = sqlx::query_scalar!(
+        r#"select exists(select 1 from teacher_agent where student_id = ? and book_id = ?) as "enrolled!: bool""#,
+        id,
+        book_id
+    )
+    .fetch_one(database)
+    .await?;
+    Ok(enrolled)
+}
+
 pub async fn add_student_books(
     database: &SqlitePool,
     id: i64,
@@ -82,6 +172,7 @@ pub async fn add_student_books(
 }
 pub async fn delete_student_book(
     database: &SqlitePool,
+    message_store: &dyn MessageStore,
     id: i64,
     book_id: i64,
 ) -> anyhow::Result<()> {
@@ -92,8 +183,34 @@ pub async fn delete_student_book(
     )
     .execute(database)
     .await?;
+    message_store.clear(id, book_id).await?;
+    course::delete_course(database, id, book_id).await?;
+    agent_profile::delete_profile(database, id, Some(book_id)).await?;
+    sqlx::query!(
+        "DELETE FROM session_book WHERE student_id = ? AND (book_id = ? OR linked_book_id = ?)",
+        id,
+        book_id,
+        book_id
+    )
+    .execute(database)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM student_project WHERE student_id = ?
+        AND project_id IN (SELECT id FROM project WHERE book_id = ?)",
+        id,
+        book_id
+    )
+    .execute(database)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM scratchpad WHERE student_id = ? AND book_id = ?",
+        id,
+        book_id
+    )
+    .execute(database)
+    .await?;
     sqlx::query!(
-        "DELETE FROM history_message WHERE student_id = ? AND book_id = ?",
+        "DELETE FROM scheduled_review WHERE student_id = ? AND book_id = ?",
         id,
         book_id
     )
@@ -124,7 +241,7 @@ pub async fn login(database: &SqlitePool, email: String, password: String) -> an
 pub async fn get_student_info(database: &SqlitePool, id: i64) -> anyhow::Result<StudentInfo> {
     let student = sqlx::query_as!(
         StudentInfo,
-        "SELECT id, name, email FROM student WHERE id = ?",
+        "SELECT id, name, email, locale FROM student WHERE id = ?",
         id
     )
     .fetch_one(database)
@@ -0,0 +1,123 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use time::OffsetDateTime;
+use utoipa::ToSchema;
+
+/// the most facts kept per student, the least recently updated ones are dropped beyond it
+pub const MAX_STUDENT_MEMORIES: i64 = 50;
+/// the longest fact kept, in characters
+const MAX_FACT_LENGTH: usize = 200;
+
+/// A fact the teacher remembers about a student in every book and session
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StudentMemory {
+    pub id: i64,
+    /// e.g. "struggles with the past perfect tense"
+    pub fact: String,
+    #[serde(with = "time::serde::rfc3339")]
+    #[schema(value_type = String)]
+    pub update_time: OffsetDateTime,
+}
+
+/// Changes to what the teacher remembers about the student
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema, ToSchema)]
+pub struct StudentMemoryUpdate {
+    /// Short lasting facts about the student, e.g. "prefers sports examples",
+    /// "struggles with the past perfect tense"
+    #[serde(default)]
+    pub remember: Vec<String>,
+    /// Remembered facts that are no longer true, exactly as remembered
+    #[serde(default)]
+    pub forget: Vec<String>,
+}
+
+/// the fact on one line without surrounding whitespace, cut to [`MAX_FACT_LENGTH`],
+/// `None` if empty
+fn normalize(fact: &str) -> Option<String> {
+    let fact: String = fact
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(MAX_FACT_LENGTH)
+        .collect();
+    (!fact.is_empty()).then_some(fact)
+}
+
+/// the facts remembered about the student, most recently updated first
+pub async fn list(database: &SqlitePool, student_id: i64) -> anyhow::Result<Vec<StudentMemory>> {
+    let memories = sqlx::query_as!(
+        StudentMemory,
+        r#"select id, fact, update_time as "update_time: OffsetDateTime" from student_memory where student_id = ? order by update_time desc, id desc"#,
+        student_id
+    )
+    .fetch_all(database)
+    .await?;
+    Ok(memories)
+}
+
+/// forget and then remember facts, remembering a known fact again marks it as updated
+pub async fn update(
+    database: &SqlitePool,
+    student_id: i64,
+    update: StudentMemoryUpdate,
+) -> anyhow::Result<Vec<StudentMemory>> {
+    let now = OffsetDateTime::now_utc();
+    let mut tx = database.begin().await?;
+    for fact in update.forget.iter().filter_map(|fact| normalize(fact)) {
+        sqlx::query!(
+            "delete from student_memory where student_id = ? and lower(fact) = lower(?)",
+            student_id,
+            fact
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    for fact in update.remember.iter().filter_map(|fact| normalize(fact)) {
+        sqlx::query!(
+            "insert into student_memory (student_id, fact, update_time) values (?, ?, ?) \
+            on conflict (student_id, fact) do update set update_time = excluded.update_time",
+            student_id,
+            fact,
+            now
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    sqlx::query!(
+        "delete from student_memory where student_id = ? and id not in \
+        (select id from student_memory where student_id = ? order by update_time desc, id desc limit ?)",
+        student_id,
+        student_id,
+        MAX_STUDENT_MEMORIES
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    list(database, student_id).await
+}
+
+/// forget everything remembered about the student
+pub async fn clear(database: &SqlitePool, student_id: i64) -> anyhow::Result<()> {
+    sqlx::query!(
+        "delete from student_memory where student_id = ?",
+        student_id
+    )
+    .execute(database)
+    .await?;
+    Ok(())
+}
+
+#[test]
+fn normalize_fact() {
+    assert_eq!(
+        normalize("  prefers\n sports   examples ").as_deref(),
+        Some("prefers sports examples")
+    );
+    assert_eq!(normalize(" \n "), None);
+    assert_eq!(
+        normalize(&"a".repeat(300)).map(|fact| fact.len()),
+        Some(MAX_FACT_LENGTH)
+    );
+}
@@ -1,29 +1,75 @@
+pub mod blocks;
+pub mod capabilities;
+pub mod catalog;
+pub mod eval;
+pub mod filters;
 pub mod messages;
+pub mod suggestions;
+pub mod verify_math;
 
-use std::convert::Infallible;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Instant;
 
-use async_openai::tools::{ToolCallStreamManager, ToolManager};
+use async_openai::tools::{Tool, ToolCallStreamManager, ToolManager};
 use async_openai::types::{
     ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessageArgs,
     ChatCompletionRequestMessage, ChatCompletionRequestToolMessage,
-    ChatCompletionRequestUserMessage, CreateChatCompletionRequestArgs,
+    ChatCompletionRequestToolMessageContent, ChatCompletionRequestUserMessage,
+    ChatCompletionResponseStream, ChatCompletionStreamOptions, CreateChatCompletionRequest,
+    CreateChatCompletionRequestArgs,
 };
-use axum::response::sse::Event;
+use blocks::{ResponseBlock, ShowBlockTool};
+use capabilities::ClientCapabilities;
+use catalog::ToolCatalog;
+use filters::FilterContext;
 use futures::StreamExt;
-use messages::MessagesManager;
+use messages::history::{MessageRole, read_message};
+use messages::replay::ResponseTiming;
+use messages::tools::{
+    CreateQuizTool, EstimateStudyTimeTool, ReadStudentCodeTool, UpdateScratchpadTool,
+};
+use messages::{MessagesDatabase, MessagesManager};
 use serde::Serialize;
 use sqlx::SqlitePool;
-use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::{self, Sender};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use verify_math::VerifyMathTool;
 
-use crate::ai_utils::{AI_CLIENT, AI_MODEL};
+use crate::abuse::ThrottleReason;
+use crate::ai_utils::{self, Provider};
+use crate::books::content_rating::AgeGate;
+use crate::books::custom_tools::HttpTool;
 use crate::books::library::Library;
-use crate::books::tools::{BookJumpTool, GetChapterTool};
+use crate::books::tools::{
+    BookJumpTool, BookLocation, FindChapterTool, GetBlockTool, GetChapterTool, GetSectionTool,
+    PreviewDatasetTool, ResolvePageTool, SearchBookTool, SemanticSearchTool, SessionBooks,
+};
+use crate::focus;
+use crate::guardrail::{self, Direction, Guardrail, TopicFilter};
+use crate::quota;
+use crate::scratchpad::{self, Scratchpad};
+use crate::spend::{self, BudgetStatus};
+use crate::usage;
+use crate::{i18n, student};
 
 /// The AI Teacher Agent that interacts with students
 pub struct TeacherAgent {
     messages: MessagesManager,
     tool_manager: ToolManager,
+    focus_idle_threshold: time::Duration,
+    locale: String,
+    library: Arc<Library>,
+    provider: Arc<dyn Provider>,
+    /// what the client of the current input renders
+    capabilities: ClientCapabilities,
+    /// replies suggested to the student after each turn, 0 for none
+    suggested_replies: usize,
+    /// keeps the chapters rated above the age of a minor out of the tool results
+    age_gate: Option<AgeGate>,
+    /// the books of the session, tools read the main one unless they name another
+    books: SessionBooks,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -32,6 +78,13 @@ pub enum ResponseEvent {
     Refusal(String),
     ToolCall(ChatCompletionMessageToolCall),
     ToolResult(ChatCompletionRequestToolMessage),
+    Block(ResponseBlock),
+    /// move the reader to this location
+    Navigate(BookLocation),
+    /// replies the student may send next, after the last turn of a response
+    Suggestions(Vec<String>),
+    /// the teacher changed the shared scratchpad
+    Scratchpad(Scratchpad),
 }
 
 impl TeacherAgent {
@@ -47,6 +100,7 @@ impl TeacherAgent {
     }
     pub async fn new(library: Arc<Library>, student_id: i64, book_id: i64) -> anyhow::Result<Self> {
         let database = library.database.clone();
+        let locale = student::get_student_locale(&database, student_id).await?;
 
         if sqlx::query_scalar!(
             "select book_id from teacher_agent where student_id = ? and book_id = ?",
@@ -57,26 +111,171 @@ impl TeacherAgent {
         .await?
         .is_none()
         {
-            return Err(anyhow::anyhow!("Teacher agent not found"));
+            return Err(anyhow::anyhow!(i18n::tr(
+                &locale,
+                "error-teacher-not-found",
+                &[]
+            )));
         }
 
-        let record = sqlx::query!("select ai_model, token_budget FROM agent_setting")
-            .fetch_one(&database)
-            .await?;
+        let record = sqlx::query!(
+            "select ai_model, token_budget, focus_idle_minutes, suggested_replies FROM agent_setting"
+        )
+        .fetch_one(&database)
+        .await?;
         let book = library.get_book(book_id).await?;
-        let messages =
-            MessagesManager::load(student_id, &book, record.token_budget as u64, database).await?;
+        let provider =
+            ai_utils::resolve_provider(&database, Some(student_id), Some(book_id)).await?;
+        let books = MessagesDatabase::new(
+            book_id,
+            student_id,
+            database.clone(),
+            library.message_store.clone(),
+        )
+        .await?
+        .get_session_books()
+        .await?;
+        let mut linked_books = Vec::new();
+        for linked_id in &books.linked {
+            linked_books.push(library.get_book(*linked_id).await?);
+        }
+        let messages = MessagesManager::load(
+            student_id,
+            &book,
+            &linked_books,
+            record.token_budget as u64,
+            database,
+            library.message_store.clone(),
+        )
+        .await?;
+        let age = student::get_student_age(&database, student_id).await?;
+        let age_gate = AgeGate::new(
+            age,
+            book_id,
+            std::iter::once(&book)
+                .chain(&linked_books)
+                .flat_map(|book| {
+                    book.content_ratings
+                        .iter()
+                        .map(move |(number, rating)| (book.id, number, rating))
+                }),
+        );
         let mut tool_manager = ToolManager::default();
-        tool_manager.add_tool(GetChapterTool::new(book_id, library.clone()));
-        tool_manager.add_tool(BookJumpTool::new(book_id, library.clone()));
+        tool_manager.add_tool(GetChapterTool::new(books.clone(), library.clone()));
+        tool_manager.add_tool(GetSectionTool::new(books.clone(), library.clone()));
+        tool_manager.add_tool(BookJumpTool::new(books.clone(), library.clone()));
+        tool_manager.add_tool(FindChapterTool::new(books.clone(), library.clone()));
+        tool_manager.add_tool(SearchBookTool::new(books.clone(), library.clone()));
+        tool_manager.add_tool(SemanticSearchTool::new(
+            books.clone(),
+            library.clone(),
+            student_id,
+        ));
+        tool_manager.add_tool(ResolvePageTool::new(books.clone(), library.clone()));
+        tool_manager.add_tool(GetBlockTool::new(books.clone(), library.clone()));
+        tool_manager.add_tool(PreviewDatasetTool::new(books.clone(), library.clone()));
+        tool_manager.add_tool(EstimateStudyTimeTool::new(
+            messages.get_database(),
+            library.clone(),
+        ));
+        tool_manager.add_tool(CreateQuizTool::new(
+            messages.get_database(),
+            library.clone(),
+        ));
+        tool_manager.add_tool(ReadStudentCodeTool::new(
+            messages.get_database(),
+            library.clone(),
+        ));
+        tool_manager.add_tool(ShowBlockTool);
+        tool_manager.add_tool(VerifyMathTool);
         for tool in messages.get_tools() {
             tool_manager.add_tool_dyn(tool);
         }
+        match library.custom_tools(book_id).await {
+            Ok(book_tools) => {
+                for spec in book_tools.tools {
+                    tool_manager.add_tool_dyn(Arc::new(HttpTool::new(spec)?));
+                }
+                #[cfg(feature = "plugins")]
+                if !book_tools.exercises.is_empty() {
+                    match library.test_runner(book_id, book_tools.exercises).await {
+                        Ok(tool) => tool_manager.add_tool_dyn(Arc::new(tool)),
+                        Err(e) => warn!("ignoring the exercises of book {book_id}: {e:?}"),
+                    }
+                }
+                #[cfg(feature = "plugins")]
+                for spec in book_tools.plugins {
+                    let path = spec.path.clone();
+                    match library.plugin_tool(book_id, spec).await {
+                        Ok(tool) => {
+                            let taken = tool_manager
+                                .get_tools()
+                                .iter()
+                                .any(|existing| existing.function.name == tool.name());
+                            if taken {
+                                warn!(
+                                    "ignoring plugin {path} of book {book_id}, its name is taken"
+                                );
+                            } else {
+                                tool_manager.add_tool_dyn(Arc::new(tool));
+                            }
+                        }
+                        Err(e) => warn!("ignoring plugin {path} of book {book_id}: {e:?}"),
+                    }
+                }
+                #[cfg(not(feature = "plugins"))]
+                if !book_tools.plugins.is_empty() || !book_tools.exercises.is_empty() {
+                    warn!(
+                        "ignoring the plugins and exercises of book {book_id}, built without the plugins feature"
+                    );
+                }
+            }
+            // checked on upload, so only a book edited in the bookbase gets here
+            Err(e) => warn!("ignoring the custom tools of book {book_id}: {e:?}"),
+        }
         Ok(Self {
             messages,
             tool_manager,
+            focus_idle_threshold: time::Duration::minutes(record.focus_idle_minutes),
+            locale,
+            library,
+            provider,
+            capabilities: ClientCapabilities::default(),
+            suggested_replies: record.suggested_replies.max(0) as usize,
+            age_gate,
+            books,
         })
     }
+    /// start or resume teaching `book_id` to the student, for running the teacher in-process
+    pub async fn open(
+        library: Arc<Library>,
+        student_id: i64,
+        book_id: i64,
+    ) -> anyhow::Result<Self> {
+        Self::init(student_id, book_id, library.database.clone()).await?;
+        Self::new(library, student_id, book_id).await
+    }
+
+    /// send a student message and pass the response events to `on_event` as they stream in
+    pub async fn ask(
+        &mut self,
+        message: impl Into<String>,
+        mut on_event: impl FnMut(ResponseEvent),
+    ) -> anyhow::Result<()> {
+        let message: String = message.into();
+        let (tx, mut rx) = mpsc::channel(100);
+        let input = self.input(ChatCompletionRequestUserMessage::from(message.as_str()), tx);
+        // the sender is dropped when the input is done, which ends the receiving loop
+        let receive = async {
+            while let Some(event) = rx.recv().await {
+                on_event(event);
+            }
+        };
+        let (result, ()) = tokio::join!(input, receive);
+        result
+    }
+
+    /// send a student message, the response events are sent to `tx` as they stream in
     pub async fn input<E>(
         &mut self,
         msg: ChatCompletionRequestUserMessage,
@@ -85,28 +284,240 @@ impl TeacherAgent {
     where
         E: From<ResponseEvent> + Send + Sync + 'static,
     {
+        self.input_cancellable(msg, tx, &CancellationToken::new())
+            .await
+    }
+
+    /// [`Self::input`] stopping when `cancel` fires: the content streamed so far is kept as the
+    /// answer, the tool calls the model was making are dropped
+    pub async fn input_cancellable<E>(
+        &mut self,
+        msg: ChatCompletionRequestUserMessage,
+        tx: Sender<E>,
+        cancel: &CancellationToken,
+    ) -> anyhow::Result<()>
+    where
+        E: From<ResponseEvent> + Send + Sync + 'static,
+    {
+        let database = self.messages.get_database();
+        if let Some(event) = quota::check(database.pool(), database.student_id()).await? {
+            self.messages.add_conversation_message(msg).await?;
+            let message_id = match event.reason {
+                ThrottleReason::DailyMessages => "teacher-message-quota-exhausted",
+                _ => "teacher-token-quota-exhausted",
+            };
+            self.reply_offline(message_id, &tx).await?;
+            return Ok(());
+        }
+        usage::record_message(database.pool(), database.student_id(), database.book_id()).await?;
+        // only the requests of this response see the note, the stored history doesn't keep it
+        let focus_note = focus::record_activity(
+            database.pool(),
+            database.student_id(),
+            database.book_id(),
+            self.focus_idle_threshold,
+        )
+        .await?
+        .map(|gap| {
+            i18n::tr(
+                &self.locale,
+                "teacher-focus-return",
+                &[("minutes", gap.whole_minutes().into())],
+            )
+        });
+        // reloaded on every input, like the catalog, so class edits take effect right away
+        let guardrail = Guardrail::for_student(database.pool(), database.student_id()).await?;
+        if let Some(guardrail) = &guardrail {
+            let (_, question, _) = read_message(msg.clone().into());
+            if let Some(topic) = guardrail.screen(&question) {
+                info!(
+                    "question of student {} touches the forbidden topic {}",
+                    database.student_id(),
+                    topic
+                );
+                guardrail::log_event(
+                    database.pool(),
+                    database.student_id(),
+                    database.book_id(),
+                    topic,
+                    Direction::Question,
+                    &question,
+                )
+                .await?;
+                let reply = i18n::tr(
+                    &self.locale,
+                    "teacher-forbidden-topic",
+                    &[("topic", topic.to_string().into())],
+                );
+                self.messages.add_conversation_message(msg).await?;
+                tx.send(ResponseEvent::Content(reply.clone()).into())
+                    .await?;
+                let message = ChatCompletionRequestAssistantMessageArgs::default()
+                    .content(reply)
+                    .build()?;
+                self.messages.add_conversation_message(message).await?;
+                return Ok(());
+            }
+        }
         self.messages.add_conversation_message(msg).await?;
-        let tools = self.tool_manager.get_tools();
+        // reloaded on every input so catalog edits take effect without restarting the agent
+        let catalog = ToolCatalog::load_current(database.pool()).await?;
+        self.provider = self.current_provider().await?;
+        let tools = catalog.apply(
+            self.capabilities
+                .filter_tools(self.tool_manager.get_tools()),
+        );
+        // lines of the response touching a forbidden topic are removed before the student sees them
+        let topic_filter = guardrail.map(|guardrail| {
+            Arc::new(TopicFilter::new(
+                guardrail,
+                i18n::tr(&self.locale, "teacher-forbidden-removed", &[]),
+            ))
+        });
+        let response_filters = match &topic_filter {
+            Some(filter) => Arc::new(
+                self.library
+                    .response_filters
+                    .as_ref()
+                    .clone()
+                    .with_filter(filter.clone()),
+            ),
+            None => self.library.response_filters.clone(),
+        };
+        let filter_context = FilterContext {
+            book_id: database.book_id(),
+        };
         loop {
-            let messages = self.messages.get_messages();
+            if cancel.is_cancelled() {
+                info!("response to student {} stopped", database.student_id());
+                break;
+            }
+            if let BudgetStatus::Exhausted { spent, limit } =
+                spend::check_budget(database.pool(), database.student_id()).await?
+            {
+                info!(
+                    "budget of student {} exhausted: {:.2}/{:.2} USD",
+                    database.student_id(),
+                    spent,
+                    limit
+                );
+                self.reply_offline("teacher-budget-exhausted", &tx).await?;
+                break;
+            }
+            if self.provider.breaker().check().is_err() {
+                self.reply_offline("teacher-ai-unavailable", &tx).await?;
+                break;
+            }
+            self.messages.compact(self.provider.as_ref()).await?;
+            let mut messages = self.messages.get_messages();
+            catalog.apply_to_instruction(&mut messages);
+            if let Some(note) = &focus_note {
+                // right before the message of the student coming back
+                let at = messages
+                    .iter()
+                    .rposition(|message| matches!(message, ChatCompletionRequestMessage::User(_)))
+                    .unwrap_or(messages.len());
+                messages.insert(
+                    at,
+                    ChatCompletionRequestMessage::System(note.clone().into()),
+                );
+            }
             let request = CreateChatCompletionRequestArgs::default()
-                .model(AI_MODEL.as_str())
+                .model(self.provider.model())
                 .messages(messages)
                 .tools(tools.clone())
+                .stream_options(ChatCompletionStreamOptions {
+                    include_usage: true,
+                })
                 .build()
                 .unwrap();
-            let mut stream = AI_CLIENT.chat().create_stream(request).await?;
+            let requested = Instant::now();
+            let started = tokio::select! {
+                biased;
+                _ = cancel.cancelled() => break,
+                started = self.provider.chat_stream(request.clone()) => started,
+            };
+            let mut stream = match started {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("AI provider failed, answering from the lesson notes: {e:?}");
+                    self.reply_offline("teacher-ai-unavailable", &tx).await?;
+                    break;
+                }
+            };
             let mut tool_call_manager = ToolCallStreamManager::new();
+            let mut filter_stream = response_filters.stream(filter_context);
             let mut whole_content = String::new();
+            // the content as the model streamed it, before the filters held back or replaced lines
+            let mut streamed_content = String::new();
             let mut whole_refusal = String::new();
-            while let Some(result) = stream.next().await {
-                let Some(choice) = result?.choices.pop() else {
+            let mut timing = ResponseTiming::default();
+            let mut attempt = 1;
+            // the stream broke off and could not be resumed, the student gets the lesson notes
+            // after the part already sent
+            let mut broken_off = false;
+            while let Some(result) = next_chunk(&mut stream, cancel).await {
+                let mut response = match result {
+                    Ok(response) => response,
+                    Err(e) => {
+                        // a stream breaking off midway counts against the provider as well
+                        self.provider.breaker().record(false);
+                        let e = anyhow::Error::from(e);
+                        let Some(delay) = self.provider.retry_policy().delay(attempt, &e) else {
+                            return Err(e);
+                        };
+                        warn!(
+                            "response stream broke off after {} characters, resuming in {}ms: {e:#}",
+                            streamed_content.len(),
+                            delay.as_millis()
+                        );
+                        tokio::select! {
+                            biased;
+                            _ = cancel.cancelled() => break,
+                            _ = tokio::time::sleep(delay) => {}
+                        }
+                        attempt += 1;
+                        // the tool calls of the broken stream are dropped, the model makes them again
+                        tool_call_manager = ToolCallStreamManager::new();
+                        if self.provider.breaker().check().is_err() {
+                            warn!("AI provider unavailable, the response stays unfinished");
+                            broken_off = true;
+                            break;
+                        }
+                        let request = resume_request(&request, &streamed_content, &self.locale);
+                        let resumed = tokio::select! {
+                            biased;
+                            _ = cancel.cancelled() => break,
+                            resumed = self.provider.chat_stream(request) => resumed,
+                        };
+                        match resumed {
+                            Ok(resumed) => stream = resumed,
+                            Err(e) => {
+                                warn!("failed to resume the response stream: {e:?}");
+                                broken_off = true;
+                                break;
+                            }
+                        }
+                        continue;
+                    }
+                };
+                // with `include_usage` the last chunk carries the usage and no choices, the
+                // metered provider records it
+                if let Some(reported) = response.usage.take() {
+                    self.messages
+                        .set_prompt_tokens(reported.prompt_tokens as u64);
+                }
+                let Some(choice) = response.choices.pop() else {
                     continue;
                 };
                 if let Some(content) = choice.delta.content.as_ref() {
-                    whole_content.push_str(content);
-                    tx.send(ResponseEvent::Content(content.to_string()).into())
-                        .await?;
+                    streamed_content.push_str(content);
+                    let content = filter_stream.push(content);
+                    if !content.is_empty() {
+                        timing.record(requested.elapsed(), &content);
+                        whole_content.push_str(&content);
+                        tx.send(ResponseEvent::Content(content).into()).await?;
+                    }
                 }
                 if let Some(refusal) = choice.delta.refusal.as_ref() {
                     whole_refusal.push_str(refusal);
@@ -115,6 +526,39 @@ impl TeacherAgent {
                     tool_call_manager.process_chunks(tool_call_chunks);
                 }
             }
+            // the last line, held back until the stream ended
+            let content = filter_stream.finish();
+            if !content.is_empty() {
+                timing.record(requested.elapsed(), &content);
+                whole_content.push_str(&content);
+                tx.send(ResponseEvent::Content(content).into()).await?;
+            }
+            if let Some(filter) = &topic_filter {
+                for (topic, line) in filter.take_removed() {
+                    warn!(
+                        "removed a line of the response to student {} on the forbidden topic {}",
+                        database.student_id(),
+                        topic
+                    );
+                    guardrail::log_event(
+                        database.pool(),
+                        database.student_id(),
+                        database.book_id(),
+                        &topic,
+                        Direction::Response,
+                        &line,
+                    )
+                    .await?;
+                }
+            }
+            let cancelled = cancel.is_cancelled();
+            if (cancelled || broken_off) && whole_content.is_empty() && whole_refusal.is_empty() {
+                // stopped before the model said anything, there is no answer to keep
+                if broken_off {
+                    self.reply_offline("teacher-ai-unavailable", &tx).await?;
+                }
+                break;
+            }
             let mut message_builder = ChatCompletionRequestAssistantMessageArgs::default();
             if !whole_content.is_empty() {
                 message_builder.content(whole_content);
@@ -124,39 +568,329 @@ impl TeacherAgent {
                     .await?;
                 message_builder.refusal(whole_refusal);
             }
-            let tool_calls = tool_call_manager.finish_stream();
+            let tool_calls = if cancelled || broken_off {
+                Vec::new()
+            } else {
+                tool_call_manager.finish_stream()
+            };
             if !tool_calls.is_empty() {
                 message_builder.tool_calls(tool_calls.clone());
             }
             let assistant_message = message_builder.build()?;
             self.messages
-                .add_conversation_message(assistant_message)
+                .add_generated_message(assistant_message, self.provider.model(), &timing)
                 .await?;
+            if broken_off {
+                self.reply_offline("teacher-ai-unavailable", &tx).await?;
+                break;
+            }
             if tool_calls.is_empty() {
+                if self.suggested_replies > 0 && !cancelled {
+                    self.suggest_replies(&tx).await?;
+                }
                 break;
             }
             for tool_call in &tool_calls {
                 tx.send(ResponseEvent::ToolCall(tool_call.clone()).into())
                     .await?;
             }
-            let tool_results = self.tool_manager.call(tool_calls).await;
+            let tool_calls = catalog.canonical_calls(tool_calls);
+            for tool_call in &tool_calls {
+                if let Some(block) = ResponseBlock::from_tool_call(tool_call) {
+                    tx.send(self.capabilities.present(block).into()).await?;
+                }
+                if let Some(location) = navigation(tool_call) {
+                    let restricted = self.age_gate.as_ref().is_some_and(|gate| {
+                        gate.check_call(&tool_call.function.arguments).is_some()
+                    });
+                    if self.capabilities.navigation && !restricted {
+                        // only sections of the books of the session are navigated to, with
+                        // their anchor
+                        let book_id = self.books.select(location.book_id);
+                        let book = match book_id {
+                            Ok(book_id) => Some(self.library.get_book(book_id).await?),
+                            Err(_) => None,
+                        };
+                        if let Some(Ok(location)) = book.map(|book| location.resolve(&book)) {
+                            tx.send(ResponseEvent::Navigate(location).into()).await?;
+                        }
+                    }
+                }
+            }
+            let edits_scratchpad = tool_calls
+                .iter()
+                .any(|tool_call| tool_call.function.name == UpdateScratchpadTool::name());
+            let tool_results = self.call_tools(tool_calls).await;
             for tool_result in &tool_results {
                 tx.send(ResponseEvent::ToolResult(tool_result.clone()).into())
                     .await?;
             }
+            if edits_scratchpad {
+                let database = self.messages.get_database();
+                let scratchpad =
+                    scratchpad::get(database.pool(), database.student_id(), database.book_id())
+                        .await?;
+                tx.send(ResponseEvent::Scratchpad(scratchpad).into())
+                    .await?;
+            }
             self.messages
                 .add_conversation_messages(tool_results)
                 .await?;
         }
         Ok(())
     }
+    /// call the tools, answering the calls on restricted chapters with a refusal instead
+    async fn call_tools(
+        &self,
+        tool_calls: Vec<ChatCompletionMessageToolCall>,
+    ) -> Vec<ChatCompletionRequestToolMessage> {
+        let Some(gate) = &self.age_gate else {
+            return self.tool_manager.call(tool_calls).await;
+        };
+        let mut refused = Vec::new();
+        let mut allowed = Vec::new();
+        for tool_call in tool_calls {
+            match gate.check_call(&tool_call.function.arguments) {
+                Some(refusal) => refused.push(ChatCompletionRequestToolMessage {
+                    content: ChatCompletionRequestToolMessageContent::Text(refusal),
+                    tool_call_id: tool_call.id,
+                }),
+                None => allowed.push(tool_call),
+            }
+        }
+        let book_ids: HashMap<String, i64> = allowed
+            .iter()
+            .map(|tool_call| {
+                (
+                    tool_call.id.clone(),
+                    gate.book_of(&tool_call.function.arguments),
+                )
+            })
+            .collect();
+        let mut results = self.tool_manager.call(allowed).await;
+        for result in &mut results {
+            let book_id = book_ids
+                .get(&result.tool_call_id)
+                .copied()
+                .unwrap_or(self.books.main);
+            gate.filter_result(book_id, result);
+        }
+        results.extend(refused);
+        results
+    }
+    /// the provider of the model chosen for the conversation, or the configured one
+    async fn current_provider(&self) -> anyhow::Result<Arc<dyn Provider>> {
+        let database = self.messages.get_database();
+        if let Some(model) = self.messages.model() {
+            match ai_utils::provider_for_model(database.pool(), model).await {
+                Ok(provider) => {
+                    return Ok(usage::metered(
+                        provider,
+                        database.pool(),
+                        database.student_id(),
+                        database.book_id(),
+                    ));
+                }
+                // the model was removed from the providers since it was chosen
+                Err(e) => warn!("falling back to the configured provider: {e:?}"),
+            }
+        }
+        ai_utils::resolve_provider(
+            database.pool(),
+            Some(database.student_id()),
+            Some(database.book_id()),
+        )
+        .await
+    }
+
+    /// switch the conversation to `model` from the next response on, none goes back to the
+    /// configured provider, fails for a model not allowed
+    pub async fn set_model(&mut self, model: Option<String>) -> anyhow::Result<()> {
+        if let Some(model) = &model {
+            ai_utils::provider_for_model(self.messages.get_database().pool(), model).await?;
+        }
+        self.messages.set_model(model).await?;
+        self.provider = self.current_provider().await?;
+        Ok(())
+    }
+
+    /// send replies the student may follow the last exchange with, failing only warns since
+    /// the response itself is complete
+    async fn suggest_replies<E>(&self, tx: &Sender<E>) -> anyhow::Result<()>
+    where
+        E: From<ResponseEvent> + Send + Sync + 'static,
+    {
+        let mut exchange = Vec::new();
+        for message in self.messages.get_conversation().into_iter().rev() {
+            let (role, text, _) = read_message(message);
+            let speaker = match role {
+                MessageRole::User => "Student",
+                MessageRole::Assistant => "Teacher",
+                MessageRole::System | MessageRole::Tool => continue,
+            };
+            if !text.is_empty() {
+                exchange.push(format!("{speaker}: {text}"));
+            }
+            if role == MessageRole::User {
+                break;
+            }
+        }
+        exchange.reverse();
+        match suggestions::generate(
+            self.provider.as_ref(),
+            &self.locale,
+            &exchange.join("\n"),
+            self.suggested_replies,
+        )
+        .await
+        {
+            Ok(replies) if !replies.is_empty() => {
+                tx.send(ResponseEvent::Suggestions(replies).into()).await?;
+            }
+            Ok(_) => {}
+            Err(e) => warn!("failed to suggest replies: {e:?}"),
+        }
+        Ok(())
+    }
+
+    /// adapt the events and tools of the following inputs to what the client renders
+    pub fn set_capabilities(&mut self, capabilities: ClientCapabilities) {
+        self.capabilities = capabilities;
+    }
+
+    /// the model that produced each conversation message, see [`Self::get_conversation`]
+    pub fn get_message_models(&self) -> Vec<Option<String>> {
+        self.messages.get_message_models()
+    }
+
+    /// answer without the model: the localized guidance `message_id` and the lesson notes
+    async fn reply_offline<E>(&mut self, message_id: &str, tx: &Sender<E>) -> anyhow::Result<()>
+    where
+        E: From<ResponseEvent> + Send + Sync + 'static,
+    {
+        let mut reply = i18n::tr(&self.locale, message_id, &[]);
+        match self.lesson_notes().await {
+            Ok(Some(notes)) => {
+                reply.push_str("\n\n");
+                reply.push_str(&notes);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("failed to load the lesson notes: {e:?}"),
+        }
+        tx.send(ResponseEvent::Content(reply.clone()).into())
+            .await?;
+        let message = ChatCompletionRequestAssistantMessageArgs::default()
+            .content(reply)
+            .build()?;
+        self.messages.add_conversation_message(message).await?;
+        Ok(())
+    }
+
+    /// the stored summary and plan of the current chapter (the first one before the student
+    /// started) and the next steps of its open objectives, no model call needed
+    async fn lesson_notes(&self) -> anyhow::Result<Option<String>> {
+        let database = self.messages.get_database();
+        let progress = database.get_book_progress().await?;
+        let book = self.library.get_book(database.book_id()).await?;
+        let Some(chapter) = book
+            .chapters
+            .get(&progress.current_learning_chapter)
+            .or_else(|| book.chapters.values().next())
+        else {
+            return Ok(None);
+        };
+        let mut notes = i18n::tr(
+            &self.locale,
+            "teacher-lesson-notes",
+            &[
+                ("number", chapter.number.to_string().into()),
+                ("name", chapter.name.clone().into()),
+            ],
+        );
+        notes.push_str(&format!(
+            "\n\n{}\n\n{}",
+            chapter.chapter_plan.summary, chapter.chapter_plan.plan
+        ));
+        let next_steps: Vec<_> = progress
+            .chapter_progress
+            .get(&chapter.number)
+            .into_iter()
+            .flat_map(|chapter| &chapter.objectives)
+            .filter(|objective| !objective.completed)
+            .map(|objective| match &objective.next_step {
+                Some(next_step) => format!("- {}: {}", objective.description, next_step),
+                None => format!("- {}", objective.description),
+            })
+            .collect();
+        if !next_steps.is_empty() {
+            notes.push_str("\n\n");
+            notes.push_str(&i18n::tr(&self.locale, "teacher-next-steps", &[]));
+            notes.push('\n');
+            notes.push_str(&next_steps.join("\n"));
+        }
+        Ok(Some(notes))
+    }
+
+    /// store what only lives in memory before the agent is dropped: the context over the token
+    /// budget is summarized, the messages themselves are stored as they are added
+    pub async fn suspend(&mut self) -> anyhow::Result<()> {
+        self.messages.compact(self.provider.as_ref()).await
+    }
+
     pub async fn get_conversation(&self) -> Vec<ChatCompletionRequestMessage> {
         self.messages.get_conversation()
     }
 }
 
-impl From<ResponseEvent> for Result<Event, Infallible> {
+/// the location a `BookJump` call moves the reader to, `None` for other tools
+pub fn navigation(call: &ChatCompletionMessageToolCall) -> Option<BookLocation> {
+    if call.function.name != BookJumpTool::name() {
+        return None;
+    }
+    serde_json::from_str(&call.function.arguments).ok()
+}
+
+/// the next chunk of `stream`, `None` at its end or once `cancel` fires
+async fn next_chunk(
+    stream: &mut ChatCompletionResponseStream,
+    cancel: &CancellationToken,
+) -> Option<<ChatCompletionResponseStream as futures::Stream>::Item> {
+    tokio::select! {
+        biased;
+        _ = cancel.cancelled() => None,
+        chunk = stream.next() => chunk,
+    }
+}
+
+/// `request` again after its stream broke off with `streamed` sent, the student already has
+/// that part so the model is asked to continue it
+fn resume_request(
+    request: &CreateChatCompletionRequest,
+    streamed: &str,
+    locale: &str,
+) -> CreateChatCompletionRequest {
+    let mut request = request.clone();
+    if !streamed.is_empty() {
+        let partial = ChatCompletionRequestAssistantMessageArgs::default()
+            .content(streamed)
+            .build()
+            .expect("an assistant message with content is valid");
+        let note = i18n::tr(locale, "teacher-resume-response", &[]);
+        request
+            .messages
+            .push(ChatCompletionRequestMessage::Assistant(partial));
+        request
+            .messages
+            .push(ChatCompletionRequestMessage::System(note.into()));
+    }
+    request
+}
+
+#[cfg(feature = "server")]
+impl From<ResponseEvent> for Result<axum::response::sse::Event, std::convert::Infallible> {
     fn from(event: ResponseEvent) -> Self {
-        Ok(Event::default().json_data(event).unwrap())
+        Ok(axum::response::sse::Event::default()
+            .json_data(event)
+            .unwrap())
     }
 }
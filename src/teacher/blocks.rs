@@ -0,0 +1,179 @@
+use std::fmt::Write;
+
+use async_openai::{tools::Tool, types::ChatCompletionMessageToolCall};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CalloutStyle {
+    Note,
+    Tip,
+    Warning,
+}
+
+/// A typed piece of a teacher response, for clients that render blocks natively
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ResponseBlock {
+    Table {
+        /// The column headers
+        headers: Vec<String>,
+        /// The rows, each with one cell per header
+        rows: Vec<Vec<String>>,
+        caption: Option<String>,
+    },
+    Code {
+        /// The language of the code, e.g. "rust", empty for plain text
+        language: String,
+        code: String,
+    },
+    /// A question for the student to answer in the conversation, the answer is not shown
+    Quiz {
+        question: String,
+        /// The options to choose from
+        options: Vec<String>,
+        /// Whether more than one option may be chosen
+        multiple: bool,
+    },
+    Callout {
+        style: CalloutStyle,
+        title: Option<String>,
+        text: String,
+    },
+    Diagram {
+        /// The diagram in mermaid syntax
+        source: String,
+        /// What the diagram shows, for students who can't see it
+        description: String,
+    },
+}
+
+impl ResponseBlock {
+    /// the block for clients without block support
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = String::from("\n\n");
+        match self {
+            Self::Table {
+                headers,
+                rows,
+                caption,
+            } => {
+                let cell = |text: &str| text.replace('|', "\\|").replace('\n', " ");
+                let line = |cells: &[String]| {
+                    let cells: Vec<_> = cells.iter().map(|c| cell(c)).collect();
+                    format!("| {} |", cells.join(" | "))
+                };
+                let _ = writeln!(markdown, "{}", line(headers));
+                let _ = writeln!(markdown, "|{}", " --- |".repeat(headers.len()));
+                for row in rows {
+                    let _ = writeln!(markdown, "{}", line(row));
+                }
+                if let Some(caption) = caption {
+                    let _ = writeln!(markdown, "\n_{caption}_");
+                }
+            }
+            Self::Code { language, code } => {
+                let _ = writeln!(markdown, "```{language}\n{}\n```", code.trim_end());
+            }
+            Self::Quiz {
+                question,
+                options,
+                multiple: _,
+            } => {
+                let _ = writeln!(markdown, "**{question}**\n");
+                for (i, option) in options.iter().enumerate() {
+                    let label = (b'A' + (i % 26) as u8) as char;
+                    let _ = writeln!(markdown, "- {label}. {option}");
+                }
+            }
+            Self::Callout { style, title, text } => {
+                let label = match style {
+                    CalloutStyle::Note => "NOTE",
+                    CalloutStyle::Tip => "TIP",
+                    CalloutStyle::Warning => "WARNING",
+                };
+                let _ = writeln!(markdown, "> [!{label}]");
+                if let Some(title) = title {
+                    let _ = writeln!(markdown, "> **{title}**");
+                }
+                for line in text.lines() {
+                    let _ = writeln!(markdown, "> {line}");
+                }
+            }
+            Self::Diagram {
+                source,
+                description,
+            } => {
+                let _ = writeln!(markdown, "```mermaid\n{}\n```\n", source.trim_end());
+                let _ = writeln!(markdown, "_{description}_");
+            }
+        }
+        markdown.push('\n');
+        markdown
+    }
+
+    /// the block shown by a `ShowBlock` call, `None` for other tools
+    pub fn from_tool_call(call: &ChatCompletionMessageToolCall) -> Option<Self> {
+        if call.function.name != ShowBlockTool::name() {
+            return None;
+        }
+        serde_json::from_str::<ShowBlockArgs>(&call.function.arguments)
+            .ok()
+            .map(|args| args.block)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct ShowBlockArgs {
+    /// The block to show
+    pub block: ResponseBlock,
+}
+
+/// Shows a table, code, quiz question, callout or diagram to the student, the teacher sends the
+/// block itself to the client when it sees the call
+pub struct ShowBlockTool;
+
+impl Tool for ShowBlockTool {
+    type Args = ShowBlockArgs;
+    type Output = String;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "ShowBlock".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Show the student a table, a code block, a quiz question, a callout or a diagram, \
+            prefer it to writing these in markdown"
+                .to_string(),
+        )
+    }
+    async fn call(&self, _args: Self::Args) -> anyhow::Result<Self::Output> {
+        Ok("shown to the student".to_string())
+    }
+}
+
+#[test]
+fn block_markdown() {
+    let table = ResponseBlock::Table {
+        headers: vec!["Type".to_string(), "Size".to_string()],
+        rows: vec![vec!["u8".to_string(), "1".to_string()]],
+        caption: None,
+    };
+    assert_eq!(
+        table.to_markdown(),
+        "\n\n| Type | Size |\n| --- | --- |\n| u8 | 1 |\n\n"
+    );
+    let call: ChatCompletionMessageToolCall = serde_json::from_value(serde_json::json!({
+        "id": "call_1",
+        "type": "function",
+        "function": {
+            "name": "ShowBlock",
+            "arguments": r#"{"block": {"kind": "code", "language": "rust", "code": "let x = 1;"}}"#
+        }
+    }))
+    .unwrap();
+    let block = ResponseBlock::from_tool_call(&call).unwrap();
+    assert_eq!(block.to_markdown(), "\n\n```rust\nlet x = 1;\n```\n\n");
+}
@@ -0,0 +1,89 @@
+use async_openai::{tools::Tool, types::ChatCompletionTool};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{ResponseEvent, blocks::ResponseBlock};
+use crate::books::tools::BookJumpTool;
+use crate::teacher::messages::tools::{CreateQuizTool, GradeQuizTool};
+
+/// What a client can render, declared when it starts a session, everything off by default
+/// so older clients only get content, refusal and tool events
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct ClientCapabilities {
+    /// follows navigate events to the chapter, enables the BookJump tool
+    pub navigation: bool,
+    /// renders block events, blocks are sent as markdown content otherwise
+    pub blocks: bool,
+    /// renders diagram blocks, their description is sent instead otherwise
+    pub diagrams: bool,
+    /// renders quiz blocks and answers structured quizzes, enables the quiz tools
+    pub quizzes: bool,
+}
+
+impl ClientCapabilities {
+    /// whether the built-in tool `name` is offered to the model
+    pub fn allows_tool(&self, name: &str) -> bool {
+        if name == BookJumpTool::name() {
+            self.navigation
+        } else if name == CreateQuizTool::name() || name == GradeQuizTool::name() {
+            self.quizzes
+        } else {
+            true
+        }
+    }
+
+    /// the tools of `tools`, by built-in name, the client can follow
+    pub fn filter_tools(&self, tools: Vec<ChatCompletionTool>) -> Vec<ChatCompletionTool> {
+        tools
+            .into_iter()
+            .filter(|tool| self.allows_tool(&tool.function.name))
+            .collect()
+    }
+
+    /// the event showing `block` to the client
+    pub fn present(&self, block: ResponseBlock) -> ResponseEvent {
+        let renders = match &block {
+            ResponseBlock::Diagram { description, .. } if !self.diagrams => {
+                return ResponseEvent::Content(format!("\n\n{description}\n\n"));
+            }
+            ResponseBlock::Quiz { .. } => self.blocks && self.quizzes,
+            _ => self.blocks,
+        };
+        if renders {
+            ResponseEvent::Block(block)
+        } else {
+            ResponseEvent::Content(block.to_markdown())
+        }
+    }
+}
+
+#[test]
+fn client_capabilities() {
+    let old: ClientCapabilities = serde_json::from_str("{}").unwrap();
+    assert!(!old.allows_tool("BookJump"));
+    assert!(!old.allows_tool("CreateQuiz"));
+    assert!(old.allows_tool("GetChapter"));
+
+    let diagram = ResponseBlock::Diagram {
+        source: "graph TD; A-->B".to_string(),
+        description: "A leads to B".to_string(),
+    };
+    let ResponseEvent::Content(content) = old.present(diagram.clone()) else {
+        panic!("a diagram for a client without diagrams");
+    };
+    assert_eq!(content.trim(), "A leads to B");
+
+    let new = ClientCapabilities {
+        blocks: true,
+        diagrams: true,
+        ..Default::default()
+    };
+    assert!(matches!(new.present(diagram), ResponseEvent::Block(_)));
+    let quiz = ResponseBlock::Quiz {
+        question: "1 + 1?".to_string(),
+        options: vec!["1".to_string(), "2".to_string()],
+        multiple: false,
+    };
+    assert!(matches!(new.present(quiz), ResponseEvent::Content(_)));
+}
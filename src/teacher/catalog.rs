@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+
+use async_openai::{
+    tools::Tool,
+    types::{
+        ChatCompletionMessageToolCall, ChatCompletionRequestMessage,
+        ChatCompletionRequestSystemMessageContent, ChatCompletionTool,
+    },
+};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use utoipa::ToSchema;
+
+use super::blocks::ShowBlockTool;
+use super::messages::tools::{
+    AddMemoryTool, CreateFlashcardTool, CreateQuizTool, EstimateStudyTimeTool, GetBookProgressTool,
+    GetTodaysLessonTool, GradeQuizTool, ProgressUpdateTool, ReadScratchpadTool,
+    ReadStudentCodeTool, RecordConfidenceTool, UpdateScratchpadTool, UpdateStudentMemoryTool,
+};
+use super::verify_math::VerifyMathTool;
+use crate::books::tools::{
+    BookJumpTool, FindChapterTool, GetBlockTool, GetChapterTool, GetSectionTool,
+    PreviewDatasetTool, ResolvePageTool, SearchBookTool, SemanticSearchTool,
+};
+
+/// Name and description of a tool as presented to the model
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ToolText {
+    /// the built-in name the server dispatches on, e.g. "BookJump"
+    pub tool_name: String,
+    /// the name shown to the model, the built-in name if `None`
+    pub display_name: Option<String>,
+    /// the description shown to the model, the built-in description if `None`
+    pub description: Option<String>,
+}
+
+fn builtin<T: Tool>() -> ToolText {
+    ToolText {
+        tool_name: T::name(),
+        display_name: None,
+        description: T::description(),
+    }
+}
+
+/// built-in names and descriptions of all teacher tools
+pub fn builtin_tool_texts() -> Vec<ToolText> {
+    vec![
+        builtin::<GetChapterTool>(),
+        builtin::<GetSectionTool>(),
+        builtin::<BookJumpTool>(),
+        builtin::<FindChapterTool>(),
+        builtin::<SearchBookTool>(),
+        builtin::<SemanticSearchTool>(),
+        builtin::<ResolvePageTool>(),
+        builtin::<GetBlockTool>(),
+        builtin::<PreviewDatasetTool>(),
+        builtin::<EstimateStudyTimeTool>(),
+        builtin::<ProgressUpdateTool>(),
+        builtin::<AddMemoryTool>(),
+        builtin::<UpdateStudentMemoryTool>(),
+        builtin::<GetBookProgressTool>(),
+        builtin::<RecordConfidenceTool>(),
+        builtin::<CreateQuizTool>(),
+        builtin::<GradeQuizTool>(),
+        builtin::<CreateFlashcardTool>(),
+        builtin::<GetTodaysLessonTool>(),
+        builtin::<ReadScratchpadTool>(),
+        builtin::<UpdateScratchpadTool>(),
+        builtin::<ReadStudentCodeTool>(),
+        builtin::<ShowBlockTool>(),
+        builtin::<VerifyMathTool>(),
+    ]
+}
+
+/// function names accepted by the chat completion API
+pub(crate) fn is_valid_function_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// The DB-backed overrides of tool names and descriptions for a locale
+#[derive(Debug, Clone, Default)]
+pub struct ToolCatalog {
+    /// built-in name -> override
+    entries: HashMap<String, ToolText>,
+}
+
+impl ToolCatalog {
+    /// the catalog of the locale set in `agent_setting`
+    pub async fn load_current(database: &SqlitePool) -> anyhow::Result<Self> {
+        Self::load(database, &get_locale(database).await?).await
+    }
+
+    pub async fn load(database: &SqlitePool, locale: &str) -> anyhow::Result<Self> {
+        let entries = list_tool_texts(database, locale)
+            .await?
+            .into_iter()
+            .map(|text| (text.tool_name.clone(), text))
+            .collect();
+        Ok(Self { entries })
+    }
+
+    fn display_name<'a>(&'a self, tool_name: &'a str) -> &'a str {
+        self.entries
+            .get(tool_name)
+            .and_then(|text| text.display_name.as_deref())
+            .unwrap_or(tool_name)
+    }
+
+    /// present the tools with the catalog names and descriptions
+    pub fn apply(&self, mut tools: Vec<ChatCompletionTool>) -> Vec<ChatCompletionTool> {
+        for tool in &mut tools {
+            let Some(text) = self.entries.get(&tool.function.name) else {
+                continue;
+            };
+            if let Some(description) = &text.description {
+                tool.function.description = Some(description.clone());
+            }
+            if let Some(name) = &text.display_name {
+                tool.function.name = name.clone();
+            }
+        }
+        tools
+    }
+
+    /// use the display names in the instruction, so it matches the tools the model sees
+    pub fn apply_to_instruction(&self, messages: &mut [ChatCompletionRequestMessage]) {
+        let renamed: Vec<(&str, &str)> = self
+            .entries
+            .values()
+            .filter_map(|text| Some((text.tool_name.as_str(), text.display_name.as_deref()?)))
+            .collect();
+        if renamed.is_empty() {
+            return;
+        }
+        let Some(ChatCompletionRequestMessage::System(instruction)) = messages.first_mut() else {
+            return;
+        };
+        if let ChatCompletionRequestSystemMessageContent::Text(text) = &mut instruction.content {
+            for (tool_name, display_name) in renamed {
+                *text = text
+                    .replace(&format!("[{tool_name}"), &format!("[{display_name}"))
+                    .replace(&format!("**{tool_name}**"), &format!("**{display_name}**"));
+            }
+        }
+    }
+
+    /// map the display names of the model's tool calls back to the built-in names
+    pub fn canonical_calls(
+        &self,
+        mut tool_calls: Vec<ChatCompletionMessageToolCall>,
+    ) -> Vec<ChatCompletionMessageToolCall> {
+        for call in &mut tool_calls {
+            if let Some(text) = self
+                .entries
+                .values()
+                .find(|text| text.display_name.as_deref() == Some(call.function.name.as_str()))
+            {
+                call.function.name = text.tool_name.clone();
+            }
+        }
+        tool_calls
+    }
+}
+
+/// the locale of the tool catalog used by the teacher
+pub async fn get_locale(database: &SqlitePool) -> anyhow::Result<String> {
+    let locale = sqlx::query_scalar!("select locale from agent_setting")
+        .fetch_one(database)
+        .await?;
+    Ok(locale)
+}
+
+pub async fn set_locale(database: &SqlitePool, locale: &str) -> anyhow::Result<()> {
+    sqlx::query!("update agent_setting set locale = ?", locale)
+        .execute(database)
+        .await?;
+    Ok(())
+}
+
+/// the text of every tool in the locale, overrides merged over the built-in text
+pub async fn get_catalog(database: &SqlitePool, locale: &str) -> anyhow::Result<Vec<ToolText>> {
+    let catalog = ToolCatalog::load(database, locale).await?;
+    Ok(builtin_tool_texts()
+        .into_iter()
+        .map(|builtin| match catalog.entries.get(&builtin.tool_name) {
+            Some(text) => ToolText {
+                tool_name: builtin.tool_name,
+                display_name: text.display_name.clone(),
+                description: text.description.clone().or(builtin.description),
+            },
+            None => builtin,
+        })
+        .collect())
+}
+
+pub async fn list_tool_texts(database: &SqlitePool, locale: &str) -> anyhow::Result<Vec<ToolText>> {
+    let texts = sqlx::query_as!(
+        ToolText,
+        "select tool_name, display_name, description from tool_catalog where locale = ? order by tool_name",
+        locale
+    )
+    .fetch_all(database)
+    .await?;
+    Ok(texts)
+}
+
+/// insert or replace the override of a tool, display names must stay unique per locale
+pub async fn set_tool_text(
+    database: &SqlitePool,
+    locale: &str,
+    text: &ToolText,
+) -> anyhow::Result<()> {
+    let builtins = builtin_tool_texts();
+    if !builtins.iter().any(|b| b.tool_name == text.tool_name) {
+        anyhow::bail!("Unknown tool: {}", text.tool_name);
+    }
+    if let Some(name) = &text.display_name {
+        if !is_valid_function_name(name) {
+            anyhow::bail!(
+                "Invalid tool name {name:?}, use 1 to 64 ASCII letters, digits, '_' or '-'"
+            );
+        }
+        let catalog = ToolCatalog::load(database, locale).await?;
+        let taken = builtins
+            .iter()
+            .filter(|b| b.tool_name != text.tool_name)
+            .any(|b| catalog.display_name(&b.tool_name) == name || &b.tool_name == name);
+        if taken {
+            anyhow::bail!("Tool name {name:?} is already used by another tool");
+        }
+    }
+    sqlx::query!(
+        "insert or replace into tool_catalog (locale, tool_name, display_name, description) values (?, ?, ?, ?)",
+        locale,
+        text.tool_name,
+        text.display_name,
+        text.description
+    )
+    .execute(database)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_tool_text(
+    database: &SqlitePool,
+    locale: &str,
+    tool_name: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "delete from tool_catalog where locale = ? and tool_name = ?",
+        locale,
+        tool_name
+    )
+    .execute(database)
+    .await?;
+    Ok(())
+}
+
+#[test]
+fn function_names() {
+    assert!(is_valid_function_name("Buch_Sprung-2"));
+    assert!(!is_valid_function_name("章节"));
+    assert!(!is_valid_function_name(""));
+}
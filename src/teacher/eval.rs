@@ -0,0 +1,353 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{ResponseEvent, TeacherAgent};
+use crate::{
+    ai_utils::{self, Provider},
+    books::library::Library,
+    student,
+};
+
+/// A scripted conversation of a student with the teacher, read from a toml file:
+///
+/// ```toml
+/// name = "asks for the answer of an exercise"
+/// book_id = 1
+/// locale = "en"
+/// rubric = ["Answers in English"]
+///
+/// [[turns]]
+/// student = "Just give me the answer of exercise 3.2"
+/// rubric = ["Gives a hint instead of the answer", "Points to the section explaining it"]
+/// ```
+///
+/// Recorded scenarios also hold the `response` and `tools` of each turn, and are judged
+/// without calling the teacher.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    pub book_id: i64,
+    /// locale of the student, the default one when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// criteria every turn is judged on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rubric: Vec<String>,
+    pub turns: Vec<ScenarioTurn>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScenarioTurn {
+    pub student: String,
+    /// criteria of this turn, judged with those of the scenario
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rubric: Vec<String>,
+    /// the recorded teacher response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+    /// the tools the teacher called for the recorded response
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<String>,
+}
+
+impl Scenario {
+    pub async fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = tokio::fs::read_to_string(path).await?;
+        let scenario: Scenario = toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("invalid scenario {}: {}", path.display(), e))?;
+        if scenario.turns.is_empty() {
+            anyhow::bail!("scenario {} has no turns", path.display());
+        }
+        Ok(scenario)
+    }
+
+    /// the scenario with its responses, to replay it with [`EvalMode::Recorded`]
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        tokio::fs::write(path, toml::to_string_pretty(self)?).await?;
+        Ok(())
+    }
+}
+
+/// the scenario files at `paths`, the `.toml` files of directories, sorted by path
+pub fn scenario_files(paths: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            for entry in std::fs::read_dir(path)? {
+                let entry = entry?.path();
+                if entry.extension().is_some_and(|ext| ext == "toml") {
+                    files.push(entry);
+                }
+            }
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Where the teacher responses of a scenario come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvalMode {
+    /// the teacher answers a throwaway student, filling the responses of the scenario
+    Live,
+    /// the responses recorded in the scenario
+    Recorded,
+}
+
+/// The judge's score of a response on one criterion
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CriterionScore {
+    /// the criterion, as written in the rubric
+    pub criterion: String,
+    /// how well the response meets the criterion, from 0 to 10
+    pub score: u8,
+    /// why, in one or two sentences
+    pub reason: String,
+}
+
+/// The scores of every criterion of a turn, in the order of the rubric
+#[derive(Debug, Deserialize, JsonSchema)]
+struct Judgement {
+    scores: Vec<CriterionScore>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TurnResult {
+    pub student: String,
+    pub response: String,
+    pub tools: Vec<String>,
+    pub scores: Vec<CriterionScore>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScenarioResult {
+    pub name: String,
+    pub turns: Vec<TurnResult>,
+    /// mean of the criterion scores of every turn, 0 when the scenario failed to run
+    pub mean_score: f64,
+    /// why the scenario couldn't be run or judged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ScenarioResult {
+    pub fn failed(name: &str, error: anyhow::Error) -> Self {
+        Self {
+            name: name.to_string(),
+            turns: vec![],
+            mean_score: 0.0,
+            error: Some(error.to_string()),
+        }
+    }
+
+    /// the criteria scored below `threshold`, with their turn index
+    pub fn below(&self, threshold: f64) -> impl Iterator<Item = (usize, &CriterionScore)> {
+        self.turns.iter().enumerate().flat_map(move |(i, turn)| {
+            turn.scores
+                .iter()
+                .filter(move |score| (score.score as f64) < threshold)
+                .map(move |score| (i, score))
+        })
+    }
+}
+
+fn transcript(turns: &[TurnResult]) -> String {
+    let mut transcript = String::new();
+    for turn in turns {
+        transcript.push_str(&format!(
+            "Student: {}\nTeacher: {}\n\n",
+            turn.student, turn.response
+        ));
+    }
+    transcript
+}
+
+async fn judge_turn(
+    judge: &dyn Provider,
+    book_title: &str,
+    history: &[TurnResult],
+    turn: &TurnResult,
+    rubric: &[String],
+) -> anyhow::Result<Vec<CriterionScore>> {
+    let criteria: String = rubric
+        .iter()
+        .enumerate()
+        .map(|(i, criterion)| format!("{}. {}\n", i + 1, criterion))
+        .collect();
+    let tools = if turn.tools.is_empty() {
+        "none".to_string()
+    } else {
+        turn.tools.join(", ")
+    };
+    let prompt = format!(
+        "You are reviewing an AI teacher tutoring a student on the book \"{book_title}\". \
+        Score the teacher's last response on each criterion from 0 (not met at all) to 10 \
+        (fully met), judging only that response in the context of the conversation. \
+        Return one score per criterion, in the same order and with the criterion copied as written.\n\n\
+        Conversation so far:\n{}\
+        Student: {}\nTeacher (the response to score): {}\nTools the teacher called: {tools}\n\n\
+        Criteria:\n{criteria}",
+        transcript(history),
+        turn.student,
+        turn.response
+    );
+    let judgement: Judgement = ai_utils::extract(judge, prompt).await?;
+    if judgement.scores.len() != rubric.len() {
+        anyhow::bail!(
+            "the judge scored {} criteria instead of {}",
+            judgement.scores.len(),
+            rubric.len()
+        );
+    }
+    Ok(judgement
+        .scores
+        .into_iter()
+        .zip(rubric)
+        .map(|(score, criterion)| CriterionScore {
+            criterion: criterion.clone(),
+            score: score.score.min(10),
+            reason: score.reason,
+        })
+        .collect())
+}
+
+/// send the turns of the scenario to the teacher of a new student, filling their responses
+async fn play(library: Arc<Library>, scenario: &mut Scenario) -> anyhow::Result<()> {
+    let database = library.database.clone();
+    let student_id = student::create_student(
+        &database,
+        format!("eval: {}", scenario.name),
+        format!("eval-{:016x}@eval.invalid", rand::random::<u64>()),
+        format!("{:032x}", rand::random::<u128>()),
+    )
+    .await?;
+    let result = async {
+        if let Some(locale) = &scenario.locale {
+            student::set_student_locale(&database, student_id, locale).await?;
+        }
+        let mut teacher = TeacherAgent::open(library.clone(), student_id, scenario.book_id).await?;
+        for turn in &mut scenario.turns {
+            let mut response = String::new();
+            let mut tools = Vec::new();
+            teacher
+                .ask(turn.student.clone(), |event| match event {
+                    ResponseEvent::Content(content) => response.push_str(&content),
+                    ResponseEvent::ToolCall(call) => tools.push(call.function.name),
+                    _ => {}
+                })
+                .await?;
+            turn.response = Some(response);
+            turn.tools = tools;
+        }
+        anyhow::Ok(())
+    }
+    .await;
+    student::delete_student_book(
+        &database,
+        library.message_store.as_ref(),
+        student_id,
+        scenario.book_id,
+    )
+    .await?;
+    student::delete_student(&database, student_id).await?;
+    result
+}
+
+/// Play the scenario against the teacher or take its recorded responses, then score every
+/// turn on its rubric with the `judge` model.
+///
+/// In [`EvalMode::Live`] the responses are written into `scenario`, so it can be saved as a
+/// recording.
+pub async fn run_scenario(
+    library: Arc<Library>,
+    judge: &dyn Provider,
+    scenario: &mut Scenario,
+    mode: EvalMode,
+) -> anyhow::Result<ScenarioResult> {
+    let book_title = library.get_book(scenario.book_id).await?.title.clone();
+    if mode == EvalMode::Live {
+        play(library, scenario).await?;
+    }
+    let mut turns: Vec<TurnResult> = Vec::new();
+    let mut total = 0.0;
+    let mut count = 0;
+    for (i, turn) in scenario.turns.iter().enumerate() {
+        let Some(response) = &turn.response else {
+            anyhow::bail!("turn {} has no recorded response", i + 1);
+        };
+        let mut result = TurnResult {
+            student: turn.student.clone(),
+            response: response.clone(),
+            tools: turn.tools.clone(),
+            scores: vec![],
+        };
+        let rubric: Vec<String> = scenario
+            .rubric
+            .iter()
+            .chain(&turn.rubric)
+            .cloned()
+            .collect();
+        if !rubric.is_empty() {
+            result.scores = judge_turn(judge, &book_title, &turns, &result, &rubric).await?;
+        }
+        for score in &result.scores {
+            total += score.score as f64;
+            count += 1;
+        }
+        turns.push(result);
+    }
+    Ok(ScenarioResult {
+        name: scenario.name.clone(),
+        turns,
+        mean_score: if count == 0 {
+            0.0
+        } else {
+            total / count as f64
+        },
+        error: None,
+    })
+}
+
+#[test]
+fn scenario_file() {
+    let scenario: Scenario = toml::from_str(
+        r#"
+        name = "asks for the answer"
+        book_id = 1
+        rubric = ["Answers in English"]
+
+        [[turns]]
+        student = "Just give me the answer of exercise 3.2"
+        rubric = ["Gives a hint instead of the answer"]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(scenario.turns[0].rubric.len(), 1);
+    assert!(scenario.turns[0].response.is_none());
+    let recorded = toml::to_string_pretty(&scenario).unwrap();
+    assert!(!recorded.contains("response"));
+    let result = ScenarioResult {
+        name: scenario.name,
+        turns: vec![TurnResult {
+            student: String::new(),
+            response: String::new(),
+            tools: vec![],
+            scores: vec![CriterionScore {
+                criterion: "Answers in English".to_string(),
+                score: 6,
+                reason: String::new(),
+            }],
+        }],
+        mean_score: 6.0,
+        error: None,
+    };
+    assert_eq!(result.below(7.0).count(), 1);
+    assert_eq!(result.below(6.0).count(), 0);
+}
@@ -0,0 +1,324 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::Context;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+/// What a filter may know about the response it rewrites
+#[derive(Debug, Clone, Copy)]
+pub struct FilterContext {
+    pub book_id: i64,
+}
+
+/// A rewrite of the model output before the student sees it and it is stored
+pub trait ResponseFilter: Send + Sync {
+    fn name(&self) -> &str;
+    /// rewrite one complete line of the response, without its line break
+    fn apply(&self, line: &str, context: &FilterContext) -> String;
+}
+
+/// Consistent markdown: `-` bullets, a space after heading marks, no trailing spaces
+pub struct MarkdownFilter {
+    bullet: Regex,
+    heading: Regex,
+}
+
+impl Default for MarkdownFilter {
+    fn default() -> Self {
+        Self {
+            bullet: Regex::new(r"^(\s*)[*+](\s+)").unwrap(),
+            heading: Regex::new(r"^(#{1,6})([^#\s])").unwrap(),
+        }
+    }
+}
+
+impl ResponseFilter for MarkdownFilter {
+    fn name(&self) -> &str {
+        "markdown"
+    }
+    fn apply(&self, line: &str, _context: &FilterContext) -> String {
+        let line = line.trim_end();
+        let line = self.bullet.replace(line, "$1-$2");
+        self.heading.replace(&line, "$1 $2").into_owned()
+    }
+}
+
+/// Turns chapter markers like `[[3.2]]` into links to the chapter
+pub struct CitationFilter {
+    marker: Regex,
+    /// link target, `{book_id}` and `{chapter}` are filled in
+    url: String,
+}
+
+impl CitationFilter {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            marker: Regex::new(r"\[\[(\d+(?:\.\d+)*)\]\]").unwrap(),
+            url: url.into(),
+        }
+    }
+}
+
+impl ResponseFilter for CitationFilter {
+    fn name(&self) -> &str {
+        "citation"
+    }
+    fn apply(&self, line: &str, context: &FilterContext) -> String {
+        self.marker
+            .replace_all(line, |caps: &regex::Captures| {
+                let chapter = &caps[1];
+                let url = self
+                    .url
+                    .replace("{book_id}", &context.book_id.to_string())
+                    .replace("{chapter}", chapter);
+                format!("[{chapter}]({url})")
+            })
+            .into_owned()
+    }
+}
+
+/// A phrase to replace, matched case-insensitively
+#[derive(Debug, Clone, Deserialize)]
+pub struct PhraseReplacement {
+    pub phrase: String,
+    #[serde(default)]
+    pub replacement: String,
+}
+
+/// Replaces banned phrases, in the configured order
+pub struct BannedPhrasesFilter {
+    phrases: Vec<(Regex, String)>,
+}
+
+impl BannedPhrasesFilter {
+    pub fn new(phrases: Vec<PhraseReplacement>) -> anyhow::Result<Self> {
+        let phrases = phrases
+            .into_iter()
+            .map(|p| {
+                let regex = RegexBuilder::new(&regex::escape(&p.phrase))
+                    .case_insensitive(true)
+                    .build()?;
+                Ok((regex, p.replacement))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { phrases })
+    }
+}
+
+impl ResponseFilter for BannedPhrasesFilter {
+    fn name(&self) -> &str {
+        "banned_phrases"
+    }
+    fn apply(&self, line: &str, _context: &FilterContext) -> String {
+        let mut line = line.to_string();
+        for (regex, replacement) in &self.phrases {
+            line = regex
+                .replace_all(&line, regex::NoExpand(replacement))
+                .into_owned();
+        }
+        line
+    }
+}
+
+/// Rewrites link targets starting with `from` to start with `to`
+pub struct LinkRewriteFilter {
+    link: Regex,
+    from: String,
+    to: String,
+}
+
+impl LinkRewriteFilter {
+    pub fn new(from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self {
+            link: Regex::new(r"\]\(([^)\s]+)\)").unwrap(),
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+}
+
+impl ResponseFilter for LinkRewriteFilter {
+    fn name(&self) -> &str {
+        "link_rewrite"
+    }
+    fn apply(&self, line: &str, _context: &FilterContext) -> String {
+        self.link
+            .replace_all(line, |caps: &regex::Captures| {
+                match caps[1].strip_prefix(self.from.as_str()) {
+                    Some(rest) => format!("]({}{rest})", self.to),
+                    None => caps[0].to_string(),
+                }
+            })
+            .into_owned()
+    }
+}
+
+/// One entry of the filters file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "filter", rename_all = "snake_case")]
+pub enum FilterConfig {
+    Markdown,
+    Citation { url: String },
+    BannedPhrases { phrases: Vec<PhraseReplacement> },
+    LinkRewrite { from: String, to: String },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct FilterEntry {
+    /// filters run by ascending order, equal orders in file order
+    #[serde(default)]
+    order: i32,
+    #[serde(flatten)]
+    config: FilterConfig,
+}
+
+impl FilterConfig {
+    pub fn build(self) -> anyhow::Result<Arc<dyn ResponseFilter>> {
+        Ok(match self {
+            Self::Markdown => Arc::new(MarkdownFilter::default()),
+            Self::Citation { url } => Arc::new(CitationFilter::new(url)),
+            Self::BannedPhrases { phrases } => Arc::new(BannedPhrasesFilter::new(phrases)?),
+            Self::LinkRewrite { from, to } => Arc::new(LinkRewriteFilter::new(from, to)),
+        })
+    }
+}
+
+/// The filters of a deployment, run in order on every line of a response
+#[derive(Clone, Default)]
+pub struct ResponsePipeline {
+    filters: Vec<Arc<dyn ResponseFilter>>,
+}
+
+impl std::fmt::Debug for ResponsePipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.filters.iter().map(|filter| filter.name()))
+            .finish()
+    }
+}
+
+impl ResponsePipeline {
+    /// the pipeline of a JSON array of filters, e.g.
+    /// `[{"filter": "markdown"}, {"filter": "citation", "url": "/books/{book_id}/{chapter}"}]`
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let mut entries: Vec<FilterEntry> = serde_json::from_str(json)?;
+        // stable, so equal orders keep the file order
+        entries.sort_by_key(|entry| entry.order);
+        let filters = entries
+            .into_iter()
+            .map(|entry| entry.config.build())
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { filters })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read the response filters {}", path.display()))?;
+        Self::from_json(&json)
+    }
+
+    pub fn with_filter(mut self, filter: Arc<dyn ResponseFilter>) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// run all filters on a whole text
+    pub fn apply(&self, text: &str, context: &FilterContext) -> String {
+        let mut stream = self.stream(*context);
+        let mut output = stream.push(text);
+        output.push_str(&stream.finish());
+        output
+    }
+
+    /// filter a response arriving in chunks
+    pub fn stream(&self, context: FilterContext) -> FilterStream<'_> {
+        FilterStream {
+            pipeline: self,
+            context,
+            pending: String::new(),
+        }
+    }
+
+    fn apply_line(&self, line: &str, context: &FilterContext) -> String {
+        let mut line = line.to_string();
+        for filter in &self.filters {
+            line = filter.apply(&line, context);
+        }
+        line
+    }
+}
+
+/// Holds back the unfinished last line of a streamed response, filters see whole lines
+pub struct FilterStream<'a> {
+    pipeline: &'a ResponsePipeline,
+    context: FilterContext,
+    pending: String,
+}
+
+impl FilterStream<'_> {
+    /// the filtered complete lines of `chunk` and the text held back before it
+    pub fn push(&mut self, chunk: &str) -> String {
+        if self.pipeline.is_empty() {
+            return chunk.to_string();
+        }
+        self.pending.push_str(chunk);
+        let Some(end) = self.pending.rfind('\n') else {
+            return String::new();
+        };
+        let rest = self.pending.split_off(end + 1);
+        let lines = std::mem::replace(&mut self.pending, rest);
+        lines
+            .split_inclusive('\n')
+            .map(|line| {
+                let mut filtered = self
+                    .pipeline
+                    .apply_line(line.trim_end_matches('\n'), &self.context);
+                filtered.push('\n');
+                filtered
+            })
+            .collect()
+    }
+
+    /// the filtered text held back, at the end of the response
+    pub fn finish(&mut self) -> String {
+        if self.pending.is_empty() {
+            return String::new();
+        }
+        let line = std::mem::take(&mut self.pending);
+        self.pipeline.apply_line(&line, &self.context)
+    }
+}
+
+#[test]
+fn response_pipeline() {
+    let pipeline = ResponsePipeline::from_json(
+        r#"[
+            {"filter": "link_rewrite", "order": 2, "from": "http://", "to": "https://"},
+            {"filter": "citation", "order": 1, "url": "http://reader/{book_id}/{chapter}"},
+            {"filter": "markdown"},
+            {"filter": "banned_phrases", "phrases": [{"phrase": "as an ai"}]}
+        ]"#,
+    )
+    .unwrap();
+    let names: Vec<_> = pipeline.filters.iter().map(|f| f.name()).collect();
+    assert_eq!(
+        names,
+        ["markdown", "banned_phrases", "citation", "link_rewrite"]
+    );
+    let context = FilterContext { book_id: 7 };
+    let mut stream = pipeline.stream(context);
+    let mut output = stream.push("#Recap  \n* As an AI, see [[3");
+    assert_eq!(output, "# Recap\n");
+    output.push_str(&stream.push(".2]] "));
+    output.push_str(&stream.finish());
+    assert_eq!(output, "# Recap\n- , see [3.2](https://reader/7/3.2)");
+    assert_eq!(
+        pipeline.apply("#Recap  \n* As an AI, see [[3.2]] ", &context),
+        output
+    );
+}
@@ -1,4 +1,9 @@
+pub mod encryption;
+pub mod history;
+pub mod pace;
 pub mod progress;
+pub mod replay;
+pub mod store;
 pub mod tools;
 use std::{
     collections::{BTreeMap, BTreeSet},
@@ -7,14 +12,32 @@ use std::{
 
 use anyhow::bail;
 use async_openai::{tools::ToolDyn, types::ChatCompletionRequestMessage};
-use progress::{BookProgress, ChapterObjective, ChapterProgress, ChapterStatus};
+use fluent_bundle::FluentValue;
+use history::{MessageRole, read_message};
+use pace::StudyPace;
+use progress::{BookProgress, ChapterObjective, ChapterProgress, ChapterStatus, ConfidenceCheckIn};
+use replay::ResponseTiming;
 use sqlx::SqlitePool;
+use store::{MessageStore, StoredMessage};
 use time::OffsetDateTime;
-use tools::{AddMemoryTool, GetBookProgressTool, ProgressUpdateTool};
+use tools::{
+    AddMemoryTool, CreateFlashcardTool, GetBookProgressTool, GetTodaysLessonTool, GradeQuizTool,
+    ProgressUpdateTool, ReadScratchpadTool, RecordConfidenceTool, UpdateScratchpadTool,
+    UpdateStudentMemoryTool,
+};
+use tracing::warn;
 
 use crate::{
-    ai_utils::Tokens,
-    books::{book::Book, chapter::ChapterNumber},
+    agent_profile,
+    ai_utils::{self, Provider, Tokens},
+    books::{book::Book, chapter::ChapterNumber, tools::SessionBooks},
+    course::{self, TodaysLesson},
+    guardrail::Guardrail,
+    i18n, project,
+    prompts::{PromptName, PromptTemplates},
+    scripting::{self, HookEvent},
+    student, student_memory,
+    utils::now_local,
 };
 
 #[derive(Debug, Clone)]
@@ -22,94 +45,192 @@ pub struct MessagesDatabase {
     book_id: i64,
     student_id: i64,
     database: SqlitePool,
+    store: Arc<dyn MessageStore>,
 }
 
 impl MessagesDatabase {
-    pub async fn new(book_id: i64, student_id: i64, database: SqlitePool) -> anyhow::Result<Self> {
+    pub async fn new(
+        book_id: i64,
+        student_id: i64,
+        database: SqlitePool,
+        store: Arc<dyn MessageStore>,
+    ) -> anyhow::Result<Self> {
         Ok(Self {
             book_id,
             student_id,
             database,
+            store,
         })
     }
+    pub fn book_id(&self) -> i64 {
+        self.book_id
+    }
+    pub fn student_id(&self) -> i64 {
+        self.student_id
+    }
+    pub fn pool(&self) -> &SqlitePool {
+        &self.database
+    }
+    pub async fn get_locale(&self) -> anyhow::Result<String> {
+        student::get_student_locale(&self.database, self.student_id).await
+    }
     pub async fn get_instruction(&self) -> anyhow::Result<String> {
         let student_name =
             sqlx::query_scalar!("select name from student where id = ?", self.student_id)
                 .fetch_one(&self.database)
                 .await?;
-        let book_name = sqlx::query_scalar!("select title from book where id = ?", self.book_id)
-            .fetch_one(&self.database)
-            .await?;
-        let instruction = format!(
-            r#"
-## Role:
-You are Vera, a sharp-witted AI tutor who loves Agatha Christie, artisanal coffee, linguistics trivia, comic sketching, and noir films. You’re direct, sarcastic yet motivating, expecting {student_name} to keep up while secretly rooting for them.
-
-## Teaching Approach:
-- Plan lessons using {book_name}’s structure via [GetChapterContent].
-- Deliver chapter-based lessons with clear objectives, engaging activities, and progress tracking.
-- Adapt to {student_name}’s needs, balancing critique with encouragement.
-
-## Teaching Process:
-1. **Chapter Intro**: Use [GetChapterContent: "X.Y."] to outline objectives. Set the stage briefly. Example: "Hey, {student_name}, Chapter 1.3 is verbs—sentence superstars. Ready?"
-2. **Guided Reading**: Direct to a section with [BookJump: {{"chapter_number": "X.Y.", "sector_title": "Section Title"}}]. Example: "Check out the verb section in Chapter 1.3."
-3. **Explanation**: Explain one concept in 2-3 sentences, using [AddMemory] for personalization. Example: "Verbs are actions, like ‘run.’ Since you love mysteries, think ‘investigate.’"
-4. **Check**: Ask one question post-explanation. Example: "What’s a verb for a detective story?"
-5. **Feedback**: Encourage or correct, updating [AddMemory]. Example (correct): "‘Snoop’? Nice one, sleuth!" Example (incorrect): "‘Clue’ is a noun. Try an action word."
-6. **Adjust**: Move forward if understood; simplify or revisit (one [BookJump] max) if not. Log issues in [UpdateProgress].
-7. **Summary**: Summarize and log with [UpdateProgress], updating [AddMemory].
-
-## Tools:
-- **GetChapterContent**: Retrieve chapter objectives and content.
-- **BookJump**: Guide to textbook sections.
-- **AddMemory**: Store student data for personalization.
-- **UpdateProgress**: Log progress with objectives and next steps.
-
-## Instructions:
-- **Start**: Introduce Vera and {book_name} with [GetChapterContent: "1.0."]. Begin with Chapter 1.1.
-- **Stay Structured**: Teach one concept at a time, using tools to plan and personalize. Guide back if off-topic.
-- **Engage**: Weave in Vera’s hobbies (e.g., “Tougher than a Christie twist”).
-- **Tool Invocation**: Execute tools internally; do NOT include `[ToolName: ...]` in responses. Integrate results naturally (e.g., [BookJump] becomes "Read this section").
-- **Constraints**:
-  - One concept, one question per step.
-  - Responses must be conversational, tool-syntax-free, and tailored to {student_name}.
-  - If tools fail, assume plausible content and log in [UpdateProgress].
-"#
-        );
-        Ok(instruction)
-    }
-
-    /// return (saved_conversation, unsaved_conversation)
-    pub async fn get_conversation(&self) -> anyhow::Result<Vec<ChatCompletionRequestMessage>> {
-        let conversation: Vec<ChatCompletionRequestMessage> = sqlx::query_scalar!(
-            "select content from history_message where student_id = ? and book_id = ? order by update_time asc",
-            self.student_id,
+        let book = sqlx::query!(
+            "select title, difficulty, tags from book where id = ?",
+            self.book_id
+        )
+        .fetch_one(&self.database)
+        .await?;
+        let locale = self.get_locale().await?;
+        let chapter_list: Vec<String> = sqlx::query!(
+            "select chapter_number, name from chapter where book_id = ? order by chapter_number",
             self.book_id
         )
         .fetch_all(&self.database)
         .await?
         .into_iter()
-        .map(|content| serde_json::from_str::<ChatCompletionRequestMessage>(&content).unwrap())
+        .map(|chapter| format!("{} {}", chapter.chapter_number, chapter.name))
         .collect();
+        let student_profile: Vec<String> = student_memory::list(&self.database, self.student_id)
+            .await?
+            .into_iter()
+            .map(|memory| format!("- {}", memory.fact))
+            .collect();
+        let context = minijinja::context! {
+            student_name => &student_name,
+            student_profile => student_profile.join("\n"),
+            book_title => &book.title,
+            chapter_list => chapter_list.join("\n"),
+            locale => &locale,
+        };
+        let custom = PromptTemplates::load(&self.database)
+            .await?
+            .render(PromptName::TeacherInstruction, context)?;
+        let names: [(&str, FluentValue); 2] = [
+            ("student_name", student_name.into()),
+            ("book_name", book.title.into()),
+        ];
+        let mut instruction =
+            custom.unwrap_or_else(|| i18n::tr(&locale, "teacher-instruction", &names));
+        let mut audience = vec![];
+        if let Some(difficulty) = book.difficulty {
+            let mut args = names.to_vec();
+            args.push(("difficulty", difficulty.into()));
+            audience.push(i18n::tr(&locale, "teacher-book-difficulty", &args));
+        }
+        if !book.tags.is_empty() {
+            let subjects = book.tags.replace(',', ", ");
+            audience.push(i18n::tr(
+                &locale,
+                "teacher-book-subjects",
+                &[("subjects", subjects.into())],
+            ));
+        }
+        if !audience.is_empty() {
+            instruction.push_str(&format!(
+                "\n\n{}\n{}",
+                i18n::tr(&locale, "teacher-book-audience", &[]),
+                audience.join("\n")
+            ));
+        }
+        Ok(instruction)
+    }
+
+    /// the stored conversation with the model that produced each message
+    pub async fn get_conversation(
+        &self,
+    ) -> anyhow::Result<Vec<(ChatCompletionRequestMessage, Option<String>)>> {
+        let conversation = self
+            .store
+            .load(self.student_id, self.book_id)
+            .await?
+            .into_iter()
+            .map(|message| {
+                let content =
+                    serde_json::from_str::<ChatCompletionRequestMessage>(&message.content).unwrap();
+                (content, message.model)
+            })
+            .collect();
         Ok(conversation)
     }
     pub async fn add_conversation_message(
         &self,
         message: &ChatCompletionRequestMessage,
+        model: Option<&str>,
+        timing: Option<&ResponseTiming>,
     ) -> anyhow::Result<()> {
-        let now = OffsetDateTime::now_utc();
-        let content = serde_json::to_string(&message)?;
+        let message = StoredMessage {
+            content: serde_json::to_string(&message)?,
+            update_time: OffsetDateTime::now_utc(),
+            model: model.map(str::to_string),
+            timing: timing.map(serde_json::to_string).transpose()?,
+        };
+        self.store
+            .append(self.student_id, self.book_id, message)
+            .await
+    }
+    /// the model chosen for the conversation, none for the configured provider
+    pub async fn get_model(&self) -> anyhow::Result<Option<String>> {
+        let model = sqlx::query_scalar!(
+            "select model from teacher_agent where student_id = ? and book_id = ?",
+            self.student_id,
+            self.book_id
+        )
+        .fetch_one(&self.database)
+        .await?;
+        Ok(model)
+    }
+    pub async fn set_model(&self, model: Option<&str>) -> anyhow::Result<()> {
         sqlx::query!(
-            "insert into history_message (student_id, book_id, content, update_time) values (?, ?, ?, ?)",
+            "update teacher_agent set model = ? where student_id = ? and book_id = ?",
+            model,
             self.student_id,
-            self.book_id,
-            content,
-            now
+            self.book_id
         )
         .execute(&self.database)
         .await?;
         Ok(())
     }
+    /// the summary of the archived messages, none before the first compaction
+    pub async fn get_conversation_summary(&self) -> anyhow::Result<Option<String>> {
+        let summary = sqlx::query_scalar!(
+            "select conversation_summary from teacher_agent where student_id = ? and book_id = ?",
+            self.student_id,
+            self.book_id
+        )
+        .fetch_one(&self.database)
+        .await?;
+        match summary {
+            Some(summary) => Ok(Some(self.store.open_text(self.student_id, summary).await?)),
+            None => Ok(None),
+        }
+    }
+    /// stored sealed like the messages it summarizes when they are encrypted
+    pub async fn set_conversation_summary(&self, summary: &str) -> anyhow::Result<()> {
+        let summary = self
+            .store
+            .seal_text(self.student_id, summary.to_string())
+            .await?;
+        sqlx::query!(
+            "update teacher_agent set conversation_summary = ? where student_id = ? and book_id = ?",
+            summary,
+            self.student_id,
+            self.book_id
+        )
+        .execute(&self.database)
+        .await?;
+        Ok(())
+    }
+    /// move the oldest `count` conversation messages to the archive
+    pub async fn archive_conversation(&self, count: usize) -> anyhow::Result<()> {
+        self.store
+            .archive(self.student_id, self.book_id, count)
+            .await
+    }
     pub async fn add_memory(&self, memory: String) -> anyhow::Result<()> {
         let memories = sqlx::query_scalar!(
             "select memories from teacher_agent where student_id = ? and book_id = ?",
@@ -144,6 +265,9 @@ You are Vera, a sharp-witted AI tutor who loves Agatha Christie, artisanal coffe
         )
         .fetch_optional(&self.database)
         .await?;
+        let was_completed = record.as_ref().is_some_and(|record| {
+            matches!(ChapterStatus::from(record.status), ChapterStatus::Completed)
+        });
         let new_chapter_progress = if let Some(record) = record {
             let mut old_chapter_progress = ChapterProgress {
                 chapter_number: chapter_progress.chapter_number.clone(),
@@ -169,9 +293,165 @@ You are Vera, a sharp-witted AI tutor who loves Agatha Christie, artisanal coffe
         )
         .execute(&self.database)
         .await?;
+        if !was_completed && matches!(new_chapter_progress.status, ChapterStatus::Completed) {
+            let event = HookEvent::ChapterCompleted {
+                chapter_number: new_chapter_progress.chapter_number.clone(),
+            };
+            scripting::run_hook(&self.database, self.student_id, self.book_id, event).await;
+        }
         Ok(new_chapter_progress)
     }
 
+    /// the main book of the session and the books linked to it
+    pub async fn get_session_books(&self) -> anyhow::Result<SessionBooks> {
+        let linked = sqlx::query_scalar!(
+            "select linked_book_id from session_book where student_id = ? and book_id = ? order by linked_book_id",
+            self.student_id,
+            self.book_id
+        )
+        .fetch_all(&self.database)
+        .await?;
+        Ok(SessionBooks {
+            main: self.book_id,
+            linked,
+        })
+    }
+
+    /// link `linked` books to the session, replacing the linked ones, each must be in the
+    /// student's library
+    pub async fn set_linked_books(&self, linked: &[i64]) -> anyhow::Result<SessionBooks> {
+        for &book_id in linked {
+            if book_id == self.book_id {
+                bail!("Book {} is the main book of the session", book_id);
+            }
+            if !student::is_enrolled(&self.database, self.student_id, book_id).await? {
+                bail!("Book {} is not in the student's library", book_id);
+            }
+        }
+        let mut tx = self.database.begin().await?;
+        sqlx::query!(
+            "delete from session_book where student_id = ? and book_id = ?",
+            self.student_id,
+            self.book_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        for book_id in linked {
+            sqlx::query!(
+                "insert or ignore into session_book (student_id, book_id, linked_book_id) values (?, ?, ?)",
+                self.student_id,
+                self.book_id,
+                book_id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        self.get_session_books().await
+    }
+
+    /// the chapters of the book the student completed
+    pub async fn completed_chapters(&self) -> anyhow::Result<Vec<ChapterNumber>> {
+        let completed = sqlx::query_scalar!(
+            "select chapter_number from chapter_progress where student_id = ? and book_id = ? and status = ?",
+            self.student_id,
+            self.book_id,
+            ChapterStatus::Completed as i64
+        )
+        .fetch_all(&self.database)
+        .await?
+        .into_iter()
+        .map(|number| number.parse())
+        .collect::<Result<Vec<ChapterNumber>, _>>()?;
+        Ok(completed)
+    }
+
+    /// the pacing of the student on their course of the book, `None` without a course
+    pub async fn get_todays_lesson(&self) -> anyhow::Result<Option<TodaysLesson>> {
+        let Some(course) =
+            course::get_course(&self.database, self.student_id, self.book_id).await?
+        else {
+            return Ok(None);
+        };
+        let completed = self.completed_chapters().await?.into_iter().collect();
+        Ok(Some(course.todays_lesson(now_local().date(), &completed)))
+    }
+
+    /// the student's pace on this book, measured on the completed chapters
+    pub async fn get_study_pace(&self, book: &Book) -> anyhow::Result<StudyPace> {
+        // the archived messages are the older ones
+        let mut messages = self
+            .store
+            .load_archived(self.student_id, self.book_id)
+            .await?;
+        messages.extend(self.store.load(self.student_id, self.book_id).await?);
+        let message_times: Vec<OffsetDateTime> = messages
+            .into_iter()
+            .map(|message| message.update_time)
+            .collect();
+        let completed = sqlx::query!(
+            "select chapter_number, update_time from chapter_progress where student_id = ? and book_id = ? and status = ?",
+            self.student_id,
+            self.book_id,
+            ChapterStatus::Completed as i64
+        )
+        .fetch_all(&self.database)
+        .await?
+        .into_iter()
+        .map(|record| Ok((record.chapter_number.parse()?, record.update_time)))
+        .collect::<anyhow::Result<Vec<(ChapterNumber, OffsetDateTime)>>>()?;
+        let average_confidence = sqlx::query_scalar!(
+            r#"select avg(confidence) as "average: f64" from confidence_checkin where student_id = ? and book_id = ?"#,
+            self.student_id,
+            self.book_id
+        )
+        .fetch_one(&self.database)
+        .await?;
+        let mut pace = StudyPace::compute(book, &message_times, &completed);
+        pace.adjust_for_confidence(average_confidence);
+        Ok(pace)
+    }
+
+    pub async fn add_checkin(&self, checkin: &ConfidenceCheckIn) -> anyhow::Result<()> {
+        checkin.validate()?;
+        let chapter_number = checkin.chapter_number.to_string();
+        let confidence = checkin.confidence as i64;
+        let create_time = now_local();
+        sqlx::query!(
+            "insert into confidence_checkin (student_id, book_id, chapter_number, confidence, note, create_time) values (?, ?, ?, ?, ?, ?)",
+            self.student_id,
+            self.book_id,
+            chapter_number,
+            confidence,
+            checkin.note,
+            create_time
+        )
+        .execute(&self.database)
+        .await?;
+        Ok(())
+    }
+
+    /// all check-ins of the student on this book, oldest first
+    pub async fn get_checkins(&self) -> anyhow::Result<Vec<ConfidenceCheckIn>> {
+        let records = sqlx::query!(
+            "select chapter_number, confidence, note, create_time from confidence_checkin where student_id = ? and book_id = ? order by create_time asc",
+            self.student_id,
+            self.book_id
+        )
+        .fetch_all(&self.database)
+        .await?;
+        let mut checkins = Vec::new();
+        for record in records {
+            checkins.push(ConfidenceCheckIn {
+                chapter_number: record.chapter_number.parse()?,
+                confidence: record.confidence as u8,
+                note: record.note,
+                create_time: record.create_time,
+            });
+        }
+        Ok(checkins)
+    }
+
     pub async fn get_book_progress(&self) -> anyhow::Result<BookProgress> {
         let record = sqlx::query!(
             "select current_chapter_number, memories, update_time from teacher_agent where student_id = ? and book_id = ?",
@@ -182,10 +462,17 @@ You are Vera, a sharp-witted AI tutor who loves Agatha Christie, artisanal coffe
         .await?;
         let memories = serde_json::from_str::<BTreeSet<String>>(&record.memories)?;
         let current_learning_chapter = record.current_chapter_number.parse()?;
+        let confidence = self
+            .get_checkins()
+            .await?
+            .into_iter()
+            .map(|checkin| (checkin.chapter_number, checkin.confidence))
+            .collect();
         let mut book_progress = BookProgress {
             current_learning_chapter,
             chapter_progress: BTreeMap::new(),
             memories,
+            confidence,
             update_time: record.update_time,
         };
         let chapter_progresses = sqlx::query!(
@@ -211,23 +498,53 @@ You are Vera, a sharp-witted AI tutor who loves Agatha Christie, artisanal coffe
     }
 }
 
+const COMPACTION_PROMPT: &str = "Summarize this tutoring conversation for the teacher to continue it: \
+    what was covered, what the student understood or struggled with, open questions and anything agreed on. \
+    Keep what still matters from the summary of the conversation before.";
+
+/// the system message carrying the summary of the archived conversation
+fn summary_message(locale: &str, summary: String) -> ChatCompletionRequestMessage {
+    ChatCompletionRequestMessage::System(
+        i18n::tr(
+            locale,
+            "teacher-conversation-summary",
+            &[("summary", summary.into())],
+        )
+        .into(),
+    )
+}
+
 pub struct MessagesManager {
     instruction: ChatCompletionRequestMessage,
     book_info: ChatCompletionRequestMessage,
+    /// what the teacher remembers about the student from earlier sessions
+    student_memory: Option<ChatCompletionRequestMessage>,
+    /// the summary of the archived conversation messages
+    summary: Option<ChatCompletionRequestMessage>,
     conversation: Vec<ChatCompletionRequestMessage>,
+    /// stored messages before `conversation` that were dropped from the context without a summary
+    dropped: usize,
+    /// the model of each conversation message, in step with `conversation`
+    models: Vec<Option<String>>,
+    /// the model chosen for the conversation
+    model: Option<String>,
     token_count: u64,
     token_budget: u64,
     database: MessagesDatabase,
 }
 
 impl MessagesManager {
+    /// the messages of the session of the student on `book`, `linked_books` are the other books
+    /// of the session
     pub async fn load(
         student_id: i64,
         book: &Book,
+        linked_books: &[Arc<Book>],
         token_budget: u64,
         database: SqlitePool,
+        store: Arc<dyn MessageStore>,
     ) -> anyhow::Result<Self> {
-        let database = MessagesDatabase::new(book.id, student_id, database).await?;
+        let database = MessagesDatabase::new(book.id, student_id, database, store).await?;
 
         let instruction =
             ChatCompletionRequestMessage::System(database.get_instruction().await?.into());
@@ -235,18 +552,116 @@ impl MessagesManager {
         if token_count > token_budget / 4 {
             bail!("Instruction token: {} is too much", token_count);
         }
-        let book_info = ChatCompletionRequestMessage::System(
-            format!("## Book Info\n```toml\n{}\n```", toml::to_string(&book)?).into(),
+        let locale = database.get_locale().await?;
+        let mut book_info = format!(
+            "{}\n```toml\n{}\n```",
+            i18n::tr(&locale, "teacher-book-info", &[]),
+            toml::to_string(&book)?
         );
+        let age = student::get_student_age(database.pool(), student_id).await?;
+        let restricted: Vec<String> = book
+            .restricted_chapters(age)
+            .into_iter()
+            .map(|(number, _)| number.to_string())
+            .collect();
+        if !restricted.is_empty() {
+            book_info.push_str("\n\n");
+            book_info.push_str(&i18n::tr(
+                &locale,
+                "teacher-age-restricted",
+                &[("chapters", restricted.join(", ").into())],
+            ));
+        }
+        if !linked_books.is_empty() {
+            let books: Vec<String> = std::iter::once(book)
+                .chain(linked_books.iter().map(|book| book.as_ref()))
+                .map(|book| {
+                    let mut entry = format!("- {}: {}", book.id, book.title);
+                    let restricted: Vec<String> = book
+                        .restricted_chapters(age)
+                        .into_iter()
+                        .map(|(number, _)| number.to_string())
+                        .collect();
+                    if !restricted.is_empty() {
+                        entry.push_str(&format!(" ({})", restricted.join(", ")));
+                    }
+                    entry
+                })
+                .collect();
+            book_info.push_str("\n\n");
+            book_info.push_str(&i18n::tr(
+                &locale,
+                "teacher-session-books",
+                &[
+                    ("book_id", book.id.into()),
+                    ("books", books.join("\n").into()),
+                ],
+            ));
+        }
+        if let Some(guardrail) = Guardrail::for_student(database.pool(), student_id).await? {
+            let topics: Vec<&str> = guardrail.topics().collect();
+            book_info.push_str("\n\n");
+            book_info.push_str(&i18n::tr(
+                &locale,
+                "teacher-forbidden-topics",
+                &[("topics", topics.join(", ").into())],
+            ));
+        }
+        if let Some(today) = database.get_todays_lesson().await? {
+            book_info.push_str("\n\n");
+            book_info.push_str(&course::pacing_prompt(&locale, &today));
+        }
+        let reviews = scripting::list_reviews(database.pool(), student_id, book.id, true).await?;
+        if let Some(reviews) = scripting::reviews_prompt(&locale, &reviews) {
+            book_info.push_str("\n\n");
+            book_info.push_str(&reviews);
+        }
+        let projects = project::active_projects(database.pool(), student_id, book.id).await?;
+        if let Some(projects) = project::projects_prompt(&locale, &projects) {
+            book_info.push_str("\n\n");
+            book_info.push_str(&projects);
+        }
+        let profile =
+            agent_profile::effective_profile(database.pool(), student_id, book.id).await?;
+        if let Some(style) = profile.prompt(&locale) {
+            book_info.push_str("\n\n");
+            book_info.push_str(&style);
+        }
+        let book_info = ChatCompletionRequestMessage::System(book_info.into());
         let token_count = book_info.tokens();
         if token_count > token_budget / 4 {
             bail!("Book info token: {} is too much", token_count);
         }
-        let conversation = database.get_conversation().await?;
+        let facts: Vec<_> = student_memory::list(database.pool(), student_id)
+            .await?
+            .into_iter()
+            .map(|memory| format!("- {}", memory.fact))
+            .collect();
+        let student_memory = (!facts.is_empty()).then(|| {
+            ChatCompletionRequestMessage::System(
+                i18n::tr(
+                    &locale,
+                    "teacher-student-memory",
+                    &[("facts", facts.join("\n").into())],
+                )
+                .into(),
+            )
+        });
+        let summary = database
+            .get_conversation_summary()
+            .await?
+            .map(|summary| summary_message(&locale, summary));
+        let (conversation, models) = database.get_conversation().await?.into_iter().unzip();
+        let model = database.get_model().await?;
         let mut messages = Self {
             instruction,
             book_info,
+            student_memory,
+            summary,
             conversation,
+            dropped: 0,
+            models,
+            model,
             token_count: 0,
             token_budget,
             database,
@@ -259,6 +674,8 @@ impl MessagesManager {
     pub fn get_messages(&self) -> Vec<ChatCompletionRequestMessage> {
         // get system prompt
         let mut result = vec![self.instruction.clone(), self.book_info.clone()];
+        result.extend(self.student_memory.clone());
+        result.extend(self.summary.clone());
         result.extend(self.conversation.clone());
         result
     }
@@ -267,10 +684,31 @@ impl MessagesManager {
         self.conversation.clone()
     }
 
+    /// the model that produced each conversation message, none for messages not generated
+    pub fn get_message_models(&self) -> Vec<Option<String>> {
+        self.models.clone()
+    }
+
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    /// choose the model of the conversation from the next response on, none for the configured provider
+    pub async fn set_model(&mut self, model: Option<String>) -> anyhow::Result<()> {
+        self.database.set_model(model.as_deref()).await?;
+        self.model = model;
+        Ok(())
+    }
+
     fn update_token_count(&mut self) {
         let mut token_count = 0;
         token_count += self.instruction.tokens();
         token_count += self.book_info.tokens();
+        token_count += self
+            .student_memory
+            .as_ref()
+            .map_or(0, |memory| memory.tokens());
+        token_count += self.summary.as_ref().map_or(0, |summary| summary.tokens());
         for message in &self.conversation {
             token_count += message.tokens();
         }
@@ -281,15 +719,43 @@ impl MessagesManager {
         self.token_count
     }
 
+    /// the prompt tokens the provider counted for the last request, the context is compacted
+    /// to the token budget on them when they are more than the local count
+    pub fn set_prompt_tokens(&mut self, prompt_tokens: u64) {
+        self.token_count = self.token_count.max(prompt_tokens);
+    }
+
     pub async fn add_conversation_message(
         &mut self,
         message: impl Into<ChatCompletionRequestMessage>,
     ) -> anyhow::Result<()> {
-        let message = message.into();
+        self.add_message(message.into(), None, None).await
+    }
+
+    /// add an assistant message with the model that generated it and when its content was
+    /// streamed
+    pub async fn add_generated_message(
+        &mut self,
+        message: impl Into<ChatCompletionRequestMessage>,
+        model: &str,
+        timing: &ResponseTiming,
+    ) -> anyhow::Result<()> {
+        let timing = (!timing.is_empty()).then_some(timing);
+        self.add_message(message.into(), Some(model), timing).await
+    }
+
+    async fn add_message(
+        &mut self,
+        message: ChatCompletionRequestMessage,
+        model: Option<&str>,
+        timing: Option<&ResponseTiming>,
+    ) -> anyhow::Result<()> {
         self.token_count += message.tokens();
-        self.database.add_conversation_message(&message).await?;
+        self.database
+            .add_conversation_message(&message, model, timing)
+            .await?;
         self.conversation.push(message);
-        self.clean_conversation_messages();
+        self.models.push(model.map(str::to_string));
         Ok(())
     }
 
@@ -303,20 +769,113 @@ impl MessagesManager {
         Ok(())
     }
 
+    /// drop the oldest messages from the context until it fits the token budget, they stay
+    /// stored and are archived without a summary by the next [`compact`](Self::compact)
     pub fn clean_conversation_messages(&mut self) {
-        while self.token_count > self.token_budget {
-            let Some(message) = self.conversation.pop() else {
+        while self.token_count > self.token_budget
+            // a tool result can't start the conversation without the call before it
+            || matches!(
+                self.conversation.first(),
+                Some(ChatCompletionRequestMessage::Tool(_))
+            )
+        {
+            if self.conversation.is_empty() {
                 break;
-            };
+            }
+            let message = self.conversation.remove(0);
+            self.models.remove(0);
+            self.dropped += 1;
             self.token_count -= message.tokens();
         }
     }
 
+    /// once the context is over the token budget, summarize the oldest messages into the
+    /// conversation summary and archive them, down to half of the budget, falls back to
+    /// [`clean_conversation_messages`](Self::clean_conversation_messages) if the summary fails
+    pub async fn compact(&mut self, provider: &dyn Provider) -> anyhow::Result<()> {
+        if self.token_count <= self.token_budget {
+            return Ok(());
+        }
+        let target = self.token_budget / 2;
+        let mut count = 0;
+        let mut token_count = self.token_count;
+        while count < self.conversation.len() && token_count > target {
+            token_count -= self.conversation[count].tokens();
+            count += 1;
+        }
+        // keep tool results with their calls
+        while matches!(
+            self.conversation.get(count),
+            Some(ChatCompletionRequestMessage::Tool(_))
+        ) {
+            count += 1;
+        }
+        let mut content = String::new();
+        if let Some(previous) = self.database.get_conversation_summary().await? {
+            content.push_str(&format!(
+                "Summary of the conversation before:\n{previous}\n\n"
+            ));
+        }
+        for message in &self.conversation[..count] {
+            let (role, text, tool_calls) = read_message(message.clone());
+            let speaker = match role {
+                MessageRole::User => "Student",
+                MessageRole::Assistant => "Teacher",
+                // instructions and tool output, the teacher's context holds what matters of them
+                MessageRole::System | MessageRole::Tool => continue,
+            };
+            if !text.is_empty() {
+                content.push_str(&format!("{speaker}: {text}\n"));
+            }
+            if !tool_calls.is_empty() {
+                content.push_str(&format!("{speaker} used: {}\n", tool_calls.join(", ")));
+            }
+        }
+        let limit = (self.token_budget / 8).clamp(100, 1000) as usize;
+        let summary = match ai_utils::summarize(
+            provider,
+            &content,
+            limit,
+            Some(COMPACTION_PROMPT.to_string()),
+        )
+        .await
+        {
+            Ok(summary) => summary,
+            Err(e) => {
+                warn!("failed to summarize the conversation, dropping the oldest messages: {e:?}");
+                self.clean_conversation_messages();
+                return Ok(());
+            }
+        };
+        self.database.set_conversation_summary(&summary).await?;
+        self.database
+            .archive_conversation(self.dropped + count)
+            .await?;
+        self.dropped = 0;
+        self.conversation.drain(..count);
+        self.models.drain(..count);
+        let locale = self.database.get_locale().await?;
+        self.summary = Some(summary_message(&locale, summary));
+        self.update_token_count();
+        Ok(())
+    }
+
+    pub fn get_database(&self) -> MessagesDatabase {
+        self.database.clone()
+    }
+
     pub fn get_tools(&self) -> Vec<Arc<dyn ToolDyn>> {
         vec![
             Arc::new(ProgressUpdateTool::new(self.database.clone())),
             Arc::new(AddMemoryTool::new(self.database.clone())),
+            Arc::new(UpdateStudentMemoryTool::new(self.database.clone())),
             Arc::new(GetBookProgressTool::new(self.database.clone())),
+            Arc::new(RecordConfidenceTool::new(self.database.clone())),
+            Arc::new(GradeQuizTool::new(self.database.clone())),
+            Arc::new(CreateFlashcardTool::new(self.database.clone())),
+            Arc::new(GetTodaysLessonTool::new(self.database.clone())),
+            Arc::new(ReadScratchpadTool::new(self.database.clone())),
+            Arc::new(UpdateScratchpadTool::new(self.database.clone())),
         ]
     }
 }
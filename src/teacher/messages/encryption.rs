@@ -0,0 +1,264 @@
+use std::{fmt::Debug, sync::Arc};
+
+use aes_gcm::{
+    Aes256Gcm, Key, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng},
+};
+use anyhow::{anyhow, bail};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use dashmap::DashMap;
+use futures::future::BoxFuture;
+use sqlx::SqlitePool;
+
+use super::store::{MessageStore, StoredMessage};
+
+/// prefix of encrypted message content, content without it is read as plain text
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const NONCE_LEN: usize = 12;
+
+/// The key encryption key that wraps the per-student data keys,
+/// implement it on top of a KMS to keep the master key out of the server
+pub trait MasterKey: Send + Sync + Debug {
+    fn wrap(&self, data_key: &[u8]) -> BoxFuture<'_, anyhow::Result<String>>;
+    fn unwrap(&self, wrapped: &str) -> BoxFuture<'_, anyhow::Result<Vec<u8>>>;
+}
+
+/// A 256 bit master key kept in memory, read from the `MESSAGE_MASTER_KEY` env var as base64
+pub struct LocalMasterKey {
+    key: Key<Aes256Gcm>,
+}
+
+impl Debug for LocalMasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LocalMasterKey").finish_non_exhaustive()
+    }
+}
+
+impl LocalMasterKey {
+    pub fn new(key: &[u8]) -> anyhow::Result<Self> {
+        if key.len() != 32 {
+            bail!("Master key must be 32 bytes, got {}", key.len());
+        }
+        Ok(Self {
+            key: *Key::<Aes256Gcm>::from_slice(key),
+        })
+    }
+
+    pub fn from_env() -> anyhow::Result<Self> {
+        let key = dotenvy::var("MESSAGE_MASTER_KEY")?;
+        Self::new(&STANDARD.decode(key.trim())?)
+    }
+}
+
+impl MasterKey for LocalMasterKey {
+    fn wrap(&self, data_key: &[u8]) -> BoxFuture<'_, anyhow::Result<String>> {
+        let wrapped = seal(&self.key, data_key);
+        Box::pin(async move { wrapped })
+    }
+
+    fn unwrap(&self, wrapped: &str) -> BoxFuture<'_, anyhow::Result<Vec<u8>>> {
+        let data_key = open(&self.key, wrapped);
+        Box::pin(async move { data_key })
+    }
+}
+
+/// Envelope encryption of message content on top of any [`MessageStore`],
+/// each student gets a random data key stored wrapped by the master key in `student_data_key`
+#[derive(Debug)]
+pub struct EncryptedMessageStore {
+    inner: Arc<dyn MessageStore>,
+    database: SqlitePool,
+    master_key: Arc<dyn MasterKey>,
+    data_keys: DashMap<i64, Key<Aes256Gcm>>,
+}
+
+impl EncryptedMessageStore {
+    pub fn new(
+        inner: Arc<dyn MessageStore>,
+        database: SqlitePool,
+        master_key: Arc<dyn MasterKey>,
+    ) -> Self {
+        Self {
+            inner,
+            database,
+            master_key,
+            data_keys: DashMap::new(),
+        }
+    }
+
+    /// the data key of the student, created on first use
+    async fn data_key(&self, student_id: i64) -> anyhow::Result<Key<Aes256Gcm>> {
+        if let Some(key) = self.data_keys.get(&student_id) {
+            return Ok(*key);
+        }
+        let wrapped = match self.stored_data_key(student_id).await? {
+            Some(wrapped) => wrapped,
+            None => {
+                let key = Aes256Gcm::generate_key(&mut OsRng);
+                let wrapped = self.master_key.wrap(&key).await?;
+                // sessions of the student on other books may create a key at the same time,
+                // the first one stored is used by all of them
+                sqlx::query!(
+                    "insert into student_data_key (student_id, wrapped_key) values (?, ?)
+                    on conflict (student_id) do nothing",
+                    student_id,
+                    wrapped
+                )
+                .execute(&self.database)
+                .await?;
+                self.stored_data_key(student_id)
+                    .await?
+                    .ok_or_else(|| anyhow!("Data key of student {student_id} was not stored"))?
+            }
+        };
+        let key = self.master_key.unwrap(&wrapped).await?;
+        if key.len() != 32 {
+            bail!("Corrupted data key of student {student_id}");
+        }
+        let key = *Key::<Aes256Gcm>::from_slice(&key);
+        self.data_keys.insert(student_id, key);
+        Ok(key)
+    }
+
+    /// the wrapped data key of the student, if it has one
+    async fn stored_data_key(&self, student_id: i64) -> anyhow::Result<Option<String>> {
+        let wrapped = sqlx::query_scalar!(
+            "select wrapped_key from student_data_key where student_id = ?",
+            student_id
+        )
+        .fetch_optional(&self.database)
+        .await?;
+        Ok(wrapped)
+    }
+
+    /// open the sealed contents of `messages`, plaintext ones from before encryption stay as they are
+    async fn decrypt(
+        &self,
+        student_id: i64,
+        mut messages: Vec<StoredMessage>,
+    ) -> anyhow::Result<Vec<StoredMessage>> {
+        if messages
+            .iter()
+            .all(|m| !m.content.starts_with(ENCRYPTED_PREFIX))
+        {
+            return Ok(messages);
+        }
+        let key = self.data_key(student_id).await?;
+        for message in &mut messages {
+            if let Some(sealed) = message.content.strip_prefix(ENCRYPTED_PREFIX) {
+                message.content = String::from_utf8(open(&key, sealed)?)?;
+            }
+        }
+        Ok(messages)
+    }
+}
+
+impl MessageStore for EncryptedMessageStore {
+    fn load(
+        &self,
+        student_id: i64,
+        book_id: i64,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<StoredMessage>>> {
+        Box::pin(async move {
+            let messages = self.inner.load(student_id, book_id).await?;
+            self.decrypt(student_id, messages).await
+        })
+    }
+
+    fn append(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        mut message: StoredMessage,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            let key = self.data_key(student_id).await?;
+            message.content = format!(
+                "{ENCRYPTED_PREFIX}{}",
+                seal(&key, message.content.as_bytes())?
+            );
+            self.inner.append(student_id, book_id, message).await
+        })
+    }
+
+    fn archive(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        count: usize,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        // archived as stored, still sealed
+        self.inner.archive(student_id, book_id, count)
+    }
+
+    fn load_archived(
+        &self,
+        student_id: i64,
+        book_id: i64,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<StoredMessage>>> {
+        Box::pin(async move {
+            let messages = self.inner.load_archived(student_id, book_id).await?;
+            self.decrypt(student_id, messages).await
+        })
+    }
+
+    fn clear(&self, student_id: i64, book_id: i64) -> BoxFuture<'_, anyhow::Result<()>> {
+        self.inner.clear(student_id, book_id)
+    }
+
+    fn count(&self, student_id: i64, book_id: i64) -> BoxFuture<'_, anyhow::Result<i64>> {
+        self.inner.count(student_id, book_id)
+    }
+
+    fn seal_text(&self, student_id: i64, text: String) -> BoxFuture<'_, anyhow::Result<String>> {
+        Box::pin(async move {
+            let key = self.data_key(student_id).await?;
+            Ok(format!(
+                "{ENCRYPTED_PREFIX}{}",
+                seal(&key, text.as_bytes())?
+            ))
+        })
+    }
+
+    fn open_text(&self, student_id: i64, text: String) -> BoxFuture<'_, anyhow::Result<String>> {
+        Box::pin(async move {
+            let Some(sealed) = text.strip_prefix(ENCRYPTED_PREFIX) else {
+                return Ok(text);
+            };
+            let key = self.data_key(student_id).await?;
+            Ok(String::from_utf8(open(&key, sealed)?)?)
+        })
+    }
+}
+
+/// encrypt with a random nonce, returns base64 of nonce || ciphertext
+fn seal(key: &Key<Aes256Gcm>, plaintext: &[u8]) -> anyhow::Result<String> {
+    let cipher = Aes256Gcm::new(key);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| anyhow!("Encryption failed"))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(STANDARD.encode(sealed))
+}
+
+fn open(key: &Key<Aes256Gcm>, sealed: &str) -> anyhow::Result<Vec<u8>> {
+    let sealed = STANDARD.decode(sealed)?;
+    if sealed.len() < NONCE_LEN {
+        bail!("Encrypted content is too short");
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    Aes256Gcm::new(key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("Decryption failed, wrong key or corrupted content"))
+}
+
+#[test]
+fn seal_and_open() {
+    let key = Aes256Gcm::generate_key(&mut OsRng);
+    let sealed = seal(&key, b"hello").unwrap();
+    assert_eq!(open(&key, &sealed).unwrap(), b"hello");
+    let other = Aes256Gcm::generate_key(&mut OsRng);
+    assert!(open(&other, &sealed).is_err());
+}
@@ -0,0 +1,249 @@
+use std::fmt::Write;
+
+use async_openai::types::{
+    ChatCompletionRequestAssistantMessageContent, ChatCompletionRequestAssistantMessageContentPart,
+    ChatCompletionRequestDeveloperMessageContent, ChatCompletionRequestMessage,
+    ChatCompletionRequestSystemMessageContent, ChatCompletionRequestSystemMessageContentPart,
+    ChatCompletionRequestToolMessageContent, ChatCompletionRequestToolMessageContentPart,
+    ChatCompletionRequestUserMessageContent, ChatCompletionRequestUserMessageContentPart,
+};
+use serde::{Deserialize, Serialize};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use utoipa::ToSchema;
+
+use super::store::{MessageStore, StoredMessage};
+use crate::pagination::{PageQuery, Paginated, SortOrder};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageRole {
+    System,
+    User,
+    Assistant,
+    Tool,
+}
+
+/// A stored conversation message, including the ones trimmed from the teacher's context
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct HistoryEntry {
+    /// position in the whole conversation, the cursor of the history pages
+    pub position: usize,
+    pub role: MessageRole,
+    pub content: String,
+    /// names of the tools an assistant message called
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<String>,
+    /// the model that produced an assistant message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub time: OffsetDateTime,
+}
+
+/// Which messages of the history to list, all by default
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HistoryFilter {
+    pub book_id: i64,
+    pub role: Option<MessageRole>,
+    /// only messages stored at or after this time
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub from: Option<OffsetDateTime>,
+    /// only messages stored before this time
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub to: Option<OffsetDateTime>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.role.is_none_or(|role| role == entry.role)
+            && self.from.is_none_or(|from| entry.time >= from)
+            && self.to.is_none_or(|to| entry.time < to)
+    }
+}
+
+/// the role, the text and the names of the called tools of a message
+pub fn read_message(message: ChatCompletionRequestMessage) -> (MessageRole, String, Vec<String>) {
+    let mut tool_calls = Vec::new();
+    let (role, content) = match message {
+        ChatCompletionRequestMessage::System(msg) => {
+            let content = match msg.content {
+                ChatCompletionRequestSystemMessageContent::Text(text) => text,
+                ChatCompletionRequestSystemMessageContent::Array(parts) => parts
+                    .into_iter()
+                    .map(|ChatCompletionRequestSystemMessageContentPart::Text(t)| t.text)
+                    .collect(),
+            };
+            (MessageRole::System, content)
+        }
+        // developer messages are instructions as well
+        ChatCompletionRequestMessage::Developer(msg) => {
+            let content = match msg.content {
+                ChatCompletionRequestDeveloperMessageContent::Text(text) => text,
+                ChatCompletionRequestDeveloperMessageContent::Array(parts) => {
+                    parts.into_iter().map(|part| part.text).collect()
+                }
+            };
+            (MessageRole::System, content)
+        }
+        ChatCompletionRequestMessage::User(msg) => {
+            let content = match msg.content {
+                ChatCompletionRequestUserMessageContent::Text(text) => text,
+                ChatCompletionRequestUserMessageContent::Array(parts) => parts
+                    .into_iter()
+                    .filter_map(|part| match part {
+                        ChatCompletionRequestUserMessageContentPart::Text(t) => Some(t.text),
+                        _ => None,
+                    })
+                    .collect(),
+            };
+            (MessageRole::User, content)
+        }
+        ChatCompletionRequestMessage::Assistant(msg) => {
+            let content = match msg.content {
+                Some(ChatCompletionRequestAssistantMessageContent::Text(text)) => text,
+                Some(ChatCompletionRequestAssistantMessageContent::Array(parts)) => parts
+                    .into_iter()
+                    .filter_map(|part| match part {
+                        ChatCompletionRequestAssistantMessageContentPart::Text(t) => Some(t.text),
+                        _ => None,
+                    })
+                    .collect(),
+                None => msg.refusal.unwrap_or_default(),
+            };
+            tool_calls = msg
+                .tool_calls
+                .unwrap_or_default()
+                .into_iter()
+                .map(|call| call.function.name)
+                .collect();
+            (MessageRole::Assistant, content)
+        }
+        ChatCompletionRequestMessage::Tool(msg) => {
+            let content = match msg.content {
+                ChatCompletionRequestToolMessageContent::Text(text) => text,
+                ChatCompletionRequestToolMessageContent::Array(parts) => parts
+                    .into_iter()
+                    .map(|ChatCompletionRequestToolMessageContentPart::Text(t)| t.text)
+                    .collect(),
+            };
+            (MessageRole::Tool, content)
+        }
+        ChatCompletionRequestMessage::Function(msg) => {
+            (MessageRole::Tool, msg.content.unwrap_or_default())
+        }
+    };
+    (role, content, tool_calls)
+}
+
+impl HistoryEntry {
+    fn new(position: usize, stored: StoredMessage) -> anyhow::Result<Self> {
+        let message = serde_json::from_str::<ChatCompletionRequestMessage>(&stored.content)?;
+        let (role, content, tool_calls) = read_message(message);
+        Ok(Self {
+            position,
+            role,
+            content,
+            tool_calls,
+            model: stored.model,
+            time: stored.update_time,
+        })
+    }
+}
+
+/// the messages of a student's conversation on a book matching `filter`, oldest first,
+/// the archived ones included
+pub async fn list(
+    store: &dyn MessageStore,
+    student_id: i64,
+    filter: &HistoryFilter,
+) -> anyhow::Result<Vec<HistoryEntry>> {
+    let mut messages = store.load_archived(student_id, filter.book_id).await?;
+    messages.extend(store.load(student_id, filter.book_id).await?);
+    let mut entries = Vec::new();
+    for (position, stored) in messages.into_iter().enumerate() {
+        let entry = HistoryEntry::new(position, stored)?;
+        if filter.matches(&entry) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// a page of [`list`], keyed by the message positions, oldest first by default
+pub async fn page(
+    store: &dyn MessageStore,
+    student_id: i64,
+    filter: &HistoryFilter,
+    page: &PageQuery,
+) -> anyhow::Result<Paginated<HistoryEntry>> {
+    let entries = list(store, student_id, filter).await?;
+    page.paginate(entries, SortOrder::Asc, |entry| entry.position)
+}
+
+/// the entries as a markdown transcript, one section per message
+pub fn transcript(entries: &[HistoryEntry]) -> String {
+    let mut transcript = String::new();
+    for entry in entries {
+        let role = match entry.role {
+            MessageRole::System => "System",
+            MessageRole::User => "Student",
+            MessageRole::Assistant => "Teacher",
+            MessageRole::Tool => "Tool",
+        };
+        let time = entry.time.format(&Rfc3339).unwrap_or_default();
+        let _ = writeln!(transcript, "### {role} ({time})\n");
+        if !entry.content.is_empty() {
+            let _ = writeln!(transcript, "{}\n", entry.content.trim_end());
+        }
+        if !entry.tool_calls.is_empty() {
+            let _ = writeln!(transcript, "_Tools: {}_\n", entry.tool_calls.join(", "));
+        }
+    }
+    transcript
+}
+
+#[test]
+fn history_filter_and_transcript() {
+    use time::macros::datetime;
+
+    let stored = |content: serde_json::Value, time| StoredMessage {
+        content: content.to_string(),
+        update_time: time,
+        model: None,
+        timing: None,
+    };
+    let entries: Vec<_> = [
+        stored(
+            serde_json::json!({"role": "user", "content": "What is a monad?"}),
+            datetime!(2025-01-01 10:00 UTC),
+        ),
+        stored(
+            serde_json::json!({"role": "assistant", "content": "A monoid in the category of endofunctors."}),
+            datetime!(2025-01-01 10:01 UTC),
+        ),
+        stored(
+            serde_json::json!({"role": "user", "content": "Thanks"}),
+            datetime!(2025-01-02 09:00 UTC),
+        ),
+    ]
+    .into_iter()
+    .enumerate()
+    .map(|(position, stored)| HistoryEntry::new(position, stored).unwrap())
+    .collect();
+
+    let filter = HistoryFilter {
+        role: Some(MessageRole::User),
+        to: Some(datetime!(2025-01-02 00:00 UTC)),
+        ..Default::default()
+    };
+    let matching: Vec<_> = entries.iter().filter(|e| filter.matches(e)).collect();
+    assert_eq!(matching.len(), 1);
+    assert_eq!(matching[0].content, "What is a monad?");
+
+    let transcript = transcript(&entries[..2]);
+    assert_eq!(
+        transcript,
+        "### Student (2025-01-01T10:00:00Z)\n\nWhat is a monad?\n\n\
+         ### Teacher (2025-01-01T10:01:00Z)\n\nA monoid in the category of endofunctors.\n\n"
+    );
+}
@@ -0,0 +1,95 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+use utoipa::ToSchema;
+
+use crate::books::{book::Book, chapter::ChapterNumber};
+
+/// messages further apart than this belong to different study sessions
+pub const SESSION_GAP: Duration = Duration::minutes(15);
+/// studying with a tutor takes longer than reading, used until the student has history
+pub const DEFAULT_PACE_FACTOR: f64 = 2.0;
+const MIN_PACE_FACTOR: f64 = 0.5;
+const MAX_PACE_FACTOR: f64 = 4.0;
+
+/// How fast a student gets through the book compared to the plain reading time
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StudyPace {
+    /// minutes spent studying per minute of estimated reading time
+    pub pace_factor: f64,
+    /// whether the factor comes from the student's own completed chapters
+    pub based_on_history: bool,
+}
+
+impl StudyPace {
+    /// `message_times` are the times of the conversation messages in ascending order,
+    /// `completed` the chapters the student has completed with the time they were. The chapters
+    /// are studied one after the other, so only the time up to the last completion went into
+    /// completed chapters; what came after is spent on a chapter not finished yet.
+    pub fn compute(
+        book: &Book,
+        message_times: &[OffsetDateTime],
+        completed: &[(ChapterNumber, OffsetDateTime)],
+    ) -> Self {
+        let last_completion = completed.iter().map(|(_, time)| *time).max();
+        let studied: Duration = message_times
+            .windows(2)
+            .filter(|w| last_completion.is_some_and(|last| w[1] <= last))
+            .map(|w| w[1] - w[0])
+            .filter(|gap| *gap < SESSION_GAP)
+            .sum();
+        let reading_minutes: u64 = completed
+            .iter()
+            .filter_map(|(number, _)| book.chapters.get(number))
+            .map(|ch| ch.stats.reading_minutes)
+            .sum();
+        if reading_minutes == 0 || studied.is_zero() {
+            return Self {
+                pace_factor: DEFAULT_PACE_FACTOR,
+                based_on_history: false,
+            };
+        }
+        let pace_factor = studied.whole_minutes() as f64 / reading_minutes as f64;
+        Self {
+            pace_factor: pace_factor.clamp(MIN_PACE_FACTOR, MAX_PACE_FACTOR),
+            based_on_history: true,
+        }
+    }
+
+    /// slow down for students who report low confidence, `average` is on the 1-5 scale
+    pub fn adjust_for_confidence(&mut self, average: Option<f64>) {
+        if let Some(average) = average {
+            self.pace_factor = (self.pace_factor * (1.0 + (3.0 - average) * 0.1))
+                .clamp(MIN_PACE_FACTOR, MAX_PACE_FACTOR);
+        }
+    }
+
+    pub fn estimate(&self, book: &Book, chapter_number: &ChapterNumber) -> Option<StudyEstimate> {
+        let chapter = book.chapters.get(chapter_number)?;
+        let reading_minutes = chapter.stats.reading_minutes;
+        Some(StudyEstimate {
+            chapter_number: chapter_number.clone(),
+            reading_minutes,
+            estimated_minutes: (reading_minutes as f64 * self.pace_factor).ceil() as u64,
+            pace: self.clone(),
+        })
+    }
+}
+
+/// Calibrated time a student needs for a chapter
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StudyEstimate {
+    pub chapter_number: ChapterNumber,
+    /// plain reading time of the chapter
+    pub reading_minutes: u64,
+    /// reading time adjusted by the student's pace
+    pub estimated_minutes: u64,
+    pub pace: StudyPace,
+}
+
+/// The chapter to estimate the study time for
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct EstimateQuery {
+    /// The chapter number, e.g. "3.", "4.2."
+    pub chapter_number: ChapterNumber,
+}
@@ -7,6 +7,7 @@ use crate::utils::now_local;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
+use utoipa::ToSchema;
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, Hash, JsonSchema)]
 #[repr(i64)]
@@ -107,6 +108,38 @@ impl ChapterProgress {
     }
 }
 
+/// A self-reported confidence of the student on a chapter
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, ToSchema)]
+pub struct ConfidenceCheckIn {
+    /// The chapter number the check-in is about, e.g. "3.", "4.2."
+    pub chapter_number: ChapterNumber,
+    /// How confident the student feels, from 1 (lost) to 5 (fully confident)
+    pub confidence: u8,
+    /// What the student said about how they feel, if anything
+    pub note: Option<String>,
+    /// The time of the check-in, set by the server when it is recorded
+    #[serde(
+        skip_deserializing,
+        default = "now_local",
+        serialize_with = "time::serde::rfc3339::serialize"
+    )]
+    #[schemars(skip)]
+    #[schema(value_type = String, read_only)]
+    pub create_time: OffsetDateTime,
+}
+
+impl ConfidenceCheckIn {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !(1..=5).contains(&self.confidence) {
+            anyhow::bail!(
+                "Confidence must be between 1 and 5, got {}",
+                self.confidence
+            );
+        }
+        Ok(())
+    }
+}
+
 /// Tracks student progress through book chapters and learning objectives
 #[derive(Debug, Clone, Deserialize, Serialize, Hash, JsonSchema)]
 pub struct BookProgress {
@@ -118,6 +151,9 @@ pub struct BookProgress {
     /// General notes and feedback about the student's progress through the book
     #[serde(skip_serializing_if = "BTreeSet::is_empty", default)]
     pub memories: BTreeSet<String>,
+    /// The latest self-reported confidence (1-5) for each chapter the student checked in on
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub confidence: BTreeMap<ChapterNumber, u8>,
     /// The time when the book progress was last updated
     #[serde(default = "now_local", with = "time::serde::rfc3339")]
     #[schemars(skip)]
@@ -140,6 +176,7 @@ fn tt() {
         current_learning_chapter: "3.1".parse().unwrap(),
         chapter_progress: BTreeMap::new(),
         memories: BTreeSet::new(),
+        confidence: BTreeMap::new(),
         update_time: now_local(),
     };
     book_progress
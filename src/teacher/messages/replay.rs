@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+use async_openai::types::ChatCompletionRequestMessage;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    history::read_message,
+    store::{MessageStore, StoredMessage},
+};
+
+/// the longest pause replayed between two messages, a student coming back the next day
+/// should not stall the replay
+const MAX_GAP: Duration = Duration::from_secs(5);
+
+/// When the pieces of a streamed assistant message were sent
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResponseTiming {
+    /// milliseconds since the request to the model and the characters of content sent then
+    pub chunks: Vec<(u64, usize)>,
+}
+
+impl ResponseTiming {
+    /// note `content` sent `elapsed` after the request
+    pub fn record(&mut self, elapsed: Duration, content: &str) {
+        self.chunks
+            .push((elapsed.as_millis() as u64, content.chars().count()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// `content` cut into the recorded pieces with the wait before each, at `speed` times the
+    /// original pace, text beyond the recorded pieces comes with the last one
+    pub fn split(&self, content: &str, speed: f64) -> Vec<(Duration, String)> {
+        let mut chars = content.chars();
+        let mut last = 0;
+        let mut pieces: Vec<(Duration, String)> = Vec::with_capacity(self.chunks.len());
+        for &(offset, count) in &self.chunks {
+            let wait = Duration::from_millis(offset.saturating_sub(last)).div_f64(speed);
+            last = offset.max(last);
+            pieces.push((wait, chars.by_ref().take(count).collect()));
+        }
+        let rest: String = chars.collect();
+        match pieces.last_mut() {
+            Some((_, piece)) => piece.push_str(&rest),
+            None if !rest.is_empty() => pieces.push((Duration::ZERO, rest)),
+            None => {}
+        }
+        pieces.retain(|(_, piece)| !piece.is_empty());
+        pieces
+    }
+}
+
+/// A stored message with when to replay it
+#[derive(Debug, Clone)]
+pub struct ReplayMessage {
+    /// the wait before the message
+    pub delay: Duration,
+    pub message: ChatCompletionRequestMessage,
+    /// the content of a streamed assistant message in its original pieces, empty for other
+    /// messages
+    pub pieces: Vec<(Duration, String)>,
+}
+
+/// the messages of a conversation, the archived ones included, paced like they were sent at
+/// `speed` times the original pace, pauses between messages are shortened to a few seconds
+pub async fn load(
+    store: &dyn MessageStore,
+    student_id: i64,
+    book_id: i64,
+    speed: f64,
+) -> anyhow::Result<Vec<ReplayMessage>> {
+    let mut messages = store.load_archived(student_id, book_id).await?;
+    messages.extend(store.load(student_id, book_id).await?);
+    pace(messages, speed)
+}
+
+fn pace(messages: Vec<StoredMessage>, speed: f64) -> anyhow::Result<Vec<ReplayMessage>> {
+    let mut replay = Vec::with_capacity(messages.len());
+    let mut previous = None;
+    for stored in messages {
+        let message = serde_json::from_str::<ChatCompletionRequestMessage>(&stored.content)?;
+        let timing = match &stored.timing {
+            Some(timing) => serde_json::from_str::<ResponseTiming>(timing)?,
+            None => ResponseTiming::default(),
+        };
+        let gap = previous.map_or(Duration::ZERO, |previous| {
+            Duration::try_from(stored.update_time - previous).unwrap_or_default()
+        });
+        previous = Some(stored.update_time);
+        let (delay, pieces) = match &message {
+            // stored once streamed, the pieces carry the pace from the request on
+            ChatCompletionRequestMessage::Assistant(_) if !timing.is_empty() => {
+                let (_, content, _) = read_message(message.clone());
+                (Duration::ZERO, timing.split(&content, speed))
+            }
+            _ => (gap.min(MAX_GAP).div_f64(speed), Vec::new()),
+        };
+        replay.push(ReplayMessage {
+            delay,
+            message,
+            pieces,
+        });
+    }
+    Ok(replay)
+}
+
+#[test]
+fn replay_pace() {
+    use time::macros::datetime;
+
+    let timing = ResponseTiming {
+        chunks: vec![(800, 5), (900, 6), (1300, 1)],
+    };
+    assert_eq!(
+        timing.split("Hello, world!", 2.0),
+        [
+            (Duration::from_millis(400), "Hello".to_string()),
+            (Duration::from_millis(50), ", worl".to_string()),
+            (Duration::from_millis(200), "d!".to_string()),
+        ]
+    );
+
+    let messages = vec![
+        StoredMessage {
+            content: serde_json::json!({"role": "user", "content": "Hi"}).to_string(),
+            update_time: datetime!(2025-01-01 10:00 UTC),
+            model: None,
+            timing: None,
+        },
+        StoredMessage {
+            content: serde_json::json!({"role": "assistant", "content": "Hello, world!"})
+                .to_string(),
+            update_time: datetime!(2025-01-01 10:00:02 UTC),
+            model: None,
+            timing: Some(serde_json::to_string(&timing).unwrap()),
+        },
+        StoredMessage {
+            content: serde_json::json!({"role": "user", "content": "Thanks"}).to_string(),
+            update_time: datetime!(2025-01-02 09:00 UTC),
+            model: None,
+            timing: None,
+        },
+    ];
+    let replay = pace(messages, 1.0).unwrap();
+    assert_eq!(replay[1].delay, Duration::ZERO);
+    assert_eq!(replay[1].pieces.len(), 3);
+    assert_eq!(replay[2].delay, MAX_GAP);
+}
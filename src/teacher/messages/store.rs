@@ -0,0 +1,559 @@
+use std::{fmt::Debug, sync::LazyLock};
+
+use futures::future::BoxFuture;
+use redis::{AsyncCommands, Script, aio::ConnectionManager, streams::StreamRangeReply};
+use sqlx::{PgPool, SqlitePool};
+use time::OffsetDateTime;
+
+/// A serialized conversation message with the time it was stored
+#[derive(Debug, Clone)]
+pub struct StoredMessage {
+    /// the json of a `ChatCompletionRequestMessage`
+    pub content: String,
+    pub update_time: OffsetDateTime,
+    /// the model that produced an assistant message
+    pub model: Option<String>,
+    /// the json of the `ResponseTiming` of a streamed assistant message
+    pub timing: Option<String>,
+}
+
+/// Persistence of the conversation messages of each (student, book) pair,
+/// memories and progress stay in SQLite whatever the backend is
+pub trait MessageStore: Send + Sync + Debug {
+    /// all messages of the conversation, oldest first
+    fn load(
+        &self,
+        student_id: i64,
+        book_id: i64,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<StoredMessage>>>;
+    fn append(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        message: StoredMessage,
+    ) -> BoxFuture<'_, anyhow::Result<()>>;
+    /// move the oldest `count` messages of the conversation to its archive
+    fn archive(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        count: usize,
+    ) -> BoxFuture<'_, anyhow::Result<()>>;
+    /// the archived messages of the conversation, oldest first
+    fn load_archived(
+        &self,
+        student_id: i64,
+        book_id: i64,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<StoredMessage>>>;
+    /// remove the conversation and its archive
+    fn clear(&self, student_id: i64, book_id: i64) -> BoxFuture<'_, anyhow::Result<()>>;
+    /// the number of messages of the conversation and its archive, those `clear` removes
+    fn count(&self, student_id: i64, book_id: i64) -> BoxFuture<'_, anyhow::Result<i64>>;
+    /// protect conversation text kept outside the store, e.g. its summary, like stored messages
+    fn seal_text(&self, _student_id: i64, text: String) -> BoxFuture<'_, anyhow::Result<String>> {
+        Box::pin(async move { Ok(text) })
+    }
+    /// the text given to [`seal_text`](Self::seal_text)
+    fn open_text(&self, _student_id: i64, text: String) -> BoxFuture<'_, anyhow::Result<String>> {
+        Box::pin(async move { Ok(text) })
+    }
+}
+
+/// The default backend, the `history_message` table of the main database
+#[derive(Debug, Clone)]
+pub struct SqliteMessageStore {
+    database: SqlitePool,
+}
+
+impl SqliteMessageStore {
+    pub fn new(database: SqlitePool) -> Self {
+        Self { database }
+    }
+}
+
+impl MessageStore for SqliteMessageStore {
+    fn load(
+        &self,
+        student_id: i64,
+        book_id: i64,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<StoredMessage>>> {
+        Box::pin(async move {
+            let messages = sqlx::query!(
+                "select content, update_time, model, timing from history_message where student_id = ? and book_id = ? order by update_time asc, id asc",
+                student_id,
+                book_id
+            )
+            .fetch_all(&self.database)
+            .await?
+            .into_iter()
+            .map(|record| StoredMessage {
+                content: record.content,
+                update_time: record.update_time,
+                model: record.model,
+                timing: record.timing,
+            })
+            .collect();
+            Ok(messages)
+        })
+    }
+
+    fn append(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        message: StoredMessage,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            sqlx::query!(
+                "insert into history_message (student_id, book_id, content, update_time, model, timing) values (?, ?, ?, ?, ?, ?)",
+                student_id,
+                book_id,
+                message.content,
+                message.update_time,
+                message.model,
+                message.timing
+            )
+            .execute(&self.database)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn archive(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        count: usize,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            let count = count as i64;
+            let mut tx = self.database.begin().await?;
+            let ids = sqlx::query_scalar!(
+                "select id from history_message where student_id = ? and book_id = ? order by update_time asc, id asc limit ?",
+                student_id,
+                book_id,
+                count
+            )
+            .fetch_all(&mut *tx)
+            .await?;
+            for id in ids {
+                sqlx::query!(
+                    "insert into archived_message (student_id, book_id, content, update_time, model, timing) select student_id, book_id, content, update_time, model, timing from history_message where id = ?",
+                    id
+                )
+                .execute(&mut *tx)
+                .await?;
+                sqlx::query!("delete from history_message where id = ?", id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            tx.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn load_archived(
+        &self,
+        student_id: i64,
+        book_id: i64,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<StoredMessage>>> {
+        Box::pin(async move {
+            let messages = sqlx::query!(
+                "select content, update_time, model, timing from archived_message where student_id = ? and book_id = ? order by update_time asc, id asc",
+                student_id,
+                book_id
+            )
+            .fetch_all(&self.database)
+            .await?
+            .into_iter()
+            .map(|record| StoredMessage {
+                content: record.content,
+                update_time: record.update_time,
+                model: record.model,
+                timing: record.timing,
+            })
+            .collect();
+            Ok(messages)
+        })
+    }
+
+    fn clear(&self, student_id: i64, book_id: i64) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            sqlx::query!(
+                "delete from history_message where student_id = ? and book_id = ?",
+                student_id,
+                book_id
+            )
+            .execute(&self.database)
+            .await?;
+            sqlx::query!(
+                "delete from archived_message where student_id = ? and book_id = ?",
+                student_id,
+                book_id
+            )
+            .execute(&self.database)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn count(&self, student_id: i64, book_id: i64) -> BoxFuture<'_, anyhow::Result<i64>> {
+        Box::pin(async move {
+            let count = sqlx::query_scalar!(
+                r#"select (select count(*) from history_message where student_id = ?1 and book_id = ?2)
+                + (select count(*) from archived_message where student_id = ?1 and book_id = ?2) as "count!: i64""#,
+                student_id,
+                book_id
+            )
+            .fetch_one(&self.database)
+            .await?;
+            Ok(count)
+        })
+    }
+}
+
+/// Keeps each conversation in a Redis stream, for deployments where SQLite write contention
+/// on the messages table becomes the bottleneck
+#[derive(Clone)]
+pub struct RedisMessageStore {
+    /// one multiplexed connection, reconnected when it drops
+    connection: ConnectionManager,
+    prefix: String,
+}
+
+impl Debug for RedisMessageStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisMessageStore")
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+/// moves the `ARGV[1]` oldest entries of the stream `KEYS[1]` to the stream `KEYS[2]` in one
+/// step, so a failure never leaves a message in both or in neither
+static ARCHIVE_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r"
+        local entries = redis.call('XRANGE', KEYS[1], '-', '+', 'COUNT', ARGV[1])
+        for _, entry in ipairs(entries) do
+            redis.call('XADD', KEYS[2], '*', unpack(entry[2]))
+            redis.call('XDEL', KEYS[1], entry[1])
+        end
+        return #entries
+        ",
+    )
+});
+
+impl RedisMessageStore {
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        let client = redis::Client::open(url)?;
+        Ok(Self {
+            connection: ConnectionManager::new(client).await?,
+            prefix: "book-server:messages".to_string(),
+        })
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn key(&self, student_id: i64, book_id: i64) -> String {
+        format!("{}:{student_id}:{book_id}", self.prefix)
+    }
+
+    fn archive_key(&self, student_id: i64, book_id: i64) -> String {
+        format!("{}:archive:{student_id}:{book_id}", self.prefix)
+    }
+
+    /// the messages of a stream range, entries written before `time_ms` have unix seconds in
+    /// `time`
+    fn parse_reply(reply: StreamRangeReply) -> anyhow::Result<Vec<StoredMessage>> {
+        let mut messages = Vec::with_capacity(reply.ids.len());
+        for entry in reply.ids {
+            let update_time = match (entry.get::<i64>("time_ms"), entry.get::<i64>("time")) {
+                (Some(millis), _) => {
+                    OffsetDateTime::from_unix_timestamp_nanos(millis as i128 * 1_000_000)?
+                }
+                (None, Some(seconds)) => OffsetDateTime::from_unix_timestamp(seconds)?,
+                (None, None) => anyhow::bail!("Malformed message entry: {}", entry.id),
+            };
+            let Some(content) = entry.get::<String>("content") else {
+                anyhow::bail!("Malformed message entry: {}", entry.id);
+            };
+            messages.push(StoredMessage {
+                content,
+                update_time,
+                model: entry.get::<String>("model"),
+                timing: entry.get::<String>("timing"),
+            });
+        }
+        Ok(messages)
+    }
+
+    async fn load_stream(&self, key: String) -> anyhow::Result<Vec<StoredMessage>> {
+        let mut conn = self.connection.clone();
+        let reply: StreamRangeReply = conn.xrange_all(key).await?;
+        Self::parse_reply(reply)
+    }
+
+    async fn append_stream(&self, key: String, message: &StoredMessage) -> anyhow::Result<()> {
+        let mut conn = self.connection.clone();
+        let time = (message.update_time.unix_timestamp_nanos() / 1_000_000).to_string();
+        let mut fields = vec![
+            ("content", message.content.as_str()),
+            ("time_ms", time.as_str()),
+        ];
+        if let Some(model) = &message.model {
+            fields.push(("model", model.as_str()));
+        }
+        if let Some(timing) = &message.timing {
+            fields.push(("timing", timing.as_str()));
+        }
+        let _: String = conn.xadd(key, "*", &fields).await?;
+        Ok(())
+    }
+}
+
+impl MessageStore for RedisMessageStore {
+    fn load(
+        &self,
+        student_id: i64,
+        book_id: i64,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<StoredMessage>>> {
+        Box::pin(self.load_stream(self.key(student_id, book_id)))
+    }
+
+    fn append(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        message: StoredMessage,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            self.append_stream(self.key(student_id, book_id), &message)
+                .await
+        })
+    }
+
+    fn archive(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        count: usize,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            let mut conn = self.connection.clone();
+            let _: i64 = ARCHIVE_SCRIPT
+                .key(self.key(student_id, book_id))
+                .key(self.archive_key(student_id, book_id))
+                .arg(count)
+                .invoke_async(&mut conn)
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn load_archived(
+        &self,
+        student_id: i64,
+        book_id: i64,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<StoredMessage>>> {
+        Box::pin(self.load_stream(self.archive_key(student_id, book_id)))
+    }
+
+    fn clear(&self, student_id: i64, book_id: i64) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            let mut conn = self.connection.clone();
+            let _: i64 = conn
+                .del(&[
+                    self.key(student_id, book_id),
+                    self.archive_key(student_id, book_id),
+                ])
+                .await?;
+            Ok(())
+        })
+    }
+
+    fn count(&self, student_id: i64, book_id: i64) -> BoxFuture<'_, anyhow::Result<i64>> {
+        Box::pin(async move {
+            let mut conn = self.connection.clone();
+            let messages: i64 = conn.xlen(self.key(student_id, book_id)).await?;
+            let archived: i64 = conn.xlen(self.archive_key(student_id, book_id)).await?;
+            Ok(messages + archived)
+        })
+    }
+}
+
+/// Keeps conversations in Postgres, for deployments running several servers on one database;
+/// the tables are created on connect and match those of a database migrated with
+/// `book_teacher migrate-db`
+#[derive(Debug, Clone)]
+pub struct PgMessageStore {
+    database: PgPool,
+}
+
+/// the conversation and archive tables, with the columns of [`StoredMessage`]
+const PG_MESSAGE_TABLES: [&str; 2] = ["history_message", "archived_message"];
+
+impl PgMessageStore {
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        let database = PgPool::connect(url).await?;
+        for table in PG_MESSAGE_TABLES {
+            sqlx::query(&format!(
+                "create table if not exists {table} (
+                    id bigint generated by default as identity primary key,
+                    student_id bigint not null,
+                    book_id bigint not null,
+                    content text not null,
+                    update_time timestamptz not null default now(),
+                    model text,
+                    timing text
+                )"
+            ))
+            .execute(&database)
+            .await?;
+            sqlx::query(&format!(
+                "create index if not exists {table}_conversation
+                on {table} (student_id, book_id, update_time, id)"
+            ))
+            .execute(&database)
+            .await?;
+        }
+        Ok(Self { database })
+    }
+
+    async fn load_table(
+        &self,
+        table: &str,
+        student_id: i64,
+        book_id: i64,
+    ) -> anyhow::Result<Vec<StoredMessage>> {
+        let rows: Vec<(String, OffsetDateTime, Option<String>, Option<String>)> =
+            sqlx::query_as(&format!(
+                "select content, update_time, model, timing from {table}
+                where student_id = $1 and book_id = $2 order by update_time asc, id asc"
+            ))
+            .bind(student_id)
+            .bind(book_id)
+            .fetch_all(&self.database)
+            .await?;
+        let messages = rows
+            .into_iter()
+            .map(|(content, update_time, model, timing)| StoredMessage {
+                content,
+                update_time,
+                model,
+                timing,
+            })
+            .collect();
+        Ok(messages)
+    }
+}
+
+impl MessageStore for PgMessageStore {
+    fn load(
+        &self,
+        student_id: i64,
+        book_id: i64,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<StoredMessage>>> {
+        Box::pin(self.load_table("history_message", student_id, book_id))
+    }
+
+    fn append(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        message: StoredMessage,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            sqlx::query(
+                "insert into history_message (student_id, book_id, content, update_time, model, timing)
+                values ($1, $2, $3, $4, $5, $6)",
+            )
+            .bind(student_id)
+            .bind(book_id)
+            .bind(message.content)
+            .bind(message.update_time)
+            .bind(message.model)
+            .bind(message.timing)
+            .execute(&self.database)
+            .await?;
+            Ok(())
+        })
+    }
+
+    fn archive(
+        &self,
+        student_id: i64,
+        book_id: i64,
+        count: usize,
+    ) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            let mut tx = self.database.begin().await?;
+            let ids: Vec<i64> = sqlx::query_scalar(
+                "select id from history_message where student_id = $1 and book_id = $2
+                order by update_time asc, id asc limit $3",
+            )
+            .bind(student_id)
+            .bind(book_id)
+            .bind(count as i64)
+            .fetch_all(&mut *tx)
+            .await?;
+            sqlx::query(
+                "insert into archived_message (student_id, book_id, content, update_time, model, timing)
+                select student_id, book_id, content, update_time, model, timing from history_message
+                where id = any($1) order by update_time asc, id asc",
+            )
+            .bind(&ids)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("delete from history_message where id = any($1)")
+                .bind(&ids)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            Ok(())
+        })
+    }
+
+    fn load_archived(
+        &self,
+        student_id: i64,
+        book_id: i64,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<StoredMessage>>> {
+        Box::pin(self.load_table("archived_message", student_id, book_id))
+    }
+
+    fn clear(&self, student_id: i64, book_id: i64) -> BoxFuture<'_, anyhow::Result<()>> {
+        Box::pin(async move {
+            for table in PG_MESSAGE_TABLES {
+                sqlx::query(&format!(
+                    "delete from {table} where student_id = $1 and book_id = $2"
+                ))
+                .bind(student_id)
+                .bind(book_id)
+                .execute(&self.database)
+                .await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn count(&self, student_id: i64, book_id: i64) -> BoxFuture<'_, anyhow::Result<i64>> {
+        Box::pin(async move {
+            let mut count = 0;
+            for table in PG_MESSAGE_TABLES {
+                let messages: i64 = sqlx::query_scalar(&format!(
+                    "select count(*) from {table} where student_id = $1 and book_id = $2"
+                ))
+                .bind(student_id)
+                .bind(book_id)
+                .fetch_one(&self.database)
+                .await?;
+                count += messages;
+            }
+            Ok(count)
+        })
+    }
+}
@@ -1,8 +1,20 @@
+use std::sync::Arc;
+
 use async_openai::tools::Tool;
 
+use crate::ai_utils;
+use crate::books::library::Library;
+use crate::course::TodaysLesson;
+use crate::flashcard::{self, Flashcard, NewFlashcard};
+use crate::quiz::{self, Quiz, QuizAnswers, QuizRequest, QuizResult};
+use crate::scratchpad::{self, Editor, Scratchpad, ScratchpadUpdate};
+use crate::student_memory::{self, StudentMemory, StudentMemoryUpdate};
+use crate::workspace::{self, CodeRequest, StudentCode};
+
 use super::{
     MessagesDatabase,
-    progress::{BookProgress, ChapterProgress},
+    pace::{EstimateQuery, StudyEstimate},
+    progress::{BookProgress, ChapterProgress, ConfidenceCheckIn},
 };
 
 pub struct ProgressUpdateTool {
@@ -28,7 +40,6 @@ impl Tool for ProgressUpdateTool {
     async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
         self.messages_db.update_chapter_progress(args).await
     }
-    
 }
 
 pub struct AddMemoryTool {
@@ -56,6 +67,35 @@ impl Tool for AddMemoryTool {
     }
 }
 
+pub struct UpdateStudentMemoryTool {
+    messages_db: MessagesDatabase,
+}
+
+impl UpdateStudentMemoryTool {
+    pub fn new(messages_db: MessagesDatabase) -> Self {
+        Self { messages_db }
+    }
+}
+
+impl Tool for UpdateStudentMemoryTool {
+    type Args = StudentMemoryUpdate;
+    type Output = Vec<StudentMemory>;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "UpdateStudentMemory".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Remember lasting facts about the student for all books and later sessions, \
+            like what they struggle with or which examples they like, and forget outdated ones"
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        student_memory::update(self.messages_db.pool(), self.messages_db.student_id(), args).await
+    }
+}
+
 pub struct GetBookProgressTool {
     messages_db: MessagesDatabase,
 }
@@ -80,3 +120,350 @@ impl Tool for GetBookProgressTool {
         self.messages_db.get_book_progress().await
     }
 }
+
+pub struct RecordConfidenceTool {
+    messages_db: MessagesDatabase,
+}
+
+impl RecordConfidenceTool {
+    pub fn new(messages_db: MessagesDatabase) -> Self {
+        Self { messages_db }
+    }
+}
+
+impl Tool for RecordConfidenceTool {
+    type Args = ConfidenceCheckIn;
+    type Output = ();
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "RecordConfidence".to_string()
+    }
+    fn description() -> Option<String> {
+        Some("Record how confident the student feels about a chapter, from 1 to 5".to_string())
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        self.messages_db.add_checkin(&args).await
+    }
+}
+
+pub struct EstimateStudyTimeTool {
+    messages_db: MessagesDatabase,
+    library: Arc<Library>,
+}
+
+impl EstimateStudyTimeTool {
+    pub fn new(messages_db: MessagesDatabase, library: Arc<Library>) -> Self {
+        Self {
+            messages_db,
+            library,
+        }
+    }
+}
+
+impl Tool for EstimateStudyTimeTool {
+    type Args = EstimateQuery;
+    type Output = StudyEstimate;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "EstimateStudyTime".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Estimate how many minutes the student needs for a chapter, \
+            calibrated with the pace of the chapters they already completed"
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        let book = self.library.get_book(self.messages_db.book_id()).await?;
+        let pace = self.messages_db.get_study_pace(&book).await?;
+        pace.estimate(&book, &args.chapter_number)
+            .ok_or(anyhow::anyhow!(
+                "Chapter not found: {}",
+                args.chapter_number
+            ))
+    }
+}
+
+pub struct CreateQuizTool {
+    messages_db: MessagesDatabase,
+    library: Arc<Library>,
+}
+
+impl CreateQuizTool {
+    pub fn new(messages_db: MessagesDatabase, library: Arc<Library>) -> Self {
+        Self {
+            messages_db,
+            library,
+        }
+    }
+}
+
+impl Tool for CreateQuizTool {
+    type Args = QuizRequest;
+    type Output = Quiz;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "CreateQuiz".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Create a quiz with multiple choice and short answer questions on a chapter. \
+            The answers are kept on the server, collect the student's answers and pass them to GradeQuiz"
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        let book = self.library.get_book(self.messages_db.book_id()).await?;
+        let provider = self
+            .library
+            .provider(Some(self.messages_db.student_id()), Some(book.id))
+            .await?;
+        let quiz = quiz::generate(
+            self.messages_db.pool(),
+            provider.as_ref(),
+            &book,
+            &args.chapter_number,
+            args.questions(),
+        )
+        .await?;
+        Ok(quiz.without_answers())
+    }
+}
+
+pub struct GradeQuizTool {
+    messages_db: MessagesDatabase,
+}
+
+impl GradeQuizTool {
+    pub fn new(messages_db: MessagesDatabase) -> Self {
+        Self { messages_db }
+    }
+}
+
+impl Tool for GradeQuizTool {
+    type Args = QuizAnswers;
+    type Output = QuizResult;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "GradeQuiz".to_string()
+    }
+    fn description() -> Option<String> {
+        Some("Grade the student's answers to a quiz and record the score".to_string())
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        let quiz = quiz::get_quiz(self.messages_db.pool(), args.quiz_id).await?;
+        if quiz.book_id != self.messages_db.book_id() {
+            anyhow::bail!("Quiz not found: {}", args.quiz_id);
+        }
+        let provider = ai_utils::resolve_provider(
+            self.messages_db.pool(),
+            Some(self.messages_db.student_id()),
+            Some(self.messages_db.book_id()),
+        )
+        .await?;
+        quiz::submit(
+            self.messages_db.pool(),
+            provider.as_ref(),
+            args.quiz_id,
+            self.messages_db.student_id(),
+            args.answers,
+        )
+        .await
+    }
+}
+
+pub struct CreateFlashcardTool {
+    messages_db: MessagesDatabase,
+}
+
+impl CreateFlashcardTool {
+    pub fn new(messages_db: MessagesDatabase) -> Self {
+        Self { messages_db }
+    }
+}
+
+impl Tool for CreateFlashcardTool {
+    type Args = NewFlashcard;
+    type Output = Flashcard;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "CreateFlashcard".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Create a flashcard on a concept the student struggles with, \
+            it is scheduled for spaced-repetition review"
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        flashcard::create(
+            self.messages_db.pool(),
+            self.messages_db.student_id(),
+            self.messages_db.book_id(),
+            args,
+        )
+        .await
+    }
+}
+
+pub struct GetTodaysLessonTool {
+    messages_db: MessagesDatabase,
+}
+
+impl GetTodaysLessonTool {
+    pub fn new(messages_db: MessagesDatabase) -> Self {
+        Self { messages_db }
+    }
+}
+
+impl Tool for GetTodaysLessonTool {
+    type Args = ();
+    type Output = TodaysLesson;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "GetTodaysLesson".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Get the chapters of today's lesson in the student's course schedule, \
+            the chapters overdue from past lessons and whether the student is on track"
+                .to_string(),
+        )
+    }
+    async fn call(&self, _args: Self::Args) -> anyhow::Result<Self::Output> {
+        self.messages_db
+            .get_todays_lesson()
+            .await?
+            .ok_or(anyhow::anyhow!(
+                "The student has no course schedule for this book"
+            ))
+    }
+}
+
+pub struct ReadScratchpadTool {
+    messages_db: MessagesDatabase,
+}
+
+impl ReadScratchpadTool {
+    pub fn new(messages_db: MessagesDatabase) -> Self {
+        Self { messages_db }
+    }
+}
+
+impl Tool for ReadScratchpadTool {
+    type Args = ();
+    type Output = Scratchpad;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "ReadScratchpad".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Read the Markdown scratchpad you share with the student on this book, \
+            the working notes of long projects kept across sessions"
+                .to_string(),
+        )
+    }
+    async fn call(&self, _args: Self::Args) -> anyhow::Result<Self::Output> {
+        scratchpad::get(
+            self.messages_db.pool(),
+            self.messages_db.student_id(),
+            self.messages_db.book_id(),
+        )
+        .await
+    }
+}
+
+pub struct UpdateScratchpadTool {
+    messages_db: MessagesDatabase,
+}
+
+impl UpdateScratchpadTool {
+    pub fn new(messages_db: MessagesDatabase) -> Self {
+        Self { messages_db }
+    }
+}
+
+impl Tool for UpdateScratchpadTool {
+    type Args = ScratchpadUpdate;
+    type Output = Scratchpad;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "UpdateScratchpad".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Replace the shared scratchpad or add to its end, the student sees and can edit it"
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        let (database, student_id, book_id) = (
+            self.messages_db.pool(),
+            self.messages_db.student_id(),
+            self.messages_db.book_id(),
+        );
+        if args.append {
+            scratchpad::append(
+                database,
+                student_id,
+                book_id,
+                &args.content,
+                Editor::Teacher,
+            )
+            .await
+        } else {
+            scratchpad::save(
+                database,
+                student_id,
+                book_id,
+                &args.content,
+                Editor::Teacher,
+                args.version,
+            )
+            .await
+        }
+    }
+}
+
+pub struct ReadStudentCodeTool {
+    messages_db: MessagesDatabase,
+    library: Arc<Library>,
+}
+
+impl ReadStudentCodeTool {
+    pub fn new(messages_db: MessagesDatabase, library: Arc<Library>) -> Self {
+        Self {
+            messages_db,
+            library,
+        }
+    }
+}
+
+impl Tool for ReadStudentCodeTool {
+    type Args = CodeRequest;
+    type Output = StudentCode;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "ReadStudentCode".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Read files of the Git repository the student linked to this book, at its latest \
+            commit, to review their actual code; with no path, list the files of the repository"
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        workspace::read_code(
+            self.messages_db.pool(),
+            &self.library.bookbase,
+            self.messages_db.student_id(),
+            self.messages_db.book_id(),
+            &args,
+        )
+        .await
+    }
+}
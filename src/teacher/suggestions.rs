@@ -0,0 +1,89 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use sqlx::SqlitePool;
+
+use crate::ai_utils::{self, Provider};
+
+/// the most replies suggested after a teacher turn
+pub const MAX_SUGGESTED_REPLIES: i64 = 3;
+
+/// how many replies are suggested after each teacher turn, 0 when disabled
+pub async fn get_count(database: &SqlitePool) -> anyhow::Result<i64> {
+    let count = sqlx::query_scalar!("select suggested_replies from agent_setting")
+        .fetch_one(database)
+        .await?;
+    Ok(count)
+}
+
+pub async fn set_count(database: &SqlitePool, count: i64) -> anyhow::Result<()> {
+    if !(0..=MAX_SUGGESTED_REPLIES).contains(&count) {
+        anyhow::bail!("suggested replies must be between 0 and {MAX_SUGGESTED_REPLIES}");
+    }
+    sqlx::query!("update agent_setting set suggested_replies = ?", count)
+        .execute(database)
+        .await?;
+    Ok(())
+}
+
+/// The replies the student may send next
+#[derive(Debug, JsonSchema, Deserialize)]
+struct SuggestedReplies {
+    /// Short replies or questions in the student's words, e.g. "Give me an example"
+    replies: Vec<String>,
+}
+
+/// up to `count` short replies the student may send after `exchange`, the last student message
+/// and the teacher's answer, in the student's locale
+pub async fn generate(
+    provider: &dyn Provider,
+    locale: &str,
+    exchange: &str,
+    count: usize,
+) -> anyhow::Result<Vec<String>> {
+    let prompt = format!(
+        "A student is learning a book with a teacher. Suggest {count} short replies the student \
+        could send next, in the student's own words, such as asking for an example, a quiz or a \
+        simpler explanation. Each reply has at most eight words and is written in the language \
+        of the locale \"{locale}\".\n\n# Conversation\n{exchange}"
+    );
+    let suggested: SuggestedReplies = ai_utils::extract(provider, prompt).await?;
+    Ok(tidy(suggested.replies, count))
+}
+
+/// trimmed, without empty and repeated replies, at most `count`
+fn tidy(replies: Vec<String>, count: usize) -> Vec<String> {
+    let mut tidied: Vec<String> = Vec::new();
+    for reply in replies {
+        let reply = reply.trim().trim_matches('"').trim().to_string();
+        if reply.is_empty()
+            || tidied
+                .iter()
+                .any(|r| r.to_lowercase() == reply.to_lowercase())
+        {
+            continue;
+        }
+        tidied.push(reply);
+    }
+    tidied.truncate(count);
+    tidied
+}
+
+#[test]
+fn tidy_suggested_replies() {
+    let replies = vec![
+        " \"Give me an example\" ".to_string(),
+        "".to_string(),
+        "give me an example".to_string(),
+        "Quiz me on this".to_string(),
+        "Explain it more simply".to_string(),
+        "What comes next?".to_string(),
+    ];
+    assert_eq!(
+        tidy(replies, 3),
+        [
+            "Give me an example",
+            "Quiz me on this",
+            "Explain it more simply"
+        ]
+    );
+}
@@ -0,0 +1,687 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write;
+
+use async_openai::tools::Tool;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+const MAX_INPUT: usize = 2000;
+const MAX_DEPTH: usize = 64;
+const DEFAULT_TOLERANCE: f64 = 1e-9;
+/// values tried for the free variables of an identity, away from the usual singular points
+const SAMPLES: [f64; 8] = [0.7, -1.3, 2.9, 0.41, -3.7, 5.3, 1.9, -0.23];
+/// points of an identity that must evaluate, the others fall outside its domain
+const MIN_POINTS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(usize),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Caret,
+    Open,
+    Close,
+    Equals,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Func {
+    Sqrt,
+    Cbrt,
+    Abs,
+    Exp,
+    Ln,
+    Log,
+    Log2,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Floor,
+    Ceil,
+}
+
+impl Func {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "sqrt" => Self::Sqrt,
+            "cbrt" => Self::Cbrt,
+            "abs" => Self::Abs,
+            "exp" => Self::Exp,
+            "ln" => Self::Ln,
+            "log" | "log10" => Self::Log,
+            "log2" => Self::Log2,
+            "sin" => Self::Sin,
+            "cos" => Self::Cos,
+            "tan" => Self::Tan,
+            "asin" | "arcsin" => Self::Asin,
+            "acos" | "arccos" => Self::Acos,
+            "atan" | "arctan" => Self::Atan,
+            "sinh" => Self::Sinh,
+            "cosh" => Self::Cosh,
+            "tanh" => Self::Tanh,
+            "floor" => Self::Floor,
+            "ceil" => Self::Ceil,
+            _ => return None,
+        })
+    }
+
+    fn apply(self, x: f64) -> f64 {
+        match self {
+            Self::Sqrt => x.sqrt(),
+            Self::Cbrt => x.cbrt(),
+            Self::Abs => x.abs(),
+            Self::Exp => x.exp(),
+            Self::Ln => x.ln(),
+            Self::Log => x.log10(),
+            Self::Log2 => x.log2(),
+            Self::Sin => x.sin(),
+            Self::Cos => x.cos(),
+            Self::Tan => x.tan(),
+            Self::Asin => x.asin(),
+            Self::Acos => x.acos(),
+            Self::Atan => x.atan(),
+            Self::Sinh => x.sinh(),
+            Self::Cosh => x.cosh(),
+            Self::Tanh => x.tanh(),
+            Self::Floor => x.floor(),
+            Self::Ceil => x.ceil(),
+        }
+    }
+}
+
+/// A parsed expression, evaluated numerically
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Var(String),
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Div(Box<Expr>, Box<Expr>),
+    Pow(Box<Expr>, Box<Expr>),
+    Call(Func, Box<Expr>),
+}
+
+impl Expr {
+    fn parse(input: &str) -> anyhow::Result<Self> {
+        let mut sides = parse_equation(input)?;
+        if sides.len() != 1 {
+            anyhow::bail!("Expected an expression without \"=\"");
+        }
+        Ok(sides.remove(0))
+    }
+
+    /// the value with `vars`, NaN or infinite outside the domain of the expression
+    fn eval(&self, vars: &HashMap<String, f64>) -> anyhow::Result<f64> {
+        Ok(match self {
+            Self::Number(n) => *n,
+            Self::Var(name) => match vars.get(name) {
+                Some(value) => *value,
+                None => anyhow::bail!("No value for the variable {name}"),
+            },
+            Self::Neg(e) => -e.eval(vars)?,
+            Self::Add(a, b) => a.eval(vars)? + b.eval(vars)?,
+            Self::Sub(a, b) => a.eval(vars)? - b.eval(vars)?,
+            Self::Mul(a, b) => a.eval(vars)? * b.eval(vars)?,
+            Self::Div(a, b) => a.eval(vars)? / b.eval(vars)?,
+            Self::Pow(a, b) => pow(a.eval(vars)?, b.eval(vars)?),
+            Self::Call(func, e) => func.apply(e.eval(vars)?),
+        })
+    }
+
+    fn collect_vars(&self, vars: &mut BTreeSet<String>) {
+        match self {
+            Self::Number(_) => {}
+            Self::Var(name) => {
+                vars.insert(name.clone());
+            }
+            Self::Neg(e) | Self::Call(_, e) => e.collect_vars(vars),
+            Self::Add(a, b)
+            | Self::Sub(a, b)
+            | Self::Mul(a, b)
+            | Self::Div(a, b)
+            | Self::Pow(a, b) => {
+                a.collect_vars(vars);
+                b.collect_vars(vars);
+            }
+        }
+    }
+}
+
+/// `base ^ exponent`, with the real odd roots of negative numbers, e.g. (-8)^(1/3) = -2
+fn pow(base: f64, exponent: f64) -> f64 {
+    if base < 0.0 && exponent.fract() != 0.0 {
+        let odd = 1.0 / exponent;
+        if (odd.round() - odd).abs() < 1e-9 && odd.round() as i64 % 2 != 0 {
+            return -(-base).powf(exponent);
+        }
+    }
+    base.powf(exponent)
+}
+
+fn tokenize(input: &str) -> anyhow::Result<(Vec<Token>, Vec<String>)> {
+    let mut tokens = Vec::new();
+    let mut names = Vec::new();
+    let chars = input.chars().collect::<Vec<_>>();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let token = match c {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '0'..='9' | '.' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                // scientific notation, 1.5e3, but not 2e as 2 times e
+                if i + 1 < chars.len()
+                    && matches!(chars[i], 'e' | 'E')
+                    && (chars[i + 1].is_ascii_digit()
+                        || (matches!(chars[i + 1], '+' | '-')
+                            && chars.get(i + 2).is_some_and(|c| c.is_ascii_digit())))
+                {
+                    i += 2;
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+                let number = chars[start..i].iter().collect::<String>();
+                match number.parse::<f64>() {
+                    Ok(n) => tokens.push(Token::Number(n)),
+                    Err(_) => anyhow::bail!("Invalid number {number}"),
+                }
+                continue;
+            }
+            c if c.is_alphabetic() => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                names.push(chars[start..i].iter().collect::<String>());
+                tokens.push(Token::Ident(names.len() - 1));
+                continue;
+            }
+            '+' => Token::Plus,
+            '-' | '−' => Token::Minus,
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                i += 1;
+                Token::Caret
+            }
+            '*' | '×' | '·' => Token::Star,
+            '/' | '÷' => Token::Slash,
+            '^' => Token::Caret,
+            '(' | '[' | '{' => Token::Open,
+            ')' | ']' | '}' => Token::Close,
+            '=' => Token::Equals,
+            c => anyhow::bail!("Unexpected character {c:?}"),
+        };
+        tokens.push(token);
+        i += 1;
+    }
+    Ok((tokens, names))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    names: Vec<String>,
+    pos: usize,
+    depth: usize,
+}
+
+/// the sides of `a = b = c`, or the one expression without "="
+fn parse_equation(input: &str) -> anyhow::Result<Vec<Expr>> {
+    if input.len() > MAX_INPUT {
+        anyhow::bail!("An equation is limited to {} characters", MAX_INPUT);
+    }
+    let (tokens, names) = tokenize(input)?;
+    let mut parser = Parser {
+        tokens,
+        names,
+        pos: 0,
+        depth: 0,
+    };
+    let mut sides = vec![parser.expr()?];
+    while parser.eat(Token::Equals) {
+        sides.push(parser.expr()?);
+    }
+    if let Some(token) = parser.peek() {
+        anyhow::bail!("Unexpected {token:?} in {input:?}");
+    }
+    Ok(sides)
+}
+
+impl Parser {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, token: Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expr(&mut self) -> anyhow::Result<Expr> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            anyhow::bail!("The expression is nested too deeply");
+        }
+        let mut expr = self.term()?;
+        loop {
+            if self.eat(Token::Plus) {
+                expr = Expr::Add(Box::new(expr), Box::new(self.term()?));
+            } else if self.eat(Token::Minus) {
+                expr = Expr::Sub(Box::new(expr), Box::new(self.term()?));
+            } else {
+                break;
+            }
+        }
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    /// products and quotients, `2x` and `(x+1)(x-1)` multiply too
+    fn term(&mut self) -> anyhow::Result<Expr> {
+        let mut expr = self.unary()?;
+        loop {
+            if self.eat(Token::Star) {
+                expr = Expr::Mul(Box::new(expr), Box::new(self.unary()?));
+            } else if self.eat(Token::Slash) {
+                expr = Expr::Div(Box::new(expr), Box::new(self.unary()?));
+            } else if matches!(
+                self.peek(),
+                Some(Token::Number(_) | Token::Ident(_) | Token::Open)
+            ) {
+                expr = Expr::Mul(Box::new(expr), Box::new(self.power()?));
+            } else {
+                break;
+            }
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> anyhow::Result<Expr> {
+        if self.eat(Token::Minus) {
+            self.nested(|parser| Ok(Expr::Neg(Box::new(parser.unary()?))))
+        } else if self.eat(Token::Plus) {
+            self.nested(Self::unary)
+        } else {
+            self.power()
+        }
+    }
+
+    /// `a^b^c` is `a^(b^c)` and `-x^2` is `-(x^2)`
+    fn power(&mut self) -> anyhow::Result<Expr> {
+        let base = self.primary()?;
+        if self.eat(Token::Caret) {
+            let exponent = self.nested(Self::unary)?;
+            return Ok(Expr::Pow(Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> anyhow::Result<Expr>,
+    ) -> anyhow::Result<Expr> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            anyhow::bail!("The expression is nested too deeply");
+        }
+        let expr = parse(self)?;
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> anyhow::Result<Expr> {
+        let Some(token) = self.peek() else {
+            anyhow::bail!("The expression ends too early");
+        };
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Open => {
+                let expr = self.expr()?;
+                if !self.eat(Token::Close) {
+                    anyhow::bail!("Missing a closing parenthesis");
+                }
+                Ok(expr)
+            }
+            Token::Ident(index) => {
+                let name = self.names[index].clone();
+                if let Some(func) = Func::parse(&name) {
+                    let argument = self.nested(Self::power)?;
+                    return Ok(Expr::Call(func, Box::new(argument)));
+                }
+                if let Some(constant) = constant(&name) {
+                    return Ok(constant);
+                }
+                // letters written together multiply, `2ab` is 2 * a * b, while `x1` and `x_1`
+                // are names
+                if name.chars().count() > 1 && name.chars().all(char::is_alphabetic) {
+                    let mut letters = name.chars().map(|c| {
+                        constant(&c.to_string()).unwrap_or_else(|| Expr::Var(c.to_string()))
+                    });
+                    let first = letters.next().expect("the name has letters");
+                    return Ok(letters.fold(first, |product, letter| {
+                        Expr::Mul(Box::new(product), Box::new(letter))
+                    }));
+                }
+                Ok(Expr::Var(name))
+            }
+            token => anyhow::bail!("Unexpected {token:?}"),
+        }
+    }
+}
+
+fn constant(name: &str) -> Option<Expr> {
+    match name {
+        "pi" | "π" => Some(Expr::Number(std::f64::consts::PI)),
+        "e" => Some(Expr::Number(std::f64::consts::E)),
+        _ => None,
+    }
+}
+
+fn close(a: f64, b: f64, tolerance: f64) -> bool {
+    (a - b).abs() <= tolerance * a.abs().max(b.abs()).max(1.0)
+}
+
+fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{value:.0}")
+    } else {
+        format!("{value:.10}")
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    }
+}
+
+/// Whether an equation holds, what [`VerifyMathTool`] reports
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// the value of an expression without "="
+    Value(f64),
+    Holds,
+    /// the sides differ, at the values of the variables
+    Differs {
+        at: BTreeMap<String, f64>,
+        sides: Vec<f64>,
+    },
+    /// no values of the free variables are in the domain of the equation
+    Undefined,
+}
+
+/// check `equation` with the variables of `solution` set to their expressions, the free
+/// variables left are tried at several values, so an equation with them must be an identity
+pub fn verify(
+    equation: &str,
+    solution: &BTreeMap<String, String>,
+    tolerance: f64,
+) -> anyhow::Result<Verdict> {
+    let sides = parse_equation(equation)?;
+    let values = solution
+        .iter()
+        .map(|(name, value)| Ok((name.clone(), Expr::parse(value)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut free = BTreeSet::new();
+    for side in &sides {
+        side.collect_vars(&mut free);
+    }
+    for (name, _) in &values {
+        free.remove(name);
+    }
+    for (_, value) in &values {
+        value.collect_vars(&mut free);
+    }
+    if sides.len() == 1 && !free.is_empty() {
+        anyhow::bail!(
+            "The expression has the free variables {}, give their values or an equation to check",
+            free.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+    let points = if free.is_empty() { 1 } else { SAMPLES.len() };
+    let mut evaluated = 0;
+    for point in 0..points {
+        let mut vars = free
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let sample = SAMPLES[(point + 3 * i) % SAMPLES.len()] + 0.1 * i as f64;
+                (name.clone(), sample)
+            })
+            .collect::<HashMap<_, _>>();
+        let at = vars.clone();
+        for (name, value) in &values {
+            let value = value.eval(&at)?;
+            vars.insert(name.clone(), value);
+        }
+        let results = sides
+            .iter()
+            .map(|side| side.eval(&vars))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if results.iter().any(|value| !value.is_finite()) {
+            continue;
+        }
+        if sides.len() == 1 {
+            return Ok(Verdict::Value(results[0]));
+        }
+        if results
+            .windows(2)
+            .any(|pair| !close(pair[0], pair[1], tolerance))
+        {
+            return Ok(Verdict::Differs {
+                at: vars.into_iter().collect(),
+                sides: results,
+            });
+        }
+        evaluated += 1;
+    }
+    if evaluated < points.min(MIN_POINTS) {
+        return Ok(Verdict::Undefined);
+    }
+    Ok(Verdict::Holds)
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub struct VerifyMathArgs {
+    /// The equation to check, in plain notation, e.g. "x^2 - 5x + 6 = 0", "(a+b)^2 = a^2 + 2ab + b^2"
+    /// or "3/4 + 1/6 = 11/12". Chains like "2(x+3) = 2x + 6" check every step, an expression
+    /// without "=" is evaluated. Functions: sqrt, cbrt, abs, exp, ln, log (base 10), log2,
+    /// sin, cos, tan, asin, acos, atan, sinh, cosh, tanh, floor, ceil; constants pi and e
+    pub equation: String,
+    /// The solutions to check, each the values of the variables solved for, e.g.
+    /// [{"x": "2"}, {"x": "3"}]. Values are expressions like "sqrt(2)/2", they may use the free
+    /// variables left. Empty to check the equation as an identity for all values of its variables.
+    /// Letters written together multiply, name indexed variables like x1 or x_1
+    #[serde(default)]
+    pub solutions: Vec<BTreeMap<String, String>>,
+    /// The relative difference tolerated between the sides, e.g. 0.01 for answers rounded to
+    /// two digits, exact by default
+    pub tolerance: Option<f64>,
+}
+
+/// Checks algebra and arithmetic numerically, so the teacher corrects itself before presenting
+/// a wrong answer
+pub struct VerifyMathTool;
+
+impl VerifyMathTool {
+    fn report(args: &VerifyMathArgs) -> anyhow::Result<String> {
+        let tolerance = args
+            .tolerance
+            .unwrap_or(DEFAULT_TOLERANCE)
+            .clamp(DEFAULT_TOLERANCE, 0.5);
+        let solutions = if args.solutions.is_empty() {
+            vec![BTreeMap::new()]
+        } else {
+            args.solutions.clone()
+        };
+        let mut report = String::new();
+        let mut wrong = false;
+        for solution in &solutions {
+            let label = if solution.is_empty() {
+                args.equation.trim().to_string()
+            } else {
+                solution
+                    .iter()
+                    .map(|(name, value)| format!("{name} = {value}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            match verify(&args.equation, solution, tolerance)? {
+                Verdict::Value(value) => {
+                    let _ = writeln!(report, "{label}: the value is {}", format_value(value));
+                }
+                Verdict::Holds if solution.is_empty() => {
+                    let _ = writeln!(report, "{label}: correct");
+                }
+                Verdict::Holds => {
+                    let _ = writeln!(report, "{label}: correct, the equation holds");
+                }
+                Verdict::Differs { at, sides } => {
+                    wrong = true;
+                    let sides = sides
+                        .iter()
+                        .map(|value| format_value(*value))
+                        .collect::<Vec<_>>()
+                        .join(" vs ");
+                    if solution.is_empty() && !at.is_empty() {
+                        let at = at
+                            .iter()
+                            .map(|(name, value)| format!("{name} = {}", format_value(*value)))
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        let _ = writeln!(report, "{label}: WRONG, at {at} the sides are {sides}");
+                    } else {
+                        let _ = writeln!(report, "{label}: WRONG, the sides are {sides}");
+                    }
+                }
+                Verdict::Undefined => {
+                    wrong = true;
+                    let _ = writeln!(
+                        report,
+                        "{label}: WRONG, the equation is undefined there, e.g. a division by zero \
+                        or the root of a negative number"
+                    );
+                }
+            }
+        }
+        if wrong {
+            report.push_str(
+                "Don't present this answer. Find the mistake in your work, correct it and check \
+                the corrected answer again.",
+            );
+        }
+        Ok(report.trim_end().to_string())
+    }
+}
+
+impl Tool for VerifyMathTool {
+    type Args = VerifyMathArgs;
+    type Output = String;
+    type Error = anyhow::Error;
+    fn name() -> String {
+        "VerifyMath".to_string()
+    }
+    fn description() -> Option<String> {
+        Some(
+            "Check a calculation, a simplification or the solutions of an equation numerically, \
+            before presenting the final answer of any algebra or arithmetic"
+                .to_string(),
+        )
+    }
+    async fn call(&self, args: Self::Args) -> anyhow::Result<Self::Output> {
+        Self::report(&args)
+    }
+}
+
+#[test]
+fn verify_math() {
+    let no_solution = BTreeMap::new();
+    let x = |value: &str| BTreeMap::from([("x".to_string(), value.to_string())]);
+    let check = |equation: &str, solution: &BTreeMap<String, String>| {
+        verify(equation, solution, DEFAULT_TOLERANCE).unwrap()
+    };
+
+    assert_eq!(check("x^2 - 5x + 6 = 0", &x("2")), Verdict::Holds);
+    assert_eq!(check("x^2 - 5x + 6 = 0", &x("3")), Verdict::Holds);
+    assert!(matches!(
+        check("x^2 - 5x + 6 = 0", &x("4")),
+        Verdict::Differs { .. }
+    ));
+    assert_eq!(check("2x^2 = 1", &x("sqrt(2)/2")), Verdict::Holds);
+    assert_eq!(
+        check("(x+1)^2 = x^2 + 2x + 1", &no_solution),
+        Verdict::Holds
+    );
+    assert_eq!(
+        check("(a+b)(a-b) = a^2 - b^2", &no_solution),
+        Verdict::Holds
+    );
+    assert_eq!(
+        check("(a+b)^2 = a^2 + 2ab + b^2", &no_solution),
+        Verdict::Holds
+    );
+    assert_eq!(check("ab = a b", &no_solution), Verdict::Holds);
+    assert_eq!(check("x_1 + x1 = x1 + x_1", &no_solution), Verdict::Holds);
+    assert!(matches!(
+        check("(x+1)^2 = x^2 + 1", &no_solution),
+        Verdict::Differs { .. }
+    ));
+    assert_eq!(
+        check("2(x+3) = 2x + 6 = 2(x + 3)", &no_solution),
+        Verdict::Holds
+    );
+    assert_eq!(check("3/4 + 1/6 = 11/12", &no_solution), Verdict::Holds);
+    assert!(matches!(
+        check("1/3 + 1/4 = 2/7", &no_solution),
+        Verdict::Differs { .. }
+    ));
+    assert_eq!(check("2^-1 = 0.5", &no_solution), Verdict::Holds);
+    assert_eq!(check("-x^2 = -9", &x("3")), Verdict::Holds);
+    assert_eq!(check("(-8)^(1/3) = -2", &no_solution), Verdict::Holds);
+    assert_eq!(check("sin(pi/6) = 1/2", &no_solution), Verdict::Holds);
+    assert_eq!(check("1.5e3 = 1500", &no_solution), Verdict::Holds);
+    assert_eq!(check("2 * 3 + 1", &no_solution), Verdict::Value(7.0));
+    assert_eq!(check("1/x = 1", &x("0")), Verdict::Undefined);
+    // y in terms of the free variable x
+    let y = BTreeMap::from([("y".to_string(), "2x + 1".to_string())]);
+    assert_eq!(check("y - 2x = 1", &y), Verdict::Holds);
+
+    assert!(verify("x + 1", &no_solution, DEFAULT_TOLERANCE).is_err());
+    assert!(verify("(x + 1 = 2", &no_solution, DEFAULT_TOLERANCE).is_err());
+    assert!(verify("x $ 2 = 1", &no_solution, DEFAULT_TOLERANCE).is_err());
+    assert!(verify(&"(".repeat(100), &no_solution, DEFAULT_TOLERANCE).is_err());
+
+    let args = VerifyMathArgs {
+        equation: "x^2 = 4".to_string(),
+        solutions: vec![x("2"), x("-3")],
+        tolerance: None,
+    };
+    let report = VerifyMathTool::report(&args).unwrap();
+    assert!(report.contains("x = 2: correct"));
+    assert!(report.contains("x = -3: WRONG, the sides are 9 vs 4"));
+    assert!(report.contains("Don't present this answer"));
+    let rounded = VerifyMathArgs {
+        equation: "x^2 = 2".to_string(),
+        solutions: vec![x("1.414")],
+        tolerance: Some(0.01),
+    };
+    assert!(!VerifyMathTool::report(&rounded).unwrap().contains("WRONG"));
+}
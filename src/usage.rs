@@ -0,0 +1,505 @@
+use std::{
+    pin::Pin,
+    sync::{Arc, LazyLock},
+    task::{Context, Poll},
+};
+
+use async_openai::{
+    error::OpenAIError,
+    types::{
+        ChatCompletionRequestMessage, ChatCompletionResponseStream, CompletionUsage,
+        CreateChatCompletionRequest, CreateChatCompletionResponse,
+        CreateChatCompletionStreamResponse, CreateEmbeddingRequest, CreateEmbeddingResponse,
+    },
+};
+use futures::{FutureExt, Stream, future::BoxFuture};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+use tiktoken_rs::CoreBPE;
+use time::{Date, OffsetDateTime};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{
+    abuse::ThrottleReason,
+    ai_utils::{CircuitBreaker, Provider, RetryPolicy, Tokens},
+    quota,
+    spend::{self, BudgetStatus},
+};
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+/// the tokens a chat message costs besides its content, for its role and delimiters
+const MESSAGE_OVERHEAD: u64 = 3;
+/// the tokens priming the reply of the model
+const REPLY_OVERHEAD: u64 = 3;
+
+static BPE: LazyLock<CoreBPE> =
+    LazyLock::new(|| tiktoken_rs::o200k_base().expect("the o200k_base ranks are bundled"));
+
+/// the tokens of `text` with the `o200k_base` encoding, close to what most providers count
+pub fn count_tokens(text: &str) -> u64 {
+    BPE.encode_ordinary(text).len() as u64
+}
+
+/// the prompt tokens of a chat request with `messages`
+pub fn count_messages(messages: &[ChatCompletionRequestMessage]) -> u64 {
+    messages
+        .iter()
+        .map(|message| message.tokens() + MESSAGE_OVERHEAD)
+        .sum::<u64>()
+        + REPLY_OVERHEAD
+}
+
+/// The tokens of a model call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// counted locally, the provider returned no usage
+    pub estimated: bool,
+}
+
+impl Usage {
+    /// the usage the provider returned
+    pub fn reported(usage: &CompletionUsage) -> Self {
+        Self {
+            prompt_tokens: usage.prompt_tokens as u64,
+            completion_tokens: usage.completion_tokens as u64,
+            estimated: false,
+        }
+    }
+
+    /// the usage counted locally, for providers that don't return it
+    pub fn estimated(prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            estimated: true,
+        }
+    }
+}
+
+/// The tokens of a student on a book in a day
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DailyUsage {
+    pub student_id: i64,
+    pub book_id: i64,
+    #[serde(with = "iso_date")]
+    #[schema(value_type = String, format = Date)]
+    pub day: Date,
+    /// chat messages of the student
+    pub messages: i64,
+    /// model calls of the teacher, several per message when it uses tools
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    /// requests whose tokens were counted locally, the provider returned no usage
+    pub estimated_requests: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UsageFilter {
+    pub student_id: Option<i64>,
+    pub book_id: Option<i64>,
+    /// the first day, included
+    #[serde(default, with = "iso_date::option")]
+    pub from: Option<Date>,
+    /// the last day, included
+    #[serde(default, with = "iso_date::option")]
+    pub to: Option<Date>,
+}
+
+/// add a model call of the teacher to the daily usage of the student on the book, and charge
+/// its cost to the class of the student
+pub async fn record(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+    usage: Usage,
+) -> anyhow::Result<()> {
+    let day = OffsetDateTime::now_utc().date();
+    let (prompt_tokens, completion_tokens) =
+        (usage.prompt_tokens as i64, usage.completion_tokens as i64);
+    let estimated = usage.estimated as i64;
+    sqlx::query!(
+        "insert into token_usage
+        (student_id, book_id, day, requests, prompt_tokens, completion_tokens, estimated_requests)
+        values (?, ?, ?, 1, ?, ?, ?)
+        on conflict (student_id, book_id, day) do update set
+        requests = requests + 1,
+        prompt_tokens = prompt_tokens + excluded.prompt_tokens,
+        completion_tokens = completion_tokens + excluded.completion_tokens,
+        estimated_requests = estimated_requests + excluded.estimated_requests",
+        student_id,
+        book_id,
+        day,
+        prompt_tokens,
+        completion_tokens,
+        estimated
+    )
+    .execute(database)
+    .await?;
+    spend::record_usage(
+        database,
+        student_id,
+        book_id,
+        usage.prompt_tokens.min(u32::MAX as u64) as u32,
+        usage.completion_tokens.min(u32::MAX as u64) as u32,
+    )
+    .await
+}
+
+/// count a chat message of the student on the book in its daily usage
+pub async fn record_message(
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+) -> anyhow::Result<()> {
+    let day = OffsetDateTime::now_utc().date();
+    sqlx::query!(
+        "insert into token_usage
+        (student_id, book_id, day, messages, requests, prompt_tokens, completion_tokens, estimated_requests)
+        values (?, ?, ?, 1, 0, 0, 0, 0)
+        on conflict (student_id, book_id, day) do update set messages = messages + 1",
+        student_id,
+        book_id,
+        day
+    )
+    .execute(database)
+    .await?;
+    Ok(())
+}
+
+/// the daily usage matching `filter`, newest day first
+pub async fn report(
+    database: &SqlitePool,
+    filter: &UsageFilter,
+) -> anyhow::Result<Vec<DailyUsage>> {
+    let usage = sqlx::query_as!(
+        DailyUsage,
+        r#"select student_id, book_id, day as "day: Date", messages, requests, prompt_tokens,
+        completion_tokens, estimated_requests
+        from token_usage
+        where (?1 is null or student_id = ?1) and (?2 is null or book_id = ?2)
+        and (?3 is null or day >= ?3) and (?4 is null or day <= ?4)
+        order by day desc, student_id, book_id"#,
+        filter.student_id,
+        filter.book_id,
+        filter.from,
+        filter.to
+    )
+    .fetch_all(database)
+    .await?;
+    Ok(usage)
+}
+
+/// `provider` charging every call to the student on the book: refused once the budget of their
+/// class or their daily tokens are used up, and recorded with [`record`] like the teacher's
+pub fn metered(
+    provider: Arc<dyn Provider>,
+    database: &SqlitePool,
+    student_id: i64,
+    book_id: i64,
+) -> Arc<dyn Provider> {
+    Arc::new(MeteredProvider {
+        inner: provider,
+        meter: Meter {
+            database: database.clone(),
+            student_id,
+            book_id,
+        },
+    })
+}
+
+#[derive(Clone)]
+struct Meter {
+    database: SqlitePool,
+    student_id: i64,
+    book_id: i64,
+}
+
+impl Meter {
+    async fn check(&self) -> anyhow::Result<()> {
+        if let BudgetStatus::Exhausted { spent, limit } =
+            spend::check_budget(&self.database, self.student_id).await?
+        {
+            anyhow::bail!("Monthly budget of the class exhausted: {spent:.2}/{limit:.2} USD");
+        }
+        let quota = quota::get(&self.database, self.student_id).await?;
+        if quota.exhausted() == Some(ThrottleReason::DailyTokens) {
+            anyhow::bail!("Daily tokens of the student exhausted");
+        }
+        Ok(())
+    }
+
+    async fn record(&self, usage: Usage) -> anyhow::Result<()> {
+        record(&self.database, self.student_id, self.book_id, usage).await
+    }
+}
+
+struct MeteredProvider {
+    inner: Arc<dyn Provider>,
+    meter: Meter,
+}
+
+/// the prompt tokens of `request`, for providers that don't return the usage
+fn count_request(request: &CreateChatCompletionRequest) -> u64 {
+    let tools = request
+        .tools
+        .as_ref()
+        .and_then(|tools| serde_json::to_string(tools).ok())
+        .unwrap_or_default();
+    count_messages(&request.messages) + count_tokens(&tools)
+}
+
+impl Provider for MeteredProvider {
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    fn breaker(&self) -> &CircuitBreaker {
+        self.inner.breaker()
+    }
+
+    fn retry_policy(&self) -> &RetryPolicy {
+        self.inner.retry_policy()
+    }
+
+    fn chat(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> BoxFuture<'_, anyhow::Result<CreateChatCompletionResponse>> {
+        async move {
+            self.meter.check().await?;
+            let prompt_tokens = count_request(&request);
+            let response = self.inner.chat(request).await?;
+            let usage = match &response.usage {
+                Some(reported) => Usage::reported(reported),
+                None => {
+                    let completion: String = response
+                        .choices
+                        .iter()
+                        .flat_map(|choice| {
+                            let message = &choice.message;
+                            message.content.iter().cloned().chain(
+                                message.tool_calls.iter().flatten().map(|tool_call| {
+                                    format!(
+                                        "{}{}",
+                                        tool_call.function.name, tool_call.function.arguments
+                                    )
+                                }),
+                            )
+                        })
+                        .collect();
+                    Usage::estimated(prompt_tokens, count_tokens(&completion))
+                }
+            };
+            self.meter.record(usage).await?;
+            Ok(response)
+        }
+        .boxed()
+    }
+
+    fn chat_stream(
+        &self,
+        request: CreateChatCompletionRequest,
+    ) -> BoxFuture<'_, anyhow::Result<ChatCompletionResponseStream>> {
+        async move {
+            self.meter.check().await?;
+            let prompt_tokens = count_request(&request);
+            let stream = self.inner.chat_stream(request).await?;
+            Ok(Box::pin(MeteredStream {
+                inner: stream,
+                meter: Some(self.meter.clone()),
+                prompt_tokens,
+                completion: String::new(),
+            }) as ChatCompletionResponseStream)
+        }
+        .boxed()
+    }
+
+    fn embeddings(
+        &self,
+        request: CreateEmbeddingRequest,
+    ) -> BoxFuture<'_, anyhow::Result<CreateEmbeddingResponse>> {
+        async move {
+            self.meter.check().await?;
+            let response = self.inner.embeddings(request).await?;
+            let usage = Usage {
+                prompt_tokens: response.usage.prompt_tokens as u64,
+                completion_tokens: 0,
+                estimated: false,
+            };
+            self.meter.record(usage).await?;
+            Ok(response)
+        }
+        .boxed()
+    }
+}
+
+/// A response stream recording its usage from its last chunk, or estimated from what it
+/// streamed when it ends without one or is dropped midway
+struct MeteredStream {
+    inner: ChatCompletionResponseStream,
+    /// taken once the usage is recorded
+    meter: Option<Meter>,
+    prompt_tokens: u64,
+    completion: String,
+}
+
+impl MeteredStream {
+    /// polling can't wait for the database, the usage is recorded aside
+    fn charge(&mut self, usage: Usage) {
+        let Some(meter) = self.meter.take() else {
+            return;
+        };
+        // a stream dropped while the runtime shuts down isn't charged
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        runtime.spawn(async move {
+            if let Err(e) = meter.record(usage).await {
+                warn!(
+                    "recording the usage of student {} failed: {e:?}",
+                    meter.student_id
+                );
+            }
+        });
+    }
+
+    fn charge_estimated(&mut self) {
+        let usage = Usage::estimated(self.prompt_tokens, count_tokens(&self.completion));
+        self.charge(usage);
+    }
+}
+
+impl Stream for MeteredStream {
+    type Item = Result<CreateChatCompletionStreamResponse, OpenAIError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = std::task::ready!(self.inner.as_mut().poll_next(cx));
+        match &item {
+            Some(Ok(response)) => {
+                if let Some(reported) = &response.usage {
+                    self.charge(Usage::reported(reported));
+                }
+                for choice in &response.choices {
+                    if let Some(content) = &choice.delta.content {
+                        self.completion.push_str(content);
+                    }
+                    for chunk in choice.delta.tool_calls.iter().flatten() {
+                        if let Some(function) = &chunk.function {
+                            self.completion
+                                .push_str(function.name.as_deref().unwrap_or_default());
+                            self.completion
+                                .push_str(function.arguments.as_deref().unwrap_or_default());
+                        }
+                    }
+                }
+            }
+            Some(Err(_)) => {}
+            None => self.charge_estimated(),
+        }
+        Poll::Ready(item)
+    }
+}
+
+impl Drop for MeteredStream {
+    fn drop(&mut self) {
+        self.charge_estimated();
+    }
+}
+
+#[tokio::test]
+async fn daily_usage() {
+    use crate::books::library::{Library, LibraryConfig};
+
+    assert_eq!(count_tokens(""), 0);
+    assert!(count_tokens("The teacher reads the chapter.") < 10);
+    // CJK text is about a token a character, more than its bytes / 4
+    assert!(count_tokens(&"学习".repeat(100)) > 150);
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = LibraryConfig {
+        database: dir.path().join("book.db"),
+        bookbase: dir.path().join("bookbase"),
+        migrate: true,
+        ..Default::default()
+    };
+    let database = Library::open(&config).await.unwrap().database.clone();
+    sqlx::query!("insert into book (id, title, authors) values (1, 'Rust', '')")
+        .execute(&database)
+        .await
+        .unwrap();
+    sqlx::query!("insert into student (id, name, email, password) values (1, 'Ann', 'a@b.c', '')")
+        .execute(&database)
+        .await
+        .unwrap();
+    let reported = CompletionUsage {
+        prompt_tokens: 1200,
+        completion_tokens: 80,
+        total_tokens: 1280,
+        prompt_tokens_details: None,
+        completion_tokens_details: None,
+    };
+    record(&database, 1, 1, Usage::reported(&reported))
+        .await
+        .unwrap();
+    record(&database, 1, 1, Usage::estimated(1300, 20))
+        .await
+        .unwrap();
+    record_message(&database, 1, 1).await.unwrap();
+    let usage = report(&database, &UsageFilter::default()).await.unwrap();
+    assert_eq!(usage.len(), 1);
+    assert_eq!(
+        (
+            usage[0].messages,
+            usage[0].requests,
+            usage[0].prompt_tokens,
+            usage[0].completion_tokens,
+            usage[0].estimated_requests
+        ),
+        (1, 2, 2500, 100, 1)
+    );
+    let other = UsageFilter {
+        book_id: Some(2),
+        ..Default::default()
+    };
+    assert!(report(&database, &other).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn exhausted_budget() {
+    use crate::books::library::{Library, LibraryConfig};
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = LibraryConfig {
+        database: dir.path().join("book.db"),
+        bookbase: dir.path().join("bookbase"),
+        migrate: true,
+        ..Default::default()
+    };
+    let database = Library::open(&config).await.unwrap().database.clone();
+    sqlx::query!("insert into student (id, name, email, password) values (1, 'Ann', 'a@b.c', '')")
+        .execute(&database)
+        .await
+        .unwrap();
+    let meter = Meter {
+        database: database.clone(),
+        student_id: 1,
+        book_id: 1,
+    };
+    meter.check().await.unwrap();
+    let setting = spend::ClassSetting {
+        name: "Rust 101".to_string(),
+        monthly_limit: Some(0.0),
+        warning_webhook: None,
+    };
+    let class_id = spend::create_class(&database, &setting).await.unwrap();
+    spend::set_student_class(&database, 1, Some(class_id))
+        .await
+        .unwrap();
+    // quizzes, flashcards and the other calls besides the chat are refused as well
+    assert!(meter.check().await.is_err());
+}
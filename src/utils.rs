@@ -1,8 +1,13 @@
-use std::{path::PathBuf, sync::LazyLock};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::LazyLock,
+};
 
 use time::{UtcOffset, format_description::well_known};
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{EnvFilter, fmt::time::OffsetTime};
+use url::{Host, Url};
 
 pub static LOCAL_OFFSET: LazyLock<UtcOffset> =
     LazyLock::new(|| match time::UtcOffset::current_local_offset() {
@@ -66,3 +71,93 @@ pub fn init_log(log_dir: Option<PathBuf>) -> tracing_appender::non_blocking::Wor
     tracing::subscriber::set_global_default(subscriber).expect("init log failed");
     guard
 }
+
+/// whether `ip` is reachable from the internet, i.e. not a loopback, private, link-local,
+/// shared or unspecified address
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || a == 0
+                // 100.64.0.0/10, carrier-grade NAT
+                || (a == 100 && b & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    || ip.is_multicast())
+            }
+        },
+    }
+}
+
+/// whether the host of `url` is local or private by its name alone, the addresses a public
+/// name resolves to are checked by [`resolve_public`]
+pub fn is_local_host(url: &Url) -> bool {
+    match url.host() {
+        Some(Host::Domain(domain)) => {
+            domain == "localhost" || domain.ends_with(".localhost") || !domain.contains('.')
+        }
+        Some(Host::Ipv4(ip)) => !is_public_ip(ip.into()),
+        Some(Host::Ipv6(ip)) => !is_public_ip(ip.into()),
+        None => true,
+    }
+}
+
+/// the addresses of `host`, failing when it resolves to a local or private address
+pub async fn resolve_public(host: &str, port: u16) -> anyhow::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port)).await?.collect();
+    if addrs.is_empty() {
+        anyhow::bail!("{host} has no address");
+    }
+    if let Some(addr) = addrs.iter().find(|addr| !is_public_ip(addr.ip())) {
+        anyhow::bail!("{host} resolves to the local address {}", addr.ip());
+    }
+    Ok(addrs)
+}
+
+/// DNS resolver of the reqwest clients calling urls chosen by books or students, so that a
+/// public name can't point them at the server's own network
+pub struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Result<reqwest::dns::Addrs, Box<dyn std::error::Error + Send + Sync>> =
+                match resolve_public(&host, 0).await {
+                    Ok(addrs) => Ok(Box::new(addrs.into_iter())),
+                    Err(e) => Err(e.into()),
+                };
+            addrs
+        })
+    }
+}
+
+#[test]
+fn public_hosts() {
+    let local = |url: &str| is_local_host(&Url::parse(url).unwrap());
+    assert!(local("http://169.254.169.254/latest/meta-data"));
+    assert!(local("http://127.0.0.1:8080"));
+    assert!(local("http://10.0.0.1"));
+    assert!(local("http://100.64.0.1"));
+    assert!(local("http://[::1]"));
+    assert!(local("http://[fd00::1]"));
+    assert!(local("http://[fe80::1]"));
+    assert!(local("http://[::ffff:127.0.0.1]"));
+    assert!(local("http://localhost:3000"));
+    assert!(local("http://intranet"));
+    assert!(!local("https://example.com"));
+    assert!(!local("https://93.184.215.14"));
+    assert!(!local("https://[2606:2800:21f:cb07:6820:80da:af6b:8b2c]"));
+}